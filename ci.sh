@@ -9,6 +9,7 @@ rustup target add \
 
 # formatting
 cargo fmt --all --manifest-path source/postcard-rpc/Cargo.toml -- --check
+cargo fmt --all --manifest-path source/postcard-rpc-macros/Cargo.toml -- --check
 cargo fmt --all --manifest-path example/workbook-host/Cargo.toml -- --check
 cargo fmt --all --manifest-path example/serial-host/Cargo.toml -- --check
 cargo fmt --all --manifest-path example/firmware/Cargo.toml -- --check
@@ -30,11 +31,11 @@ cargo test \
 cargo check \
     --manifest-path source/postcard-rpc/Cargo.toml \
     --no-default-features \
-    --features=use-std,cobs-serial,raw-nusb
+    --features=use-std,cobs-serial,raw-nusb,tcp,json,macros,auth,checksum,compress,fragment,dyn-dispatch,metrics,worker-pool,device-requests,offload,channel-server,multi-transport,framing,embedded-io-async-0_6-server,embassy-sync-0_7
 cargo test \
     --manifest-path source/postcard-rpc/Cargo.toml \
     --no-default-features \
-    --features=use-std,cobs-serial,raw-nusb
+    --features=use-std,cobs-serial,raw-nusb,tcp,json,macros,auth,checksum,compress,fragment,dyn-dispatch,metrics,worker-pool,device-requests,offload,channel-server,multi-transport,framing,embedded-io-async-0_6-server,embassy-sync-0_7
 
 # Host + wasm host-client impls
 RUSTFLAGS="--cfg=web_sys_unstable_apis" \
@@ -59,12 +60,18 @@ cargo check \
 cargo check \
     --manifest-path source/postcard-rpc/Cargo.toml \
     --no-default-features \
-    --features=embassy-usb-0_5-server \
+    --features=embassy-usb-0_5-server,defmt,auth,checksum,compress,fragment,dyn-dispatch,metrics,worker-pool,device-requests,offload,channel-server,multi-transport,framing \
     --target thumbv7em-none-eabihf
 cargo check \
     --manifest-path source/postcard-rpc/Cargo.toml \
     --no-default-features \
-    --features=embedded-io-async-0_6-server \
+    --features=embedded-io-async-0_6-server,gatt-server,udp-server,embassy-sync-0_7 \
+    --target thumbv7em-none-eabihf
+# embedded-io server without embassy-sync
+cargo check \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=embedded-io-async-0_6-server,rtt-server \
     --target thumbv7em-none-eabihf
 
 # Example projects
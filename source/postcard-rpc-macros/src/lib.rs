@@ -0,0 +1,585 @@
+//! Procedural macros for postcard-rpc
+//!
+//! These are re-exported by `postcard-rpc` when its `macros` feature is enabled,
+//! and should be used through that crate, e.g. `#[postcard_rpc::define_endpoint]`.
+
+#![deny(missing_docs)]
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Attribute, Data, DeriveInput, Fields, Ident, LitInt, LitStr, Path, Token, Type, Visibility,
+};
+
+/// Define an endpoint from its request type
+///
+/// Placed on the request type, this derives `Serialize`, `Deserialize`, and `Schema`
+/// for it, and defines an endpoint marker type, like [`endpoint!()`]. The response
+/// type is given with a `#[response(Type)]` attribute, and must already implement
+/// these traits.
+///
+/// ```rust,ignore
+/// use postcard_rpc::define_endpoint;
+///
+/// #[define_endpoint(path = "alpha")]
+/// #[response(AResp)]
+/// pub struct AReq(pub u8);
+///
+/// #[derive(Serialize, Deserialize, Schema)]
+/// pub struct AResp(pub u8);
+///
+/// // Equivalent to:
+/// //
+/// // #[derive(Serialize, Deserialize, Schema)]
+/// // pub struct AReq(pub u8);
+/// //
+/// // endpoint!(AEndpoint, AReq, AResp, "alpha");
+/// ```
+///
+/// The following options are accepted:
+///
+/// * `path = "..."`: the path of the endpoint. Defaults to the name of the marker type.
+/// * `name = Ident`: the name of the marker type. Defaults to the name of the request
+///   type, with any `Req` or `Request` suffix replaced with `Endpoint`.
+/// * `idempotent`: marks the endpoint as idempotent, i.e. handling the same request
+///   more than once is harmless, which allows clients to retry it automatically.
+/// * `tolerant`: decodes requests missing trailing fields with their defaults, and
+///   derives the request key from the path only, so fields can be appended to the
+///   request. See the `tolerant` module of `postcard-rpc` for the safe changes.
+/// * `max_req_size = N`: the largest serialized size of a request, checked by
+///   clients before sending. Defaults to the size computed from the schema of the
+///   request, which is unbounded for requests holding sequences, strings, or maps.
+///
+/// The marker type has the same visibility as the request type. Generic request
+/// types are not supported.
+///
+/// [`endpoint!()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/macro.endpoint.html
+#[proc_macro_attribute]
+pub fn define_endpoint(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let mut args = EndpointArgs::default();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("path") {
+            args.path = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("name") {
+            args.name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("idempotent") {
+            args.idempotent = true;
+            Ok(())
+        } else if meta.path.is_ident("tolerant") {
+            args.tolerant = true;
+            Ok(())
+        } else if meta.path.is_ident("max_req_size") {
+            args.max_req_size = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error(
+                "unsupported `define_endpoint` option, expected `path`, `name`, `idempotent`, `tolerant`, or `max_req_size`",
+            ))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let input = parse_macro_input!(item as DeriveInput);
+
+    expand_endpoint(args, input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct EndpointArgs {
+    path: Option<LitStr>,
+    name: Option<Ident>,
+    idempotent: bool,
+    tolerant: bool,
+    max_req_size: Option<LitInt>,
+}
+
+fn expand_endpoint(args: EndpointArgs, mut input: DeriveInput) -> syn::Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "`define_endpoint` does not support generic request types",
+        ));
+    }
+
+    // Take the `#[response(...)]` attribute, it is not a real attribute so it
+    // must not be emitted again
+    let mut response: Option<Type> = None;
+    let mut attrs = Vec::with_capacity(input.attrs.len());
+    for attr in core::mem::take(&mut input.attrs) {
+        if !attr.path().is_ident("response") {
+            attrs.push(attr);
+            continue;
+        }
+        if response.is_some() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "duplicate `#[response(...)]` attribute",
+            ));
+        }
+        response = Some(attr.parse_args()?);
+    }
+    input.attrs = attrs;
+    let Some(response) = response else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "missing `#[response(Type)]` attribute for `define_endpoint`",
+        ));
+    };
+
+    let request = &input.ident;
+    let name = args.name.unwrap_or_else(|| default_name(request));
+    let path = args
+        .path
+        .unwrap_or_else(|| LitStr::new(&name.to_string(), name.span()));
+    let vis = &input.vis;
+    let idempotent = args.idempotent;
+    let tolerant = args.tolerant;
+    let req_key = req_key(tolerant, &syn::parse_quote!(#request), &path);
+    let max_req_size = max_req_size(args.max_req_size.as_ref());
+
+    Ok(quote! {
+        #[derive(
+            ::postcard_rpc::serde::Serialize,
+            ::postcard_rpc::serde::Deserialize,
+            ::postcard_rpc::postcard_schema::Schema,
+        )]
+        #[serde(crate = "::postcard_rpc::serde")]
+        #[postcard(crate = ::postcard_rpc::postcard_schema)]
+        #input
+
+        #[doc = concat!("Endpoint marker type for [`", stringify!(#request), "`], at path `", #path, "`")]
+        #vis struct #name;
+
+        impl ::postcard_rpc::Endpoint for #name {
+            type Request = #request;
+            type Response = #response;
+            const PATH: &'static str = #path;
+            const REQ_KEY: ::postcard_rpc::Key = #req_key;
+            const RESP_KEY: ::postcard_rpc::Key = ::postcard_rpc::Key::for_path::<#response>(#path);
+            const IDEMPOTENT: bool = #idempotent;
+            const TOLERANT: bool = #tolerant;
+            #max_req_size
+        }
+    })
+}
+
+/// The declared `MAX_REQ_SIZE` of an endpoint, if any, or the default computed
+/// from the request schema
+fn max_req_size(max: Option<&LitInt>) -> TokenStream {
+    match max {
+        Some(max) => {
+            quote!(const MAX_REQ_SIZE: ::core::option::Option<usize> = ::core::option::Option::Some(#max);)
+        }
+        None => TokenStream::new(),
+    }
+}
+
+/// The request key of an endpoint, which only depends on the path if it is tolerant
+fn req_key(tolerant: bool, request: &Type, path: &LitStr) -> TokenStream {
+    if tolerant {
+        quote!(::postcard_rpc::tolerant::request_key(#path))
+    } else {
+        quote!(::postcard_rpc::Key::for_path::<#request>(#path))
+    }
+}
+
+/// `AlphaReq` or `AlphaRequest` becomes `AlphaEndpoint`
+fn default_name(request: &Ident) -> Ident {
+    let req = request.to_string();
+    let base = req
+        .strip_suffix("Request")
+        .or_else(|| req.strip_suffix("Req"))
+        .unwrap_or(&req);
+    format_ident!("{}Endpoint", base, span = request.span())
+}
+
+/// Define an endpoint for each variant of an enum of commands
+///
+/// Placed on an enum whose variants each hold the request type of one endpoint,
+/// this defines an endpoint marker type per variant, like [`define_endpoint`], so
+/// the enum is the single source of truth of the protocol. Each variant is
+/// configured with a `#[rpc(...)]` attribute:
+///
+/// ```rust,ignore
+/// use postcard_rpc::PostcardRpcCommands;
+///
+/// #[derive(PostcardRpcCommands)]
+/// pub enum Command {
+///     #[rpc(path = "motor/stop")]
+///     Stop(StopReq),
+///     #[rpc(path = "config/get", response = Config, name = ReadConfigEndpoint)]
+///     GetConfig(ConfigReq),
+/// }
+///
+/// // Equivalent to:
+/// //
+/// // endpoint!(StopEndpoint, StopReq, (), "motor/stop");
+/// // endpoint!(ReadConfigEndpoint, ConfigReq, Config, "config/get");
+/// ```
+///
+/// The following options are accepted:
+///
+/// * `path = "..."`: the path of the endpoint. Required.
+/// * `response = Type`: the response type of the endpoint. Defaults to `()`.
+/// * `name = Ident`: the name of the marker type. Defaults to the name of the
+///   variant, followed by `Endpoint`.
+/// * `idempotent`: marks the endpoint as idempotent, like with [`define_endpoint`].
+/// * `tolerant`: makes the endpoint tolerant, like with [`define_endpoint`].
+/// * `max_req_size = N`: caps the size of requests, like with [`define_endpoint`].
+///
+/// The request and response types must implement `Serialize`, `Deserialize`, and
+/// `Schema`. The marker types have the same visibility as the enum. The enum also
+/// gets a dispatch table:
+///
+/// * `Command::ENDPOINT_LIST`: the `EndpointMap` of all variants, e.g. for the
+///   `list` of `define_dispatch!`
+/// * `Command::from_frame(&hdr, body)`: the command of a request frame, or `None`
+///   if its key matches no variant
+/// * `command.req_key()`: the request key of the endpoint of a command
+///
+/// Generic enums, and variants that don't hold exactly one unnamed field, are not
+/// supported.
+#[proc_macro_derive(PostcardRpcCommands, attributes(rpc))]
+pub fn derive_commands(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    expand_commands(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct CommandVariant {
+    variant: Ident,
+    request: Type,
+    response: Type,
+    path: LitStr,
+    name: Ident,
+    idempotent: bool,
+    tolerant: bool,
+    max_req_size: Option<LitInt>,
+}
+
+fn expand_commands(input: DeriveInput) -> syn::Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "`PostcardRpcCommands` does not support generic enums",
+        ));
+    }
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "`PostcardRpcCommands` can only be derived for enums",
+        ));
+    };
+
+    let mut commands = Vec::with_capacity(data.variants.len());
+    for variant in &data.variants {
+        let request = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => fields.unnamed[0].ty.clone(),
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "expected a variant holding exactly one request type, e.g. `Stop(StopReq)`",
+                ))
+            }
+        };
+
+        let mut path: Option<LitStr> = None;
+        let mut response: Option<Type> = None;
+        let mut name: Option<Ident> = None;
+        let mut idempotent = false;
+        let mut tolerant = false;
+        let mut max_req_size: Option<LitInt> = None;
+        for attr in variant.attrs.iter().filter(|a| a.path().is_ident("rpc")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("path") {
+                    path = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("response") {
+                    response = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("name") {
+                    name = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("idempotent") {
+                    idempotent = true;
+                    Ok(())
+                } else if meta.path.is_ident("tolerant") {
+                    tolerant = true;
+                    Ok(())
+                } else if meta.path.is_ident("max_req_size") {
+                    max_req_size = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error(
+                        "unsupported `rpc` option, expected `path`, `response`, `name`, `idempotent`, `tolerant`, or `max_req_size`",
+                    ))
+                }
+            })?;
+        }
+        let Some(path) = path else {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "missing `#[rpc(path = \"...\")]` attribute",
+            ));
+        };
+
+        let variant = variant.ident.clone();
+        commands.push(CommandVariant {
+            name: name.unwrap_or_else(|| format_ident!("{}Endpoint", variant)),
+            response: response.unwrap_or_else(|| syn::parse_quote!(())),
+            variant,
+            request,
+            path,
+            idempotent,
+            tolerant,
+            max_req_size,
+        });
+    }
+
+    let enum_name = &input.ident;
+    let vis = &input.vis;
+    let variants: Vec<&Ident> = commands.iter().map(|c| &c.variant).collect();
+    let requests: Vec<&Type> = commands.iter().map(|c| &c.request).collect();
+    let responses: Vec<&Type> = commands.iter().map(|c| &c.response).collect();
+    let paths: Vec<&LitStr> = commands.iter().map(|c| &c.path).collect();
+    let names: Vec<&Ident> = commands.iter().map(|c| &c.name).collect();
+    let idempotents: Vec<bool> = commands.iter().map(|c| c.idempotent).collect();
+    let tolerants: Vec<bool> = commands.iter().map(|c| c.tolerant).collect();
+    let req_keys: Vec<TokenStream> = commands
+        .iter()
+        .map(|c| req_key(c.tolerant, &c.request, &c.path))
+        .collect();
+    let max_req_sizes: Vec<TokenStream> = commands
+        .iter()
+        .map(|c| max_req_size(c.max_req_size.as_ref()))
+        .collect();
+
+    Ok(quote! {
+        #(
+            #[doc = concat!("Endpoint marker type for [`", stringify!(#enum_name), "::", stringify!(#variants), "`], at path `", #paths, "`")]
+            #vis struct #names;
+
+            impl ::postcard_rpc::Endpoint for #names {
+                type Request = #requests;
+                type Response = #responses;
+                const PATH: &'static str = #paths;
+                const REQ_KEY: ::postcard_rpc::Key = #req_keys;
+                const RESP_KEY: ::postcard_rpc::Key = ::postcard_rpc::Key::for_path::<#responses>(#paths);
+                const IDEMPOTENT: bool = #idempotents;
+                const TOLERANT: bool = #tolerants;
+                #max_req_sizes
+            }
+        )*
+
+        impl #enum_name {
+            /// The endpoints of all commands, including the standard endpoints
+            pub const ENDPOINT_LIST: ::postcard_rpc::EndpointMap = ::postcard_rpc::EndpointMap {
+                types: ::postcard_rpc::endpoints!(@ep_tys #([[] #names])*),
+                endpoints: ::postcard_rpc::endpoints!(@ep_eps #([[] #names])*),
+            };
+
+            /// Decode the command of a request frame
+            ///
+            /// Returns `None` if the key of `hdr` matches no command, or the result
+            /// of deserializing `body` as the request of the matching command.
+            pub fn from_frame(
+                hdr: &::postcard_rpc::header::VarHeader,
+                body: &[u8],
+            ) -> ::core::option::Option<::core::result::Result<Self, ::postcard_rpc::postcard::Error>> {
+                #(
+                    if hdr.key == ::postcard_rpc::header::VarKey::Key8(<#names as ::postcard_rpc::Endpoint>::REQ_KEY) {
+                        return ::core::option::Option::Some(
+                            ::postcard_rpc::tolerant::request_from_bytes::<#names, _>(body).map(Self::#variants),
+                        );
+                    }
+                )*
+                ::core::option::Option::None
+            }
+
+            /// The request key of the endpoint of this command
+            pub fn req_key(&self) -> ::postcard_rpc::Key {
+                match self {
+                    #(
+                        Self::#variants(_) => <#names as ::postcard_rpc::Endpoint>::REQ_KEY,
+                    )*
+                }
+            }
+        }
+    })
+}
+
+/// Generate a typed client trait from a list of endpoints
+///
+/// Each endpoint becomes an async method of the trait, which is implemented for
+/// `HostClient`, so requests can be sent with e.g. `client.alpha(&req).await`
+/// instead of `client.send_resp::<AlphaEndpoint>(&req).await`:
+///
+/// ```rust,ignore
+/// use postcard_rpc::generate_client;
+///
+/// generate_client! {
+///     /// A typed client for our device
+///     pub trait DeviceClient {
+///         AlphaEndpoint,
+///         BetaEndpoint,
+///         // Methods can be renamed with `as`
+///         icd::SetLedEndpoint as led,
+///     }
+/// }
+///
+/// // `client` is a `HostClient<WireError>`
+/// let resp: AResp = client.alpha(&AReq(42)).await?;
+/// ```
+///
+/// Each method is named after its endpoint, without any `Endpoint` suffix, in
+/// snake case: `SetLedEndpoint` becomes `set_led`. Names that are Rust keywords
+/// are used as raw identifiers, e.g. `TryEndpoint` becomes `r#try`.
+///
+/// The trait is generic over the wire error type of the client. Methods return the
+/// same `Result` as `HostClient::send_resp()`, so endpoints with borrowed response
+/// types are not supported.
+#[proc_macro]
+pub fn generate_client(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(item as ClientInput);
+    expand_client(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct ClientInput {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    name: Ident,
+    methods: Punctuated<ClientMethod, Token![,]>,
+}
+
+struct ClientMethod {
+    endpoint: Path,
+    name: Option<Ident>,
+}
+
+impl Parse for ClientInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![trait]>()?;
+        let name = input.parse()?;
+        let content;
+        braced!(content in input);
+        let methods = content.parse_terminated(ClientMethod::parse, Token![,])?;
+        Ok(Self {
+            attrs,
+            vis,
+            name,
+            methods,
+        })
+    }
+}
+
+impl Parse for ClientMethod {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let endpoint = input.parse()?;
+        let name = if input.parse::<Option<Token![as]>>()?.is_some() {
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(Self { endpoint, name })
+    }
+}
+
+fn expand_client(input: ClientInput) -> syn::Result<TokenStream> {
+    let ClientInput {
+        attrs,
+        vis,
+        name,
+        methods,
+    } = input;
+    let endpoints: Vec<&Path> = methods.iter().map(|m| &m.endpoint).collect();
+    let names = methods
+        .iter()
+        .map(|m| match &m.name {
+            Some(name) => Ok(name.clone()),
+            None => method_name(&m.endpoint),
+        })
+        .collect::<syn::Result<Vec<Ident>>>()?;
+
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(async_fn_in_trait)]
+        #vis trait #name<WireErr> {
+            #(
+                #[doc = concat!("Send a request to [`", stringify!(#endpoints), "`], and await its response")]
+                async fn #names(
+                    &self,
+                    req: &<#endpoints as ::postcard_rpc::Endpoint>::Request,
+                ) -> ::core::result::Result<
+                    <#endpoints as ::postcard_rpc::Endpoint>::Response,
+                    ::postcard_rpc::host_client::HostErr<WireErr>,
+                >;
+            )*
+        }
+
+        impl<WireErr> #name<WireErr> for ::postcard_rpc::host_client::HostClient<WireErr>
+        where
+            WireErr: ::postcard_rpc::serde::de::DeserializeOwned + ::postcard_rpc::postcard_schema::Schema,
+        {
+            #(
+                async fn #names(
+                    &self,
+                    req: &<#endpoints as ::postcard_rpc::Endpoint>::Request,
+                ) -> ::core::result::Result<
+                    <#endpoints as ::postcard_rpc::Endpoint>::Response,
+                    ::postcard_rpc::host_client::HostErr<WireErr>,
+                > {
+                    self.send_resp::<#endpoints>(req).await
+                }
+            )*
+        }
+    })
+}
+
+/// `SetLedEndpoint` becomes `set_led`
+fn method_name(endpoint: &Path) -> syn::Result<Ident> {
+    let Some(last) = endpoint.segments.last().map(|s| &s.ident) else {
+        return Err(syn::Error::new_spanned(
+            endpoint,
+            "expected an endpoint type",
+        ));
+    };
+    let ep = last.to_string();
+    let base = ep.strip_suffix("Endpoint").unwrap_or(&ep);
+    let mut snake = String::with_capacity(base.len() + 4);
+    for (i, c) in base.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    if matches!(snake.as_str(), "" | "self" | "super" | "crate") {
+        return Err(syn::Error::new_spanned(
+            endpoint,
+            "can't name a method after this endpoint, rename it with `as`",
+        ));
+    }
+    // Keywords, such as `try`, can only be used as raw identifiers
+    match syn::parse_str::<Ident>(&snake) {
+        Ok(_) => Ok(Ident::new(&snake, last.span())),
+        Err(_) => Ok(Ident::new_raw(&snake, last.span())),
+    }
+}
@@ -0,0 +1,186 @@
+//! Compares the time and allocations per request of `HostClient::send_batch()`,
+//! which awaits one future per request, and `HostClient::send_cb()`, which
+//! stores one callback per request.
+//!
+//! Run with `cargo bench --bench host_callbacks`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use postcard_rpc::{
+    endpoints,
+    header::{VarHeader, VarKey, VarSeqKind},
+    host_client::{HostClient, HostClientBuilder, RpcFrame, WireRx, WireSpawn, WireTx},
+    standard_icd::{WireError, ERROR_PATH},
+    Endpoint,
+};
+use tokio::sync::{mpsc, Notify};
+
+/// Counts every allocation made by the process
+struct CountingAlloc;
+
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+endpoints! {
+    list = ENDPOINT_LIST;
+    | EndpointTy   | RequestTy | ResponseTy | Path   |
+    | ----------   | --------- | ---------- | ----   |
+    | TinyEndpoint | u32       | u32        | "tiny" |
+}
+
+const REQUESTS: u32 = 100_000;
+
+/// A transport answering every request immediately, echoing its body
+struct EchoTx {
+    replies: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl WireTx for EchoTx {
+    type Error = Infallible;
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), Self::Error> {
+        let (hdr, body) = VarHeader::take_from_slice(&data).unwrap();
+        let reply = RpcFrame {
+            header: VarHeader {
+                key: VarKey::Key8(TinyEndpoint::RESP_KEY),
+                seq_no: hdr.seq_no,
+            },
+            body: body.to_vec(),
+        };
+        _ = self.replies.send(reply.to_bytes());
+        Ok(())
+    }
+}
+
+struct EchoRx {
+    replies: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl WireRx for EchoRx {
+    type Error = Infallible;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        match self.replies.recv().await {
+            Some(frame) => Ok(frame),
+            None => std::future::pending().await,
+        }
+    }
+}
+
+struct TokioSpawn;
+
+impl WireSpawn for TokioSpawn {
+    fn spawn(&mut self, fut: impl std::future::Future<Output = ()> + Send + 'static) {
+        _ = tokio::task::spawn(fut);
+    }
+}
+
+fn client() -> HostClient<WireError> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .seq_kind(VarSeqKind::Seq4)
+        .build()
+        .unwrap();
+    HostClient::new_with_wire_and_config(
+        EchoTx { replies: tx },
+        EchoRx { replies: rx },
+        TokioSpawn,
+        &config,
+    )
+}
+
+async fn run_futures() -> (Duration, f64) {
+    let client = client();
+    let reqs: Vec<u32> = (0..REQUESTS).collect();
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let resps = client.send_batch::<TinyEndpoint>(&reqs).await;
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+
+    assert!(resps.into_iter().all(|r| r.is_ok()));
+    client.close();
+    (elapsed, allocs as f64 / f64::from(REQUESTS))
+}
+
+async fn run_callbacks() -> (Duration, f64) {
+    let client = client();
+    let done = Arc::new(AtomicUsize::new(0));
+    let all_done = Arc::new(Notify::new());
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..REQUESTS {
+        let done = done.clone();
+        let all_done = all_done.clone();
+        client
+            .send_cb::<TinyEndpoint, _>(&i, move |resp| {
+                assert_eq!(resp.unwrap(), i);
+                if done.fetch_add(1, Ordering::Relaxed) + 1 == REQUESTS as usize {
+                    all_done.notify_one();
+                }
+            })
+            .await;
+    }
+    all_done.notified().await;
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+
+    client.close();
+    (elapsed, allocs as f64 / f64::from(REQUESTS))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    println!("{REQUESTS} tiny requests, answered immediately");
+    println!(
+        "{:<10} {:>12} {:>16} {:>18}",
+        "path", "wall", "per request", "allocs per request"
+    );
+    for (name, callbacks) in [("futures", false), ("callbacks", true)] {
+        // Warm up, then measure
+        let run = || async {
+            if callbacks {
+                run_callbacks().await
+            } else {
+                run_futures().await
+            }
+        };
+        run().await;
+        let (wall, allocs) = run().await;
+        println!(
+            "{:<10} {:>12?} {:>16?} {:>18.3}",
+            name,
+            wall,
+            wall / REQUESTS,
+            allocs
+        );
+    }
+}
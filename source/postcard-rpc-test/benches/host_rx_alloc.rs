@@ -0,0 +1,175 @@
+//! Counts the allocations made by a `HostClient` for each incoming topic
+//! message, with and without the frame pool.
+//!
+//! Run with `cargo bench --bench host_rx_alloc`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    convert::Infallible,
+    future::pending,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use postcard_rpc::{
+    header::{VarHeader, VarKey, VarSeq},
+    host_client::{HostClient, HostClientBuilder, RpcFrame, WireRx, WireSpawn, WireTx},
+    standard_icd::{WireError, ERROR_PATH},
+    topics, Topic, TopicDirection,
+};
+use postcard_schema::Schema;
+use serde::{Deserialize, Serialize};
+
+/// Counts every allocation made by the process
+struct CountingAlloc;
+
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct Telemetry {
+    pub seq: u32,
+    pub samples: [u16; 32],
+}
+
+topics! {
+    list = TOPICS_OUT_LIST;
+    direction = TopicDirection::ToClient;
+    | TopicTy        | MessageTy | Path        |
+    | -------        | --------- | ----        |
+    | TelemetryTopic | Telemetry | "telemetry" |
+}
+
+const FRAMES: u32 = 100_000;
+
+/// A transport receiving the same telemetry frame `FRAMES` times
+struct ReplayRx {
+    frame: Vec<u8>,
+    left: u32,
+    /// Whether to receive into the buffer from the pool
+    into: bool,
+}
+
+impl WireRx for ReplayRx {
+    type Error = Infallible;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        if self.left == 0 {
+            pending::<()>().await;
+        }
+        self.left -= 1;
+        Ok(self.frame.clone())
+    }
+
+    async fn receive_into(&mut self, buf: &mut Vec<u8>) -> Result<(), Self::Error> {
+        if !self.into {
+            *buf = self.receive().await?;
+            return Ok(());
+        }
+        if self.left == 0 {
+            pending::<()>().await;
+        }
+        self.left -= 1;
+        buf.clear();
+        buf.extend_from_slice(&self.frame);
+        Ok(())
+    }
+}
+
+struct NullTx;
+
+impl WireTx for NullTx {
+    type Error = Infallible;
+
+    async fn send(&mut self, _data: Vec<u8>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+struct TokioSpawn;
+
+impl WireSpawn for TokioSpawn {
+    fn spawn(&mut self, fut: impl std::future::Future<Output = ()> + Send + 'static) {
+        _ = tokio::task::spawn(fut);
+    }
+}
+
+async fn run(pool: usize) -> (Duration, f64) {
+    let msg = Telemetry {
+        seq: 1234,
+        samples: core::array::from_fn(|i| i as u16 * 997),
+    };
+    let frame = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(TelemetryTopic::TOPIC_KEY),
+            seq_no: VarSeq::Seq4(0),
+        },
+        body: postcard::to_stdvec(&msg).unwrap(),
+    };
+    let rx = ReplayRx {
+        frame: frame.to_bytes(),
+        left: FRAMES,
+        into: pool != 0,
+    };
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .frame_pool(pool)
+        // Wait for the subscriber instead of dropping messages
+        .subscriber_timeout_if_full(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    let client = HostClient::<WireError>::new_with_wire_and_config(NullTx, rx, TokioSpawn, &config);
+    let mut sub = client
+        .subscribe_exclusive::<TelemetryTopic>(16)
+        .await
+        .unwrap();
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        let msg = sub.recv().await.unwrap();
+        assert_eq!(msg.seq, 1234);
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+    client.close();
+    (elapsed, allocs as f64 / f64::from(FRAMES))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    println!("{FRAMES} telemetry messages received by one subscription");
+    println!(
+        "{:<10} {:>12} {:>16} {:>18}",
+        "pool", "wall", "per message", "allocs per message"
+    );
+    for pool in [0, 32] {
+        // Warm up, then measure
+        run(pool).await;
+        let (wall, allocs) = run(pool).await;
+        println!(
+            "{:<10} {:>12?} {:>16?} {:>18.3}",
+            pool,
+            wall,
+            wall / FRAMES,
+            allocs
+        );
+    }
+}
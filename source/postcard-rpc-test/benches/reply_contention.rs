@@ -0,0 +1,192 @@
+//! Compares the time spent holding, and waiting for, the lock of a shared send
+//! buffer when replying with `Sender::reply()` and `Sender::reply_from_buf()`.
+//!
+//! Run with `cargo bench --bench reply_contention`.
+
+use core::{fmt::Arguments, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use postcard_rpc::{
+    endpoints,
+    header::{VarHeader, VarKeyKind, VarSeq},
+    server::{frame::serialize_frame, Sender, WireTx, WireTxErrorKind},
+};
+use postcard_schema::Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task::yield_now};
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct Telemetry {
+    pub samples: Vec<u32>,
+    pub label: String,
+}
+
+endpoints! {
+    list = ENDPOINT_LIST;
+    | EndpointTy        | RequestTy | ResponseTy | Path        |
+    | ----------        | --------- | ---------- | ----        |
+    | TelemetryEndpoint | ()        | Telemetry  | "telemetry" |
+}
+
+const TASKS: usize = 8;
+const REPLIES_PER_TASK: usize = 2000;
+/// The number of times the simulated write yields, like a USB write waiting for
+/// the host to poll the endpoint
+const WRITE_YIELDS: usize = 2;
+
+#[derive(Default)]
+struct Stats {
+    held_ns: AtomicU64,
+    waited_ns: AtomicU64,
+}
+
+struct Inner {
+    buf: Vec<u8>,
+    written: u64,
+}
+
+/// A `WireTx` with a single send buffer behind a mutex, like the embassy-usb impls
+#[derive(Clone)]
+struct SharedTx {
+    inner: Arc<Mutex<Inner>>,
+    stats: Arc<Stats>,
+}
+
+impl SharedTx {
+    fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                buf: vec![0; 1024],
+                written: 0,
+            })),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    async fn write(inner: &mut Inner, frame_len: usize) {
+        for _ in 0..WRITE_YIELDS {
+            yield_now().await;
+        }
+        inner.written += frame_len as u64;
+    }
+
+    fn record(&self, start: Instant, locked: Instant) {
+        let waited = locked.duration_since(start).as_nanos() as u64;
+        let held = locked.elapsed().as_nanos() as u64;
+        self.stats.waited_ns.fetch_add(waited, Ordering::Relaxed);
+        self.stats.held_ns.fetch_add(held, Ordering::Relaxed);
+    }
+}
+
+impl WireTx for SharedTx {
+    type Error = WireTxErrorKind;
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let mut inner = self.inner.lock().await;
+        let locked = Instant::now();
+        let Inner { buf, .. } = &mut *inner;
+        let len = serialize_frame(buf, hdr, msg)?.len();
+        Self::write(&mut inner, len).await;
+        drop(inner);
+        self.record(start, locked);
+        Ok(())
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let mut inner = self.inner.lock().await;
+        let locked = Instant::now();
+        Self::write(&mut inner, buf.len()).await;
+        drop(inner);
+        self.record(start, locked);
+        Ok(())
+    }
+
+    async fn send_log_str(&self, _kkind: VarKeyKind, _s: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        _kkind: VarKeyKind,
+        _a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Reply,
+    ReplyFromBuf,
+}
+
+async fn run(mode: Mode) -> (Duration, Duration, Duration) {
+    let tx = SharedTx::new();
+    let stats = tx.stats.clone();
+    let start = Instant::now();
+    let handles: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let sender = Sender::new(tx.clone(), VarKeyKind::Key8);
+            tokio::task::spawn(async move {
+                let resp = Telemetry {
+                    samples: (0..128).map(|i| i * 7919).collect(),
+                    label: format!("task {task}"),
+                };
+                let mut buf = [0u8; 1024];
+                for i in 0..REPLIES_PER_TASK {
+                    let seq_no = VarSeq::Seq4(i as u32);
+                    match mode {
+                        Mode::Reply => sender.reply::<TelemetryEndpoint>(seq_no, &resp).await,
+                        Mode::ReplyFromBuf => {
+                            sender
+                                .reply_from_buf::<TelemetryEndpoint>(seq_no, &resp, &mut buf)
+                                .await
+                        }
+                    }
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    let wall = start.elapsed();
+    let held = Duration::from_nanos(stats.held_ns.load(Ordering::Relaxed));
+    let waited = Duration::from_nanos(stats.waited_ns.load(Ordering::Relaxed));
+    (wall, held, waited)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let frames = (TASKS * REPLIES_PER_TASK) as u32;
+    println!("{TASKS} tasks sending {REPLIES_PER_TASK} replies each");
+    println!(
+        "{:<14} {:>12} {:>18} {:>18}",
+        "mode", "wall", "held per frame", "waited per frame"
+    );
+    for mode in [Mode::Reply, Mode::ReplyFromBuf] {
+        // Warm up, then measure
+        run(mode).await;
+        let (wall, held, waited) = run(mode).await;
+        println!(
+            "{:<14} {:>12?} {:>18?} {:>18?}",
+            format!("{mode:?}"),
+            wall,
+            held / frames,
+            waited / frames
+        );
+    }
+}
@@ -1,27 +1,64 @@
 use core::{
-    sync::atomic::{AtomicUsize, Ordering},
+    ops::ControlFlow,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
     time::Duration,
 };
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use postcard_schema::{schema::owned::OwnedNamedType, Schema};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use tokio::{sync::mpsc, task::yield_now, time::timeout};
 
 use postcard_rpc::{
-    define_dispatch, endpoints,
-    header::{VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
-    host_client::{test_channels as client, HostClient},
+    auth::{AuthWireRx, AuthWireTx, Authenticator},
+    checksum::{ChecksumWireRx, ChecksumWireTx},
+    compress::{CompressWireRx, CompressWireTx, Compression, Lzss},
+    define_dispatch, define_endpoint, device_info, endpoints,
+    fragment::{FragWireRx, FragWireTx, FragmentInfo, FRAGMENT_KEY},
+    generate_client,
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind, PROTOCOL_VERSION},
+    host_client::{
+        codec::{CodecErr, Json},
+        key_for_path,
+        record::{FrameRecorder, FrameReplayer, ReplayTiming},
+        test_channels as client, ConnectionState, EndpointErr, Health, HealthConfig, HostClient,
+        HostClientBuilder, HostClientConfig, HostClientConfigError, HostErr, MultiSubRxError,
+        RetryPolicy, RpcFrame, SchemaReport, SeqNoGenerator, SubscribeError, UnmatchedKind,
+    },
+    key_hex,
     server::{
+        device_request::{DeviceRequestDispatch, DeviceRequests},
+        dyn_dispatch::{DynDispatcher, Handler, HandlerFuture},
+        impls::channel::{ChannelWire, Frame},
         impls::test_channels::{
             dispatch_impl::{
-                new_server, new_server_stoppable, spawn_fn, Settings, WireSpawnImpl, WireTxImpl,
+                loopback, new_server, new_server_stoppable, spawn_fn, Settings, WireSpawnImpl,
+                WireTxImpl,
             },
             ChannelWireRx, ChannelWireSpawn, ChannelWireTx,
         },
-        Dispatch, Sender, SpawnContext,
+        log_topic::TopicLogger,
+        offload::{OffloadSpawn, Offloader},
+        pool::{
+            PoolFrontend, PoolQueue, PoolWorker, PriorityFrontend, PriorityQueue,
+            PriorityQueueFrontend, PriorityQueueWorker,
+        },
+        AsWireRxErrorKind, CancelToken, Deadline, Dispatch, Interceptor, Liveness, LoopEvent,
+        OffloadError, ReplyToken, Sender, Server, Service, Shutdown, SpawnContext, SpawnContextFor,
+        WireOffload, WireRx, WireRxErrorKind,
+    },
+    standard_icd::{
+        fault_hash, DeviceInfo, GetCreditsEndpoint, LogLevel, OwnedEndpointInfo, OwnedLogRecord,
+        WireError, ERROR_KEY, ERROR_PATH,
     },
-    topics, Endpoint, Topic,
+    test_utils::MockServer,
+    topics,
+    upload::{UploadAck, UploadChunk},
+    Endpoint, FrameDirection, Key, PostcardRpcCommands, Topic,
 };
 
 #[derive(Serialize, Deserialize, Schema)]
@@ -45,7 +82,76 @@ pub struct EReq;
 #[derive(Serialize, Deserialize, Schema)]
 pub struct EResp;
 #[derive(Serialize, Deserialize, Schema)]
+pub struct SReq(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct SResp(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct CReq;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct CResp;
+#[derive(Serialize, Deserialize, Schema)]
 pub struct ZMsg(pub i16);
+pub type HalfResult = Result<u32, OddError>;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct NameReq<'a> {
+    pub name: &'a str,
+}
+#[derive(Serialize, Deserialize, Schema)]
+pub struct Blob(pub Vec<u8>);
+
+/// The number of times a `Counted` was deserialized
+static COUNTED: AtomicUsize = AtomicUsize::new(0);
+
+/// A request counting how often it is deserialized
+#[derive(Serialize, Schema)]
+pub struct Counted(pub Vec<u8>);
+
+impl<'de> Deserialize<'de> for Counted {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        COUNTED.fetch_add(1, Ordering::Relaxed);
+        Vec::deserialize(deserializer).map(Counted)
+    }
+}
+
+#[define_endpoint(path = "omega")]
+#[response(OResp)]
+#[derive(Debug, PartialEq)]
+pub struct OmegaReq(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct OResp(pub u8);
+
+#[define_endpoint(path = "flaky", idempotent)]
+#[response(u8)]
+pub struct FlakyReq(pub u8);
+
+/// An unbounded request, with a declared cap
+#[define_endpoint(path = "capped", max_req_size = 8)]
+#[response(u32)]
+pub struct CappedReq(pub Vec<u8>);
+
+/// The second version of a tolerant request, which gained a field
+#[define_endpoint(path = "evolve", tolerant)]
+#[response(u32)]
+pub struct EvolveReq {
+    pub a: u8,
+    #[serde(default)]
+    pub b: u16,
+}
+
+/// The first version of [`EvolveReq`], as sent by older clients
+#[define_endpoint(path = "evolve", name = EvolveEndpointV1, tolerant)]
+#[response(u32)]
+pub struct EvolveReqV1 {
+    pub a: u8,
+}
+
+#[derive(Debug, PartialEq, PostcardRpcCommands)]
+pub enum Command {
+    #[rpc(path = "cmd/stop")]
+    Stop(u8),
+    #[rpc(path = "cmd/echo", response = String, name = EchoCmdEndpoint, idempotent)]
+    Echo(String),
+}
 
 #[cfg(feature = "alpha")]
 #[derive(Serialize, Deserialize, Schema)]
@@ -74,6 +180,22 @@ endpoints! {
     | GammaEndpoint     | GReq                  | GResp                 | "gamma"           |                        |
     | DeltaEndpoint     | DReq                  | DResp                 | "delta"           |                        |
     | EpsilonEndpoint   | EReq                  | EResp                 | "epsilon"         |                        |
+    | StreamEndpoint    | SReq                  | SResp                 | "stream"          |                        |
+    | CancelEndpoint    | CReq                  | CResp                 | "cancel"          |                        |
+    | SleepEndpoint     | u32                   | u32                   | "sleep"           |                        |
+    | TryEndpoint       | u32                   | u32                   | "try"             |                        |
+    | TryBlockingEndpoint | u32                 | u32                   | "try/blocking"    |                        |
+    | FaultEndpoint     | u32                   | u32                   | "fault"           |                        |
+    | WorkEndpoint      | u32                   | u32                   | "work"            |                        |
+    | CredentialEndpoint | u32                  | u64                   | "credential"      |                        |
+    | HalfEndpoint      | u32                   | HalfResult            | "half"            |                        |
+    | NameEndpoint      | NameReq<'a>           | u32                   | "name"            |                        |
+    | BlobEndpoint      | Blob                  | Blob                  | "blob"            |                        |
+    | RawBodyEndpoint   | NameReq<'a>           | Blob                  | "raw_body"        |                        |
+    | TriggerEndpoint   | ()                    | ()                    | "trigger"         |                        |
+    | UnlockEndpoint    | ()                    | u32                   | "unlock"          |                        |
+    | CountedEndpoint   | Counted               | u32                   | "counted"         |                        |
+    | UploadEndpoint    | UploadChunk<'a>       | UploadAck             | "upload"          |                        |
     | BorrowEndpoint1   | Message<'a>           | u8                    | "borrow1"         | cfg(feature = "alpha") |
     | BorrowEndpoint2   | ()                    | Message<'a>           | "borrow2"         |                        |
     | BorrowEndpoint3   | Message<'a>           | Message<'b>           | "borrow3"         |                        |
@@ -92,6 +214,7 @@ topics! {
     | BorrowTopic   | DoubleMessage<'a, 'b> | "msg1"    | cfg(not(feature = "alpha"))   |
     | BerpTopic1    | u8                    | "empty"   |                               |
     | BerpTopic2    | ()                    | "empty"   |                               |
+    | CommandTopic  | ZMsg                  | "command" |                               |
 }
 
 topics! {
@@ -102,6 +225,15 @@ topics! {
     | ZetaTopic10       | ZMsg          | "zeta10"          |
 }
 
+generate_client! {
+    /// A typed client for some of the test endpoints
+    pub trait TestClient {
+        AlphaEndpoint,
+        TryEndpoint,
+        crate::HalfEndpoint as halve,
+    }
+}
+
 pub struct TestContext {
     pub ctr: Arc<AtomicUsize>,
     pub topic_ctr: Arc<AtomicUsize>,
@@ -113,6 +245,17 @@ pub struct TestSpawnContext {
     pub topic_ctr: Arc<AtomicUsize>,
 }
 
+impl Default for TestContext {
+    /// A context with fresh counters
+    fn default() -> Self {
+        Self {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: Arc::new(AtomicUsize::new(0)),
+            msg: String::from("hello"),
+        }
+    }
+}
+
 impl SpawnContext for TestContext {
     type SpawnCtxt = TestSpawnContext;
 
@@ -124,12 +267,87 @@ impl SpawnContext for TestContext {
     }
 }
 
+/// A [`SingleDispatcher`] with a fresh context
+fn test_app() -> SingleDispatcher {
+    SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {})
+}
+
+/// Run `app` on a loopback connection in the background, evaluating to its client,
+/// made with the given [`HostClientConfig`] or the default one
+///
+/// A macro rather than a function, as the futures of a generic `Dispatch` impl are
+/// not known to be `Send`.
+macro_rules! serve {
+    ($app:expr) => {
+        serve!($app, &HostClientConfig::default())
+    };
+    ($app:expr, $config:expr) => {{
+        let (client_tx, client_rx) = serve_raw!($app);
+        client::new_from_channels_with_config(client_tx, client_rx, $config)
+    }};
+}
+
+/// Like [`serve!`], but evaluating to the channels of the connection, for tests
+/// sending and checking frames by hand
+macro_rules! serve_raw {
+    ($app:expr) => {{
+        let app = $app;
+        let (client_tx, server_rx) = mpsc::channel::<Vec<u8>>(16);
+        let (server_tx, client_rx) = mpsc::channel::<Vec<u8>>(16);
+        let kkind = app.min_key_len();
+        let mut server = new_server(
+            app,
+            Settings {
+                tx: ChannelWireTx::new(server_tx),
+                rx: ChannelWireRx::new(server_rx),
+                buf: 1024,
+                kkind,
+            },
+        );
+        tokio::task::spawn(async move {
+            server.run().await;
+        });
+        (client_tx, client_rx)
+    }};
+}
+
+/// Records the order of calls into a shared log, and rejects the `deny` key
+#[derive(Default)]
+pub struct Recorder {
+    pub name: &'static str,
+    pub log: Arc<Mutex<Vec<String>>>,
+    pub deny: Option<VarKey>,
+}
+
+impl Interceptor for Recorder {
+    async fn before(&mut self, hdr: &VarHeader, _body: &[u8]) -> ControlFlow<WireError> {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{}.before", self.name));
+        if self.deny == Some(hdr.key) {
+            return ControlFlow::Break(WireError::Rejected);
+        }
+        ControlFlow::Continue(())
+    }
+
+    async fn after(&mut self, _hdr: &VarHeader) {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{}.after", self.name));
+    }
+}
+
 define_dispatch! {
     app: SingleDispatcher;
     spawn_fn: spawn_fn;
     tx_impl: WireTxImpl;
     spawn_impl: WireSpawnImpl;
     context: TestContext;
+    interceptors: [Recorder, Recorder];
+    max_in_flight: 2;
+    reply_buf: 64;
 
     endpoints: {
         list: crate::ENDPOINT_LIST;
@@ -140,6 +358,19 @@ define_dispatch! {
         | BetaEndpoint      | spawn     | test_beta_handler         |
         | BorrowEndpoint1   | blocking  | test_borrowep_blocking    |
         | BorrowEndpoint2   | blocking  | test_borrowep_blocking2   |
+        | StreamEndpoint    | stream    | test_stream_handler       |
+        | CancelEndpoint    | cancellable | test_cancel_handler     |
+        | SleepEndpoint     | async     | test_sleep_handler [timeout_ms = 100] |
+        | TryEndpoint       | async_try | test_try_handler [timeout_ms = 100] |
+        | TryBlockingEndpoint | blocking_try | test_try_blocking      |
+        | FaultEndpoint     | blocking  | test_fault_handler        |
+        | WorkEndpoint      | async_deadline | test_work_handler    |
+        | HalfEndpoint      | async     | test_half_handler         |
+        | NameEndpoint      | async     | test_name_handler         |
+        | TriggerEndpoint   | blocking  | test_trigger_handler      |
+        | RawBodyEndpoint   | async_raw | test_raw_body_handler     |
+
+        _ => async test_unknown_handler;
     };
     topics_in: {
         list: crate::TOPICS_IN_LIST;
@@ -150,6 +381,7 @@ define_dispatch! {
         | ZetaTopic2        | async     | test_zeta_async       |
         | ZetaTopic3        | spawn     | test_zeta_spawn       |
         | BorrowTopic       | blocking  | test_borrow_blocking  |
+        | CommandTopic      | topic     | test_command_topic    |
     };
     topics_out: {
         list: TOPICS_OUT_LIST;
@@ -227,6 +459,154 @@ async fn test_beta_handler(
         .await;
 }
 
+async fn test_stream_handler(
+    context: &mut TestContext,
+    header: VarHeader,
+    body: SReq,
+    out: &Sender<ChannelWireTx>,
+) {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    for i in 0..body.0 {
+        let _ = out
+            .reply_chunk::<StreamEndpoint>(header.seq_no, &SResp(i))
+            .await;
+    }
+}
+
+async fn test_cancel_handler(
+    context: TestSpawnContext,
+    header: VarHeader,
+    _body: CReq,
+    out: Sender<ChannelWireTx>,
+    token: CancelToken,
+) {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    while !token.is_cancelled() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let _ = out.error(header.seq_no, WireError::Cancelled).await;
+}
+
+fn test_trigger_handler(context: &mut TestContext, _header: VarHeader, _body: ()) {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+}
+
+async fn test_sleep_handler(_context: &mut TestContext, _header: VarHeader, body: u32) -> u32 {
+    tokio::time::sleep(Duration::from_millis(body.into())).await;
+    body
+}
+
+fn test_command_topic(context: &mut TestContext, _header: VarHeader, body: ZMsg) {
+    context
+        .topic_ctr
+        .fetch_add(body.0 as usize, Ordering::Relaxed);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Schema)]
+pub struct OddError;
+
+impl From<OddError> for WireError {
+    fn from(_: OddError) -> Self {
+        WireError::Rejected
+    }
+}
+
+fn only_even(body: u32) -> Result<u32, OddError> {
+    if body.is_multiple_of(2) {
+        Ok(body / 2)
+    } else {
+        Err(OddError)
+    }
+}
+
+async fn test_try_handler(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    body: u32,
+) -> Result<u32, OddError> {
+    let half = only_even(body)?;
+    Ok(half)
+}
+
+fn test_try_blocking(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    body: u32,
+) -> Result<u32, OddError> {
+    only_even(body)
+}
+
+async fn test_work_handler(
+    context: &mut TestContext,
+    _header: VarHeader,
+    steps: u32,
+    deadline: Deadline,
+) -> Result<u32, WireError> {
+    for _ in 0..steps {
+        deadline.check()?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        context.ctr.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(steps)
+}
+
+fn test_fault_handler(_context: &mut TestContext, _header: VarHeader, body: u32) -> u32 {
+    if body % 2 == 1 {
+        panic!("odd request {body}");
+    }
+    body
+}
+
+async fn test_half_handler(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    body: u32,
+) -> HalfResult {
+    only_even(body)
+}
+
+async fn test_name_handler(
+    context: &mut TestContext,
+    _header: VarHeader,
+    body: NameReq<'_>,
+) -> u32 {
+    // The name is borrowed from the receive buffer
+    tokio::task::yield_now().await;
+    context.msg.clear();
+    context.msg.push_str(body.name);
+    body.name.len() as u32
+}
+
+async fn test_raw_body_handler(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    req: NameReq<'_>,
+    body: &[u8],
+) -> Blob {
+    // The body is exactly the serialized request
+    assert_eq!(body[1..], *req.name.as_bytes());
+    Blob(body.to_vec())
+}
+
+async fn test_blob_handler(_context: &mut TestContext, _header: VarHeader, mut body: Blob) -> Blob {
+    body.0.reverse();
+    body
+}
+
+async fn test_unknown_handler(
+    _context: &mut TestContext,
+    header: VarHeader,
+    _body: &[u8],
+    sender: &Sender<WireTxImpl>,
+) {
+    // Gamma is listed, but has no handler of its own
+    if header.key == VarKey::Key8(GammaEndpoint::REQ_KEY) {
+        let _ = sender.reply::<GammaEndpoint>(header.seq_no, &GResp).await;
+    } else {
+        let _ = sender.error(header.seq_no, WireError::UnknownKey).await;
+    }
+}
+
 #[tokio::test]
 async fn smoke() {
     let (client_tx, server_rx) = mpsc::channel(16);
@@ -409,201 +789,4331 @@ async fn end_to_end() {
     assert_eq!(resp.0, 42);
     let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
     assert_eq!(resp.0, 1234);
+
+    // Metadata comes back with the matching response, or the error
+    let (a, b) = tokio::join!(
+        cli.send_resp_with_meta::<AlphaEndpoint, _>(&AReq(1), "first"),
+        cli.send_resp_with_meta::<AlphaEndpoint, _>(&AReq(2), "second"),
+    );
+    assert_eq!((a.0.unwrap().0, a.1), (1, "first"));
+    assert_eq!((b.0.unwrap().0, b.1), (2, "second"));
+    let (resp, meta) = cli
+        .send_resp_with_meta::<DeltaEndpoint, _>(&DReq, 7u32)
+        .await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
+    assert_eq!(meta, 7);
+
+    let mut stream = cli
+        .send_resp_stream::<StreamEndpoint>(&SReq(5))
+        .await
+        .unwrap();
+    let mut got = vec![];
+    while let Some(chunk) = stream.recv().await {
+        got.push(chunk.unwrap().0);
+    }
+    assert_eq!(got, [0, 1, 2, 3, 4]);
+    assert!(stream.recv().await.is_none());
+
+    // An empty stream ends immediately
+    let mut stream = cli
+        .send_resp_stream::<StreamEndpoint>(&SReq(0))
+        .await
+        .unwrap();
+    assert!(stream.recv().await.is_none());
+
+    // Regular requests still work afterwards
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+
+    // Cancellable requests run until cancelled
+    let (handle, fut) = cli.send_resp_cancellable::<CancelEndpoint>(&CReq);
+    let cancel = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.cancel().await.unwrap();
+        Instant::now()
+    };
+    let (resp, cancelled_at) = tokio::join!(timeout(Duration::from_secs(1), fut), cancel);
+    assert!(matches!(resp, Ok(Err(HostErr::Wire(WireError::Cancelled)))));
+    assert!(cancelled_at.elapsed() < Duration::from_secs(1));
+
+    // Handlers that finish in time reply as usual, others time out
+    let resp = cli.send_resp::<SleepEndpoint>(&10).await.unwrap();
+    assert_eq!(resp, 10);
+    let resp = cli.send_resp::<SleepEndpoint>(&1000).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Timeout))));
+
+    // The device reports every key it was compiled with
+    let keys = cli.get_device_map().await.unwrap();
+    assert!(keys.missing_endpoints(&ENDPOINT_LIST).is_empty());
+    assert!(keys.missing_topics(&TOPICS_IN_LIST).is_empty());
+    assert!(keys.missing_topics(&TOPICS_OUT_LIST).is_empty());
+    assert!(keys.endpoints.iter().any(|(path, _, _)| path == "alpha"));
+    assert!(keys
+        .missing_topics(&postcard_rpc::standard_icd::STANDARD_ICD_TOPICS_IN)
+        .is_empty());
+
+    // The device info defaults to the crate using `define_dispatch!`
+    let info = cli.device_info().await.unwrap();
+    assert_eq!(info.product, "postcard-rpc-test");
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+    // Fallible handlers reply with either the response or an error
+    assert_eq!(cli.send_resp::<TryEndpoint>(&8).await.unwrap(), 4);
+    let resp = cli.send_resp::<TryEndpoint>(&7).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Rejected))));
+    assert_eq!(cli.send_resp::<TryBlockingEndpoint>(&8).await.unwrap(), 4);
+    let resp = cli.send_resp::<TryBlockingEndpoint>(&7).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Rejected))));
+
+    // Application errors are decoded into the error type of the endpoint
+    assert_eq!(cli.send_resp_fallible::<HalfEndpoint>(&8).await, Ok(4));
+    let resp = cli.send_resp_fallible::<HalfEndpoint>(&7).await;
+    assert_eq!(resp, Err(EndpointErr::App(OddError)));
+
+    // Borrowed requests are deserialized from the receive buffer
+    let resp = cli
+        .send_resp::<NameEndpoint>(&NameReq { name: "borrowed" })
+        .await
+        .unwrap();
+    assert_eq!(resp, 8);
+
+    // Raw body handlers also get the request as received
+    let req = NameReq { name: "raw" };
+    let resp = cli.send_resp::<RawBodyEndpoint>(&req).await.unwrap();
+    assert_eq!(resp.0, postcard::to_stdvec(&req).unwrap());
+
+    // Panics in blocking handlers are reported as faults, and the server keeps running
+    let resp = cli.send_resp::<FaultEndpoint>(&7).await;
+    let message_hash = fault_hash("odd request 7");
+    assert!(matches!(resp, Err(HostErr::HandlerFault { message_hash: h }) if h == message_hash));
+    assert_eq!(cli.send_resp::<FaultEndpoint>(&8).await.unwrap(), 8);
+
+    // Unknown keys go to the catch-all handler
+    cli.send_resp::<GammaEndpoint>(&GReq).await.unwrap();
+    let resp = cli.send_resp::<DeltaEndpoint>(&DReq).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
 }
 
 #[tokio::test]
-async fn end_to_end_schema() {
-    let (client_tx, server_rx) = mpsc::channel(16);
-    let (server_tx, client_rx) = mpsc::channel(16);
+async fn end_to_end_loopback() {
     let topic_ctr = Arc::new(AtomicUsize::new(0));
-
     let app = SingleDispatcher::new(
         TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
             topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
+            ..TestContext::default()
         },
         ChannelWireSpawn {},
     );
-
-    let cwrx = ChannelWireRx::new(server_rx);
-    let cwtx = ChannelWireTx::new(server_tx);
-
-    let kkind = app.min_key_len();
-    let report = app.device_map;
-    let mut server = new_server(
-        app,
-        Settings {
-            tx: cwtx,
-            rx: cwrx,
-            buf: 1024,
-            kkind,
-        },
-    );
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq2);
+    let sender = server.sender();
     tokio::task::spawn(async move {
         server.run().await;
     });
 
-    let cli: HostClient<_> = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
-    let schema = cli.get_schema_report().await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
 
-    for ep in &schema.endpoints {
-        println!("'{}': {} -> {}", ep.path, ep.req_ty, ep.resp_ty);
+    // Pings are handled automatically
+    let rtt = cli.ping().await.unwrap();
+    assert!(rtt < Duration::from_secs(1));
+
+    // The server advertises how many requests it can buffer
+    assert_eq!(cli.enable_flow_control().await.unwrap(), 2);
+
+    // Batched requests reply in order, with more requests than may be in-flight
+    let reqs: Vec<AReq> = (0..100).map(AReq).collect();
+    let resps = cli.send_batch::<AlphaEndpoint>(&reqs).await;
+    assert_eq!(resps.len(), 100);
+    for (i, resp) in resps.into_iter().enumerate() {
+        assert_eq!(resp.unwrap().0 as usize, i);
     }
-    for tp in &schema.topics_in {
-        println!("'{}' ---> {}", tp.path, tp.ty);
+
+    // Topics from the client reach the dispatcher
+    cli.publish::<ZetaTopic2>(VarSeq::Seq2(1), &ZMsg(56))
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(1), async {
+        while topic_ctr.load(Ordering::Relaxed) == 0 {
+            yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Fire-and-forget topic handlers get the message, without a sender
+    let before = topic_ctr.load(Ordering::Relaxed);
+    cli.publish::<CommandTopic>(VarSeq::Seq2(4), &ZMsg(10))
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(1), async {
+        while topic_ctr.load(Ordering::Relaxed) != before + 10 {
+            yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Topics from the server reach the client
+    let mut sub = cli.subscribe_multi::<ZetaTopic10>(8).await.unwrap();
+    sender
+        .publish::<ZetaTopic10>(VarSeq::Seq2(2), &ZMsg(78))
+        .await
+        .unwrap();
+    let msg = timeout(Duration::from_secs(1), sub.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.0, 78);
+}
+
+#[tokio::test]
+async fn end_to_end_callbacks() {
+    let cli = serve!(test_app());
+
+    // Replies are passed to the callbacks as they arrive
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for i in 0..100 {
+        let tx = tx.clone();
+        cli.send_cb::<AlphaEndpoint, _>(&AReq(i), move |resp| {
+            tx.send((i, resp.map(|r| r.0))).unwrap();
+        })
+        .await;
     }
-    for tp in &schema.topics_out {
-        println!("'{}' <--- {}", tp.path, tp.ty);
+    for _ in 0..100 {
+        let (i, resp) = rx.recv().await.unwrap();
+        assert_eq!(resp.unwrap(), i);
     }
 
-    assert_eq!(schema.endpoints.len(), report.endpoints.len());
-    assert_eq!(schema.topics_in.len(), report.topics_in.len());
-    assert_eq!(schema.topics_out.len(), report.topics_out.len());
+    // Errors are decoded like with `send_resp`
+    cli.send_cb::<TryEndpoint, _>(&7, move |resp| {
+        tx.send((7, resp.map(|_| 0))).unwrap();
+    })
+    .await;
+    let (_, resp) = rx.recv().await.unwrap();
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Rejected))));
+
+    // Callbacks waiting for a reply are failed when the client closes
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tx2 = tx.clone();
+    cli.send_cb::<SleepEndpoint, _>(&1000, move |resp| tx2.send(resp).unwrap())
+        .await;
+    cli.close();
+    let resp = rx.recv().await.unwrap();
+    assert!(matches!(resp, Err(HostErr::Closed)));
+    cli.send_cb::<SleepEndpoint, _>(&0, move |resp| tx.send(resp).unwrap())
+        .await;
+    let resp = rx.recv().await.unwrap();
+    assert!(matches!(resp, Err(HostErr::Closed)));
 }
 
 #[tokio::test]
-async fn end_to_end_force8() {
-    let (client_tx, server_rx) = mpsc::channel(16);
-    let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
+async fn unmatched_replies() {
+    let (cli, mut server_rx, server_tx) = fake_device(&HostClientConfig::default());
+    let (hook_tx, mut hook_rx) = mpsc::unbounded_channel();
+    cli.set_unmatched_hook(move |unmatched| hook_tx.send(*unmatched).unwrap());
+    cli.set_unmatched_warnings(true);
 
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
+    // The reply arrives after the request timed out
+    let resp = cli
+        .send_resp_timeout::<AlphaEndpoint>(&AReq(42), Duration::from_millis(10))
+        .await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+    let req = server_rx.recv().await.unwrap();
+    let (hdr, _body) = VarHeader::take_from_slice(&req).unwrap();
+    let late = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::RESP_KEY),
+            seq_no: hdr.seq_no,
         },
-        ChannelWireSpawn {},
-    );
-
-    let cwrx = ChannelWireRx::new(server_rx);
-    let cwtx = ChannelWireTx::new(server_tx);
+        body: postcard::to_stdvec(&AResp(42)).unwrap(),
+    };
+    server_tx.send(late.to_bytes()).await.unwrap();
+    let unmatched = hook_rx.recv().await.unwrap();
+    assert_eq!(unmatched.header, late.header);
+    assert_eq!(unmatched.kind, UnmatchedKind::Expired);
 
-    let kkind = VarKeyKind::Key8;
-    let mut server = new_server(
-        app,
-        Settings {
-            tx: cwtx,
-            rx: cwrx,
-            buf: 1024,
-            kkind,
+    // A reply to a request that was never sent
+    let stray = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::RESP_KEY),
+            seq_no: VarSeq::Seq1(200),
         },
-    );
-    tokio::task::spawn(async move {
-        server.run().await;
-    });
-
-    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+        body: postcard::to_stdvec(&AResp(1)).unwrap(),
+    };
+    server_tx.send(stray.to_bytes()).await.unwrap();
+    let unmatched = hook_rx.recv().await.unwrap();
+    assert_eq!(unmatched.header, stray.header);
+    assert_eq!(unmatched.kind, UnmatchedKind::Unknown);
+    assert_eq!(cli.unmatched_replies(), 2);
 
-    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
-    assert_eq!(resp.0, 42);
-    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
-    assert_eq!(resp.0, 1234);
+    cli.clear_unmatched_hook();
+    server_tx.send(stray.to_bytes()).await.unwrap();
+    // Wait for the I/O worker to drop the frame
+    while cli.unmatched_replies() < 3 {
+        yield_now().await;
+    }
+    assert!(hook_rx.try_recv().is_err());
 }
 
-#[test]
-fn device_map() {
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
-    );
-
-    println!("# SingleDispatcher");
-    println!();
+/// Hands out only the sequence numbers 0 to 3, wrapping around after four requests
+#[derive(Default)]
+struct TinySeqNoGenerator {
+    ctr: AtomicU32,
+}
 
-    println!("## Types");
-    println!();
-    for ty in app.device_map.types {
-        let ty = OwnedNamedType::from(*ty);
-        println!("* {ty}");
+impl SeqNoGenerator for TinySeqNoGenerator {
+    fn next(&self) -> u32 {
+        self.ctr.fetch_add(1, Ordering::Relaxed) % 4
     }
 
-    println!();
-    println!("## Endpoints");
-    println!();
-    for ep in app.device_map.endpoints {
-        println!(
-            "* {} ({:016X} -> {:016X})",
-            ep.0,
-            u64::from_le_bytes(ep.1.to_bytes()),
-            u64::from_le_bytes(ep.2.to_bytes()),
-        );
+    fn space(&self) -> u64 {
+        4
     }
+}
 
-    println!();
-    println!("## Topics (In)");
-    println!();
-    for tp in app.device_map.topics_in {
-        println!(
-            "* {} <- ({:016X})",
-            tp.0,
-            u64::from_le_bytes(tp.1.to_bytes()),
-        );
+/// A client whose device is played by the test, receiving the requests and sending
+/// the replies through the returned channels
+fn fake_device(
+    config: &HostClientConfig<'_>,
+) -> (
+    HostClient<WireError>,
+    mpsc::Receiver<Vec<u8>>,
+    mpsc::Sender<Vec<u8>>,
+) {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let cli = client::new_from_channels_with_config(client_tx, client_rx, config);
+    (cli, server_rx, server_tx)
+}
+
+/// Receive an [`AlphaEndpoint`] request sent by a client, with its sequence number
+async fn recv_alpha_req(rx: &mut mpsc::Receiver<Vec<u8>>) -> (VarSeq, u8) {
+    let req = rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&req).unwrap();
+    (hdr.seq_no, postcard::from_bytes::<AReq>(body).unwrap().0)
+}
+
+/// The frame of an [`AlphaEndpoint`] request
+fn alpha_request(seq_no: VarSeq, val: u8) -> Vec<u8> {
+    let mut frame = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no,
     }
+    .write_to_vec();
+    frame.extend_from_slice(&postcard::to_stdvec(&AReq(val)).unwrap());
+    frame
+}
 
-    println!();
-    println!("## Topics (Out)");
-    println!();
-    for tp in app.device_map.topics_out {
-        println!(
-            "* {} -> ({:016X})",
-            tp.0,
-            u64::from_le_bytes(tp.1.to_bytes()),
-        );
+/// The frame of an [`AlphaEndpoint`] reply
+fn alpha_reply(seq_no: VarSeq, val: u8) -> Vec<u8> {
+    RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::RESP_KEY),
+            seq_no,
+        },
+        body: postcard::to_stdvec(&AResp(val)).unwrap(),
     }
-    println!();
-    println!("## Min Key Length");
-    println!();
-    println!("{:?}", app.device_map.min_key_len);
-    println!();
+    .to_bytes()
 }
 
 #[tokio::test]
-async fn end_to_end_stoppable() {
-    let (client_tx, server_rx) = mpsc::channel(16);
-    let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
+async fn seq_no_wraparound() {
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .seq_no_generator(Arc::new(TinySeqNoGenerator::default()))
+        .build()
+        .unwrap();
+    let (cli, mut server_rx, server_tx) = fake_device(&config);
+    // Fill the sequence space with pending requests
+    let mut tasks = vec![];
+    let mut seqs = vec![];
+    for val in 0..4 {
+        let cli = cli.clone();
+        tasks.push(tokio::task::spawn(async move {
+            cli.send_resp::<AlphaEndpoint>(&AReq(val)).await
+        }));
+        let (seq_no, got) = recv_alpha_req(&mut server_rx).await;
+        assert_eq!(got, val);
+        seqs.push(seq_no);
+    }
+    assert_eq!(seqs, [0, 1, 2, 3].map(VarSeq::Seq4));
 
-    let app = SingleDispatcher::new(
+    // No free sequence number is left, the request is not sent
+    let res = cli.send_resp::<AlphaEndpoint>(&AReq(9)).await;
+    assert!(matches!(res, Err(HostErr::SeqNoExhausted)));
+
+    // Completing one request frees its number, and the generator wraps around,
+    // skipping numbers still in use
+    server_tx
+        .send(alpha_reply(VarSeq::Seq4(2), 2))
+        .await
+        .unwrap();
+    assert_eq!(tasks.remove(2).await.unwrap().unwrap().0, 2);
+    let late = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(10)).await }
+    });
+    let (seq_no, got) = recv_alpha_req(&mut server_rx).await;
+    assert_eq!((seq_no, got), (VarSeq::Seq4(2), 10));
+
+    // Every reply still reaches its own request
+    server_tx
+        .send(alpha_reply(VarSeq::Seq4(2), 10))
+        .await
+        .unwrap();
+    for (seq_no, val) in [(3, 3), (0, 0), (1, 1)] {
+        server_tx
+            .send(alpha_reply(VarSeq::Seq4(seq_no), val))
+            .await
+            .unwrap();
+    }
+    assert_eq!(late.await.unwrap().unwrap().0, 10);
+    for (task, val) in tasks.into_iter().zip([0, 1, 3]) {
+        assert_eq!(task.await.unwrap().unwrap().0, val);
+    }
+
+    // All numbers are free again
+    let res = tokio::join!(cli.send_resp::<AlphaEndpoint>(&AReq(20)), async {
+        let (seq_no, val) = recv_alpha_req(&mut server_rx).await;
+        server_tx.send(alpha_reply(seq_no, val)).await.unwrap();
+    });
+    assert_eq!(res.0.unwrap().0, 20);
+}
+
+#[tokio::test]
+async fn flow_control_limits_in_flight() {
+    let (cli, mut server_rx, server_tx) = fake_device(&HostClientConfig::default());
+
+    // The server advertises room for two requests
+    let (credits, ()) = tokio::join!(cli.enable_flow_control(), async {
+        let req = server_rx.recv().await.unwrap();
+        let (hdr, _) = VarHeader::take_from_slice(&req).unwrap();
+        assert_eq!(hdr.key, VarKey::Key8(GetCreditsEndpoint::REQ_KEY));
+        let frame = RpcFrame {
+            header: VarHeader {
+                key: VarKey::Key8(GetCreditsEndpoint::RESP_KEY),
+                seq_no: hdr.seq_no,
+            },
+            body: postcard::to_stdvec(&2u32).unwrap(),
+        };
+        server_tx.send(frame.to_bytes()).await.unwrap();
+    });
+    assert_eq!(credits.unwrap(), 2);
+
+    let tasks: Vec<_> = (0..3)
+        .map(|val| {
+            let cli = cli.clone();
+            tokio::task::spawn(async move { cli.send_resp::<AlphaEndpoint>(&AReq(val)).await })
+        })
+        .collect();
+
+    // Two requests are sent, the third waits for a credit
+    let first = recv_alpha_req(&mut server_rx).await;
+    let second = recv_alpha_req(&mut server_rx).await;
+    let waiting = timeout(Duration::from_millis(50), server_rx.recv()).await;
+    assert!(waiting.is_err(), "the third request was sent early");
+
+    // Completing a request returns its credit
+    server_tx.send(alpha_reply(first.0, first.1)).await.unwrap();
+    let third = recv_alpha_req(&mut server_rx).await;
+    server_tx
+        .send(alpha_reply(second.0, second.1))
+        .await
+        .unwrap();
+    server_tx.send(alpha_reply(third.0, third.1)).await.unwrap();
+
+    for (task, val) in tasks.into_iter().zip(0..) {
+        assert_eq!(task.await.unwrap().unwrap().0, val);
+    }
+}
+
+/// Hands out only the sequence number 0
+struct SingleSeqNoGenerator;
+
+impl SeqNoGenerator for SingleSeqNoGenerator {
+    fn next(&self) -> u32 {
+        0
+    }
+
+    fn space(&self) -> u64 {
+        1
+    }
+}
+
+#[tokio::test]
+async fn seq_no_claimed_atomically() {
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .seq_no_generator(Arc::new(SingleSeqNoGenerator))
+        .build()
+        .unwrap();
+    let (cli, mut server_rx, server_tx) = fake_device(&config);
+
+    // The number is claimed when the request is created, before it is sent, so
+    // only the first of two requests gets it
+    let (_handle_a, fut_a) = cli.send_resp_cancellable::<AlphaEndpoint>(&AReq(1));
+    let (_handle_b, fut_b) = cli.send_resp_cancellable::<AlphaEndpoint>(&AReq(2));
+    let (a, b, _) = tokio::join!(fut_a, fut_b, async {
+        let (seq_no, val) = recv_alpha_req(&mut server_rx).await;
+        server_tx.send(alpha_reply(seq_no, val)).await.unwrap();
+    });
+    assert_eq!(a.unwrap().0, 1);
+    assert!(matches!(b, Err(HostErr::SeqNoExhausted)));
+    assert!(server_rx.try_recv().is_err());
+}
+
+/// Hands out every seventh sequence number, starting at 1000
+struct SteppingSeqNoGenerator {
+    ctr: AtomicU32,
+}
+
+impl SeqNoGenerator for SteppingSeqNoGenerator {
+    fn next(&self) -> u32 {
+        self.ctr.fetch_add(7, Ordering::Relaxed)
+    }
+}
+
+#[tokio::test]
+async fn custom_seq_no_generator() {
+    let mut config = HostClientConfig::default();
+    config.seq_no_generator = Some(Arc::new(SteppingSeqNoGenerator {
+        ctr: AtomicU32::new(1000),
+    }));
+    let (cli, mut server_rx, server_tx) = fake_device(&config);
+
+    // Requests carry the numbers of the generator, and get their replies
+    for (val, expected) in [(1, 1000), (2, 1007), (3, 1014)] {
+        let req = AReq(val);
+        let (res, _) = tokio::join!(cli.send_resp::<AlphaEndpoint>(&req), async {
+            let (seq_no, got) = recv_alpha_req(&mut server_rx).await;
+            assert_eq!((seq_no, got), (VarSeq::Seq4(expected), val));
+            server_tx.send(alpha_reply(seq_no, got)).await.unwrap();
+        });
+        assert_eq!(res.unwrap().0, val);
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_codec() {
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(SingleDispatcher::new(
         TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
             topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
+            ..TestContext::default()
         },
         ChannelWireSpawn {},
-    );
+    ));
 
-    let cwrx = ChannelWireRx::new(server_rx);
-    let cwtx = ChannelWireTx::new(server_tx);
+    // Requests and responses are converted from and to JSON on the host
+    let json = cli.with_codec(Json);
+    let resp = json.send_resp::<AlphaEndpoint>(b"42").await.unwrap();
+    assert_eq!(resp, b"42");
+    let resp = json.send_resp::<AlphaEndpoint>(b"\"nope\"").await;
+    assert!(matches!(resp, Err(CodecErr::Codec(_))));
+    let resp = json.send_resp::<HalfEndpoint>(b"8").await.unwrap();
+    assert_eq!(resp, br#"{"Ok":4}"#);
 
-    let kkind = app.min_key_len();
-    let (mut server, stopper) = new_server_stoppable(
-        app,
-        Settings {
-            tx: cwtx,
-            rx: cwrx,
-            buf: 1024,
-            kkind,
-        },
+    json.publish::<ZetaTopic2>(VarSeq::Seq2(1), b"56")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(topic_ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn end_to_end_generated_client() {
+    let cli = serve!(test_app());
+
+    assert_eq!(cli.alpha(&AReq(42)).await.unwrap().0, 42);
+    assert_eq!(cli.r#try(&4).await.unwrap(), 2);
+    assert!(matches!(cli.r#try(&3).await, Err(HostErr::Wire(_))));
+    assert_eq!(cli.halve(&8).await.unwrap(), Ok(4));
+}
+
+#[tokio::test]
+async fn end_to_end_interceptors() {
+    let mut app = test_app();
+    let log = Arc::new(Mutex::new(vec![]));
+    let (outer, (inner, ())) = &mut app.interceptors;
+    outer.name = "outer";
+    outer.log = log.clone();
+    inner.name = "inner";
+    inner.log = log.clone();
+    inner.deny = Some(VarKey::Key8(BetaEndpoint::REQ_KEY));
+
+    let cli = serve!(app);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert_eq!(
+        core::mem::take(&mut *log.lock().unwrap()),
+        ["outer.before", "inner.before", "inner.after", "outer.after"],
     );
-    let hdl = tokio::task::spawn(async move {
+
+    // Rejected requests skip the handler, and the hooks of the rejecting interceptor
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Rejected))));
+    assert_eq!(
+        core::mem::take(&mut *log.lock().unwrap()),
+        ["outer.before", "inner.before", "outer.after"],
+    );
+}
+
+#[tokio::test]
+async fn end_to_end_body_too_large() {
+    let app = test_app();
+    let (cli, mut server) = loopback(app, 64, VarSeqKind::Seq1);
+    tokio::task::spawn(async move {
         server.run().await;
     });
 
+    // The header is 10 bytes, with a full key and one byte sequence number
+    let frame = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+            seq_no: VarSeq::Seq1(1),
+        },
+        body: vec![0; 100],
+    };
+    let resp = cli.send_resp_raw(frame, AlphaEndpoint::RESP_KEY).await;
+    assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 54 })));
+    assert_eq!(cli.max_request_body(), Some(54));
+
+    // The server is still usable afterwards
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+#[tokio::test]
+async fn oversized_requests_are_not_sent() {
+    assert_eq!(AlphaEndpoint::MAX_REQ_SIZE, Some(1));
+    assert_eq!(SleepEndpoint::MAX_REQ_SIZE, Some(5));
+    assert_eq!(BlobEndpoint::MAX_REQ_SIZE, None);
+    assert_eq!(CappedEndpoint::MAX_REQ_SIZE, Some(8));
+
+    let (client_tx, mut server_rx) = mpsc::channel::<Vec<u8>>(16);
+    let (_server_tx, client_rx) = mpsc::channel::<Vec<u8>>(16);
     let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
 
+    // The declared cap of the endpoint
+    let resp = cli
+        .send_resp::<CappedEndpoint>(&CappedReq(vec![0; 8]))
+        .await;
+    assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 8 })));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    cli.send_cb::<CappedEndpoint, _>(&CappedReq(vec![0; 8]), move |resp| tx.send(resp).unwrap())
+        .await;
+    let resp = rx.recv().await.unwrap();
+    assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 8 })));
+
+    // The limit of the device applies to all endpoints
+    cli.set_max_request_body(Some(4));
+    let resp = cli.send_resp::<BlobEndpoint>(&Blob(vec![0; 4])).await;
+    assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 4 })));
+    let resp = cli
+        .send_resp::<CappedEndpoint>(&CappedReq(vec![0; 4]))
+        .await;
+    assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 4 })));
+
+    // None of them were sent
+    assert!(server_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn end_to_end_unsolicited_errors() {
+    let cli = serve!(test_app());
+    let mut errors = cli.subscribe_errors(8).await.unwrap();
+
+    // Errors for in-flight requests are returned from the request
+    let frame = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+            seq_no: VarSeq::Seq1(1),
+        },
+        body: vec![],
+    };
+    let resp = cli.send_resp_raw(frame, AlphaEndpoint::RESP_KEY).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::DeserFailed))));
+
+    // Errors for requests nobody is waiting for go to the listeners
+    let frame = RpcFrame {
+        header: VarHeader {
+            key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+            seq_no: VarSeq::Seq1(200),
+        },
+        body: vec![],
+    };
+    cli.publish_raw(frame).await.unwrap();
+    let (hdr, err) = timeout(Duration::from_secs(1), errors.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq1(200));
+    assert_eq!(err, WireError::DeserFailed);
+    assert!(timeout(Duration::from_millis(50), errors.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn end_to_end_frame_stream() {
+    let cli = serve!(test_app());
+    let mut frames = cli.frame_stream(8).await.unwrap();
+    let mut frames2 = cli.frame_stream(8).await.unwrap();
+
+    // The response still reaches the request
     let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
     assert_eq!(resp.0, 42);
-    stopper.stop();
-    match timeout(Duration::from_millis(100), hdl).await {
-        Ok(Ok(())) => {}
-        Ok(Err(e)) => panic!("Server task panicked? {e:?}"),
-        Err(_) => panic!("Server task did not stop!"),
+
+    // And every stream sees a copy of it
+    for frames in [&mut frames, &mut frames2] {
+        let frame = timeout(Duration::from_secs(1), frames.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.header.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+        assert_eq!(postcard::from_bytes::<AResp>(&frame.body).unwrap().0, 42);
+    }
+    assert!(timeout(Duration::from_millis(50), frames.recv())
+        .await
+        .is_err());
+
+    // Streams are closed along with the client
+    cli.close();
+    assert!(matches!(
+        frames.recv().await,
+        Err(MultiSubRxError::IoClosed)
+    ));
+}
+
+/// A separate dispatcher, as the shutdown signal is shared by all instances
+mod shutdown {
+    use super::*;
+
+    define_dispatch! {
+        app: ShutdownDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind        | handler               |
+            | ----------        | ----        | -------               |
+            | AlphaEndpoint     | async       | test_alpha_handler    |
+            | CancelEndpoint    | cancellable | test_cancel_handler   |
+            | CountedEndpoint   | blocking    | test_counted_handler  |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | ZetaTopic1        | blocking  | test_zeta_blocking    |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+fn test_counted_handler(_context: &mut TestContext, _header: VarHeader, body: Counted) -> u32 {
+    body.0.len() as u32
+}
+
+#[tokio::test]
+async fn end_to_end_shutdown() {
+    let app = shutdown::ShutdownDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let shutdown = app.shutdown_handle();
+    let spawn = app.spawn.clone();
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq1);
+    let server = tokio::task::spawn(async move {
+        tokio::select! {
+            _ = server.run() => panic!("Server stopped before draining"),
+            _ = shutdown.wait_drained(&spawn) => {}
+        }
+    });
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let resp = cli
+        .send_resp::<CountedEndpoint>(&Counted(vec![0; 512]))
+        .await;
+    assert_eq!(resp.unwrap(), 512);
+    assert_eq!(COUNTED.load(Ordering::Relaxed), 1);
+
+    let (handle, fut) = cli.send_resp_cancellable::<CancelEndpoint>(&CReq);
+    let drain = async {
+        // Wait for the handler to start
+        while shutdown.in_flight() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        shutdown.shutdown();
+
+        // New requests are rejected, but the server keeps running while the
+        // cancellable handler is in-flight
+        let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await;
+        assert!(matches!(resp, Err(HostErr::Wire(WireError::ShuttingDown))));
+        cli.ping().await.unwrap();
+
+        // Rejected requests are not deserialized
+        let resp = cli
+            .send_resp::<CountedEndpoint>(&Counted(vec![0; 512]))
+            .await;
+        assert!(matches!(resp, Err(HostErr::Wire(WireError::ShuttingDown))));
+        assert_eq!(COUNTED.load(Ordering::Relaxed), 1);
+        assert!(!server.is_finished());
+
+        handle.cancel().await.unwrap();
+    };
+    let (resp, ()) = tokio::join!(timeout(Duration::from_secs(1), fut), drain);
+    assert!(matches!(resp, Ok(Err(HostErr::Wire(WireError::Cancelled)))));
+
+    timeout(Duration::from_secs(1), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(shutdown.is_drained());
+}
+
+/// A separate dispatcher, with a handler shutting it down before replying
+mod reset {
+    use super::*;
+
+    pub static SHUTDOWN: std::sync::OnceLock<&'static Shutdown> = std::sync::OnceLock::new();
+
+    async fn test_reset_handler(
+        _context: &mut TestContext,
+        _header: VarHeader,
+        body: AReq,
+    ) -> AResp {
+        SHUTDOWN.get().unwrap().shutdown();
+        AResp(body.0)
+    }
+
+    define_dispatch! {
+        app: ResetDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_reset_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_run_until_drained() {
+    let app = reset::ResetDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let shutdown = app.shutdown_handle();
+    assert!(reset::SHUTDOWN.set(shutdown).is_ok());
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq1);
+    let server = tokio::task::spawn(async move { server.run_until_drained(shutdown).await });
+
+    cli.ping().await.unwrap();
+    assert!(!server.is_finished());
+
+    // The reply acknowledging the shutdown is sent before the server returns
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let res = timeout(Duration::from_secs(1), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(res.is_ok());
+    assert!(shutdown.is_drained());
+}
+
+mod deferred {
+    use super::*;
+
+    pub type AlphaToken = ReplyToken<AlphaEndpoint, WireTxImpl>;
+
+    pub static TOKENS: std::sync::OnceLock<mpsc::UnboundedSender<(AReq, AlphaToken)>> =
+        std::sync::OnceLock::new();
+
+    fn test_deferred_handler(
+        _context: &mut TestContext,
+        _header: VarHeader,
+        body: AReq,
+        token: AlphaToken,
+    ) {
+        TOKENS.get().unwrap().send((body, token)).ok().unwrap();
+    }
+
+    define_dispatch! {
+        app: DeferredDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | deferred  | test_deferred_handler |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_deferred_reply() {
+    let (token_tx, mut token_rx) = mpsc::unbounded_channel();
+    assert!(deferred::TOKENS.set(token_tx).is_ok());
+    let app = deferred::DeferredDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let shutdown = app.shutdown_handle();
+    let cli = serve!(app);
+
+    let req1 = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(1)).await }
+    });
+    let req2 = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(2)).await }
+    });
+
+    // Both handlers returned, but their requests are still in-flight
+    let (body_a, token_a) = token_rx.recv().await.unwrap();
+    let (body_b, token_b) = token_rx.recv().await.unwrap();
+    assert_eq!(shutdown.in_flight(), 2);
+    assert!(!req1.is_finished());
+    assert!(!req2.is_finished());
+
+    // The dispatcher keeps serving other requests meanwhile
+    cli.ping().await.unwrap();
+
+    // Reply out of order, from another task, and with an error
+    tokio::task::spawn(async move {
+        token_b.reply(&AResp(body_b.0 + 10)).await.unwrap();
+    })
+    .await
+    .unwrap();
+    let (ok, err) = if body_a.0 == 1 {
+        (req2, req1)
+    } else {
+        (req1, req2)
+    };
+    let resp = timeout(Duration::from_secs(1), ok)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.0, body_b.0 + 10);
+    assert_eq!(shutdown.in_flight(), 1);
+
+    token_a.error(WireError::Busy).await.unwrap();
+    let res = timeout(Duration::from_secs(1), err).await.unwrap().unwrap();
+    assert!(matches!(res, Err(HostErr::Wire(WireError::Busy))));
+    assert_eq!(shutdown.in_flight(), 0);
+}
+
+mod limited {
+    use super::*;
+
+    define_dispatch! {
+        app: LimitedDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        max_spawned: 1;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind        | handler               |
+            | ----------        | ----        | -------               |
+            | AlphaEndpoint     | async       | test_alpha_handler    |
+            | CancelEndpoint    | cancellable | test_cancel_handler   |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | ZetaTopic1        | blocking  | test_zeta_blocking    |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_spawn_limit() {
+    let app = limited::LimitedDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let limit = app.spawn_limit();
+    assert_eq!(limit.max(), 1);
+    let cli = serve!(app);
+
+    for _ in 0..2 {
+        let (handle, fut) = cli.send_resp_cancellable::<CancelEndpoint>(&CReq);
+        let busy = async {
+            // Wait for the handler to start
+            while limit.active() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            // Further spawned handlers are rejected, inline handlers are not limited
+            let resp = cli.send_resp::<CancelEndpoint>(&CReq).await;
+            assert!(matches!(resp, Err(HostErr::Wire(WireError::Busy))));
+            let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+            assert_eq!(resp.0, 42);
+
+            handle.cancel().await.unwrap();
+        };
+        let (resp, ()) = tokio::join!(timeout(Duration::from_secs(1), fut), busy);
+        assert!(matches!(resp, Ok(Err(HostErr::Wire(WireError::Cancelled)))));
+
+        // The permit is returned once the handler completes
+        timeout(Duration::from_secs(1), async {
+            while limit.active() != 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
+
+mod few_cancels {
+    use super::*;
+
+    define_dispatch! {
+        app: FewCancelsDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        max_cancellable: 1;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind        | handler               |
+            | ----------        | ----        | -------               |
+            | CancelEndpoint    | cancellable | test_cancel_handler   |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_max_cancellable() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(few_cancels::FewCancelsDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    ));
+
+    let (handle, fut) = cli.send_resp_cancellable::<CancelEndpoint>(&CReq);
+    let full = async {
+        // Wait for the handler to start
+        while ctr.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // The only slot is taken
+        let resp = cli.send_resp::<CancelEndpoint>(&CReq).await;
+        assert!(matches!(resp, Err(HostErr::Wire(WireError::FailedToSpawn))));
+
+        handle.cancel().await.unwrap();
+    };
+    let (resp, ()) = tokio::join!(timeout(Duration::from_secs(1), fut), full);
+    assert!(matches!(resp, Ok(Err(HostErr::Wire(WireError::Cancelled)))));
+}
+
+mod auth {
+    use super::*;
+    use postcard_rpc::auth::AuthWireTx;
+
+    define_dispatch! {
+        app: AuthDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: AuthWireTx<WireTxImpl, 256>;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+mod checksum {
+    use super::*;
+    use postcard_rpc::checksum::ChecksumWireTx;
+
+    define_dispatch! {
+        app: ChecksumDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: ChecksumWireTx<WireTxImpl, 256>;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_protocol_version() {
+    let (client_tx, mut client_rx) = serve_raw!(test_app());
+
+    // A request from a client using another version of the protocol
+    let mut frame = alpha_request(VarSeq::Seq1(3), 42);
+    frame[0] |= PROTOCOL_VERSION + 1;
+    client_tx.send(frame).await.unwrap();
+
+    let reply = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(ERROR_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq1(3));
+    let err = postcard::from_bytes::<WireError>(body).unwrap();
+    assert!(matches!(
+        err,
+        WireError::ProtocolVersionMismatch { expected, got }
+            if expected == PROTOCOL_VERSION && got == PROTOCOL_VERSION + 1
+    ));
+}
+
+#[tokio::test]
+async fn end_to_end_auth() {
+    let app = auth::AuthDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let key = Authenticator::new(b"shared secret", 8);
+    let mut server = Server::new(
+        AuthWireTx::new(ChannelWireTx::new(server_tx), key.clone()),
+        AuthWireRx::new(ChannelWireRx::new(server_rx), key.clone()),
+        vec![0; 1024].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // Requests without a valid tag are rejected
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::AuthFailed))));
+
+    cli.set_auth(Some(key));
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+
+    // Replies without a valid tag are dropped
+    cli.set_auth(Some(Authenticator::new(b"wrong secret", 8)));
+    let resp = timeout(
+        Duration::from_millis(100),
+        cli.send_resp::<AlphaEndpoint>(&AReq(42)),
+    )
+    .await;
+    assert!(resp.is_err());
+}
+
+#[tokio::test]
+async fn end_to_end_checksum() {
+    let app = checksum::ChecksumDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let mut server = Server::new(
+        ChecksumWireTx::new(ChannelWireTx::new(server_tx)),
+        ChecksumWireRx::new(ChannelWireRx::new(server_rx)),
+        vec![0; 1024].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // Requests without a valid checksum are rejected
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await;
+    assert!(matches!(
+        resp,
+        Err(HostErr::Wire(WireError::ChecksumFailed))
+    ));
+
+    cli.set_checksum(true);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+/// Reports the first frame as cut short, as an interrupted USB transfer would
+struct TruncatingRx {
+    rx: ChannelWireRx,
+    truncated: bool,
+}
+
+impl WireRx for TruncatingRx {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let frame = self.rx.receive(buf).await.map_err(|e| e.as_kind())?;
+        if !self.truncated {
+            self.truncated = true;
+            let (hdr, _) = VarHeader::take_from_slice(frame).unwrap();
+            return Err(WireRxErrorKind::TruncatedFrame(hdr));
+        }
+        Ok(frame)
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_truncated() {
+    let app = test_app();
+    let kkind = app.min_key_len();
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let mut server = Server::new(
+        ChannelWireTx::new(server_tx),
+        TruncatingRx {
+            rx: ChannelWireRx::new(server_rx),
+            truncated: false,
+        },
+        vec![0; 1024].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // The truncated request is answered with an error, instead of a failed decode
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await;
+    assert!(matches!(
+        resp,
+        Err(HostErr::Wire(WireError::TruncatedFrame))
+    ));
+
+    // Later requests are unaffected
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+#[tokio::test]
+async fn reply_raw() {
+    let (server_tx, mut client_rx) = mpsc::channel(16);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key2);
+
+    // Pre-serialized bodies are sent as-is, in the same frame as a normal reply
+    let body = postcard::to_stdvec(&AResp(42)).unwrap();
+    sender
+        .reply_raw::<AlphaEndpoint>(VarSeq::Seq2(7), &body)
+        .await
+        .unwrap();
+    sender
+        .reply::<AlphaEndpoint>(VarSeq::Seq2(7), &AResp(42))
+        .await
+        .unwrap();
+    let raw = client_rx.recv().await.unwrap();
+    let normal = client_rx.recv().await.unwrap();
+    assert_eq!(raw, normal);
+
+    let (hdr, rest) = VarHeader::take_from_slice(&raw).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+    assert_eq!(rest, &body[..]);
+}
+
+#[tokio::test]
+async fn end_to_end_timeout() {
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .outgoing_depth(16)
+        .default_timeout(Duration::from_millis(30))
+        .build()
+        .unwrap();
+    let cli = serve!(test_app(), &config);
+
+    // The default timeout expires before the reply is sent
+    let res = cli.send_resp::<SleepEndpoint>(&60).await;
+    assert_eq!(res, Err(HostErr::Timeout));
+
+    // A longer timeout can be used for a single request
+    let res = cli
+        .send_resp_timeout::<SleepEndpoint>(&10, Duration::from_millis(200))
+        .await;
+    assert_eq!(res, Ok(10));
+
+    // The late reply was dropped, and later requests get their own replies
+    assert_eq!(cli.send_resp::<SleepEndpoint>(&5).await, Ok(5));
+    assert_eq!(
+        cli.send_resp_timeout::<SleepEndpoint>(&50, Duration::from_millis(10))
+            .await,
+        Err(HostErr::Timeout)
+    );
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(cli.send_resp::<SleepEndpoint>(&1).await, Ok(1));
+}
+
+#[tokio::test]
+async fn host_client_builder() {
+    // Invalid settings are rejected when building
+    let res = HostClientBuilder::new(ERROR_PATH).outgoing_depth(0).build();
+    assert!(matches!(
+        res,
+        Err(HostClientConfigError::ZeroDepth("outgoing_depth"))
+    ));
+    let res = HostClientBuilder::new(ERROR_PATH)
+        .subscription_depth(0)
+        .build();
+    assert!(matches!(
+        res,
+        Err(HostClientConfigError::ZeroDepth("subscription_depth"))
+    ));
+    let res = HostClientBuilder::new(ERROR_PATH).max_frame_size(4).build();
+    assert!(matches!(
+        res,
+        Err(HostClientConfigError::FrameSizeTooSmall { min: 13, got: 4 })
+    ));
+
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .seq_kind(VarSeqKind::Seq2)
+        .outgoing_depth(256)
+        .incoming_depth(16)
+        .max_frame_size(16 * 1024)
+        .subscription_depth(2)
+        .build()
+        .unwrap();
+    assert_eq!(config.outgoing_depth, 256);
+    assert_eq!(config.incoming_depth, 16);
+    assert_eq!(config.max_frame_size, Some(16 * 1024));
+
+    let app = test_app();
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind,
+        },
+    );
+    let sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels_with_config(client_tx, client_rx, &config);
+
+    // Subscriptions with a depth of zero use the configured depth
+    let mut sub = cli.subscribe_multi::<ZetaTopic10>(0).await.unwrap();
+    for i in 0..3 {
+        sender
+            .publish::<ZetaTopic10>(VarSeq::Seq2(i), &ZMsg(i as i16))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(matches!(sub.recv().await, Err(MultiSubRxError::Lagged(1))));
+    assert_eq!(sub.recv().await.unwrap().0, 1);
+}
+
+mod fragment {
+    use super::*;
+
+    define_dispatch! {
+        app: FragmentDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: FragWireTx<WireTxImpl, 2048, 64>;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | BlobEndpoint      | async     | test_blob_handler     |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+type FragmentServer = Server<
+    FragWireTx<WireTxImpl, 2048, 64>,
+    FragWireRx<ChannelWireRx>,
+    Box<[u8]>,
+    fragment::FragmentDispatcher,
+>;
+
+fn new_fragment_server(rx: mpsc::Receiver<Vec<u8>>, tx: mpsc::Sender<Vec<u8>>) -> FragmentServer {
+    let app = fragment::FragmentDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    Server::new(
+        FragWireTx::new(ChannelWireTx::new(tx)),
+        FragWireRx::new(ChannelWireRx::new(rx)),
+        vec![0; 1024].into_boxed_slice(),
+        app,
+        kkind,
+    )
+}
+
+#[tokio::test]
+async fn end_to_end_fragment() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let mut server = new_fragment_server(server_rx, server_tx);
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    cli.set_fragmentation(Some(64));
+    let frames = Arc::new(Mutex::new(vec![]));
+    cli.set_wire_tap({
+        let frames = frames.clone();
+        move |_dir, frame: &[u8]| frames.lock().unwrap().push(frame.len())
+    });
+
+    // Small messages are sent unchanged
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert_eq!(frames.lock().unwrap().len(), 2);
+
+    // Large requests and responses are split and reassembled
+    let blob: Vec<u8> = (0..=255).cycle().take(700).collect();
+    let resp = cli
+        .send_resp::<BlobEndpoint>(&Blob(blob.clone()))
+        .await
+        .unwrap();
+    assert!(resp.0.iter().eq(blob.iter().rev()));
+    let frames = frames.lock().unwrap();
+    assert!(frames.len() > 20);
+    assert!(frames.iter().all(|len| *len <= 64));
+}
+
+#[tokio::test]
+async fn end_to_end_fragment_lost() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, mut client_rx) = mpsc::channel(16);
+    let mut server = new_fragment_server(server_rx, server_tx);
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // The first of three fragments of a request
+    let blob_hdr = VarHeader {
+        key: VarKey::Key8(BlobEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq1(4),
+    };
+    let mut blob = blob_hdr.write_to_vec();
+    blob.extend_from_slice(&postcard::to_stdvec(&vec![0u8; 60]).unwrap());
+    let mut frame = VarHeader {
+        key: VarKey::Key8(FRAGMENT_KEY),
+        seq_no: VarSeq::Seq2(0),
+    }
+    .write_to_vec();
+    frame.extend_from_slice(&postcard::to_stdvec(&FragmentInfo { index: 0, total: 3 }).unwrap());
+    frame.extend_from_slice(&blob[..32]);
+    client_tx.send(frame).await.unwrap();
+
+    // Interrupted by a regular request, which is handled
+    client_tx
+        .send(alpha_request(VarSeq::Seq1(5), 42))
+        .await
+        .unwrap();
+
+    let reply = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 42);
+
+    // The interrupted request fails
+    let reply = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(ERROR_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq1(4));
+    let err = postcard::from_bytes::<WireError>(body).unwrap();
+    assert_eq!(err, WireError::ReassemblyFailed);
+}
+
+mod compress {
+    use super::*;
+
+    define_dispatch! {
+        app: CompressDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: CompressWireTx<WireTxImpl, Lzss, 2048>;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        reply_buf: 64;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | BlobEndpoint      | async     | test_blob_handler     |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_deadline() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(SingleDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    ));
+    cli.set_deadline_propagation(true);
+
+    // The handler stops working once the client gave up waiting...
+    let resp = cli
+        .send_resp_timeout::<WorkEndpoint>(&100, Duration::from_millis(50))
+        .await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+
+    // ...and requests without a timeout have no deadline
+    assert_eq!(cli.send_resp::<WorkEndpoint>(&2).await.unwrap(), 2);
+    assert!(ctr.load(Ordering::Relaxed) < 20);
+}
+
+#[tokio::test]
+async fn end_to_end_compress() {
+    let app = compress::CompressDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let mut server = Server::new(
+        CompressWireTx::new(ChannelWireTx::new(server_tx), Lzss, 64),
+        CompressWireRx::<_, _, 2048>::new(ChannelWireRx::new(server_rx), Lzss),
+        vec![0; 2048].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    cli.set_compression(Some(Compression::new(Lzss, 64)));
+    let frames = Arc::new(Mutex::new(vec![]));
+    cli.set_wire_tap({
+        let frames = frames.clone();
+        move |dir, frame: &[u8]| frames.lock().unwrap().push((dir, frame.len()))
+    });
+
+    // Small messages are sent unchanged
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert!(frames.lock().unwrap().iter().all(|(_, len)| *len < 64));
+    frames.lock().unwrap().clear();
+
+    // Large requests and responses are compressed in both directions
+    let blob: Vec<u8> = b"config.value = 1234; "
+        .iter()
+        .cycle()
+        .take(1000)
+        .copied()
+        .collect();
+    let resp = cli
+        .send_resp::<BlobEndpoint>(&Blob(blob.clone()))
+        .await
+        .unwrap();
+    assert!(resp.0.iter().eq(blob.iter().rev()));
+    let frames = frames.lock().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert!(frames
+        .iter()
+        .any(|(dir, _)| *dir == FrameDirection::Outgoing));
+    assert!(frames
+        .iter()
+        .any(|(dir, _)| *dir == FrameDirection::Incoming));
+    assert!(frames.iter().all(|(_, len)| *len < 200));
+}
+
+struct DynAlphaHandler;
+
+impl Handler<TestContext, WireTxImpl> for DynAlphaHandler {
+    fn handle<'a>(
+        &'a mut self,
+        context: &'a mut TestContext,
+        hdr: VarHeader,
+        body: &'a [u8],
+        tx: &'a Sender<WireTxImpl>,
+    ) -> HandlerFuture<'a, WireTxImpl> {
+        Box::pin(async move {
+            let Ok(req) = postcard::from_bytes::<AReq>(body) else {
+                return tx.dispatch_error(&hdr, WireError::DeserFailed).await;
+            };
+            context.ctr.fetch_add(1, Ordering::Relaxed);
+            tx.reply::<AlphaEndpoint>(hdr.seq_no, &AResp(req.0)).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_dyn_dispatch() {
+    let mut app = DynDispatcher::<TestContext, WireTxImpl, 4>::new(TestContext::default());
+    assert!(app
+        .register_endpoint::<AlphaEndpoint>(Box::new(DynAlphaHandler))
+        .is_ok());
+    assert!(app
+        .register_endpoint::<BetaEndpoint>(Box::new(DynAlphaHandler))
+        .is_ok());
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq1);
+
+    // Handlers can be changed while the server isn't running
+    let dispatch = server.dispatch_mut();
+    assert!(dispatch.unregister(&BetaEndpoint::REQ_KEY).is_some());
+    assert!(!dispatch.is_registered(&BetaEndpoint::REQ_KEY));
+    let ctr = dispatch.context().ctr.clone();
+
+    // The handlers are not `Send`, so run the server on a LocalSet
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(async move {
+        server.run().await;
+    });
+    local
+        .run_until(async move {
+            let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+            assert_eq!(resp.0, 42);
+            assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+            let resp = cli.send_resp::<BetaEndpoint>(&BReq(1)).await;
+            assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
+
+            // Pings are answered without a handler
+            cli.ping().await.unwrap();
+        })
+        .await;
+}
+
+mod idempotent {
+    use super::*;
+
+    define_dispatch! {
+        app: IdempotentDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        reply_buf: 64;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler [idempotent = true] |
+            | SleepEndpoint     | async     | test_sleep_handler [timeout_ms = 100] [idempotent = true] |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_idempotent() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let (client_tx, mut client_rx) = serve_raw!(idempotent::IdempotentDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    ));
+    let request = |seq: u8, val: u8| alpha_request(VarSeq::Seq1(seq), val);
+
+    // A retransmitted request gets the same reply, without running the handler again
+    client_tx.send(request(1, 10)).await.unwrap();
+    let first = client_rx.recv().await.unwrap();
+    client_tx.send(request(1, 10)).await.unwrap();
+    let second = client_rx.recv().await.unwrap();
+    assert_eq!(first, second);
+    let (hdr, body) = VarHeader::take_from_slice(&second).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq1(1));
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 10);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Requests with another sequence number are handled
+    client_tx.send(request(2, 20)).await.unwrap();
+    let reply = client_rx.recv().await.unwrap();
+    let (_hdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 20);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+/// A separate dispatcher, so its cache isn't cleared by the other idempotent tests
+mod reconnected {
+    use super::*;
+
+    define_dispatch! {
+        app: ReconnectedDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler [idempotent = true] |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_idempotent_reconnect() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let new_app = || {
+        let context = TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        };
+        reconnected::ReconnectedDispatcher::new(context, ChannelWireSpawn {})
+    };
+
+    // The first client closes its connection after a request
+    let (client_tx, mut client_rx) = serve_raw!(new_app());
+    client_tx
+        .send(alpha_request(VarSeq::Seq1(1), 10))
+        .await
+        .unwrap();
+    client_rx.recv().await.unwrap();
+    drop(client_tx);
+    assert!(client_rx.recv().await.is_none());
+
+    // A client connecting later reuses the sequence number, but isn't answered
+    // with the cached reply
+    let (client_tx, mut client_rx) = serve_raw!(new_app());
+    client_tx
+        .send(alpha_request(VarSeq::Seq1(1), 20))
+        .await
+        .unwrap();
+    let reply = client_rx.recv().await.unwrap();
+    let (_hdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 20);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn end_to_end_send_resp_retry() {
+    let cli = serve!(idempotent::IdempotentDispatcher::new(
+        TestContext::default(),
+        ChannelWireSpawn {}
+    ));
+
+    let resp = cli
+        .send_resp_retry::<AlphaEndpoint>(&AReq(42), Duration::from_millis(100), 2)
+        .await
+        .unwrap();
+    assert_eq!(resp.0, 42);
+
+    // The handler times out, so there is never a reply
+    let resp = cli
+        .send_resp_retry::<SleepEndpoint>(&1000, Duration::from_millis(10), 2)
+        .await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+}
+
+/// Echoes the body of every request, after dropping as many requests as `lose`
+async fn flaky_device(
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    tx: mpsc::Sender<Vec<u8>>,
+    lose: Arc<AtomicUsize>,
+    seqs: Arc<Mutex<Vec<VarSeq>>>,
+) {
+    while let Some(frame) = rx.recv().await {
+        let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+        seqs.lock().unwrap().push(hdr.seq_no);
+        if lose.load(Ordering::Relaxed) > 0 {
+            lose.fetch_sub(1, Ordering::Relaxed);
+            continue;
+        }
+        let key = if hdr.key == VarKey::Key8(FlakyEndpoint::REQ_KEY) {
+            FlakyEndpoint::RESP_KEY
+        } else {
+            OmegaEndpoint::RESP_KEY
+        };
+        let mut reply = VarHeader {
+            key: VarKey::Key8(key),
+            seq_no: hdr.seq_no,
+        }
+        .write_to_vec();
+        reply.extend_from_slice(body);
+        if tx.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_retry_policy() {
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .default_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let (cli, server_rx, server_tx) = fake_device(&config);
+    let lose = Arc::new(AtomicUsize::new(0));
+    let seqs = Arc::new(Mutex::new(Vec::new()));
+    tokio::task::spawn(flaky_device(
+        server_rx,
+        server_tx,
+        lose.clone(),
+        seqs.clone(),
+    ));
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    cli.set_retry_policy(Some(policy.clone()));
+    let sent = || core::mem::take(&mut *seqs.lock().unwrap());
+
+    // A lost request to an idempotent endpoint is sent again, with a new sequence number
+    lose.store(1, Ordering::Relaxed);
+    assert_eq!(
+        cli.send_resp::<FlakyEndpoint>(&FlakyReq(5)).await.unwrap(),
+        5
+    );
+    let seqs_sent = sent();
+    assert_eq!(seqs_sent.len(), 2);
+    assert_ne!(seqs_sent[0], seqs_sent[1]);
+
+    // Other endpoints are not retried...
+    lose.store(1, Ordering::Relaxed);
+    let resp = cli.send_resp::<OmegaEndpoint>(&OmegaReq(6)).await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+    assert_eq!(sent().len(), 1);
+
+    // ...unless the request overrides the policy
+    lose.store(1, Ordering::Relaxed);
+    let always = RetryPolicy {
+        retry_non_idempotent: true,
+        ..policy.clone()
+    };
+    let resp = cli
+        .send_resp_with_retry::<OmegaEndpoint>(&OmegaReq(7), &always)
+        .await
+        .unwrap();
+    assert_eq!(resp.0, 7);
+    assert_eq!(sent().len(), 2);
+
+    // The number of attempts is limited
+    lose.store(5, Ordering::Relaxed);
+    let resp = cli.send_resp::<FlakyEndpoint>(&FlakyReq(8)).await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+    assert_eq!(sent().len(), policy.max_attempts);
+
+    // Retries can be disabled for a single request
+    lose.store(1, Ordering::Relaxed);
+    let resp = cli
+        .send_resp_with_retry::<FlakyEndpoint>(&FlakyReq(9), &RetryPolicy::none())
+        .await;
+    assert!(matches!(resp, Err(HostErr::Timeout)));
+    assert_eq!(sent().len(), 1);
+}
+
+/// A context giving each spawned handler only the counter it uses
+pub struct SplitContext {
+    pub ctr: Arc<AtomicUsize>,
+    pub topic_ctr: Arc<AtomicUsize>,
+}
+
+pub struct RequestCounter(Arc<AtomicUsize>);
+pub struct TopicCounter(Arc<AtomicUsize>);
+
+impl SpawnContextFor<BetaEndpoint> for SplitContext {
+    type SpawnCtxt = RequestCounter;
+
+    fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt {
+        RequestCounter(self.ctr.clone())
+    }
+}
+
+impl SpawnContextFor<ZetaTopic3> for SplitContext {
+    type SpawnCtxt = TopicCounter;
+
+    fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt {
+        TopicCounter(self.topic_ctr.clone())
+    }
+}
+
+async fn split_beta_handler(
+    context: RequestCounter,
+    header: VarHeader,
+    body: BReq,
+    out: Sender<ChannelWireTx>,
+) {
+    context.0.fetch_add(1, Ordering::Relaxed);
+    let _ = out
+        .reply::<BetaEndpoint>(header.seq_no, &BResp(body.0.into()))
+        .await;
+}
+
+async fn split_zeta_spawn(
+    context: TopicCounter,
+    _header: VarHeader,
+    _body: ZMsg,
+    _out: Sender<ChannelWireTx>,
+) {
+    context.0.fetch_add(1, Ordering::Relaxed);
+}
+
+mod split {
+    use super::*;
+
+    define_dispatch! {
+        app: SplitDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: SplitContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | BetaEndpoint      | spawn     | split_beta_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | ZetaTopic3        | spawn     | split_zeta_spawn      |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_spawn_context_for() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(split::SplitDispatcher::new(
+        SplitContext {
+            ctr: ctr.clone(),
+            topic_ctr: topic_ctr.clone(),
+        },
+        ChannelWireSpawn {},
+    ));
+
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    cli.publish::<ZetaTopic3>(VarSeq::Seq1(0), &ZMsg(1))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(topic_ctr.load(Ordering::Relaxed), 1);
+}
+
+mod metrics {
+    use super::*;
+
+    define_dispatch! {
+        app: MetricsDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | SleepEndpoint     | async     | test_sleep_handler [timeout_ms = 100] |
+            | TryBlockingEndpoint | blocking_try | test_try_blocking |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_metrics() {
+    let app = metrics::MetricsDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let metrics = app.metrics();
+    assert_eq!(metrics.len(), 3);
+    let cli = serve!(app);
+
+    cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap();
+    let res = cli.send_resp::<SleepEndpoint>(&1000).await;
+    assert!(matches!(res, Err(HostErr::Wire(WireError::Timeout))));
+    cli.send_resp::<TryBlockingEndpoint>(&2).await.unwrap();
+    let res = cli.send_resp::<TryBlockingEndpoint>(&3).await;
+    assert!(res.is_err());
+
+    let alpha = metrics.get(&AlphaEndpoint::REQ_KEY).unwrap();
+    assert_eq!((alpha.requests, alpha.errors, alpha.timed_out), (2, 0, 0));
+    let sleep = metrics.get(&SleepEndpoint::REQ_KEY).unwrap();
+    assert_eq!((sleep.requests, sleep.errors, sleep.timed_out), (1, 0, 1));
+    let try_blocking = metrics.get(&TryBlockingEndpoint::REQ_KEY).unwrap();
+    assert_eq!((try_blocking.requests, try_blocking.errors), (2, 1));
+
+    // The same counters can be read by the client
+    let remote = cli.get_metrics().await.unwrap();
+    assert_eq!(remote, metrics.iter().collect::<Vec<_>>());
+
+    metrics.reset();
+    assert_eq!(metrics.get(&AlphaEndpoint::REQ_KEY).unwrap().requests, 0);
+}
+
+#[tokio::test]
+async fn end_to_end_record_replay() {
+    // Record a session
+    let cli = serve!(test_app());
+    let recorder = FrameRecorder::new();
+    recorder.attach(&cli);
+    cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(cli.send_resp::<HalfEndpoint>(&8).await.unwrap(), Ok(4));
+    cli.clear_wire_tap();
+
+    let replayer = FrameReplayer::from_bytes(&recorder.to_bytes()).unwrap();
+    assert_eq!(replayer.frames(), recorder.frames());
+    assert_eq!(replayer.outgoing().count(), 2);
+    assert_eq!(replayer.incoming().count(), 2);
+
+    // Replaying the requests into another server produces the same replies
+    let (client_tx, mut client_rx) = serve_raw!(test_app());
+    let sent = replayer
+        .replay(&client_tx, ReplayTiming::Accelerated(100.0))
+        .await
+        .unwrap();
+    assert_eq!(sent, 2);
+    for expected in replayer.incoming() {
+        assert_eq!(client_rx.recv().await.unwrap(), expected.frame);
+    }
+}
+
+/// Liveness of the server in `end_to_end_liveness`
+static LIVENESS: Liveness = Liveness::new();
+
+#[tokio::test]
+async fn end_to_end_liveness() {
+    let (cli, mut server) = loopback(test_app(), 1024, VarSeqKind::Seq1);
+    server.set_loop_hook(Some(|event: LoopEvent<'_>| LIVENESS.record(&event)));
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // Waiting for a frame is healthy
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(!LIVENESS.is_busy());
+    assert!(LIVENESS.check());
+    assert!(LIVENESS.check());
+
+    let req = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<SleepEndpoint>(&80).await }
+    });
+
+    // The handler started since the last check, but then makes no progress
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(LIVENESS.is_busy());
+    assert!(LIVENESS.check());
+    assert!(!LIVENESS.check());
+
+    assert_eq!(req.await.unwrap().unwrap(), 80);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(!LIVENESS.is_busy());
+    assert!(LIVENESS.check());
+}
+
+mod nested {
+    use super::*;
+    use postcard_rpc::{nest, TopicDirection};
+
+    endpoints! {
+        list = MOTOR_ENDPOINTS;
+        service = "motor";
+        | EndpointTy            | RequestTy     | ResponseTy    | Path      |
+        | ----------            | ---------     | ----------    | ----      |
+        | MotorStatusEndpoint   | ()            | u32           | "status"  |
+        | MotorSpinEndpoint     | ()            | ()            | "spin"    |
+    }
+
+    topics! {
+        list = MOTOR_TOPICS_IN;
+        direction = TopicDirection::ToServer;
+        service = "motor";
+        | TopicTy           | MessageTy     | Path      |
+        | ----------        | ---------     | ----      |
+        | MotorSetTopic     | u32           | "set"     |
+    }
+
+    endpoints! {
+        list = IMU_ENDPOINTS;
+        service = "imu";
+        | EndpointTy            | RequestTy     | ResponseTy    | Path      |
+        | ----------            | ---------     | ----------    | ----      |
+        | ImuStatusEndpoint     | ()            | u32           | "status"  |
+    }
+
+    endpoints! {
+        list = ROOT_ENDPOINTS;
+        | EndpointTy            | RequestTy     | ResponseTy    | Path      |
+        | ----------            | ---------     | ----------    | ----      |
+        | RootStatusEndpoint    | ()            | u32           | "status"  |
+    }
+
+    topics! {
+        list = NO_TOPICS_IN;
+        direction = TopicDirection::ToServer;
+        | TopicTy           | MessageTy     | Path      |
+        | ----------        | ---------     | ----      |
+    }
+
+    topics! {
+        list = NO_TOPICS_OUT;
+        direction = TopicDirection::ToClient;
+        | TopicTy           | MessageTy     | Path      |
+        | ----------        | ---------     | ----      |
+    }
+
+    pub struct Status(pub u32);
+
+    impl SpawnContext for Status {
+        type SpawnCtxt = ();
+
+        fn spawn_ctxt(&mut self) -> Self::SpawnCtxt {}
+    }
+
+    fn status_handler(context: &mut Status, _header: VarHeader, _body: ()) -> u32 {
+        context.0
+    }
+
+    async fn spin_handler(
+        _context: (),
+        header: VarHeader,
+        _body: (),
+        out: Sender<ChannelWireTx>,
+        token: CancelToken,
+    ) {
+        while !token.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let _ = out.error(header.seq_no, WireError::Cancelled).await;
+    }
+
+    fn set_handler(
+        context: &mut Status,
+        _header: VarHeader,
+        msg: u32,
+        _out: &Sender<ChannelWireTx>,
+    ) {
+        context.0 = msg;
+    }
+
+    pub mod motor {
+        use super::*;
+
+        define_dispatch! {
+            app: MotorDispatcher;
+            spawn_fn: spawn_fn;
+            tx_impl: WireTxImpl;
+            spawn_impl: WireSpawnImpl;
+            context: Status;
+
+            endpoints: {
+                list: MOTOR_ENDPOINTS;
+
+                | EndpointTy            | kind      | handler           |
+                | ----------            | ----      | -------           |
+                | MotorStatusEndpoint   | blocking  | status_handler    |
+                | MotorSpinEndpoint     | cancellable | spin_handler    |
+            };
+            topics_in: {
+                list: MOTOR_TOPICS_IN;
+
+                | TopicTy           | kind      | handler       |
+                | ----------        | ----      | -------       |
+                | MotorSetTopic     | blocking  | set_handler   |
+            };
+            topics_out: {
+                list: NO_TOPICS_OUT;
+            };
+        }
+    }
+
+    pub mod imu {
+        use super::*;
+
+        define_dispatch! {
+            app: ImuDispatcher;
+            spawn_fn: spawn_fn;
+            tx_impl: WireTxImpl;
+            spawn_impl: WireSpawnImpl;
+            context: Status;
+
+            endpoints: {
+                list: IMU_ENDPOINTS;
+
+                | EndpointTy            | kind      | handler           |
+                | ----------            | ----      | -------           |
+                | ImuStatusEndpoint     | blocking  | status_handler    |
+            };
+            topics_in: {
+                list: NO_TOPICS_IN;
+
+                | TopicTy           | kind      | handler       |
+                | ----------        | ----      | -------       |
+            };
+            topics_out: {
+                list: NO_TOPICS_OUT;
+            };
+        }
+    }
+
+    pub mod root {
+        use super::*;
+
+        define_dispatch! {
+            app: RootDispatcher;
+            spawn_fn: spawn_fn;
+            tx_impl: WireTxImpl;
+            spawn_impl: WireSpawnImpl;
+            context: Status;
+
+            endpoints: {
+                list: ROOT_ENDPOINTS;
+
+                | EndpointTy            | kind      | handler           |
+                | ----------            | ----      | -------           |
+                | RootStatusEndpoint    | blocking  | status_handler    |
+            };
+            topics_in: {
+                list: NO_TOPICS_IN;
+
+                | TopicTy           | kind      | handler       |
+                | ----------        | ----      | -------       |
+            };
+            topics_out: {
+                list: NO_TOPICS_OUT;
+            };
+        }
+    }
+
+    nest! {
+        app: DeviceDispatcher;
+        tx_impl: WireTxImpl;
+        root: root::RootDispatcher;
+
+        services: {
+            | Field     | Prefix    | Dispatcher            |
+            | -----     | ------    | ----------            |
+            | motor     | "motor"   | motor::MotorDispatcher |
+            | imu       | "imu"     | imu::ImuDispatcher    |
+        };
+    }
+
+    /// A device whose root and services report the statuses 1, 2, and 3
+    pub fn new_device() -> DeviceDispatcher {
+        DeviceDispatcher::new(
+            root::RootDispatcher::new(Status(1), ChannelWireSpawn {}),
+            motor::MotorDispatcher::new(Status(2), ChannelWireSpawn {}),
+            imu::ImuDispatcher::new(Status(3), ChannelWireSpawn {}),
+        )
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_nested() {
+    use nested::*;
+
+    assert_eq!(MotorStatusEndpoint::PATH, "motor/status");
+    assert_eq!(ImuStatusEndpoint::PATH, "imu/status");
+    assert_eq!(MotorSetTopic::PATH, "motor/set");
+    assert_ne!(MotorStatusEndpoint::REQ_KEY, ImuStatusEndpoint::REQ_KEY);
+    assert_ne!(MotorStatusEndpoint::REQ_KEY, RootStatusEndpoint::REQ_KEY);
+
+    let app = new_device();
+    assert!(app.motor.handles(&VarKey::Key8(MotorSetTopic::TOPIC_KEY)));
+    assert!(!app.imu.handles(&VarKey::Key8(MotorStatusEndpoint::REQ_KEY)));
+    let cli = serve!(app);
+
+    assert_eq!(cli.send_resp::<RootStatusEndpoint>(&()).await.unwrap(), 1);
+    assert_eq!(cli.send_resp::<MotorStatusEndpoint>(&()).await.unwrap(), 2);
+    assert_eq!(cli.send_resp::<ImuStatusEndpoint>(&()).await.unwrap(), 3);
+
+    // Topics are routed to the service too
+    cli.publish::<MotorSetTopic>(VarSeq::Seq1(0), &20)
+        .await
+        .unwrap();
+    assert_eq!(cli.send_resp::<MotorStatusEndpoint>(&()).await.unwrap(), 20);
+    assert_eq!(cli.send_resp::<ImuStatusEndpoint>(&()).await.unwrap(), 3);
+
+    // Standard endpoints are handled by the root dispatcher
+    cli.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn end_to_end_nested_cancel_with_deadline() {
+    use nested::*;
+
+    let config = HostClientBuilder::new(ERROR_PATH)
+        .default_timeout(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let cli = serve!(new_device(), &config);
+    cli.set_deadline_propagation(true);
+
+    // The cancellation shares the sequence number, and so the deadline, of the
+    // request, and must still reach the service handling it
+    let (handle, fut) = cli.send_resp_cancellable::<MotorSpinEndpoint>(&());
+    let cancel = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        handle.cancel().await.unwrap();
+    };
+    let (resp, ()) = tokio::join!(timeout(Duration::from_millis(500), fut), cancel);
+    assert!(matches!(resp, Ok(Err(HostErr::Wire(WireError::Cancelled)))));
+}
+
+/// Frames waiting for one of the workers of `end_to_end_worker_pool`
+static POOL_QUEUE: PoolQueue<CriticalSectionRawMutex, 256, 4> = PoolQueue::new();
+
+#[tokio::test]
+async fn end_to_end_worker_pool() {
+    let kkind = test_app().min_key_len();
+    let (cli, mut server) = loopback(
+        PoolFrontend::new(&POOL_QUEUE, kkind),
+        1024,
+        VarSeqKind::Seq1,
+    );
+    for _ in 0..2 {
+        let mut worker = PoolWorker::new(&POOL_QUEUE, test_app(), server.sender());
+        tokio::task::spawn(async move {
+            worker.run().await;
+        });
+    }
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // The slow request does not delay the fast one
+    let start = Instant::now();
+    let slow = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<SleepEndpoint>(&80).await }
+    });
+    yield_now().await;
+    let fast = cli.send_resp::<SleepEndpoint>(&10).await.unwrap();
+    assert_eq!(fast, 10);
+    assert!(start.elapsed() < Duration::from_millis(80));
+    assert!(!slow.is_finished());
+    assert_eq!(slow.await.unwrap().unwrap(), 80);
+
+    // With both workers busy, further requests wait in the queue
+    let reqs: Vec<_> = [30, 20, 10, 0]
+        .into_iter()
+        .map(|ms| {
+            let cli = cli.clone();
+            tokio::task::spawn(async move { cli.send_resp::<SleepEndpoint>(&ms).await })
+        })
+        .collect();
+    for (req, ms) in reqs.into_iter().zip([30, 20, 10, 0]) {
+        assert_eq!(req.await.unwrap().unwrap(), ms);
+    }
+    assert!(POOL_QUEUE.is_empty());
+}
+
+mod prioritized {
+    use super::*;
+
+    define_dispatch! {
+        app: PriorityDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler [priority = high] |
+            | SleepEndpoint     | async     | test_sleep_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+/// Frames waiting for the workers of `end_to_end_priority_pool`
+static HIGH_QUEUE: PoolQueue<CriticalSectionRawMutex, 256, 1> = PoolQueue::new();
+static LOW_QUEUE: PoolQueue<CriticalSectionRawMutex, 256, 1> = PoolQueue::new();
+
+#[tokio::test]
+async fn end_to_end_priority_pool() {
+    use prioritized::PriorityDispatcher;
+
+    let new_app = || PriorityDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = new_app().min_key_len();
+    let frontend = PriorityFrontend::new(
+        &HIGH_QUEUE,
+        &LOW_QUEUE,
+        kkind,
+        PriorityDispatcher::priority_of,
+    );
+    let (cli, mut server) = loopback(frontend, 1024, VarSeqKind::Seq1);
+    for queue in [&HIGH_QUEUE, &LOW_QUEUE] {
+        let mut worker = PoolWorker::new(queue, new_app(), server.sender());
+        tokio::task::spawn(async move {
+            worker.run().await;
+        });
+    }
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // One slow request is handled, and one waits in the queue, further
+    // low-priority requests are rejected instead of blocking the server
+    let slow: Vec<_> = (0..3)
+        .map(|_| {
+            let cli = cli.clone();
+            tokio::task::spawn(async move { cli.send_resp::<SleepEndpoint>(&100).await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    // The high-priority request does not wait for the slow ones
+    let start = Instant::now();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    let mut busy = 0;
+    for req in slow {
+        match req.await.unwrap() {
+            Ok(ms) => assert_eq!(ms, 100),
+            Err(HostErr::Wire(WireError::Busy)) => busy += 1,
+            Err(e) => panic!("unexpected error: {e:?}"),
+        }
+    }
+    assert!(busy >= 1);
+}
+
+/// Frames waiting for the worker of `end_to_end_priority_queue`
+static PRIORITY_QUEUE: PriorityQueue<CriticalSectionRawMutex, 256, 4, 2> = PriorityQueue::new();
+
+#[tokio::test]
+async fn end_to_end_priority_queue() {
+    static LEVELS: &[(Key, u8)] = &[(AlphaEndpoint::REQ_KEY, 1)];
+
+    let kkind = test_app().min_key_len();
+    let frontend = PriorityQueueFrontend::new(&PRIORITY_QUEUE, kkind, LEVELS);
+    let (cli, mut server) = loopback(frontend, 1024, VarSeqKind::Seq1);
+    let mut worker = PriorityQueueWorker::new(&PRIORITY_QUEUE, test_app(), server.sender());
+    tokio::task::spawn(async move {
+        worker.run().await;
+    });
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // The only worker is busy, so the next request waits in the queue
+    let send_sleep = |ms: u32| {
+        let cli = cli.clone();
+        tokio::task::spawn(async move { cli.send_resp::<SleepEndpoint>(&ms).await })
+    };
+    let busy = send_sleep(50);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let queued = send_sleep(30);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(PRIORITY_QUEUE.len(), 1);
+
+    // The request of the higher level is handled before the one queued earlier
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+    assert!(!queued.is_finished());
+    assert_eq!(busy.await.unwrap().unwrap(), 50);
+    assert_eq!(queued.await.unwrap().unwrap(), 30);
+    assert!(PRIORITY_QUEUE.is_empty());
+}
+
+/// Requests sent by the server of `end_to_end_device_requests`
+static DEVICE_REQUESTS: DeviceRequests<CriticalSectionRawMutex, 2, 16> = DeviceRequests::new();
+
+#[tokio::test]
+async fn end_to_end_device_requests() {
+    let (cli, mut server) = loopback(
+        DeviceRequestDispatch::new(&DEVICE_REQUESTS, test_app()),
+        1024,
+        VarSeqKind::Seq1,
+    );
+    let sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // Without a server on the host, requests are never answered, and free their
+    // slot once cancelled
+    let res = timeout(
+        Duration::from_millis(50),
+        DEVICE_REQUESTS.request::<CredentialEndpoint, _>(&sender, &1),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(DEVICE_REQUESTS.pending(), 0);
+
+    let srv = cli
+        .serve_device_requests::<CredentialEndpoint>(4)
+        .await
+        .unwrap();
+    tokio::task::spawn(srv.run(|id| async move { u64::from(id) * 1000 }));
+    let res = cli.serve_device_requests::<CredentialEndpoint>(4).await;
+    assert!(matches!(res, Err(SubscribeError::AlreadySubscribed)));
+
+    // Concurrent requests are matched to their answers
+    let (a, b) = tokio::join!(
+        DEVICE_REQUESTS.request::<CredentialEndpoint, _>(&sender, &7),
+        DEVICE_REQUESTS.request::<CredentialEndpoint, _>(&sender, &9),
+    );
+    assert_eq!(a.unwrap(), 7000);
+    assert_eq!(b.unwrap(), 9000);
+    assert_eq!(DEVICE_REQUESTS.pending(), 0);
+
+    // Requests from the host are still handled as usual
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+#[tokio::test]
+async fn end_to_end_pooled_frames() {
+    let (cli, mut server) = loopback(test_app(), 1024, VarSeqKind::Seq1);
+    let sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let mut sub = cli
+        .subscribe_exclusive_raw(ZetaTopic10::TOPIC_KEY, 8)
+        .await
+        .unwrap();
+    for i in 0..4 {
+        sender
+            .publish::<ZetaTopic10>(VarSeq::Seq2(i), &ZMsg(i as i16))
+            .await
+            .unwrap();
+        let frame = timeout(Duration::from_secs(1), sub.recv_pooled())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.header().key, VarKey::Key8(ZetaTopic10::TOPIC_KEY));
+        assert_eq!(
+            postcard::from_bytes::<ZMsg>(frame.body()).unwrap().0,
+            i as i16
+        );
+    }
+
+    // A pooled frame can be kept as a regular frame
+    sender
+        .publish::<ZetaTopic10>(VarSeq::Seq2(9), &ZMsg(9))
+        .await
+        .unwrap();
+    let frame = sub.recv_pooled().await.unwrap().into_frame();
+    assert_eq!(postcard::from_bytes::<ZMsg>(&frame.body).unwrap().0, 9);
+
+    // Requests still work with buffers returned to the pool
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+/// Frames observed by the server's wire tap
+static SERVER_FRAMES: Mutex<Vec<(FrameDirection, Vec<u8>)>> = Mutex::new(Vec::new());
+
+fn server_tap(dir: FrameDirection, frame: &[u8]) {
+    SERVER_FRAMES.lock().unwrap().push((dir, frame.to_vec()));
+}
+
+#[tokio::test]
+async fn end_to_end_log_stream() {
+    static LOGGER: TopicLogger<CriticalSectionRawMutex, 64, 4> = TopicLogger::new();
+
+    let (cli, mut server) = loopback(test_app(), 1024, VarSeqKind::Seq1);
+    let sender = server.sender();
+    tokio::task::spawn(async move { server.run().await });
+    tokio::task::spawn(async move { LOGGER.run(&sender).await });
+    let mut logs = cli.log_stream(8).await.unwrap();
+
+    LOGGER.log_record(
+        LogLevel::Warn,
+        "app::motor",
+        format_args!("stalled at {}", 42),
+    );
+    let record = logs.recv().await.unwrap();
+    assert_eq!(
+        record,
+        OwnedLogRecord {
+            level: LogLevel::Warn,
+            target: String::from("app::motor"),
+            message: String::from("stalled at 42"),
+        }
+    );
+
+    // Logs share the link with requests
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    LOGGER.log_record(LogLevel::Info, "app", format_args!("done"));
+    assert_eq!(logs.recv().await.unwrap().message, "done");
+    assert_eq!(LOGGER.dropped(), 0);
+}
+
+#[tokio::test]
+async fn end_to_end_wire_tap() {
+    let (cli, mut server) = loopback(test_app(), 1024, VarSeqKind::Seq1);
+    server.set_wire_tap(Some(server_tap));
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let client_frames = Arc::new(Mutex::new(vec![]));
+    cli.set_wire_tap({
+        let client_frames = client_frames.clone();
+        move |dir, frame: &[u8]| client_frames.lock().unwrap().push((dir, frame.to_vec()))
+    });
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    cli.clear_wire_tap();
+
+    // Both sides saw the same request and response, with opposite directions
+    let client_frames = core::mem::take(&mut *client_frames.lock().unwrap());
+    let server_frames = core::mem::take(&mut *SERVER_FRAMES.lock().unwrap());
+    assert_eq!(client_frames.len(), 2);
+    assert_eq!(client_frames[0].0, FrameDirection::Outgoing);
+    assert_eq!(client_frames[1].0, FrameDirection::Incoming);
+    let flipped: Vec<_> = server_frames
+        .into_iter()
+        .map(|(dir, frame)| match dir {
+            FrameDirection::Outgoing => (FrameDirection::Incoming, frame),
+            FrameDirection::Incoming => (FrameDirection::Outgoing, frame),
+        })
+        .collect();
+    assert_eq!(client_frames, flipped);
+
+    let (hdr, body) = VarHeader::take_from_slice(&client_frames[1].1).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+    assert_eq!(body, [42]);
+}
+
+#[tokio::test]
+async fn end_to_end_unit() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(SingleDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    ));
+
+    let client_frames = Arc::new(Mutex::new(vec![]));
+    cli.set_wire_tap({
+        let client_frames = client_frames.clone();
+        move |_dir, frame: &[u8]| client_frames.lock().unwrap().push(frame.to_vec())
+    });
+    cli.send_resp::<TriggerEndpoint>(&()).await.unwrap();
+    cli.clear_wire_tap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Unit requests and responses are sent as header-only frames
+    let client_frames = core::mem::take(&mut *client_frames.lock().unwrap());
+    assert_eq!(client_frames.len(), 2);
+    for frame in client_frames.iter() {
+        let (hdr, body) = VarHeader::take_from_slice(frame).unwrap();
+        assert_eq!(hdr.key, VarKey::Key8(TriggerEndpoint::REQ_KEY));
+        assert!(body.is_empty());
+    }
+
+    // The keys are calculated like those of any other type
+    assert_eq!(TriggerEndpoint::REQ_KEY, Key::for_path::<()>("trigger"));
+    assert_eq!(TriggerEndpoint::RESP_KEY, Key::for_path::<()>("trigger"));
+}
+
+#[tokio::test]
+async fn end_to_end_schema() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = app.min_key_len();
+    let report = app.device_map;
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli: HostClient<_> = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let schema = cli.get_schema_report().await.unwrap();
+
+    for ep in &schema.endpoints {
+        println!("'{}': {} -> {}", ep.path, ep.req_ty, ep.resp_ty);
+    }
+    for tp in &schema.topics_in {
+        println!("'{}' ---> {}", tp.path, tp.ty);
+    }
+    for tp in &schema.topics_out {
+        println!("'{}' <--- {}", tp.path, tp.ty);
+    }
+
+    assert_eq!(schema.endpoints.len(), report.endpoints.len());
+    assert_eq!(schema.topics_in.len(), report.topics_in.len());
+    assert_eq!(schema.topics_out.len(), report.topics_out.len());
+}
+
+#[tokio::test]
+async fn end_to_end_force8() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = VarKeyKind::Key8;
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
+    assert_eq!(resp.0, 1234);
+}
+
+#[test]
+fn device_map() {
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    println!("# SingleDispatcher");
+    println!();
+
+    println!("## Types");
+    println!();
+    for ty in app.device_map.types {
+        let ty = OwnedNamedType::from(*ty);
+        println!("* {ty}");
+    }
+
+    println!();
+    println!("## Endpoints");
+    println!();
+    for ep in app.device_map.endpoints {
+        println!(
+            "* {} ({:016X} -> {:016X})",
+            ep.0,
+            u64::from_le_bytes(ep.1.to_bytes()),
+            u64::from_le_bytes(ep.2.to_bytes()),
+        );
+    }
+
+    println!();
+    println!("## Topics (In)");
+    println!();
+    for tp in app.device_map.topics_in {
+        println!(
+            "* {} <- ({:016X})",
+            tp.0,
+            u64::from_le_bytes(tp.1.to_bytes()),
+        );
+    }
+
+    println!();
+    println!("## Topics (Out)");
+    println!();
+    for tp in app.device_map.topics_out {
+        println!(
+            "* {} -> ({:016X})",
+            tp.0,
+            u64::from_le_bytes(tp.1.to_bytes()),
+        );
+    }
+    println!();
+    println!("## Min Key Length");
+    println!();
+    println!("{:?}", app.device_map.min_key_len);
+    println!();
+}
+
+#[tokio::test]
+async fn end_to_end_stoppable() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = app.min_key_len();
+    let (mut server, stopper) = new_server_stoppable(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let hdl = tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    stopper.stop();
+    match timeout(Duration::from_millis(100), hdl).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => panic!("Server task panicked? {e:?}"),
+        Err(_) => panic!("Server task did not stop!"),
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_health() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let app = test_app();
+    let kkind = app.min_key_len();
+    let (mut server, stopper) = new_server_stoppable(
+        app,
+        Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind,
+        },
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let mut health = cli.monitor_health(HealthConfig {
+        interval: Duration::from_millis(5),
+        timeout: Duration::from_millis(20),
+        degraded_rtt: Duration::from_secs(1),
+        degraded_after: 1,
+        lost_after: 2,
+    });
+
+    // Pings are answered, the connection stays healthy
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(*health.borrow_and_update(), Health::Connected);
+    assert!(!health.has_changed().unwrap());
+
+    // Once the device stops answering, the connection is lost
+    stopper.stop();
+    timeout(
+        Duration::from_secs(1),
+        health.wait_for(|h| *h == Health::Lost),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    // The monitor stops with the client
+    cli.close();
+    timeout(Duration::from_secs(1), async {
+        while health.changed().await.is_ok() {}
+    })
+    .await
+    .unwrap();
+    assert_eq!(*health.borrow(), Health::Lost);
+}
+
+#[tokio::test]
+async fn end_to_end_reconnect() {
+    let (conn_tx, mut conn_rx) = mpsc::channel(4);
+
+    let cli = client::new_from_channels_with_reconnect(
+        move || {
+            let conn_tx = conn_tx.clone();
+            async move {
+                let (client_tx, server_rx) = mpsc::channel(16);
+                let (server_tx, client_rx) = mpsc::channel(16);
+                conn_tx
+                    .send((server_rx, server_tx))
+                    .await
+                    .map_err(|_| client::ChannelError::TxClosed)?;
+                Ok((client_tx, client_rx))
+            }
+        },
+        VarSeqKind::Seq1,
+    );
+    let mut state = cli.connection_state();
+
+    // The first "device" receives a request, then goes away without replying
+    let (mut server_rx, server_tx): (mpsc::Receiver<Vec<u8>>, mpsc::Sender<Vec<u8>>) =
+        conn_rx.recv().await.unwrap();
+    state
+        .wait_for(|s| *s == ConnectionState::Connected)
+        .await
+        .unwrap();
+    let pending = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(42)).await }
+    });
+    assert!(server_rx.recv().await.is_some());
+    drop((server_rx, server_tx));
+    let res = timeout(Duration::from_millis(100), pending)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(res, Err(HostErr::Disconnected)));
+
+    // The client reconnects to a second, working, device
+    let (server_rx, server_tx) = conn_rx.recv().await.unwrap();
+    let app = test_app();
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind,
+        },
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    state
+        .wait_for(|s| *s == ConnectionState::Connected)
+        .await
+        .unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+#[test]
+fn schema_json() {
+    let rpt =
+        SchemaReport::from_maps(&[&ENDPOINT_LIST], &[&TOPICS_IN_LIST, &TOPICS_OUT_LIST]).unwrap();
+    assert_eq!(rpt.endpoints.len(), ENDPOINT_LIST.endpoints.len());
+    assert_eq!(rpt.topics_in.len(), TOPICS_IN_LIST.topics.len());
+    assert_eq!(rpt.topics_out.len(), TOPICS_OUT_LIST.topics.len());
+
+    let json = rpt.to_json();
+    assert!(json.contains(r#""path": "alpha""#));
+    assert!(json.contains(r#""name": "AReq""#));
+    // The output is stable
+    assert_eq!(json, rpt.clone().to_json());
+
+    // Keys seen on the wire can be mapped back to their paths
+    let mut key = VarKey::Key8(AlphaEndpoint::RESP_KEY);
+    assert_eq!(rpt.paths_for_key(&key), ["alpha"]);
+    key.shrink_to(VarKeyKind::Key4);
+    assert_eq!(rpt.paths_for_key(&key), ["alpha"]);
+    assert_eq!(key.to_string().len(), 8);
+
+    // ...and recomputed from the reported schemas
+    let alpha = rpt.endpoints.iter().find(|e| e.path == "alpha").unwrap();
+    assert_eq!(key_for_path(&alpha.req_ty, "alpha"), AlphaEndpoint::REQ_KEY);
+    assert_eq!(
+        key_for_path(&alpha.resp_ty, "alpha"),
+        AlphaEndpoint::RESP_KEY
+    );
+    assert_eq!(
+        key_hex(&AlphaEndpoint::RESP_KEY).to_string(),
+        VarKey::Key8(AlphaEndpoint::RESP_KEY).to_string()
+    );
+}
+
+#[test]
+fn attribute_endpoint() {
+    assert_eq!(OmegaEndpoint::PATH, "omega");
+    assert_eq!(OmegaEndpoint::REQ_KEY, Key::for_path::<OmegaReq>("omega"));
+    assert_eq!(OmegaEndpoint::RESP_KEY, Key::for_path::<OResp>("omega"));
+
+    // The serde and schema impls are derived for the request
+    let bytes = postcard::to_stdvec(&OmegaReq(7)).unwrap();
+    assert_eq!(
+        postcard::from_bytes::<OmegaReq>(&bytes).unwrap(),
+        OmegaReq(7)
+    );
+    assert_eq!(OmegaReq::SCHEMA.name, "OmegaReq");
+}
+
+#[test]
+fn derived_commands() {
+    assert_eq!(StopEndpoint::PATH, "cmd/stop");
+    assert_eq!(StopEndpoint::REQ_KEY, Key::for_path::<u8>("cmd/stop"));
+    assert_eq!(StopEndpoint::RESP_KEY, Key::for_path::<()>("cmd/stop"));
+    const { assert!(!StopEndpoint::IDEMPOTENT) };
+    assert_eq!(EchoCmdEndpoint::PATH, "cmd/echo");
+    assert_eq!(
+        EchoCmdEndpoint::RESP_KEY,
+        Key::for_path::<String>("cmd/echo")
+    );
+    const { assert!(EchoCmdEndpoint::IDEMPOTENT) };
+
+    // The dispatch table lists the commands after the standard endpoints
+    let eps = Command::ENDPOINT_LIST.endpoints;
+    assert_eq!(eps[eps.len() - 2].0, "cmd/stop");
+    assert_eq!(eps[eps.len() - 1].0, "cmd/echo");
+    assert_eq!(Command::Stop(1).req_key(), StopEndpoint::REQ_KEY);
+
+    // Frames are decoded by their key
+    let hdr = VarHeader {
+        key: VarKey::Key8(EchoCmdEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq1(0),
+    };
+    let body = postcard::to_stdvec("hi").unwrap();
+    assert_eq!(
+        Command::from_frame(&hdr, &body).unwrap().unwrap(),
+        Command::Echo("hi".into())
+    );
+    assert!(Command::from_frame(&hdr, &[0xFF]).unwrap().is_err());
+    let hdr = VarHeader {
+        key: VarKey::Key8(OmegaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq1(0),
+    };
+    assert!(Command::from_frame(&hdr, &body).is_none());
+}
+
+/// A separate dispatcher, as the granted capabilities are shared by all instances
+mod caps {
+    use super::*;
+
+    pub const CAP_UNLOCK: u32 = 1 << 0;
+
+    pub const INFO: DeviceInfo<'static> =
+        device_info!(product = "locked widget", git_hash = "c0ffee");
+
+    fn test_unlock_handler(_context: &mut TestContext, _header: VarHeader, _body: ()) -> u32 {
+        0xC0DE
+    }
+
+    fn test_grant_caps(
+        _context: &mut TestContext,
+        _header: VarHeader,
+        caps: u32,
+        proof: &[u8],
+    ) -> bool {
+        caps == CAP_UNLOCK && proof == b"open sesame"
+    }
+
+    define_dispatch! {
+        app: CapsDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        grant_caps: test_grant_caps;
+        device_info: INFO;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | UnlockEndpoint    | blocking  | test_unlock_handler [requires_cap = CAP_UNLOCK] |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_capabilities() {
+    let app = caps::CapsDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq1);
+    let server = tokio::task::spawn(async move {
+        server.run().await;
+        server
+    });
+
+    let info = cli.device_info().await.unwrap();
+    assert_eq!(info.product, "locked widget");
+    assert_eq!(info.git_hash, "c0ffee");
+
+    // Ungated endpoints work as usual, gated ones are rejected
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let resp = cli.send_resp::<UnlockEndpoint>(&()).await;
+    assert!(matches!(resp, Err(HostErr::Unauthorized)));
+
+    // A bad proof grants nothing
+    let resp = cli.grant_caps(caps::CAP_UNLOCK, b"guess").await;
+    assert!(matches!(resp, Err(HostErr::Unauthorized)));
+    let resp = cli.send_resp::<UnlockEndpoint>(&()).await;
+    assert!(matches!(resp, Err(HostErr::Unauthorized)));
+
+    let granted = cli
+        .grant_caps(caps::CAP_UNLOCK, b"open sesame")
+        .await
+        .unwrap();
+    assert_eq!(granted, caps::CAP_UNLOCK);
+    let resp = cli.send_resp::<UnlockEndpoint>(&()).await.unwrap();
+    assert_eq!(resp, 0xC0DE);
+
+    // The grants belong to the dispatcher instance, not its type
+    let other = caps::CapsDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    assert_eq!(other.capabilities().granted(), 0);
+
+    // ...and are revoked when the connection is closed
+    cli.close();
+    let mut server = server.await.unwrap();
+    assert_eq!(server.dispatch_mut().capabilities().granted(), 0);
+}
+
+/// A separate dispatcher, with a context holding the upload
+mod uploads {
+    use super::*;
+    use postcard_rpc::upload::ChunkedUpload;
+
+    pub struct UploadContext {
+        pub upload: ChunkedUpload,
+        pub stored: Arc<Mutex<Vec<u8>>>,
+        /// Storing chunks at or beyond this offset fails
+        pub fail_at: Arc<AtomicUsize>,
+    }
+
+    async fn test_upload_handler(
+        context: &mut UploadContext,
+        _header: VarHeader,
+        chunk: UploadChunk<'_>,
+    ) -> Result<UploadAck, WireError> {
+        if let Some((offset, data)) = context.upload.next_write(&chunk) {
+            if offset as usize >= context.fail_at.load(Ordering::Relaxed) {
+                return Err(WireError::FailedToSpawn);
+            }
+            let mut stored = context.stored.lock().unwrap();
+            assert_eq!(stored.len(), offset as usize);
+            stored.extend_from_slice(data);
+            context.upload.commit(data.len());
+        }
+        Ok(context.upload.ack())
+    }
+
+    define_dispatch! {
+        app: UploadDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: UploadContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | UploadEndpoint    | async_try | test_upload_handler   |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_resumed_upload() {
+    use postcard_rpc::host_client::ChunkedUpload;
+
+    let stored = Arc::new(Mutex::new(Vec::new()));
+    let fail_at = Arc::new(AtomicUsize::new(300));
+    let app = uploads::UploadDispatcher::new(
+        uploads::UploadContext {
+            upload: Default::default(),
+            stored: stored.clone(),
+            fail_at: fail_at.clone(),
+        },
+        ChannelWireSpawn {},
+    );
+    // Chunks of 256 bytes don't fit into the receive buffer, and are made smaller
+    let (cli, mut server) = loopback(app, 128, VarSeqKind::Seq1);
+    tokio::task::spawn(async move { server.run().await });
+
+    let image: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let mut upload = ChunkedUpload::new(&image, 256);
+    let res = upload.run::<UploadEndpoint, _>(&cli).await;
+    assert!(res.is_err());
+    assert!(upload.chunk_len() < 128);
+    assert!(upload.acked() >= 300);
+    assert!(!upload.is_done());
+
+    // Resuming, e.g. after reconnecting, continues at the acknowledged offset
+    fail_at.store(usize::MAX, Ordering::Relaxed);
+    upload.run::<UploadEndpoint, _>(&cli).await.unwrap();
+    assert!(upload.is_done());
+    assert_eq!(*stored.lock().unwrap(), image);
+
+    // A host that doesn't know the progress learns it from the first chunk
+    let mut upload = ChunkedUpload::new(&image, 64);
+    upload.run::<UploadEndpoint, _>(&cli).await.unwrap();
+    assert_eq!(upload.acked(), 1000);
+    assert_eq!(stored.lock().unwrap().len(), 1000);
+}
+
+/// A separate dispatcher, with handlers for one-way commands
+mod oneway {
+    use super::*;
+
+    async fn test_trigger_handler(context: &mut TestContext, _header: VarHeader, _body: ()) {
+        context.ctr.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn test_fault_handler(_context: &mut TestContext, _header: VarHeader, body: u32) {
+        assert!(body != 7, "seven is right out");
+    }
+
+    define_dispatch! {
+        app: OnewayDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind              | handler               |
+            | ----------        | ----              | -------               |
+            | TriggerEndpoint   | async_noreply     | test_trigger_handler  |
+            | FaultEndpoint     | blocking_noreply  | test_fault_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_send_without_reply() {
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(oneway::OnewayDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    ));
+
+    for _ in 0..3 {
+        cli.send::<TriggerEndpoint>(&()).await.unwrap();
+    }
+    // A fault is not reported either
+    cli.send::<FaultEndpoint>(&7).await.unwrap();
+    cli.send::<FaultEndpoint>(&8).await.unwrap();
+
+    // Requests are handled in order, so the commands were handled before the ping
+    cli.ping().await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 3);
+    assert_eq!(cli.unmatched_replies(), 0);
+
+    cli.close();
+    assert!(matches!(
+        cli.send::<TriggerEndpoint>(&()).await,
+        Err(HostErr::Closed)
+    ));
+}
+
+mod mapped {
+    use super::*;
+
+    /// Responses enriched by [`stamp()`]
+    pub trait Stamped {
+        fn stamp(&mut self, seq_no: u32);
+    }
+
+    impl Stamped for AResp {
+        fn stamp(&mut self, _seq_no: u32) {
+            self.0 += 100;
+        }
+    }
+
+    impl Stamped for u32 {
+        fn stamp(&mut self, seq_no: u32) {
+            *self = seq_no;
+        }
+    }
+
+    fn stamp<T: Stamped>(hdr: &VarHeader, mut resp: T) -> T {
+        resp.stamp(hdr.seq_no.into());
+        resp
+    }
+
+    fn test_unlock_handler(_context: &mut TestContext, _header: VarHeader, _body: ()) -> u32 {
+        0xC0DE
+    }
+
+    define_dispatch! {
+        app: MappedDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        map_response: stamp;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | UnlockEndpoint    | blocking  | test_unlock_handler   |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_map_response() {
+    let cli = serve!(mapped::MappedDispatcher::new(
+        TestContext::default(),
+        ChannelWireSpawn {}
+    ));
+
+    // Each response is mapped with the hook of its own type
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 101);
+    let resp = cli.send_resp::<UnlockEndpoint>(&()).await.unwrap();
+    assert_eq!(resp, 1);
+    let resp = cli.send_resp::<UnlockEndpoint>(&()).await.unwrap();
+    assert_eq!(resp, 2);
+
+    // Standard endpoints are not mapped
+    cli.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn end_to_end_endpoint_info() {
+    use postcard_rpc::standard_icd::schema_hash;
+
+    let app = mapped::MappedDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let local = app.endpoint_info();
+    let cli = serve!(app);
+
+    let info = cli.get_endpoint_info().await.unwrap();
+    assert_eq!(info.len(), 2);
+    assert_eq!(local.len(), 2);
+    for (remote, local) in info.iter().zip(local) {
+        assert_eq!(remote, &OwnedEndpointInfo::from(local));
+    }
+
+    assert_eq!(info[0].path, "alpha");
+    assert_eq!(info[0].req_key, AlphaEndpoint::REQ_KEY);
+    assert_eq!(info[0].resp_key, AlphaEndpoint::RESP_KEY);
+    assert_eq!(info[0].req_schema, schema_hash::<AReq>());
+    assert_eq!(info[0].resp_schema, schema_hash::<AResp>());
+    assert_eq!(info[0].kind, "async");
+    assert_eq!(info[1].path, "unlock");
+    assert_eq!(info[1].resp_schema, schema_hash::<u32>());
+    assert_eq!(info[1].kind, "blocking");
+
+    // Schema hashes don't depend on the name of the type
+    assert_eq!(schema_hash::<AReq>(), schema_hash::<AResp>());
+    assert_ne!(schema_hash::<AReq>(), schema_hash::<BReq>());
+}
+
+/// A separate dispatcher, as the subscriptions are shared by all instances
+mod subs {
+    use super::*;
+
+    define_dispatch! {
+        app: SubsDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+/// A separate dispatcher, handling the tolerant endpoint
+mod evolve {
+    use super::*;
+
+    fn test_evolve_handler(_context: &mut TestContext, _header: VarHeader, req: EvolveReq) -> u32 {
+        u32::from(req.a) * 1000 + u32::from(req.b)
+    }
+
+    pub const EVOLVE_LIST: postcard_rpc::EndpointMap = postcard_rpc::EndpointMap {
+        types: endpoints!(@ep_tys [[] EvolveEndpoint]),
+        endpoints: endpoints!(@ep_eps [[] EvolveEndpoint]),
+    };
+
+    define_dispatch! {
+        app: EvolveDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: EVOLVE_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | EvolveEndpoint    | blocking  | test_evolve_handler   |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_tolerant() {
+    // The key only depends on the path, so both versions share it
+    assert_eq!(EvolveEndpoint::REQ_KEY, EvolveEndpointV1::REQ_KEY);
+    assert_eq!(
+        EvolveEndpoint::REQ_KEY,
+        postcard_rpc::tolerant::request_key("evolve")
+    );
+    assert_ne!(
+        EvolveEndpoint::REQ_KEY,
+        Key::for_path::<EvolveReq>("evolve")
+    );
+    const { assert!(EvolveEndpoint::TOLERANT) };
+    const { assert!(!AlphaEndpoint::TOLERANT) };
+
+    let cli = serve!(evolve::EvolveDispatcher::new(
+        TestContext::default(),
+        ChannelWireSpawn {}
+    ));
+
+    let resp = cli
+        .send_resp::<EvolveEndpoint>(&EvolveReq { a: 3, b: 7 })
+        .await
+        .unwrap();
+    assert_eq!(resp, 3007);
+
+    // An older client doesn't send the new field, which gets its default
+    let resp = cli
+        .send_resp::<EvolveEndpointV1>(&EvolveReqV1 { a: 4 })
+        .await
+        .unwrap();
+    assert_eq!(resp, 4000);
+}
+
+#[tokio::test]
+async fn end_to_end_subscriptions() {
+    let app = subs::SubsDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let subs = app.subscriptions();
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq1);
+    server.set_subscriptions(Some(subs));
+    let mut publisher = server.publisher::<ZetaTopic10>();
+    let server = tokio::task::spawn(async move { server.run().await });
+    let mut sub = cli.subscribe_exclusive::<ZetaTopic10>(8).await.unwrap();
+
+    // Nothing is sent, or counted, until the topic is announced
+    assert!(!publisher.has_subscribers());
+    publisher.publish(&ZMsg(1)).await.unwrap();
+    assert_eq!(publisher.next_seq_no(), 0);
+
+    // ...except for the standard topics
+    let report = cli.get_schema_report().await.unwrap();
+    assert!(!report.endpoints.is_empty());
+
+    // Announcements are not answered, the ping waits until they were handled
+    cli.announce_subscribe::<ZetaTopic10>().await.unwrap();
+    cli.announce_subscribe::<ZetaTopic10>().await.unwrap();
+    cli.ping().await.unwrap();
+    assert_eq!(subs.subscribers(ZetaTopic10::TOPIC_KEY), 2);
+    publisher.publish(&ZMsg(2)).await.unwrap();
+    assert_eq!(sub.recv().await.unwrap().0, 2);
+
+    // The topic is published until the last subscriber is gone
+    cli.announce_unsubscribe::<ZetaTopic10>().await.unwrap();
+    cli.ping().await.unwrap();
+    publisher.publish(&ZMsg(3)).await.unwrap();
+    assert_eq!(sub.recv().await.unwrap().0, 3);
+    cli.announce_unsubscribe::<ZetaTopic10>().await.unwrap();
+    cli.ping().await.unwrap();
+    assert!(!publisher.has_subscribers());
+    publisher.publish(&ZMsg(4)).await.unwrap();
+
+    cli.announce_subscribe::<ZetaTopic10>().await.unwrap();
+    cli.ping().await.unwrap();
+    publisher.publish(&ZMsg(5)).await.unwrap();
+    assert_eq!(sub.recv().await.unwrap().0, 5);
+    assert_eq!(publisher.next_seq_no(), 3);
+
+    // The subscriptions are forgotten when the connection is closed
+    cli.close();
+    let _ = server.await.unwrap();
+    assert!(!publisher.has_subscribers());
+}
+
+#[tokio::test]
+async fn mock_server() {
+    let mut calls = 0;
+    let mut mock = MockServer::new()
+        .on::<AlphaEndpoint>(move |req| {
+            calls += 1;
+            AResp(req.0 + calls)
+        })
+        .on_try::<TryEndpoint>(|req| match req {
+            0 => Err(WireError::Rejected),
+            n => Ok(n * 2),
+        });
+    let cli = mock.client::<WireError>(ERROR_PATH);
+
+    cli.ping().await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(10)).await.unwrap();
+    assert_eq!(resp.0, 11);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(10)).await.unwrap();
+    assert_eq!(resp.0, 12);
+
+    assert_eq!(cli.send_resp::<TryEndpoint>(&21).await.unwrap(), 42);
+    let resp = cli.send_resp::<TryEndpoint>(&0).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::Rejected))));
+    let resp = cli.send_resp::<SleepEndpoint>(&1).await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
+
+    let mut sub = cli.subscribe_exclusive::<ZetaTopic10>(8).await.unwrap();
+    mock.publish::<ZetaTopic10>(&ZMsg(7)).await.unwrap();
+    assert_eq!(sub.recv().await.unwrap().0, 7);
+}
+
+mod offloaded {
+    use super::*;
+
+    pub type OffloadSpawnImpl = OffloadSpawn<ChannelWireSpawn, CriticalSectionRawMutex, 64>;
+
+    define_dispatch! {
+        app: OffloadDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: OffloadSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | SleepEndpoint     | offload   | test_offload_handler  |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+fn test_offload_handler(context: TestSpawnContext, _header: VarHeader, ms: u32) -> u32 {
+    // Stalls whichever thread runs it
+    std::thread::sleep(Duration::from_millis(ms.into()));
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    ms
+}
+
+/// Runs the offloaded handlers of `end_to_end_offload`
+static OFFLOADER: Offloader<CriticalSectionRawMutex, 64> = Offloader::new();
+
+#[tokio::test]
+async fn end_to_end_offload() {
+    use offloaded::OffloadDispatcher;
+
+    // The "lower priority executor" is a runtime on its own thread
+    std::thread::spawn(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(OFFLOADER.run())
+    });
+
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let cli = serve!(OffloadDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        OffloadSpawn::new(ChannelWireSpawn {}, &OFFLOADER),
+    ));
+
+    // Other tasks on the executor of the server keep running while the
+    // handler sleeps
+    let slow = {
+        let cli = cli.clone();
+        tokio::task::spawn(async move { cli.send_resp::<SleepEndpoint>(&100).await })
+    };
+    let mut ticks = 0;
+    while !OFFLOADER.is_busy() {
+        yield_now().await;
+    }
+    while OFFLOADER.is_busy() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        ticks += 1;
+    }
+    assert!(ticks > 5, "{ticks}");
+    assert!(matches!(slow.await.unwrap(), Ok(100)));
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // One job runs at a time, e.g. with several dispatchers in a worker pool
+    let slow = {
+        let cli = cli.clone();
+        tokio::task::spawn(async move { cli.send_resp::<SleepEndpoint>(&50).await })
+    };
+    while !OFFLOADER.is_busy() {
+        yield_now().await;
+    }
+    assert_eq!(OFFLOADER.offload(|| 1).await, Err(OffloadError::Busy));
+    assert!(matches!(slow.await.unwrap(), Ok(50)));
+    assert_eq!(OFFLOADER.offload(|| 1).await, Ok(1));
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+
+    // With tokio, jobs run with `spawn_blocking`
+    let spawn = ChannelWireSpawn {};
+    assert_eq!(spawn.offload(|| 2 + 2).await, Ok(4));
+    let res = spawn.offload(|| -> u32 { panic!("oops") }).await;
+    assert_eq!(res, Err(OffloadError::Failed));
+}
+
+mod muxed {
+    use super::*;
+    use postcard_rpc::server::impls::channel::ChannelWireTx;
+
+    pub type MuxWireTx = ChannelWireTx<CriticalSectionRawMutex, 256, 4>;
+
+    define_dispatch! {
+        app: MuxDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: MuxWireTx;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+/// The frames of `end_to_end_channels`, as seen by the server
+static MUX_WIRE: ChannelWire<CriticalSectionRawMutex, 256, 4> = ChannelWire::new();
+
+/// The tags of the protocols sharing the "port" of `end_to_end_channels`
+const PROTO_RPC: u8 = 1;
+const PROTO_OTHER: u8 = 2;
+
+#[tokio::test]
+async fn end_to_end_channels() {
+    use muxed::MuxDispatcher;
+
+    // The "port" carries tagged packets of several protocols
+    let (port_down_tx, mut port_down_rx) = mpsc::channel::<Vec<u8>>(4);
+    let (port_up_tx, mut port_up_rx) = mpsc::channel::<Vec<u8>>(4);
+    let (other_tx, mut other_rx) = mpsc::channel::<Vec<u8>>(4);
+
+    // The device side multiplexer
+    tokio::task::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(packet) = port_down_rx.recv() => match packet.split_first() {
+                    Some((&PROTO_RPC, frame)) => {
+                        MUX_WIRE.incoming().send(Frame::from_slice(frame).unwrap()).await;
+                    }
+                    _ => other_tx.send(packet).await.unwrap(),
+                },
+                frame = MUX_WIRE.outgoing().receive() => {
+                    let mut packet = vec![PROTO_RPC];
+                    packet.extend_from_slice(&frame);
+                    port_up_tx.send(packet).await.unwrap();
+                }
+            }
+        }
+    });
+
+    let app = MuxDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let (rx, tx) = MUX_WIRE.split();
+    let mut server = Server::new(tx, rx, vec![0u8; 256].into_boxed_slice(), app, kkind);
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // The host side multiplexer
+    let (client_tx, mut mux_rx) = mpsc::channel::<Vec<u8>>(4);
+    let (mux_tx, client_rx) = mpsc::channel::<Vec<u8>>(4);
+    let cli = HostClient::<WireError>::new_with_channels(
+        client_tx,
+        client_rx,
+        ERROR_PATH,
+        8,
+        VarSeqKind::Seq2,
+    );
+    let port_down = port_down_tx.clone();
+    tokio::task::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(frame) = mux_rx.recv() => {
+                    let mut packet = vec![PROTO_RPC];
+                    packet.extend_from_slice(&frame);
+                    port_down.send(packet).await.unwrap();
+                }
+                Some(packet) = port_up_rx.recv() => {
+                    assert_eq!(packet[0], PROTO_RPC);
+                    mux_tx.send(packet[1..].to_vec()).await.unwrap();
+                }
+            }
+        }
+    });
+
+    // Requests and replies pass through both multiplexers
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    cli.ping().await.unwrap();
+
+    // Other protocols share the port
+    port_down_tx.send(vec![PROTO_OTHER, 1, 2, 3]).await.unwrap();
+    assert_eq!(other_rx.recv().await.unwrap(), [PROTO_OTHER, 1, 2, 3]);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(43)).await.unwrap();
+    assert_eq!(resp.0, 43);
+}
+
+// The endpoints as known to hosts built before `BetaEndpoint` was renamed
+endpoints! {
+    list = LEGACY_ENDPOINT_LIST;
+    | EndpointTy        | RequestTy | ResponseTy | Path           |
+    | ----------        | --------- | ---------- | ----           |
+    | LegacyBetaEndpoint | BReq     | BResp      | "legacy/beta"  |
+}
+
+mod aliased {
+    use super::*;
+
+    define_dispatch! {
+        app: AliasDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+            | BetaEndpoint      | spawn     | test_beta_handler [alias = "legacy/beta"] |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_alias() {
+    use aliased::AliasDispatcher;
+
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let app = AliasDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    );
+    let (cli, mut server) = loopback(app, 1024, VarSeqKind::Seq2);
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // Both the current and the legacy key reach the same handler, and each is
+    // answered with its own response key
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+    let resp = cli.send_resp::<LegacyBetaEndpoint>(&BReq(2)).await.unwrap();
+    assert_eq!(resp.0, 2);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+
+    // Other endpoints are unaffected
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+}
+
+mod multi {
+    use super::*;
+    use postcard_rpc::server::multi::EitherTx;
+
+    /// A USB and a UART, say
+    pub type MultiTx = EitherTx<WireTxImpl, WireTxImpl>;
+
+    define_dispatch! {
+        app: MultiDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: MultiTx;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler [idempotent = true] |
+            | BetaEndpoint      | spawn     | test_multi_beta       |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+
+    async fn test_multi_beta(
+        context: TestSpawnContext,
+        header: VarHeader,
+        body: BReq,
+        out: Sender<MultiTx>,
+    ) {
+        context.ctr.fetch_add(1, Ordering::Relaxed);
+        // Give the other transport a chance to send its request in the meantime
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let _ = out
+            .reply::<BetaEndpoint>(header.seq_no, &BResp(body.0.into()))
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_multi_transport() {
+    use multi::MultiDispatcher;
+    use postcard_rpc::server::multi::{EitherTx, SharedDispatcher};
+
+    let ctr = Arc::new(AtomicUsize::new(0));
+    let app = MultiDispatcher::new(
+        TestContext {
+            ctr: ctr.clone(),
+            ..TestContext::default()
+        },
+        ChannelWireSpawn {},
+    );
+    let shared: &'static SharedDispatcher<CriticalSectionRawMutex, MultiDispatcher> =
+        Box::leak(Box::new(SharedDispatcher::new(app)));
+    let kkind = shared.min_key_len();
+
+    // One server per transport, all sharing the dispatcher
+    let mut clients = vec![];
+    for transport in 0..2 {
+        let (client_tx, server_rx) = mpsc::channel(16);
+        let (server_tx, client_rx) = mpsc::channel(16);
+        let tx = ChannelWireTx::new(server_tx);
+        let tx = if transport == 0 {
+            EitherTx::A(tx)
+        } else {
+            EitherTx::B(tx)
+        };
+        let rx = ChannelWireRx::new(server_rx);
+        let buf = vec![0u8; 256].into_boxed_slice();
+        let mut server = Server::new(tx, rx, buf, shared.handle(), kkind);
+        tokio::task::spawn(async move {
+            server.run().await;
+        });
+        clients.push(client::new_from_channels(
+            client_tx,
+            client_rx,
+            VarSeqKind::Seq1,
+        ));
+    }
+    let (usb, uart) = (&clients[0], &clients[1]);
+
+    // Both clients use the same sequence numbers, so a reply sent on the wrong
+    // transport, or cached for the other client, would be taken for the reply of
+    // the other client
+    let (a, b) = tokio::join!(
+        usb.send_resp::<AlphaEndpoint>(&AReq(1)),
+        uart.send_resp::<AlphaEndpoint>(&AReq(2)),
+    );
+    assert_eq!(a.unwrap().0, 1);
+    assert_eq!(b.unwrap().0, 2);
+
+    // Spawned handlers reply on the transport of their request too
+    let (a, b) = tokio::join!(
+        usb.send_resp::<BetaEndpoint>(&BReq(10)),
+        uart.send_resp::<BetaEndpoint>(&BReq(20)),
+    );
+    assert_eq!(a.unwrap().0, 10);
+    assert_eq!(b.unwrap().0, 20);
+
+    // All requests were handled with the same context
+    assert_eq!(ctr.load(Ordering::Relaxed), 4);
+    usb.ping().await.unwrap();
+    uart.ping().await.unwrap();
+}
+
+mod framed {
+    use super::*;
+    use postcard_rpc::framing::{FramedWireTx, LengthPrefixFraming};
+
+    pub type FramedTx = FramedWireTx<WireTxImpl, LengthPrefixFraming, 256>;
+
+    define_dispatch! {
+        app: FramedDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: FramedTx;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+
+    /// A byte pipe, sending each encoded frame in chunks of three bytes
+    pub struct PipeTx(pub mpsc::Sender<Vec<u8>>);
+
+    /// A byte pipe, receiving chunks of bytes
+    pub struct PipeRx(pub mpsc::Receiver<Vec<u8>>);
+
+    pub struct TokioSpawn;
+
+    impl postcard_rpc::host_client::WireTx for PipeTx {
+        type Error = std::io::Error;
+
+        async fn send(&mut self, data: Vec<u8>) -> Result<(), Self::Error> {
+            for chunk in data.chunks(3) {
+                self.0
+                    .send(chunk.to_vec())
+                    .await
+                    .map_err(|_| std::io::ErrorKind::BrokenPipe)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl postcard_rpc::host_client::WireRx for PipeRx {
+        type Error = std::io::Error;
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+            self.0
+                .recv()
+                .await
+                .ok_or(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    impl postcard_rpc::host_client::WireSpawn for TokioSpawn {
+        fn spawn(&mut self, fut: impl std::future::Future<Output = ()> + Send + 'static) {
+            drop(tokio::task::spawn(fut));
+        }
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_framing() {
+    use framed::{FramedDispatcher, PipeRx, PipeTx, TokioSpawn};
+    use postcard_rpc::framing::{FramedWireRx, FramedWireTx, LengthPrefixFraming};
+
+    let (client_tx, server_rx) = mpsc::channel(64);
+    let (server_tx, client_rx) = mpsc::channel(64);
+    let app = FramedDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let mut server = Server::new(
+        FramedWireTx::new(ChannelWireTx::new(server_tx), LengthPrefixFraming::new()),
+        // The chunks are decoded into frames, wherever they were split
+        FramedWireRx::<_, _, 16, 256>::new(
+            ChannelWireRx::new(server_rx),
+            LengthPrefixFraming::new(),
+        ),
+        vec![0u8; 256].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = HostClient::<WireError>::new_with_framing(
+        PipeTx(client_tx),
+        PipeRx(client_rx),
+        LengthPrefixFraming::new(),
+        TokioSpawn,
+        VarSeqKind::Seq2,
+        ERROR_PATH,
+        8,
+    );
+
+    for i in 0..4 {
+        let resp = cli.send_resp::<AlphaEndpoint>(&AReq(i)).await.unwrap();
+        assert_eq!(resp.0, i);
+    }
+    cli.ping().await.unwrap();
+    cli.close();
+}
+
+mod rtt_link {
+    use super::*;
+    use postcard_rpc::framing::{CobsFraming, FramedWireTx};
+    use postcard_rpc::host_client::RttLink;
+    use tokio::sync::mpsc::error::TrySendError;
+
+    pub type RttTx = FramedWireTx<WireTxImpl, CobsFraming, 256>;
+
+    define_dispatch! {
+        app: RttDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: RttTx;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: crate::ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | AlphaEndpoint     | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: crate::TOPICS_IN_LIST;
+
+            | TopicTy           | kind      | handler               |
+            | ----------        | ----      | -------               |
+        };
+        topics_out: {
+            list: crate::TOPICS_OUT_LIST;
+        };
+    }
+
+    /// Fake RTT channels, with a down channel taking 16 bytes at a time
+    pub struct FakeLink {
+        pub up: mpsc::Receiver<Vec<u8>>,
+        pub down: mpsc::Sender<Vec<u8>>,
+        pub rest: Vec<u8>,
+    }
+
+    impl RttLink for FakeLink {
+        type Error = &'static str;
+
+        fn read_up(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.rest.is_empty() {
+                match self.up.try_recv() {
+                    Ok(chunk) => self.rest = chunk,
+                    Err(mpsc::error::TryRecvError::Empty) => return Ok(0),
+                    Err(mpsc::error::TryRecvError::Disconnected) => return Err("closed"),
+                }
+            }
+            let used = buf.len().min(self.rest.len());
+            buf[..used].copy_from_slice(&self.rest[..used]);
+            self.rest.drain(..used);
+            Ok(used)
+        }
+
+        fn write_down(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+            let used = data.len().min(16);
+            match self.down.try_send(data[..used].to_vec()) {
+                Ok(()) => Ok(used),
+                Err(TrySendError::Full(_)) => Ok(0),
+                Err(TrySendError::Closed(_)) => Err("closed"),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn end_to_end_rtt() {
+    use postcard_rpc::framing::{CobsFraming, FramedWireRx, FramedWireTx};
+    use rtt_link::{FakeLink, RttDispatcher};
+
+    let (client_tx, server_rx) = mpsc::channel(4);
+    let (server_tx, client_rx) = mpsc::channel(64);
+    let app = RttDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let mut server = Server::new(
+        FramedWireTx::new(ChannelWireTx::new(server_tx), CobsFraming),
+        FramedWireRx::<_, _, 16, 256>::new(ChannelWireRx::new(server_rx), CobsFraming),
+        vec![0u8; 256].into_boxed_slice(),
+        app,
+        kkind,
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = HostClient::<WireError>::new_rtt(
+        FakeLink {
+            up: client_rx,
+            down: client_tx,
+            rest: Vec::new(),
+        },
+        ERROR_PATH,
+        8,
+        VarSeqKind::Seq2,
+    );
+
+    for i in 0..4 {
+        let resp = cli.send_resp::<AlphaEndpoint>(&AReq(i)).await.unwrap();
+        assert_eq!(resp.0, i);
     }
+    cli.ping().await.unwrap();
+    cli.close();
 }
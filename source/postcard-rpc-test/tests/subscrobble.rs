@@ -14,7 +14,9 @@ use tokio::{
 use postcard_rpc::{
     define_dispatch, endpoints,
     header::{VarHeader, VarSeq, VarSeqKind},
-    host_client::test_channels as client,
+    host_client::{
+        router::TopicRouter, test_channels as client, BackpressurePolicy, HostErr, SubscribeError,
+    },
     server::{
         impls::test_channels::{
             dispatch_impl::{new_server, spawn_fn, Settings, WireSpawnImpl, WireTxImpl},
@@ -22,7 +24,8 @@ use postcard_rpc::{
         },
         Dispatch, Sender, SpawnContext,
     },
-    topics,
+    standard_icd::WireError,
+    topics, Topic,
 };
 
 #[derive(Serialize, Deserialize, Schema)]
@@ -131,6 +134,7 @@ define_dispatch! {
     tx_impl: WireTxImpl;
     spawn_impl: WireSpawnImpl;
     context: TestContext;
+    auto_ping: false;
 
     endpoints: {
         list: ENDPOINT_LIST;
@@ -263,6 +267,15 @@ async fn exclusive_subs_work() {
 
     // Subbing works
     let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // Pings are not handled when `auto_ping` is disabled
+    let resp = cli.ping().await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
+
+    // Nor are credits advertised without `max_in_flight`
+    let resp = cli.enable_flow_control().await;
+    assert!(matches!(resp, Err(HostErr::Wire(WireError::UnknownKey))));
+
     #[allow(deprecated)]
     let mut sub = cli.subscribe::<ZetaTopic10>(16).await.unwrap();
     server_sender
@@ -366,6 +379,26 @@ async fn exclusive_subs_work() {
     let _: () = timeout(Duration::from_millis(100), get_fut_bcst)
         .await
         .unwrap();
+
+    // Publishers number their messages
+    let mut sub6 = cli
+        .subscribe_multi_raw(ZetaTopic10::TOPIC_KEY, 16)
+        .await
+        .unwrap();
+    let mut publisher = server_sender.publisher::<ZetaTopic10>();
+    for i in 0..3 {
+        publisher.publish(&ZMsg(i)).await.unwrap();
+    }
+    assert_eq!(publisher.next_seq_no(), 3);
+    let get_fut = async move {
+        for i in 0..3 {
+            let frame = sub6.recv().await.unwrap();
+            let seq_no: u32 = frame.header.seq_no.into();
+            assert_eq!(seq_no, i as u32);
+            assert_eq!(postcard::from_bytes::<ZMsg>(&frame.body).unwrap(), ZMsg(i));
+        }
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
 }
 
 #[tokio::test]
@@ -466,3 +499,190 @@ async fn broadcast_subs_work() {
     let _: () = timeout(Duration::from_millis(100), get_fut2).await.unwrap();
     let _: () = timeout(Duration::from_millis(100), get_fut3).await.unwrap();
 }
+
+#[tokio::test]
+async fn bounded_subs_work() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let server_sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // Oldest messages are dropped when full
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let mut sub = cli
+        .subscribe_bounded::<ZetaTopic10>(2, BackpressurePolicy::DropOldest)
+        .await
+        .unwrap();
+
+    // Only one bounded sub at a time
+    let res = cli
+        .subscribe_bounded::<ZetaTopic10>(2, BackpressurePolicy::DropOldest)
+        .await;
+    assert!(matches!(res, Err(SubscribeError::AlreadySubscribed)));
+
+    for (i, val) in [10, 20, 30].into_iter().enumerate() {
+        server_sender
+            .publish::<ZetaTopic10>(VarSeq::Seq4(i as u32), &ZMsg(val))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(sub.dropped(), 1);
+    let get_fut = async move {
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(20));
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(30));
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+
+    // Newest messages are dropped when full. The old sub was dropped, so
+    // we can subscribe again.
+    let mut sub = cli
+        .subscribe_bounded::<ZetaTopic10>(2, BackpressurePolicy::DropNewest)
+        .await
+        .unwrap();
+    for (i, val) in [11, 21, 31].into_iter().enumerate() {
+        server_sender
+            .publish::<ZetaTopic10>(VarSeq::Seq4(i as u32), &ZMsg(val))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(sub.dropped(), 1);
+    let get_fut = async move {
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(11));
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(21));
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+
+    // Blocking subs receive everything
+    let mut sub = cli
+        .subscribe_bounded::<ZetaTopic10>(1, BackpressurePolicy::Block)
+        .await
+        .unwrap();
+    let pub_fut = tokio::task::spawn(async move {
+        for (i, val) in [12, 22, 32].into_iter().enumerate() {
+            server_sender
+                .publish::<ZetaTopic10>(VarSeq::Seq4(i as u32), &ZMsg(val))
+                .await
+                .unwrap();
+        }
+    });
+    let get_fut = async move {
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(12));
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(22));
+        assert_eq!(sub.recv().await.unwrap(), ZMsg(32));
+        assert_eq!(sub.dropped(), 0);
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+    pub_fut.await.unwrap();
+}
+
+#[tokio::test]
+async fn topic_router_fans_out() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: Arc::new(AtomicUsize::new(0)),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let server_sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let router = TopicRouter::new::<ZetaTopic10, _>(&cli, 16).await.unwrap();
+    let mut all = router.subscribe(8, BackpressurePolicy::Block);
+    let mut latest = router.subscribe(1, BackpressurePolicy::DropNewest);
+    let dropped = router.subscribe(8, BackpressurePolicy::DropOldest);
+    assert_eq!(router.subscriber_count(), 3);
+    drop(dropped);
+    assert_eq!(router.subscriber_count(), 2);
+
+    // Each subscriber gets every message, with its own backpressure
+    for (i, val) in [10, 20, 30].into_iter().enumerate() {
+        server_sender
+            .publish::<ZetaTopic10>(VarSeq::Seq4(i as u32), &ZMsg(val))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(latest.dropped(), 2);
+    assert_eq!(all.dropped(), 0);
+    assert_eq!(router.lost(), 0);
+    let get_fut = async {
+        assert_eq!(latest.recv().await.unwrap(), ZMsg(10));
+        for val in [10, 20, 30] {
+            assert_eq!(all.recv().await.unwrap(), ZMsg(val));
+        }
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+
+    // Subscribers may join late, and only see later messages
+    let mut late = router.subscribe(8, BackpressurePolicy::DropOldest);
+    server_sender
+        .publish::<ZetaTopic10>(VarSeq::Seq4(3), &ZMsg(40))
+        .await
+        .unwrap();
+    let get_fut = async {
+        assert_eq!(late.recv().await.unwrap(), ZMsg(40));
+        assert_eq!(latest.recv().await.unwrap(), ZMsg(40));
+        assert_eq!(all.recv().await.unwrap(), ZMsg(40));
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+
+    // Closing the client closes all subscriptions
+    cli.close();
+    let get_fut = async {
+        assert!(all.recv().await.is_none());
+        assert!(latest.recv().await.is_none());
+        assert!(late.recv().await.is_none());
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+    assert!(router.is_closed());
+}
@@ -0,0 +1,150 @@
+use core::time::Duration;
+
+use postcard_schema::Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, time::timeout};
+
+use postcard_rpc::{
+    define_dispatch, endpoints,
+    header::{VarHeader, VarKeyKind, VarSeqKind},
+    host_client::{HostClient, HostErr},
+    server::{
+        impls::tcp::{
+            dispatch_impl::{serve, WireSpawnImpl, WireTxImpl},
+            TcpWireSpawn,
+        },
+        Dispatch, SpawnContext,
+    },
+    standard_icd::{PingEndpoint, WireError, ERROR_PATH},
+    topics,
+};
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AReq(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AResp(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct BigReq(pub Vec<u8>);
+
+endpoints! {
+    list = ENDPOINT_LIST;
+    | EndpointTy        | RequestTy     | ResponseTy    | Path      |
+    | ----------        | ---------     | ----------    | ----      |
+    | AlphaEndpoint     | AReq          | AResp         | "alpha"   |
+    | BigEndpoint       | BigReq        | u32           | "big"     |
+}
+
+topics! {
+    list = TOPICS_IN_LIST;
+    direction = postcard_rpc::TopicDirection::ToServer;
+    | TopicTy       | MessageTy     | Path      |
+    | ----------    | ---------     | ----      |
+}
+
+topics! {
+    list = TOPICS_OUT_LIST;
+    direction = postcard_rpc::TopicDirection::ToClient;
+    | TopicTy       | MessageTy     | Path      |
+    | ----------    | ---------     | ----      |
+}
+
+pub struct TestContext;
+
+impl SpawnContext for TestContext {
+    type SpawnCtxt = ();
+
+    fn spawn_ctxt(&mut self) -> Self::SpawnCtxt {}
+}
+
+define_dispatch! {
+    app: SingleDispatcher;
+    spawn_fn: spawn_fn;
+    tx_impl: WireTxImpl;
+    spawn_impl: WireSpawnImpl;
+    context: TestContext;
+    // Wider than needed, for a bit of margin against newer clients
+    key_len: 4;
+
+    endpoints: {
+        list: crate::ENDPOINT_LIST;
+
+        | EndpointTy        | kind      | handler           |
+        | ----------        | ----      | -------           |
+        | AlphaEndpoint     | async     | alpha_handler     |
+        | BigEndpoint       | blocking  | big_handler       |
+    };
+    topics_in: {
+        list: crate::TOPICS_IN_LIST;
+
+        | TopicTy           | kind      | handler           |
+        | ----------        | ----      | -------           |
+    };
+    topics_out: {
+        list: crate::TOPICS_OUT_LIST;
+    };
+}
+
+async fn alpha_handler(_context: &mut TestContext, _header: VarHeader, body: AReq) -> AResp {
+    AResp(body.0 + 1)
+}
+
+fn big_handler(_context: &mut TestContext, _header: VarHeader, body: BigReq) -> u32 {
+    body.0.len() as u32
+}
+
+#[test]
+fn fixed_key_len() {
+    let app = SingleDispatcher::new(TestContext, TcpWireSpawn);
+    assert_eq!(app.min_key_len(), VarKeyKind::Key4);
+    assert_eq!(app.device_map.min_key_len, VarKeyKind::Key4);
+}
+
+#[tokio::test]
+async fn end_to_end_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // The server is not `Send`, so run it on a LocalSet
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(async move {
+        let _ = serve(listener, 256, || {
+            SingleDispatcher::new(TestContext, TcpWireSpawn)
+        })
+        .await;
+    });
+    local
+        .run_until(async move {
+            // Connect twice in a row, to make sure the server accepts the next connection
+            for _ in 0..2 {
+                let cli = HostClient::<WireError>::new_tcp(addr, ERROR_PATH, 8, VarSeqKind::Seq2);
+                let resp = timeout(
+                    Duration::from_secs(1),
+                    cli.send_resp::<AlphaEndpoint>(&AReq(41)),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                assert_eq!(resp.0, 42);
+                let resp = cli.send_resp::<PingEndpoint>(&1234).await.unwrap();
+                assert_eq!(resp, 1234);
+
+                // Frames larger than the server's buffer are rejected...
+                let resp = timeout(
+                    Duration::from_millis(100),
+                    cli.send_resp::<BigEndpoint>(&BigReq(vec![0; 1024])),
+                )
+                .await
+                .unwrap();
+                // The header has a four byte key and a four byte sequence number
+                assert!(matches!(resp, Err(HostErr::BodyTooLarge { max: 247 })));
+                // ...without losing sync
+                let resp = cli
+                    .send_resp::<BigEndpoint>(&BigReq(vec![0; 16]))
+                    .await
+                    .unwrap();
+                assert_eq!(resp, 16);
+                cli.close();
+            }
+        })
+        .await;
+}
@@ -4,6 +4,34 @@
 //!
 //! Unlike the `CobsAccumulator` from `postcard`, these versions do not deserialize
 //! directly.
+//!
+//! This is useful on both the target and the host, to turn a stream of bytes, e.g.
+//! from a serial port, into complete postcard-rpc frames:
+//!
+//! ```rust
+//! use postcard_rpc::accumulator::raw::{CobsAccumulator, FeedResult};
+//!
+//! let mut acc = CobsAccumulator::<64>::new();
+//! // Two frames, split across reads at arbitrary points
+//! let reads: [&[u8]; 2] = [&[0x03, 0x01, 0x02, 0x00, 0x02], &[0x05, 0x00]];
+//! let mut frames = vec![];
+//!
+//! for mut window in reads {
+//!     while !window.is_empty() {
+//!         window = match acc.feed(window) {
+//!             FeedResult::Consumed => break,
+//!             FeedResult::OverFull(rest) | FeedResult::DeserError(rest) => rest,
+//!             FeedResult::Success { data, remaining } => {
+//!                 frames.push(data.to_vec());
+//!                 remaining
+//!             }
+//!         };
+//!     }
+//! }
+//! assert_eq!(frames, [vec![0x01, 0x02], vec![0x05]]);
+//! ```
+//!
+//! **Requires feature**: `cobs`
 
 /// Decode-only accumulator
 pub mod raw {
@@ -23,16 +51,16 @@ pub mod raw {
         /// Buffer was filled. Contains remaining section of input, if any.
         OverFull(&'a [u8]),
 
-        /// Reached end of chunk, but deserialization failed. Contains remaining section of input, if.
+        /// Reached end of chunk, but COBS decoding failed. Contains remaining section of input, if
         /// any
         DeserError(&'a [u8]),
 
-        /// Deserialization complete. Contains deserialized data and remaining section of input, if any.
+        /// Decoding complete. Contains the decoded frame and remaining section of input, if any.
         Success {
-            /// Deserialize data.
+            /// The decoded frame
             data: &'b [u8],
 
-            /// Remaining data left in the buffer after deserializing.
+            /// Remaining data left in the input after the end of this frame
             remaining: &'a [u8],
         },
     }
@@ -46,74 +74,132 @@ pub mod raw {
             }
         }
 
-        /// Appends data to the internal buffer and attempts to deserialize the accumulated data into
-        /// `T`.
+        /// Appends data to the internal buffer and attempts to decode the accumulated data
+        ///
+        /// At most one frame is decoded per call. If a frame is found, any input after
+        /// it is returned as `remaining`, and should be fed again.
         #[inline]
         pub fn feed<'a, 'b>(&'b mut self, input: &'a [u8]) -> FeedResult<'a, 'b> {
             self.feed_ref(input)
         }
 
-        /// Appends data to the internal buffer and attempts to deserialize the accumulated data into
-        /// `T`.
+        /// Appends data to the internal buffer and attempts to decode the accumulated data
         ///
-        /// This differs from feed, as it allows the `T` to reference data within the internal buffer, but
-        /// mutably borrows the accumulator for the lifetime of the deserialization.
-        /// If `T` does not require the reference, the borrow of `self` ends at the end of the function.
+        /// This is identical to [`feed()`](Self::feed).
         pub fn feed_ref<'a, 'b>(&'b mut self, input: &'a [u8]) -> FeedResult<'a, 'b> {
-            if input.is_empty() {
-                return FeedResult::Consumed;
-            }
+            feed_slice(&mut self.buf, &mut self.idx, input)
+        }
+
+        /// Discard any partially accumulated frame
+        pub fn reset(&mut self) {
+            self.idx = 0;
+        }
+    }
+
+    /// Accumulate COBS encoded `input` into the first `*idx` bytes of `buf`
+    ///
+    /// This is the algorithm of [`CobsAccumulator::feed()`], for buffers that are
+    /// not owned by an accumulator.
+    pub(crate) fn feed_slice<'a, 'b>(
+        buf: &'b mut [u8],
+        idx: &mut usize,
+        input: &'a [u8],
+    ) -> FeedResult<'a, 'b> {
+        if input.is_empty() {
+            return FeedResult::Consumed;
+        }
+
+        let zero_pos = input.iter().position(|&i| i == 0);
 
-            let zero_pos = input.iter().position(|&i| i == 0);
-
-            if let Some(n) = zero_pos {
-                // Yes! We have an end of message here.
-                // Add one to include the zero in the "take" portion
-                // of the buffer, rather than in "release".
-                let (take, release) = input.split_at(n + 1);
-
-                // Does it fit?
-                if (self.idx + take.len()) <= N {
-                    // Aw yiss - add to array
-                    self.extend_unchecked(take);
-
-                    let retval = match decode_in_place(&mut self.buf[..self.idx]) {
-                        Ok(used) => FeedResult::Success {
-                            data: &self.buf[..used],
-                            remaining: release,
-                        },
-                        Err(_) => FeedResult::DeserError(release),
-                    };
-                    self.idx = 0;
-                    retval
-                } else {
-                    self.idx = 0;
-                    FeedResult::OverFull(release)
+        if let Some(n) = zero_pos {
+            // Yes! We have an end of message here.
+            // Add one to include the zero in the "take" portion
+            // of the buffer, rather than in "release".
+            let (take, release) = input.split_at(n + 1);
+
+            // Does it fit?
+            if (*idx + take.len()) <= buf.len() {
+                // Aw yiss - add to array
+                let end = *idx + take.len();
+                buf[*idx..end].copy_from_slice(take);
+                *idx = 0;
+
+                match decode_in_place(&mut buf[..end]) {
+                    Ok(used) => FeedResult::Success {
+                        data: &buf[..used],
+                        remaining: release,
+                    },
+                    Err(_) => FeedResult::DeserError(release),
                 }
             } else {
-                // Does it fit?
-                if (self.idx + input.len()) > N {
-                    // nope
-                    let new_start = N - self.idx;
-                    self.idx = 0;
-                    FeedResult::OverFull(&input[new_start..])
-                } else {
-                    // yup!
-                    self.extend_unchecked(input);
-                    FeedResult::Consumed
-                }
+                *idx = 0;
+                FeedResult::OverFull(release)
+            }
+        } else {
+            // Does it fit?
+            if (*idx + input.len()) > buf.len() {
+                // nope
+                let new_start = buf.len() - *idx;
+                *idx = 0;
+                FeedResult::OverFull(&input[new_start..])
+            } else {
+                // yup!
+                let end = *idx + input.len();
+                buf[*idx..end].copy_from_slice(input);
+                *idx = end;
+                FeedResult::Consumed
             }
         }
+    }
 
-        /// Extend the internal buffer with the given input.
-        ///
-        /// # Panics
-        ///
-        /// Will panic if the input does not fit in the internal buffer.
-        fn extend_unchecked(&mut self, input: &[u8]) {
-            let new_end = self.idx + input.len();
-            self.buf[self.idx..new_end].copy_from_slice(input);
-            self.idx = new_end;
+    impl<const N: usize> Default for CobsAccumulator<N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::{CobsAccumulator, FeedResult};
+
+        #[test]
+        fn split_frames() {
+            let mut acc = CobsAccumulator::<8>::new();
+            assert!(matches!(acc.feed(&[0x03, 0x01]), FeedResult::Consumed));
+            let FeedResult::Success { data, remaining } = acc.feed(&[0x02, 0x00, 0x02]) else {
+                panic!()
+            };
+            assert_eq!(data, [0x01, 0x02]);
+            assert_eq!(remaining, [0x02]);
+            assert!(matches!(acc.feed(remaining), FeedResult::Consumed));
+            let FeedResult::Success { data, remaining } = acc.feed(&[0x05, 0x00]) else {
+                panic!()
+            };
+            assert_eq!(data, [0x05]);
+            assert!(remaining.is_empty());
+        }
+
+        #[test]
+        fn overfull_and_errors() {
+            let mut acc = CobsAccumulator::<4>::new();
+            // Too long, the rest of the frame is returned
+            let FeedResult::OverFull(rest) = acc.feed(&[0x06, 1, 2, 3, 4, 5, 0x00, 0x02, 0x07])
+            else {
+                panic!()
+            };
+            assert_eq!(rest, [0x02, 0x07]);
+            let FeedResult::Success { data, .. } = acc.feed(&[0x02, 0x07, 0x00]) else {
+                panic!()
+            };
+            assert_eq!(data, [0x07]);
+            // Invalid COBS, the accumulator recovers at the next frame
+            let FeedResult::DeserError(rest) = acc.feed(&[0x05, 0x00, 0x02, 0x07, 0x00]) else {
+                panic!()
+            };
+            let FeedResult::Success { data, .. } = acc.feed(rest) else {
+                panic!()
+            };
+            assert_eq!(data, [0x07]);
         }
     }
 }
@@ -0,0 +1,330 @@
+//! Message authentication
+//!
+//! These tools append a truncated HMAC-SHA256 tag to every frame, computed over
+//! the header and body with a key shared by the client and server, and check the
+//! tag of every received frame. This prevents a third party on the link from
+//! sending commands, or tampering with messages, without knowing the key.
+//!
+//! On the client, authentication is enabled with [`HostClient::set_auth()`]. Frames
+//! with an invalid tag are dropped.
+//!
+//! On the server, the [`WireTx`] and [`WireRx`] impls are wrapped with [`AuthWireTx`]
+//! and [`AuthWireRx`]. Requests with an invalid tag are answered with
+//! [`WireError::AuthFailed`], and are never dispatched:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::auth::{Authenticator, AuthWireRx, AuthWireTx};
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 256 bytes, including the tag, may be sent
+//!     tx_impl: AuthWireTx<WireTxImpl, 256>;
+//!     // ...
+//! }
+//!
+//! let auth = Authenticator::new(KEY, 8);
+//! let server = Server::new(
+//!     AuthWireTx::new(tx, auth.clone()),
+//!     AuthWireRx::new(rx, auth),
+//!     buf,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+//!
+//! Tags do not protect against replaying previously sent frames.
+//!
+//! **Requires feature**: `auth`
+//!
+//! [`HostClient::set_auth()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/host_client/struct.HostClient.html#method.set_auth
+//! [`WireError::AuthFailed`]: crate::standard_icd::WireError::AuthFailed
+
+use core::fmt::Arguments;
+
+use hmac::{Hmac, Mac};
+use portable_atomic::{AtomicU32, Ordering};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{
+        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+/// The length of a full HMAC-SHA256 tag, and the longest supported tag length
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Computes and checks the authentication tags of frames
+#[derive(Clone)]
+pub struct Authenticator {
+    mac: Hmac<Sha256>,
+    tag_len: usize,
+}
+
+impl Authenticator {
+    /// Create a new `Authenticator` with the given shared key
+    ///
+    /// The HMAC-SHA256 tag of each frame is truncated to `tag_len` bytes. Shorter
+    /// tags reduce overhead, but are easier to guess: a forged frame is accepted
+    /// with a chance of `1 / 2^(8 * tag_len)`.
+    ///
+    /// Panics if `tag_len` is zero or larger than [`MAX_TAG_LEN`].
+    pub fn new(key: &[u8], tag_len: usize) -> Self {
+        assert!(
+            tag_len > 0 && tag_len <= MAX_TAG_LEN,
+            "tag_len must be between 1 and 32"
+        );
+        Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+            tag_len,
+        }
+    }
+
+    /// The length of the tag appended to each frame
+    pub fn tag_len(&self) -> usize {
+        self.tag_len
+    }
+
+    /// Compute the tag of `frame`, writing it to the first `tag_len` bytes of `tag`
+    ///
+    /// Panics if `tag` is shorter than [`Authenticator::tag_len()`].
+    pub fn sign(&self, frame: &[u8], tag: &mut [u8]) {
+        let mut mac = self.mac.clone();
+        mac.update(frame);
+        let full = mac.finalize().into_bytes();
+        tag[..self.tag_len].copy_from_slice(&full[..self.tag_len]);
+    }
+
+    /// Check the tag at the end of `frame`
+    ///
+    /// Returns the length of the frame without the tag, or `None` if the frame is
+    /// too short or the tag is invalid.
+    pub fn verify(&self, frame: &[u8]) -> Option<usize> {
+        let len = frame.len().checked_sub(self.tag_len)?;
+        let (frame, tag) = frame.split_at(len);
+        let mut mac = self.mac.clone();
+        mac.update(frame);
+        mac.verify_truncated_left(tag).ok()?;
+        Some(len)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl that appends an authentication tag to each frame
+///
+/// Frames are serialized into a buffer of `N` bytes on the stack, which limits
+/// the size of frames that can be sent, including the tag. Larger frames fail
+/// to send with [`AuthWireTxError::MessageTooLarge`].
+pub struct AuthWireTx<Tx, const N: usize> {
+    tx: Tx,
+    auth: Authenticator,
+    log_seq: AtomicU32,
+}
+
+impl<Tx, const N: usize> AuthWireTx<Tx, N> {
+    /// Wrap `tx`, authenticating frames with `auth`
+    pub fn new(tx: Tx, auth: Authenticator) -> Self {
+        Self {
+            tx,
+            auth,
+            log_seq: AtomicU32::new(0),
+        }
+    }
+
+    fn log_header(&self, kkind: VarKeyKind) -> VarHeader {
+        let seq = self.log_seq.fetch_add(1, Ordering::Relaxed);
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq4(seq),
+        }
+    }
+}
+
+impl<Tx: Clone, const N: usize> Clone for AuthWireTx<Tx, N> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            auth: self.auth.clone(),
+            log_seq: AtomicU32::new(self.log_seq.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<Tx: WireTx, const N: usize> AuthWireTx<Tx, N> {
+    /// Sign the first `len` bytes of `buf`, and send them with the tag
+    async fn send_signed(
+        &self,
+        buf: &mut [u8; N],
+        len: usize,
+    ) -> Result<(), AuthWireTxError<Tx::Error>> {
+        let tag_len = self.auth.tag_len();
+        if len + tag_len > N {
+            return Err(AuthWireTxError::MessageTooLarge);
+        }
+        let (frame, tag) = buf.split_at_mut(len);
+        self.auth.sign(frame, tag);
+        self.tx
+            .send_raw(&buf[..len + tag_len])
+            .await
+            .map_err(AuthWireTxError::Inner)
+    }
+}
+
+impl<Tx: WireTx, const N: usize> WireTx for AuthWireTx<Tx, N> {
+    type Error = AuthWireTxError<Tx::Error>;
+
+    async fn wait_connection(&self) {
+        self.tx.wait_connection().await;
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        let (hdr_used, remain) = hdr
+            .write_to_slice(&mut buf)
+            .ok_or(AuthWireTxError::MessageTooLarge)?;
+        let hdr_len = hdr_used.len();
+        let body_len = postcard::to_slice(msg, remain)
+            .map_err(|_| AuthWireTxError::MessageTooLarge)?
+            .len();
+        self.send_signed(&mut buf, hdr_len + body_len).await
+    }
+
+    async fn send_raw(&self, frame: &[u8]) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        buf.get_mut(..frame.len())
+            .ok_or(AuthWireTxError::MessageTooLarge)?
+            .copy_from_slice(frame);
+        self.send_signed(&mut buf, frame.len()).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, s).await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, &a).await
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.flush().await.map_err(AuthWireTxError::Inner)
+    }
+}
+
+/// The error type of [`AuthWireTx`]
+#[derive(Debug)]
+pub enum AuthWireTxError<E> {
+    /// The wrapped [`WireTx`] impl returned an error
+    Inner(E),
+    /// The frame did not fit in the buffer
+    MessageTooLarge,
+}
+
+impl<E: AsWireTxErrorKind> AsWireTxErrorKind for AuthWireTxError<E> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            AuthWireTxError::Inner(e) => e.as_kind(),
+            AuthWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl that checks and removes the authentication tag of each frame
+///
+/// The receive buffer must have room for the tag, in addition to the frame.
+pub struct AuthWireRx<Rx> {
+    rx: Rx,
+    auth: Authenticator,
+}
+
+impl<Rx> AuthWireRx<Rx> {
+    /// Wrap `rx`, authenticating frames with `auth`
+    pub fn new(rx: Rx, auth: Authenticator) -> Self {
+        Self { rx, auth }
+    }
+}
+
+impl<Rx: WireRx> WireRx for AuthWireRx<Rx> {
+    type Error = AuthWireRxError<Rx::Error>;
+
+    async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await;
+    }
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let frame = self.rx.receive(buf).await.map_err(AuthWireRxError::Inner)?;
+        match self.auth.verify(frame) {
+            Some(len) => Ok(&mut frame[..len]),
+            None => Err(AuthWireRxError::AuthFailed(
+                VarHeader::take_from_slice(frame).map(|(hdr, _)| hdr),
+            )),
+        }
+    }
+}
+
+/// The error type of [`AuthWireRx`]
+#[derive(Debug)]
+pub enum AuthWireRxError<E> {
+    /// The wrapped [`WireRx`] impl returned an error
+    Inner(E),
+    /// The frame had an invalid tag, with the given header, if it could be read
+    AuthFailed(Option<VarHeader>),
+}
+
+impl<E: AsWireRxErrorKind> AsWireRxErrorKind for AuthWireRxError<E> {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            AuthWireRxError::Inner(e) => e.as_kind(),
+            AuthWireRxError::AuthFailed(Some(hdr)) => WireRxErrorKind::AuthFailed(*hdr),
+            AuthWireRxError::AuthFailed(None) => WireRxErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Authenticator;
+
+    #[test]
+    fn sign_verify() {
+        let auth = Authenticator::new(b"secret", 8);
+        let mut frame = [1u8, 2, 3, 4, 5, 0, 0, 0, 0, 0, 0, 0, 0];
+        let (body, tag) = frame.split_at_mut(5);
+        auth.sign(body, tag);
+        assert_eq!(auth.verify(&frame), Some(5));
+
+        // Tampered frames, wrong keys, and short frames are rejected
+        let mut tampered = frame;
+        tampered[0] ^= 1;
+        assert_eq!(auth.verify(&tampered), None);
+        assert_eq!(Authenticator::new(b"other", 8).verify(&frame), None);
+        assert_eq!(auth.verify(&frame[..7]), None);
+    }
+}
@@ -0,0 +1,323 @@
+//! Frame checksums
+//!
+//! These tools append a CRC-32 checksum to every frame, computed over the header
+//! and body, and check the checksum of every received frame. This detects frames
+//! corrupted by noise on links without integrity checks of their own, e.g. a UART,
+//! which could otherwise deserialize into valid-looking garbage.
+//!
+//! On the client, checksums are enabled with [`HostClient::set_checksum()`].
+//! Frames with an invalid checksum are dropped.
+//!
+//! On the server, the [`WireTx`] and [`WireRx`] impls are wrapped with
+//! [`ChecksumWireTx`] and [`ChecksumWireRx`]. Requests with an invalid checksum are
+//! answered with [`WireError::ChecksumFailed`], if their header could still be read,
+//! and are never dispatched:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::checksum::{ChecksumWireRx, ChecksumWireTx};
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 256 bytes, including the checksum, may be sent
+//!     tx_impl: ChecksumWireTx<WireTxImpl, 256>;
+//!     // ...
+//! }
+//!
+//! let server = Server::new(
+//!     ChecksumWireTx::new(tx),
+//!     ChecksumWireRx::new(rx),
+//!     buf,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+//!
+//! The checksum is part of the frame, so it is protected by any framing of the
+//! transport, e.g. COBS on a serial port. When combined with [`auth`](crate::auth),
+//! the checksum wrappers must be the innermost, i.e. `AuthWireTx<ChecksumWireTx<Tx>>`,
+//! matching the client, which checks the checksum before the authentication tag.
+//!
+//! **Requires feature**: `checksum`
+//!
+//! [`HostClient::set_checksum()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/host_client/struct.HostClient.html#method.set_checksum
+//! [`WireError::ChecksumFailed`]: crate::standard_icd::WireError::ChecksumFailed
+
+use core::fmt::Arguments;
+
+use portable_atomic::{AtomicU32, Ordering};
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{
+        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+/// The length of the checksum appended to each frame
+pub const CHECKSUM_LEN: usize = 4;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Calculate the CRC-32 (IEEE 802.3) checksum of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = TABLE[((crc ^ u32::from(*b)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Write the checksum of `frame` to the first [`CHECKSUM_LEN`] bytes of `out`
+///
+/// Panics if `out` is shorter than [`CHECKSUM_LEN`].
+pub fn append(frame: &[u8], out: &mut [u8]) {
+    out[..CHECKSUM_LEN].copy_from_slice(&crc32(frame).to_le_bytes());
+}
+
+/// Check the checksum at the end of `frame`
+///
+/// Returns the length of the frame without the checksum, or `None` if the frame
+/// is too short or the checksum is invalid.
+pub fn verify(frame: &[u8]) -> Option<usize> {
+    let len = frame.len().checked_sub(CHECKSUM_LEN)?;
+    let (frame, sum) = frame.split_at(len);
+    (sum == crc32(frame).to_le_bytes()).then_some(len)
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl that appends a checksum to each frame
+///
+/// Frames are serialized into a buffer of `N` bytes on the stack, which limits
+/// the size of frames that can be sent, including the checksum. Larger frames
+/// fail to send with [`ChecksumWireTxError::MessageTooLarge`].
+pub struct ChecksumWireTx<Tx, const N: usize> {
+    tx: Tx,
+    log_seq: AtomicU32,
+}
+
+impl<Tx, const N: usize> ChecksumWireTx<Tx, N> {
+    /// Wrap `tx`, appending a checksum to each frame
+    pub fn new(tx: Tx) -> Self {
+        Self {
+            tx,
+            log_seq: AtomicU32::new(0),
+        }
+    }
+
+    fn log_header(&self, kkind: VarKeyKind) -> VarHeader {
+        let seq = self.log_seq.fetch_add(1, Ordering::Relaxed);
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq4(seq),
+        }
+    }
+}
+
+impl<Tx: Clone, const N: usize> Clone for ChecksumWireTx<Tx, N> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            log_seq: AtomicU32::new(self.log_seq.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<Tx: WireTx, const N: usize> ChecksumWireTx<Tx, N> {
+    /// Append the checksum of the first `len` bytes of `buf`, and send them
+    async fn send_summed(
+        &self,
+        buf: &mut [u8; N],
+        len: usize,
+    ) -> Result<(), ChecksumWireTxError<Tx::Error>> {
+        if len + CHECKSUM_LEN > N {
+            return Err(ChecksumWireTxError::MessageTooLarge);
+        }
+        let (frame, sum) = buf.split_at_mut(len);
+        append(frame, sum);
+        self.tx
+            .send_raw(&buf[..len + CHECKSUM_LEN])
+            .await
+            .map_err(ChecksumWireTxError::Inner)
+    }
+}
+
+impl<Tx: WireTx, const N: usize> WireTx for ChecksumWireTx<Tx, N> {
+    type Error = ChecksumWireTxError<Tx::Error>;
+
+    async fn wait_connection(&self) {
+        self.tx.wait_connection().await;
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        let (hdr_used, remain) = hdr
+            .write_to_slice(&mut buf)
+            .ok_or(ChecksumWireTxError::MessageTooLarge)?;
+        let hdr_len = hdr_used.len();
+        let body_len = postcard::to_slice(msg, remain)
+            .map_err(|_| ChecksumWireTxError::MessageTooLarge)?
+            .len();
+        self.send_summed(&mut buf, hdr_len + body_len).await
+    }
+
+    async fn send_raw(&self, frame: &[u8]) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        buf.get_mut(..frame.len())
+            .ok_or(ChecksumWireTxError::MessageTooLarge)?
+            .copy_from_slice(frame);
+        self.send_summed(&mut buf, frame.len()).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, s).await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, &a).await
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.flush().await.map_err(ChecksumWireTxError::Inner)
+    }
+}
+
+/// The error type of [`ChecksumWireTx`]
+#[derive(Debug)]
+pub enum ChecksumWireTxError<E> {
+    /// The wrapped [`WireTx`] impl returned an error
+    Inner(E),
+    /// The frame did not fit in the buffer
+    MessageTooLarge,
+}
+
+impl<E: AsWireTxErrorKind> AsWireTxErrorKind for ChecksumWireTxError<E> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            ChecksumWireTxError::Inner(e) => e.as_kind(),
+            ChecksumWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl that checks and removes the checksum of each frame
+///
+/// The receive buffer must have room for the checksum, in addition to the frame.
+pub struct ChecksumWireRx<Rx> {
+    rx: Rx,
+}
+
+impl<Rx> ChecksumWireRx<Rx> {
+    /// Wrap `rx`, checking the checksum of each frame
+    pub fn new(rx: Rx) -> Self {
+        Self { rx }
+    }
+}
+
+impl<Rx: WireRx> WireRx for ChecksumWireRx<Rx> {
+    type Error = ChecksumWireRxError<Rx::Error>;
+
+    async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await;
+    }
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let frame = self
+            .rx
+            .receive(buf)
+            .await
+            .map_err(ChecksumWireRxError::Inner)?;
+        match verify(frame) {
+            Some(len) => Ok(&mut frame[..len]),
+            None => Err(ChecksumWireRxError::ChecksumFailed(
+                VarHeader::take_from_slice(frame).map(|(hdr, _)| hdr),
+            )),
+        }
+    }
+}
+
+/// The error type of [`ChecksumWireRx`]
+#[derive(Debug)]
+pub enum ChecksumWireRxError<E> {
+    /// The wrapped [`WireRx`] impl returned an error
+    Inner(E),
+    /// The frame had an invalid checksum, with the given header, if it could be
+    /// read. The header itself may have been corrupted.
+    ChecksumFailed(Option<VarHeader>),
+}
+
+impl<E: AsWireRxErrorKind> AsWireRxErrorKind for ChecksumWireRxError<E> {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            ChecksumWireRxError::Inner(e) => e.as_kind(),
+            ChecksumWireRxError::ChecksumFailed(Some(hdr)) => WireRxErrorKind::ChecksumFailed(*hdr),
+            ChecksumWireRxError::ChecksumFailed(None) => WireRxErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{append, crc32, verify};
+
+    #[test]
+    fn append_verify() {
+        // The standard check value of CRC-32
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut frame = [1u8, 2, 3, 4, 5, 0, 0, 0, 0];
+        let (body, sum) = frame.split_at_mut(5);
+        append(body, sum);
+        assert_eq!(verify(&frame), Some(5));
+
+        // Corrupted and short frames are rejected
+        let mut corrupted = frame;
+        corrupted[2] ^= 0x10;
+        assert_eq!(verify(&corrupted), None);
+        assert_eq!(verify(&frame[..3]), None);
+    }
+}
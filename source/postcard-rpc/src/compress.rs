@@ -0,0 +1,558 @@
+//! Compression of large bodies
+//!
+//! These tools compress the body of frames larger than a given threshold on the
+//! sending side, and decompress them on the receiving side before the frame is
+//! decoded. This reduces the time needed to send large, repetitive, messages,
+//! e.g. configuration dumps. Smaller frames, and frames that do not get any
+//! smaller when compressed, are sent unchanged.
+//!
+//! Compressed frames are marked with the [`VarHeader::COMPRESSED_BITS`] flag in
+//! the discriminant of their header, which is the most significant bit of the
+//! protocol version. Receivers that do not decompress frames see them as frames
+//! of an unknown protocol version, and reject them. Only the body is compressed,
+//! the rest of the header is unchanged.
+//!
+//! The compression algorithm is pluggable with the [`Compressor`] trait. [`Lzss`]
+//! is a simple implementation, without allocations, that works on all targets.
+//!
+//! On the client, compression is enabled with [`HostClient::set_compression()`].
+//!
+//! On the server, the [`WireTx`] and [`WireRx`] impls are wrapped with
+//! [`CompressWireTx`] and [`CompressWireRx`]. Each direction can be enabled on its
+//! own, e.g. when only the replies of the device are large:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::compress::{CompressWireTx, Lzss};
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 1024 bytes are sent, and compressed if the body is
+//!     // larger than the threshold given to `new`
+//!     tx_impl: CompressWireTx<WireTxImpl, Lzss, 1024>;
+//!     // ...
+//! }
+//!
+//! let server = Server::new(
+//!     CompressWireTx::new(tx, Lzss, 64),
+//!     rx,
+//!     buf,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+//!
+//! Compression applies to whole frames, so the compression wrappers must be the
+//! outermost, i.e. `CompressWireTx<FragWireTx<AuthWireTx<ChecksumWireTx<Tx>>>>`,
+//! matching the client, which compresses frames before splitting them into
+//! fragments.
+//!
+//! **Requires feature**: `compress`
+//!
+//! [`HostClient::set_compression()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/host_client/struct.HostClient.html#method.set_compression
+
+use core::fmt::Arguments;
+
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKeyKind},
+    server::{
+        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
+};
+
+/// An error when compressing or decompressing data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressError {
+    /// The output buffer was too small for the result
+    OutputTooSmall,
+    /// The compressed data was invalid
+    Malformed,
+}
+
+/// A compression algorithm
+pub trait Compressor {
+    /// Compress `input` into `output`, returning the length of the compressed data
+    fn compress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError>;
+
+    /// Decompress `input` into `output`, returning the length of the decompressed data
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError>;
+}
+
+impl<C: Compressor + ?Sized> Compressor for &C {
+    fn compress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError> {
+        C::compress(self, input, output)
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError> {
+        C::decompress(self, input, output)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// LZSS
+//////////////////////////////////////////////////////////////////////////////
+
+/// A simple LZSS compressor, in the spirit of `heatshrink`
+///
+/// The compressed data is a sequence of groups of up to eight items, each preceded
+/// by a flag byte. Bit `n` of the flag byte, starting at the least significant bit,
+/// is set if item `n` is a literal byte, or clear if it is a back-reference. A
+/// back-reference is a little-endian `u16`, whose 12 lower bits are the distance
+/// back into the decompressed data, minus one, and whose 4 upper bits are the
+/// number of bytes to copy, minus [`Lzss::MIN_MATCH`].
+///
+/// Compression searches the previous [`Lzss::WINDOW`] bytes for the longest match
+/// at every position, which is slow for large inputs, but needs no memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lzss;
+
+impl Lzss {
+    /// The distance a back-reference can reach back
+    pub const WINDOW: usize = 4096;
+    /// The length of the shortest back-reference
+    pub const MIN_MATCH: usize = 3;
+    /// The length of the longest back-reference
+    pub const MAX_MATCH: usize = Self::MIN_MATCH + 15;
+
+    /// The distance and length of the longest match for `input[pos..]`
+    fn longest_match(input: &[u8], pos: usize) -> (usize, usize) {
+        let max = Self::MAX_MATCH.min(input.len() - pos);
+        let mut best = (0, 0);
+        for start in pos.saturating_sub(Self::WINDOW)..pos {
+            // Matches may overlap the current position, as decompression copies
+            // one byte at a time
+            let len = (0..max)
+                .take_while(|i| input[start + i] == input[pos + i])
+                .count();
+            if len > best.1 {
+                best = (pos - start, len);
+                if len == max {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+impl Compressor for Lzss {
+    fn compress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError> {
+        let mut pos = 0;
+        let mut out = 0;
+        while pos < input.len() {
+            let flag_idx = out;
+            *output
+                .get_mut(flag_idx)
+                .ok_or(CompressError::OutputTooSmall)? = 0;
+            out += 1;
+            for bit in 0..8 {
+                if pos >= input.len() {
+                    break;
+                }
+                let (dist, len) = Self::longest_match(input, pos);
+                if len >= Self::MIN_MATCH {
+                    let token = (((len - Self::MIN_MATCH) as u16) << 12) | ((dist - 1) as u16);
+                    output
+                        .get_mut(out..out + 2)
+                        .ok_or(CompressError::OutputTooSmall)?
+                        .copy_from_slice(&token.to_le_bytes());
+                    out += 2;
+                    pos += len;
+                } else {
+                    *output.get_mut(out).ok_or(CompressError::OutputTooSmall)? = input[pos];
+                    output[flag_idx] |= 1 << bit;
+                    out += 1;
+                    pos += 1;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, CompressError> {
+        let mut pos = 0;
+        let mut out = 0;
+        while let Some(flags) = input.get(pos) {
+            pos += 1;
+            for bit in 0..8 {
+                if pos >= input.len() {
+                    break;
+                }
+                if flags & (1 << bit) != 0 {
+                    *output.get_mut(out).ok_or(CompressError::OutputTooSmall)? = input[pos];
+                    pos += 1;
+                    out += 1;
+                    continue;
+                }
+                let token = input.get(pos..pos + 2).ok_or(CompressError::Malformed)?;
+                let token = u16::from_le_bytes([token[0], token[1]]);
+                pos += 2;
+                let dist = usize::from(token & 0x0FFF) + 1;
+                let len = usize::from(token >> 12) + Self::MIN_MATCH;
+                let start = out.checked_sub(dist).ok_or(CompressError::Malformed)?;
+                if out + len > output.len() {
+                    return Err(CompressError::OutputTooSmall);
+                }
+                for i in 0..len {
+                    output[out + i] = output[start + i];
+                }
+                out += len;
+            }
+        }
+        Ok(out)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// FRAMES
+//////////////////////////////////////////////////////////////////////////////
+
+/// Is the body of `frame` compressed?
+pub fn is_compressed(frame: &[u8]) -> bool {
+    frame
+        .first()
+        .is_some_and(|disc| disc & VarHeader::COMPRESSED_BITS != 0)
+}
+
+/// The length of the header of `frame`, if it has a well-formed header
+fn header_len(frame: &[u8]) -> Option<usize> {
+    let (_hdr, _ver, body) = VarHeader::take_versioned_from_slice(frame)?;
+    Some(frame.len() - body.len())
+}
+
+/// Compress the body of `frame` into `out`, if the body is at least `threshold`
+/// bytes long
+///
+/// Returns the length of the compressed frame, or `None` if the frame should be
+/// sent unchanged, because it is too small, the compressed frame would not be any
+/// smaller, or it does not fit in `out`.
+pub fn compress_frame<C: Compressor + ?Sized>(
+    compressor: &C,
+    frame: &[u8],
+    threshold: usize,
+    out: &mut [u8],
+) -> Option<usize> {
+    let hdr_len = header_len(frame)?;
+    let (hdr, body) = frame.split_at(hdr_len);
+    if body.len() < threshold || is_compressed(frame) {
+        return None;
+    }
+    let (out_hdr, out_body) = out.split_at_mut_checked(hdr_len)?;
+    // A compressed body that isn't smaller is not worth it
+    let limit = out_body.len().min(body.len().saturating_sub(1));
+    let body_len = compressor.compress(body, &mut out_body[..limit]).ok()?;
+    out_hdr.copy_from_slice(hdr);
+    out_hdr[0] |= VarHeader::COMPRESSED_BITS;
+    Some(hdr_len + body_len)
+}
+
+/// Decompress the body of a compressed `frame` into `out`, clearing the
+/// compressed flag of its header
+///
+/// Returns the length of the decompressed frame.
+pub fn decompress_frame<C: Compressor + ?Sized>(
+    compressor: &C,
+    frame: &[u8],
+    out: &mut [u8],
+) -> Result<usize, CompressError> {
+    let hdr_len = header_len(frame).ok_or(CompressError::Malformed)?;
+    let (hdr, body) = frame.split_at(hdr_len);
+    let (out_hdr, out_body) = out
+        .split_at_mut_checked(hdr_len)
+        .ok_or(CompressError::OutputTooSmall)?;
+    let body_len = compressor.decompress(body, out_body)?;
+    out_hdr.copy_from_slice(hdr);
+    out_hdr[0] &= !VarHeader::COMPRESSED_BITS;
+    Ok(hdr_len + body_len)
+}
+
+/// The compression settings of a client
+///
+/// See [`HostClient::set_compression()`].
+///
+/// [`HostClient::set_compression()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/host_client/struct.HostClient.html#method.set_compression
+#[cfg(feature = "use-std")]
+#[derive(Clone)]
+pub struct Compression {
+    /// The compression algorithm, used in both directions
+    pub compressor: std::sync::Arc<dyn Compressor + Send + Sync>,
+    /// Compress outgoing frames with bodies of at least this many bytes, or never
+    /// compress outgoing frames if `None`. Incoming frames are always decompressed.
+    pub threshold: Option<usize>,
+    /// The size of the largest decompressed incoming frame
+    pub max_frame_len: usize,
+}
+
+#[cfg(feature = "use-std")]
+impl Compression {
+    /// Compress outgoing frames with bodies of at least `threshold` bytes with
+    /// `compressor`, and accept decompressed frames of up to 64KiB
+    pub fn new<C: Compressor + Send + Sync + 'static>(compressor: C, threshold: usize) -> Self {
+        Self {
+            compressor: std::sync::Arc::new(compressor),
+            threshold: Some(threshold),
+            max_frame_len: 64 * 1024,
+        }
+    }
+
+    /// Compress an outgoing frame, if enabled and worth it
+    pub(crate) fn compress(&self, frame: Vec<u8>) -> Vec<u8> {
+        let Some(threshold) = self.threshold else {
+            return frame;
+        };
+        let mut out = vec![0u8; frame.len()];
+        match compress_frame(&*self.compressor, &frame, threshold, &mut out) {
+            Some(len) => {
+                out.truncate(len);
+                out
+            }
+            None => frame,
+        }
+    }
+
+    /// Decompress an incoming frame, if it is compressed
+    ///
+    /// Returns `None` if the frame could not be decompressed.
+    pub(crate) fn decompress(&self, frame: Vec<u8>) -> Option<Vec<u8>> {
+        if !is_compressed(&frame) {
+            return Some(frame);
+        }
+        let mut out = vec![0u8; self.max_frame_len];
+        let len = decompress_frame(&*self.compressor, &frame, &mut out).ok()?;
+        out.truncate(len);
+        Some(out)
+    }
+}
+
+#[cfg(feature = "use-std")]
+impl core::fmt::Debug for Compression {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Compression")
+            .field("threshold", &self.threshold)
+            .field("max_frame_len", &self.max_frame_len)
+            .finish_non_exhaustive()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl that compresses the body of large frames
+///
+/// Frames are serialized and compressed in two buffers of `N` bytes on the stack,
+/// which limits the size of frames that can be sent. Larger frames fail to send
+/// with [`CompressWireTxError::MessageTooLarge`]. Log messages are never compressed.
+pub struct CompressWireTx<Tx, C, const N: usize> {
+    tx: Tx,
+    compressor: C,
+    threshold: usize,
+}
+
+impl<Tx, C, const N: usize> CompressWireTx<Tx, C, N> {
+    /// Wrap `tx`, compressing bodies of at least `threshold` bytes with `compressor`
+    pub fn new(tx: Tx, compressor: C, threshold: usize) -> Self {
+        Self {
+            tx,
+            compressor,
+            threshold,
+        }
+    }
+}
+
+impl<Tx: Clone, C: Clone, const N: usize> Clone for CompressWireTx<Tx, C, N> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            compressor: self.compressor.clone(),
+            threshold: self.threshold,
+        }
+    }
+}
+
+impl<Tx: WireTx, C: Compressor, const N: usize> WireTx for CompressWireTx<Tx, C, N> {
+    type Error = CompressWireTxError<Tx::Error>;
+
+    async fn wait_connection(&self) {
+        self.tx.wait_connection().await;
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        let (hdr_used, remain) = hdr
+            .write_to_slice(&mut buf)
+            .ok_or(CompressWireTxError::MessageTooLarge)?;
+        let hdr_len = hdr_used.len();
+        let body_len = postcard::to_slice(msg, remain)
+            .map_err(|_| CompressWireTxError::MessageTooLarge)?
+            .len();
+        self.send_raw(&buf[..hdr_len + body_len]).await
+    }
+
+    async fn send_raw(&self, frame: &[u8]) -> Result<(), Self::Error> {
+        let mut out = [0u8; N];
+        let frame = match compress_frame(&self.compressor, frame, self.threshold, &mut out) {
+            Some(len) => &out[..len],
+            None => frame,
+        };
+        self.tx
+            .send_raw(frame)
+            .await
+            .map_err(CompressWireTxError::Inner)
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        self.tx
+            .send_log_str(kkind, s)
+            .await
+            .map_err(CompressWireTxError::Inner)
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        self.tx
+            .send_log_fmt(kkind, a)
+            .await
+            .map_err(CompressWireTxError::Inner)
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.flush().await.map_err(CompressWireTxError::Inner)
+    }
+}
+
+/// The error type of [`CompressWireTx`]
+#[derive(Debug)]
+pub enum CompressWireTxError<E> {
+    /// The wrapped [`WireTx`] impl returned an error
+    Inner(E),
+    /// The frame did not fit in the buffer
+    MessageTooLarge,
+}
+
+impl<E: AsWireTxErrorKind> AsWireTxErrorKind for CompressWireTxError<E> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            CompressWireTxError::Inner(e) => e.as_kind(),
+            CompressWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl that decompresses compressed frames
+///
+/// Frames are received into a buffer of `N` bytes on the stack, and then copied or
+/// decompressed into the receive buffer, which must have room for the decompressed
+/// frame.
+pub struct CompressWireRx<Rx, C, const N: usize> {
+    rx: Rx,
+    compressor: C,
+}
+
+impl<Rx, C, const N: usize> CompressWireRx<Rx, C, N> {
+    /// Wrap `rx`, decompressing frames with `compressor`
+    pub fn new(rx: Rx, compressor: C) -> Self {
+        Self { rx, compressor }
+    }
+}
+
+impl<Rx: WireRx, C: Compressor, const N: usize> WireRx for CompressWireRx<Rx, C, N> {
+    type Error = CompressWireRxError<Rx::Error>;
+
+    async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await;
+    }
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let mut scratch = [0u8; N];
+        let frame = self
+            .rx
+            .receive(&mut scratch)
+            .await
+            .map_err(CompressWireRxError::Inner)?;
+        if !is_compressed(frame) {
+            let out = buf
+                .get_mut(..frame.len())
+                .ok_or(CompressWireRxError::MessageTooLarge)?;
+            out.copy_from_slice(frame);
+            return Ok(out);
+        }
+        match decompress_frame(&self.compressor, frame, buf) {
+            Ok(len) => Ok(&mut buf[..len]),
+            Err(CompressError::OutputTooSmall) => Err(CompressWireRxError::MessageTooLarge),
+            Err(CompressError::Malformed) => Err(CompressWireRxError::Malformed),
+        }
+    }
+}
+
+/// The error type of [`CompressWireRx`]
+#[derive(Debug)]
+pub enum CompressWireRxError<E> {
+    /// The wrapped [`WireRx`] impl returned an error
+    Inner(E),
+    /// The received or decompressed frame did not fit in its buffer
+    MessageTooLarge,
+    /// The compressed body was invalid
+    Malformed,
+}
+
+impl<E: AsWireRxErrorKind> AsWireRxErrorKind for CompressWireRxError<E> {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            CompressWireRxError::Inner(e) => e.as_kind(),
+            CompressWireRxError::MessageTooLarge => WireRxErrorKind::ReceivedMessageTooLarge,
+            CompressWireRxError::Malformed => WireRxErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CompressError, Compressor, Lzss};
+
+    #[test]
+    fn lzss_round_trip() {
+        let input: Vec<u8> = b"config.value = 1234; "
+            .iter()
+            .cycle()
+            .take(1000)
+            .copied()
+            .collect();
+        let mut compressed = [0u8; 1024];
+        let len = Lzss.compress(&input, &mut compressed).unwrap();
+        assert!(len < input.len() / 5);
+
+        let mut out = [0u8; 1024];
+        let out_len = Lzss.decompress(&compressed[..len], &mut out).unwrap();
+        assert_eq!(out[..out_len], input[..]);
+
+        // Incompressible data still round-trips, and output must fit
+        let noise: Vec<u8> = (0..=255u8).collect();
+        let len = Lzss.compress(&noise, &mut compressed).unwrap();
+        let out_len = Lzss.decompress(&compressed[..len], &mut out).unwrap();
+        assert_eq!(out[..out_len], noise[..]);
+        assert_eq!(
+            Lzss.compress(&noise, &mut compressed[..100]),
+            Err(CompressError::OutputTooSmall)
+        );
+
+        // Back-references before the start are rejected
+        assert_eq!(
+            Lzss.decompress(&[0x00, 0x05, 0x00], &mut out),
+            Err(CompressError::Malformed)
+        );
+    }
+}
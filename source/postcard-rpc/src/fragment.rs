@@ -0,0 +1,510 @@
+//! Fragmentation of large frames
+//!
+//! Some transports can only carry frames up to a fixed size, e.g. because of the
+//! size of their packet buffers. These tools split frames larger than a given
+//! size into multiple fragments on the sending side, and reassemble them on the
+//! receiving side before the frame is decoded, so that large requests and
+//! responses can still be sent. Smaller frames are sent unchanged.
+//!
+//! Fragments are sent on the [`FRAGMENT_KEY`], always with a full 8-byte key, and
+//! with a sequence number identifying the fragmented frame. Their body contains the
+//! [`FragmentInfo`], followed by the next part of the fragmented frame.
+//!
+//! On the client, fragmentation is enabled with [`HostClient::set_fragmentation()`].
+//! Incoming fragments are always reassembled.
+//!
+//! On the server, the [`WireTx`] and [`WireRx`] impls are wrapped with [`FragWireTx`]
+//! and [`FragWireRx`]. Requests with lost fragments are answered with
+//! [`WireError::ReassemblyFailed`]:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::fragment::{FragWireRx, FragWireTx};
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 1024 bytes are sent, in fragments of up to 64 bytes
+//!     tx_impl: FragWireTx<WireTxImpl, 1024, 64>;
+//!     // ...
+//! }
+//!
+//! let server = Server::new(
+//!     FragWireTx::new(tx),
+//!     FragWireRx::new(rx),
+//!     // Requests are reassembled in the receive buffer
+//!     buf_1024,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+//!
+//! Fragments of a frame are expected in order, as all transports of this crate
+//! deliver them. A missing fragment fails the reassembly of the frame, instead of
+//! waiting for it to arrive later.
+//!
+//! **Requires feature**: `fragment`
+//!
+//! [`HostClient::set_fragmentation()`]: https://docs.rs/postcard-rpc/latest/postcard_rpc/host_client/struct.HostClient.html#method.set_fragmentation
+//! [`WireError::ReassemblyFailed`]: crate::standard_icd::WireError::ReassemblyFailed
+
+use core::fmt::Arguments;
+
+use portable_atomic::{AtomicU16, Ordering};
+use postcard_schema::Schema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{
+        frame::serialize_frame, AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind,
+        WireTx, WireTxErrorKind,
+    },
+    Key,
+};
+
+/// The path of fragments
+pub const FRAGMENT_PATH: &str = "postcard-rpc/fragment";
+
+/// The key of fragments
+pub const FRAGMENT_KEY: Key = Key::for_path::<FragmentInfo>(FRAGMENT_PATH);
+
+/// The position of a fragment within the fragmented frame
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FragmentInfo {
+    /// The index of this fragment, starting at zero
+    pub index: u16,
+    /// The number of fragments of the frame
+    pub total: u16,
+}
+
+/// The largest number of bytes a fragment adds to the part of the frame it carries
+///
+/// This is the header with a full key and 2-byte sequence number, and the
+/// [`FragmentInfo`] with two 3-byte varints.
+pub const FRAGMENT_OVERHEAD: usize = 1 + 8 + 2 + 3 + 3;
+
+/// The smallest supported size of fragments
+///
+/// This makes sure the first fragment contains the whole header of the fragmented
+/// frame, so that failures can be reported.
+pub const MIN_FRAGMENT_LEN: usize = FRAGMENT_OVERHEAD + 16;
+
+/// The number of bytes of the fragmented frame carried by each fragment of up
+/// to `max_len` bytes
+const fn chunk_len(max_len: usize) -> usize {
+    max_len - FRAGMENT_OVERHEAD
+}
+
+/// Write a fragment to `out`, returning the used part
+pub(crate) fn write_fragment<'a>(
+    out: &'a mut [u8],
+    id: u16,
+    info: FragmentInfo,
+    data: &[u8],
+) -> Option<&'a [u8]> {
+    let hdr = VarHeader {
+        key: VarKey::Key8(FRAGMENT_KEY),
+        seq_no: VarSeq::Seq2(id),
+    };
+    let (hdr_used, remain) = hdr.write_to_slice(out)?;
+    let hdr_len = hdr_used.len();
+    let info_len = postcard::to_slice(&info, remain).ok()?.len();
+    let start = hdr_len + info_len;
+    out.get_mut(start..start + data.len())?
+        .copy_from_slice(data);
+    out.get(..start + data.len())
+}
+
+/// Decode a fragment, returning its id, info, and the part of the frame it carries
+///
+/// Returns `None` if `frame` is not a fragment.
+pub fn take_fragment(frame: &[u8]) -> Option<(u16, FragmentInfo, &[u8])> {
+    let (hdr, body) = VarHeader::take_from_slice(frame)?;
+    let (VarKey::Key8(key), VarSeq::Seq2(id)) = (hdr.key, hdr.seq_no) else {
+        return None;
+    };
+    if key != FRAGMENT_KEY {
+        return None;
+    }
+    let (info, data) = postcard::take_from_bytes::<FragmentInfo>(body).ok()?;
+    Some((id, info, data))
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl that splits frames larger than `F` bytes into fragments
+///
+/// Frames are serialized into a buffer of `N` bytes on the stack, which limits
+/// the size of frames that can be sent. Larger frames fail to send with
+/// [`FragWireTxError::MessageTooLarge`]. Each fragment is built in a buffer of
+/// `F` bytes on the stack, which must be at least [`MIN_FRAGMENT_LEN`].
+///
+/// Log messages are passed to the wrapped [`WireTx`] impl unchanged.
+#[derive(Clone)]
+pub struct FragWireTx<Tx, const N: usize, const F: usize> {
+    tx: Tx,
+}
+
+/// The id of the next fragmented frame
+///
+/// This is shared by all [`FragWireTx`] impls, so that the clones used by spawned
+/// handlers don't reuse ids.
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+impl<Tx, const N: usize, const F: usize> FragWireTx<Tx, N, F> {
+    /// Wrap `tx`, splitting frames larger than `F` bytes
+    pub fn new(tx: Tx) -> Self {
+        const {
+            assert!(
+                F >= MIN_FRAGMENT_LEN,
+                "Fragments must be at least MIN_FRAGMENT_LEN bytes"
+            );
+        }
+        Self { tx }
+    }
+}
+
+impl<Tx: WireTx, const N: usize, const F: usize> FragWireTx<Tx, N, F> {
+    /// Send `frame`, in fragments if necessary
+    async fn send_frame(&self, frame: &[u8]) -> Result<(), FragWireTxError<Tx::Error>> {
+        if frame.len() <= F {
+            return self
+                .tx
+                .send_raw(frame)
+                .await
+                .map_err(FragWireTxError::Inner);
+        }
+
+        let chunks = frame.chunks(chunk_len(F));
+        let total = u16::try_from(chunks.len()).map_err(|_| FragWireTxError::MessageTooLarge)?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        for (index, chunk) in (0..total).zip(chunks) {
+            let mut out = [0u8; F];
+            let info = FragmentInfo { index, total };
+            let used = write_fragment(&mut out, id, info, chunk)
+                .ok_or(FragWireTxError::MessageTooLarge)?;
+            self.tx
+                .send_raw(used)
+                .await
+                .map_err(FragWireTxError::Inner)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Tx: WireTx, const N: usize, const F: usize> WireTx for FragWireTx<Tx, N, F> {
+    type Error = FragWireTxError<Tx::Error>;
+
+    async fn wait_connection(&self) {
+        self.tx.wait_connection().await;
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        let frame =
+            serialize_frame(&mut buf, hdr, msg).map_err(|_| FragWireTxError::MessageTooLarge)?;
+        self.send_frame(frame).await
+    }
+
+    async fn send_raw(&self, frame: &[u8]) -> Result<(), Self::Error> {
+        self.send_frame(frame).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        self.tx
+            .send_log_str(kkind, s)
+            .await
+            .map_err(FragWireTxError::Inner)
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        self.tx
+            .send_log_fmt(kkind, a)
+            .await
+            .map_err(FragWireTxError::Inner)
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.flush().await.map_err(FragWireTxError::Inner)
+    }
+}
+
+/// The error type of [`FragWireTx`]
+#[derive(Debug)]
+pub enum FragWireTxError<E> {
+    /// The wrapped [`WireTx`] impl returned an error
+    Inner(E),
+    /// The frame did not fit in the buffer
+    MessageTooLarge,
+}
+
+impl<E: AsWireTxErrorKind> AsWireTxErrorKind for FragWireTxError<E> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            FragWireTxError::Inner(e) => e.as_kind(),
+            FragWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl that reassembles fragmented frames
+///
+/// Frames are reassembled in the receive buffer, which limits the size of frames
+/// that can be received. Other frames are passed through unchanged.
+pub struct FragWireRx<Rx> {
+    rx: Rx,
+    /// The header of a frame whose reassembly failed, reported by the next receive
+    failed: Option<VarHeader>,
+}
+
+impl<Rx> FragWireRx<Rx> {
+    /// Wrap `rx`, reassembling fragmented frames
+    pub fn new(rx: Rx) -> Self {
+        Self { rx, failed: None }
+    }
+}
+
+/// A frame being reassembled
+#[derive(Clone, Copy)]
+struct Partial {
+    id: u16,
+    next: u16,
+    total: u16,
+}
+
+impl<Rx: WireRx> WireRx for FragWireRx<Rx> {
+    type Error = FragWireRxError<Rx::Error>;
+
+    async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await;
+    }
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        if let Some(hdr) = self.failed.take() {
+            return Err(FragWireRxError::ReassemblyFailed(Some(hdr)));
+        }
+
+        let mut partial: Option<Partial> = None;
+        // The number of bytes of the partial frame at the start of `buf`
+        let mut filled = 0;
+        loop {
+            // Receive the next frame after the partial frame
+            let start = filled;
+            let len = match self.rx.receive(&mut buf[start..]).await {
+                Ok(frame) => frame.len(),
+                Err(e) => {
+                    let too_large = matches!(
+                        e.as_kind(),
+                        WireRxErrorKind::ReceivedMessageTooLarge
+                            | WireRxErrorKind::ReceivedBodyTooLarge(_)
+                    );
+                    return Err(match partial_header(&buf[..filled], partial) {
+                        Some(hdr) if too_large => FragWireRxError::BodyTooLarge(hdr),
+                        _ => FragWireRxError::Inner(e),
+                    });
+                }
+            };
+            let frame = &buf[start..start + len];
+
+            let Some((id, info, data)) = take_fragment(frame) else {
+                // A frame that isn't fragmented. If it interrupted a fragmented frame,
+                // report the failure after handling this frame
+                if let Some(hdr) = partial_header(&buf[..filled], partial) {
+                    self.failed = Some(hdr);
+                }
+                buf.copy_within(start..start + len, 0);
+                return Ok(&mut buf[..len]);
+            };
+            let data_len = data.len();
+            let data_start = start + len - data_len;
+
+            let mut current = match partial {
+                Some(p) if p.id == id && p.next == info.index && p.total == info.total => p,
+                _ => {
+                    // A fragment of another frame, so the partial frame is lost
+                    if let Some(hdr) = partial_header(&buf[..filled], partial) {
+                        self.failed = Some(hdr);
+                    }
+                    partial = None;
+                    filled = 0;
+                    if info.index != 0 || info.total == 0 {
+                        // Not the first fragment, we can't use it
+                        continue;
+                    }
+                    Partial {
+                        id,
+                        next: 0,
+                        total: info.total,
+                    }
+                }
+            };
+
+            buf.copy_within(data_start..data_start + data_len, filled);
+            filled += data_len;
+            current.next += 1;
+            if current.next == current.total {
+                return Ok(&mut buf[..filled]);
+            }
+            partial = Some(current);
+        }
+    }
+}
+
+/// The header of the partial frame in `buf`, if there is one
+fn partial_header(buf: &[u8], partial: Option<Partial>) -> Option<VarHeader> {
+    partial?;
+    VarHeader::take_from_slice(buf).map(|(hdr, _)| hdr)
+}
+
+/// The error type of [`FragWireRx`]
+#[derive(Debug)]
+pub enum FragWireRxError<E> {
+    /// The wrapped [`WireRx`] impl returned an error
+    Inner(E),
+    /// Fragments of the frame with the given header, if it could be read, were lost
+    ReassemblyFailed(Option<VarHeader>),
+    /// The fragmented frame with the given header did not fit in the receive buffer
+    BodyTooLarge(VarHeader),
+}
+
+impl<E: AsWireRxErrorKind> AsWireRxErrorKind for FragWireRxError<E> {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            FragWireRxError::Inner(e) => e.as_kind(),
+            FragWireRxError::ReassemblyFailed(Some(hdr)) => WireRxErrorKind::ReassemblyFailed(*hdr),
+            FragWireRxError::ReassemblyFailed(None) => WireRxErrorKind::Other,
+            FragWireRxError::BodyTooLarge(hdr) => WireRxErrorKind::ReceivedBodyTooLarge(*hdr),
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// HOST
+//////////////////////////////////////////////////////////////////////////////
+
+/// Split `frame` into fragments of up to `max_len` bytes, if it is larger
+#[cfg(feature = "use-std")]
+pub(crate) fn split(frame: Vec<u8>, max_len: usize, id: u16) -> Vec<Vec<u8>> {
+    if frame.len() <= max_len {
+        return vec![frame];
+    }
+    let chunks = frame.chunks(chunk_len(max_len));
+    let total = u16::try_from(chunks.len()).unwrap_or(u16::MAX);
+    (0..total)
+        .zip(chunks)
+        .map(|(index, chunk)| {
+            let mut out = vec![0u8; FRAGMENT_OVERHEAD + chunk.len()];
+            let info = FragmentInfo { index, total };
+            let len = write_fragment(&mut out, id, info, chunk)
+                .map(<[u8]>::len)
+                .unwrap_or_default();
+            out.truncate(len);
+            out
+        })
+        .collect()
+}
+
+/// Reassembles fragmented frames received by the host
+///
+/// Unlike the server, frames are reassembled separately per id, as fragments of
+/// frames sent by different tasks on the server may be interleaved.
+#[cfg(feature = "use-std")]
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    partials: Vec<(Partial, Vec<u8>)>,
+}
+
+#[cfg(feature = "use-std")]
+impl Reassembler {
+    /// The number of frames reassembled at the same time, older frames are dropped
+    const MAX_PARTIALS: usize = 16;
+
+    /// Handle a received frame
+    ///
+    /// Returns the frame if it was not a fragment, or the reassembled frame if it
+    /// was the last fragment. Returns `Err` with the id of a frame whose fragments
+    /// were lost.
+    pub(crate) fn push(&mut self, frame: Vec<u8>) -> Result<Option<Vec<u8>>, u16> {
+        let Some((id, info, data)) = take_fragment(&frame) else {
+            return Ok(Some(frame));
+        };
+        let pos = self.partials.iter().position(|(p, _)| p.id == id);
+
+        if info.index == 0 {
+            // A new frame, replacing any earlier frame with the same id
+            if let Some(pos) = pos {
+                self.partials.remove(pos);
+            }
+            if self.partials.len() >= Self::MAX_PARTIALS {
+                self.partials.remove(0);
+            }
+            let partial = Partial {
+                id,
+                next: 0,
+                total: info.total,
+            };
+            self.partials.push((partial, Vec::new()));
+        }
+
+        let Some(pos) = self.partials.iter().position(|(p, _)| p.id == id) else {
+            return Err(id);
+        };
+        let (partial, buf) = &mut self.partials[pos];
+        if partial.next != info.index || partial.total != info.total {
+            self.partials.remove(pos);
+            return Err(id);
+        }
+        buf.extend_from_slice(data);
+        partial.next += 1;
+        if partial.next == partial.total {
+            let (_, buf) = self.partials.remove(pos);
+            return Ok(Some(buf));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(all(test, feature = "use-std"))]
+mod test {
+    use super::{split, take_fragment, Reassembler, MIN_FRAGMENT_LEN};
+
+    #[test]
+    fn split_reassemble() {
+        let frame: Vec<u8> = (0..200).collect();
+        let frags = split(frame.clone(), MIN_FRAGMENT_LEN, 7);
+        assert!(frags.len() > 1);
+        assert!(frags.iter().all(|f| f.len() <= MIN_FRAGMENT_LEN));
+        assert!(frags.iter().all(|f| take_fragment(f).unwrap().0 == 7));
+
+        let mut re = Reassembler::default();
+        let (last, rest) = frags.split_last().unwrap();
+        for f in rest {
+            assert_eq!(re.push(f.clone()), Ok(None));
+        }
+        assert_eq!(re.push(last.clone()), Ok(Some(frame.clone())));
+
+        // Small frames are not fragmented
+        assert_eq!(split(vec![1, 2, 3], MIN_FRAGMENT_LEN, 8), [vec![1, 2, 3]]);
+        assert_eq!(re.push(vec![1, 2, 3]), Ok(Some(vec![1, 2, 3])));
+
+        // Lost fragments are reported
+        assert_eq!(re.push(frags[0].clone()), Ok(None));
+        assert_eq!(re.push(frags[2].clone()), Err(7));
+        assert_eq!(re.push(frags[1].clone()), Err(7));
+    }
+}
@@ -0,0 +1,579 @@
+//! Selectable framing strategies
+//!
+//! Transports that carry a stream of bytes, e.g. a UART or a socket, need a way
+//! to find the boundaries of frames in the stream. Transports that carry whole
+//! packets, e.g. a USB bulk endpoint or a UDP socket, do not. The [`Framing`]
+//! trait abstracts over this choice, so the same byte transport can be used with:
+//!
+//! * [`CobsFraming`]: each frame is COBS encoded, and terminated with a zero byte.
+//!   This is the framing of the `cobs-serial` client and the `embedded-io-async`
+//!   server, and resynchronizes on the next zero byte after line errors.
+//! * [`LengthPrefixFraming`]: each frame is prefixed with its length, as a 4-byte
+//!   little-endian integer. This is the framing of the TCP client and server, and
+//!   has a fixed overhead, but cannot resynchronize after lost bytes.
+//! * [`NoFraming`]: each chunk of bytes of the transport is one frame.
+//!
+//! On the server, the [`WireTx`] and [`WireRx`] impls of the byte transport are
+//! wrapped with [`FramedWireTx`] and [`FramedWireRx`]. The inner [`WireTx`] is
+//! only used with [`WireTx::send_raw()`], to send encoded frames, and each chunk
+//! received by the inner [`WireRx`] is fed to a [`FrameAccumulator`]:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::framing::{CobsFraming, FramedWireRx, FramedWireTx};
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 256 bytes, before encoding, may be sent
+//!     tx_impl: FramedWireTx<UartTx, CobsFraming, 256>;
+//!     // ...
+//! }
+//!
+//! let server = Server::new(
+//!     FramedWireTx::new(uart_tx, CobsFraming),
+//!     // Reads of up to 64 bytes, and frames of up to 256 bytes
+//!     FramedWireRx::<_, _, 64, 256>::new(uart_rx, CobsFraming),
+//!     buf,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+//!
+//! On the client, `HostClient::new_with_framing()` takes the framing, along with
+//! the byte transport.
+//!
+//! **Requires feature**: `framing`
+
+use core::fmt::Arguments;
+
+use portable_atomic::{AtomicU32, Ordering};
+use serde::Serialize;
+
+use crate::{
+    accumulator::raw::{feed_slice, FeedResult},
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{
+        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+/// The length of the prefix of [`LengthPrefixFraming`]
+pub const LEN_PREFIX_LEN: usize = 4;
+
+/// A strategy for finding the boundaries of frames in a stream of bytes
+pub trait Framing {
+    /// The largest encoding of a frame of `len` bytes
+    fn max_encoded_len(&self, len: usize) -> usize;
+
+    /// Encode `frame` into `out`
+    ///
+    /// Returns the length of the encoded frame, or `None` if it did not fit in `out`.
+    fn encode(&self, frame: &[u8], out: &mut [u8]) -> Option<usize>;
+
+    /// Accumulate `input` into `buf`, of which the first `*filled` bytes hold
+    /// the state of a partially received frame
+    ///
+    /// At most one frame is decoded per call, and returned in `buf`. Any input
+    /// after it is returned as `remaining`, and should be fed again.
+    fn decode<'a, 'b>(
+        &mut self,
+        buf: &'b mut [u8],
+        filled: &mut usize,
+        input: &'a [u8],
+    ) -> FeedResult<'a, 'b>;
+
+    /// Discard the state of any partially received frame
+    fn reset(&mut self) {}
+}
+
+/// Decodes a stream of bytes into frames of up to `N` bytes, with a [`Framing`]
+pub struct FrameAccumulator<F, const N: usize> {
+    framing: F,
+    buf: [u8; N],
+    filled: usize,
+}
+
+/// Frames are COBS encoded, and terminated with a zero byte
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CobsFraming;
+
+/// Frames are prefixed with their length, as a 4-byte little-endian integer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LengthPrefixFraming {
+    prefix: [u8; LEN_PREFIX_LEN],
+    /// Bytes left to discard of a frame that did not fit
+    skip: usize,
+}
+
+/// Each chunk of bytes is one frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoFraming;
+
+// ----- IMPLS -----
+
+// impl FrameAccumulator
+
+impl<F: Framing, const N: usize> FrameAccumulator<F, N> {
+    /// Create a new, empty accumulator
+    pub const fn new(framing: F) -> Self {
+        Self {
+            framing,
+            buf: [0; N],
+            filled: 0,
+        }
+    }
+
+    /// Appends data to the internal buffer and attempts to decode the accumulated data
+    ///
+    /// At most one frame is decoded per call. If a frame is found, any input after
+    /// it is returned as `remaining`, and should be fed again.
+    pub fn feed<'a, 'b>(&'b mut self, input: &'a [u8]) -> FeedResult<'a, 'b> {
+        self.framing.decode(&mut self.buf, &mut self.filled, input)
+    }
+
+    /// Discard any partially accumulated frame
+    pub fn reset(&mut self) {
+        self.filled = 0;
+        self.framing.reset();
+    }
+}
+
+// impl CobsFraming
+
+impl Framing for CobsFraming {
+    fn max_encoded_len(&self, len: usize) -> usize {
+        cobs::max_encoding_length(len) + 1
+    }
+
+    fn encode(&self, frame: &[u8], out: &mut [u8]) -> Option<usize> {
+        let used = cobs::try_encode(frame, out).ok()?;
+        *out.get_mut(used)? = 0;
+        Some(used + 1)
+    }
+
+    fn decode<'a, 'b>(
+        &mut self,
+        buf: &'b mut [u8],
+        filled: &mut usize,
+        input: &'a [u8],
+    ) -> FeedResult<'a, 'b> {
+        feed_slice(buf, filled, input)
+    }
+}
+
+// impl LengthPrefixFraming
+
+impl LengthPrefixFraming {
+    /// Create a new length prefix framing
+    pub const fn new() -> Self {
+        Self {
+            prefix: [0; LEN_PREFIX_LEN],
+            skip: 0,
+        }
+    }
+}
+
+impl Framing for LengthPrefixFraming {
+    fn max_encoded_len(&self, len: usize) -> usize {
+        len + LEN_PREFIX_LEN
+    }
+
+    fn encode(&self, frame: &[u8], out: &mut [u8]) -> Option<usize> {
+        let len = u32::try_from(frame.len()).ok()?;
+        let out = out.get_mut(..frame.len() + LEN_PREFIX_LEN)?;
+        let (prefix, body) = out.split_at_mut(LEN_PREFIX_LEN);
+        prefix.copy_from_slice(&len.to_le_bytes());
+        body.copy_from_slice(frame);
+        Some(out.len())
+    }
+
+    fn decode<'a, 'b>(
+        &mut self,
+        buf: &'b mut [u8],
+        filled: &mut usize,
+        mut input: &'a [u8],
+    ) -> FeedResult<'a, 'b> {
+        // Discard the rest of a frame that did not fit
+        if self.skip != 0 {
+            let n = self.skip.min(input.len());
+            self.skip -= n;
+            input = &input[n..];
+        }
+        if input.is_empty() {
+            return FeedResult::Consumed;
+        }
+
+        // The prefix is kept separately, `filled` counts it along with the body
+        if *filled < LEN_PREFIX_LEN {
+            let n = (LEN_PREFIX_LEN - *filled).min(input.len());
+            self.prefix[*filled..][..n].copy_from_slice(&input[..n]);
+            *filled += n;
+            input = &input[n..];
+            if *filled < LEN_PREFIX_LEN {
+                return FeedResult::Consumed;
+            }
+        }
+
+        let len = u32::from_le_bytes(self.prefix) as usize;
+        if len > buf.len() {
+            *filled = 0;
+            let n = len.min(input.len());
+            self.skip = len - n;
+            return FeedResult::OverFull(&input[n..]);
+        }
+
+        let have = *filled - LEN_PREFIX_LEN;
+        let n = (len - have).min(input.len());
+        buf[have..][..n].copy_from_slice(&input[..n]);
+        *filled += n;
+        if have + n < len {
+            return FeedResult::Consumed;
+        }
+        *filled = 0;
+        FeedResult::Success {
+            data: &buf[..len],
+            remaining: &input[n..],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.skip = 0;
+    }
+}
+
+// impl NoFraming
+
+impl Framing for NoFraming {
+    fn max_encoded_len(&self, len: usize) -> usize {
+        len
+    }
+
+    fn encode(&self, frame: &[u8], out: &mut [u8]) -> Option<usize> {
+        out.get_mut(..frame.len())?.copy_from_slice(frame);
+        Some(frame.len())
+    }
+
+    fn decode<'a, 'b>(
+        &mut self,
+        buf: &'b mut [u8],
+        _filled: &mut usize,
+        input: &'a [u8],
+    ) -> FeedResult<'a, 'b> {
+        if input.is_empty() {
+            return FeedResult::Consumed;
+        }
+        let Some(out) = buf.get_mut(..input.len()) else {
+            return FeedResult::OverFull(&[]);
+        };
+        out.copy_from_slice(input);
+        FeedResult::Success {
+            data: out,
+            remaining: &[],
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl that encodes each frame with a [`Framing`]
+///
+/// Frames are serialized into a buffer of `N` bytes on the stack, and encoded
+/// into a second one, which limits the size of frames that can be sent, including
+/// the encoding overhead. Larger frames fail to send with
+/// [`FramedWireTxError::MessageTooLarge`].
+pub struct FramedWireTx<Tx, F, const N: usize> {
+    tx: Tx,
+    framing: F,
+    log_seq: AtomicU32,
+}
+
+impl<Tx, F, const N: usize> FramedWireTx<Tx, F, N> {
+    /// Wrap `tx`, encoding each frame with `framing`
+    pub fn new(tx: Tx, framing: F) -> Self {
+        Self {
+            tx,
+            framing,
+            log_seq: AtomicU32::new(0),
+        }
+    }
+
+    fn log_header(&self, kkind: VarKeyKind) -> VarHeader {
+        let seq = self.log_seq.fetch_add(1, Ordering::Relaxed);
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq4(seq),
+        }
+    }
+}
+
+impl<Tx: Clone, F: Clone, const N: usize> Clone for FramedWireTx<Tx, F, N> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            framing: self.framing.clone(),
+            log_seq: AtomicU32::new(self.log_seq.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<Tx: WireTx, F: Framing, const N: usize> WireTx for FramedWireTx<Tx, F, N> {
+    type Error = FramedWireTxError<Tx::Error>;
+
+    async fn wait_connection(&self) {
+        self.tx.wait_connection().await;
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = [0u8; N];
+        let (hdr_used, remain) = hdr
+            .write_to_slice(&mut buf)
+            .ok_or(FramedWireTxError::MessageTooLarge)?;
+        let hdr_len = hdr_used.len();
+        let body_len = postcard::to_slice(msg, remain)
+            .map_err(|_| FramedWireTxError::MessageTooLarge)?
+            .len();
+        self.send_raw(&buf[..hdr_len + body_len]).await
+    }
+
+    async fn send_raw(&self, frame: &[u8]) -> Result<(), Self::Error> {
+        let mut out = [0u8; N];
+        let used = self
+            .framing
+            .encode(frame, &mut out)
+            .ok_or(FramedWireTxError::MessageTooLarge)?;
+        self.tx
+            .send_raw(&out[..used])
+            .await
+            .map_err(FramedWireTxError::Inner)
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, s).await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let hdr = self.log_header(kkind);
+        self.send(hdr, &a).await
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.flush().await.map_err(FramedWireTxError::Inner)
+    }
+}
+
+/// The error type of [`FramedWireTx`]
+#[derive(Debug)]
+pub enum FramedWireTxError<E> {
+    /// The wrapped [`WireTx`] impl returned an error
+    Inner(E),
+    /// The encoded frame did not fit in the buffer
+    MessageTooLarge,
+}
+
+impl<E: AsWireTxErrorKind> AsWireTxErrorKind for FramedWireTxError<E> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            FramedWireTxError::Inner(e) => e.as_kind(),
+            FramedWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl that decodes frames from the chunks of bytes of another
+///
+/// Chunks of up to `R` bytes are received from the wrapped [`WireRx`], and
+/// accumulated into frames of up to `N` bytes. Bytes received after the end of
+/// a frame are kept for the next call to [`receive()`](WireRx::receive), so it
+/// is cancel safe if the wrapped [`WireRx`] is.
+pub struct FramedWireRx<Rx, F, const R: usize, const N: usize> {
+    rx: Rx,
+    acc: FrameAccumulator<F, N>,
+    chunk: [u8; R],
+    pos: usize,
+    len: usize,
+}
+
+impl<Rx, F: Framing, const R: usize, const N: usize> FramedWireRx<Rx, F, R, N> {
+    /// Wrap `rx`, decoding frames with `framing`
+    pub fn new(rx: Rx, framing: F) -> Self {
+        Self {
+            rx,
+            acc: FrameAccumulator::new(framing),
+            chunk: [0; R],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<Rx: WireRx, F: Framing, const R: usize, const N: usize> WireRx for FramedWireRx<Rx, F, R, N> {
+    type Error = FramedWireRxError<Rx::Error>;
+
+    async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await;
+    }
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        loop {
+            if self.pos < self.len {
+                let input = &self.chunk[self.pos..self.len];
+                let (res, remaining) = match self.acc.feed(input) {
+                    FeedResult::Consumed => (None, 0),
+                    FeedResult::OverFull(rest) => {
+                        (Some(Err(FramedWireRxError::FrameTooLarge)), rest.len())
+                    }
+                    FeedResult::DeserError(rest) => {
+                        (Some(Err(FramedWireRxError::DecodeFailed)), rest.len())
+                    }
+                    FeedResult::Success { data, remaining } => {
+                        let res = match buf.get_mut(..data.len()) {
+                            Some(out) => {
+                                out.copy_from_slice(data);
+                                Ok(data.len())
+                            }
+                            None => Err(FramedWireRxError::FrameTooLarge),
+                        };
+                        (Some(res), remaining.len())
+                    }
+                };
+                self.pos = self.len - remaining;
+                match res {
+                    Some(Ok(used)) => return Ok(&mut buf[..used]),
+                    Some(Err(e)) => return Err(e),
+                    None => {}
+                }
+            }
+
+            let used = self
+                .rx
+                .receive(&mut self.chunk)
+                .await
+                .map_err(FramedWireRxError::Inner)?
+                .len();
+            self.pos = 0;
+            self.len = used;
+        }
+    }
+}
+
+/// The error type of [`FramedWireRx`]
+#[derive(Debug)]
+pub enum FramedWireRxError<E> {
+    /// The wrapped [`WireRx`] impl returned an error
+    Inner(E),
+    /// The decoded frame did not fit in the accumulator or the receive buffer,
+    /// and was discarded
+    FrameTooLarge,
+    /// The frame could not be decoded, and was discarded
+    DecodeFailed,
+}
+
+impl<E: AsWireRxErrorKind> AsWireRxErrorKind for FramedWireRxError<E> {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            FramedWireRxError::Inner(e) => e.as_kind(),
+            FramedWireRxError::FrameTooLarge => WireRxErrorKind::ReceivedMessageTooLarge,
+            FramedWireRxError::DecodeFailed => WireRxErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CobsFraming, FeedResult, FrameAccumulator, Framing, LengthPrefixFraming, NoFraming,
+    };
+
+    /// Encode `frames`, then decode them from chunks of `chunk` bytes
+    fn roundtrip<F: Framing + Clone>(framing: F, frames: &[&[u8]], chunk: usize) -> Vec<Vec<u8>> {
+        let mut stream = vec![];
+        for frame in frames {
+            let mut out = vec![0; framing.max_encoded_len(frame.len())];
+            let used = framing.encode(frame, &mut out).unwrap();
+            stream.extend_from_slice(&out[..used]);
+        }
+
+        let mut acc = FrameAccumulator::<_, 16>::new(framing);
+        let mut decoded = vec![];
+        for mut window in stream.chunks(chunk) {
+            while !window.is_empty() {
+                window = match acc.feed(window) {
+                    FeedResult::Consumed => break,
+                    FeedResult::OverFull(rest) | FeedResult::DeserError(rest) => rest,
+                    FeedResult::Success { data, remaining } => {
+                        decoded.push(data.to_vec());
+                        remaining
+                    }
+                };
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn split_frames() {
+        let frames: [&[u8]; 3] = [&[1, 0, 2], &[], &[0; 10]];
+        for chunk in [1, 3, 7, 64] {
+            assert_eq!(roundtrip(CobsFraming, &frames, chunk), frames);
+            assert_eq!(
+                roundtrip(LengthPrefixFraming::new(), &frames, chunk),
+                frames
+            );
+        }
+    }
+
+    #[test]
+    fn overfull_frames_are_skipped() {
+        let frames: [&[u8]; 3] = [&[1, 2], &[7; 20], &[3, 4]];
+        for chunk in [1, 5, 64] {
+            let expected = [vec![1, 2], vec![3, 4]];
+            assert_eq!(roundtrip(CobsFraming, &frames, chunk), expected);
+            assert_eq!(
+                roundtrip(LengthPrefixFraming::new(), &frames, chunk),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn encode_limits() {
+        let mut out = [0u8; 4];
+        assert_eq!(LengthPrefixFraming::new().encode(&[], &mut out), Some(4));
+        assert_eq!(LengthPrefixFraming::new().encode(&[1], &mut out), None);
+        assert_eq!(CobsFraming.encode(&[1, 2], &mut out), Some(4));
+        assert_eq!(out, [3, 1, 2, 0]);
+        assert_eq!(CobsFraming.encode(&[1, 2, 3], &mut out), None);
+        assert_eq!(NoFraming.encode(&[1, 2, 3, 4], &mut out), Some(4));
+
+        // Each chunk is one frame
+        let mut acc = FrameAccumulator::<_, 4>::new(NoFraming);
+        let FeedResult::Success { data, remaining } = acc.feed(&[5, 6]) else {
+            panic!()
+        };
+        assert_eq!((data, remaining), (&[5, 6][..], &[][..]));
+        assert!(matches!(acc.feed(&[0; 5]), FeedResult::OverFull(&[])));
+    }
+}
@@ -19,7 +19,12 @@
 //!   bits represent a sequence number length of 2^M. Values 00, 01, and 10
 //!   are valid.
 //! * The four lsbits are "protocol version", where the four V version bits
-//!   represent an unsigned 4-bit number. Currently only 0000 is a valid value.
+//!   represent an unsigned 4-bit number. Headers are always written with the
+//!   current [`PROTOCOL_VERSION`], and [`VarHeader::take_from_slice()`] rejects
+//!   headers of any other version. The two most significant version bits are
+//!   reserved as flags for compressed bodies, see [`VarHeader::COMPRESSED_BITS`],
+//!   and for request deadlines, see [`VarHeader::DEADLINE_BITS`], so versions are
+//!   at most 3.
 //!
 //! ## Key
 //!
@@ -57,6 +62,21 @@
 //! The length of the key is chosen by the "originator" of the message. For Endpoints
 //! this is the client making the request. For Topics, this is the device sending the
 //! topic message.
+//!
+//! ## Deadline
+//!
+//! If the [`VarHeader::DEADLINE_BITS`] flag is set, the Sequence Number is followed
+//! by a 4-byte little-endian deadline: the number of milliseconds after which the
+//! client no longer waits for a reply. The deadline is part of the header, but not
+//! of [`VarHeader`], see [`take_deadline()`].
+//!
+//! ## Fixed-size Headers
+//!
+//! The largest form of the header, with an 8-byte key and a 4-byte sequence
+//! number, always has the same size and layout, and is available as
+//! [`WireHeader`]. Its layout is part of the protocol, and only changes along
+//! with the [`PROTOCOL_VERSION`], so it can be relied on by implementations in
+//! other languages.
 
 use crate::{Key, Key1, Key2, Key4};
 
@@ -85,6 +105,34 @@ pub enum VarKey {
     Key8(Key),
 }
 
+/// The inner key types don't impl `defmt::Format`, so print the raw bytes
+#[cfg(feature = "defmt")]
+impl defmt::Format for VarKey {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            VarKey::Key1(k) => defmt::write!(f, "Key1({=u8:02X})", k.to_bytes()),
+            VarKey::Key2(k) => defmt::write!(f, "Key2({=[u8]:02X})", k.to_bytes()),
+            VarKey::Key4(k) => defmt::write!(f, "Key4({=[u8]:02X})", k.to_bytes()),
+            VarKey::Key8(k) => defmt::write!(f, "Key8({=[u8]:02X})", k.to_bytes()),
+        }
+    }
+}
+
+/// Formats the key as it appears on the wire, as lowercase hex
+///
+/// Full keys, e.g. [`Endpoint::REQ_KEY`](crate::Endpoint::REQ_KEY), can be printed
+/// by wrapping them in [`VarKey::Key8`].
+impl core::fmt::Display for VarKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VarKey::Key1(k) => k.fmt(f),
+            VarKey::Key2(k) => k.fmt(f),
+            VarKey::Key4(k) => k.fmt(f),
+            VarKey::Key8(k) => crate::write_hex(f, &k.to_bytes()),
+        }
+    }
+}
+
 /// We implement PartialEq MANUALLY for VarKey, because keys of different lengths SHOULD compare
 /// as equal.
 impl PartialEq for VarKey {
@@ -235,6 +283,7 @@ pub enum VarKeyKind {
 /// We DO NOT impl Serialize/Deserialize for this type because we use
 /// non-postcard-compatible format (externally tagged)
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum VarSeq {
     /// A one byte sequence number
     Seq1(u8),
@@ -352,6 +401,36 @@ pub enum VarSeqKind {
 // VARHEADER
 //////////////////////////////////////////////////////////////////////////////
 
+/// The version of the wire format, sent in the discriminant of every header
+///
+/// This is bumped whenever the wire format changes in an incompatible way, so
+/// that mismatched clients and servers reject each other's frames instead of
+/// misinterpreting them. Servers answer requests of another version with
+/// [`WireError::ProtocolVersionMismatch`](crate::standard_icd::WireError::ProtocolVersionMismatch).
+pub const PROTOCOL_VERSION: u8 = 0;
+
+const _: () = assert!(PROTOCOL_VERSION & !VarHeader::VER_MASK_BITS == 0);
+const _: () = assert!(PROTOCOL_VERSION & VarHeader::COMPRESSED_BITS == 0);
+const _: () = assert!(PROTOCOL_VERSION & VarHeader::DEADLINE_BITS == 0);
+
+/// The length of the deadline following the header, see [`VarHeader::DEADLINE_BITS`]
+pub const DEADLINE_LEN: usize = 4;
+
+/// Split the deadline off the `body` of a frame with the given `version` bits,
+/// as returned by [`VarHeader::take_versioned_from_slice()`]
+///
+/// Returns the deadline in milliseconds, if any, the version without the
+/// [`VarHeader::DEADLINE_BITS`] flag, and the remaining body, or `None` if the
+/// deadline is incomplete.
+pub fn take_deadline(version: u8, body: &[u8]) -> Option<(Option<u32>, u8, &[u8])> {
+    if version & VarHeader::DEADLINE_BITS == 0 {
+        return Some((None, version, body));
+    }
+    let (deadline, body) = body.split_first_chunk::<DEADLINE_LEN>()?;
+    let version = version & !VarHeader::DEADLINE_BITS;
+    Some((Some(u32::from_le_bytes(*deadline)), version, body))
+}
+
 /// A variably sized message header
 ///
 /// NOTE: We use the standard PartialEq here as it will do the correct things.
@@ -362,6 +441,7 @@ pub enum VarSeqKind {
 /// We DO NOT impl Serialize/Deserialize for this type because we use
 /// non-postcard-compatible format (externally tagged)
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct VarHeader {
     /// The variably sized Key
     pub key: VarKey,
@@ -395,6 +475,19 @@ impl VarHeader {
     pub const VER_ZERO_BITS: u8 = 0b00_00_0000;
     /// Mask bits
     pub const VER_MASK_BITS: u8 = 0b00_00_1111;
+    /// Flag bit for a compressed body, see the `compress` module
+    ///
+    /// This is the most significant bit of the protocol version, so receivers
+    /// that do not decompress frames reject compressed frames as frames of an
+    /// unknown version.
+    pub const COMPRESSED_BITS: u8 = 0b00_00_1000;
+    /// Flag bit for a header followed by a deadline, see the "Deadline" section
+    /// of the [`header`](crate::header) module
+    ///
+    /// Like [`COMPRESSED_BITS`](Self::COMPRESSED_BITS), this is a bit of the
+    /// protocol version, so receivers that do not support deadlines reject these
+    /// frames as frames of an unknown version.
+    pub const DEADLINE_BITS: u8 = 0b00_00_0100;
 
     /// Encode the header to a Vec of bytes
     #[cfg(feature = "use-std")]
@@ -434,6 +527,7 @@ impl VarHeader {
                 out.extend_from_slice(&s.to_le_bytes());
             }
         }
+        disc_out |= PROTOCOL_VERSION;
         // push discriminant to the end...
         out.push(disc_out);
         // ...and swap-remove the placeholder byte, moving the discriminant to the front
@@ -502,6 +596,7 @@ impl VarHeader {
                 used += 4;
             }
         }
+        *disc_out |= PROTOCOL_VERSION;
         Some(buf.split_at_mut(used))
     }
 
@@ -510,14 +605,25 @@ impl VarHeader {
     /// If a well-formed header was found, a `Some` will be returned with the
     /// decoded header and unused remaining bytes.
     ///
-    /// If no well-formed header was found, a `None` will be returned.
+    /// If no well-formed header was found, or the header is not of the current
+    /// [`PROTOCOL_VERSION`], a `None` will be returned.
     pub fn take_from_slice(buf: &[u8]) -> Option<(Self, &[u8])> {
-        let (disc, mut remain) = buf.split_first()?;
-
-        // For now, we only trust version zero
-        if (*disc & Self::VER_MASK_BITS) != Self::VER_ZERO_BITS {
-            return None;
+        match Self::take_versioned_from_slice(buf)? {
+            (hdr, PROTOCOL_VERSION, remain) => Some((hdr, remain)),
+            _ => None,
         }
+    }
+
+    /// Attempt to decode a header of any protocol version from the given bytes.
+    ///
+    /// If a well-formed header was found, a `Some` will be returned with the
+    /// decoded header, its protocol version, and unused remaining bytes. Headers
+    /// of other versions may not be meaningful, but allow replying with an error.
+    ///
+    /// If no well-formed header was found, a `None` will be returned.
+    pub fn take_versioned_from_slice(buf: &[u8]) -> Option<(Self, u8, &[u8])> {
+        let (disc, mut remain) = buf.split_first()?;
+        let version = *disc & Self::VER_MASK_BITS;
 
         let key = match (*disc) & Self::KEY_MASK_BITS {
             Self::KEY_ONE_BITS => {
@@ -572,13 +678,123 @@ impl VarHeader {
             // Possible (could be 0b11), is invalid
             _ => return None,
         };
-        Some((Self { key, seq_no }, remain))
+        Some((Self { key, seq_no }, version, remain))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// WIREHEADER
+//////////////////////////////////////////////////////////////////////////////
+
+/// A header with a fixed size and layout
+///
+/// This is the largest form of [`VarHeader`], with an 8-byte key and a 4-byte
+/// sequence number, which is always [`WireHeader::WIRE_SIZE`] bytes long:
+///
+/// | Offset | Size | Field                                     |
+/// | :----- | :--- | :---------------------------------------- |
+/// | 0      | 1    | [`WireHeader::DISCRIMINANT`], i.e. `0xE0` |
+/// | 1      | 8    | Key bytes, in the order of [`Key`]        |
+/// | 9      | 4    | Sequence number, little-endian            |
+///
+/// All servers accept this form, so it is the simplest way to send requests from
+/// other languages. Note that replies still use the key length chosen by the
+/// server, and the sequence number length of the request, so they must be decoded
+/// as described in the [module docs](self).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WireHeader {
+    /// The full 8-byte Key
+    pub key: Key,
+    /// The Sequence Number
+    pub seq_no: u32,
+}
+
+impl WireHeader {
+    /// The size of the encoded header, in bytes
+    pub const WIRE_SIZE: usize = 1 + 8 + 4;
+
+    /// The discriminant of every encoded header
+    pub const DISCRIMINANT: u8 =
+        VarHeader::KEY_EIGHT_BITS | VarHeader::SEQ_FOUR_BITS | PROTOCOL_VERSION;
+
+    /// Encode the header
+    pub const fn encode(&self) -> [u8; Self::WIRE_SIZE] {
+        let mut out = [0u8; Self::WIRE_SIZE];
+        out[0] = Self::DISCRIMINANT;
+        let key = self.key.to_bytes();
+        let seq = self.seq_no.to_le_bytes();
+        let mut i = 0;
+        while i < 8 {
+            out[1 + i] = key[i];
+            i += 1;
+        }
+        let mut i = 0;
+        while i < 4 {
+            out[9 + i] = seq[i];
+            i += 1;
+        }
+        out
+    }
+
+    /// Attempt to decode a header from the start of the given bytes.
+    ///
+    /// Returns the header and the remaining bytes, or `None` if there are fewer
+    /// than [`WireHeader::WIRE_SIZE`] bytes, or the discriminant is not
+    /// [`WireHeader::DISCRIMINANT`].
+    pub fn decode(buf: &[u8]) -> Option<(Self, &[u8])> {
+        let (hdr, remain) = buf.split_at_checked(Self::WIRE_SIZE)?;
+        if hdr[0] != Self::DISCRIMINANT {
+            return None;
+        }
+        let mut key = [0u8; 8];
+        key.copy_from_slice(&hdr[1..9]);
+        let mut seq = [0u8; 4];
+        seq.copy_from_slice(&hdr[9..13]);
+        let hdr = Self {
+            key: unsafe { Key::from_bytes(key) },
+            seq_no: u32::from_le_bytes(seq),
+        };
+        Some((hdr, remain))
+    }
+
+    /// Convert a [`VarHeader`], which must have an 8-byte key
+    ///
+    /// Returns `None` for shorter keys, as they can't be expanded.
+    pub fn from_var_header(hdr: &VarHeader) -> Option<Self> {
+        match hdr.key {
+            VarKey::Key8(key) => Some(Self {
+                key,
+                seq_no: hdr.seq_no.into(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for WireHeader {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "WireHeader {{ key: {=[u8]:02X}, seq_no: {=u32} }}",
+            self.key.to_bytes(),
+            self.seq_no
+        )
+    }
+}
+
+impl From<WireHeader> for VarHeader {
+    fn from(hdr: WireHeader) -> Self {
+        Self {
+            key: VarKey::Key8(hdr.key),
+            seq_no: VarSeq::Seq4(hdr.seq_no),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{VarHeader, VarKey, VarSeq};
+    use super::{VarHeader, VarKey, VarSeq, WireHeader, PROTOCOL_VERSION};
     use crate::{Key, Key1, Key2};
 
     #[test]
@@ -668,6 +884,57 @@ mod test {
         }
     }
 
+    #[test]
+    fn protocol_version() {
+        let hdr = VarHeader {
+            key: VarKey::Key1(Key1(1)),
+            seq_no: VarSeq::Seq1(2),
+        };
+        let mut buf = [0u8; 3];
+        let (used, _) = hdr.write_to_slice(&mut buf).unwrap();
+        assert_eq!(used[0] & VarHeader::VER_MASK_BITS, PROTOCOL_VERSION);
+
+        // Headers of other versions are only accepted by `take_versioned_from_slice`
+        used[0] |= PROTOCOL_VERSION + 1;
+        assert!(VarHeader::take_from_slice(used).is_none());
+        let (deser, version, _) = VarHeader::take_versioned_from_slice(used).unwrap();
+        assert_eq!(deser, hdr);
+        assert_eq!(version, PROTOCOL_VERSION + 1);
+    }
+
+    #[test]
+    fn wire_header() {
+        let hdr = WireHeader {
+            key: unsafe { Key::from_bytes([0x12, 0x23, 0x34, 0x45, 0x56, 0x67, 0x78, 0x89]) },
+            seq_no: 0x42_AF_AA_BB,
+        };
+        let bytes = hdr.encode();
+        assert_eq!(
+            bytes,
+            [0xE0, 0x12, 0x23, 0x34, 0x45, 0x56, 0x67, 0x78, 0x89, 0xBB, 0xAA, 0xAF, 0x42]
+        );
+
+        // The same bytes as the equivalent VarHeader
+        let var = VarHeader::from(hdr);
+        assert_eq!(var.write_to_vec(), bytes);
+        assert_eq!(WireHeader::from_var_header(&var), Some(hdr));
+
+        let mut buf = bytes.to_vec();
+        buf.push(0x99);
+        let (deser, remain) = WireHeader::decode(&buf).unwrap();
+        assert_eq!(deser, hdr);
+        assert_eq!(remain, &[0x99]);
+
+        // Other forms and short buffers are rejected
+        assert!(WireHeader::decode(&bytes[..12]).is_none());
+        let short = VarHeader {
+            key: VarKey::Key1(Key1(1)),
+            seq_no: VarSeq::Seq4(2),
+        };
+        assert!(WireHeader::decode(&short.write_to_vec()).is_none());
+        assert!(WireHeader::from_var_header(&short).is_none());
+    }
+
     #[test]
     fn var_seq_equality() {
         let val32 = 0x12345678;
@@ -0,0 +1,196 @@
+//! Receiving replies in callbacks, instead of one future per request
+//!
+//! See [`HostClient::send_cb()`].
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq},
+    host_client::{decode_wire_err, ConnectionState, HostClient, HostContext, HostErr, RpcFrame},
+    Endpoint, Key,
+};
+
+/// The reply passed to a [`PendingCallback`]
+pub(crate) enum CallbackReply {
+    /// The body of the response
+    Resp(Vec<u8>),
+    /// The body of an error
+    Err(Vec<u8>),
+    /// The client was closed before the reply was received
+    Closed,
+    /// The sequence number was reused before the reply was received
+    Replaced,
+}
+
+/// A request waiting for its reply in a callback
+pub(crate) struct PendingCallback {
+    resp_key: Key,
+    err_key: Key,
+    cb: Box<dyn FnOnce(CallbackReply) + Send>,
+    /// The flow control credit, held until the reply is received
+    _credit: Option<OwnedSemaphorePermit>,
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Send a message of type [Endpoint::Request][Endpoint] to `path`, and call
+    /// `cb` with the [Endpoint::Response][Endpoint]
+    ///
+    /// Like [`send_resp()`](Self::send_resp), but without a future waiting for
+    /// each reply: the callback is stored by sequence number, and called by the
+    /// I/O worker when the reply is received. This has less overhead when sending
+    /// many small requests concurrently, at the cost of running `cb` in the
+    /// receive loop, so it must not block.
+    ///
+    /// This waits until the request was queued for sending, e.g. while the
+    /// outgoing queue or the [flow control](Self::enable_flow_control) credits are
+    /// exhausted, but not for the reply. `cb` is called exactly once, with the
+    /// reply, or with:
+    ///
+    /// * [`HostErr::Closed`] if the client is closed before the reply is received
+    /// * [`HostErr::Disconnected`] if the device is not connected
+    /// * [`HostErr::BodyTooLarge`] if the request is too large to be sent, see
+    ///   [`send_resp()`](Self::send_resp)
+    /// * [`HostErr::Timeout`] if the sequence number is reused by another
+    ///   callback before the reply is received, e.g. after the reply was lost
+    ///
+    /// Callbacks never time out otherwise, and the default timeout and retry
+    /// policy of the client do not apply.
+    pub async fn send_cb<E, F>(&self, t: &E::Request, cb: F)
+    where
+        E: Endpoint,
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+        F: FnOnce(Result<E::Response, HostErr<WireErr>>) + Send + 'static,
+    {
+        if self.is_closed() {
+            return cb(Err(HostErr::Closed));
+        }
+        let body = postcard::to_stdvec(t).expect("Allocations should not ever fail");
+        if let Err(e) = self.check_request_size::<E>(body.len()) {
+            return cb(Err(e));
+        }
+
+        // If flow control is enabled, hold a credit until we have received a reply
+        let credits = self.ctx.credits.read().unwrap().clone();
+        let credit = match credits {
+            Some(sem) => tokio::select! {
+                _c = self.stopper.wait_stopped() => return cb(Err(HostErr::Closed)),
+                p = sem.acquire_owned() => match p {
+                    Ok(p) => Some(p),
+                    Err(_) => return cb(Err(HostErr::Closed)),
+                },
+            },
+            None => None,
+        };
+
+        // Don't bother sending if we know there's nobody on the other side
+        if *self.ctx.conn.borrow() == ConnectionState::Connecting {
+            return cb(Err(HostErr::Disconnected));
+        }
+
+        let Some(claim) = self.ctx.next_seq() else {
+            return cb(Err(HostErr::SeqNoExhausted));
+        };
+        let seq_no = claim.seq_no();
+        let err_key = self.err_key;
+        let pending = PendingCallback {
+            resp_key: E::RESP_KEY,
+            err_key,
+            cb: Box::new(move |reply| {
+                cb(match reply {
+                    CallbackReply::Resp(body) => {
+                        postcard::from_bytes::<E::Response>(&body).map_err(HostErr::from)
+                    }
+                    CallbackReply::Err(body) => Err(decode_wire_err(err_key, &body)),
+                    CallbackReply::Closed => Err(HostErr::Closed),
+                    CallbackReply::Replaced => Err(HostErr::Timeout),
+                })
+            }),
+            _credit: credit,
+        };
+
+        // Register BEFORE sending, so the reply can't arrive first
+        let replaced = self.ctx.callbacks.lock().unwrap().insert(seq_no, pending);
+        // The registered callback keeps the number in use from here on
+        drop(claim);
+        if let Some(old) = replaced {
+            (old.cb)(CallbackReply::Replaced);
+        }
+        // The callbacks may have been closed since we checked
+        if self.is_closed() {
+            return self.ctx.fail_callback(seq_no);
+        }
+
+        let kkind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(E::REQ_KEY);
+        key.shrink_to(kkind);
+        let frame = RpcFrame {
+            header: VarHeader {
+                key,
+                seq_no: VarSeq::Seq4(seq_no),
+            },
+            body,
+        };
+        if self.out.send(frame).await.is_err() {
+            self.ctx.fail_callback(seq_no);
+        }
+    }
+}
+
+impl HostContext {
+    /// Pass a reply to the callback waiting for it, if any
+    ///
+    /// Returns the frame if no callback was waiting for it.
+    pub(crate) fn wake_callback(&self, frame: RpcFrame) -> Result<(), RpcFrame> {
+        let seq_no: u32 = frame.header.seq_no.into();
+        let (pending, is_resp) = {
+            let mut callbacks = self.callbacks.lock().unwrap();
+            let is_resp = match callbacks.get(&seq_no) {
+                Some(p) if frame.header.key == VarKey::Key8(p.resp_key) => true,
+                Some(p) if frame.header.key == VarKey::Key8(p.err_key) => false,
+                _ => return Err(frame),
+            };
+            match callbacks.remove(&seq_no) {
+                Some(pending) => (pending, is_resp),
+                None => return Err(frame),
+            }
+        };
+
+        self.unmatched
+            .completed(seq_no, pending.resp_key, pending.err_key);
+        let kkind = frame.header.key.kind();
+        if *self.kkind.read().unwrap() != kkind {
+            *self.kkind.write().unwrap() = kkind;
+        }
+        let reply = if is_resp {
+            CallbackReply::Resp(frame.body)
+        } else {
+            CallbackReply::Err(frame.body)
+        };
+        (pending.cb)(reply);
+        Ok(())
+    }
+
+    /// Fail the callback of `seq_no` as closed, unless it was already called
+    fn fail_callback(&self, seq_no: u32) {
+        let pending = self.callbacks.lock().unwrap().remove(&seq_no);
+        if let Some(pending) = pending {
+            self.unmatched
+                .completed(seq_no, pending.resp_key, pending.err_key);
+            (pending.cb)(CallbackReply::Closed);
+        }
+    }
+
+    /// Fail all callbacks waiting for a reply, as the client was closed
+    pub(crate) fn close_callbacks(&self) {
+        let callbacks = core::mem::take(&mut *self.callbacks.lock().unwrap());
+        for (_, pending) in callbacks {
+            (pending.cb)(CallbackReply::Closed);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! A HostClient sending and receiving whole frames over channels
+//!
+//! This is useful when the transport is not owned by postcard-rpc, e.g. when a
+//! port carries several protocols, and a multiplexer routes the frames of each
+//! protocol to its own handler.
+
+use std::future::Future;
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::{
+    header::VarSeqKind,
+    host_client::{HostClient, HostClientConfig, WireRx, WireSpawn, WireTx},
+};
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a new [HostClient] sending frames to `tx`, and receiving frames
+    /// from `rx`
+    ///
+    /// Each message of the channels is one whole frame, i.e. a header followed by
+    /// a body, as sent and received by the server. Framing them on the actual
+    /// transport is up to the owner of the other ends of the channels, e.g. a
+    /// multiplexer sharing the transport with other protocols. The client stops
+    /// once either channel is closed.
+    ///
+    /// `err_uri_path` is the path associated with the `WireErr` message type.
+    ///
+    /// This constructor must be called from within a tokio runtime. See
+    /// `server::impls::channel` for the matching server.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::standard_icd::{WireError, ERROR_PATH};
+    /// use tokio::sync::mpsc;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let (to_mux, mut from_client) = mpsc::channel::<Vec<u8>>(8);
+    /// let (to_client, from_mux) = mpsc::channel::<Vec<u8>>(8);
+    /// let client = HostClient::<WireError>::new_with_channels(
+    ///     to_mux,
+    ///     from_mux,
+    ///     // the URI/path for `Error` messages
+    ///     ERROR_PATH,
+    ///     // Outgoing queue depth in messages
+    ///     8,
+    ///     // Use one-byte sequence numbers
+    ///     VarSeqKind::Seq1,
+    /// );
+    /// // The multiplexer sends the frames from `from_client` to the device, and
+    /// // passes the frames it receives from the device to `to_client`
+    /// # }
+    /// ```
+    pub fn new_with_channels(
+        tx: mpsc::Sender<Vec<u8>>,
+        rx: mpsc::Receiver<Vec<u8>>,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Self {
+        let config = HostClientConfig::new_default(seq_no_kind, err_uri_path, outgoing_depth);
+        Self::new_with_channels_and_config(tx, rx, &config)
+    }
+
+    /// Create a new [HostClient] with the given configuration
+    ///
+    /// See [`HostClient::new_with_channels`] for more details
+    pub fn new_with_channels_and_config(
+        tx: mpsc::Sender<Vec<u8>>,
+        rx: mpsc::Receiver<Vec<u8>>,
+        config: &HostClientConfig<'_>,
+    ) -> Self {
+        HostClient::new_with_wire_and_config(ChannelTx { tx }, ChannelRx { rx }, TokioSpawn, config)
+    }
+}
+
+/// Channel error kinds
+#[derive(Debug)]
+pub enum ChannelError {
+    /// Rx was closed
+    RxClosed,
+    /// Tx was closed
+    TxClosed,
+}
+
+impl std::fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <Self as core::fmt::Debug>::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// Trait impl for channels
+pub struct ChannelRx {
+    pub(crate) rx: mpsc::Receiver<Vec<u8>>,
+}
+/// Trait impl for channels
+pub struct ChannelTx {
+    pub(crate) tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl WireRx for ChannelRx {
+    type Error = ChannelError;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        match self.rx.recv().await {
+            Some(v) => {
+                #[cfg(test)]
+                println!("c<-s: {v:?}");
+                Ok(v)
+            }
+            None => Err(ChannelError::RxClosed),
+        }
+    }
+}
+
+impl WireTx for ChannelTx {
+    type Error = ChannelError;
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), Self::Error> {
+        #[cfg(test)]
+        println!("c->s: {data:?}");
+        self.tx.send(data).await.map_err(|_| ChannelError::TxClosed)
+    }
+}
+
+/// Spawns the workers of the client with tokio
+pub(crate) struct TokioSpawn;
+
+impl WireSpawn for TokioSpawn {
+    fn spawn(&mut self, fut: impl Future<Output = ()> + Send + 'static) {
+        _ = tokio::task::spawn(fut);
+    }
+}
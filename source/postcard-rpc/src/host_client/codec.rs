@@ -0,0 +1,171 @@
+//! Alternate encodings of messages on the host
+//!
+//! Devices only speak postcard. A [`CodecClient`] wraps a [`HostClient`], and
+//! converts requests and responses between postcard and another encoding, e.g.
+//! JSON with the [`Json`] codec. This allows a host-side shim to present a REPL or
+//! a scripting interface, without the script knowing anything about postcard:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "json")]
+//! # async fn example(client: postcard_rpc::host_client::HostClient<postcard_rpc::standard_icd::WireError>) {
+//! use postcard_rpc::{host_client::codec::Json, standard_icd::PingEndpoint};
+//!
+//! let json = client.with_codec(Json);
+//! let resp = json.send_resp::<PingEndpoint>(b"42").await.unwrap();
+//! assert_eq!(resp, b"42");
+//! # }
+//! ```
+//!
+//! The codec is only used for the local encode and decode steps: the frames sent
+//! to the device are the same as those sent by [`HostClient::send_resp()`].
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::{
+    header::VarSeq,
+    host_client::{HostClient, HostErr},
+    Endpoint, Topic,
+};
+
+/// An encoding of messages, used by a [`CodecClient`]
+pub trait Codec {
+    /// The error returned when a message can't be encoded or decoded
+    type Error: core::fmt::Debug;
+
+    /// Encode a message
+    fn encode<T: Serialize + ?Sized>(&self, msg: &T) -> Result<Vec<u8>, Self::Error>;
+
+    /// Decode a message
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error>;
+}
+
+/// The postcard encoding, as used on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Postcard;
+
+impl Codec for Postcard {
+    type Error = postcard::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, msg: &T) -> Result<Vec<u8>, Self::Error> {
+        postcard::to_stdvec(msg)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+/// The JSON encoding
+///
+/// **Requires feature**: `json`
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, msg: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(msg)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// The error returned by [`CodecClient`]
+#[derive(Debug, PartialEq, Error)]
+pub enum CodecErr<E, WireErr> {
+    /// The message could not be encoded or decoded by the codec
+    #[error("the message could not be converted by the codec")]
+    Codec(E),
+    /// The request failed, see [HostErr]
+    #[error(transparent)]
+    Host(#[from] HostErr<WireErr>),
+}
+
+/// A [`HostClient`] sending and receiving messages encoded with the codec `C`
+///
+/// Created with [`HostClient::with_codec()`], see the [module docs](self).
+pub struct CodecClient<WireErr, C> {
+    client: HostClient<WireErr>,
+    codec: C,
+}
+
+impl<WireErr, C: Clone> Clone for CodecClient<WireErr, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Wrap this client, to send and receive messages encoded with `codec`
+    ///
+    /// See the [`codec`](self) module for details.
+    pub fn with_codec<C: Codec>(&self, codec: C) -> CodecClient<WireErr, C> {
+        CodecClient {
+            client: self.clone(),
+            codec,
+        }
+    }
+}
+
+impl<WireErr, C> CodecClient<WireErr, C>
+where
+    WireErr: DeserializeOwned + Schema,
+    C: Codec,
+{
+    /// The wrapped client
+    pub fn client(&self) -> &HostClient<WireErr> {
+        &self.client
+    }
+
+    /// The codec
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Send an encoded request to the endpoint `E`, and return the encoded response
+    ///
+    /// See [`HostClient::send_resp()`].
+    pub async fn send_resp<E: Endpoint>(
+        &self,
+        req: &[u8],
+    ) -> Result<Vec<u8>, CodecErr<C::Error, WireErr>>
+    where
+        E::Request: Serialize + DeserializeOwned + Schema,
+        E::Response: Serialize + DeserializeOwned + Schema,
+    {
+        let req: E::Request = self.codec.decode(req).map_err(CodecErr::Codec)?;
+        let resp = self.client.send_resp::<E>(&req).await?;
+        self.codec.encode(&resp).map_err(CodecErr::Codec)
+    }
+
+    /// Publish an encoded message to the topic `T`
+    ///
+    /// See [`HostClient::publish()`].
+    pub async fn publish<T: Topic>(
+        &self,
+        seq_no: VarSeq,
+        msg: &[u8],
+    ) -> Result<(), CodecErr<C::Error, WireErr>>
+    where
+        T::Message: Serialize + DeserializeOwned,
+    {
+        let msg: T::Message = self.codec.decode(msg).map_err(CodecErr::Codec)?;
+        self.client
+            .publish::<T>(seq_no, &msg)
+            .await
+            .map_err(|_| CodecErr::Host(HostErr::Closed))
+    }
+}
@@ -0,0 +1,104 @@
+//! Answering requests sent by the device
+
+use core::{future::Future, marker::PhantomData};
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::select;
+
+use crate::{
+    header::{VarHeader, VarKey},
+    host_client::{HostClient, IoClosed, RawSubscription, RpcFrame, SubscribeError},
+    standard_icd::{WireError, ERROR_KEY},
+    Endpoint,
+};
+
+/// Answers requests to `E` sent by the device, see
+/// [`HostClient::serve_device_requests()`]
+pub struct DeviceRequestServer<E, WireErr> {
+    client: HostClient<WireErr>,
+    sub: RawSubscription,
+    _pd: PhantomData<fn() -> E>,
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Start receiving requests to `E` sent by the device, e.g. with the
+    /// `server::device_request` module
+    ///
+    /// Up to `depth` requests are buffered until they are answered with
+    /// [`DeviceRequestServer::run()`] or [`DeviceRequestServer::serve_one()`].
+    ///
+    /// There can only be one server for each endpoint, so this returns
+    /// [`SubscribeError::AlreadySubscribed`] while another server for `E` exists.
+    pub async fn serve_device_requests<E>(
+        &self,
+        depth: usize,
+    ) -> Result<DeviceRequestServer<E, WireErr>, SubscribeError>
+    where
+        E: Endpoint,
+        E::Request: DeserializeOwned,
+        E::Response: Serialize,
+    {
+        let sub = self.subscribe_exclusive_raw(E::REQ_KEY, depth).await?;
+        Ok(DeviceRequestServer {
+            client: self.clone(),
+            sub,
+            _pd: PhantomData,
+        })
+    }
+}
+
+impl<E, WireErr> DeviceRequestServer<E, WireErr>
+where
+    E: Endpoint,
+    E::Request: DeserializeOwned,
+    E::Response: Serialize,
+{
+    /// Answer a single request with `handler`, waiting for one if none is buffered
+    ///
+    /// Requests that can't be deserialized are answered with
+    /// [`WireError::DeserFailed`].
+    pub async fn serve_one<F, Fut>(&mut self, handler: F) -> Result<(), IoClosed>
+    where
+        F: FnOnce(E::Request) -> Fut,
+        Fut: Future<Output = E::Response>,
+    {
+        let frame = self.sub.recv().await.ok_or(IoClosed)?;
+        let seq_no = frame.header.seq_no;
+        let (key, body) = match postcard::from_bytes::<E::Request>(&frame.body) {
+            Ok(req) => {
+                let resp = handler(req).await;
+                (E::RESP_KEY, postcard::to_stdvec(&resp))
+            }
+            Err(_) => (ERROR_KEY, postcard::to_stdvec(&WireError::DeserFailed)),
+        };
+        let frame = RpcFrame {
+            // NOTE: answers are always sent with the full key, so the device can
+            // tell them apart from requests
+            header: VarHeader {
+                key: VarKey::Key8(key),
+                seq_no,
+            },
+            body: body.expect("Allocations should not ever fail"),
+        };
+
+        let cancel_fut = self.client.stopper.wait_stopped();
+        let operate_fut = self.client.out.send(frame);
+        select! {
+            _ = cancel_fut => Err(IoClosed),
+            res = operate_fut => res.map_err(|_| IoClosed),
+        }
+    }
+
+    /// Answer requests with `handler` until the client is closed
+    pub async fn run<F, Fut>(mut self, mut handler: F)
+    where
+        F: FnMut(E::Request) -> Fut,
+        Fut: Future<Output = E::Response>,
+    {
+        while self.serve_one(&mut handler).await.is_ok() {}
+    }
+}
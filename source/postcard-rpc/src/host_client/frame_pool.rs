@@ -0,0 +1,137 @@
+//! Reuse of buffers for incoming frames
+//!
+//! Every frame received by a [`HostClient`](crate::host_client::HostClient) is
+//! read into a buffer taken from its [`FramePool`], and the bodies handed to
+//! subscriptions and pending requests are copied into buffers from the same
+//! pool. Once a message has been deserialized, its buffer is returned to the
+//! pool, so under a steady stream of frames, no allocations are needed.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{header::VarHeader, host_client::RpcFrame};
+
+/// A bounded set of free buffers
+pub(crate) struct FramePool {
+    free: Mutex<Vec<Vec<u8>>>,
+    cap: usize,
+}
+
+impl FramePool {
+    /// Create a pool keeping up to `cap` free buffers, or none if `cap` is zero
+    pub(crate) fn new(cap: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::with_capacity(cap)),
+            cap,
+        }
+    }
+
+    /// Take an empty buffer, allocating a new one if none are free
+    pub(crate) fn take(&self) -> Vec<u8> {
+        if self.cap == 0 {
+            return Vec::new();
+        }
+        self.free.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Take a buffer holding a copy of `data`
+    pub(crate) fn copy_of(&self, data: &[u8]) -> Vec<u8> {
+        let mut buf = self.take();
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    /// Return a buffer to the pool, or free it if the pool is full
+    pub(crate) fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.cap {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+
+    /// Deserialize a message from `buf`, and return `buf` to the pool
+    pub(crate) fn decode<T: DeserializeOwned>(&self, buf: Vec<u8>) -> Result<T, postcard::Error> {
+        let res = postcard::from_bytes(&buf);
+        self.put(buf);
+        res
+    }
+}
+
+/// A buffer that is returned to its pool when dropped
+pub(crate) struct PoolGuard<'a> {
+    pool: &'a FramePool,
+    buf: Vec<u8>,
+}
+
+impl<'a> PoolGuard<'a> {
+    pub(crate) fn new(pool: &'a FramePool, buf: Vec<u8>) -> Self {
+        Self { pool, buf }
+    }
+}
+
+impl Deref for PoolGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PoolGuard<'_> {
+    fn drop(&mut self) {
+        self.pool.put(core::mem::take(&mut self.buf));
+    }
+}
+
+/// A frame whose body is borrowed from the buffer pool of a
+/// [`HostClient`](crate::host_client::HostClient)
+///
+/// The buffer is returned to the pool when the frame is dropped. See
+/// [`RawSubscription::recv_pooled()`](crate::host_client::RawSubscription::recv_pooled).
+pub struct PooledFrame {
+    header: VarHeader,
+    body: Vec<u8>,
+    pool: Arc<FramePool>,
+}
+
+impl PooledFrame {
+    pub(crate) fn new(frame: RpcFrame, pool: Arc<FramePool>) -> Self {
+        Self {
+            header: frame.header,
+            body: frame.body,
+            pool,
+        }
+    }
+
+    /// The header of the frame
+    pub fn header(&self) -> &VarHeader {
+        &self.header
+    }
+
+    /// The serialized message payload
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Take the frame out of the pool, e.g. to keep it
+    pub fn into_frame(mut self) -> RpcFrame {
+        RpcFrame {
+            header: self.header,
+            body: core::mem::take(&mut self.body),
+        }
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        self.pool.put(core::mem::take(&mut self.body));
+    }
+}
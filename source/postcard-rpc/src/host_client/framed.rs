@@ -0,0 +1,201 @@
+//! A HostClient over a byte transport, with a selectable framing
+//!
+//! See the [`framing`](crate::framing) module for the available strategies.
+
+use std::collections::VecDeque;
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    accumulator::raw::FeedResult,
+    framing::Framing,
+    header::VarSeqKind,
+    host_client::{HostClient, HostClientConfig, WireRx, WireSpawn, WireTx},
+};
+
+/// The default size of the largest received frame
+const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// # Framed Constructor Methods
+///
+/// These methods are used to create a new [HostClient] instance for use with a
+/// transport of bytes, e.g. a pipe or a serial port, with the given [`Framing`].
+///
+/// **Requires feature**: `framing`
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a new [HostClient] encoding frames with `framing`
+    ///
+    /// Each message sent to `tx` is one encoded frame, and the chunks received
+    /// from `rx` are decoded into frames, regardless of where they were split.
+    /// `err_uri_path` is the path associated with the `WireErr` message type.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,ignore
+    /// use postcard_rpc::framing::LengthPrefixFraming;
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::standard_icd::{WireError, ERROR_PATH};
+    ///
+    /// let client = HostClient::<WireError>::new_with_framing(
+    ///     pipe_tx,
+    ///     pipe_rx,
+    ///     LengthPrefixFraming::new(),
+    ///     TokioSpawn,
+    ///     VarSeqKind::Seq2,
+    ///     // the URI/path for `Error` messages
+    ///     ERROR_PATH,
+    ///     // Outgoing queue depth in messages
+    ///     8,
+    /// );
+    /// ```
+    pub fn new_with_framing<WTX, WRX, F, WSP>(
+        tx: WTX,
+        rx: WRX,
+        framing: F,
+        sp: WSP,
+        seq_kind: VarSeqKind,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+    ) -> Self
+    where
+        WTX: WireTx,
+        WRX: WireRx,
+        F: Framing + Clone + Send + 'static,
+        WSP: WireSpawn,
+    {
+        let config = HostClientConfig::new_default(seq_kind, err_uri_path, outgoing_depth);
+        Self::new_with_framing_and_config(tx, rx, framing, sp, &config)
+    }
+
+    /// Create a new [HostClient] with the given configuration
+    ///
+    /// The [`max_frame_size`](HostClientConfig::max_frame_size) limits the size of
+    /// received frames, and defaults to 1 MiB.
+    ///
+    /// See [`HostClient::new_with_framing`] for more details
+    pub fn new_with_framing_and_config<WTX, WRX, F, WSP>(
+        tx: WTX,
+        rx: WRX,
+        framing: F,
+        sp: WSP,
+        config: &HostClientConfig<'_>,
+    ) -> Self
+    where
+        WTX: WireTx,
+        WRX: WireRx,
+        F: Framing + Clone + Send + 'static,
+        WSP: WireSpawn,
+    {
+        let max_frame_len = config.max_frame_size.unwrap_or(MAX_FRAME_LEN);
+        HostClient::new_with_wire_and_config(
+            FramedWireTx {
+                tx,
+                framing: framing.clone(),
+            },
+            FramedWireRx {
+                rx,
+                framing,
+                buf: vec![0; max_frame_len].into_boxed_slice(),
+                filled: 0,
+                pending: VecDeque::new(),
+            },
+            sp,
+            config,
+        )
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// Wire Interface Implementation
+//////////////////////////////////////////////////////////////////////////////
+
+/// Encodes each frame before sending it
+struct FramedWireTx<Tx, F> {
+    tx: Tx,
+    framing: F,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum FramedWireTxError<E> {
+    #[error("Transport error: {0}")]
+    Inner(E),
+    #[error("Frame too large to encode")]
+    TooLarge,
+}
+
+impl<Tx, F> WireTx for FramedWireTx<Tx, F>
+where
+    Tx: WireTx,
+    F: Framing + Send + 'static,
+{
+    type Error = FramedWireTxError<Tx::Error>;
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), Self::Error> {
+        let mut out = vec![0; self.framing.max_encoded_len(data.len())];
+        let used = self
+            .framing
+            .encode(&data, &mut out)
+            .ok_or(FramedWireTxError::TooLarge)?;
+        out.truncate(used);
+        self.tx.send(out).await.map_err(FramedWireTxError::Inner)
+    }
+}
+
+/// Decodes frames from the received chunks
+struct FramedWireRx<Rx, F> {
+    rx: Rx,
+    framing: F,
+    buf: Box<[u8]>,
+    filled: usize,
+    pending: VecDeque<Vec<u8>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum FramedWireRxError<E> {
+    #[error("Transport error: {0}")]
+    Inner(E),
+}
+
+impl<Rx, F> WireRx for FramedWireRx<Rx, F>
+where
+    Rx: WireRx,
+    F: Framing + Send + 'static,
+{
+    type Error = FramedWireRxError<Rx::Error>;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        // Receive until we've gotten AT LEAST one frame, though we will continue
+        // decoding the rest of the chunk, to ensure no frames are lost.
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Ok(frame);
+            }
+
+            let chunk = self.rx.receive().await.map_err(FramedWireRxError::Inner)?;
+            let mut window = chunk.as_slice();
+            while !window.is_empty() {
+                window = match self.framing.decode(&mut self.buf, &mut self.filled, window) {
+                    FeedResult::Consumed => break,
+                    // Ignore line errors
+                    FeedResult::OverFull(new_wind) => {
+                        tracing::warn!("Overflowed frame accumulator");
+                        new_wind
+                    }
+                    FeedResult::DeserError(new_wind) => {
+                        tracing::warn!("Frame decoding error");
+                        new_wind
+                    }
+                    FeedResult::Success { data, remaining } => {
+                        self.pending.push_back(data.to_vec());
+                        remaining
+                    }
+                };
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Monitoring the health of the connection with periodic pings
+
+use core::time::Duration;
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::{
+    select,
+    sync::watch,
+    time::{interval, timeout, MissedTickBehavior},
+};
+
+use crate::host_client::HostClient;
+
+/// The health of the connection to the device, as seen by pings
+///
+/// See [`HostClient::monitor_health()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Pings are answered in time
+    Connected,
+    /// Pings are answered slowly, or some were missed
+    Degraded,
+    /// Too many pings in a row were missed, or the client was closed
+    Lost,
+}
+
+/// The thresholds of [`HostClient::monitor_health()`]
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// The time between pings
+    pub interval: Duration,
+    /// The time to wait for the reply to a ping, before counting it as missed
+    pub timeout: Duration,
+    /// The connection is [`Health::Degraded`] if the round trip time of a ping
+    /// exceeds this
+    pub degraded_rtt: Duration,
+    /// The connection is [`Health::Degraded`] after this many missed pings in a row
+    pub degraded_after: u32,
+    /// The connection is [`Health::Lost`] after this many missed pings in a row
+    pub lost_after: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            degraded_rtt: Duration::from_millis(100),
+            degraded_after: 1,
+            lost_after: 3,
+        }
+    }
+}
+
+impl HealthConfig {
+    /// The health after `missed` pings in a row were missed, or after a ping
+    /// answered in `rtt`
+    pub fn classify(&self, missed: u32, rtt: Option<Duration>) -> Health {
+        if missed >= self.lost_after {
+            Health::Lost
+        } else if missed >= self.degraded_after || rtt.is_some_and(|rtt| rtt > self.degraded_rtt) {
+            Health::Degraded
+        } else {
+            Health::Connected
+        }
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema + Send + 'static,
+{
+    /// Periodically ping the device, and observe the health of the connection
+    ///
+    /// The device is pinged with [`HostClient::ping()`] every
+    /// [`interval`](HealthConfig::interval), starting immediately. The returned
+    /// receiver starts as [`Health::Connected`], and is notified whenever the
+    /// health changes, as classified by [`HealthConfig::classify()`]. Pings that
+    /// fail, e.g. while a client created with [`HostClient::new_with_reconnect()`]
+    /// is reconnecting, count as missed.
+    ///
+    /// The pings are sent by a background task, which ends when all receivers are
+    /// dropped, or when the client is closed, after changing the health to
+    /// [`Health::Lost`].
+    ///
+    /// This function must be called from within a tokio runtime.
+    pub fn monitor_health(&self, config: HealthConfig) -> watch::Receiver<Health> {
+        let (tx, rx) = watch::channel(Health::Connected);
+        let client = self.clone();
+        drop(tokio::task::spawn(async move {
+            select! {
+                _ = health_worker(&client, &config, &tx) => {}
+                _ = client.wait_closed() => {
+                    tx.send_replace(Health::Lost);
+                }
+                _ = tx.closed() => {}
+            }
+        }));
+        rx
+    }
+}
+
+async fn health_worker<WireErr>(
+    client: &HostClient<WireErr>,
+    config: &HealthConfig,
+    tx: &watch::Sender<Health>,
+) where
+    WireErr: DeserializeOwned + Schema,
+{
+    let mut ticker = interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut missed = 0u32;
+    loop {
+        ticker.tick().await;
+        let rtt = match timeout(config.timeout, client.ping()).await {
+            Ok(Ok(rtt)) => {
+                missed = 0;
+                Some(rtt)
+            }
+            Ok(Err(_)) | Err(_) => {
+                missed = missed.saturating_add(1);
+                None
+            }
+        };
+        let health = config.classify(missed, rtt);
+        tx.send_if_modified(|old| core::mem::replace(old, health) != health);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+
+    use super::{Health, HealthConfig};
+
+    #[test]
+    fn classify() {
+        let config = HealthConfig::default();
+        let fast = Some(Duration::from_millis(5));
+        let slow = Some(Duration::from_millis(500));
+        assert_eq!(config.classify(0, fast), Health::Connected);
+        assert_eq!(config.classify(0, slow), Health::Degraded);
+        assert_eq!(config.classify(1, None), Health::Degraded);
+        assert_eq!(config.classify(2, None), Health::Degraded);
+        assert_eq!(config.classify(3, None), Health::Lost);
+    }
+}
@@ -5,13 +5,15 @@
 
 use core::time::Duration;
 use std::{
-    collections::HashSet,
-    future::Future,
+    collections::{HashMap, HashSet},
+    future::{poll_fn, Future},
     marker::PhantomData,
+    pin::Pin,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc, RwLock,
     },
+    task::Poll,
 };
 use thiserror::Error;
 
@@ -23,35 +25,103 @@ use postcard_schema::{schema::owned::OwnedNamedType, Schema};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{
     select,
-    sync::{broadcast, mpsc, Mutex},
+    sync::{broadcast, mpsc, watch, Mutex, Semaphore},
 };
-use util::Subscriptions;
+use util::{BoundedQueue, BoundedSender, StreamSender, Subscriptions};
 
 use crate::{
     header::{VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
-    standard_icd::{GetAllSchemaDataTopic, GetAllSchemasEndpoint, OwnedSchemaData},
-    Endpoint, Key, Topic, TopicDirection,
+    standard_icd::{
+        CancelTopic, DeviceInfoEndpoint, EndpointStats, GetAllSchemaDataTopic,
+        GetAllSchemasEndpoint, GetCreditsEndpoint, GetEndpointInfoEndpoint, GetKeysEndpoint,
+        GetMetricsEndpoint, GrantCapsEndpoint, LogTopic, OwnedCapRequest, OwnedDeviceInfo,
+        OwnedDeviceKeys, OwnedEndpointInfo, OwnedLogRecord, OwnedSchemaData, PingEndpoint,
+        SubscribeTopic, UnsubscribeTopic, WireError, ERROR_KEY, STREAM_END_KEY,
+    },
+    Endpoint, EndpointMap, FrameDirection, Key, Topic, TopicDirection, TopicMap,
 };
 
-use self::util::Stopper;
-pub use crate::host_client::util::HostClientConfig;
+use self::{callback::PendingCallback, frame_pool::FramePool, unmatched::Unmatched, util::Stopper};
+pub use crate::host_client::device_request::DeviceRequestServer;
+pub use crate::host_client::frame_pool::PooledFrame;
+pub use crate::host_client::retry::{RetryOn, RetryPolicy};
+pub use crate::host_client::unmatched::{UnmatchedHookFn, UnmatchedKind, UnmatchedReply};
+pub use crate::host_client::util::{HostClientBuilder, HostClientConfig, HostClientConfigError};
+
+#[cfg(not(target_family = "wasm"))]
+pub use crate::host_client::reconnect::ReconnectConfig;
+
+#[cfg(not(target_family = "wasm"))]
+pub use crate::host_client::health::{Health, HealthConfig};
 
 #[cfg(all(feature = "raw-nusb", not(target_family = "wasm")))]
 mod raw_nusb;
 
+#[cfg(all(feature = "raw-nusb", not(target_family = "wasm")))]
+pub use crate::host_client::raw_nusb::{UsbDevice, UsbFilter};
+
 #[cfg(all(feature = "cobs-serial", not(target_family = "wasm")))]
 mod serial;
 
+#[cfg(all(feature = "tcp", not(target_family = "wasm")))]
+mod tcp;
+
+#[cfg(all(feature = "framing", not(target_family = "wasm")))]
+mod framed;
+
+#[cfg(feature = "upload")]
+mod upload;
+
+#[cfg(feature = "upload")]
+pub use crate::host_client::upload::ChunkedUpload;
+
+#[cfg(all(feature = "rtt-host", not(target_family = "wasm")))]
+mod rtt;
+
+#[cfg(all(feature = "rtt-host", not(target_family = "wasm")))]
+pub use crate::host_client::rtt::{RttLink, DEFAULT_RTT_POLL};
+
 #[cfg(all(feature = "webusb", target_family = "wasm"))]
 pub mod webusb;
 
+pub mod codec;
+
+#[cfg(not(target_family = "wasm"))]
+pub mod record;
+
+#[cfg(not(target_family = "wasm"))]
+pub mod router;
+
 pub(crate) mod util;
 
+#[cfg(not(target_family = "wasm"))]
+mod reconnect;
+
+#[cfg(not(target_family = "wasm"))]
+mod health;
+
+mod retry;
+
+mod callback;
+
+mod unmatched;
+
+mod device_request;
+
+mod frame_pool;
+
+#[cfg(not(target_family = "wasm"))]
+pub mod channels;
+
 #[cfg(feature = "test-utils")]
 pub mod test_channels;
 
 /// Host Error Kind
+///
+/// New kinds of errors may be added in minor versions, so matches need a
+/// wildcard arm.
 #[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
 pub enum HostErr<WireErr> {
     /// An error of the user-specified wire error type
     #[error("a wire error occurred")]
@@ -69,6 +139,71 @@ pub enum HostErr<WireErr> {
     /// The interface has been closed, and no further messages are possible
     #[error("the interface has been closed, and no further messages are possible")]
     Closed,
+    /// The connection to the device was lost while the request was in-flight,
+    /// or no connection was available when the request was made.
+    ///
+    /// This is only returned by clients that automatically reconnect, see
+    /// [`HostClient::new_with_reconnect()`]. A later request may succeed.
+    #[error("the connection to the device was lost")]
+    Disconnected,
+    /// The request was too large for the receive buffer of the device
+    ///
+    /// Reported by devices using the standard [`WireError`], see
+    /// [`WireError::BodyTooLarge`]. Also returned without sending the request if
+    /// it exceeds the [`Endpoint::MAX_REQ_SIZE`] of the endpoint, or the limit of
+    /// the device, see [`HostClient::set_max_request_body()`]. The request may be
+    /// retried with a smaller body, or the data may be sent with multiple requests
+    /// instead.
+    #[error("the request was too large for the device, the body may be at most {max} bytes")]
+    BodyTooLarge {
+        /// The largest request body the device can receive
+        max: u32,
+    },
+    /// No reply was received in time, see [`HostClient::send_resp_timeout()`]
+    #[error("no reply was received in time")]
+    Timeout,
+    /// The handler on the device faulted, e.g. panicked, while handling the request
+    ///
+    /// Reported by devices using the standard [`WireError`], see
+    /// [`WireError::HandlerFault`]. The request may or may not have taken effect.
+    #[error(
+        "the handler faulted while handling the request, with message hash {message_hash:#010x}"
+    )]
+    HandlerFault {
+        /// The [`fault_hash()`](crate::standard_icd::fault_hash) of the fault message
+        message_hash: u32,
+    },
+    /// The endpoint requires a capability that was not granted to the client
+    ///
+    /// Reported by devices using the standard [`WireError`], see
+    /// [`WireError::Unauthorized`] and [`HostClient::grant_caps()`].
+    #[error("the endpoint requires a capability that was not granted")]
+    Unauthorized,
+    /// No sequence number was free for the request, as all sequence numbers of
+    /// the [`SeqNoGenerator`] are in use by pending requests
+    ///
+    /// Returned without sending the request. A later request may succeed, once
+    /// pending requests complete.
+    #[error("all sequence numbers are in use by pending requests")]
+    SeqNoExhausted,
+}
+
+/// Decode an error reply, mapping standard errors that have a dedicated [HostErr] variant
+fn decode_wire_err<WireErr: DeserializeOwned>(err_key: Key, body: &[u8]) -> HostErr<WireErr> {
+    if err_key == ERROR_KEY {
+        match postcard::from_bytes::<WireError>(body) {
+            Ok(WireError::BodyTooLarge { max }) => return HostErr::BodyTooLarge { max },
+            Ok(WireError::HandlerFault { message_hash, .. }) => {
+                return HostErr::HandlerFault { message_hash }
+            }
+            Ok(WireError::Unauthorized) => return HostErr::Unauthorized,
+            _ => {}
+        }
+    }
+    match postcard::from_bytes::<WireErr>(body) {
+        Ok(e) => HostErr::Wire(e),
+        Err(e) => HostErr::Postcard(e),
+    }
 }
 
 impl<T> From<WaitError> for HostErr<T> {
@@ -77,6 +212,41 @@ impl<T> From<WaitError> for HostErr<T> {
     }
 }
 
+/// The error returned by [`HostClient::send_resp_fallible()`]
+#[derive(Debug, PartialEq, Error)]
+pub enum EndpointErr<E, WireErr> {
+    /// The handler replied with an application error
+    #[error("the endpoint replied with an error")]
+    App(E),
+    /// The request failed, see [HostErr]
+    #[error(transparent)]
+    Host(#[from] HostErr<WireErr>),
+}
+
+/// A response that is either a value or an application error
+///
+/// This is implemented for [`Result`], and is used by
+/// [`HostClient::send_resp_fallible()`] for endpoints with a response of type
+/// `Result<T, E>`.
+pub trait FallibleResponse {
+    /// The type of successful responses
+    type Ok;
+    /// The type of application errors
+    type Error;
+
+    /// Convert into a [`Result`]
+    fn into_result(self) -> Result<Self::Ok, Self::Error>;
+}
+
+impl<T, E> FallibleResponse for Result<T, E> {
+    type Ok = T;
+    type Error = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
 /// Wire Transmit Interface
 ///
 /// Responsible for taking a serialized frame (including header and payload),
@@ -108,6 +278,18 @@ pub trait WireRx: 'static {
     type Error: std::error::Error; // or std?
     /// Receive a single frame
     fn receive(&mut self) -> impl Future<Output = Result<Vec<u8>, Self::Error>>;
+
+    /// Receive a single frame into `buf`, replacing its contents
+    ///
+    /// `buf` is taken from the frame pool of the client, so implementations
+    /// should write into it instead of allocating. The default implementation
+    /// replaces `buf` with the frame returned by [`receive()`](Self::receive).
+    fn receive_into(&mut self, buf: &mut Vec<u8>) -> impl Future<Output = Result<(), Self::Error>> {
+        async move {
+            *buf = self.receive().await?;
+            Ok(())
+        }
+    }
 }
 
 /// Wire Spawn Interface
@@ -150,6 +332,21 @@ pub trait WireRx: Send + 'static {
     type Error: std::error::Error;
     /// Receive a single frame
     fn receive(&mut self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send;
+
+    /// Receive a single frame into `buf`, replacing its contents
+    ///
+    /// `buf` is taken from the frame pool of the client, so implementations
+    /// should write into it instead of allocating. The default implementation
+    /// replaces `buf` with the frame returned by [`receive()`](Self::receive).
+    fn receive_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            *buf = self.receive().await?;
+            Ok(())
+        }
+    }
 }
 
 /// Wire Spawn Interface
@@ -169,10 +366,11 @@ pub trait WireSpawn: 'static {
 ///
 /// [HostClient]s can be cloned, and used across multiple tasks/threads.
 ///
-/// There are currently two ways to create one, based on the transport used:
+/// There are currently three ways to create one, based on the transport used:
 ///
 /// 1. With raw USB Bulk transfers: [`HostClient::new_raw_nusb()`] (**recommended**)
 /// 2. With cobs CDC-ACM transfers: [`HostClient::new_serial_cobs()`]
+/// 3. With length-prefixed TCP frames: [`HostClient::new_tcp()`], e.g. for simulators
 pub struct HostClient<WireErr> {
     ctx: Arc<HostContext>,
     out: mpsc::Sender<RpcFrame>,
@@ -180,6 +378,8 @@ pub struct HostClient<WireErr> {
     err_key: Key,
     stopper: Stopper,
     seq_kind: VarSeqKind,
+    default_timeout: Option<Duration>,
+    subscription_depth: usize,
     _pd: PhantomData<fn() -> WireErr>,
 }
 
@@ -198,15 +398,39 @@ where
     pub(crate) fn new_manual_priv(config: &HostClientConfig) -> (Self, WireContext) {
         let (tx_pc, rx_pc) = tokio::sync::mpsc::channel(config.outgoing_depth);
 
+        let err_key = Key::for_path::<WireErr>(config.err_uri_path);
+
         let ctx = Arc::new(HostContext {
             kkind: RwLock::new(VarKeyKind::Key8),
             map: WaitMap::new(),
-            seq: AtomicU32::new(0),
+            callbacks: std::sync::Mutex::new(HashMap::new()),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            seq: match &config.seq_no_generator {
+                Some(gen) => gen.clone(),
+                None => Arc::new(CounterSeqNoGenerator::new()),
+            },
             subscription_timeout: config.subscriber_timeout_if_full,
+            conn: watch::channel(ConnectionState::Connected).0,
+            credits: RwLock::new(None),
+            tap: RwLock::new(None),
+            retry: RwLock::new(None),
+            deadlines: std::sync::Mutex::new(None),
+            pool: Arc::new(FramePool::new(config.frame_pool)),
+            unmatched: Unmatched::new(),
+            max_body: RwLock::new(None),
+            #[cfg(feature = "auth")]
+            auth: RwLock::new(None),
+            #[cfg(feature = "checksum")]
+            checksum: std::sync::atomic::AtomicBool::new(false),
+            #[cfg(feature = "compress")]
+            compression: RwLock::new(None),
+            #[cfg(feature = "fragment")]
+            fragment: RwLock::new(None),
+            #[cfg(feature = "fragment")]
+            fragment_id: std::sync::atomic::AtomicU16::new(0),
+            err_key,
         });
 
-        let err_key = Key::for_path::<WireErr>(config.err_uri_path);
-
         let me = HostClient {
             ctx: ctx.clone(),
             out: tx_pc,
@@ -215,6 +439,8 @@ where
             subscriptions: Arc::new(Mutex::new(Subscriptions::default())),
             stopper: Stopper::new(),
             seq_kind: config.seq_kind,
+            default_timeout: config.default_timeout,
+            subscription_depth: config.subscription_depth,
         };
 
         let wire = WireContext {
@@ -258,9 +484,272 @@ impl<WireErr> HostClient<WireErr>
 where
     WireErr: DeserializeOwned + Schema,
 {
+    /// Send a [`PingEndpoint`] request, and measure the round trip time
+    ///
+    /// Servers using [`define_dispatch!`](crate::define_dispatch) reply to pings
+    /// automatically, so this is useful for checking that the device (and the link
+    /// to it) is still responsive. Returns [`HostErr::BadResponse`] if the reply does
+    /// not echo the request.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn ping(&self) -> Result<Duration, HostErr<WireErr>> {
+        let nonce = self.ctx.seq.next();
+        let start = std::time::Instant::now();
+        let resp = self.send_resp::<PingEndpoint>(&nonce).await?;
+        let rtt = start.elapsed();
+        if resp != nonce {
+            return Err(HostErr::BadResponse);
+        }
+        Ok(rtt)
+    }
+
+    /// Obtain the set of keys supported by the connected device
+    ///
+    /// This is much cheaper than [`HostClient::get_schema_report()`], and is useful
+    /// for checking that the device supports the endpoints and topics the host
+    /// was compiled with, e.g. using [`OwnedDeviceKeys::missing_endpoints()`].
+    pub async fn get_device_map(&self) -> Result<OwnedDeviceKeys, HostErr<WireErr>> {
+        self.send_resp::<GetKeysEndpoint>(&()).await
+    }
+
+    /// Obtain the firmware information of the connected device
+    ///
+    /// Answered by all servers using [`define_dispatch!`](crate::define_dispatch),
+    /// see [`DeviceInfoEndpoint`].
+    pub async fn device_info(&self) -> Result<OwnedDeviceInfo, HostErr<WireErr>> {
+        self.send_resp::<DeviceInfoEndpoint>(&()).await
+    }
+
+    /// Obtain the description of each endpoint of the connected device
+    ///
+    /// Queries the [`GetEndpointInfoEndpoint`] once per endpoint, which is answered
+    /// by all servers using [`define_dispatch!`](crate::define_dispatch). Whether
+    /// the device has the same types as the host can be checked by comparing the
+    /// schema hashes with [`schema_hash()`](crate::standard_icd::schema_hash).
+    pub async fn get_endpoint_info(&self) -> Result<Vec<OwnedEndpointInfo>, HostErr<WireErr>> {
+        let mut all = vec![];
+        while let Some(info) = self
+            .send_resp::<GetEndpointInfoEndpoint>(&(all.len() as u32))
+            .await?
+        {
+            all.push(info);
+        }
+        Ok(all)
+    }
+
+    /// Obtain the request counters of each endpoint of the connected device
+    ///
+    /// Queries the [`GetMetricsEndpoint`] once per endpoint. Servers only answer
+    /// this request when built with the `metrics` feature.
+    pub async fn get_metrics(&self) -> Result<Vec<EndpointStats>, HostErr<WireErr>> {
+        let mut all = vec![];
+        while let Some(stats) = self
+            .send_resp::<GetMetricsEndpoint>(&(all.len() as u32))
+            .await?
+        {
+            all.push(stats);
+        }
+        Ok(all)
+    }
+
+    /// Request the capabilities in `caps` from the connected device
+    ///
+    /// `proof` is checked by the `grant_caps` handler of the device, e.g. a password
+    /// or the answer to a challenge. Returns the mask of all capabilities granted
+    /// to this connection, or [`HostErr::Unauthorized`] if the request was denied.
+    pub async fn grant_caps(&self, caps: u32, proof: &[u8]) -> Result<u32, HostErr<WireErr>> {
+        let req = OwnedCapRequest {
+            caps,
+            proof: proof.to_vec(),
+        };
+        self.send_resp::<GrantCapsEndpoint>(&req).await
+    }
+
+    /// Limit the number of outstanding requests to what the device can buffer
+    ///
+    /// Queries the [`GetCreditsEndpoint`], and from then on waits before sending
+    /// a request if the device has not yet replied to as many requests as it
+    /// advertised. A credit is returned once the reply (or an error) is received.
+    /// Returns the number of credits.
+    ///
+    /// Servers only answer this request when configured with `max_in_flight` in
+    /// [`define_dispatch!`](crate::define_dispatch). Topic messages do not consume
+    /// credits, as the device never acknowledges them.
+    ///
+    /// The credits are not renegotiated automatically, so this should be called
+    /// again after reconnecting to a different device.
+    pub async fn enable_flow_control(&self) -> Result<u32, HostErr<WireErr>> {
+        let credits = self.send_resp::<GetCreditsEndpoint>(&()).await?;
+        // A device advertising zero credits could never be sent a request
+        let permits = credits.max(1) as usize;
+        *self.ctx.credits.write().unwrap() = Some(Arc::new(Semaphore::new(permits)));
+        Ok(credits)
+    }
+
+    /// Stop limiting the number of outstanding requests
+    ///
+    /// See [`HostClient::enable_flow_control()`].
+    pub fn disable_flow_control(&self) {
+        *self.ctx.credits.write().unwrap() = None;
+    }
+
+    /// Set the largest request body the device can receive, or forget it if
+    /// `max` is `None`
+    ///
+    /// Larger requests are rejected with [`HostErr::BodyTooLarge`] before they
+    /// are sent, like requests exceeding [`Endpoint::MAX_REQ_SIZE`]. The limit is
+    /// also learned from the first [`WireError::BodyTooLarge`] reply of the
+    /// device, and kept until changed here, e.g. after connecting to another
+    /// device.
+    pub fn set_max_request_body(&self, max: Option<u32>) {
+        *self.ctx.max_body.write().unwrap() = max;
+    }
+
+    /// The largest request body the device can receive, if known
+    ///
+    /// See [`HostClient::set_max_request_body()`].
+    pub fn max_request_body(&self) -> Option<u32> {
+        *self.ctx.max_body.read().unwrap()
+    }
+
+    /// Reject a request body of `len` bytes if it exceeds the
+    /// [`Endpoint::MAX_REQ_SIZE`] of `E`, or the limit of the device
+    fn check_request_size<E: Endpoint>(&self, len: usize) -> Result<(), HostErr<WireErr>> {
+        let device = self.max_request_body().map(|max| max as usize);
+        let max = match (E::MAX_REQ_SIZE, device) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(max), None) | (None, Some(max)) => max,
+            (None, None) => return Ok(()),
+        };
+        if len > max {
+            Err(HostErr::BodyTooLarge {
+                max: u32::try_from(max).unwrap_or(u32::MAX),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Draw and claim the sequence number for a new request, skipping numbers
+    /// still in use by pending requests, see [`SeqNoGenerator`]
+    fn next_seq(&self) -> Result<PendingSeq, HostErr<WireErr>> {
+        self.ctx.next_seq().ok_or(HostErr::SeqNoExhausted)
+    }
+
+    /// Observe every frame sent or received by this client
+    ///
+    /// `tap` is called with each full frame (header and body), just before it is
+    /// sent, and just after it is received, including frames that are not handled
+    /// by anyone. It is called from the I/O workers, so it should return quickly.
+    /// Replaces any previously set tap.
+    ///
+    /// ```rust,no_run
+    /// # use postcard_rpc::{host_client::HostClient, standard_icd::WireError, FrameDirection};
+    /// # fn example(client: &HostClient<WireError>) {
+    /// client.set_wire_tap(|dir, frame| match dir {
+    ///     FrameDirection::Outgoing => println!("--> {frame:02X?}"),
+    ///     FrameDirection::Incoming => println!("<-- {frame:02X?}"),
+    /// });
+    /// # }
+    /// ```
+    pub fn set_wire_tap<F>(&self, tap: F)
+    where
+        F: Fn(FrameDirection, &[u8]) + Send + Sync + 'static,
+    {
+        *self.ctx.tap.write().unwrap() = Some(Arc::new(tap));
+    }
+
+    /// Remove the tap set with [`HostClient::set_wire_tap()`]
+    pub fn clear_wire_tap(&self) {
+        *self.ctx.tap.write().unwrap() = None;
+    }
+
+    /// Authenticate all frames sent or received by this client, or stop
+    /// authenticating frames if `auth` is `None`
+    ///
+    /// Each outgoing frame gets an authentication tag, and incoming frames with
+    /// an invalid tag are dropped. The server must use the same key and tag length,
+    /// see the [`auth`](crate::auth) module.
+    ///
+    /// **Requires feature**: `auth`
+    #[cfg(feature = "auth")]
+    pub fn set_auth(&self, auth: Option<crate::auth::Authenticator>) {
+        *self.ctx.auth.write().unwrap() = auth;
+    }
+
+    /// Append a checksum to all frames sent by this client, and check the checksum
+    /// of all frames received, or stop if `enabled` is false
+    ///
+    /// Incoming frames with an invalid checksum are dropped. The server must use
+    /// checksums too, see the [`checksum`](crate::checksum) module.
+    ///
+    /// **Requires feature**: `checksum`
+    #[cfg(feature = "checksum")]
+    pub fn set_checksum(&self, enabled: bool) {
+        self.ctx.checksum.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Send the timeout of each request to the server as its deadline, or stop
+    /// sending deadlines
+    ///
+    /// Requests made with a timeout, either from
+    /// [`default_timeout`](crate::host_client::HostClientConfig::default_timeout)
+    /// or [`send_resp_timeout()`](Self::send_resp_timeout), carry the time left
+    /// until the timeout in their header, see the [`header`](crate::header) module.
+    /// Handlers on the server may check this [`Deadline`](crate::server::Deadline)
+    /// to stop working on requests the client no longer waits for.
+    ///
+    /// Deadlines are disabled by default, as servers that don't support them reject
+    /// these requests with [`WireError::ProtocolVersionMismatch`]. Only requests
+    /// sent by the I/O workers of the client carry deadlines, custom I/O tasks using
+    /// a [`WireContext`] send requests without them.
+    pub fn set_deadline_propagation(&self, enabled: bool) {
+        *self.ctx.deadlines.lock().unwrap() = enabled.then(HashMap::new);
+    }
+
+    /// Compress and decompress frames with `compression`, or stop compressing
+    /// frames if `compression` is `None`
+    ///
+    /// Outgoing frames are compressed if their body is at least as large as the
+    /// [`threshold`](crate::compress::Compression::threshold), and incoming frames
+    /// are decompressed if they are compressed. The server must use the same
+    /// compressor, see the [`compress`](crate::compress) module.
+    ///
+    /// **Requires feature**: `compress`
+    #[cfg(feature = "compress")]
+    pub fn set_compression(&self, compression: Option<crate::compress::Compression>) {
+        *self.ctx.compression.write().unwrap() = compression;
+    }
+
+    /// Split outgoing frames larger than `max_len` bytes into fragments, or stop
+    /// splitting frames if `max_len` is `None`
+    ///
+    /// Incoming fragments are always reassembled. The server must reassemble
+    /// fragments too, see the [`fragment`](crate::fragment) module.
+    ///
+    /// **Requires feature**: `fragment`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_len` is less than [`MIN_FRAGMENT_LEN`](crate::fragment::MIN_FRAGMENT_LEN).
+    #[cfg(feature = "fragment")]
+    pub fn set_fragmentation(&self, max_len: Option<usize>) {
+        if let Some(max_len) = max_len {
+            assert!(
+                max_len >= crate::fragment::MIN_FRAGMENT_LEN,
+                "Fragments must be at least MIN_FRAGMENT_LEN bytes"
+            );
+        }
+        *self.ctx.fragment.write().unwrap() = max_len;
+    }
+
     /// Obtain a [`SchemaReport`] describing the connected device
     pub async fn get_schema_report(&self) -> Result<SchemaReport, SchemaError<WireErr>> {
-        let Ok(mut sub) = self.subscribe_multi::<GetAllSchemaDataTopic>(64).await else {
+        let Ok(mut sub) = self
+            .subscribe_multi::<GetAllSchemaDataTopic>(self.subscription_depth)
+            .await
+        else {
             return Err(SchemaError::Comms(HostErr::Closed));
         };
 
@@ -334,7 +823,15 @@ where
     /// Send a message of type [Endpoint::Request][Endpoint] to `path`, and await
     /// a response of type [Endpoint::Response][Endpoint] (or WireErr) to `path`.
     ///
-    /// This function will wait potentially forever. Consider using with a timeout.
+    /// This function will wait potentially forever, unless a
+    /// [`default_timeout`](HostClientConfig::default_timeout) was configured.
+    /// Consider using [`send_resp_timeout()`](Self::send_resp_timeout) instead.
+    ///
+    /// Failed requests may be retried, see [`set_retry_policy()`](Self::set_retry_policy).
+    ///
+    /// Requests larger than the [`Endpoint::MAX_REQ_SIZE`] of the endpoint, or than
+    /// the device can receive, are rejected with [`HostErr::BodyTooLarge`] without
+    /// being sent, see [`set_max_request_body()`](Self::set_max_request_body).
     pub async fn send_resp<E: Endpoint>(
         &self,
         t: &E::Request,
@@ -343,31 +840,324 @@ where
         E::Request: Serialize + Schema,
         E::Response: DeserializeOwned + Schema,
     {
-        let seq_no = self.ctx.seq.fetch_add(1, Ordering::Relaxed);
+        self.send_resp_inner::<E>(t, self.default_timeout).await
+    }
+
+    /// Send a message like [`send_resp()`](Self::send_resp), waiting at most
+    /// `timeout` for the response
+    ///
+    /// Returns [`HostErr::Timeout`] if no reply was received in time. The request
+    /// then no longer waits for a reply, so a reply arriving later is dropped, and
+    /// is never matched to a later request reusing the same sequence number.
+    pub async fn send_resp_timeout<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        timeout: Duration,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        self.send_resp_inner::<E>(t, Some(timeout)).await
+    }
+
+    async fn send_resp_inner<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        timeout: Option<Duration>,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let policy = self.retry_policy_for::<E>();
+        self.send_resp_retrying::<E>(t, timeout, policy.as_ref())
+            .await
+    }
 
+    /// Send a message like [`send_resp()`](Self::send_resp), sending it again up to
+    /// `retries` times if no reply is received within `timeout`
+    ///
+    /// Every attempt uses the same sequence number, so the server can recognize
+    /// repeated requests. Endpoints marked as `idempotent` in
+    /// [`define_dispatch!`][crate::define_dispatch] answer them with the reply they
+    /// already sent, instead of running the handler again. Other endpoints handle
+    /// every request that arrives, so a request may be handled more than once if
+    /// only its reply was lost.
+    ///
+    /// Returns [`HostErr::Timeout`] if none of the attempts received a reply in time.
+    pub async fn send_resp_retry<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        timeout: Duration,
+        retries: usize,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let pending = self.next_seq()?;
         let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
-        let frame = RpcFrame {
-            // NOTE: send_resp_raw automatically shrinks down key and sequence
-            // kinds to the appropriate amount
-            header: VarHeader {
-                key: VarKey::Key8(E::REQ_KEY),
-                seq_no: VarSeq::Seq4(seq_no),
-            },
-            body: msg,
+        self.check_request_size::<E>(msg.len())?;
+        for _ in 0..=retries {
+            let frame = RpcFrame {
+                header: VarHeader {
+                    key: VarKey::Key8(E::REQ_KEY),
+                    seq_no: VarSeq::Seq4(pending.seq_no()),
+                },
+                body: msg.clone(),
+            };
+            let res = self
+                .send_resp_raw_timeout(frame, E::RESP_KEY, Some(timeout))
+                .await;
+            match res {
+                Ok(frame) => return Ok(self.ctx.pool.decode::<E::Response>(frame.body)?),
+                Err(HostErr::Timeout) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(HostErr::Timeout)
+    }
+
+    /// Send many requests of type [Endpoint::Request][Endpoint] to `path` concurrently,
+    /// and await all of their responses
+    ///
+    /// Responses are returned in the same order as `reqs`, once all requests have
+    /// completed. This is much faster than calling [`send_resp()`](Self::send_resp)
+    /// in a loop when the link has high latency, as requests are sent without
+    /// waiting for the previous reply.
+    ///
+    /// At most [`BATCH_MAX_IN_FLIGHT`] requests are in-flight at once, further requests
+    /// are sent as earlier ones complete. All in-flight requests are queued by the
+    /// device's transport (e.g. USB endpoint or serial receive buffers) while it
+    /// handles them one at a time, and if the device can't buffer them, requests may
+    /// be dropped and never answered. Additionally, the sequence numbers of in-flight
+    /// requests must be unique, so [`VarSeqKind::Seq1`] clients should not have more
+    /// than 256 requests in flight, including those from other tasks.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_batch<E: Endpoint>(
+        &self,
+        reqs: &[E::Request],
+    ) -> Vec<Result<E::Response, HostErr<WireErr>>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        type BatchFut<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+        let mut results: Vec<Option<Result<E::Response, HostErr<WireErr>>>> =
+            reqs.iter().map(|_| None).collect();
+        let mut pending: Vec<(usize, BatchFut<'_, _>)> = vec![];
+        let mut next = 0;
+
+        poll_fn(|cx| loop {
+            while pending.len() < BATCH_MAX_IN_FLIGHT && next < reqs.len() {
+                pending.push((next, Box::pin(self.send_resp::<E>(&reqs[next]))));
+                next += 1;
+            }
+            let before = pending.len();
+            pending.retain_mut(|(idx, fut)| match fut.as_mut().poll(cx) {
+                Poll::Ready(res) => {
+                    results[*idx] = Some(res);
+                    false
+                }
+                Poll::Pending => true,
+            });
+            if pending.is_empty() && next == reqs.len() {
+                return Poll::Ready(());
+            }
+            // If nothing completed, we'll be woken when something does. Otherwise,
+            // start the next requests now.
+            if pending.len() == before {
+                return Poll::Pending;
+            }
+        })
+        .await;
+
+        results
+            .into_iter()
+            .map(|res| res.expect("All requests completed"))
+            .collect()
+    }
+
+    /// Like [`send_resp()`](Self::send_resp), but also returns a [`CancelHandle`]
+    /// that may be used to request cancellation of the request while it is in-flight.
+    ///
+    /// The request is not sent until the returned future is polled. Only endpoints
+    /// using the `cancellable` flavor of [`define_dispatch!`](crate::define_dispatch)
+    /// can be cancelled, and it is up to the handler how (and whether) it replies
+    /// after being cancelled, typically with
+    /// [`WireError::Cancelled`](crate::standard_icd::WireError::Cancelled).
+    pub fn send_resp_cancellable<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> (
+        CancelHandle<WireErr>,
+        impl Future<Output = Result<E::Response, HostErr<WireErr>>> + '_,
+    )
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let pending = self.ctx.next_seq();
+        let seq_no = pending.as_ref().map(|p| VarSeq::Seq4(p.seq_no()));
+
+        let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+        let handle = CancelHandle {
+            client: self.clone(),
+            seq_no,
+        };
+        let fut = async move {
+            let pending = pending.ok_or(HostErr::SeqNoExhausted)?;
+            let frame = RpcFrame {
+                header: VarHeader {
+                    key: VarKey::Key8(E::REQ_KEY),
+                    seq_no: VarSeq::Seq4(pending.seq_no()),
+                },
+                body: msg,
+            };
+            self.check_request_size::<E>(frame.body.len())?;
+            let frame = self.send_resp_raw(frame, E::RESP_KEY).await?;
+            let r = self.ctx.pool.decode::<E::Response>(frame.body)?;
+            Ok(r)
         };
-        let frame = self.send_resp_raw(frame, E::RESP_KEY).await?;
-        let r = postcard::from_bytes::<E::Response>(&frame.body)?;
-        Ok(r)
+        (handle, fut)
+    }
+
+    /// Like [`send_resp()`](Self::send_resp), but carries `meta` alongside the
+    /// request, and hands it back with the result.
+    ///
+    /// `meta` is never sent to the device, it is held with the pending request
+    /// until the reply (or an error) arrives. This is useful for attaching
+    /// routing information when many requests are in flight at once, e.g. in a
+    /// proxy, without keeping a separate map of sequence numbers.
+    ///
+    /// `meta` is returned even if the request failed.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_resp_with_meta<E: Endpoint, M>(
+        &self,
+        t: &E::Request,
+        meta: M,
+    ) -> (Result<E::Response, HostErr<WireErr>>, M)
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let res = self.send_resp::<E>(t).await;
+        (res, meta)
+    }
+
+    /// Like [`send_resp()`](Self::send_resp), for endpoints whose response is a
+    /// `Result<T, E>`, where `E` is an application error
+    ///
+    /// Application errors are sent by the handler as part of the response, e.g.
+    /// by an `async` handler returning `Result<T, E>`, and are returned as
+    /// [`EndpointErr::App`]. Errors of the transport or dispatcher, sent as `WireErr`,
+    /// are returned as [`EndpointErr::Host`] instead.
+    ///
+    /// ```rust,ignore
+    /// pub type SetResult = Result<(), SetError>;
+    ///
+    /// endpoints! {
+    ///     list = ENDPOINT_LIST;
+    ///     | EndpointTy    | RequestTy | ResponseTy | Path  |
+    ///     | ----------    | --------- | ---------- | ----  |
+    ///     | SetEndpoint   | SetReq    | SetResult  | "set" |
+    /// }
+    ///
+    /// match client.send_resp_fallible::<SetEndpoint>(&req).await {
+    ///     Ok(()) => {}
+    ///     Err(EndpointErr::App(SetError::OutOfRange)) => { /* ... */ }
+    ///     Err(EndpointErr::Host(e)) => { /* ... */ }
+    /// }
+    /// ```
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_resp_fallible<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<
+        <E::Response as FallibleResponse>::Ok,
+        EndpointErr<<E::Response as FallibleResponse>::Error, WireErr>,
+    >
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema + FallibleResponse,
+    {
+        self.send_resp::<E>(t)
+            .await?
+            .into_result()
+            .map_err(EndpointErr::App)
     }
 
     /// Perform an endpoint request/response,but without handling the
     /// Ser/De automatically
+    ///
+    /// This uses the [`default_timeout`](HostClientConfig::default_timeout), if any.
     pub async fn send_resp_raw(
+        &self,
+        rqst: RpcFrame,
+        resp_key: Key,
+    ) -> Result<RpcFrame, HostErr<WireErr>> {
+        self.send_resp_raw_timeout(rqst, resp_key, self.default_timeout)
+            .await
+    }
+
+    /// Perform a raw request/response, giving up after `timeout`, if any
+    ///
+    /// Dropping the request future on timeout removes its entry from the map of
+    /// pending replies.
+    async fn send_resp_raw_timeout(
+        &self,
+        rqst: RpcFrame,
+        resp_key: Key,
+        timeout: Option<Duration>,
+    ) -> Result<RpcFrame, HostErr<WireErr>> {
+        let _deadline = timeout.and_then(|t| self.ctx.track_deadline(rqst.header.seq_no, t));
+        let _completion = self
+            .ctx
+            .track_completion(rqst.header.seq_no, resp_key, self.err_key);
+        let fut = self.send_resp_raw_untimed(rqst, resp_key);
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .unwrap_or(Err(HostErr::Timeout)),
+            None => fut.await,
+        }
+    }
+
+    async fn send_resp_raw_untimed(
         &self,
         mut rqst: RpcFrame,
         resp_key: Key,
     ) -> Result<RpcFrame, HostErr<WireErr>> {
         let cancel_fut = self.stopper.wait_stopped();
+
+        // If flow control is enabled, hold a credit until we have received a reply
+        let credits = self.ctx.credits.read().unwrap().clone();
+        let _credit = match credits {
+            Some(sem) => select! {
+                _c = self.stopper.wait_stopped() => return Err(HostErr::Closed),
+                p = sem.acquire_owned() => Some(p.map_err(|_| HostErr::Closed)?),
+            },
+            None => None,
+        };
+
+        // Don't bother sending if we know there's nobody on the other side
+        let mut conn = self.ctx.conn.subscribe();
+        if *conn.borrow_and_update() == ConnectionState::Connecting {
+            return Err(HostErr::Disconnected);
+        }
+        // Any state change after this point means the connection we are sending
+        // on has gone away, even if we have already reconnected since
+        let disconn_fut = async {
+            conn.changed().await.ok()?;
+            let state = *conn.borrow();
+            Some(state)
+        };
+
         let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
         rqst.header.key.shrink_to(kkind);
         let mut resp_key = VarKey::Key8(resp_key);
@@ -375,6 +1165,10 @@ where
         resp_key.shrink_to(kkind);
         err_key.shrink_to(kkind);
 
+        // Keep the sequence number from being drawn for other requests while
+        // we are waiting for the reply
+        let _pending = self.ctx.hold_seq(rqst.header.seq_no.into());
+
         // Prepare to receive the reply, BEFORE we send the request.
         // This uses the `enqueue` feature of WaitMap, which makes sure that
         // our receiver is ready to "catch" before we even send the request.
@@ -402,7 +1196,7 @@ where
                 WaitError::Duplicate => {
                     tracing::error!("Attempted to register a duplicate wait for a reply. This can happen if sequence numbers are reused.");
                     // TODO: This is the wrong kind of error, but we don't want to report closed.
-                    // Fix this in the next breaking change of postcard-rpc, or make HostErr non-exhaustive
+                    // Fix this with a dedicated variant, now that HostErr is non-exhaustive
                     HostErr::BadResponse
                 }
 
@@ -417,8 +1211,10 @@ where
 
         self.out.send(rqst).await.map_err(|_| HostErr::Closed)?;
 
+        // A reply received right before the link dropped, e.g. acknowledging a
+        // reset, wins over the close
         select! {
-            _c = cancel_fut => Err(HostErr::Closed),
+            biased;
             o = ok_resp => {
                 let (hdr, resp) = o?;
                 if hdr.key.kind() != kkind {
@@ -431,12 +1227,128 @@ where
                 if hdr.key.kind() != kkind {
                     *self.ctx.kkind.write().unwrap() = hdr.key.kind();
                 }
-                let r = postcard::from_bytes::<WireErr>(&resp)?;
-                Err(HostErr::Wire(r))
+                let err = decode_wire_err(self.err_key, &resp);
+                if let HostErr::BodyTooLarge { max } = err {
+                    // Remember the limit, to reject larger requests before sending them
+                    *self.ctx.max_body.write().unwrap() = Some(max);
+                }
+                Err(err)
+            },
+            _c = cancel_fut => Err(HostErr::Closed),
+            d = disconn_fut => match d {
+                Some(ConnectionState::Closed) | None => Err(HostErr::Closed),
+                Some(_) => Err(HostErr::Disconnected),
             },
         }
     }
 
+    /// Send a message of type [Endpoint::Request][Endpoint] to `path`, and receive
+    /// a [ResponseStream] of [Endpoint::Response][Endpoint] chunks.
+    ///
+    /// This is intended for use with endpoints using the `stream` flavor of
+    /// [`define_dispatch!`](crate::define_dispatch), which may reply any number
+    /// of times before signalling the end of the stream.
+    ///
+    /// Returns an Error if the request could not be sent.
+    pub async fn send_resp_stream<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<ResponseStream<E::Response, WireErr>, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        // Don't bother sending if we know there's nobody on the other side
+        let mut conn = self.ctx.conn.subscribe();
+        if *conn.borrow_and_update() == ConnectionState::Connecting {
+            return Err(HostErr::Disconnected);
+        }
+
+        let pending = self.next_seq()?;
+        let seq_no = VarSeq::Seq4(pending.seq_no());
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(E::REQ_KEY);
+        key.shrink_to(kkind);
+
+        let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+        self.check_request_size::<E>(msg.len())?;
+        let frame = RpcFrame {
+            header: VarHeader { key, seq_no },
+            body: msg,
+        };
+
+        // Register the stream BEFORE we send the request, so we don't miss
+        // any early chunks
+        let (tx, rx) = mpsc::unbounded_channel();
+        {
+            let mut guard = self.subscriptions.lock().await;
+            if guard.stopped {
+                return Err(HostErr::Closed);
+            }
+            if guard.stream_list.iter().any(|s| s.seq_no == seq_no) {
+                tracing::error!("Attempted to register a duplicate response stream. This can happen if sequence numbers are reused.");
+                return Err(HostErr::BadResponse);
+            }
+            guard.stream_list.push(StreamSender {
+                seq_no,
+                keys: [E::RESP_KEY, STREAM_END_KEY, self.err_key],
+                tx,
+            });
+        }
+
+        let cancel_fut = self.stopper.wait_stopped();
+        let operate_fut = self.out.send(frame);
+        select! {
+            _ = cancel_fut => return Err(HostErr::Closed),
+            res = operate_fut => res.map_err(|_| HostErr::Closed)?,
+        }
+
+        Ok(ResponseStream {
+            rx,
+            ctx: self.ctx.clone(),
+            conn,
+            resp_key: E::RESP_KEY,
+            err_key: self.err_key,
+            done: false,
+            _pending: pending,
+            _pd: PhantomData,
+        })
+    }
+
+    /// Send a request of type [Endpoint::Request][Endpoint] to `path`, without
+    /// waiting for a response
+    ///
+    /// Returns once the frame is queued for sending. No reply is expected, so no
+    /// pending slot is registered, and there is no feedback whether the device
+    /// received or handled the request. This is meant for one-way commands, handled
+    /// with the `blocking_noreply` or `async_noreply` flavors of
+    /// [`define_dispatch!`](crate::define_dispatch), which never reply. Replies sent
+    /// anyway, e.g. errors for requests the device could not dispatch, are dropped
+    /// and counted as [unmatched](Self::unmatched_replies).
+    ///
+    /// Returns [`HostErr::BodyTooLarge`] without sending if the request exceeds the
+    /// [`Endpoint::MAX_REQ_SIZE`], or [`HostErr::Closed`] if the client is closed.
+    pub async fn send<E: Endpoint>(&self, t: &E::Request) -> Result<(), HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+    {
+        // Queueing may still succeed after closing, but the frame would never be sent
+        if self.is_closed() {
+            return Err(HostErr::Closed);
+        }
+        let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+        self.check_request_size::<E>(msg.len())?;
+        let pending = self.next_seq()?;
+        let frame = RpcFrame {
+            header: VarHeader {
+                key: VarKey::Key8(E::REQ_KEY),
+                seq_no: VarSeq::Seq4(pending.seq_no()),
+            },
+            body: msg,
+        };
+        self.publish_raw(frame).await.map_err(|_| HostErr::Closed)
+    }
+
     /// Publish a [Topic] [Message][Topic::Message].
     ///
     /// There is no feedback if the server received our message. If the I/O worker is
@@ -470,6 +1382,18 @@ where
         }
     }
 
+    /// The depth to use for a subscription requested with `depth`
+    ///
+    /// A depth of zero uses the [`subscription_depth`](HostClientConfig::subscription_depth)
+    /// of the config.
+    fn sub_depth(&self, depth: usize) -> usize {
+        if depth == 0 {
+            self.subscription_depth
+        } else {
+            depth
+        }
+    }
+
     ///////////////////////////////////////////////////////////////////////////
     // Subscribe Multi
     ///////////////////////////////////////////////////////////////////////////
@@ -514,7 +1438,7 @@ where
             {
                 entry.1.subscribe()
             } else {
-                let (tx, rx) = broadcast::channel(depth);
+                let (tx, rx) = broadcast::channel(self.sub_depth(depth));
                 guard.broadcast_list.push((T::TOPIC_KEY, tx));
                 rx
             }
@@ -553,7 +1477,7 @@ where
             if let Some(entry) = guard.broadcast_list.iter_mut().find(|(k, _)| *k == key) {
                 entry.1.subscribe()
             } else {
-                let (tx, rx) = broadcast::channel(depth);
+                let (tx, rx) = broadcast::channel(self.sub_depth(depth));
                 guard.broadcast_list.push((key, tx));
                 rx
             }
@@ -561,6 +1485,102 @@ where
         Ok(RawMultiSubscription { rx })
     }
 
+    ///////////////////////////////////////////////////////////////////////////
+    // Unsolicited errors
+    ///////////////////////////////////////////////////////////////////////////
+
+    /// Begin listening to errors sent by the server that don't belong to any
+    /// in-flight request
+    ///
+    /// These are errors on the error path whose sequence number matches no
+    /// pending request, e.g. from a spawned handler whose request future was
+    /// already dropped, or from a request that timed out on the host. Errors
+    /// for in-flight requests are returned from the request as usual.
+    ///
+    /// Multiple listeners are allowed, and behave as a broadcast channel. Only
+    /// errors received after subscribing are delivered.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn subscribe_errors(
+        &self,
+        depth: usize,
+    ) -> Result<ErrorSubscription<WireErr>, IoClosed> {
+        let cancel_fut = self.stopper.wait_stopped();
+        let operate_fut = async {
+            let mut guard = self.subscriptions.lock().await;
+            if guard.stopped {
+                return Err(IoClosed);
+            }
+            let rx = match guard.unsolicited_errors.as_ref() {
+                Some(tx) => tx.subscribe(),
+                None => {
+                    let (tx, rx) = broadcast::channel(self.sub_depth(depth));
+                    guard.unsolicited_errors = Some(tx);
+                    rx
+                }
+            };
+            Ok(ErrorSubscription {
+                rx,
+                _pd: PhantomData,
+            })
+        };
+        select! {
+            _ = cancel_fut => Err(IoClosed),
+            res = operate_fut => res,
+        }
+    }
+
+    /// Begin listening to every frame received from the server
+    ///
+    /// Frames are delivered after any decoding, e.g. reassembly of fragments or
+    /// decompression, and are still routed to pending requests and subscriptions
+    /// as usual, so this can be used to build custom routing, analytics, or
+    /// bridging on top of the client.
+    ///
+    /// Multiple listeners are allowed, and behave as a broadcast channel. Only
+    /// frames received after subscribing are delivered, and a listener that falls
+    /// more than `depth` frames behind loses the oldest ones, see
+    /// [`MultiSubRxError::Lagged`].
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn frame_stream(&self, depth: usize) -> Result<RawMultiSubscription, IoClosed> {
+        let cancel_fut = self.stopper.wait_stopped();
+        let operate_fut = async {
+            let mut guard = self.subscriptions.lock().await;
+            if guard.stopped {
+                return Err(IoClosed);
+            }
+            let rx = match guard.all_frames.as_ref() {
+                Some(tx) => tx.subscribe(),
+                None => {
+                    let (tx, rx) = broadcast::channel(self.sub_depth(depth));
+                    guard.all_frames = Some(tx);
+                    rx
+                }
+            };
+            Ok(RawMultiSubscription { rx })
+        };
+        select! {
+            _ = cancel_fut => Err(IoClosed),
+            res = operate_fut => res,
+        }
+    }
+
+    /// Begin listening to the log records sent by the device
+    ///
+    /// Subscribes to the [`LogTopic`], on which devices send records with e.g. a
+    /// `server::log_topic::TopicLogger`. This is [`subscribe_multi()`](Self::subscribe_multi)
+    /// for the [`LogTopic`], so multiple listeners are allowed, and only records
+    /// received after subscribing are delivered.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn log_stream(
+        &self,
+        depth: usize,
+    ) -> Result<MultiSubscription<OwnedLogRecord>, IoClosed> {
+        self.subscribe_multi::<LogTopic>(depth).await
+    }
+
     ///////////////////////////////////////////////////////////////////////////
     // Subscribe (Legacy)
     ///////////////////////////////////////////////////////////////////////////
@@ -598,7 +1618,7 @@ where
     where
         T::Message: DeserializeOwned,
     {
-        let (tx, rx) = tokio::sync::mpsc::channel(depth);
+        let (tx, rx) = tokio::sync::mpsc::channel(self.sub_depth(depth));
         {
             let mut guard = self.subscriptions.lock().await;
             if guard.stopped {
@@ -619,6 +1639,7 @@ where
         }
         Ok(Subscription {
             rx,
+            pool: self.ctx.pool.clone(),
             _pd: PhantomData,
         })
     }
@@ -646,7 +1667,7 @@ where
         key: Key,
         depth: usize,
     ) -> Result<RawSubscription, IoClosed> {
-        let (tx, rx) = tokio::sync::mpsc::channel(depth);
+        let (tx, rx) = tokio::sync::mpsc::channel(self.sub_depth(depth));
         {
             let mut guard = self.subscriptions.lock().await;
             if guard.stopped {
@@ -661,7 +1682,10 @@ where
                 guard.exclusive_list.push((key, tx));
             }
         }
-        Ok(RawSubscription { rx })
+        Ok(RawSubscription {
+            rx,
+            pool: self.ctx.pool.clone(),
+        })
     }
 
     ///////////////////////////////////////////////////////////////////////////
@@ -699,7 +1723,7 @@ where
     where
         T::Message: DeserializeOwned,
     {
-        let (tx, rx) = tokio::sync::mpsc::channel(depth);
+        let (tx, rx) = tokio::sync::mpsc::channel(self.sub_depth(depth));
         {
             let mut guard = self.subscriptions.lock().await;
             if guard.stopped {
@@ -720,6 +1744,7 @@ where
         }
         Ok(Subscription {
             rx,
+            pool: self.ctx.pool.clone(),
             _pd: PhantomData,
         })
     }
@@ -728,44 +1753,149 @@ where
     ///
     /// If you try to subscribe to the same topic multiple times, this function returns a
     /// [`SubscribeError::AlreadySubscribed`] (there can be only one).
-    /// This does not apply to subscriptions created with `subscribe_multi`.
+    /// This does not apply to subscriptions created with `subscribe_multi`.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn subscribe_exclusive_raw(
+        &self,
+        key: Key,
+        depth: usize,
+    ) -> Result<RawSubscription, SubscribeError> {
+        let cancel_fut = self.stopper.wait_stopped();
+        let operate_fut = self.subscribe_inner_exclusive_raw(key, depth);
+        select! {
+            _ = cancel_fut => Err(SubscribeError::IoClosed),
+            res = operate_fut => res,
+        }
+    }
+
+    /// Inner function version of [Self::subscribe_exclusive_raw]
+    async fn subscribe_inner_exclusive_raw(
+        &self,
+        key: Key,
+        depth: usize,
+    ) -> Result<RawSubscription, SubscribeError> {
+        let (tx, rx) = tokio::sync::mpsc::channel(self.sub_depth(depth));
+        {
+            let mut guard = self.subscriptions.lock().await;
+            if guard.stopped {
+                return Err(SubscribeError::IoClosed);
+            }
+            if let Some(entry) = guard.exclusive_list.iter_mut().find(|(k, _)| *k == key) {
+                if !entry.1.is_closed() {
+                    return Err(SubscribeError::AlreadySubscribed);
+                }
+                entry.1 = tx;
+            } else {
+                guard.exclusive_list.push((key, tx));
+            }
+        }
+        Ok(RawSubscription {
+            rx,
+            pool: self.ctx.pool.clone(),
+        })
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    // Subscribe Bounded
+    ///////////////////////////////////////////////////////////////////////////
+
+    /// Begin listening to a [Topic], receiving a [BoundedSubscription] that will
+    /// give a stream of [Message][Topic::Message]s.
+    ///
+    /// At most `depth` messages are buffered. When the buffer is full, the given
+    /// [`BackpressurePolicy`] decides what happens to new messages. The number of
+    /// messages lost can be read with [`BoundedSubscription::dropped()`].
+    ///
+    /// If you try to subscribe to the same topic multiple times, this function returns a
+    /// [`SubscribeError::AlreadySubscribed`] (there can be only one).
+    /// This does not apply to subscriptions created with other `subscribe` methods.
     ///
     /// Returns an Error if the I/O worker is closed.
-    pub async fn subscribe_exclusive_raw(
+    pub async fn subscribe_bounded<T: Topic>(
         &self,
-        key: Key,
         depth: usize,
-    ) -> Result<RawSubscription, SubscribeError> {
+        policy: BackpressurePolicy,
+    ) -> Result<BoundedSubscription<T::Message>, SubscribeError>
+    where
+        T::Message: DeserializeOwned,
+    {
         let cancel_fut = self.stopper.wait_stopped();
-        let operate_fut = self.subscribe_inner_exclusive_raw(key, depth);
+        let operate_fut = self.subscribe_inner_bounded::<T>(depth, policy);
         select! {
             _ = cancel_fut => Err(SubscribeError::IoClosed),
             res = operate_fut => res,
         }
     }
 
-    /// Inner function version of [Self::subscribe_exclusive_raw]
-    async fn subscribe_inner_exclusive_raw(
+    /// Inner function version of [Self::subscribe_bounded]
+    async fn subscribe_inner_bounded<T: Topic>(
         &self,
-        key: Key,
         depth: usize,
-    ) -> Result<RawSubscription, SubscribeError> {
-        let (tx, rx) = tokio::sync::mpsc::channel(depth);
+        policy: BackpressurePolicy,
+    ) -> Result<BoundedSubscription<T::Message>, SubscribeError>
+    where
+        T::Message: DeserializeOwned,
+    {
+        let queue = BoundedQueue::new(self.sub_depth(depth), policy);
         {
             let mut guard = self.subscriptions.lock().await;
             if guard.stopped {
                 return Err(SubscribeError::IoClosed);
             }
-            if let Some(entry) = guard.exclusive_list.iter_mut().find(|(k, _)| *k == key) {
-                if !entry.1.is_closed() {
+            let tx = BoundedSender {
+                queue: queue.clone(),
+            };
+            if let Some(entry) = guard
+                .bounded_list
+                .iter_mut()
+                .find(|(k, _)| *k == T::TOPIC_KEY)
+            {
+                if !entry.1.queue.is_rx_closed() {
                     return Err(SubscribeError::AlreadySubscribed);
                 }
                 entry.1 = tx;
             } else {
-                guard.exclusive_list.push((key, tx));
+                guard.bounded_list.push((T::TOPIC_KEY, tx));
             }
         }
-        Ok(RawSubscription { rx })
+        Ok(BoundedSubscription {
+            queue,
+            pool: self.ctx.pool.clone(),
+            _pd: PhantomData,
+        })
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    // Announced subscriptions
+    ///////////////////////////////////////////////////////////////////////////
+
+    /// Tell the server that we are listening to a [Topic]
+    ///
+    /// Sends a [SubscribeTopic] message. Servers tracking
+    /// [`Subscriptions`](crate::server::Subscriptions) only publish topics that were
+    /// announced more often than they were withdrawn with
+    /// [`HostClient::announce_unsubscribe()`], and other servers ignore it.
+    ///
+    /// This is independent of the local `subscribe` methods: a topic should be
+    /// announced once per subscription, typically right after subscribing, and
+    /// withdrawn once it is dropped. The server forgets all announcements when it
+    /// resets the connection, so they must be repeated after reconnecting.
+    ///
+    /// There is no feedback if the server received our message. If the I/O worker
+    /// is closed, an error is returned.
+    pub async fn announce_subscribe<T: Topic + ?Sized>(&self) -> Result<(), IoClosed> {
+        self.publish::<SubscribeTopic>(VarSeq::Seq4(0), &T::TOPIC_KEY)
+            .await
+    }
+
+    /// Tell the server that we are no longer listening to a [Topic]
+    ///
+    /// Sends an [UnsubscribeTopic] message, withdrawing one announcement made with
+    /// [`HostClient::announce_subscribe()`].
+    pub async fn announce_unsubscribe<T: Topic + ?Sized>(&self) -> Result<(), IoClosed> {
+        self.publish::<UnsubscribeTopic>(VarSeq::Seq4(0), &T::TOPIC_KEY)
+            .await
     }
 
     /// Permanently close the connection to the client
@@ -788,12 +1918,56 @@ where
     pub async fn wait_closed(&self) {
         self.stopper.wait_stopped().await;
     }
+
+    /// Observe the state of the connection to the device
+    ///
+    /// The returned receiver is notified on every transition, e.g. when a
+    /// client created with [`HostClient::new_with_reconnect()`] loses and
+    /// later re-establishes its connection.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.ctx.conn.subscribe()
+    }
+}
+
+/// A handle used to cancel a request made with [`HostClient::send_resp_cancellable()`]
+pub struct CancelHandle<WireErr> {
+    client: HostClient<WireErr>,
+    /// The sequence number of the request, `None` if none was free
+    seq_no: Option<VarSeq>,
+}
+
+impl<WireErr> CancelHandle<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Ask the server to cancel the request
+    ///
+    /// There is no feedback if the server received our message, the outcome of
+    /// the request is reported by the response future.
+    pub async fn cancel(&self) -> Result<(), IoClosed> {
+        // Without a sequence number, the request was never sent
+        let Some(seq_no) = self.seq_no else {
+            return Ok(());
+        };
+        self.client.publish::<CancelTopic>(seq_no, &()).await
+    }
+}
+
+// Manual Clone impl because WireErr may not impl Clone
+impl<WireErr> Clone for CancelHandle<WireErr> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            seq_no: self.seq_no,
+        }
+    }
 }
 
 /// Like Subscription, but receives Raw frames that are not
 /// automatically deserialized
 pub struct RawSubscription {
     rx: mpsc::Receiver<RpcFrame>,
+    pool: Arc<FramePool>,
 }
 
 impl RawSubscription {
@@ -803,11 +1977,21 @@ impl RawSubscription {
     pub async fn recv(&mut self) -> Option<RpcFrame> {
         self.rx.recv().await
     }
+
+    /// Await a message like [`recv()`](Self::recv), returning its buffer to the
+    /// client for reuse once the frame is dropped
+    ///
+    /// Returns [None]` if the subscription was closed
+    pub async fn recv_pooled(&mut self) -> Option<PooledFrame> {
+        let frame = self.rx.recv().await?;
+        Some(PooledFrame::new(frame, self.pool.clone()))
+    }
 }
 
 /// A structure that represents a subscription to the given topic
 pub struct Subscription<M> {
     rx: mpsc::Receiver<RpcFrame>,
+    pool: Arc<FramePool>,
     _pd: PhantomData<M>,
 }
 
@@ -821,11 +2005,131 @@ where
     pub async fn recv(&mut self) -> Option<M> {
         loop {
             let frame = self.rx.recv().await?;
-            if let Ok(m) = postcard::from_bytes(&frame.body) {
+            if let Ok(m) = self.pool.decode(frame.body) {
+                return Some(m);
+            }
+        }
+    }
+}
+
+/// A stream of response chunks from a streaming endpoint
+///
+/// See [`HostClient::send_resp_stream()`].
+pub struct ResponseStream<M, WireErr> {
+    rx: mpsc::UnboundedReceiver<RpcFrame>,
+    ctx: Arc<HostContext>,
+    conn: watch::Receiver<ConnectionState>,
+    resp_key: Key,
+    err_key: Key,
+    done: bool,
+    /// Holds the sequence number until the stream is dropped
+    _pending: PendingSeq,
+    _pd: PhantomData<fn() -> (M, WireErr)>,
+}
+
+impl<M, WireErr> ResponseStream<M, WireErr>
+where
+    M: DeserializeOwned,
+    WireErr: DeserializeOwned,
+{
+    /// Await the next chunk of the response.
+    ///
+    /// Returns [None] once the server has signalled the end of the stream. If the
+    /// server replies with an error, or the connection is lost, the error is
+    /// returned and the stream ends.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn recv(&mut self) -> Option<Result<M, HostErr<WireErr>>> {
+        if self.done {
+            return None;
+        }
+        let disconn_fut = async {
+            self.conn.changed().await.ok()?;
+            let state = *self.conn.borrow();
+            Some(state)
+        };
+        let frame = select! {
+            f = self.rx.recv() => f,
+            d = disconn_fut => {
+                self.done = true;
+                return Some(Err(match d {
+                    Some(ConnectionState::Closed) | None => HostErr::Closed,
+                    Some(_) => HostErr::Disconnected,
+                }));
+            }
+        };
+        let Some(frame) = frame else {
+            self.done = true;
+            return Some(Err(HostErr::Closed));
+        };
+
+        let kkind = frame.header.key.kind();
+        if *self.ctx.kkind.read().unwrap() != kkind {
+            *self.ctx.kkind.write().unwrap() = kkind;
+        }
+
+        if frame.header.key == VarKey::Key8(self.resp_key) {
+            return Some(self.ctx.pool.decode(frame.body).map_err(HostErr::from));
+        }
+
+        self.done = true;
+        if frame.header.key == VarKey::Key8(STREAM_END_KEY) {
+            None
+        } else {
+            Some(Err(decode_wire_err(self.err_key, &frame.body)))
+        }
+    }
+}
+
+/// What to do with new messages when a [BoundedSubscription] is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Discard the oldest buffered message to make room for the new one
+    DropOldest,
+    /// Discard the new message
+    DropNewest,
+    /// Wait until there is room for the new message.
+    ///
+    /// NOTE: This stops ALL incoming messages from being processed, including
+    /// responses and other subscriptions, until the subscriber catches up.
+    Block,
+}
+
+/// A structure that represents a bounded subscription to the given topic
+///
+/// See [`HostClient::subscribe_bounded()`].
+pub struct BoundedSubscription<M> {
+    queue: Arc<BoundedQueue>,
+    pool: Arc<FramePool>,
+    _pd: PhantomData<M>,
+}
+
+impl<M> BoundedSubscription<M>
+where
+    M: DeserializeOwned,
+{
+    /// Await a message for the given subscription.
+    ///
+    /// Returns [None]` if the subscription was closed
+    pub async fn recv(&mut self) -> Option<M> {
+        loop {
+            let frame = self.queue.pop().await?;
+            if let Ok(m) = self.pool.decode(frame.body) {
                 return Some(m);
             }
         }
     }
+
+    /// The number of messages that have been dropped due to backpressure
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl<M> Drop for BoundedSubscription<M> {
+    fn drop(&mut self) {
+        self.queue.close_rx();
+    }
 }
 
 /// Like MultiSubscription, but receives Raw frames that are not
@@ -887,6 +2191,36 @@ where
     }
 }
 
+/// A subscription to errors not belonging to any in-flight request, see
+/// [`HostClient::subscribe_errors()`]
+pub struct ErrorSubscription<WireErr> {
+    rx: broadcast::Receiver<RpcFrame>,
+    _pd: PhantomData<fn() -> WireErr>,
+}
+
+impl<WireErr> ErrorSubscription<WireErr>
+where
+    WireErr: DeserializeOwned,
+{
+    /// Await the next error, along with the header it was sent with
+    ///
+    /// Errors that fail to deserialize are skipped.
+    pub async fn recv(&mut self) -> Result<(VarHeader, WireErr), MultiSubRxError> {
+        loop {
+            let frame = match self.rx.recv().await {
+                Ok(f) => f,
+                Err(broadcast::error::RecvError::Closed) => return Err(MultiSubRxError::IoClosed),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    return Err(MultiSubRxError::Lagged(n))
+                }
+            };
+            if let Ok(e) = postcard::from_bytes(&frame.body) {
+                return Ok((frame.header, e));
+            }
+        }
+    }
+}
+
 // Manual Clone impl because WireErr may not impl Clone
 impl<WireErr> Clone for HostClient<WireErr> {
     fn clone(&self) -> Self {
@@ -898,6 +2232,8 @@ impl<WireErr> Clone for HostClient<WireErr> {
             subscriptions: self.subscriptions.clone(),
             stopper: self.stopper.clone(),
             seq_kind: self.seq_kind,
+            default_timeout: self.default_timeout,
+            subscription_depth: self.subscription_depth,
         }
     }
 }
@@ -934,8 +2270,104 @@ impl RpcFrame {
 pub struct HostContext {
     kkind: RwLock<VarKeyKind>,
     map: WaitMap<VarHeader, (VarHeader, Vec<u8>)>,
-    seq: AtomicU32,
+    /// The callbacks of requests sent with `send_cb`, by sequence number
+    callbacks: std::sync::Mutex<HashMap<u32, PendingCallback>>,
+    /// The sequence numbers in use by pending requests, with the number of holders
+    pending: std::sync::Mutex<HashMap<u32, usize>>,
+    seq: Arc<dyn SeqNoGenerator>,
     subscription_timeout: Duration,
+    conn: watch::Sender<ConnectionState>,
+    credits: RwLock<Option<Arc<Semaphore>>>,
+    tap: RwLock<Option<Arc<WireTapFn>>>,
+    retry: RwLock<Option<RetryPolicy>>,
+    /// The deadlines of pending requests by sequence number, if deadlines are sent
+    deadlines: std::sync::Mutex<Option<HashMap<u32, tokio::time::Instant>>>,
+    /// Buffers for incoming frames
+    pool: Arc<FramePool>,
+    /// Counts and reports frames nobody was waiting for
+    unmatched: Unmatched,
+    /// The largest request body the device can receive, if known
+    max_body: RwLock<Option<u32>>,
+    #[cfg(feature = "auth")]
+    auth: RwLock<Option<crate::auth::Authenticator>>,
+    #[cfg(feature = "checksum")]
+    checksum: std::sync::atomic::AtomicBool,
+    #[cfg(feature = "compress")]
+    compression: RwLock<Option<crate::compress::Compression>>,
+    #[cfg(feature = "fragment")]
+    fragment: RwLock<Option<usize>>,
+    #[cfg(feature = "fragment")]
+    fragment_id: std::sync::atomic::AtomicU16,
+    err_key: Key,
+}
+
+/// A callback observing every frame sent or received by a [HostClient]
+///
+/// See [`HostClient::set_wire_tap()`].
+pub type WireTapFn = dyn Fn(FrameDirection, &[u8]) + Send + Sync;
+
+/// A source of sequence numbers for requests made by a [HostClient]
+///
+/// Responses are matched to requests by their key and sequence number, so a
+/// sequence number must not be used by two requests in flight at the same time,
+/// or a reply could be matched to the wrong request. Generators eventually wrap
+/// around, e.g. the default [CounterSeqNoGenerator] after 2^32 requests, so
+/// the [HostClient] skips numbers that are still in use by a pending request,
+/// i.e. one still waiting for its reply, callback, or the end of its stream,
+/// and draws the next one instead. If all numbers are in use, requests fail with
+/// [`HostErr::SeqNoExhausted`] without being sent, and a warning is logged when
+/// three quarters of them are in use.
+///
+/// Frames with sequence numbers chosen by the caller, e.g. passed to
+/// [`HostClient::send_resp_raw()`], are not checked, but are considered in use
+/// while pending. A duplicate that still slips through fails with
+/// [`HostErr::BadResponse`].
+///
+/// See [`HostClientConfig::seq_no_generator`].
+pub trait SeqNoGenerator: Send + Sync + 'static {
+    /// Obtain the sequence number to use for the next request
+    fn next(&self) -> u32;
+
+    /// The number of distinct sequence numbers handed out before wrapping around
+    ///
+    /// Defaults to all 2^32 values of a `u32`.
+    fn space(&self) -> u64 {
+        1 << 32
+    }
+}
+
+/// The default [SeqNoGenerator], a monotonically increasing counter
+///
+/// The counter wraps around on overflow.
+#[derive(Debug, Default)]
+pub struct CounterSeqNoGenerator {
+    ctr: AtomicU32,
+}
+
+impl CounterSeqNoGenerator {
+    /// Create a new counter, starting at zero
+    pub const fn new() -> Self {
+        Self {
+            ctr: AtomicU32::new(0),
+        }
+    }
+}
+
+impl SeqNoGenerator for CounterSeqNoGenerator {
+    fn next(&self) -> u32 {
+        self.ctr.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// The state of the connection between a [HostClient] and the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The client is attempting to (re-)establish a connection
+    Connecting,
+    /// The client is connected, requests may be sent
+    Connected,
+    /// The client has been permanently closed
+    Closed,
 }
 
 impl core::fmt::Debug for HostContext {
@@ -969,13 +2401,241 @@ pub enum ProcessError {
     Closed,
 }
 
+/// Forgets the deadline of a request when the request completes, see
+/// [`HostContext::track_deadline()`]
+struct DeadlineGuard<'a> {
+    ctx: &'a HostContext,
+    seq_no: u32,
+}
+
+impl Drop for DeadlineGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(deadlines) = self.ctx.deadlines.lock().unwrap().as_mut() {
+            deadlines.remove(&self.seq_no);
+        }
+    }
+}
+
+/// Marks a sequence number as in use by a pending request until dropped, see
+/// [`HostContext::next_seq()`] and [`HostContext::hold_seq()`]
+pub(crate) struct PendingSeq {
+    ctx: Arc<HostContext>,
+    seq_no: u32,
+}
+
+impl PendingSeq {
+    /// The sequence number held by this guard
+    pub(crate) fn seq_no(&self) -> u32 {
+        self.seq_no
+    }
+}
+
+impl Drop for PendingSeq {
+    fn drop(&mut self) {
+        let mut pending = self.ctx.pending.lock().unwrap();
+        if let Some(holders) = pending.get_mut(&self.seq_no) {
+            *holders -= 1;
+            if *holders == 0 {
+                pending.remove(&self.seq_no);
+            }
+        }
+    }
+}
+
 impl HostContext {
+    /// Draw the sequence number for a new request, skipping numbers that are
+    /// still in use by pending requests, and mark it as in use until the
+    /// returned guard is dropped
+    ///
+    /// The number is claimed under the same lock it is checked with, so
+    /// concurrent requests never draw the same number. Returns `None` if no free
+    /// number was found, see [`SeqNoGenerator`].
+    pub(crate) fn next_seq(self: &Arc<Self>) -> Option<PendingSeq> {
+        let mut pending = self.pending.lock().unwrap();
+        let callbacks = self.callbacks.lock().unwrap();
+        let in_use = pending.len() + callbacks.len();
+        if in_use as u64 >= self.seq.space() {
+            tracing::error!("All sequence numbers are in use by pending requests");
+            return None;
+        }
+        // Enough draws to get past all pending numbers, if the generator counts
+        for _ in 0..=in_use {
+            let seq_no = self.seq.next();
+            if !pending.contains_key(&seq_no) && !callbacks.contains_key(&seq_no) {
+                pending.insert(seq_no, 1);
+                self.warn_near_full(in_use + 1);
+                return Some(PendingSeq {
+                    ctx: self.clone(),
+                    seq_no,
+                });
+            }
+            tracing::debug!("Skipping sequence number {seq_no}, still in use by a pending request");
+        }
+        tracing::error!("No free sequence number found, all drawn numbers are in use");
+        None
+    }
+
+    /// Mark `seq_no` as in use by a pending request, until the returned guard
+    /// is dropped
+    pub(crate) fn hold_seq(self: &Arc<Self>, seq_no: u32) -> PendingSeq {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.entry(seq_no).or_insert(0) += 1;
+        self.warn_near_full(pending.len() + self.callbacks.lock().unwrap().len());
+        PendingSeq {
+            ctx: self.clone(),
+            seq_no,
+        }
+    }
+
+    /// Warn when `in_use` sequence numbers, after one more was taken, reach
+    /// three quarters of all numbers
+    pub(crate) fn warn_near_full(&self, in_use: usize) {
+        let space = self.seq.space();
+        let in_use = in_use as u64;
+        if in_use * 4 >= space * 3 && in_use.saturating_sub(1) * 4 < space * 3 {
+            tracing::warn!("{in_use} of {space} sequence numbers are in use by pending requests");
+        }
+    }
+
+    /// Remember that the request with `seq_no` times out after `timeout`, if
+    /// deadlines are sent, until the returned guard is dropped
+    fn track_deadline(&self, seq_no: VarSeq, timeout: Duration) -> Option<DeadlineGuard<'_>> {
+        let seq_no: u32 = seq_no.into();
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.deadlines
+            .lock()
+            .unwrap()
+            .as_mut()?
+            .insert(seq_no, deadline);
+        Some(DeadlineGuard { ctx: self, seq_no })
+    }
+
+    /// Insert the time left until the deadline of the request with `seq_no` into
+    /// the header of an outgoing frame, if it has a deadline
+    pub(crate) fn attach_deadline(&self, seq_no: VarSeq, mut frame: Vec<u8>) -> Vec<u8> {
+        let seq_no: u32 = seq_no.into();
+        let Some(deadline) = self
+            .deadlines
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|d| d.get(&seq_no).copied())
+        else {
+            return frame;
+        };
+        let Some((_hdr, _ver, body)) = VarHeader::take_versioned_from_slice(&frame) else {
+            return frame;
+        };
+        let hdr_len = frame.len() - body.len();
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        let left_ms = u32::try_from(left.as_millis()).unwrap_or(u32::MAX);
+        frame[0] |= VarHeader::DEADLINE_BITS;
+        frame.splice(hdr_len..hdr_len, left_ms.to_le_bytes());
+        frame
+    }
+
+    /// Pass a frame to the wire tap, if any
+    pub(crate) fn tap(&self, dir: FrameDirection, frame: &[u8]) {
+        let tap = self.tap.read().unwrap().clone();
+        if let Some(tap) = tap {
+            tap(dir, frame);
+        }
+    }
+
+    /// Append an authentication tag to an outgoing frame, if enabled
+    #[cfg(feature = "auth")]
+    pub(crate) fn sign(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        if let Some(auth) = self.auth.read().unwrap().as_ref() {
+            let len = frame.len();
+            frame.resize(len + auth.tag_len(), 0);
+            let (body, tag) = frame.split_at_mut(len);
+            auth.sign(body, tag);
+        }
+        frame
+    }
+
+    /// Append a checksum to an outgoing frame, if enabled
+    #[cfg(feature = "checksum")]
+    pub(crate) fn append_checksum(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        if self.checksum.load(Ordering::Relaxed) {
+            let len = frame.len();
+            frame.resize(len + crate::checksum::CHECKSUM_LEN, 0);
+            let (body, sum) = frame.split_at_mut(len);
+            crate::checksum::append(body, sum);
+        }
+        frame
+    }
+
+    /// Check and remove the checksum of an incoming frame, if enabled
+    ///
+    /// Returns `None` if the checksum was invalid.
+    #[cfg(feature = "checksum")]
+    pub(crate) fn verify_checksum(&self, mut frame: Vec<u8>) -> Option<Vec<u8>> {
+        if self.checksum.load(Ordering::Relaxed) {
+            let len = crate::checksum::verify(&frame)?;
+            frame.truncate(len);
+        }
+        Some(frame)
+    }
+
+    /// Compress an outgoing frame, if enabled and worth it
+    #[cfg(feature = "compress")]
+    pub(crate) fn compress(&self, frame: Vec<u8>) -> Vec<u8> {
+        match self.compression.read().unwrap().as_ref() {
+            Some(compression) => compression.compress(frame),
+            None => frame,
+        }
+    }
+
+    /// Decompress an incoming frame, if it is compressed
+    ///
+    /// Returns `None` if the frame could not be decompressed.
+    #[cfg(feature = "compress")]
+    pub(crate) fn decompress(&self, frame: Vec<u8>) -> Option<Vec<u8>> {
+        match self.compression.read().unwrap().as_ref() {
+            Some(compression) => compression.decompress(frame),
+            // Let the protocol version check reject compressed frames
+            None => Some(frame),
+        }
+    }
+
+    /// Split an outgoing frame into fragments, if enabled and necessary
+    #[cfg(feature = "fragment")]
+    pub(crate) fn fragment(&self, frame: Vec<u8>) -> Vec<Vec<u8>> {
+        match *self.fragment.read().unwrap() {
+            Some(max_len) if frame.len() > max_len => {
+                let id = self.fragment_id.fetch_add(1, Ordering::Relaxed);
+                crate::fragment::split(frame, max_len, id)
+            }
+            _ => vec![frame],
+        }
+    }
+
+    /// Check and remove the authentication tag of an incoming frame, if enabled
+    ///
+    /// Returns `None` if the tag was invalid.
+    #[cfg(feature = "auth")]
+    pub(crate) fn verify(&self, mut frame: Vec<u8>) -> Option<Vec<u8>> {
+        if let Some(auth) = self.auth.read().unwrap().as_ref() {
+            let len = auth.verify(&frame)?;
+            frame.truncate(len);
+        }
+        Some(frame)
+    }
+
     /// Like `HostContext::process` but tells you if we processed the message or
     /// nobody wanted it
     pub fn process_did_wake(&self, frame: RpcFrame) -> Result<bool, ProcessError> {
+        let Err(frame) = self.wake_callback(frame) else {
+            return Ok(true);
+        };
         match self.map.wake(&frame.header, (frame.header, frame.body)) {
             WakeOutcome::Woke => Ok(true),
-            WakeOutcome::NoMatch(_) => Ok(false),
+            WakeOutcome::NoMatch((hdr, body)) => {
+                self.unmatched.report(hdr);
+                self.pool.put(body);
+                Ok(false)
+            }
             WakeOutcome::Closed(_) => Err(ProcessError::Closed),
         }
     }
@@ -984,14 +2644,21 @@ impl HostContext {
     ///
     /// Returns an Err if the map was closed.
     pub fn process(&self, frame: RpcFrame) -> Result<(), ProcessError> {
-        if let WakeOutcome::Closed(_) = self.map.wake(&frame.header, (frame.header, frame.body)) {
-            Err(ProcessError::Closed)
-        } else {
-            Ok(())
-        }
+        self.process_did_wake(frame).map(drop)
     }
 }
 
+/// The maximum number of requests in-flight at once for [`HostClient::send_batch()`]
+pub const BATCH_MAX_IN_FLIGHT: usize = 32;
+
+/// Compute the key of `path` with the schema `ty`, as read from a [`SchemaReport`]
+///
+/// This is the runtime counterpart of [`Key::for_path()`], for types that are only
+/// known by their schema, e.g. when checking the keys of a connected device.
+pub fn key_for_path(ty: &OwnedNamedType, path: &str) -> Key {
+    Key::for_owned_schema_path(path, ty)
+}
+
 /// A report describing the schema spoken by the connected device
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Schema)]
 pub struct SchemaReport {
@@ -1093,6 +2760,118 @@ pub struct EndpointReport {
 pub struct UnableToFindType;
 
 impl SchemaReport {
+    /// Create a report from lists of endpoints and topics, as generated by the
+    /// [`endpoints!()`](crate::endpoints) and [`topics!()`](crate::topics) macros
+    ///
+    /// This allows creating a report without a connected device, e.g. to generate
+    /// bindings from the ICD crate in a build script.
+    ///
+    /// Unlike the reports obtained from a device, types are matched in the order
+    /// they are listed, so structurally identical types (which have identical keys
+    /// for the same path) are always reported with the same name.
+    ///
+    /// Returns an error if we are unable to find the type used for any endpoint or topic
+    pub fn from_maps(
+        endpoints: &[&EndpointMap],
+        topics: &[&TopicMap],
+    ) -> Result<Self, UnableToFindType> {
+        let mut rpt = Self::default();
+
+        // Listed types first, then the primitives, in a stable order
+        let mut primitives: Vec<OwnedNamedType> = rpt.types.iter().cloned().collect();
+        primitives.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut ordered: Vec<OwnedNamedType> = endpoints
+            .iter()
+            .flat_map(|m| m.types)
+            .chain(topics.iter().flat_map(|m| m.types))
+            .map(|ty| OwnedNamedType::from(*ty))
+            .collect();
+        ordered.extend(primitives);
+        let find = |path: &str, key: Key| {
+            ordered
+                .iter()
+                .find(|ty| Key::for_owned_schema_path(path, ty) == key)
+                .cloned()
+                .ok_or(UnableToFindType)
+        };
+
+        for (path, req_key, resp_key) in endpoints.iter().flat_map(|m| m.endpoints) {
+            rpt.endpoints.push(EndpointReport {
+                path: path.to_string(),
+                req_key: *req_key,
+                req_ty: find(path, *req_key)?,
+                resp_key: *resp_key,
+                resp_ty: find(path, *resp_key)?,
+            });
+        }
+        for map in topics {
+            for (path, key) in map.topics {
+                let report = TopicReport {
+                    path: path.to_string(),
+                    key: *key,
+                    ty: find(path, *key)?,
+                };
+                match map.direction {
+                    TopicDirection::ToServer => rpt.topics_in.push(report),
+                    TopicDirection::ToClient => rpt.topics_out.push(report),
+                }
+            }
+        }
+        for ty in ordered {
+            rpt.add_type(ty);
+        }
+        Ok(rpt)
+    }
+
+    /// Find the paths of all endpoints and topics using the given key
+    ///
+    /// This is useful for identifying messages in captured traffic. `key` may be
+    /// shortened, as seen on the wire, in which case more than one path may match.
+    /// Both the request and response keys of endpoints are checked.
+    ///
+    /// The key of a path and type can also be recomputed directly, with
+    /// [`Key::for_path()`] for types known at compile time, or
+    /// [`key_for_path()`] for types of this report.
+    pub fn paths_for_key(&self, key: &VarKey) -> Vec<&str> {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .filter(|e| *key == VarKey::Key8(e.req_key) || *key == VarKey::Key8(e.resp_key))
+            .map(|e| e.path.as_str());
+        let topics = self
+            .topics_in
+            .iter()
+            .chain(self.topics_out.iter())
+            .filter(|t| *key == VarKey::Key8(t.key))
+            .map(|t| t.path.as_str());
+        endpoints.chain(topics).collect()
+    }
+
+    /// Serialize the endpoints and topics of this report as a JSON document
+    ///
+    /// The document contains the `endpoints`, `topics_in`, and `topics_out` of the
+    /// report, in the order they were added, each with its path, key(s), and the full
+    /// schema of its type(s). The set of all types is omitted, as it has no stable
+    /// order, and each schema is already complete.
+    ///
+    /// **Requires feature**: `json`
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct JsonReport<'a> {
+            endpoints: &'a [EndpointReport],
+            topics_in: &'a [TopicReport],
+            topics_out: &'a [TopicReport],
+        }
+
+        let rpt = JsonReport {
+            endpoints: &self.endpoints,
+            topics_in: &self.topics_in,
+            topics_out: &self.topics_out,
+        };
+        serde_json::to_string_pretty(&rpt).expect("Serializing to a String should not fail")
+    }
+
     /// Insert a new type
     pub fn add_type(&mut self, t: OwnedNamedType) {
         self.types.insert(t);
@@ -11,16 +11,12 @@ use serde::de::DeserializeOwned;
 
 use crate::{
     header::VarSeqKind,
-    host_client::{HostClient, WireRx, WireSpawn, WireTx},
+    host_client::{HostClient, HostClientConfig, WireRx, WireSpawn, WireTx},
 };
 
-// TODO: These should all be configurable, PRs welcome
-
-/// The size in bytes of the largest possible IN transfer
+/// The size in bytes of the largest possible IN transfer, by default
 pub(crate) const MAX_TRANSFER_SIZE: usize = 1024;
-/// How many in-flight requests at once - allows nusb to keep pulling frames
-/// even if we haven't processed them host-side yet.
-pub(crate) const IN_FLIGHT_REQS: usize = 4;
+// TODO: This should be configurable, PRs welcome
 /// How many consecutive IN errors will we try to recover from before giving up?
 pub(crate) const MAX_STALL_RETRIES: usize = 10;
 
@@ -228,6 +224,25 @@ where
         err_uri_path: &str,
         outgoing_depth: usize,
         seq_no_kind: VarSeqKind,
+    ) -> Result<Self, String> {
+        let config = HostClientConfig::new_default(seq_no_kind, err_uri_path, outgoing_depth);
+        Self::try_from_nusb_and_interface_with_config(dev, interface_id, &config)
+    }
+
+    /// Try to create a new link using [`nusb`] for connectivity, with the given
+    /// configuration
+    ///
+    /// The [`incoming_depth`](HostClientConfig::incoming_depth) sets the number of
+    /// IN transfers kept in flight, and the [`max_frame_size`](HostClientConfig::max_frame_size)
+    /// sets their size, which defaults to 1024 bytes.
+    ///
+    /// See [`HostClient::try_from_nusb_and_interface()`] for more details.
+    ///
+    /// This constructor is available when the `raw-nusb` feature is enabled.
+    pub fn try_from_nusb_and_interface_with_config(
+        dev: &DeviceInfo,
+        interface_id: usize,
+        config: &HostClientConfig<'_>,
     ) -> Result<Self, String> {
         let dev = dev
             .open()
@@ -272,7 +287,7 @@ where
         let boq = interface.bulk_out_queue(ep_out);
         let biq = interface.bulk_in_queue(ep_in);
 
-        Ok(HostClient::new_with_wire(
+        Ok(HostClient::new_with_wire_and_config(
             NusbWireTx {
                 boq,
                 max_packet_size: mps,
@@ -280,11 +295,11 @@ where
             NusbWireRx {
                 biq,
                 consecutive_errs: 0,
+                in_flight: config.incoming_depth,
+                transfer_size: config.max_frame_size.unwrap_or(MAX_TRANSFER_SIZE),
             },
             NusbSpawn,
-            seq_no_kind,
-            err_uri_path,
-            outgoing_depth,
+            config,
         ))
     }
 
@@ -331,6 +346,192 @@ where
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////
+// Enumeration
+//////////////////////////////////////////////////////////////////////////////
+
+/// Which USB devices [`HostClient::enumerate()`] reports
+///
+/// By default, all devices with a vendor specific (class `0xFF`) interface match.
+///
+/// ```rust
+/// use postcard_rpc::host_client::UsbFilter;
+///
+/// let filter = UsbFilter::new()
+///     .vendor_id(0x16c0)
+///     .product_id(0x27dd)
+///     .interface_string("postcard-rpc");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbFilter {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    interface_class: Option<u8>,
+    interface_string: Option<String>,
+}
+
+impl UsbFilter {
+    /// Match all devices with a vendor specific interface
+    pub fn new() -> Self {
+        Self {
+            vendor_id: None,
+            product_id: None,
+            interface_class: Some(0xFF),
+            interface_string: None,
+        }
+    }
+
+    /// Only match devices with this vendor ID
+    pub fn vendor_id(mut self, vid: u16) -> Self {
+        self.vendor_id = Some(vid);
+        self
+    }
+
+    /// Only match devices with this product ID
+    pub fn product_id(mut self, pid: u16) -> Self {
+        self.product_id = Some(pid);
+        self
+    }
+
+    /// Only match interfaces with this class, instead of `0xFF`
+    pub fn interface_class(mut self, class: u8) -> Self {
+        self.interface_class = Some(class);
+        self
+    }
+
+    /// Match interfaces of any class
+    pub fn any_interface_class(mut self) -> Self {
+        self.interface_class = None;
+        self
+    }
+
+    /// Only match interfaces with this interface string
+    pub fn interface_string(mut self, s: &str) -> Self {
+        self.interface_string = Some(s.into());
+        self
+    }
+
+    fn matches_ids(&self, vid: u16, pid: u16) -> bool {
+        self.vendor_id.is_none_or(|v| v == vid) && self.product_id.is_none_or(|p| p == pid)
+    }
+
+    fn matches_interface(&self, class: u8, string: Option<&str>) -> bool {
+        self.interface_class.is_none_or(|c| c == class)
+            && self
+                .interface_string
+                .as_deref()
+                .is_none_or(|s| string == Some(s))
+    }
+}
+
+impl Default for UsbFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A USB device found by [`HostClient::enumerate()`]
+///
+/// The strings are read from the descriptors cached by the OS, so they are
+/// available without opening the device.
+#[derive(Debug, Clone)]
+pub struct UsbDevice {
+    /// The vendor ID of the device
+    pub vendor_id: u16,
+    /// The product ID of the device
+    pub product_id: u16,
+    /// The manufacturer string of the device
+    pub manufacturer: Option<String>,
+    /// The product string of the device
+    pub product: Option<String>,
+    /// The serial number of the device
+    pub serial_number: Option<String>,
+    /// The number of the bus the device is connected to
+    pub bus_number: u8,
+    /// The address of the device on its bus
+    pub device_address: u8,
+    /// The number of the matching interface
+    pub interface_number: u8,
+    /// The string of the matching interface
+    pub interface_string: Option<String>,
+    info: DeviceInfo,
+}
+
+impl UsbDevice {
+    /// The `nusb` info of the device
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.info
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// List the connected USB devices matching `filter`, e.g. to let the user
+    /// choose one before connecting with [`HostClient::try_from_usb_device()`]
+    ///
+    /// A device with several matching interfaces is listed once per interface.
+    ///
+    /// ## Platform specific support
+    ///
+    /// When using Windows, the WinUSB driver does not allow enumerating interfaces,
+    /// so devices are only filtered by their IDs, and always use interface zero.
+    ///
+    /// **Requires feature**: `raw-nusb`
+    pub fn enumerate(filter: &UsbFilter) -> Result<Vec<UsbDevice>, String> {
+        let devices = nusb::list_devices().map_err(|e| format!("Error listing devices: {e:?}"))?;
+        let mut found = vec![];
+        for info in devices.filter(|d| filter.matches_ids(d.vendor_id(), d.product_id())) {
+            #[cfg(not(target_os = "windows"))]
+            let interfaces: Vec<_> = info
+                .interfaces()
+                .filter(|i| filter.matches_interface(i.class(), i.interface_string()))
+                .map(|i| (i.interface_number(), i.interface_string().map(String::from)))
+                .collect();
+
+            #[cfg(target_os = "windows")]
+            let interfaces = [(0, None)];
+
+            for (interface_number, interface_string) in interfaces {
+                found.push(UsbDevice {
+                    vendor_id: info.vendor_id(),
+                    product_id: info.product_id(),
+                    manufacturer: info.manufacturer_string().map(String::from),
+                    product: info.product_string().map(String::from),
+                    serial_number: info.serial_number().map(String::from),
+                    bus_number: info.bus_number(),
+                    device_address: info.device_address(),
+                    interface_number,
+                    interface_string,
+                    info: info.clone(),
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    /// Try to connect to a device found by [`HostClient::enumerate()`]
+    ///
+    /// See [`HostClient::try_from_nusb_and_interface()`] for more details.
+    ///
+    /// This constructor is available when the `raw-nusb` feature is enabled.
+    pub fn try_from_usb_device(
+        dev: &UsbDevice,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Result<Self, String> {
+        Self::try_from_nusb_and_interface(
+            &dev.info,
+            dev.interface_number.into(),
+            err_uri_path,
+            outgoing_depth,
+            seq_no_kind,
+        )
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // Wire Interface Implementation
 //////////////////////////////////////////////////////////////////////////////
@@ -406,6 +607,11 @@ impl NusbWireTx {
 struct NusbWireRx {
     biq: Queue<RequestBuffer>,
     consecutive_errs: usize,
+    /// How many in-flight requests at once - allows nusb to keep pulling frames
+    /// even if we haven't processed them host-side yet.
+    in_flight: usize,
+    /// The size in bytes of the largest possible IN transfer
+    transfer_size: usize,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -418,18 +624,28 @@ impl WireRx for NusbWireRx {
     type Error = NusbWireRxError;
 
     #[inline]
-    fn receive(&mut self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
-        self.recv_inner()
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = Vec::new();
+        self.recv_inner(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[inline]
+    fn receive_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.recv_inner(buf)
     }
 }
 
 impl NusbWireRx {
-    async fn recv_inner(&mut self) -> Result<Vec<u8>, NusbWireRxError> {
+    async fn recv_inner(&mut self, buf: &mut Vec<u8>) -> Result<(), NusbWireRxError> {
         loop {
             // Rehydrate the queue
             let pending = self.biq.pending();
-            for _ in 0..(IN_FLIGHT_REQS.saturating_sub(pending)) {
-                self.biq.submit(RequestBuffer::new(MAX_TRANSFER_SIZE));
+            for _ in 0..(self.in_flight.saturating_sub(pending)) {
+                self.biq.submit(RequestBuffer::new(self.transfer_size));
             }
 
             let res = self.biq.next_complete().await;
@@ -468,7 +684,7 @@ impl NusbWireRx {
                     tracing::info!("Cancelled all in-flight requests");
 
                     // Now we need to join all in flight requests
-                    for _ in 0..(IN_FLIGHT_REQS - 1) {
+                    for _ in 0..self.in_flight.saturating_sub(1) {
                         let res = self.biq.next_complete().await;
                         tracing::info!("Drain state: {:?}", res.status);
                     }
@@ -506,7 +722,49 @@ impl NusbWireRx {
                 self.consecutive_errs = 0;
             }
 
-            return Ok(res.data);
+            // Copy out the frame, and resubmit the transfer buffer right away
+            buf.clear();
+            buf.extend_from_slice(&res.data);
+            self.biq
+                .submit(RequestBuffer::reuse(res.data, self.transfer_size));
+            return Ok(());
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::UsbFilter;
+
+    #[test]
+    fn filter_ids() {
+        let any = UsbFilter::new();
+        assert!(any.matches_ids(0x1234, 0x5678));
+
+        let vid = UsbFilter::new().vendor_id(0x1234);
+        assert!(vid.matches_ids(0x1234, 0x5678));
+        assert!(!vid.matches_ids(0x4321, 0x5678));
+
+        let both = vid.product_id(0x5678);
+        assert!(both.matches_ids(0x1234, 0x5678));
+        assert!(!both.matches_ids(0x1234, 0x8765));
+    }
+
+    #[test]
+    fn filter_interfaces() {
+        let vendor = UsbFilter::new();
+        assert!(vendor.matches_interface(0xFF, None));
+        assert!(!vendor.matches_interface(0x02, None));
+
+        let cdc = UsbFilter::new().interface_class(0x02);
+        assert!(cdc.matches_interface(0x02, Some("serial")));
+        assert!(!cdc.matches_interface(0xFF, None));
+
+        let named = UsbFilter::new()
+            .any_interface_class()
+            .interface_string("postcard-rpc");
+        assert!(named.matches_interface(0x02, Some("postcard-rpc")));
+        assert!(!named.matches_interface(0xFF, Some("other")));
+        assert!(!named.matches_interface(0xFF, None));
+    }
+}
@@ -0,0 +1,172 @@
+//! Automatically reconnecting HostClient support
+
+use core::time::Duration;
+use std::{fmt::Debug, future::Future, sync::Arc};
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::{
+    select,
+    sync::{mpsc, Mutex},
+};
+use tracing::{info, warn};
+
+use crate::{
+    header::VarKeyKind,
+    host_client::{
+        util::{close_all, in_worker_inner, out_worker_inner, Stopper, Subscriptions, WorkerExit},
+        ConnectionState, HostClient, HostClientConfig, HostContext, RpcFrame, WireContext, WireRx,
+        WireSpawn, WireTx,
+    },
+};
+
+/// Configuration for re-establishing a lost connection
+///
+/// See [`HostClient::new_with_reconnect()`].
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// The time to wait after the first failed connection attempt
+    pub initial_backoff: Duration,
+    /// The wait time is doubled after each failed attempt, up to this limit
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a HostClient that automatically reconnects to the device
+    ///
+    /// `connect` is called to open the underlying transport, both initially,
+    /// and any time the connection is lost, e.g. when the device resets or
+    /// is unplugged. Failed attempts are retried with the backoff given in
+    /// `reconnect`.
+    ///
+    /// While disconnected, any in-flight or newly made requests return
+    /// [`HostErr::Disconnected`](crate::host_client::HostErr::Disconnected).
+    /// Subscriptions are kept across reconnects, and will continue to
+    /// receive messages once the connection has been re-established.
+    ///
+    /// Connection state changes can be observed with [`HostClient::connection_state()`].
+    pub fn new_with_reconnect<WTX, WRX, WSP, F, Fut, E>(
+        connect: F,
+        mut sp: WSP,
+        config: &HostClientConfig<'_>,
+        reconnect: ReconnectConfig,
+    ) -> Self
+    where
+        WTX: WireTx,
+        WRX: WireRx,
+        WSP: WireSpawn,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(WTX, WRX), E>> + Send + 'static,
+        E: Debug + 'static,
+    {
+        let (me, wire_ctx) = Self::new_manual_priv(config);
+        me.ctx.conn.send_replace(ConnectionState::Connecting);
+
+        let WireContext { outgoing, incoming } = wire_ctx;
+
+        sp.spawn(reconnect_worker(
+            connect,
+            reconnect,
+            outgoing,
+            incoming,
+            me.subscriptions.clone(),
+            me.stopper.clone(),
+        ));
+
+        me
+    }
+}
+
+/// Supervisor worker, (re-)opening the wire and running the I/O workers
+async fn reconnect_worker<WTX, WRX, F, Fut, E>(
+    connect: F,
+    reconnect: ReconnectConfig,
+    outgoing: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    stop: Stopper,
+) where
+    WTX: WireTx,
+    WRX: WireRx,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(WTX, WRX), E>>,
+    E: Debug,
+{
+    let cancel_fut = stop.wait_stopped();
+    let operate_fut = reconnect_worker_inner(
+        connect,
+        reconnect,
+        outgoing,
+        host_ctx.clone(),
+        subscriptions.clone(),
+    );
+    select! {
+        biased;
+        _ = cancel_fut => {},
+        _ = operate_fut => {
+            // if WE exited, notify everyone else it's stoppin time
+            stop.stop();
+        },
+    }
+    close_all(&host_ctx, &subscriptions).await;
+}
+
+async fn reconnect_worker_inner<WTX, WRX, F, Fut, E>(
+    mut connect: F,
+    reconnect: ReconnectConfig,
+    mut outgoing: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+) where
+    WTX: WireTx,
+    WRX: WireRx,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(WTX, WRX), E>>,
+    E: Debug,
+{
+    let mut backoff = reconnect.initial_backoff;
+    loop {
+        let wires = connect()
+            .await
+            .map_err(|e| warn!("Connection attempt failed: {e:?}, retrying in {backoff:?}"));
+        let Ok((tx, rx)) = wires else {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(reconnect.max_backoff);
+            continue;
+        };
+        backoff = reconnect.initial_backoff;
+
+        // We may be talking to a different device (or firmware) now, start
+        // again with full size keys.
+        *host_ctx.kkind.write().unwrap() = VarKeyKind::Key8;
+        host_ctx.conn.send_replace(ConnectionState::Connected);
+        info!("Connected");
+
+        let exit = select! {
+            e = out_worker_inner(tx, &mut outgoing, &host_ctx) => e,
+            e = in_worker_inner(rx, host_ctx.clone(), subscriptions.clone()) => e,
+        };
+        if exit == WorkerExit::Closed {
+            return;
+        }
+
+        warn!("Connection lost, reconnecting");
+        host_ctx.conn.send_replace(ConnectionState::Connecting);
+
+        // Any requests that were queued but not yet sent have already been
+        // told they were disconnected, don't send them to the new connection.
+        while outgoing.try_recv().is_ok() {}
+    }
+}
@@ -0,0 +1,221 @@
+//! Recording and replaying of the frames of a session
+//!
+//! A [`FrameRecorder`] observes the frames of a [`HostClient`] with its wire tap,
+//! and records them with the time they were sent or received. The recording can
+//! be saved to a file, and loaded by a [`FrameReplayer`], which sends the frames
+//! the host sent to a simulated server, e.g. using the `test_channels`
+//! transport, to reproduce a session deterministically:
+//!
+//! ```rust,no_run
+//! # use postcard_rpc::{host_client::{HostClient, record::{FrameRecorder, FrameReplayer, ReplayTiming}}, standard_icd::WireError};
+//! # async fn example(client: HostClient<WireError>, server_tx: tokio::sync::mpsc::Sender<Vec<u8>>) {
+//! // Record a session with a real device
+//! let recorder = FrameRecorder::new();
+//! recorder.attach(&client);
+//! // ... use the client ...
+//! recorder.save("session.bin").unwrap();
+//!
+//! // Later, send the same requests to a simulated server, ten times faster
+//! let replayer = FrameReplayer::load("session.bin").unwrap();
+//! replayer.replay(&server_tx, ReplayTiming::Accelerated(10.0)).await.unwrap();
+//! # }
+//! ```
+//!
+//! Frames are recorded as seen by the wire tap, i.e. after they are signed or
+//! fragmented, so the simulated server must use the same [`auth`](crate::auth),
+//! `checksum`, or [`fragment`](crate::fragment) settings as the device.
+//!
+//! The file is a sequence of postcard-encoded [`RecordedFrame`]s.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    host_client::{HostClient, IoClosed},
+    FrameDirection,
+};
+
+/// A single frame of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// The time since the start of the recording, in microseconds
+    pub timestamp_us: u64,
+    /// Whether the frame was sent or received by the host
+    pub direction: FrameDirection,
+    /// The full frame, including the header
+    pub frame: Vec<u8>,
+}
+
+/// Records the frames sent and received by a [`HostClient`]
+///
+/// Clones share the same recording. See the [module docs](self) for details.
+#[derive(Clone)]
+pub struct FrameRecorder {
+    start: Instant,
+    frames: Arc<Mutex<Vec<RecordedFrame>>>,
+}
+
+impl FrameRecorder {
+    /// Create a new, empty, recording, starting now
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Record all frames of `client`, using its wire tap
+    ///
+    /// This replaces any wire tap of the client, see [`HostClient::set_wire_tap()`].
+    pub fn attach<WireErr>(&self, client: &HostClient<WireErr>)
+    where
+        WireErr: DeserializeOwned + Schema,
+    {
+        let this = self.clone();
+        client.set_wire_tap(move |dir, frame| this.record(dir, frame));
+    }
+
+    /// Record a single frame, with the current time
+    pub fn record(&self, direction: FrameDirection, frame: &[u8]) {
+        let timestamp_us = self
+            .start
+            .elapsed()
+            .as_micros()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.frames.lock().unwrap().push(RecordedFrame {
+            timestamp_us,
+            direction,
+            frame: frame.to_vec(),
+        });
+    }
+
+    /// A copy of the frames recorded so far
+    pub fn frames(&self) -> Vec<RecordedFrame> {
+        self.frames.lock().unwrap().clone()
+    }
+
+    /// Encode the frames recorded so far, in the format of the file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let frames = self.frames.lock().unwrap();
+        let mut out = Vec::new();
+        for frame in frames.iter() {
+            out.extend(postcard::to_stdvec(frame).expect("Allocations should not ever fail"));
+        }
+        out
+    }
+
+    /// Save the frames recorded so far to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+impl Default for FrameRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The timing of the frames sent by [`FrameReplayer::replay()`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayTiming {
+    /// With the same delays between frames as when recorded
+    Original,
+    /// With the delays between frames divided by the given factor, which must be
+    /// positive
+    Accelerated(f64),
+    /// Without any delay between frames
+    Immediate,
+}
+
+/// Replays the frames sent by the host in a recording
+///
+/// See the [module docs](self) for details.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameReplayer {
+    frames: Vec<RecordedFrame>,
+}
+
+impl FrameReplayer {
+    /// Create a replayer for the given frames
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Self { frames }
+    }
+
+    /// Decode a recording, as produced by [`FrameRecorder::to_bytes()`]
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, postcard::Error> {
+        let mut frames = Vec::new();
+        while !bytes.is_empty() {
+            let (frame, rest) = postcard::take_from_bytes(bytes)?;
+            frames.push(frame);
+            bytes = rest;
+        }
+        Ok(Self { frames })
+    }
+
+    /// Load a recording saved with [`FrameRecorder::save()`]
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// All frames of the recording, in both directions
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    /// The frames the device received from the host
+    pub fn outgoing(&self) -> impl Iterator<Item = &RecordedFrame> {
+        self.frames
+            .iter()
+            .filter(|f| f.direction == FrameDirection::Outgoing)
+    }
+
+    /// The frames the host received from the device
+    pub fn incoming(&self) -> impl Iterator<Item = &RecordedFrame> {
+        self.frames
+            .iter()
+            .filter(|f| f.direction == FrameDirection::Incoming)
+    }
+
+    /// Send the frames the host sent to `tx`, e.g. the receiving channel of a
+    /// server using the `test_channels` transport
+    ///
+    /// Returns the number of frames sent, or an error if `tx` is closed.
+    pub async fn replay(
+        &self,
+        tx: &mpsc::Sender<Vec<u8>>,
+        timing: ReplayTiming,
+    ) -> Result<usize, IoClosed> {
+        let mut sent = 0;
+        let mut last_us = None;
+        for frame in self.outgoing() {
+            let delay_us = frame
+                .timestamp_us
+                .saturating_sub(last_us.unwrap_or(frame.timestamp_us));
+            last_us = Some(frame.timestamp_us);
+            let delay = match timing {
+                ReplayTiming::Original => Duration::from_micros(delay_us),
+                ReplayTiming::Accelerated(factor) => {
+                    Duration::from_micros(delay_us).div_f64(factor)
+                }
+                ReplayTiming::Immediate => Duration::ZERO,
+            };
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            tx.send(frame.frame.clone()).await.map_err(|_| IoClosed)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
@@ -0,0 +1,209 @@
+//! Automatic retries of failed requests
+
+use core::time::Duration;
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq},
+    host_client::{HostClient, HostErr, RpcFrame},
+    Endpoint,
+};
+
+/// The errors that a [`RetryPolicy`] retries
+///
+/// Errors that are never transient, such as [`HostErr::Closed`] or errors sent by
+/// the device, are never retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOn {
+    /// Retry requests that received no reply in time, see [`HostErr::Timeout`]
+    pub timeout: bool,
+    /// Retry requests that were cut short by a lost connection, see [`HostErr::Disconnected`]
+    pub disconnected: bool,
+    /// Retry requests whose reply could not be deserialized, e.g. because it was
+    /// corrupted, see [`HostErr::Postcard`]
+    pub postcard: bool,
+    /// Retry requests whose reply was unexpected, see [`HostErr::BadResponse`]
+    pub bad_response: bool,
+}
+
+impl RetryOn {
+    /// Whether `err` should be retried
+    pub fn matches<WireErr>(&self, err: &HostErr<WireErr>) -> bool {
+        match err {
+            HostErr::Timeout => self.timeout,
+            HostErr::Disconnected => self.disconnected,
+            HostErr::Postcard(_) => self.postcard,
+            HostErr::BadResponse => self.bad_response,
+            HostErr::Wire(_)
+            | HostErr::Closed
+            | HostErr::BodyTooLarge { .. }
+            | HostErr::HandlerFault { .. }
+            | HostErr::Unauthorized
+            | HostErr::SeqNoExhausted => false,
+        }
+    }
+}
+
+impl Default for RetryOn {
+    /// Retry timeouts and lost connections
+    fn default() -> Self {
+        Self {
+            timeout: true,
+            disconnected: true,
+            postcard: false,
+            bad_response: false,
+        }
+    }
+}
+
+/// When and how often to retry failed requests
+///
+/// Every attempt is sent with a fresh sequence number, and only a reply to that
+/// attempt is accepted, so a late reply to an earlier attempt is never mistaken
+/// for the reply to a later one. As the device can't tell the attempts apart, a
+/// request may be handled more than once, e.g. if only its reply was lost. For
+/// this reason, only requests to endpoints marked as [`Endpoint::IDEMPOTENT`] are
+/// retried, unless [`retry_non_idempotent`](Self::retry_non_idempotent) is set.
+///
+/// Requests without a timeout wait for a reply forever, so to retry timeouts, the
+/// client must be configured with a
+/// [`default_timeout`](crate::host_client::HostClientConfig::default_timeout), or
+/// requests must be sent with [`HostClient::send_resp_timeout()`].
+///
+/// See [`HostClient::set_retry_policy()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of attempts, including the first. A value of 0 or 1 disables
+    /// retries.
+    pub max_attempts: usize,
+    /// The time to wait before the first retry
+    pub initial_backoff: Duration,
+    /// The wait time is doubled after each retry, up to this limit
+    pub max_backoff: Duration,
+    /// The errors to retry
+    pub retry_on: RetryOn,
+    /// Also retry requests to endpoints that are not idempotent
+    pub retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// A policy making up to `max_attempts` attempts, with the default backoff
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// Does this policy apply to requests to `E`?
+    fn applies_to<E: Endpoint>(&self) -> bool {
+        E::IDEMPOTENT || self.retry_non_idempotent
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 10ms before the first retry, and at most 1s
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            retry_on: RetryOn::default(),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Retry failed requests made with [`send_resp()`](Self::send_resp) and
+    /// [`send_resp_timeout()`](Self::send_resp_timeout) according to `policy`, or
+    /// stop retrying if `policy` is `None`
+    ///
+    /// Retries are disabled by default. The policy applies to all clones of this
+    /// client. See [`RetryPolicy`] for details.
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        *self.ctx.retry.write().unwrap() = policy;
+    }
+
+    /// Send a message like [`send_resp()`](Self::send_resp), retrying according to
+    /// `policy` instead of the policy of the client
+    ///
+    /// [`RetryPolicy::none()`] disables retries for this request.
+    pub async fn send_resp_with_retry<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        policy: &RetryPolicy,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let policy = policy.applies_to::<E>().then_some(policy);
+        self.send_resp_retrying::<E>(t, self.default_timeout, policy)
+            .await
+    }
+
+    /// The policy of the client for requests to `E`, if any
+    pub(crate) fn retry_policy_for<E: Endpoint>(&self) -> Option<RetryPolicy> {
+        self.ctx
+            .retry
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|p| p.applies_to::<E>())
+            .cloned()
+    }
+
+    /// Send a request, waiting at most `timeout` for each attempt, and retrying
+    /// according to `policy`
+    pub(crate) async fn send_resp_retrying<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        timeout: Option<Duration>,
+        policy: Option<&RetryPolicy>,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+        self.check_request_size::<E>(msg.len())?;
+        let max_attempts = policy.map_or(1, |p| p.max_attempts.max(1));
+        let mut backoff = policy.map_or(Duration::ZERO, |p| p.initial_backoff);
+        let mut attempt = 1;
+        loop {
+            let pending = self.next_seq()?;
+            let frame = RpcFrame {
+                // NOTE: send_resp_raw automatically shrinks down key and sequence
+                // kinds to the appropriate amount
+                header: VarHeader {
+                    key: VarKey::Key8(E::REQ_KEY),
+                    seq_no: VarSeq::Seq4(pending.seq_no()),
+                },
+                body: msg.clone(),
+            };
+            let res = self
+                .send_resp_raw_timeout(frame, E::RESP_KEY, timeout)
+                .await
+                .and_then(|frame| Ok(self.ctx.pool.decode::<E::Response>(frame.body)?));
+            match (res, policy) {
+                (Err(e), Some(p)) if attempt < max_attempts && p.retry_on.matches(&e) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(p.max_backoff);
+                    attempt += 1;
+                }
+                (res, _) => return res,
+            }
+        }
+    }
+}
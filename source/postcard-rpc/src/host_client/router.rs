@@ -0,0 +1,193 @@
+//! Fan-out of a topic to many subscribers
+//!
+//! A [`TopicRouter`] receives the messages of a single [Topic] from a
+//! [`HostClient`], and forwards them to any number of [`BoundedSubscription`]s,
+//! each with its own depth and [`BackpressurePolicy`]. Subscribers can come and
+//! go while the router runs, without subscribing to the client again:
+//!
+//! ```rust,no_run
+//! # use postcard_rpc::{host_client::{BackpressurePolicy, HostClient, router::TopicRouter}, standard_icd::WireError, topics, TopicDirection};
+//! # use postcard_schema::Schema;
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Serialize, Deserialize, Schema)]
+//! # pub struct Reading(pub f32);
+//! # topics! { list = TOPICS; direction = TopicDirection::ToClient; | TopicTy | MessageTy | Path | | ------- | --------- | ---- | | SensorTopic | Reading | "sensor" | }
+//! # async fn example(client: HostClient<WireError>) {
+//! let router = TopicRouter::new::<SensorTopic, _>(&client, 64).await.unwrap();
+//!
+//! // The UI only cares about the most recent readings...
+//! let mut ui = router.subscribe(1, BackpressurePolicy::DropOldest);
+//! // ...while the logger must see all of them
+//! let mut logger = router.subscribe(1024, BackpressurePolicy::Block);
+//! # }
+//! ```
+//!
+//! Messages are forwarded by a background task, which ends when the client is
+//! closed. All subscriptions then return `None` once their buffered messages have
+//! been received. Dropped subscriptions are removed when the next message arrives.
+//!
+//! A subscriber with [`BackpressurePolicy::Block`] only stalls the router, not
+//! the client, but all subscribers of the router wait for it. If the router falls
+//! behind by more than the `depth` given to [`TopicRouter::new()`], messages are
+//! lost for all subscribers, and counted in [`TopicRouter::lost()`].
+
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::task::JoinHandle;
+
+use crate::{
+    host_client::{
+        frame_pool::FramePool,
+        util::{BoundedQueue, BoundedSender},
+        BackpressurePolicy, BoundedSubscription, HostClient, IoClosed, MultiSubRxError,
+        RawMultiSubscription,
+    },
+    Topic,
+};
+
+/// Forwards the messages of one [Topic] to many subscribers
+///
+/// Clones share the same subscribers. The router stops when the last clone is
+/// dropped, closing all of its subscriptions. See the [module docs](self) for
+/// details.
+pub struct TopicRouter<M> {
+    inner: Arc<RouterInner>,
+    _pd: PhantomData<fn() -> M>,
+}
+
+struct RouterInner {
+    subs: Arc<Mutex<RouterSubs>>,
+    lost: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+    pool: Arc<FramePool>,
+}
+
+#[derive(Default)]
+struct RouterSubs {
+    closed: bool,
+    list: Vec<BoundedSender>,
+}
+
+impl RouterSubs {
+    fn close(&mut self) {
+        self.closed = true;
+        // Dropping the senders closes the subscriptions
+        self.list.clear();
+    }
+}
+
+impl<M> TopicRouter<M> {
+    /// Subscribe to the topic `T` of `client`, and start forwarding its messages
+    ///
+    /// Up to `depth` messages are buffered between the client and the router.
+    ///
+    /// Returns an error if the client is closed.
+    pub async fn new<T, WireErr>(
+        client: &HostClient<WireErr>,
+        depth: usize,
+    ) -> Result<Self, IoClosed>
+    where
+        T: ?Sized + Topic<Message = M>,
+        WireErr: DeserializeOwned + Schema,
+    {
+        let feed = client.subscribe_multi_raw(T::TOPIC_KEY, depth).await?;
+        let subs = Arc::new(Mutex::new(RouterSubs::default()));
+        let lost = Arc::new(AtomicU64::new(0));
+        let task = tokio::task::spawn(route(feed, subs.clone(), lost.clone()));
+        Ok(Self {
+            inner: Arc::new(RouterInner {
+                subs,
+                lost,
+                task,
+                pool: client.ctx.pool.clone(),
+            }),
+            _pd: PhantomData,
+        })
+    }
+
+    /// Add a subscriber, buffering at most `depth` messages, and applying
+    /// `policy` when its buffer is full
+    ///
+    /// If the router has stopped, the subscription is already closed.
+    pub fn subscribe(&self, depth: usize, policy: BackpressurePolicy) -> BoundedSubscription<M> {
+        let queue = BoundedQueue::new(depth, policy);
+        let tx = BoundedSender {
+            queue: queue.clone(),
+        };
+        let mut subs = self.inner.subs.lock().unwrap();
+        if !subs.closed {
+            subs.list.push(tx);
+        }
+        BoundedSubscription {
+            queue,
+            pool: self.inner.pool.clone(),
+            _pd: PhantomData,
+        }
+    }
+
+    /// The number of subscriptions that have not been dropped
+    pub fn subscriber_count(&self) -> usize {
+        let subs = self.inner.subs.lock().unwrap();
+        subs.list.iter().filter(|s| !s.queue.is_rx_closed()).count()
+    }
+
+    /// The number of messages lost for all subscribers, because the router fell
+    /// behind the client
+    pub fn lost(&self) -> u64 {
+        self.inner.lost.load(Ordering::Relaxed)
+    }
+
+    /// Has the router stopped, because the client was closed?
+    pub fn is_closed(&self) -> bool {
+        self.inner.subs.lock().unwrap().closed
+    }
+}
+
+impl<M> Clone for TopicRouter<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl Drop for RouterInner {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.subs.lock().unwrap().close();
+    }
+}
+
+/// Forward frames from `feed` to all subscribers, until the client is closed
+async fn route(mut feed: RawMultiSubscription, subs: Arc<Mutex<RouterSubs>>, lost: Arc<AtomicU64>) {
+    loop {
+        let frame = match feed.recv().await {
+            Ok(frame) => frame,
+            Err(MultiSubRxError::Lagged(n)) => {
+                lost.fetch_add(n, Ordering::Relaxed);
+                continue;
+            }
+            Err(MultiSubRxError::IoClosed) => break,
+        };
+        let queues: Vec<Arc<BoundedQueue>> = {
+            let mut subs = subs.lock().unwrap();
+            subs.list.retain(|s| !s.queue.is_rx_closed());
+            subs.list.iter().map(|s| s.queue.clone()).collect()
+        };
+        for queue in queues {
+            // Errors mean the subscription was dropped in the meantime, it will
+            // be removed with the next frame
+            let _ = queue.push(frame.clone()).await;
+        }
+    }
+    subs.lock().unwrap().close();
+}
@@ -0,0 +1,197 @@
+//! A HostClient talking to a target over SEGGER RTT channels
+//!
+//! See [`HostClient::new_rtt()`], and `server::impls::rtt` for the target side.
+
+use std::{thread, time::Duration};
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+
+use crate::{
+    framing::CobsFraming,
+    header::VarSeqKind,
+    host_client::{
+        channels::{ChannelRx, ChannelTx, TokioSpawn},
+        HostClient, HostClientConfig,
+    },
+};
+
+/// The size of the chunks read from the up channel
+const READ_CHUNK: usize = 1024;
+
+/// The interval at which idle channels are polled by [`HostClient::new_rtt()`]
+pub const DEFAULT_RTT_POLL: Duration = Duration::from_millis(1);
+
+/// The RTT channels of a target, accessed through a debug probe
+///
+/// Implemented by the application, typically with `probe-rs`, which needs the
+/// attached core for every access:
+///
+/// ```rust,ignore
+/// struct ProbeLink {
+///     session: probe_rs::Session,
+///     up: probe_rs::rtt::UpChannel,
+///     down: probe_rs::rtt::DownChannel,
+/// }
+///
+/// impl RttLink for ProbeLink {
+///     type Error = probe_rs::rtt::Error;
+///
+///     fn read_up(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+///         let mut core = self.session.core(0)?;
+///         self.up.read(&mut core, buf)
+///     }
+///
+///     fn write_down(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+///         let mut core = self.session.core(0)?;
+///         self.down.write(&mut core, data)
+///     }
+/// }
+/// ```
+pub trait RttLink: Send + 'static {
+    /// The error type of the probe
+    type Error: core::fmt::Debug;
+
+    /// Read as many bytes of the up channel (target to host) as available into
+    /// `buf`, without waiting
+    ///
+    /// Returns the number of bytes read, which is 0 if the channel is empty.
+    fn read_up(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Write as many bytes of `data` as fit into the down channel (host to
+    /// target), without waiting
+    ///
+    /// Returns the number of bytes written, which is 0 if the channel is full.
+    fn write_down(&mut self, data: &[u8]) -> Result<usize, Self::Error>;
+}
+
+/// # RTT Constructor Methods
+///
+/// These methods are used to create a new [HostClient] instance for use with
+/// the RTT channels of a target, accessed through a debug probe.
+///
+/// **Requires feature**: `rtt-host`
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a new [HostClient] talking to a target over the RTT channels of
+    /// `link`
+    ///
+    /// Frames are COBS encoded, like with the `rtt-server` impl on the target.
+    /// Probe accesses are blocking, so the channels are polled by a separate
+    /// thread, every [`DEFAULT_RTT_POLL`] while both are idle. The thread stops
+    /// when the client is closed, or when `link` returns an error, which closes
+    /// the client.
+    ///
+    /// `err_uri_path` is the path associated with the `WireErr` message type.
+    ///
+    /// This constructor must be called from within a tokio runtime.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,ignore
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::standard_icd::{WireError, ERROR_PATH};
+    ///
+    /// let client = HostClient::<WireError>::new_rtt(
+    ///     ProbeLink { session, up, down },
+    ///     // the URI/path for `Error` messages
+    ///     ERROR_PATH,
+    ///     // Outgoing queue depth in messages
+    ///     8,
+    ///     // Use one-byte sequence numbers
+    ///     VarSeqKind::Seq1,
+    /// );
+    /// ```
+    pub fn new_rtt<L: RttLink>(
+        link: L,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Self {
+        let config = HostClientConfig::new_default(seq_no_kind, err_uri_path, outgoing_depth);
+        Self::new_rtt_with_config(link, DEFAULT_RTT_POLL, &config)
+    }
+
+    /// Create a new [HostClient] with the given poll interval and configuration
+    ///
+    /// See [`HostClient::new_rtt`] for more details
+    pub fn new_rtt_with_config<L: RttLink>(
+        link: L,
+        poll: Duration,
+        config: &HostClientConfig<'_>,
+    ) -> Self {
+        let (out_tx, out_rx) = mpsc::channel(config.outgoing_depth);
+        let (in_tx, in_rx) = mpsc::channel(config.outgoing_depth);
+        thread::Builder::new()
+            .name("postcard-rpc-rtt".into())
+            .spawn(move || poll_link(link, poll, out_rx, in_tx))
+            .expect("Spawning a thread should not fail");
+        HostClient::new_with_framing_and_config(
+            ChannelTx { tx: out_tx },
+            ChannelRx { rx: in_rx },
+            CobsFraming,
+            TokioSpawn,
+            config,
+        )
+    }
+}
+
+/// Move encoded frames from `outgoing` to the down channel, and chunks of the up
+/// channel to `incoming`, until either side is closed
+fn poll_link<L: RttLink>(
+    mut link: L,
+    poll: Duration,
+    mut outgoing: mpsc::Receiver<Vec<u8>>,
+    incoming: mpsc::Sender<Vec<u8>>,
+) {
+    let mut pending: Vec<u8> = Vec::new();
+    let mut offset = 0;
+    let mut chunk = [0u8; READ_CHUNK];
+    loop {
+        let mut busy = false;
+
+        if offset == pending.len() {
+            pending.clear();
+            offset = 0;
+            match outgoing.try_recv() {
+                Ok(frame) => pending = frame,
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        if offset < pending.len() {
+            match link.write_down(&pending[offset..]) {
+                Ok(used) => {
+                    offset += used;
+                    busy |= used != 0;
+                }
+                Err(e) => {
+                    tracing::warn!("RTT write error: {e:?}, closing");
+                    return;
+                }
+            }
+        }
+
+        match link.read_up(&mut chunk) {
+            Ok(0) => {}
+            Ok(used) => {
+                busy = true;
+                if incoming.blocking_send(chunk[..used].to_vec()).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::warn!("RTT read error: {e:?}, closing");
+                return;
+            }
+        }
+
+        if !busy {
+            thread::sleep(poll);
+        }
+    }
+}
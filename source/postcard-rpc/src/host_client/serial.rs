@@ -77,6 +77,7 @@ where
                 buf: Box::new([0u8; 1024]),
                 acc: Box::new(CobsAccumulator::new()),
                 pending: VecDeque::new(),
+                spare: Vec::new(),
             },
             SerialSpawn,
             seq_no_kind,
@@ -161,6 +162,8 @@ struct SerialWireRx {
     buf: Box<[u8; 1024]>,
     acc: Box<CobsAccumulator<1024>>,
     pending: VecDeque<Vec<u8>>,
+    /// Emptied buffers, reused for frames added to `pending`
+    spare: Vec<Vec<u8>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -173,19 +176,33 @@ impl WireRx for SerialWireRx {
     type Error = SerialWireRxError;
 
     #[inline]
-    fn receive(&mut self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
-        self.recv_inner()
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = Vec::new();
+        self.recv_inner(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[inline]
+    fn receive_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.recv_inner(buf)
     }
 }
 
 impl SerialWireRx {
-    async fn recv_inner(&mut self) -> Result<Vec<u8>, SerialWireRxError> {
+    async fn recv_inner(&mut self, buf: &mut Vec<u8>) -> Result<(), SerialWireRxError> {
         // Receive until we've gotten AT LEAST one message, though we will continue
         // consuming and buffering any read (partial) messages, to ensure they are not lost.
         loop {
-            // Do we have any messages already prepared?
-            if let Some(p) = self.pending.pop_front() {
-                return Ok(p);
+            // Do we have any messages already prepared? Hand out the prepared
+            // buffer, and keep the given one for later frames
+            if let Some(mut p) = self.pending.pop_front() {
+                core::mem::swap(buf, &mut p);
+                p.clear();
+                self.spare.push(p);
+                return Ok(());
             }
 
             // Nothing in the pending queue, do a read to see if we can pull more
@@ -211,7 +228,9 @@ impl SerialWireRx {
                     }
                     // We got a message! Attempt to dispatch it
                     FeedResult::Success { data, remaining } => {
-                        self.pending.push_back(data.to_vec());
+                        let mut frame = self.spare.pop().unwrap_or_default();
+                        frame.extend_from_slice(data);
+                        self.pending.push_back(frame);
                         remaining
                     }
                 };
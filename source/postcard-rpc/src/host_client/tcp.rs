@@ -0,0 +1,215 @@
+use std::{future::Future, net::SocketAddr};
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+use crate::{
+    header::VarSeqKind,
+    host_client::{HostClient, HostClientConfig, WireRx, WireSpawn, WireTx},
+};
+
+/// The largest frame we are willing to receive, by default
+const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// # TCP Constructor Methods
+///
+/// These methods are used to create a new [HostClient] instance for use with
+/// a TCP socket, e.g. to talk to a host-side simulator. Each frame is prefixed
+/// with its length, as a 4-byte little-endian integer.
+///
+/// See [`server::impls::tcp`](crate::server::impls::tcp) for the matching server.
+///
+/// **Requires feature**: `tcp`
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a new [HostClient]
+    ///
+    /// `addr` is the address of the server. `err_uri_path` is the path associated
+    /// with the `WireErr` message type.
+    ///
+    /// This constructor is available when the `tcp` feature is enabled, and
+    /// must be called from within a tokio runtime.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use postcard_rpc::standard_icd::{WireError, ERROR_PATH};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let client = HostClient::<WireError>::try_new_tcp(
+    ///     // the address of the simulator
+    ///     "127.0.0.1:4000".parse().unwrap(),
+    ///     // the URI/path for `Error` messages
+    ///     ERROR_PATH,
+    ///     // Outgoing queue depth in messages
+    ///     8,
+    ///     // Use one-byte sequence numbers
+    ///     VarSeqKind::Seq1,
+    /// ).unwrap();
+    /// # }
+    /// ```
+    pub fn try_new_tcp(
+        addr: SocketAddr,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Result<Self, String> {
+        let config = HostClientConfig::new_default(seq_no_kind, err_uri_path, outgoing_depth);
+        Self::try_new_tcp_with_config(addr, &config)
+    }
+
+    /// Create a new [HostClient] with the given configuration
+    ///
+    /// The [`max_frame_size`](HostClientConfig::max_frame_size) limits the size of
+    /// received frames, and defaults to 1 MiB.
+    ///
+    /// See [`HostClient::try_new_tcp`] for more details
+    pub fn try_new_tcp_with_config(
+        addr: SocketAddr,
+        config: &HostClientConfig<'_>,
+    ) -> Result<Self, String> {
+        let stream =
+            std::net::TcpStream::connect(addr).map_err(|e| format!("Connect Error: {e:?}"))?;
+        stream
+            .set_nonblocking(true)
+            .map_err(|e| format!("Socket Error: {e:?}"))?;
+        let _ = stream.set_nodelay(true);
+        let stream = TcpStream::from_std(stream).map_err(|e| format!("Socket Error: {e:?}"))?;
+
+        let (rx, tx) = stream.into_split();
+
+        Ok(HostClient::new_with_wire_and_config(
+            TcpWireTx { tx },
+            TcpWireRx {
+                rx,
+                max_frame_len: config.max_frame_size.unwrap_or(MAX_FRAME_LEN),
+            },
+            TcpSpawn,
+            config,
+        ))
+    }
+
+    /// Create a new [HostClient]
+    ///
+    /// Panics if we couldn't connect to the server.
+    ///
+    /// See [`HostClient::try_new_tcp`] for more details
+    pub fn new_tcp(
+        addr: SocketAddr,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Self {
+        Self::try_new_tcp(addr, err_uri_path, outgoing_depth, seq_no_kind).unwrap()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// Wire Interface Implementation
+//////////////////////////////////////////////////////////////////////////////
+
+/// Tokio TCP Wire Interface Implementor
+///
+/// Uses Tokio for spawning tasks
+struct TcpSpawn;
+
+impl WireSpawn for TcpSpawn {
+    fn spawn(&mut self, fut: impl Future<Output = ()> + Send + 'static) {
+        // Explicitly drop the joinhandle as it impls Future and this makes
+        // clippy mad if you just let it drop implicitly
+        core::mem::drop(tokio::task::spawn(fut));
+    }
+}
+
+/// Tokio TCP Wire Transmit Interface Implementor
+struct TcpWireTx {
+    tx: OwnedWriteHalf,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum TcpWireTxError {
+    #[error("Transfer Error on Send")]
+    Transfer(#[from] std::io::Error),
+    #[error("Frame too large to send")]
+    TooLarge,
+}
+
+impl WireTx for TcpWireTx {
+    type Error = TcpWireTxError;
+
+    #[inline]
+    fn send(&mut self, data: Vec<u8>) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.send_inner(data)
+    }
+}
+
+impl TcpWireTx {
+    async fn send_inner(&mut self, data: Vec<u8>) -> Result<(), TcpWireTxError> {
+        let len = u32::try_from(data.len()).map_err(|_| TcpWireTxError::TooLarge)?;
+        self.tx.write_all(&len.to_le_bytes()).await?;
+        self.tx.write_all(&data).await?;
+        Ok(())
+    }
+}
+
+/// Tokio TCP Wire Receive Interface Implementor
+struct TcpWireRx {
+    rx: OwnedReadHalf,
+    max_frame_len: usize,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum TcpWireRxError {
+    #[error("Transfer Error on Recv")]
+    Transfer(#[from] std::io::Error),
+    #[error("Received a frame larger than the maximum of {0} bytes")]
+    TooLarge(usize),
+}
+
+impl WireRx for TcpWireRx {
+    type Error = TcpWireRxError;
+
+    #[inline]
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = Vec::new();
+        self.recv_inner(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[inline]
+    fn receive_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.recv_inner(buf)
+    }
+}
+
+impl TcpWireRx {
+    async fn recv_inner(&mut self, buf: &mut Vec<u8>) -> Result<(), TcpWireRxError> {
+        let mut len = [0u8; 4];
+        self.rx.read_exact(&mut len).await?;
+        let len = u32::from_le_bytes(len) as usize;
+        // A length this large is more likely to be a framing error than a real
+        // message, and we can't recover sync either way
+        if len > self.max_frame_len {
+            return Err(TcpWireRxError::TooLarge(self.max_frame_len));
+        }
+        buf.clear();
+        buf.resize(len, 0);
+        self.rx.read_exact(buf).await?;
+        Ok(())
+    }
+}
@@ -2,84 +2,59 @@
 
 use crate::{
     header::VarSeqKind,
-    host_client::{HostClient, WireRx, WireSpawn, WireTx},
+    host_client::{channels::TokioSpawn, HostClient, HostClientConfig, ReconnectConfig},
     standard_icd::WireError,
 };
-use core::fmt::Display;
+use core::future::Future;
 use tokio::sync::mpsc;
 
+pub use crate::host_client::channels::{ChannelError, ChannelRx, ChannelTx};
+
 /// Create a new HostClient from the given server channels
 pub fn new_from_channels(
     tx: mpsc::Sender<Vec<u8>>,
     rx: mpsc::Receiver<Vec<u8>>,
     seq_kind: VarSeqKind,
 ) -> HostClient<WireError> {
-    HostClient::new_with_wire(
-        ChannelTx { tx },
-        ChannelRx { rx },
-        TokSpawn,
-        seq_kind,
-        crate::standard_icd::ERROR_PATH,
-        64,
-    )
-}
-
-/// Server error kinds
-#[derive(Debug)]
-pub enum ChannelError {
-    /// Rx was closed
-    RxClosed,
-    /// Tx was closed
-    TxClosed,
-}
-
-impl Display for ChannelError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <Self as core::fmt::Debug>::fmt(self, f)
-    }
+    HostClient::new_with_channels(tx, rx, crate::standard_icd::ERROR_PATH, 64, seq_kind)
 }
 
-impl std::error::Error for ChannelError {}
-
-/// Trait impl for channels
-pub struct ChannelRx {
-    rx: mpsc::Receiver<Vec<u8>>,
-}
-/// Trait impl for channels
-pub struct ChannelTx {
+/// Create a new HostClient from the given server channels and configuration
+pub fn new_from_channels_with_config(
     tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    config: &HostClientConfig<'_>,
+) -> HostClient<WireError> {
+    HostClient::new_with_channels_and_config(tx, rx, config)
 }
-/// Trait impl for channels
-pub struct ChannelSpawn;
 
-impl WireRx for ChannelRx {
-    type Error = ChannelError;
-
-    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
-        match self.rx.recv().await {
-            Some(v) => {
-                #[cfg(test)]
-                println!("c<-s: {v:?}");
-                Ok(v)
+/// Create a new HostClient that reconnects using channels from the given function
+///
+/// See [`HostClient::new_with_reconnect()`] for more details.
+pub fn new_from_channels_with_reconnect<F, Fut>(
+    mut connect: F,
+    seq_kind: VarSeqKind,
+) -> HostClient<WireError>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>), ChannelError>>
+        + Send
+        + 'static,
+{
+    let config = HostClientConfig::new_default(seq_kind, crate::standard_icd::ERROR_PATH, 64);
+    HostClient::new_with_reconnect(
+        move || {
+            let fut = connect();
+            async move {
+                let (tx, rx) = fut.await?;
+                Ok::<_, ChannelError>((ChannelTx { tx }, ChannelRx { rx }))
             }
-            None => Err(ChannelError::RxClosed),
-        }
-    }
-}
-
-impl WireTx for ChannelTx {
-    type Error = ChannelError;
-
-    async fn send(&mut self, data: Vec<u8>) -> Result<(), Self::Error> {
-        #[cfg(test)]
-        println!("c->s: {data:?}");
-        self.tx.send(data).await.map_err(|_| ChannelError::TxClosed)
-    }
+        },
+        TokioSpawn,
+        &config,
+        ReconnectConfig::default(),
+    )
 }
 
-struct TokSpawn;
-impl WireSpawn for TokSpawn {
-    fn spawn(&mut self, fut: impl std::future::Future<Output = ()> + Send + 'static) {
-        _ = tokio::task::spawn(fut);
-    }
-}
+/// Trait impl for channels
+pub struct ChannelSpawn;
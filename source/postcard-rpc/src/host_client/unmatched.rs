@@ -0,0 +1,198 @@
+//! Reporting replies that nobody was waiting for
+//!
+//! See [`HostClient::set_unmatched_hook()`].
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq},
+    host_client::{HostClient, HostContext},
+    Key,
+};
+
+/// The number of completed requests remembered to classify unmatched replies
+const RECENT_REQUESTS: usize = 32;
+
+/// A frame received by a [`HostClient`] that no request, subscription, or
+/// stream was waiting for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnmatchedReply {
+    /// The header of the frame
+    pub header: VarHeader,
+    /// Why the frame was not matched
+    pub kind: UnmatchedKind,
+}
+
+/// The reason a frame was not matched, see [`UnmatchedReply`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmatchedKind {
+    /// The frame is the response or error to one of the last 32 completed
+    /// requests, which is no longer waited for, e.g. because the request timed
+    /// out or was dropped, or because the reply was sent twice
+    Expired,
+    /// The frame doesn't belong to any recently completed request, e.g. a reply
+    /// with a wrong key or sequence number, or a message on a topic nobody is
+    /// subscribed to
+    Unknown,
+}
+
+/// A callback observing unmatched frames
+///
+/// See [`HostClient::set_unmatched_hook()`].
+pub type UnmatchedHookFn = dyn Fn(&UnmatchedReply) + Send + Sync;
+
+/// A recently completed request, see [`Unmatched::completed()`]
+struct RecentRequest {
+    seq_no: u32,
+    resp_key: Key,
+    err_key: Key,
+}
+
+/// The unmatched frame tracking of a [`HostContext`]
+pub(crate) struct Unmatched {
+    count: AtomicU64,
+    warn: AtomicBool,
+    hook: RwLock<Option<Arc<UnmatchedHookFn>>>,
+    recent: Mutex<VecDeque<RecentRequest>>,
+}
+
+impl Unmatched {
+    pub(crate) fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            warn: AtomicBool::new(false),
+            hook: RwLock::new(None),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_REQUESTS)),
+        }
+    }
+
+    /// Remember that the request with `seq_no` no longer waits for its reply
+    pub(crate) fn completed(&self, seq_no: u32, resp_key: Key, err_key: Key) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_REQUESTS {
+            recent.pop_front();
+        }
+        recent.push_back(RecentRequest {
+            seq_no,
+            resp_key,
+            err_key,
+        });
+    }
+
+    /// Count, log, and report a frame that was not matched
+    pub(crate) fn report(&self, header: VarHeader) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let seq_no: u32 = header.seq_no.into();
+        let expired = self.recent.lock().unwrap().iter().any(|r| {
+            r.seq_no == seq_no
+                && (VarKey::Key8(r.resp_key) == header.key || VarKey::Key8(r.err_key) == header.key)
+        });
+        let kind = if expired {
+            UnmatchedKind::Expired
+        } else {
+            UnmatchedKind::Unknown
+        };
+
+        if self.warn.load(Ordering::Relaxed) {
+            tracing::warn!(
+                key = ?header.key,
+                seq_no,
+                ?kind,
+                "Dropping frame nobody was waiting for"
+            );
+        }
+        let hook = self.hook.read().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(&UnmatchedReply { header, kind });
+        }
+    }
+}
+
+/// Remembers a request as completed when the request completes, see
+/// [`HostContext::track_completion()`]
+pub(crate) struct CompletionGuard<'a> {
+    ctx: &'a HostContext,
+    seq_no: u32,
+    resp_key: Key,
+    err_key: Key,
+}
+
+impl Drop for CompletionGuard<'_> {
+    fn drop(&mut self) {
+        self.ctx
+            .unmatched
+            .completed(self.seq_no, self.resp_key, self.err_key);
+    }
+}
+
+impl HostContext {
+    /// Remember the request with `seq_no` as completed once the returned guard
+    /// is dropped, whether it received a reply or not
+    pub(crate) fn track_completion(
+        &self,
+        seq_no: VarSeq,
+        resp_key: Key,
+        err_key: Key,
+    ) -> CompletionGuard<'_> {
+        CompletionGuard {
+            ctx: self,
+            seq_no: seq_no.into(),
+            resp_key,
+            err_key,
+        }
+    }
+}
+
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// The number of frames received so far that nobody was waiting for
+    ///
+    /// See [`HostClient::set_unmatched_hook()`].
+    pub fn unmatched_replies(&self) -> u64 {
+        self.ctx.unmatched.count.load(Ordering::Relaxed)
+    }
+
+    /// Observe every frame received that nobody was waiting for
+    ///
+    /// Frames that don't match a pending request, a subscription, or a response
+    /// stream are dropped. These are usually replies that arrived after their
+    /// request timed out, but may also hint at protocol bugs, e.g. a server
+    /// replying with the wrong sequence number. `hook` is called with the header
+    /// of each such frame, and whether it belongs to one of the last 32
+    /// completed requests, see [`UnmatchedKind`]. It is called from the I/O
+    /// worker, so it should return quickly. Replaces any previously set hook.
+    ///
+    /// Only requests made with [`send_resp()`](Self::send_resp) and its
+    /// variants, or with [`send_cb()`](Self::send_cb), are remembered.
+    pub fn set_unmatched_hook<F>(&self, hook: F)
+    where
+        F: Fn(&UnmatchedReply) + Send + Sync + 'static,
+    {
+        *self.ctx.unmatched.hook.write().unwrap() = Some(Arc::new(hook));
+    }
+
+    /// Remove the hook set with [`HostClient::set_unmatched_hook()`]
+    pub fn clear_unmatched_hook(&self) {
+        *self.ctx.unmatched.hook.write().unwrap() = None;
+    }
+
+    /// Log a warning with the key and sequence number of every frame received
+    /// that nobody was waiting for
+    ///
+    /// Disabled by default, as replies arriving after a timeout are expected
+    /// on lossy or slow links.
+    pub fn set_unmatched_warnings(&self, enabled: bool) {
+        self.ctx.unmatched.warn.store(enabled, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,129 @@
+//! Sending resumable uploads, see [`ChunkedUpload`]
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    host_client::{HostClient, HostErr},
+    upload::{UploadAck, UploadChunk},
+    Endpoint,
+};
+
+/// The most bytes the offset and length of an [`UploadChunk`] take, besides its data
+const CHUNK_OVERHEAD: usize = 5 + 5;
+
+/// The sending side of an upload, on the host
+///
+/// Sends `data` in chunks to an endpoint taking [`UploadChunk`]s, one chunk at a
+/// time, continuing at the offset acknowledged by the device. See the
+/// [`upload`](crate::upload) module for the protocol.
+///
+/// ```rust,ignore
+/// let mut upload = ChunkedUpload::new(&image, 512);
+/// while let Err(e) = upload.run::<FirmwareEndpoint, _>(&client).await {
+///     println!("Upload interrupted at {} of {} bytes: {e:?}", upload.acked(), image.len());
+///     client = reconnect().await;
+/// }
+/// ```
+///
+/// **Requires feature**: `upload`
+#[derive(Debug, Clone)]
+pub struct ChunkedUpload<'a> {
+    data: &'a [u8],
+    chunk_len: usize,
+    acked: u32,
+}
+
+impl<'a> ChunkedUpload<'a> {
+    /// Upload `data` in chunks of up to `chunk_len` bytes, starting at offset zero
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `chunk_len` is zero, or if `data` is larger than 4GiB.
+    pub fn new(data: &'a [u8], chunk_len: usize) -> Self {
+        assert!(chunk_len != 0, "chunks must not be empty");
+        assert!(
+            u32::try_from(data.len()).is_ok(),
+            "uploads are limited to 4GiB"
+        );
+        Self {
+            data,
+            chunk_len,
+            acked: 0,
+        }
+    }
+
+    /// The offset up to which the device acknowledged the data
+    pub fn acked(&self) -> u32 {
+        self.acked
+    }
+
+    /// The current length of chunks
+    ///
+    /// May be smaller than the initial length, see [`ChunkedUpload::run()`].
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Whether the device acknowledged all of the data
+    pub fn is_done(&self) -> bool {
+        self.acked as usize == self.data.len()
+    }
+
+    /// Send the remaining chunks to the endpoint `E`, until the device acknowledged
+    /// all of the data
+    ///
+    /// If an error is returned, e.g. because the connection was lost, calling this
+    /// again, possibly with a new client, resumes the upload: the device answers
+    /// the first chunk with the offset it actually received up to, which may be
+    /// more than [`ChunkedUpload::acked()`] if an acknowledgement was lost.
+    ///
+    /// Chunks rejected with [`HostErr::BodyTooLarge`] are sent again in smaller
+    /// chunks fitting into the receive buffer of the device. Returns
+    /// [`HostErr::BadResponse`] if the device acknowledges an offset beyond the
+    /// data, or doesn't acknowledge a chunk at its acknowledged offset, e.g.
+    /// because storing it failed.
+    pub async fn run<E, WireErr>(
+        &mut self,
+        client: &HostClient<WireErr>,
+    ) -> Result<(), HostErr<WireErr>>
+    where
+        E: Endpoint<Request = UploadChunk<'a>, Response = UploadAck>,
+        WireErr: DeserializeOwned + Schema,
+    {
+        // At least one chunk is sent, so even an empty chunk, e.g. of an empty upload,
+        // learns the acknowledged offset
+        loop {
+            let offset = self.acked as usize;
+            let end = self.data.len().min(offset + self.chunk_len);
+            let chunk = UploadChunk {
+                offset: self.acked,
+                data: &self.data[offset..end],
+            };
+            let ack = match client.send_resp::<E>(&chunk).await {
+                Ok(ack) => ack,
+                Err(HostErr::BodyTooLarge { max }) => {
+                    let fits = (max as usize).saturating_sub(CHUNK_OVERHEAD);
+                    if fits == 0 || fits >= self.chunk_len {
+                        return Err(HostErr::BodyTooLarge { max });
+                    }
+                    self.chunk_len = fits;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if ack.acked as usize > self.data.len() {
+                return Err(HostErr::BadResponse);
+            }
+            let stalled = ack.acked == chunk.offset && !chunk.data.is_empty();
+            self.acked = ack.acked;
+            if stalled {
+                return Err(HostErr::BadResponse);
+            }
+            if self.is_done() {
+                return Ok(());
+            }
+        }
+    }
+}
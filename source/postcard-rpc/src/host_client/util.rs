@@ -1,31 +1,195 @@
-use core::time::Duration;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 // the contents of this file can probably be moved up to `mod.rs`
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::VecDeque, fmt::Debug, sync::Arc};
 
 use maitake_sync::WaitQueue;
 use postcard_schema::Schema;
 use serde::de::DeserializeOwned;
 use tokio::{
     select,
-    sync::{broadcast, mpsc, Mutex},
+    sync::{broadcast, mpsc, Mutex, Notify},
 };
 use tracing::{debug, trace, warn};
 
 use crate::{
-    header::{VarHeader, VarKey, VarSeqKind},
+    header::{VarHeader, VarKey, VarSeq, VarSeqKind, WireHeader, PROTOCOL_VERSION},
     host_client::{
-        HostClient, HostContext, ProcessError, RpcFrame, WireContext, WireRx, WireSpawn, WireTx,
+        frame_pool::PoolGuard, BackpressurePolicy, ConnectionState, HostClient, HostContext,
+        ProcessError, RpcFrame, SeqNoGenerator, WireContext, WireRx, WireSpawn, WireTx,
     },
-    Key,
+    FrameDirection, Key,
 };
 
 #[derive(Default, Debug)]
 pub(crate) struct Subscriptions {
     pub(crate) exclusive_list: Vec<(Key, mpsc::Sender<RpcFrame>)>,
     pub(crate) broadcast_list: Vec<(Key, broadcast::Sender<RpcFrame>)>,
+    pub(crate) bounded_list: Vec<(Key, BoundedSender)>,
+    pub(crate) stream_list: Vec<StreamSender>,
+    /// Listeners for errors not matching any in-flight request
+    pub(crate) unsolicited_errors: Option<broadcast::Sender<RpcFrame>>,
+    /// Listeners for every received frame
+    pub(crate) all_frames: Option<broadcast::Sender<RpcFrame>>,
     pub(crate) stopped: bool,
 }
 
+/// The receiving end of an in-flight streaming endpoint request
+#[derive(Debug)]
+pub(crate) struct StreamSender {
+    /// The sequence number of the request
+    pub(crate) seq_no: VarSeq,
+    /// The response, stream end, and error keys, in that order
+    pub(crate) keys: [Key; 3],
+    pub(crate) tx: mpsc::UnboundedSender<RpcFrame>,
+}
+
+impl StreamSender {
+    /// Does the given header belong to this stream?
+    fn matches(&self, hdr: &VarHeader) -> bool {
+        hdr.seq_no == self.seq_no && self.keys.iter().any(|k| VarKey::Key8(*k) == hdr.key)
+    }
+
+    /// Is the given header the last frame of this stream?
+    fn is_last(&self, hdr: &VarHeader) -> bool {
+        VarKey::Key8(self.keys[0]) != hdr.key
+    }
+}
+
+/// A bounded queue of frames, shared between the I/O worker and a single
+/// [`BoundedSubscription`](crate::host_client::BoundedSubscription)
+pub(crate) struct BoundedQueue {
+    state: std::sync::Mutex<BoundedQueueState>,
+    depth: usize,
+    policy: BackpressurePolicy,
+    dropped: AtomicU64,
+    readable: Notify,
+    writable: Notify,
+}
+
+#[derive(Default)]
+struct BoundedQueueState {
+    queue: VecDeque<RpcFrame>,
+    tx_closed: bool,
+    rx_closed: bool,
+}
+
+impl core::fmt::Debug for BoundedQueue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BoundedQueue")
+            .field("depth", &self.depth)
+            .field("policy", &self.policy)
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BoundedQueue {
+    /// Create a new queue, holding at most `depth` (but at least one) frames
+    pub(crate) fn new(depth: usize, policy: BackpressurePolicy) -> Arc<Self> {
+        let depth = depth.max(1);
+        Arc::new(Self {
+            state: std::sync::Mutex::new(BoundedQueueState {
+                queue: VecDeque::with_capacity(depth),
+                ..Default::default()
+            }),
+            depth,
+            policy,
+            dropped: AtomicU64::new(0),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        })
+    }
+
+    /// Push a frame, applying the backpressure policy if the queue is full.
+    ///
+    /// Returns an error if the receiving half has been dropped.
+    pub(crate) async fn push(&self, frame: RpcFrame) -> Result<(), ()> {
+        let mut frame = Some(frame);
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.rx_closed {
+                    return Err(());
+                }
+                if state.queue.len() >= self.depth {
+                    match self.policy {
+                        BackpressurePolicy::DropOldest => {
+                            state.queue.pop_front();
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        BackpressurePolicy::DropNewest => {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            return Ok(());
+                        }
+                        BackpressurePolicy::Block => {}
+                    }
+                }
+                if state.queue.len() < self.depth {
+                    if let Some(frame) = frame.take() {
+                        state.queue.push_back(frame);
+                    }
+                    self.readable.notify_one();
+                    return Ok(());
+                }
+            }
+            // We are blocking: wait until the receiver takes a frame. `notify_one`
+            // stores a permit, so we can't miss a wakeup between the unlock and here.
+            self.writable.notified().await;
+        }
+    }
+
+    /// Take the next frame, waiting if the queue is empty.
+    ///
+    /// Returns `None` once the sending half has been closed and all frames taken.
+    pub(crate) async fn pop(&self) -> Option<RpcFrame> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(frame) = state.queue.pop_front() {
+                    self.writable.notify_one();
+                    return Some(frame);
+                }
+                if state.tx_closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    /// The number of frames dropped due to backpressure
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Mark the receiving half as closed
+    pub(crate) fn close_rx(&self) {
+        self.state.lock().unwrap().rx_closed = true;
+        self.writable.notify_one();
+    }
+
+    /// Has the receiving half been closed?
+    pub(crate) fn is_rx_closed(&self) -> bool {
+        self.state.lock().unwrap().rx_closed
+    }
+}
+
+/// The sending half of a [`BoundedQueue`], closes the queue when dropped
+#[derive(Debug)]
+pub(crate) struct BoundedSender {
+    pub(crate) queue: Arc<BoundedQueue>,
+}
+
+impl Drop for BoundedSender {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().tx_closed = true;
+        self.queue.readable.notify_one();
+    }
+}
+
 /// A basic cancellation-token
 ///
 /// Used to terminate (and signal termination of) worker tasks
@@ -79,6 +243,17 @@ impl Default for Stopper {
 }
 
 /// HostClient configuration
+///
+/// New settings may be added in minor versions, so configs are created with
+/// [`HostClientBuilder`] or [`Default`], and then adjusted:
+///
+/// ```rust
+/// use postcard_rpc::host_client::HostClientConfig;
+///
+/// let mut config = HostClientConfig::default();
+/// config.outgoing_depth = 8;
+/// ```
+#[non_exhaustive]
 pub struct HostClientConfig<'c> {
     /// The sequence kind to use
     pub seq_kind: VarSeqKind,
@@ -93,6 +268,213 @@ pub struct HostClientConfig<'c> {
     ///
     /// Does not apply to subscribe_multi channels.
     pub subscriber_timeout_if_full: Duration,
+
+    /// The source of sequence numbers for outgoing requests.
+    ///
+    /// If `None`, a [`CounterSeqNoGenerator`](crate::host_client::CounterSeqNoGenerator) is used.
+    /// Numbers still in use by pending requests are skipped, see [`SeqNoGenerator`].
+    pub seq_no_generator: Option<Arc<dyn SeqNoGenerator>>,
+
+    /// The time to wait for the reply to a request, if any.
+    ///
+    /// If `None`, requests wait for a reply until the client is closed. See
+    /// [`HostClient::send_resp_timeout()`](crate::host_client::HostClient::send_resp_timeout).
+    pub default_timeout: Option<Duration>,
+
+    /// The number of incoming frames the transport may buffer before they are
+    /// processed.
+    ///
+    /// Used by the `nusb` transport as the number of IN transfers kept in flight.
+    pub incoming_depth: usize,
+
+    /// The size of the largest incoming frame, in bytes.
+    ///
+    /// Used by the `nusb` transport as the size of IN transfers, and by the TCP
+    /// and framed transports as the largest frame they accept. If `None`, the
+    /// default of the transport is used.
+    pub max_frame_size: Option<usize>,
+
+    /// The depth of subscriptions created by the client itself, e.g. by
+    /// [`HostClient::get_schema_report()`](crate::host_client::HostClient::get_schema_report),
+    /// and of subscriptions requested with a depth of zero.
+    pub subscription_depth: usize,
+
+    /// The number of buffers for incoming frames kept for reuse.
+    ///
+    /// Frames are received into these buffers, and the messages handed to
+    /// subscriptions and pending requests are copied into them, so that at high
+    /// message rates, incoming frames need no allocations. Buffers return to the
+    /// pool once their message has been deserialized, so the pool should be
+    /// larger than the depth of busy subscriptions. If zero, every frame is
+    /// allocated.
+    pub frame_pool: usize,
+}
+
+/// The default of [`HostClientConfig::incoming_depth`]
+pub(crate) const DEFAULT_INCOMING_DEPTH: usize = 4;
+/// The default of [`HostClientConfig::subscription_depth`]
+pub(crate) const DEFAULT_SUBSCRIPTION_DEPTH: usize = 64;
+/// The default of [`HostClientConfig::frame_pool`]
+pub(crate) const DEFAULT_FRAME_POOL: usize = 32;
+
+impl<'c> HostClientConfig<'c> {
+    /// The configuration used by the constructors that don't take one
+    pub(crate) fn new_default(
+        seq_kind: VarSeqKind,
+        err_uri_path: &'c str,
+        outgoing_depth: usize,
+    ) -> Self {
+        Self {
+            seq_kind,
+            err_uri_path,
+            outgoing_depth,
+            subscriber_timeout_if_full: Duration::ZERO,
+            seq_no_generator: None,
+            default_timeout: None,
+            incoming_depth: DEFAULT_INCOMING_DEPTH,
+            max_frame_size: None,
+            subscription_depth: DEFAULT_SUBSCRIPTION_DEPTH,
+            frame_pool: DEFAULT_FRAME_POOL,
+        }
+    }
+}
+
+impl Default for HostClientConfig<'_> {
+    /// The defaults of [`HostClientBuilder`], with the standard
+    /// [`ERROR_PATH`](crate::standard_icd::ERROR_PATH)
+    fn default() -> Self {
+        Self::new_default(VarSeqKind::Seq1, crate::standard_icd::ERROR_PATH, 64)
+    }
+}
+
+/// A builder for a [`HostClientConfig`], checking its settings
+///
+/// Settings that are not set keep their defaults: one-byte sequence numbers,
+/// an outgoing depth of 64, an incoming depth of 4, the default frame size of the
+/// transport, a subscription depth of 64, and a pool of 32 frame buffers.
+///
+/// ```rust
+/// use postcard_rpc::{header::VarSeqKind, host_client::HostClientBuilder, standard_icd::ERROR_PATH};
+///
+/// let config = HostClientBuilder::new(ERROR_PATH)
+///     .seq_kind(VarSeqKind::Seq2)
+///     .outgoing_depth(256)
+///     .incoming_depth(16)
+///     .max_frame_size(16 * 1024)
+///     .subscription_depth(1024)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// The config is then passed to a constructor, e.g.
+/// [`HostClient::new_with_wire_and_config()`].
+pub struct HostClientBuilder<'c> {
+    config: HostClientConfig<'c>,
+}
+
+impl<'c> HostClientBuilder<'c> {
+    /// Start building a config, with `err_uri_path` as the path associated with
+    /// the `WireErr` message type
+    pub fn new(err_uri_path: &'c str) -> Self {
+        Self {
+            config: HostClientConfig::new_default(VarSeqKind::Seq1, err_uri_path, 64),
+        }
+    }
+
+    /// Set the [`seq_kind`](HostClientConfig::seq_kind)
+    pub fn seq_kind(mut self, seq_kind: VarSeqKind) -> Self {
+        self.config.seq_kind = seq_kind;
+        self
+    }
+
+    /// Set the [`outgoing_depth`](HostClientConfig::outgoing_depth), which must not be zero
+    pub fn outgoing_depth(mut self, depth: usize) -> Self {
+        self.config.outgoing_depth = depth;
+        self
+    }
+
+    /// Set the [`incoming_depth`](HostClientConfig::incoming_depth), which must not be zero
+    pub fn incoming_depth(mut self, depth: usize) -> Self {
+        self.config.incoming_depth = depth;
+        self
+    }
+
+    /// Set the [`max_frame_size`](HostClientConfig::max_frame_size), which must
+    /// fit at least the largest header
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.config.max_frame_size = Some(size);
+        self
+    }
+
+    /// Set the [`subscription_depth`](HostClientConfig::subscription_depth), which
+    /// must not be zero
+    pub fn subscription_depth(mut self, depth: usize) -> Self {
+        self.config.subscription_depth = depth;
+        self
+    }
+
+    /// Set the [`frame_pool`](HostClientConfig::frame_pool) size, or disable the
+    /// pool with zero
+    pub fn frame_pool(mut self, buffers: usize) -> Self {
+        self.config.frame_pool = buffers;
+        self
+    }
+
+    /// Set the [`subscriber_timeout_if_full`](HostClientConfig::subscriber_timeout_if_full)
+    pub fn subscriber_timeout_if_full(mut self, timeout: Duration) -> Self {
+        self.config.subscriber_timeout_if_full = timeout;
+        self
+    }
+
+    /// Set the [`seq_no_generator`](HostClientConfig::seq_no_generator)
+    pub fn seq_no_generator(mut self, generator: Arc<dyn SeqNoGenerator>) -> Self {
+        self.config.seq_no_generator = Some(generator);
+        self
+    }
+
+    /// Set the [`default_timeout`](HostClientConfig::default_timeout)
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.config.default_timeout = Some(timeout);
+        self
+    }
+
+    /// Check the settings, and return the config
+    pub fn build(self) -> Result<HostClientConfig<'c>, HostClientConfigError> {
+        let config = self.config;
+        let depths = [
+            ("outgoing_depth", config.outgoing_depth),
+            ("incoming_depth", config.incoming_depth),
+            ("subscription_depth", config.subscription_depth),
+        ];
+        if let Some((name, _)) = depths.iter().find(|(_, depth)| *depth == 0) {
+            return Err(HostClientConfigError::ZeroDepth(name));
+        }
+        if let Some(size) = config.max_frame_size {
+            if size < WireHeader::WIRE_SIZE {
+                return Err(HostClientConfigError::FrameSizeTooSmall {
+                    min: WireHeader::WIRE_SIZE,
+                    got: size,
+                });
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// An invalid setting of a [`HostClientBuilder`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, thiserror::Error)]
+pub enum HostClientConfigError {
+    /// The named depth was zero
+    #[error("`{0}` must not be zero")]
+    ZeroDepth(&'static str),
+    /// The maximum frame size can't fit the largest header
+    #[error("`max_frame_size` must be at least {min} bytes, got {got}")]
+    FrameSizeTooSmall {
+        /// The smallest allowed size
+        min: usize,
+        /// The configured size
+        got: usize,
+    },
 }
 
 impl<WireErr> HostClient<WireErr>
@@ -116,12 +498,7 @@ where
         WRX: WireRx,
         WSP: WireSpawn,
     {
-        let config = HostClientConfig {
-            seq_kind,
-            err_uri_path,
-            outgoing_depth,
-            subscriber_timeout_if_full: Duration::ZERO,
-        };
+        let config = HostClientConfig::new_default(seq_kind, err_uri_path, outgoing_depth);
 
         Self::new_with_wire_and_config(tx, rx, sp, &config)
     }
@@ -145,7 +522,12 @@ where
 
         let WireContext { outgoing, incoming } = wire_ctx;
 
-        sp.spawn(out_worker(tx, outgoing, me.stopper.clone()));
+        sp.spawn(out_worker(
+            tx,
+            outgoing,
+            incoming.clone(),
+            me.stopper.clone(),
+        ));
         sp.spawn(in_worker(
             rx,
             incoming,
@@ -157,14 +539,27 @@ where
     }
 }
 
+/// The reason an I/O worker stopped operating
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WorkerExit {
+    /// The wire returned an error, the connection has likely been lost
+    Disconnected,
+    /// All clients have been dropped, no further communication is possible
+    Closed,
+}
+
 /// Output worker, feeding frames to the `Client`.
-async fn out_worker<W>(wire: W, rec: mpsc::Receiver<RpcFrame>, stop: Stopper)
-where
+async fn out_worker<W>(
+    wire: W,
+    mut rec: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    stop: Stopper,
+) where
     W: WireTx,
     W::Error: Debug,
 {
     let cancel_fut = stop.wait_stopped();
-    let operate_fut = out_worker_inner(wire, rec);
+    let operate_fut = out_worker_inner(wire, &mut rec, &host_ctx);
     select! {
         biased;
         _ = cancel_fut => {},
@@ -175,7 +570,11 @@ where
     }
 }
 
-async fn out_worker_inner<W>(mut wire: W, mut rec: mpsc::Receiver<RpcFrame>)
+pub(crate) async fn out_worker_inner<W>(
+    mut wire: W,
+    rec: &mut mpsc::Receiver<RpcFrame>,
+    host_ctx: &HostContext,
+) -> WorkerExit
 where
     W: WireTx,
     W::Error: Debug,
@@ -183,11 +582,25 @@ where
     loop {
         let Some(msg) = rec.recv().await else {
             tracing::info!("Receiver Closed");
-            return;
+            return WorkerExit::Closed;
         };
-        if let Err(e) = wire.send(msg.to_bytes()).await {
-            tracing::error!("Output Queue Error: {e:?}, exiting");
-            return;
+        let frame = host_ctx.attach_deadline(msg.header.seq_no, msg.to_bytes());
+        #[cfg(feature = "compress")]
+        let frame = host_ctx.compress(frame);
+        #[cfg(feature = "fragment")]
+        let frames = host_ctx.fragment(frame);
+        #[cfg(not(feature = "fragment"))]
+        let frames = [frame];
+        for frame in frames {
+            #[cfg(feature = "auth")]
+            let frame = host_ctx.sign(frame);
+            #[cfg(feature = "checksum")]
+            let frame = host_ctx.append_checksum(frame);
+            host_ctx.tap(FrameDirection::Outgoing, &frame);
+            if let Err(e) = wire.send(frame).await {
+                tracing::error!("Output Queue Error: {e:?}, exiting");
+                return WorkerExit::Disconnected;
+            }
         }
     }
 }
@@ -203,7 +616,7 @@ async fn in_worker<W>(
     W::Error: Debug,
 {
     let cancel_fut = stop.wait_stopped();
-    let operate_fut = in_worker_inner(wire, host_ctx, subscriptions.clone());
+    let operate_fut = in_worker_inner(wire, host_ctx.clone(), subscriptions.clone());
     select! {
         biased;
         _ = cancel_fut => {},
@@ -212,32 +625,82 @@ async fn in_worker<W>(
             stop.stop();
         },
     }
+    close_all(&host_ctx, &subscriptions).await;
+}
+
+/// Mark the connection as permanently closed
+pub(crate) async fn close_all(host_ctx: &HostContext, subscriptions: &Mutex<Subscriptions>) {
+    host_ctx.conn.send_replace(ConnectionState::Closed);
+    host_ctx.close_callbacks();
+
     // If we stop, purge the subscription list so that it is clear that no more messages are coming
     // TODO: Have a "stopped" flag to prevent later additions (e.g. sub after store?)
     let mut guard = subscriptions.lock().await;
     guard.stopped = true;
     guard.exclusive_list.clear();
     guard.broadcast_list.clear();
+    guard.bounded_list.clear();
+    guard.stream_list.clear();
+    guard.unsolicited_errors = None;
+    guard.all_frames = None;
 }
 
-async fn in_worker_inner<W>(
+pub(crate) async fn in_worker_inner<W>(
     mut wire: W,
     host_ctx: Arc<HostContext>,
     subscriptions: Arc<Mutex<Subscriptions>>,
-) where
+) -> WorkerExit
+where
     W: WireRx,
     W::Error: Debug,
 {
+    #[cfg(feature = "fragment")]
+    let mut reassembler = crate::fragment::Reassembler::default();
     loop {
-        let Ok(res) = wire.receive().await else {
+        let mut res = host_ctx.pool.take();
+        if wire.receive_into(&mut res).await.is_err() {
             warn!("in_worker: wire receive error, exiting");
-            return;
+            return WorkerExit::Disconnected;
+        }
+        host_ctx.tap(FrameDirection::Incoming, &res);
+
+        #[cfg(feature = "checksum")]
+        let Some(res) = host_ctx.verify_checksum(res) else {
+            warn!("Dropping frame with an invalid checksum");
+            continue;
+        };
+
+        #[cfg(feature = "auth")]
+        let Some(res) = host_ctx.verify(res) else {
+            warn!("Dropping frame with an invalid authentication tag");
+            continue;
+        };
+
+        #[cfg(feature = "fragment")]
+        let res = match reassembler.push(res) {
+            Ok(Some(res)) => res,
+            Ok(None) => continue,
+            Err(id) => {
+                warn!("Dropping fragmented frame {id} with lost fragments");
+                continue;
+            }
         };
 
-        let Some((hdr, body)) = VarHeader::take_from_slice(&res) else {
+        #[cfg(feature = "compress")]
+        let Some(res) = host_ctx.decompress(res) else {
+            warn!("Dropping frame that could not be decompressed");
+            continue;
+        };
+
+        let res = PoolGuard::new(&host_ctx.pool, res);
+        let Some((hdr, version, body)) = VarHeader::take_versioned_from_slice(&res) else {
             warn!("Header decode error!");
             continue;
         };
+        if version != PROTOCOL_VERSION {
+            warn!("Dropping frame with protocol version {version}, expected {PROTOCOL_VERSION}");
+            continue;
+        }
 
         trace!("in_worker received {hdr:?}");
 
@@ -247,9 +710,38 @@ async fn in_worker_inner<W>(
             let mut subs_guard = subscriptions.lock().await;
             let key = hdr.key;
 
+            // Every frame is passed to the frame streams, before being routed as usual
+            if let Some(tx) = subs_guard.all_frames.as_ref() {
+                let frame = RpcFrame {
+                    header: hdr,
+                    body: host_ctx.pool.copy_of(body),
+                };
+                // A SendError means that there are no more receivers
+                if tx.send(frame).is_err() {
+                    subs_guard.all_frames = None;
+                }
+            }
+
+            // First, check in-flight streaming requests. These never overlap
+            // with topics, so we can skip the rest if there's a match
+            if let Some(idx) = subs_guard.stream_list.iter().position(|s| s.matches(&hdr)) {
+                let stream = &subs_guard.stream_list[idx];
+                let last = stream.is_last(&hdr);
+                let frame = RpcFrame {
+                    header: hdr,
+                    body: host_ctx.pool.copy_of(body),
+                };
+                // Err means the stream was dropped
+                if stream.tx.send(frame).is_err() || last {
+                    debug!("Dropping response stream");
+                    subs_guard.stream_list.swap_remove(idx);
+                }
+                continue;
+            }
+
             // Remove if sending fails
             //
-            // First, check the broadcast channels
+            // Then, check the broadcast channels
             let remove_mul_sub = if let Some((_h, m)) = subs_guard
                 .broadcast_list
                 .iter()
@@ -258,7 +750,7 @@ async fn in_worker_inner<W>(
                 handled = true;
                 let frame = RpcFrame {
                     header: hdr,
-                    body: body.to_vec(),
+                    body: host_ctx.pool.copy_of(body),
                 };
                 let res = m.send(frame);
 
@@ -282,7 +774,7 @@ async fn in_worker_inner<W>(
                 handled = true;
                 let frame = RpcFrame {
                     header: hdr,
-                    body: body.to_vec(),
+                    body: host_ctx.pool.copy_of(body),
                 };
 
                 let res = m.try_send(frame);
@@ -314,6 +806,29 @@ async fn in_worker_inner<W>(
                 false
             };
 
+            let remove_bnd_sub = if let Some((_h, m)) = subs_guard
+                .bounded_list
+                .iter()
+                .find(|(k, _)| VarKey::Key8(*k) == key)
+            {
+                handled = true;
+                let frame = RpcFrame {
+                    header: hdr,
+                    body: host_ctx.pool.copy_of(body),
+                };
+
+                // Err means the subscription was dropped
+                m.queue.push(frame).await.is_err()
+            } else {
+                false
+            };
+
+            if remove_bnd_sub {
+                debug!("Dropping bounded subscription");
+                subs_guard
+                    .bounded_list
+                    .retain(|(k, _)| VarKey::Key8(*k) != key);
+            }
             if remove_exl_sub {
                 debug!("Dropping exclusive subscription");
                 subs_guard
@@ -334,15 +849,29 @@ async fn in_worker_inner<W>(
 
         let frame = RpcFrame {
             header: hdr,
-            body: body.to_vec(),
+            body: host_ctx.pool.copy_of(body),
         };
 
         match host_ctx.process_did_wake(frame) {
             Ok(true) => debug!("Handled message via map"),
+            Ok(false) if VarKey::Key8(host_ctx.err_key) == hdr.key => {
+                debug!("Unsolicited error");
+                let mut subs_guard = subscriptions.lock().await;
+                if let Some(tx) = subs_guard.unsolicited_errors.as_ref() {
+                    let frame = RpcFrame {
+                        header: hdr,
+                        body: host_ctx.pool.copy_of(body),
+                    };
+                    // A SendError means that there are no more receivers
+                    if tx.send(frame).is_err() {
+                        subs_guard.unsolicited_errors = None;
+                    }
+                }
+            }
             Ok(false) => debug!("Message not handled"),
             Err(ProcessError::Closed) => {
                 warn!("Got process error, quitting");
-                return;
+                return WorkerExit::Closed;
             }
         }
     }
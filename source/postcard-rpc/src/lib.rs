@@ -116,12 +116,21 @@
 #![deny(unused_imports)]
 #![deny(rustdoc::broken_intra_doc_links)]
 
+#[cfg(feature = "dyn-dispatch")]
+extern crate alloc;
+
 /// Re-export used by macros
 #[doc(hidden)]
 pub use postcard;
 /// Re-export used by macros
 #[doc(hidden)]
 pub use postcard_schema;
+/// Re-export used by macros
+#[doc(hidden)]
+pub use serde;
+
+#[cfg(feature = "macros")]
+pub use postcard_rpc_macros::{define_endpoint, generate_client, PostcardRpcCommands};
 
 use header::{VarKey, VarKeyKind};
 use postcard_schema::{schema::NamedType, Schema};
@@ -129,13 +138,33 @@ use serde::{Deserialize, Serialize};
 
 pub mod header;
 mod macros;
+pub mod max_size;
 pub mod server;
 pub mod standard_icd;
+pub mod tolerant;
 pub mod uniques;
 
 #[cfg(feature = "cobs")]
 pub mod accumulator;
 
+#[cfg(feature = "auth")]
+pub mod auth;
+
+#[cfg(feature = "checksum")]
+pub mod checksum;
+
+#[cfg(feature = "compress")]
+pub mod compress;
+
+#[cfg(feature = "fragment")]
+pub mod fragment;
+
+#[cfg(feature = "framing")]
+pub mod framing;
+
+#[cfg(feature = "upload")]
+pub mod upload;
+
 #[cfg(feature = "use-std")]
 pub mod host_client;
 
@@ -174,6 +203,39 @@ pub struct Key2([u8; 2]);
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Key4([u8; 4]);
 
+/// Write `bytes` as lowercase hex, without separators
+pub(crate) fn write_hex(f: &mut core::fmt::Formatter<'_>, bytes: &[u8]) -> core::fmt::Result {
+    bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+}
+
+/// Formats a full [`Key`] as lowercase hex, the same way the compacted keys display
+///
+/// [`Key`] is defined in `postcard-schema`, so it can't implement `Display` here.
+pub fn key_hex(key: &Key) -> impl core::fmt::Display {
+    header::VarKey::Key8(*key)
+}
+
+/// Formats the key as it appears on the wire, as lowercase hex
+impl core::fmt::Display for Key1 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_hex(f, &[self.0])
+    }
+}
+
+/// Formats the key as it appears on the wire, as lowercase hex
+impl core::fmt::Display for Key2 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+/// Formats the key as it appears on the wire, as lowercase hex
+impl core::fmt::Display for Key4 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
 impl Key1 {
     /// Convert from a 2-byte key
     ///
@@ -428,6 +490,27 @@ pub trait Endpoint {
     const RESP_KEY2: Key2 = Key2::from_key8(Self::RESP_KEY);
     /// The unique [Key1] identifying the Response
     const RESP_KEY1: Key1 = Key1::from_key8(Self::RESP_KEY);
+    /// Whether handling the same request more than once is harmless
+    ///
+    /// Clients only retry requests to idempotent endpoints automatically, see
+    /// `RetryPolicy` in the `host_client` module. Set with
+    /// `#[define_endpoint(idempotent)]`, or by implementing this trait by hand.
+    const IDEMPOTENT: bool = false;
+    /// Whether requests missing trailing fields are decoded with their defaults
+    ///
+    /// Tolerant endpoints must use [`tolerant::request_key()`] as their
+    /// [`REQ_KEY`](Self::REQ_KEY), see the [`tolerant`] module. Set with
+    /// `#[define_endpoint(tolerant)]`, or by implementing this trait by hand.
+    const TOLERANT: bool = false;
+    /// The largest serialized size of a request, if bounded
+    ///
+    /// Clients reject larger requests before sending them, see
+    /// `HostClient::send_resp()` in the `host_client` module. Defaults to the
+    /// size computed from the schema of the request, see [`max_size::of()`],
+    /// which is `None` for requests holding sequences, strings, or maps. Set a
+    /// cap with `#[define_endpoint(max_req_size = 256)]`, or by implementing this
+    /// trait by hand.
+    const MAX_REQ_SIZE: Option<usize> = max_size::of::<Self::Request>();
 }
 
 /// A marker trait denoting a single topic
@@ -461,6 +544,18 @@ pub enum TopicDirection {
     ToClient,
 }
 
+/// The direction of a frame, from the point of view of the side observing it
+///
+/// Passed to wire taps, e.g. [`HostClient::set_wire_tap()`][crate::host_client::HostClient::set_wire_tap]
+/// or [`Sender::set_wire_tap()`][crate::server::Sender::set_wire_tap].
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum FrameDirection {
+    /// The frame is about to be sent
+    Outgoing,
+    /// The frame was just received
+    Incoming,
+}
+
 /// An overview of all topics (in and out) and endpoints
 ///
 /// Typically generated by the [`define_dispatch!()`] macro. Contains a list
@@ -554,4 +649,15 @@ mod test {
         assert!(k1.const_cmp(&k3));
         assert!(!k1.const_cmp(&k2));
     }
+
+    #[test]
+    fn display() {
+        use crate::header::VarKey;
+
+        assert_eq!(VarKey::Key8(K2).to_string(), "0102030405060709");
+        assert_eq!(crate::key_hex(&K2).to_string(), "0102030405060709");
+        assert_eq!(Key4::from_key8(K2).to_string(), "0307030e");
+        assert_eq!(Key2::from_key8(K2).to_string(), "040d");
+        assert_eq!(VarKey::Key1(Key1::from_key8(K2)).to_string(), "09");
+    }
 }
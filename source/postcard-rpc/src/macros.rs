@@ -54,6 +54,12 @@ macro_rules! endpoint {
 ///
 /// NOTE: Do NOT set the `omit_std` flag in your code! This is for internal use only.
 ///
+/// Endpoints with nothing to send, e.g. trigger-style endpoints, may use `()` as
+/// their request or response type. Postcard encodes `()` as zero bytes, so these
+/// are sent as frames with only a header, and received without decoding a body.
+/// Their keys are calculated from the path and the schema of `()`, like those of
+/// any other type.
+///
 /// ```rust
 /// # use postcard_schema::Schema;
 /// # use serde::{Serialize, Deserialize};
@@ -91,6 +97,28 @@ macro_rules! endpoint {
 ///     | Endpoint2      | Req2          | Resp2         | "endpoints/two"   |
 /// }
 /// ```
+///
+/// ## Services
+///
+/// Subsystems may each declare an endpoint with the same path, e.g. `"status"`, by
+/// placing their endpoints in a service with `service = "motor";`. This prefixes
+/// each path with the service name and a `/`, so `"status"` becomes `"motor/status"`,
+/// which is also used to calculate the keys. The dispatcher of each subsystem can
+/// then be mounted under its service with [`nest!`][crate::nest].
+///
+/// ```rust
+/// # use postcard_rpc::endpoints;
+/// endpoints!{
+///     list = MOTOR_ENDPOINTS;
+///     service = "motor";
+///     | EndpointTy          | RequestTy     | ResponseTy    | Path      |
+///     | ----------          | ---------     | ----------    | ----      |
+///     | MotorStatusEndpoint | ()            | u32           | "status"  |
+/// }
+///
+/// # use postcard_rpc::Endpoint;
+/// assert_eq!(MotorStatusEndpoint::PATH, "motor/status");
+/// ```
 #[macro_export]
 macro_rules! endpoints {
     (@ep_tys $([[$($meta:meta)?] $ep_name:ident])*) => {
@@ -159,7 +187,16 @@ macro_rules! endpoints {
             }
         }
     };
+    (@path [] $path_str:literal) => { $path_str };
+    (@path [$service:literal] $path_str:literal) => { concat!($service, "/", $path_str) };
+    (list = $list_name:ident; service = $service:literal; $($rest:tt)*) => {
+        $crate::endpoints!(@table [$service] list = $list_name; $($rest)*);
+    };
+    (list = $list_name:ident; $($rest:tt)*) => {
+        $crate::endpoints!(@table [] list = $list_name; $($rest)*);
+    };
     (
+           @table $service:tt
            list = $list_name:ident;
            $(omit_std = $omit:tt;)?
            | EndpointTy     | RequestTy                                | ResponseTy                                  | Path              | $( Cfg           |)?
@@ -184,9 +221,9 @@ macro_rules! endpoints {
             impl < $($($req_lt,)+)? $($($resp_lt,)+)? > $crate::Endpoint for $ep_name < $($($req_lt,)+)? $($($resp_lt,)+)? > {
                 type Request = $req_ty $(< $($req_lt,)+ >)?;
                 type Response = $resp_ty $(< $($resp_lt,)+ >)?;
-                const PATH: &'static str = $path_str;
-                const REQ_KEY: $crate::Key = $crate::Key::for_path::<$req_ty>($path_str);
-                const RESP_KEY: $crate::Key = $crate::Key::for_path::<$resp_ty>($path_str);
+                const PATH: &'static str = $crate::endpoints!(@path $service $path_str);
+                const REQ_KEY: $crate::Key = $crate::Key::for_path::<$req_ty>($crate::endpoints!(@path $service $path_str));
+                const RESP_KEY: $crate::Key = $crate::Key::for_path::<$resp_ty>($crate::endpoints!(@path $service $path_str));
             }
         )*
 
@@ -275,6 +312,9 @@ macro_rules! topic {
 ///    | Topic2         | Message2      | "topics/two"      |
 /// }
 /// ```
+///
+/// Like with [`endpoints!`][crate::endpoints], the paths may be placed in a
+/// service with `service = "motor";`, after the `direction`.
 #[macro_export]
 macro_rules! topics {
     (@tp_tys ( $dir:expr ) $([[$($meta:meta)?] $tp_name:ident])*) => {
@@ -348,7 +388,16 @@ macro_rules! topics {
             }
         }
     };
+    (@path [] $path_str:literal) => { $path_str };
+    (@path [$service:literal] $path_str:literal) => { concat!($service, "/", $path_str) };
+    (list = $list_name:ident; direction = $direction:expr; service = $service:literal; $($rest:tt)*) => {
+        $crate::topics!(@table [$service] list = $list_name; direction = $direction; $($rest)*);
+    };
+    (list = $list_name:ident; direction = $direction:expr; $($rest:tt)*) => {
+        $crate::topics!(@table [] list = $list_name; direction = $direction; $($rest)*);
+    };
     (
+        @table $service:tt
         list = $list_name:ident;
         direction = $direction:expr;
         $(omit_std = $omit:tt;)?
@@ -372,8 +421,8 @@ macro_rules! topics {
             $(#[$meta])?
             impl $(< $($msg_lt),+ >)? $crate::Topic for $tp_name $(< $($msg_lt,)+ >)? {
                 type Message = $msg_ty $(< $($msg_lt,)+ >)?;
-                const PATH: &'static str = $path_str;
-                const TOPIC_KEY: $crate::Key = $crate::Key::for_path::<$msg_ty>($path_str);
+                const PATH: &'static str = $crate::topics!(@path $service $path_str);
+                const TOPIC_KEY: $crate::Key = $crate::Key::for_path::<$msg_ty>($crate::topics!(@path $service $path_str));
             }
         )*
 
@@ -421,6 +470,57 @@ mod concat_test {
     }
 }
 
+/// Create the [`DeviceInfo`][crate::standard_icd::DeviceInfo] of the firmware
+///
+/// Only the product string is required. By default, the version is the
+/// `CARGO_PKG_VERSION` of the crate using the macro, and the git hash and build
+/// time are read from the `POSTCARD_RPC_GIT_HASH` and `POSTCARD_RPC_BUILD_TIME`
+/// environment variables at compile time, which a build script may set with
+/// `cargo:rustc-env`. Unset variables are sent as empty strings. Any field may
+/// also be given explicitly, in the order shown:
+///
+/// ```rust
+/// use postcard_rpc::{device_info, standard_icd::DeviceInfo};
+///
+/// const INFO: DeviceInfo<'static> = device_info!(product = "widget");
+/// const CUSTOM: DeviceInfo<'static> = device_info!(
+///     product = "widget",
+///     version = "1.2.3",
+///     git_hash = "0123abcd",
+///     build_time = "2024-01-01T00:00:00Z",
+/// );
+/// assert_eq!(INFO.version, env!("CARGO_PKG_VERSION"));
+/// assert_eq!(CUSTOM.git_hash, "0123abcd");
+/// ```
+///
+/// The result can be passed to [`define_dispatch!`][crate::define_dispatch] with
+/// the `device_info` option.
+#[macro_export]
+macro_rules! device_info {
+    (
+        product = $product:expr
+        $(, version = $version:expr)?
+        $(, git_hash = $git_hash:expr)?
+        $(, build_time = $build_time:expr)?
+        $(,)?
+    ) => {
+        $crate::standard_icd::DeviceInfo {
+            product: $product,
+            version: $crate::device_info!(@or [$($version)?] ::core::env!("CARGO_PKG_VERSION")),
+            git_hash: $crate::device_info!(@or [$($git_hash)?] match ::core::option_env!("POSTCARD_RPC_GIT_HASH") {
+                Some(hash) => hash,
+                None => "",
+            }),
+            build_time: $crate::device_info!(@or [$($build_time)?] match ::core::option_env!("POSTCARD_RPC_BUILD_TIME") {
+                Some(time) => time,
+                None => "",
+            }),
+        }
+    };
+    (@or [] $default:expr) => { $default };
+    (@or [$value:expr] $default:expr) => { $value };
+}
+
 /// A helper function for logging with the [Sender][crate::server::Sender]
 #[macro_export]
 macro_rules! sender_fmt {
@@ -476,11 +576,11 @@ mod endpoints_test {
         for ep in ENDPOINT_LIST.types {
             println!("{}", OwnedNamedType::from(*ep));
         }
-        assert_eq!(ENDPOINT_LIST.types.len(), 3);
+        assert_eq!(ENDPOINT_LIST.types.len(), 17);
         for ep in ENDPOINT_LIST.endpoints {
             println!("{}", ep.0);
         }
-        assert_eq!(ENDPOINT_LIST.endpoints.len(), 5);
+        assert_eq!(ENDPOINT_LIST.endpoints.len(), 11);
     }
 
     #[test]
@@ -497,9 +597,9 @@ mod endpoints_test {
         for tp in TOPICS_OUT_LIST.topics {
             println!("TP OUT: {}", tp.0);
         }
-        assert_eq!(TOPICS_IN_LIST.types.len(), 1);
-        assert_eq!(TOPICS_IN_LIST.topics.len(), 3);
-        assert_eq!(TOPICS_OUT_LIST.types.len(), 5);
-        assert_eq!(TOPICS_OUT_LIST.topics.len(), 3);
+        assert_eq!(TOPICS_IN_LIST.types.len(), 3);
+        assert_eq!(TOPICS_IN_LIST.topics.len(), 6);
+        assert_eq!(TOPICS_OUT_LIST.types.len(), 7);
+        assert_eq!(TOPICS_OUT_LIST.topics.len(), 4);
     }
 }
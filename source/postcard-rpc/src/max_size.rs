@@ -0,0 +1,200 @@
+//! The maximum serialized size of a type, computed from its schema
+//!
+//! Postcard encodes most primitives with a bounded number of bytes, so types made
+//! only of primitives, arrays, tuples, structs, options, and enums have a largest
+//! possible serialized size, which is computed at compile time by [`of()`]:
+//!
+//! ```rust
+//! use postcard_rpc::max_size;
+//!
+//! // A varint takes up to 5 bytes for a u32, plus one byte for the option tag
+//! assert_eq!(max_size::of::<(u8, Option<u32>)>(), Some(7));
+//! // Sequences, strings, and maps are unbounded
+//! assert_eq!(max_size::of::<&str>(), None);
+//! ```
+//!
+//! The size of a request is used as the default [`Endpoint::MAX_REQ_SIZE`](crate::Endpoint::MAX_REQ_SIZE), which
+//! clients check before sending requests. Endpoints with unbounded requests, e.g.
+//! holding a `heapless::Vec`, whose capacity is not part of the schema, may
+//! declare a cap instead, with `#[define_endpoint(max_req_size = 256)]`.
+//!
+//! `usize` and `isize` are counted as 64 bit values, so sizes are the same on all
+//! targets.
+
+use postcard_schema::{
+    schema::{DataModelType, DataModelVariant, NamedType, NamedValue, NamedVariant},
+    Schema,
+};
+
+/// The largest serialized size of `T`, or `None` if it is unbounded
+pub const fn of<T: Schema + ?Sized>() -> Option<usize> {
+    of_schema(T::SCHEMA)
+}
+
+/// The largest serialized size of a type with the schema `nt`, or `None` if it
+/// is unbounded
+pub const fn of_schema(nt: &NamedType) -> Option<usize> {
+    match nt.ty {
+        DataModelType::Bool | DataModelType::I8 | DataModelType::U8 => Some(1),
+        DataModelType::I16 | DataModelType::U16 => Some(varint_max(2)),
+        DataModelType::I32 | DataModelType::U32 => Some(varint_max(4)),
+        DataModelType::I64 | DataModelType::U64 | DataModelType::Isize | DataModelType::Usize => {
+            Some(varint_max(8))
+        }
+        DataModelType::I128 | DataModelType::U128 => Some(varint_max(16)),
+        DataModelType::F32 => Some(4),
+        DataModelType::F64 => Some(8),
+        // Encoded as a string of at most four UTF-8 bytes
+        DataModelType::Char => Some(1 + 4),
+        DataModelType::Unit | DataModelType::UnitStruct => Some(0),
+        DataModelType::Option(nt) => match of_schema(nt) {
+            Some(size) => size.checked_add(1),
+            None => None,
+        },
+        DataModelType::NewtypeStruct(nt) => of_schema(nt),
+        DataModelType::Tuple(nts) | DataModelType::TupleStruct(nts) => of_tuple(nts),
+        DataModelType::Struct(nvs) => of_struct(nvs),
+        DataModelType::Enum(nvs) => of_enum(nvs),
+        DataModelType::String
+        | DataModelType::ByteArray
+        | DataModelType::Seq(_)
+        | DataModelType::Map { .. }
+        | DataModelType::Schema => None,
+    }
+}
+
+/// The most bytes a varint of an integer of `bytes` bytes takes
+const fn varint_max(bytes: usize) -> usize {
+    (bytes * 8).div_ceil(7)
+}
+
+/// The number of bytes the varint of `value` takes
+const fn varint_len(mut value: usize) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+const fn of_tuple(nts: &[&NamedType]) -> Option<usize> {
+    let mut total = 0usize;
+    let mut i = 0;
+    while i < nts.len() {
+        total = match of_schema(nts[i]) {
+            Some(size) => match total.checked_add(size) {
+                Some(total) => total,
+                None => return None,
+            },
+            None => return None,
+        };
+        i += 1;
+    }
+    Some(total)
+}
+
+const fn of_struct(nvs: &[&NamedValue]) -> Option<usize> {
+    let mut total = 0usize;
+    let mut i = 0;
+    while i < nvs.len() {
+        total = match of_schema(nvs[i].ty) {
+            Some(size) => match total.checked_add(size) {
+                Some(total) => total,
+                None => return None,
+            },
+            None => return None,
+        };
+        i += 1;
+    }
+    Some(total)
+}
+
+/// The largest variant, after the varint of its index
+const fn of_enum(nvs: &[&NamedVariant]) -> Option<usize> {
+    let mut largest = 0usize;
+    let mut i = 0;
+    while i < nvs.len() {
+        let size = match nvs[i].ty {
+            DataModelVariant::UnitVariant => Some(0),
+            DataModelVariant::NewtypeVariant(nt) => of_schema(nt),
+            DataModelVariant::TupleVariant(nts) => of_tuple(nts),
+            DataModelVariant::StructVariant(nvs) => of_struct(nvs),
+        };
+        match size {
+            Some(size) if size > largest => largest = size,
+            Some(_) => {}
+            None => return None,
+        }
+        i += 1;
+    }
+    largest.checked_add(varint_len(nvs.len().saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod test {
+    use postcard_schema::Schema;
+    use serde::Serialize;
+
+    use super::of;
+
+    #[derive(Serialize, Schema)]
+    struct Fixed {
+        a: u8,
+        b: i32,
+        c: [u16; 4],
+        d: Option<f32>,
+        e: Mode,
+    }
+
+    #[derive(Serialize, Schema)]
+    enum Mode {
+        Off,
+        On(u64),
+        Both { a: u8, b: u8 },
+    }
+
+    #[derive(Serialize, Schema)]
+    struct Unbounded<'a> {
+        a: u8,
+        name: &'a str,
+    }
+
+    #[test]
+    fn bounded() {
+        // 1 + 5 + 4 * 3 + (1 + 4) + (1 + 10)
+        assert_eq!(of::<Fixed>(), Some(34));
+        let largest = Fixed {
+            a: u8::MAX,
+            b: i32::MIN,
+            c: [u16::MAX; 4],
+            d: Some(1.0),
+            e: Mode::On(u64::MAX),
+        };
+        let used = postcard::to_stdvec(&largest).unwrap();
+        assert_eq!(used.len(), 34);
+
+        // The other variants fit the bound too
+        for e in [
+            Mode::Off,
+            Mode::Both {
+                a: u8::MAX,
+                b: u8::MAX,
+            },
+        ] {
+            let smaller = Fixed { e, ..largest };
+            let used = postcard::to_stdvec(&smaller).unwrap();
+            assert!(used.len() < 34);
+        }
+
+        assert_eq!(of::<u128>(), Some(19));
+        assert_eq!(of::<char>(), Some(5));
+        assert_eq!(of::<()>(), Some(0));
+    }
+
+    #[test]
+    fn unbounded() {
+        assert_eq!(of::<Unbounded<'_>>(), None);
+        assert_eq!(of::<Option<&[u8]>>(), None);
+    }
+}
@@ -0,0 +1,298 @@
+//! Requests sent by the device, and answered by the host
+//!
+//! Sometimes the device needs to ask the host something, e.g. for a credential,
+//! and wait for the answer. With [`DeviceRequests`], the device sends a request
+//! to an [`Endpoint`] like the host does, and the host answers it with a handler
+//! registered with `HostClient::serve_device_requests()`.
+//!
+//! Requests are sent with the `REQ_KEY` of the endpoint, and a sequence number
+//! chosen by the device. The host answers with the same sequence number, and the
+//! full 8-byte `RESP_KEY` of the endpoint, or the [`ERROR_KEY`] if the request
+//! could not be handled. Answers are only accepted if both their key and their
+//! sequence number match a pending request.
+//!
+//! Answers are received by the server like any other frame, so the dispatcher
+//! must be wrapped with a [`DeviceRequestDispatch`], which passes all answers to
+//! pending requests to the [`DeviceRequests`], and all other frames to the
+//! dispatcher.
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::device_request::{DeviceRequestDispatch, DeviceRequests};
+//!
+//! // Up to 2 requests in flight, with responses of up to 64 bytes
+//! static REQUESTS: DeviceRequests<CriticalSectionRawMutex, 2, 64> = DeviceRequests::new();
+//!
+//! let dispatcher = DeviceRequestDispatch::new(&REQUESTS, MyApp::new(context, spawner.into()));
+//! let mut server = Server::new(tx_impl, rx_impl, buf, dispatcher, vkk);
+//! let sender = server.sender();
+//!
+//! // In any task
+//! let credential = REQUESTS.request::<GetCredential, _>(&sender, &()).await?;
+//! ```
+//!
+//! **Requires feature**: `device-requests`
+
+use core::{cell::RefCell, future::poll_fn, task::Poll};
+
+use embassy_sync_0_7::{
+    blocking_mutex::{raw::RawMutex, Mutex},
+    waitqueue::WakerRegistration,
+};
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{Dispatch, Sender, WireTx},
+    standard_icd::{WireError, ERROR_KEY},
+    Endpoint, Key,
+};
+
+/// An error of a request sent with [`DeviceRequests::request()`]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceRequestError<E> {
+    /// All slots for pending requests are in use
+    Busy,
+    /// The request could not be sent
+    Send(E),
+    /// The host could not handle the request
+    Host(WireError),
+    /// The response of the host did not fit in the buffer of the slot
+    ResponseTooLarge,
+    /// The response of the host could not be deserialized
+    DeserFailed,
+}
+
+/// The answer to a pending request, stored in the buffer of its slot
+#[derive(Clone, Copy)]
+enum Answer {
+    Response(usize),
+    Error(usize),
+    TooLarge,
+}
+
+struct Slot<const B: usize> {
+    /// The `RESP_KEY` and sequence number of the pending request, if any
+    pending: Option<(Key, u32)>,
+    answer: Option<Answer>,
+    buf: [u8; B],
+    waker: WakerRegistration,
+}
+
+impl<const B: usize> Slot<B> {
+    const fn new() -> Self {
+        Self {
+            pending: None,
+            answer: None,
+            buf: [0u8; B],
+            waker: WakerRegistration::new(),
+        }
+    }
+}
+
+struct Inner<const N: usize, const B: usize> {
+    next_seq: u32,
+    slots: [Slot<B>; N],
+}
+
+/// Up to `N` pending requests sent by the device, with responses of up to `B`
+/// bytes
+///
+/// See the [module docs](self) for details.
+pub struct DeviceRequests<M: RawMutex, const N: usize, const B: usize> {
+    inner: Mutex<M, RefCell<Inner<N, B>>>,
+}
+
+impl<M: RawMutex, const N: usize, const B: usize> DeviceRequests<M, N, B> {
+    /// Create a new set of slots, with no pending requests
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                next_seq: 0,
+                slots: [const { Slot::new() }; N],
+            })),
+        }
+    }
+
+    /// The number of requests waiting for an answer
+    pub fn pending(&self) -> usize {
+        self.inner.lock(|inner| {
+            let inner = inner.borrow();
+            inner.slots.iter().filter(|s| s.pending.is_some()).count()
+        })
+    }
+
+    /// Send a request to the host, and wait for its response
+    ///
+    /// Returns [`DeviceRequestError::Busy`] immediately if all `N` slots are in
+    /// use. Dropping the returned future cancels the request, and a late answer
+    /// from the host is discarded.
+    pub async fn request<E, Tx>(
+        &self,
+        sender: &Sender<Tx>,
+        req: &E::Request,
+    ) -> Result<E::Response, DeviceRequestError<Tx::Error>>
+    where
+        E: Endpoint,
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned,
+        Tx: WireTx,
+    {
+        let (idx, seq) = self
+            .inner
+            .lock(|inner| {
+                let mut inner = inner.borrow_mut();
+                let idx = inner.slots.iter().position(|s| s.pending.is_none())?;
+                let seq = inner.next_seq;
+                inner.next_seq = seq.wrapping_add(1);
+                let slot = &mut inner.slots[idx];
+                slot.pending = Some((E::RESP_KEY, seq));
+                slot.answer = None;
+                Some((idx, seq))
+            })
+            .ok_or(DeviceRequestError::Busy)?;
+        let _guard = SlotGuard { reqs: self, idx };
+
+        sender
+            .reply_keyed(VarSeq::Seq4(seq), E::REQ_KEY, req)
+            .await
+            .map_err(DeviceRequestError::Send)?;
+
+        poll_fn(|cx| {
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+                let slot = &mut inner.slots[idx];
+                let Some(answer) = slot.answer.take() else {
+                    slot.waker.register(cx.waker());
+                    return Poll::Pending;
+                };
+                Poll::Ready(match answer {
+                    Answer::Response(len) => postcard::from_bytes(&slot.buf[..len])
+                        .map_err(|_| DeviceRequestError::DeserFailed),
+                    Answer::Error(len) => Err(postcard::from_bytes(&slot.buf[..len])
+                        .map_or(DeviceRequestError::DeserFailed, DeviceRequestError::Host)),
+                    Answer::TooLarge => Err(DeviceRequestError::ResponseTooLarge),
+                })
+            })
+        })
+        .await
+    }
+
+    /// Pass an answer from the host to the pending request it belongs to
+    ///
+    /// Returns `false` if `hdr` does not belong to a pending request, in which case
+    /// the frame should be handled as usual. This is called by
+    /// [`DeviceRequestDispatch`].
+    pub fn complete(&self, hdr: &VarHeader, body: &[u8]) -> bool {
+        let VarKey::Key8(key) = hdr.key else {
+            return false;
+        };
+        let seq: u32 = hdr.seq_no.into();
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let Some(slot) = inner.slots.iter_mut().find(|s| match s.pending {
+                Some((resp_key, s_seq)) => s_seq == seq && (key == resp_key || key == ERROR_KEY),
+                None => false,
+            }) else {
+                return false;
+            };
+            let answer = match slot.buf.get_mut(..body.len()) {
+                Some(buf) => {
+                    buf.copy_from_slice(body);
+                    if key == ERROR_KEY {
+                        Answer::Error(body.len())
+                    } else {
+                        Answer::Response(body.len())
+                    }
+                }
+                None => Answer::TooLarge,
+            };
+            slot.answer = Some(answer);
+            slot.waker.wake();
+            true
+        })
+    }
+
+    fn release(&self, idx: usize) {
+        self.inner.lock(|inner| {
+            let slot = &mut inner.borrow_mut().slots[idx];
+            slot.pending = None;
+            slot.answer = None;
+        });
+    }
+}
+
+impl<M: RawMutex, const N: usize, const B: usize> Default for DeviceRequests<M, N, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Frees the slot of a request once it completes or is cancelled
+struct SlotGuard<'a, M: RawMutex, const N: usize, const B: usize> {
+    reqs: &'a DeviceRequests<M, N, B>,
+    idx: usize,
+}
+
+impl<M: RawMutex, const N: usize, const B: usize> Drop for SlotGuard<'_, M, N, B> {
+    fn drop(&mut self) {
+        self.reqs.release(self.idx);
+    }
+}
+
+/// A [`Dispatch`] impl passing answers from the host to a [`DeviceRequests`],
+/// and all other frames to the wrapped dispatcher
+pub struct DeviceRequestDispatch<M: RawMutex + 'static, D, const N: usize, const B: usize> {
+    reqs: &'static DeviceRequests<M, N, B>,
+    dispatch: D,
+}
+
+impl<M: RawMutex + 'static, D, const N: usize, const B: usize> DeviceRequestDispatch<M, D, N, B> {
+    /// Wrap `dispatch`, passing answers to pending requests to `reqs`
+    pub fn new(reqs: &'static DeviceRequests<M, N, B>, dispatch: D) -> Self {
+        Self { reqs, dispatch }
+    }
+
+    /// Access the wrapped dispatcher
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dispatch
+    }
+}
+
+impl<M: RawMutex + 'static, D: Dispatch, const N: usize, const B: usize> Dispatch
+    for DeviceRequestDispatch<M, D, N, B>
+{
+    type Tx = D::Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.dispatch.min_key_len()
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        if self.reqs.complete(hdr, body) {
+            return Ok(());
+        }
+        self.dispatch.handle(tx, hdr, body).await
+    }
+
+    async fn handle_with_deadline(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        deadline_ms: u32,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        if self.reqs.complete(hdr, body) {
+            return Ok(());
+        }
+        self.dispatch
+            .handle_with_deadline(tx, hdr, deadline_ms, body)
+            .await
+    }
+}
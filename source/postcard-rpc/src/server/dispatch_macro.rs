@@ -29,6 +29,7 @@
 ///         | ----------        | ----      | -------               |
 ///         | AlphaEndpoint     | async     | test_alpha_handler    |
 ///         | BetaEndpoint      | spawn     | test_beta_handler     |
+///         | GammaEndpoint     | stream    | test_gamma_handler    |
 ///     };
 ///     topics_in: {
 ///         // This is the list you get from the `topics!()` macro
@@ -49,6 +50,437 @@
 ///     };
 /// }
 /// ```
+///
+/// ## Handler kinds
+///
+/// Endpoint handlers may be one of the following kinds:
+///
+/// * `blocking`: `fn(&mut Context, VarHeader, Request) -> Response`
+/// * `async`: `async fn(&mut Context, VarHeader, Request) -> Response`
+/// * `spawn`: `async fn(SpawnCtxt, VarHeader, Request, Sender)`, spawned as a
+///   separate task that is responsible for sending its own reply. The `SpawnCtxt`
+///   is made from the context with [`SpawnContext`][crate::server::SpawnContext],
+///   or with [`SpawnContextFor`][crate::server::SpawnContextFor] to give each
+///   handler its own type
+/// * `stream`: `async fn(&mut Context, VarHeader, Request, &Sender)`, which may
+///   send any number of replies with [`Sender::reply_chunk()`][crate::server::Sender::reply_chunk].
+///   The end of the stream is signalled when the handler returns.
+/// * `cancellable`: `async fn(SpawnCtxt, VarHeader, Request, Sender, CancelToken)`,
+///   spawned like `spawn`, but also given a [`CancelToken`][crate::server::CancelToken]
+///   that is notified when the client sends a [`CANCEL_KEY`][crate::standard_icd::CANCEL_KEY]
+///   message with the same sequence number. At most 8 cancellable requests may
+///   be in-flight at once, or as many as set with `max_cancellable: 16;`. Further
+///   requests are rejected with
+///   [`WireError::FailedToSpawn`][crate::standard_icd::WireError::FailedToSpawn].
+/// * `blocking_try` and `async_try`: like `blocking` and `async`, but return a
+///   `Result<Response, E>`, where `E: Into<WireError>`. `Ok` values are sent as the
+///   reply, and `Err` values are sent as a [`WireError`][crate::standard_icd::WireError].
+/// * `blocking_raw` and `async_raw`: like `blocking` and `async`, but also take the
+///   body of the request as received, i.e. `fn(&mut Context, VarHeader, Request, &[u8])
+///   -> Response`. This is useful to forward or re-sign the exact bytes sent by the
+///   client, without serializing the request again.
+/// * `async_deadline`: like `async_try`, but also take the [`Deadline`][crate::server::Deadline]
+///   sent by the client, i.e. `async fn(&mut Context, VarHeader, Request, Deadline)
+///   -> Result<Response, E>`. Long-running handlers may check the deadline while
+///   working, and bail early with `deadline.check()?` once the client gave up waiting.
+///   This requires the `spawn_impl` type to implement [`WireClock`][crate::server::WireClock].
+/// * `offload`: `fn(SpawnCtxt, VarHeader, Request) -> Response`, like `blocking`, but
+///   run on another thread or a lower priority executor, with the `SpawnCtxt` made
+///   like for `spawn`. The dispatcher waits for the handler to return without
+///   blocking its executor, so other tasks, e.g. the ones driving the transport, keep
+///   running meanwhile. This requires the `spawn_impl` type to implement
+///   [`WireOffload`][crate::server::WireOffload], see the `offload` module for
+///   setting this up with embassy. If the handler can not be run, e.g. because
+///   another offloaded handler is still running, a [`WireError::Busy`][crate::standard_icd::WireError::Busy]
+///   is sent instead.
+/// * `deferred`: `fn(&mut Context, VarHeader, Request, ReplyToken)`, which does not
+///   reply itself, but is given a [`ReplyToken`][crate::server::ReplyToken] that can
+///   be passed to another task, e.g. one waiting for a DMA transfer or an interrupt,
+///   which later completes the request with `token.reply(&resp)`. The request counts
+///   as in-flight until the token is consumed or dropped, and deferred requests share
+///   the spawn limit with `spawn` and `cancellable` handlers.
+/// * `blocking_noreply` and `async_noreply`: `fn(&mut Context, VarHeader, Request)`,
+///   like `blocking` and `async`, but returning nothing, and never replying, for
+///   one-way commands sent with [`HostClient::send()`][crate::host_client::HostClient::send].
+///   Errors while dispatching, e.g. requests failing to deserialize, are still sent,
+///   but faults of the handler are only counted and logged.
+///
+/// Topic handlers may be `blocking`, `async`, or `spawn`.
+/// They are also given the [`Sender`][crate::server::Sender], and have no return value,
+/// e.g. `fn(&mut Context, VarHeader, Message, &Sender)` for `blocking`. Handlers for
+/// fire-and-forget commands, which never send anything, may instead be `topic`:
+/// `fn(&mut Context, VarHeader, Message)`.
+///
+/// ## Timeouts
+///
+/// `async`, `async_try`, `async_raw`, `async_deadline`, and `stream` handlers may be
+/// given a timeout by
+/// annotating the handler, e.g. `| AlphaEndpoint | async | test_alpha_handler [timeout_ms = 500] |`.
+/// If the handler does not complete in time, it is dropped and a
+/// [`WireError::Timeout`][crate::standard_icd::WireError::Timeout] is sent instead.
+/// This requires the `spawn_impl` type to implement [`WireTimer`][crate::server::WireTimer].
+///
+/// Timeouts are not supported for `blocking` handlers, which never yield, or for
+/// `spawn`, `cancellable`, and `offload` handlers, as spawned tasks can not be aborted.
+/// `cancellable` handlers may implement their own deadline instead. `deferred`
+/// handlers return before the reply is sent, the task holding the token is
+/// responsible for replying in time.
+///
+/// ## Handler faults
+///
+/// With the `use-std` feature, a panic in a `blocking`, `blocking_try`,
+/// or `blocking_raw` endpoint handler is caught, and a
+/// [`WireError::HandlerFault`][crate::standard_icd::WireError::HandlerFault] is sent
+/// instead of a reply. Other handlers may report faults with
+/// [`Sender::report_fault()`][crate::server::Sender::report_fault].
+///
+/// ## Idempotent endpoints
+///
+/// When a client retries a request over an unreliable link, the request may arrive
+/// twice, e.g. if only the reply was lost. Endpoints that must not run twice for the
+/// same request, e.g. incrementing a counter in flash, can be marked as idempotent:
+/// `| IncrementEndpoint | async | increment_handler [idempotent = true] |`.
+///
+/// Replies to these endpoints are kept in a small [`ReplyCache`][crate::server::ReplyCache],
+/// keyed by the request key and sequence number. A request matching a cached reply
+/// is answered with the cached reply, without running the handler again. Clients
+/// retrying a request must reuse its sequence number, e.g. with
+/// [`HostClient::send_resp_retry()`][crate::host_client::HostClient::send_resp_retry].
+///
+/// Only the [`REPLY_CACHE_SLOTS`][crate::server::REPLY_CACHE_SLOTS] most recent
+/// replies are kept, and replies larger than
+/// [`REPLY_CACHE_MAX_FRAME`][crate::server::REPLY_CACHE_MAX_FRAME] bytes or errors
+/// are not cached. The cache is cleared when the connection is closed, see
+/// [`Dispatch::reset_connection()`][crate::server::Dispatch::reset_connection].
+/// `spawn`, `cancellable`, and `stream` handlers can not be idempotent.
+/// When combined with a timeout, `idempotent` comes after it, e.g.
+/// `[timeout_ms = 500] [idempotent = true]`.
+///
+/// ## Tolerant endpoints
+///
+/// Requests to endpoints with [`Endpoint::TOLERANT`][crate::Endpoint::TOLERANT] set,
+/// e.g. with `#[define_endpoint(tolerant)]`, are decoded with
+/// [`tolerant::from_bytes()`][crate::tolerant::from_bytes], which defaults missing
+/// trailing fields instead of answering with
+/// [`WireError::DeserFailed`][crate::standard_icd::WireError::DeserFailed]. This
+/// lets older clients talk to a server whose requests gained fields. See the
+/// [`tolerant`][crate::tolerant] module for the changes this allows.
+///
+/// ## Metrics
+///
+/// With the `metrics` feature, the dispatcher counts the requests to each endpoint
+/// handler, and the errors sent in reply, in a [`Metrics`][crate::server::Metrics]
+/// table obtained with the `metrics()` method of the dispatcher. Clients can read
+/// the counters with [`HostClient::get_metrics()`][crate::host_client::HostClient::get_metrics].
+/// Without the feature, the table is empty and takes no space.
+///
+/// ## Borrowed requests
+///
+/// Requests are deserialized directly from the receive buffer, which is kept alive
+/// until the handler returns. Request types may borrow from it, e.g. with `&str`
+/// or `&[u8]` fields, to avoid copying the body:
+///
+/// ```rust,ignore
+/// #[derive(Serialize, Deserialize, Schema)]
+/// pub struct WriteReq<'a> {
+///     pub offset: u32,
+///     pub data: &'a [u8],
+/// }
+///
+/// endpoints! {
+///     list = ENDPOINT_LIST;
+///     | EndpointTy    | RequestTy     | ResponseTy | Path    |
+///     | ----------    | ---------     | ---------- | ----    |
+///     | WriteEndpoint | WriteReq<'a>  | ()         | "write" |
+/// }
+///
+/// async fn write_handler(context: &mut Context, _header: VarHeader, req: WriteReq<'_>) {
+///     context.flash.write(req.offset, req.data).await;
+/// }
+/// ```
+///
+/// This works for all handler kinds that run within the dispatcher, i.e. all kinds
+/// except `spawn` and `cancellable`, which must own their request, as the spawned
+/// task outlives the receive buffer.
+///
+/// ## Key length
+///
+/// Every message header contains a key identifying the endpoint or topic. By
+/// default, the shortest key length (1, 2, 4, or 8 bytes) that has no collisions
+/// between any of the listed endpoints and topics is used, by truncating the full
+/// 8-byte hash. Clients adopt the same length after the first response. This is
+/// checked at compile time, so adding a message may increase the key length.
+///
+/// The key length can also be fixed with `key_len`, e.g. to make sure a bandwidth
+/// constrained link never grows past 2 bytes. Compilation fails if the listed
+/// messages collide at the given length:
+///
+/// ```rust,ignore
+/// define_dispatch! {
+///     app: SingleDispatcher;
+///     spawn_fn: spawn_fn;
+///     tx_impl: WireTxImpl;
+///     spawn_impl: WireSpawnImpl;
+///     context: TestContext;
+///     key_len: 2;
+///     // ...
+/// }
+/// ```
+///
+/// Shorter keys are a tradeoff: only the *known* messages are guaranteed not to
+/// collide. A message unknown to the server, e.g. from newer host software, is
+/// mistaken for a known message if the truncated keys happen to match, which has
+/// a chance of roughly `n / 256` for 1-byte keys with `n` known messages, or
+/// `n / 65536` for 2-byte keys. Such a message will usually fail to deserialize,
+/// but may be handled by the wrong handler if the body happens to be valid. Fixing
+/// a longer `key_len` than necessary makes this less likely.
+///
+/// ## Ping
+///
+/// The [`PingEndpoint`][crate::standard_icd::PingEndpoint] is handled automatically,
+/// echoing the request, which can be used to check that the device is responsive,
+/// e.g. with [`HostClient::ping()`][crate::host_client::HostClient::ping].
+/// This can be disabled with `auto_ping: false;`, after which pings are handled like
+/// any other unknown key.
+///
+/// ## Device info
+///
+/// The [`DeviceInfoEndpoint`][crate::standard_icd::DeviceInfoEndpoint] is always
+/// answered, so the host can tell which firmware it is talking to, e.g. with
+/// [`HostClient::device_info()`][crate::host_client::HostClient::device_info].
+/// By default, the product is the name of the crate using `define_dispatch!`, and
+/// the remaining fields are filled in as described for
+/// [`device_info!`][crate::device_info]. Another [`DeviceInfo`][crate::standard_icd::DeviceInfo]
+/// constant can be sent instead with `device_info: INFO;`, e.g. made with
+/// `const INFO: DeviceInfo<'static> = device_info!(product = "widget rev. B");`.
+///
+/// ## Endpoint info
+///
+/// The [`GetEndpointInfoEndpoint`][crate::standard_icd::GetEndpointInfoEndpoint] is
+/// always answered with the [`EndpointInfo`][crate::standard_icd::EndpointInfo] of
+/// each endpoint in the table, i.e. its path, keys, the
+/// [`schema_hash()`][crate::standard_icd::schema_hash] of its request and response
+/// types, and the kind of its handler, so generic tooling can find out what a
+/// device handles, e.g. with
+/// [`HostClient::get_endpoint_info()`][crate::host_client::HostClient::get_endpoint_info].
+/// The same descriptions are returned by the `endpoint_info()` method of the
+/// dispatcher. Standard endpoints and [aliases](#aliases) are not listed.
+///
+/// ## Flow control
+///
+/// Most transports can only buffer a few incoming frames while the server is busy
+/// handling a request, and frames that arrive when the buffer is full are dropped.
+/// The number of requests the server can buffer may be advertised with
+/// `max_in_flight: 4;`, which is reported by the
+/// [`GetCreditsEndpoint`][crate::standard_icd::GetCreditsEndpoint]. Clients may then
+/// limit the number of outstanding requests, see
+/// [`HostClient::enable_flow_control()`][crate::host_client::HostClient::enable_flow_control].
+///
+/// ## Spawn limit
+///
+/// `spawn` and `cancellable` handlers are spawned as tasks, and a burst of requests
+/// may exhaust the tasks or memory available to the executor. The number of these
+/// handlers running at the same time can be limited with `max_spawned: 4;`, see
+/// [`SpawnLimit`][crate::server::SpawnLimit]. Pending `deferred` requests also count
+/// against the limit, until their reply is sent. Requests beyond the limit are
+/// answered with [`WireError::Busy`][crate::standard_icd::WireError::Busy], and topic
+/// messages are dropped.
+///
+/// ## Subscriptions
+///
+/// The [`SubscribeTopic`][crate::standard_icd::SubscribeTopic] and
+/// [`UnsubscribeTopic`][crate::standard_icd::UnsubscribeTopic] messages of the
+/// client are counted in the [`Subscriptions`][crate::server::Subscriptions] of the
+/// dispatcher, obtained with its `subscriptions()` method. When these are passed to
+/// [`Server::set_subscriptions()`][crate::server::Server::set_subscriptions],
+/// topics without subscribers are not published, e.g. to save power while no host
+/// is listening to telemetry. The subscriptions are cleared when the connection is
+/// closed, see [`Dispatch::reset_connection()`][crate::server::Dispatch::reset_connection].
+///
+/// ## Reply buffers
+///
+/// By default, replies are serialized by the `tx_impl`, which typically holds a
+/// lock on its send buffer while serializing and sending. When many tasks send at
+/// once, e.g. spawned handlers and publishers, they all wait on this lock. With
+/// `reply_buf: 256;`, replies of endpoint handlers are instead serialized into a
+/// buffer of that many bytes on the stack, and the lock is only held while sending,
+/// see [`Sender::reply_from_buf()`][crate::server::Sender::reply_from_buf], which
+/// spawned handlers may also use. This costs the size of the buffer in the dispatch
+/// future. Replies that don't fit are serialized by the `tx_impl` as usual.
+///
+/// ## Worker pools
+///
+/// `async` handlers run inside the dispatch loop, so a slow handler delays all
+/// later frames. With the `worker-pool` feature, the server can instead pass
+/// frames to a [`PoolQueue`][crate::server::pool::PoolQueue], handled by a fixed
+/// number of workers, each with its own instance of the dispatcher. Replies may
+/// then be sent out of order, matched to requests by their sequence number. See
+/// the [`pool`][crate::server::pool] module for details.
+///
+/// ## Priorities
+///
+/// Endpoints that must not wait behind other work, e.g. stopping a motor, can be
+/// given a high [`Priority`][crate::server::Priority]:
+/// `| StopEndpoint | async | stop_handler [priority = high] |`. All other endpoints
+/// have a low priority. When combined with other annotations, `priority` comes
+/// after `timeout_ms` and `idempotent`.
+///
+/// Priorities only take effect with worker pools: a
+/// [`PriorityFrontend`][crate::server::pool::PriorityFrontend], created with the
+/// `priority_of` function of the dispatcher, passes high-priority requests to a
+/// separate queue, whose workers run on a higher priority executor, e.g. an embassy
+/// `InterruptExecutor`, and preempt the handlers of low-priority requests. See the
+/// [`pool`][crate::server::pool] module for details.
+///
+/// ## Interceptors
+///
+/// Cross-cutting concerns like logging, rate limiting, or authorization can be
+/// handled with a list of [`Interceptor`][crate::server::Interceptor]s, which run
+/// around the handler of every message:
+///
+/// ```rust,ignore
+/// define_dispatch! {
+///     // ...
+///     context: TestContext;
+///     interceptors: [Logger, RateLimiter];
+///     // ...
+/// }
+/// ```
+///
+/// `before` hooks run in the order the interceptors are listed, and `after` hooks
+/// in reverse order. If any `before` hook returns [`ControlFlow::Break`][core::ops::ControlFlow::Break],
+/// the handler is skipped and the given error is sent instead.
+///
+/// Interceptors are created with [`Default`], and stored in the public `interceptors`
+/// field of the dispatcher, nested as `(Logger, (RateLimiter, ()))`, where they
+/// can be configured after creating the dispatcher.
+///
+/// ## Catch-all handler
+///
+/// By default, messages with an unknown key are rejected with
+/// [`WireError::UnknownKey`][crate::standard_icd::WireError::UnknownKey]. A catch-all
+/// handler may be added after the endpoint table, e.g. for proxying, or to gracefully
+/// handle endpoints added in newer host software:
+///
+/// ```rust,ignore
+/// endpoints: {
+///     list: ENDPOINT_LIST;
+///
+///     | EndpointTy        | kind      | handler               |
+///     | ----------        | ----      | -------               |
+///     | AlphaEndpoint     | async     | test_alpha_handler    |
+///
+///     _ => async unknown_handler;
+/// };
+/// ```
+///
+/// The catch-all must be `async`, with the signature
+/// `async fn(&mut Context, VarHeader, &[u8], &Sender)`. It receives the raw header
+/// and body, and is responsible for sending any reply.
+///
+/// ## Shutdown
+///
+/// The dispatcher can be shut down gracefully, e.g. before a firmware-initiated
+/// reset or mode change, with the [`Shutdown`][crate::server::Shutdown] signal
+/// returned by its `shutdown_handle()` method. Once shut down, requests to the
+/// listed endpoints, or the catch-all handler, are answered with
+/// [`WireError::ShuttingDown`][crate::standard_icd::WireError::ShuttingDown], while
+/// in-flight `cancellable` handlers run to completion, see
+/// [`Shutdown::wait_drained()`][crate::server::Shutdown::wait_drained]. Standard
+/// endpoints like [`PingEndpoint`][crate::standard_icd::PingEndpoint] keep working,
+/// as do requests to cancel in-flight handlers.
+///
+/// [`Server::run_until_drained()`][crate::server::Server::run_until_drained] runs
+/// the server until it is drained, then flushes the transmit buffer before
+/// returning, e.g. so a reply acknowledging a reset is sent before the USB
+/// disconnect.
+///
+/// ## Capabilities
+///
+/// Privileged endpoints, e.g. unlocking or a factory reset, can require a
+/// capability granted after an auth handshake. Capabilities are bits of a `u32`
+/// mask, and endpoints are gated by annotating their handler, e.g.
+/// `| ResetEndpoint | async | reset_handler [requires_cap = CAP_RESET] |`. Requests
+/// to gated endpoints are answered with
+/// [`WireError::Unauthorized`][crate::standard_icd::WireError::Unauthorized] unless
+/// all bits of the mask have been granted. When combined with other annotations,
+/// `requires_cap` comes after `priority`, e.g. `[priority = high] [requires_cap = CAP_RESET]`.
+///
+/// Clients request capabilities with the
+/// [`GrantCapsEndpoint`][crate::standard_icd::GrantCapsEndpoint], e.g. with
+/// [`HostClient::grant_caps()`][crate::host_client::HostClient::grant_caps], which is
+/// only answered with `grant_caps: check_caps;`. The handler has the signature
+/// `fn(&mut Context, VarHeader, u32, &[u8]) -> bool`, taking the requested mask and
+/// the proof sent by the client, and returns whether to grant all of the requested
+/// capabilities. Granted capabilities are kept in the
+/// [`Capabilities`][crate::server::Capabilities] of the dispatcher instance, returned
+/// by its `capabilities()` method, and revoked by the server when the connection is
+/// closed.
+///
+/// ## Aliases
+///
+/// Renaming the path of an endpoint changes its keys, so hosts built against the
+/// old path could no longer reach it. The old path can be kept as an alias:
+/// `| StatusEndpoint | async | status_handler [alias = "old/status"] |`. Requests
+/// with the key of the alias are handled by the same handler, and answered with
+/// the response key of the alias, as computed from the old path and the current
+/// request and response types. Aliases must therefore only be used while these
+/// types are unchanged. When combined with other annotations, `alias` comes last.
+///
+/// Aliased requests are passed to the interceptors and handlers with the key of
+/// the endpoint, so they can't tell them apart. Aliases count towards the
+/// [key length](#key-length) like any other key, but are not listed in the
+/// schemas reported to the host.
+///
+/// ## Mapping responses
+///
+/// Enrichment shared by all responses, e.g. a device timestamp in an envelope
+/// around every response, can be added in one place with `map_response: stamp;`.
+/// The hook is called with the header of the request and the output of the
+/// handler, and returns the response to send in its place:
+///
+/// ```rust,ignore
+/// fn stamp<T: Stamped>(hdr: &VarHeader, mut resp: T) -> T {
+///     resp.set_stamp(Instant::now().as_ticks(), hdr.seq_no.into());
+///     resp
+/// }
+/// ```
+///
+/// It is generic over the response type, which is inferred for each endpoint, so
+/// the reply always has the type of [`Endpoint::Response`][crate::Endpoint::Response],
+/// and still matches what the host decodes. Any bounds of the hook, like `Stamped`
+/// above, must be met by the responses of all endpoints in the table.
+///
+/// The hook sees every reply the dispatcher sends itself, i.e. of all flavors except
+/// `stream`, `spawn`, `cancellable`, and `deferred`, whose handlers send their own
+/// replies. It is not called for errors, or for the standard endpoints. Replies to
+/// [idempotent endpoints](#idempotent-endpoints) are cached after being mapped.
+///
+/// ## Order of checks
+///
+/// Requests are only deserialized once they are accepted, so a client can't make
+/// the device parse large bodies it will reject anyway. For each frame:
+///
+/// 1. The `before` hooks of the [interceptors](#interceptors) run on the raw body
+/// 2. Requests to endpoints with [`requires_cap`](#capabilities) are rejected,
+///    unless the capability was granted
+/// 3. Retransmitted requests to [idempotent endpoints](#idempotent-endpoints) are
+///    answered from the reply cache
+/// 4. Requests are rejected while [shutting down](#shutdown), and requests to
+///    `spawn` or `cancellable` handlers while the [spawn limit](#spawn-limit) is
+///    reached
+/// 5. The request is deserialized, and passed to its handler
+///
+/// Steps 2 and 4 are decided by [`sans_io::authorize()`](crate::server::sans_io::authorize)
+/// and [`sans_io::admit()`](crate::server::sans_io::admit), which can be tested
+/// without a dispatcher.
+///
+/// ## Diagnostics
+///
+/// With the `defmt` feature of `postcard-rpc` enabled, errors that occur while
+/// dispatching (e.g. unknown keys, failed deserialization, or failed spawns) are
+/// also logged on the server with `defmt::warn!`, before being sent to the client.
+/// Topic messages that fail to deserialize are logged too, even though no reply is sent.
 #[macro_export]
 macro_rules! define_dispatch {
     //////////////////////////////////////////////////////////////////////////////
@@ -56,66 +488,328 @@ macro_rules! define_dispatch {
     //////////////////////////////////////////////////////////////////////////////
 
     // This is the "blocking execution" arm for defining an endpoint
-    (@ep_arm blocking ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@ep_arm blocking [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
-            let reply = $handler($context, $header.clone(), $req);
-            if $outputter.reply::<$endpoint>($header.seq_no, &reply).await.is_err() {
-                let err = $crate::standard_icd::WireError::SerFailed;
-                $outputter.error($header.seq_no, err).await
-            } else {
-                Ok(())
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            match $crate::server::catch_fault(key, || $handler($context, $header.clone(), $req)) {
+                Ok(reply) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Err(err) => $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter),
             }
         }
     };
     // This is the "async execution" arm for defining an endpoint
-    (@ep_arm async ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@ep_arm async [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
             let reply = $handler($context, $header.clone(), $req).await;
-            if $outputter.reply::<$endpoint>($header.seq_no, &reply).await.is_err() {
-                let err = $crate::standard_icd::WireError::SerFailed;
-                $outputter.error($header.seq_no, err).await
+            $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter)
+        }
+    };
+    // This is the "streaming async execution" arm for defining an endpoint
+    (@ep_arm stream [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            $handler($context, $header.clone(), $req, $outputter).await;
+            $outputter.end_stream($header.seq_no).await
+        }
+    };
+    // This is the "spawn an embassy task" arm for defining an endpoint
+    (@ep_arm spawn [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let Some(permit) = SPAWN_LIMIT.try_acquire() else {
+                let err = $crate::standard_icd::WireError::Busy;
+                return $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter);
+            };
+            let context = $crate::server::SpawnContextFor::<$endpoint>::spawn_ctxt_for($context);
+            let sender = $outputter.clone().with_permit(permit);
+            if $spawn_fn($spawner, $handler(context, $header.clone(), $req, sender)).is_err() {
+                let err = $crate::standard_icd::WireError::FailedToSpawn;
+                $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
             } else {
                 Ok(())
             }
         }
     };
-    // This is the "spawn an embassy task" arm for defining an endpoint
-    (@ep_arm spawn ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+
+    // This is the "spawn an embassy task, with cancellation" arm for defining an endpoint
+    (@ep_arm cancellable [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
-            let context = $crate::server::SpawnContext::spawn_ctxt($context);
-            if $spawn_fn($spawner, $handler(context, $header.clone(), $req, $outputter.clone())).is_err() {
+            let Some(permit) = SPAWN_LIMIT.try_acquire() else {
+                let err = $crate::standard_icd::WireError::Busy;
+                return $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter);
+            };
+            if let Some(mut token) = CANCEL_MAP.register($header.seq_no) {
+                token.track(SHUTDOWN.enter());
+                let context = $crate::server::SpawnContextFor::<$endpoint>::spawn_ctxt_for($context);
+                let sender = $outputter.clone().with_permit(permit);
+                if $spawn_fn($spawner, $handler(context, $header.clone(), $req, sender, token)).is_err() {
+                    let err = $crate::standard_icd::WireError::FailedToSpawn;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                } else {
+                    Ok(())
+                }
+            } else {
                 let err = $crate::standard_icd::WireError::FailedToSpawn;
-                $outputter.error($header.seq_no, err).await
+                $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+            }
+        }
+    };
+
+    // This is the "reply later, from anywhere" arm for defining an endpoint
+    (@ep_arm deferred [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let Some(permit) = SPAWN_LIMIT.try_acquire() else {
+                let err = $crate::standard_icd::WireError::Busy;
+                return $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter);
+            };
+            let sender = $outputter.clone().with_permit(permit);
+            let mut token = $crate::server::ReplyToken::<$endpoint, _>::new(sender, $header.seq_no);
+            token.track(SHUTDOWN.enter());
+            $handler($context, $header.clone(), $req, token);
+            Ok(())
+        }
+    };
+
+    // This is the "run on another executor or thread" arm for defining an endpoint
+    (@ep_arm offload [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let context = $crate::server::SpawnContextFor::<$endpoint>::spawn_ctxt_for($context);
+            let header = $header.clone();
+            let job = move || $handler(context, header, $req);
+            match $crate::server::WireOffload::offload($spawner, job).await {
+                Ok(reply) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Err(err) => {
+                    let err: $crate::standard_icd::WireError = err.into();
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+
+    // This is the "async execution with a timeout" arm for defining an endpoint
+    (@ep_arm async [$timeout_ms:literal] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let fut = $handler($context, $header.clone(), $req);
+            let timeout = $crate::server::WireTimer::delay_ms($spawner, $timeout_ms);
+            match $crate::server::with_timeout(fut, timeout).await {
+                Ok(reply) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Err(_) => {
+                    let err = $crate::standard_icd::WireError::Timeout;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+    // This is the "streaming async execution with a timeout" arm for defining an endpoint
+    (@ep_arm stream [$timeout_ms:literal] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let fut = $handler($context, $header.clone(), $req, $outputter);
+            let timeout = $crate::server::WireTimer::delay_ms($spawner, $timeout_ms);
+            match $crate::server::with_timeout(fut, timeout).await {
+                Ok(()) => $outputter.end_stream($header.seq_no).await,
+                Err(_) => {
+                    let err = $crate::standard_icd::WireError::Timeout;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+    // These are the "fallible" arms, where the handler returns a `Result`, and
+    // errors are sent as a `WireError`
+    (@ep_arm blocking_try [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            match $crate::server::catch_fault(key, || $handler($context, $header.clone(), $req)) {
+                Ok(Ok(reply)) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Ok(Err(e)) => {
+                    let err: $crate::standard_icd::WireError = e.into();
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+                Err(err) => $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter),
+            }
+        }
+    };
+    (@ep_arm async_try [$($timeout_ms:literal)?] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let fut = async {
+                $handler($context, $header.clone(), $req).await.map_err(|e| {
+                    let err: $crate::standard_icd::WireError = e.into();
+                    err
+                })
+            };
+            match $crate::define_dispatch!(@with_timeout [$($timeout_ms)?] fut $spawner) {
+                Ok(Ok(reply)) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Ok(Err(err)) => $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter),
+                Err(_) => {
+                    let err = $crate::standard_icd::WireError::Timeout;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+    // These are the "one-way" arms, which never reply, for commands sent with
+    // `HostClient::send()`
+    (@ep_arm blocking_noreply [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            if let Err(err) = $crate::server::catch_fault(key, || $handler($context, $header.clone(), $req)) {
+                // Nobody is waiting for an error either, so it is only counted and logged
+                METRICS.error(&key, &err);
+                $crate::server::log_dispatch_error($header, &err);
+            }
+            Ok(())
+        }
+    };
+    (@ep_arm async_noreply [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            $handler($context, $header.clone(), $req).await;
+            Ok(())
+        }
+    };
+    // Helpers for the fallible arms
+    (@ep_reply ($endpoint:ty) $reply:ident $header:ident $outputter:ident) => {
+        {
+            let $reply: <$endpoint as $crate::Endpoint>::Response = map_response_hook($header, $reply);
+            if $outputter.reply_buffered::<$endpoint, REPLY_BUF>($header.seq_no, &$reply).await.is_err() {
+                let err = $crate::standard_icd::WireError::SerFailed;
+                $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
             } else {
                 Ok(())
             }
         }
     };
+    (@ep_error ($endpoint:ty) $header:ident $err:ident $outputter:ident) => {
+        {
+            METRICS.error(&<$endpoint as $crate::Endpoint>::REQ_KEY, &$err);
+            $outputter.dispatch_error($header, $err).await
+        }
+    };
+    (@with_timeout [] $fut:ident $spawner:ident) => {
+        Ok::<_, $crate::server::TimedOut>($fut.await)
+    };
+    (@with_timeout [$timeout_ms:literal] $fut:ident $spawner:ident) => {
+        $crate::server::with_timeout($fut, $crate::server::WireTimer::delay_ms($spawner, $timeout_ms)).await
+    };
+
+    // These are the "raw body" arms, which also give the handler the received body
+    (@ep_body blocking_raw [] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $body:ident $deadline_ms:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            match $crate::server::catch_fault(key, || $handler($context, $header.clone(), $req, $body)) {
+                Ok(reply) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Err(err) => $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter),
+            }
+        }
+    };
+    (@ep_body async_raw [$($timeout_ms:literal)?] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $body:ident $deadline_ms:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let fut = $handler($context, $header.clone(), $req, $body);
+            match $crate::define_dispatch!(@with_timeout [$($timeout_ms)?] fut $spawner) {
+                Ok(reply) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Err(_) => {
+                    let err = $crate::standard_icd::WireError::Timeout;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+    // This is the "deadline" arm, which also gives the handler the deadline of the request
+    (@ep_body async_deadline [$($timeout_ms:literal)?] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $body:ident $deadline_ms:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            let deadline = $crate::server::Deadline::start($spawner, $deadline_ms);
+            let fut = async {
+                $handler($context, $header.clone(), $req, deadline).await.map_err(|e| {
+                    let err: $crate::standard_icd::WireError = e.into();
+                    err
+                })
+            };
+            match $crate::define_dispatch!(@with_timeout [$($timeout_ms)?] fut $spawner) {
+                Ok(Ok(reply)) => $crate::define_dispatch!(@ep_reply ($endpoint) reply $header $outputter),
+                Ok(Err(err)) => $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter),
+                Err(_) => {
+                    let err = $crate::standard_icd::WireError::Timeout;
+                    $crate::define_dispatch!(@ep_error ($endpoint) $header err $outputter)
+                }
+            }
+        }
+    };
+    // All other flavors only take the decoded request
+    (@ep_body $flavor:tt [$($timeout_ms:literal)?] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $body:ident $deadline_ms:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        $crate::define_dispatch!(@ep_arm $flavor [$($timeout_ms)?] ($endpoint) $handler $context $header $req $outputter ($spawn_fn) $spawner)
+    };
+    // Other flavors can't be raced against a timer: blocking handlers never yield,
+    // and spawned or offloaded tasks (e.g. embassy tasks) can't be aborted once spawned
+    (@ep_arm $flavor:tt [$timeout_ms:literal] ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        compile_error!(concat!(
+            "`timeout_ms` is only supported for `async`, `async_try`, `async_raw`, `async_deadline`, and `stream` handlers, not `",
+            stringify!($flavor),
+            "`",
+        ))
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // CATCH-ALL HANDLER EXPANSION ARMS
+    //////////////////////////////////////////////////////////////////////////////
+
+    // This is the default arm, when no catch-all handler is defined
+    (@fb_arm () $dispatch:ident $header:ident $body:ident $outputter:ident) => {
+        {
+            // huh! We have no idea what this key is supposed to be!
+            let err = $crate::standard_icd::WireError::UnknownKey;
+            $outputter.dispatch_error($header, err).await
+        }
+    };
+    // This is the "async execution" arm for a catch-all handler
+    (@fb_arm (async $handler:ident) $dispatch:ident $header:ident $body:ident $outputter:ident) => {
+        {
+            if let Err(err) = $crate::server::sans_io::admit(Self::dispatch_state(), false) {
+                return $outputter.dispatch_error($header, err).await;
+            }
+            $handler(&mut $dispatch.context, $header.clone(), $body, $outputter).await;
+            Ok(())
+        }
+    };
+    // Blocking handlers can't send a reply, and spawned handlers can't borrow the body
+    (@fb_arm ($flavor:tt $handler:ident) $dispatch:ident $header:ident $body:ident $outputter:ident) => {
+        compile_error!(concat!(
+            "The catch-all handler must be `async`, not `",
+            stringify!($flavor),
+            "`",
+        ))
+    };
 
     //////////////////////////////////////////////////////////////////////////////
     // TOPIC HANDLER EXPANSION ARMS
     //////////////////////////////////////////////////////////////////////////////
 
     // This is the "blocking execution" arm for defining a topic
-    (@tp_arm blocking $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@tp_arm blocking ($topic:ty) $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
             $handler($context, $header.clone(), $msg, $outputter);
         }
     };
     // This is the "async execution" arm for defining a topic
-    (@tp_arm async $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@tp_arm async ($topic:ty) $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
             $handler($context, $header.clone(), $msg, $outputter).await;
         }
     };
-    (@tp_arm spawn $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    // This is the "fire-and-forget" arm for defining a topic, without a `Sender`
+    (@tp_arm topic ($topic:ty) $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
-            let context = $crate::server::SpawnContext::spawn_ctxt($context);
-            let _ = $spawn_fn($spawner, $handler(context, $header.clone(), $msg, $outputter.clone()));
+            $handler($context, $header.clone(), $msg);
+        }
+    };
+    (@tp_arm spawn ($topic:ty) $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+        {
+            if let Some(permit) = SPAWN_LIMIT.try_acquire() {
+                let context = $crate::server::SpawnContextFor::<$topic>::spawn_ctxt_for($context);
+                let sender = $outputter.clone().with_permit(permit);
+                let _ = $spawn_fn($spawner, $handler(context, $header.clone(), $msg, sender));
+            } else {
+                // Topics have no reply, so messages are just dropped when saturated
+                $crate::server::log_dispatch_error($header, &$crate::standard_icd::WireError::Busy);
+            }
         }
     };
-
-
 
     //////////////////////////////////////////////////////////////////////////////
     // Implementation of the dispatch trait for the app, where the Key length
@@ -124,8 +818,9 @@ macro_rules! define_dispatch {
     (@matcher
         $n:literal $app_name:ident $tx_impl:ty; $spawn_fn:ident $key_ty:ty; $key_kind:expr;
         $req_key_name:ident / $topic_key_name:ident = $bytes_ty:ty;
-        ($($endpoint:ty | $ep_flavor:tt | $ep_handler:ident)*)
+        ($($endpoint:ty | $ep_flavor:tt | $ep_handler:ident [$($ep_timeout:literal)?] [$($ep_idem:literal)?] [$($ep_cap:expr)?])*)
         ($($topic_in:ty | $tp_flavor:tt | $tp_handler:ident)*)
+        ($($fallback:tt)*)
     ) => {
         impl $app_name<$n> {
             /// Check if there are any unexpected duplicates, typically this occurs because
@@ -136,6 +831,15 @@ macro_rules! define_dispatch {
                     const ALL_KEYS: &[$key_ty] = &[
                         <$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name,
                         <$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::GetKeysEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::GetCreditsEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::GetMetricsEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::GrantCapsEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::DeviceInfoEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::GetEndpointInfoEndpoint as $crate::Endpoint>::$req_key_name,
+                        <$crate::standard_icd::CancelTopic as $crate::Topic>::$topic_key_name,
+                        <$crate::standard_icd::SubscribeTopic as $crate::Topic>::$topic_key_name,
+                        <$crate::standard_icd::UnsubscribeTopic as $crate::Topic>::$topic_key_name,
                         $(
                             <$endpoint as $crate::Endpoint>::$req_key_name,
                         )*
@@ -158,37 +862,27 @@ macro_rules! define_dispatch {
                 };
                 DUPE
             }
-        }
 
-        impl $crate::server::Dispatch for $app_name<$n> {
-            type Tx = $tx_impl;
-
-            fn min_key_len(&self) -> $crate::header::VarKeyKind {
-                $key_kind
-            }
-
-            /// Handle dispatching of a single frame
-            async fn handle(
+            /// Match a single frame to its handler
+            #[allow(unused_variables)]
+            async fn handle_matched(
                 &mut self,
-                tx: &$crate::server::Sender<Self::Tx>,
+                tx: &$crate::server::Sender<$tx_impl>,
                 hdr: &$crate::header::VarHeader,
+                keyb: $key_ty,
+                deadline_ms: Option<u32>,
                 body: &[u8],
-            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
-                let key = hdr.key;
-                let Ok(keyb) = <$key_ty>::try_from(&key) else {
-                    let err = $crate::standard_icd::WireError::KeyTooSmall;
-                    return tx.error(hdr.seq_no, err).await;
-                };
+            ) -> Result<(), <$tx_impl as $crate::server::WireTx>::Error> {
                 match keyb {
                     // Standard ICD endpoints
                     //
                     // WARNING! If you add any more standard icd endpoints, make sure you ALSO add them
                     // to has_dupe above!
-                    <$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name => {
+                    <$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name if AUTO_PING => {
                         // Can we deserialize the request?
                         let Ok(req) = $crate::postcard::from_bytes::<<$crate::standard_icd::PingEndpoint as $crate::Endpoint>::Request>(body) else {
                             let err = $crate::standard_icd::WireError::DeserFailed;
-                            return tx.error(hdr.seq_no, err).await;
+                            return tx.dispatch_error(hdr, err).await;
                         };
 
                         tx.reply::<$crate::standard_icd::PingEndpoint>(hdr.seq_no, &req).await
@@ -196,16 +890,102 @@ macro_rules! define_dispatch {
                     <$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::$req_key_name => {
                         tx.send_all_schemas(hdr, self.device_map).await
                     }
+                    <$crate::standard_icd::GetKeysEndpoint as $crate::Endpoint>::$req_key_name => {
+                        tx.send_device_keys(hdr, self.device_map).await
+                    }
+                    <$crate::standard_icd::GetCreditsEndpoint as $crate::Endpoint>::$req_key_name if MAX_IN_FLIGHT.is_some() => {
+                        let credits = MAX_IN_FLIGHT.unwrap_or_default();
+                        tx.reply::<$crate::standard_icd::GetCreditsEndpoint>(hdr.seq_no, &credits).await
+                    }
+                    <$crate::standard_icd::GetMetricsEndpoint as $crate::Endpoint>::$req_key_name if METRICS.is_enabled() => {
+                        let Ok(idx) = $crate::postcard::from_bytes::<u32>(body) else {
+                            let err = $crate::standard_icd::WireError::DeserFailed;
+                            return tx.dispatch_error(hdr, err).await;
+                        };
+                        let stats = METRICS.stats(idx as usize);
+                        tx.reply::<$crate::standard_icd::GetMetricsEndpoint>(hdr.seq_no, &stats).await
+                    }
+                    <$crate::standard_icd::GrantCapsEndpoint as $crate::Endpoint>::$req_key_name if GRANT_CAPS.is_some() => {
+                        let Some(grant) = GRANT_CAPS else {
+                            unreachable!()
+                        };
+                        let Ok(req) = $crate::postcard::from_bytes::<<$crate::standard_icd::GrantCapsEndpoint as $crate::Endpoint>::Request>(body) else {
+                            let err = $crate::standard_icd::WireError::DeserFailed;
+                            return tx.dispatch_error(hdr, err).await;
+                        };
+                        if !grant(&mut self.context, hdr.clone(), req.caps, &req.proof) {
+                            let err = $crate::standard_icd::WireError::Unauthorized;
+                            return tx.dispatch_error(hdr, err).await;
+                        }
+                        self.caps.grant(req.caps);
+                        tx.reply::<$crate::standard_icd::GrantCapsEndpoint>(hdr.seq_no, &self.caps.granted()).await
+                    }
+                    <$crate::standard_icd::DeviceInfoEndpoint as $crate::Endpoint>::$req_key_name => {
+                        tx.send_device_info(hdr, &DEVICE_INFO).await
+                    }
+                    <$crate::standard_icd::GetEndpointInfoEndpoint as $crate::Endpoint>::$req_key_name => {
+                        let Ok(idx) = $crate::postcard::from_bytes::<u32>(body) else {
+                            let err = $crate::standard_icd::WireError::DeserFailed;
+                            return tx.dispatch_error(hdr, err).await;
+                        };
+                        tx.send_endpoint_info(hdr, ENDPOINT_INFO.get(idx as usize)).await
+                    }
+                    <$crate::standard_icd::CancelTopic as $crate::Topic>::$topic_key_name => {
+                        // Cancellation requests for unknown or completed requests are ignored
+                        CANCEL_MAP.cancel(hdr.seq_no);
+                        Ok(())
+                    }
+                    <$crate::standard_icd::SubscribeTopic as $crate::Topic>::$topic_key_name => {
+                        if let Ok(key) = $crate::postcard::from_bytes::<$crate::Key>(body) {
+                            SUBSCRIPTIONS.subscribe(key);
+                        }
+                        Ok(())
+                    }
+                    <$crate::standard_icd::UnsubscribeTopic as $crate::Topic>::$topic_key_name => {
+                        if let Ok(key) = $crate::postcard::from_bytes::<$crate::Key>(body) {
+                            SUBSCRIPTIONS.unsubscribe(key);
+                        }
+                        Ok(())
+                    }
                     // WARNING! If you add any more standard icd endpoints, make sure you ALSO add them
                     // to has_dupe above!
                     //
                     // end standard_icd endpoints
                     $(
                         <$endpoint as $crate::Endpoint>::$req_key_name => {
-                            // Can we deserialize the request?
-                            let Ok(req) = $crate::postcard::from_bytes::<<$endpoint as $crate::Endpoint>::Request>(body) else {
+                            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+                            METRICS.request(&key);
+
+                            // Gated endpoints are only handled once the capability is granted
+                            let required: u32 = $crate::define_dispatch!(@requires_cap [$($ep_cap)?]);
+                            if let Err(err) = $crate::server::sans_io::authorize(self.caps.granted(), required) {
+                                METRICS.error(&key, &err);
+                                return tx.dispatch_error(hdr, err).await;
+                            }
+
+                            // A retransmitted request to an idempotent endpoint gets the
+                            // cached reply, instead of running the handler again
+                            let idempotent = $crate::define_dispatch!(@idempotent $ep_flavor [$($ep_idem)?]);
+                            if idempotent {
+                                if let Some(res) = REPLY_CACHE.resend(key, hdr.seq_no, tx).await {
+                                    return res;
+                                }
+                            }
+
+                            // Don't start any new work while draining, or parse requests
+                            // that can't be spawned anyway. The permit is only taken once
+                            // the request is deserialized, so this is just an early check
+                            let spawns = $crate::define_dispatch!(@spawns $ep_flavor);
+                            if let Err(err) = $crate::server::sans_io::admit(Self::dispatch_state(), spawns) {
+                                METRICS.error(&key, &err);
+                                return tx.dispatch_error(hdr, err).await;
+                            }
+
+                            // All checks passed, can we deserialize the request?
+                            let Ok(req) = $crate::tolerant::request_from_bytes::<$endpoint, _>(body) else {
                                 let err = $crate::standard_icd::WireError::DeserFailed;
-                                return tx.error(hdr.seq_no, err).await;
+                                METRICS.error(&key, &err);
+                                return tx.dispatch_error(hdr, err).await;
                             };
 
                             // Store some items as named bindings, so we can use `ident` in the
@@ -217,15 +997,29 @@ macro_rules! define_dispatch {
                             #[allow(unused)]
                             let spawninfo = &dispatch.spawn;
 
+                            // Replies to idempotent endpoints are stored in the cache
+                            #[allow(unused)]
+                            let recording: $crate::server::Sender<$tx_impl>;
+                            let tx = $crate::define_dispatch!(@reply_tx [$($ep_idem)?] tx recording);
+
                             // This will expand to the right "flavor" of handler
-                            $crate::define_dispatch!(@ep_arm $ep_flavor ($endpoint) $ep_handler context hdr req tx ($spawn_fn) spawninfo)
+                            $crate::define_dispatch!(@ep_body $ep_flavor [$($ep_timeout)?] ($endpoint) $ep_handler context hdr req body deadline_ms tx ($spawn_fn) spawninfo)
                         }
                     )*
                     $(
                         <$topic_in as $crate::Topic>::$topic_key_name => {
+                            // Topics have no reply, so messages are just dropped while
+                            // draining, or while no more handlers can be spawned
+                            let spawns = $crate::define_dispatch!(@spawns $tp_flavor);
+                            if let Err(err) = $crate::server::sans_io::admit(Self::dispatch_state(), spawns) {
+                                $crate::server::log_dispatch_error(hdr, &err);
+                                return Ok(());
+                            }
+
                             // Can we deserialize the request?
                             let Ok(msg) = $crate::postcard::from_bytes::<<$topic_in as $crate::Topic>::Message>(body) else {
-                                // This is a topic, not much to be done
+                                // This is a topic, not much to be done, other than logging it
+                                $crate::server::log_dispatch_error(hdr, &$crate::standard_icd::WireError::DeserFailed);
                                 return Ok(());
                             };
 
@@ -238,18 +1032,185 @@ macro_rules! define_dispatch {
                             #[allow(unused)]
                             let spawninfo = &dispatch.spawn;
 
-                            $crate::define_dispatch!(@tp_arm $tp_flavor $tp_handler context hdr msg tx ($spawn_fn) spawninfo);
+                            $crate::define_dispatch!(@tp_arm $tp_flavor ($topic_in) $tp_handler context hdr msg tx ($spawn_fn) spawninfo);
                             Ok(())
                         }
                     )*
                     _other => {
-                        // huh! We have no idea what this key is supposed to be!
-                        let err = $crate::standard_icd::WireError::UnknownKey;
-                        tx.error(hdr.seq_no, err).await
+                        #[allow(unused)]
+                        let dispatch = self;
+                        $crate::define_dispatch!(@fb_arm ($($fallback)*) dispatch hdr body tx)
                     },
                 }
             }
         }
+
+        impl $crate::server::Dispatch for $app_name<$n> {
+            type Tx = $tx_impl;
+
+            fn min_key_len(&self) -> $crate::header::VarKeyKind {
+                $key_kind
+            }
+
+            /// Handle dispatching of a single frame
+            async fn handle(
+                &mut self,
+                tx: &$crate::server::Sender<Self::Tx>,
+                hdr: &$crate::header::VarHeader,
+                body: &[u8],
+            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                self.handle_frame(tx, hdr, None, body).await
+            }
+
+            /// Handle dispatching of a single frame with a deadline
+            async fn handle_with_deadline(
+                &mut self,
+                tx: &$crate::server::Sender<Self::Tx>,
+                hdr: &$crate::header::VarHeader,
+                deadline_ms: u32,
+                body: &[u8],
+            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                self.handle_frame(tx, hdr, Some(deadline_ms), body).await
+            }
+
+            fn granted_caps(&self) -> u32 {
+                self.caps.granted()
+            }
+
+            fn set_granted_caps(&mut self, caps: u32) {
+                self.caps.revoke_all();
+                self.caps.grant(caps);
+            }
+
+            fn clear_replies(&mut self) {
+                REPLY_CACHE.clear();
+            }
+
+            fn reset_connection(&mut self) {
+                self.caps.revoke_all();
+                REPLY_CACHE.clear();
+                SUBSCRIPTIONS.clear();
+            }
+        }
+
+        impl $app_name<$n> {
+            /// The state new requests and messages are admitted against
+            fn dispatch_state() -> $crate::server::sans_io::DispatchState {
+                $crate::server::sans_io::DispatchState {
+                    shutting_down: SHUTDOWN.is_shutting_down(),
+                    spawn_limit_reached: SPAWN_LIMIT.is_exhausted(),
+                }
+            }
+
+            /// Handle dispatching of a single frame, with an optional deadline
+            async fn handle_frame(
+                &mut self,
+                tx: &$crate::server::Sender<$tx_impl>,
+                hdr: &$crate::header::VarHeader,
+                deadline_ms: Option<u32>,
+                body: &[u8],
+            ) -> Result<(), <$tx_impl as $crate::server::WireTx>::Error> {
+                // Requests to an alias are handled like requests to its endpoint,
+                // but answered with the response key of the alias
+                let (resolved, alias) = $crate::server::sans_io::resolve_alias(ENDPOINT_ALIASES, hdr);
+                let hdr = &resolved;
+                let aliased_tx;
+                let tx = match alias {
+                    Some(alias) => {
+                        aliased_tx = tx.clone().with_resp_alias(alias.endpoint_resp_key, alias.resp_key);
+                        &aliased_tx
+                    }
+                    None => tx,
+                };
+                let keyb = match $crate::server::sans_io::narrow_key::<$key_ty>(&hdr.key) {
+                    Ok(keyb) => keyb,
+                    Err(err) => return tx.dispatch_error(hdr, err).await,
+                };
+                let flow = $crate::server::Interceptor::before(&mut self.interceptors, hdr, body).await;
+                if let ::core::ops::ControlFlow::Break(err) = flow {
+                    return tx.dispatch_error(hdr, err).await;
+                }
+                let res = self.handle_matched(tx, hdr, keyb, deadline_ms, body).await;
+                $crate::server::Interceptor::after(&mut self.interceptors, hdr).await;
+                res
+            }
+        }
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // OPTIONS
+    //////////////////////////////////////////////////////////////////////////////
+
+    (@auto_ping) => { true };
+    (@auto_ping $auto_ping:literal) => { $auto_ping };
+    (@max_in_flight) => { None };
+    (@max_in_flight $max_in_flight:literal) => { Some($max_in_flight) };
+    (@max_spawned) => { u32::MAX };
+    (@max_spawned $max_spawned:literal) => { $max_spawned };
+    (@max_cancellable) => { 8 };
+    (@max_cancellable $max_cancellable:literal) => { $max_cancellable };
+    (@reply_buf) => { 0 };
+    (@reply_buf $reply_buf:literal) => { $reply_buf };
+    (@device_info) => { $crate::device_info!(product = ::core::env!("CARGO_PKG_NAME")) };
+    (@device_info $device_info:path) => { $device_info };
+    (@grant_caps) => { None };
+    (@grant_caps $grant_caps:ident) => { Some($grant_caps) };
+    (@map_response) => {
+        /// Passes replies through unchanged, as no `map_response` hook is set
+        #[inline(always)]
+        fn map_response_hook<T>(_hdr: &$crate::header::VarHeader, reply: T) -> T {
+            reply
+        }
+    };
+    (@map_response $map_response:ident) => {
+        /// The hook transforming replies before they are serialized
+        use super::$map_response as map_response_hook;
+    };
+    (@priority []) => { $crate::server::Priority::Low };
+    (@priority [low]) => { $crate::server::Priority::Low };
+    (@priority [high]) => { $crate::server::Priority::High };
+    (@priority [$other:ident]) => {
+        compile_error!(concat!("`priority` must be `low` or `high`, not `", stringify!($other), "`"))
+    };
+    (@requires_cap []) => { 0 };
+    (@requires_cap [$cap:expr]) => { $cap };
+
+    // Whether the handler is spawned, and needs a permit from the spawn limit
+    (@spawns spawn) => { true };
+    (@spawns cancellable) => { true };
+    (@spawns deferred) => { true };
+    (@spawns $flavor:tt) => { false };
+
+    // Only handlers replying within the dispatcher can be idempotent
+    (@idempotent $flavor:tt []) => { false };
+    (@idempotent $flavor:tt [$idem:literal]) => {
+        $crate::define_dispatch!(@idempotent_flavor $flavor $idem)
+    };
+    (@idempotent_flavor spawn $idem:literal) => { compile_error!("`idempotent` is not supported for `spawn` handlers") };
+    (@idempotent_flavor cancellable $idem:literal) => { compile_error!("`idempotent` is not supported for `cancellable` handlers") };
+    (@idempotent_flavor stream $idem:literal) => { compile_error!("`idempotent` is not supported for `stream` handlers") };
+    (@idempotent_flavor deferred $idem:literal) => { compile_error!("`idempotent` is not supported for `deferred` handlers") };
+    (@idempotent_flavor blocking_noreply $idem:literal) => { compile_error!("`idempotent` is not supported for `blocking_noreply` handlers") };
+    (@idempotent_flavor async_noreply $idem:literal) => { compile_error!("`idempotent` is not supported for `async_noreply` handlers") };
+    (@idempotent_flavor $flavor:tt $idem:literal) => { $idem };
+    (@reply_tx [] $tx:ident $recording:ident) => { $tx };
+    (@reply_tx [$idem:literal] $tx:ident $recording:ident) => {
+        if $idem {
+            $recording = $tx.clone().with_reply_cache(&REPLY_CACHE);
+            &$recording
+        } else {
+            $tx
+        }
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // INTERCEPTOR CHAIN
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Interceptors are nested as `(A, (B, ()))`, see the `Interceptor` impl for tuples
+    (@interceptors) => { () };
+    (@interceptors $head:ty $(, $rest:ty)*) => {
+        ($head, $crate::define_dispatch!(@interceptors $($rest),*))
     };
 
     //////////////////////////////////////////////////////////////////////////////
@@ -262,13 +1223,24 @@ macro_rules! define_dispatch {
         tx_impl: $tx_impl:ty;
         spawn_impl: $spawn_impl:ty;
         context: $context_ty:ty;
+        $(key_len: $key_len:literal;)?
+        $(interceptors: [$($interceptor:ty),* $(,)?];)?
+        $(auto_ping: $auto_ping:literal;)?
+        $(max_in_flight: $max_in_flight:literal;)?
+        $(max_spawned: $max_spawned:literal;)?
+        $(max_cancellable: $max_cancellable:literal;)?
+        $(reply_buf: $reply_buf:literal;)?
+        $(grant_caps: $grant_caps:ident;)?
+        $(device_info: $device_info:path;)?
+        $(map_response: $map_response:ident;)?
 
         endpoints: {
             list: $endpoint_list:path;
 
                | EndpointTy     | kind          | handler           |
                | $(-)*          | $(-)*         | $(-)*             |
-            $( | $endpoint:ty   | $ep_flavor:tt | $ep_handler:ident  | )*
+            $( | $endpoint:ty   | $ep_flavor:tt | $ep_handler:ident $([timeout_ms = $ep_timeout:literal])? $([idempotent = $ep_idem:literal])? $([priority = $ep_prio:ident])? $([requires_cap = $ep_cap:expr])? $([alias = $ep_alias:literal])? | )*
+            $( _ => $fb_flavor:tt $fb_handler:ident; )?
         };
         topics_in: {
             list: $topic_in_list:path;
@@ -360,6 +1332,14 @@ macro_rules! define_dispatch {
             const TP_HANDLER_IN_KEYS: &[Key] = &[
                 $(<$topic_in as $crate::Topic>::TOPIC_KEY,)*
             ];
+            // These are the REQUEST and RESPONSE KEYS of all endpoint aliases, which
+            // are not in any report, but must not collide either
+            const EP_ALIAS_IN_KEYS: &[Key] = &[
+                $($(Key::for_path::<<$endpoint as $crate::Endpoint>::Request>($ep_alias),)?)*
+            ];
+            const EP_ALIAS_OUT_KEYS: &[Key] = &[
+                $($(Key::for_path::<<$endpoint as $crate::Endpoint>::Response>($ep_alias),)?)*
+            ];
 
             const fn a_is_subset_of_b(a: &[Key], b: &[Key]) -> bool {
                 let mut i = 0;
@@ -387,10 +1367,12 @@ macro_rules! define_dispatch {
             pub const NEEDED_SZ_IN: usize = $crate::server::min_key_needed(&[
                 &EP_IN_KEYS,
                 &TP_IN_KEYS,
+                EP_ALIAS_IN_KEYS,
             ]);
             pub const NEEDED_SZ_OUT: usize = $crate::server::min_key_needed(&[
                 &EP_OUT_KEYS,
                 &TP_OUT_KEYS,
+                EP_ALIAS_OUT_KEYS,
             ]);
             pub const NEEDED_SZ: usize = const {
                 assert!(
@@ -405,11 +1387,23 @@ macro_rules! define_dispatch {
                     a_is_subset_of_b(TP_HANDLER_IN_KEYS, &TP_IN_KEYS),
                     "All listed endpoint handlers must be listed in endpoints->list! Missing Response Type found!",
                 );
-                if NEEDED_SZ_IN > NEEDED_SZ_OUT {
+                let needed = if NEEDED_SZ_IN > NEEDED_SZ_OUT {
                     NEEDED_SZ_IN
                 } else {
                     NEEDED_SZ_OUT
-                }
+                };
+                $(
+                    assert!(
+                        matches!($key_len, 1 | 2 | 4 | 8),
+                        "`key_len` must be one of 1, 2, 4, or 8!",
+                    );
+                    assert!(
+                        $key_len >= needed,
+                        "`key_len` is too small, some keys collide at this length! Remove `key_len` to use the smallest collision-free length.",
+                    );
+                    let needed = $key_len;
+                )?
+                needed
             };
         }
 
@@ -428,6 +1422,31 @@ macro_rules! define_dispatch {
         // same outcome.
         #[doc=concat!("This defines the postcard-rpc app implementation for ", stringify!($app_name))]
         pub type $app_name = impls::$app_name<{ sizer::NEEDED_SZ }>;
+        const _KEY_COLLISION_CHECK: () = $crate::server::assert_no_key_collisions(&[
+            ("PingEndpoint", <$crate::standard_icd::PingEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GetAllSchemasEndpoint", <$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GetKeysEndpoint", <$crate::standard_icd::GetKeysEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GetCreditsEndpoint", <$crate::standard_icd::GetCreditsEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GetMetricsEndpoint", <$crate::standard_icd::GetMetricsEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GrantCapsEndpoint", <$crate::standard_icd::GrantCapsEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("DeviceInfoEndpoint", <$crate::standard_icd::DeviceInfoEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("GetEndpointInfoEndpoint", <$crate::standard_icd::GetEndpointInfoEndpoint as $crate::Endpoint>::REQ_KEY),
+            ("CancelTopic", <$crate::standard_icd::CancelTopic as $crate::Topic>::TOPIC_KEY),
+            ("SubscribeTopic", <$crate::standard_icd::SubscribeTopic as $crate::Topic>::TOPIC_KEY),
+            ("UnsubscribeTopic", <$crate::standard_icd::UnsubscribeTopic as $crate::Topic>::TOPIC_KEY),
+            $(
+                (stringify!($endpoint), <$endpoint as $crate::Endpoint>::REQ_KEY),
+            )*
+            $($(
+                (
+                    concat!(stringify!($endpoint), " alias ", stringify!($ep_alias)),
+                    $crate::Key::for_path::<<$endpoint as $crate::Endpoint>::Request>($ep_alias),
+                ),
+            )?)*
+            $(
+                (stringify!($topic_in), <$topic_in as $crate::Topic>::TOPIC_KEY),
+            )*
+        ]);
         const HAS_DUPE: bool = $app_name::has_dupe();
         const _DUPE_CHECK: () = const {
             assert!(!HAS_DUPE, "Caught duplicate items. Is `omit_std` set? This is likely a bug in your code. See https://github.com/jamesmunns/postcard-rpc/issues/135.");
@@ -436,10 +1455,93 @@ macro_rules! define_dispatch {
         mod impls {
             use super::*;
 
+            /// In-flight requests handled by the `cancellable` flavor
+            static CANCEL_MAP: $crate::server::CancelMap<{ $crate::define_dispatch!(@max_cancellable $($max_cancellable)?) }> =
+                $crate::server::CancelMap::new();
+
+            /// Replies to endpoints marked as `idempotent`
+            static REPLY_CACHE: $crate::server::ReplyCache = $crate::server::ReplyCache::new();
+
+            /// Request counters of each endpoint handler
+            static METRICS: $crate::server::Metrics<ENDPOINT_COUNT> = $crate::server::Metrics::new(ENDPOINT_KEYS);
+
+            /// Topics the client is subscribed to
+            static SUBSCRIPTIONS: $crate::server::Subscriptions = $crate::server::Subscriptions::new();
+
+            /// Shutdown signal shared by all instances of the dispatcher
+            static SHUTDOWN: $crate::server::Shutdown = $crate::server::Shutdown::new();
+
+            /// Limit on the number of running `spawn` and `cancellable` handlers
+            static SPAWN_LIMIT: $crate::server::SpawnLimit = $crate::server::SpawnLimit::new(
+                $crate::define_dispatch!(@max_spawned $($max_spawned)?)
+            );
+
+            /// Whether [`PingEndpoint`][$crate::standard_icd::PingEndpoint] is handled automatically
+            const AUTO_PING: bool = $crate::define_dispatch!(@auto_ping $($auto_ping)?);
+
+            /// The number of requests the server advertises it can buffer, if any
+            const MAX_IN_FLIGHT: Option<u32> = $crate::define_dispatch!(@max_in_flight $($max_in_flight)?);
+
+            /// The size of the stack buffer replies are serialized into, if any
+            const REPLY_BUF: usize = $crate::define_dispatch!(@reply_buf $($reply_buf)?);
+
+            /// The firmware information sent in reply to the [`DeviceInfoEndpoint`][$crate::standard_icd::DeviceInfoEndpoint]
+            const DEVICE_INFO: $crate::standard_icd::DeviceInfo<'static> = $crate::define_dispatch!(@device_info $($device_info)?);
+
+            /// The handler deciding whether to grant requested capabilities, if any
+            const GRANT_CAPS: Option<fn(&mut $context_ty, $crate::header::VarHeader, u32, &[u8]) -> bool> =
+                $crate::define_dispatch!(@grant_caps $($grant_caps)?);
+
+            $crate::define_dispatch!(@map_response $($map_response)?);
+
+            /// The request keys of all endpoint handlers
+            const ENDPOINT_KEYS: [$crate::Key; ENDPOINT_COUNT] = [$(<$endpoint as $crate::Endpoint>::REQ_KEY,)*];
+            const ENDPOINT_COUNT: usize = {
+                let keys: &[$crate::Key] = &[$(<$endpoint as $crate::Endpoint>::REQ_KEY,)*];
+                keys.len()
+            };
+
+            /// The descriptions of all endpoint handlers, in the order of the table
+            const ENDPOINT_INFO: [$crate::standard_icd::EndpointInfo<'static>; ENDPOINT_COUNT] = [
+                $(
+                    $crate::standard_icd::EndpointInfo {
+                        path: <$endpoint as $crate::Endpoint>::PATH,
+                        req_key: <$endpoint as $crate::Endpoint>::REQ_KEY,
+                        resp_key: <$endpoint as $crate::Endpoint>::RESP_KEY,
+                        req_schema: $crate::standard_icd::schema_hash::<<$endpoint as $crate::Endpoint>::Request>(),
+                        resp_schema: $crate::standard_icd::schema_hash::<<$endpoint as $crate::Endpoint>::Response>(),
+                        kind: stringify!($ep_flavor),
+                    },
+                )*
+            ];
+
+            /// The request and response keys of all endpoint aliases, followed by
+            /// those of the endpoint they stand for
+            const ENDPOINT_ALIASES: &[$crate::server::EndpointAlias] = &[
+                $($(
+                    $crate::server::EndpointAlias {
+                        req_key: $crate::Key::for_path::<<$endpoint as $crate::Endpoint>::Request>($ep_alias),
+                        resp_key: $crate::Key::for_path::<<$endpoint as $crate::Endpoint>::Response>($ep_alias),
+                        endpoint_req_key: <$endpoint as $crate::Endpoint>::REQ_KEY,
+                        endpoint_resp_key: <$endpoint as $crate::Endpoint>::RESP_KEY,
+                    },
+                )?)*
+            ];
+
+            /// The request keys and priorities of all endpoint handlers
+            const ENDPOINT_PRIORITIES: [($crate::Key, $crate::server::Priority); ENDPOINT_COUNT] = [
+                $(
+                    (<$endpoint as $crate::Endpoint>::REQ_KEY, $crate::define_dispatch!(@priority [$($ep_prio)?])),
+                )*
+            ];
+
             pub struct $app_name<const N: usize> {
                 pub context: $context_ty,
                 pub spawn: $spawn_impl,
                 pub device_map: &'static $crate::DeviceMap,
+                pub interceptors: $crate::define_dispatch!(@interceptors $($($interceptor),*)?),
+                /// Capabilities granted to the client, checked by endpoints with `requires_cap`
+                caps: $crate::server::Capabilities,
             }
 
             impl<const N: usize> $app_name<N> {
@@ -478,35 +1580,328 @@ macro_rules! define_dispatch {
                         context,
                         spawn,
                         device_map: MAP,
+                        interceptors: Default::default(),
+                        caps: $crate::server::Capabilities::new(),
                     }
                 }
+
+                /// Obtain the [`Shutdown`][$crate::server::Shutdown] signal of this dispatcher
+                ///
+                /// The signal is shared by all instances of this dispatcher type.
+                pub fn shutdown_handle(&self) -> &'static $crate::server::Shutdown {
+                    &SHUTDOWN
+                }
+
+                /// Obtain the [`SpawnLimit`][$crate::server::SpawnLimit] of this dispatcher
+                ///
+                /// The limit is shared by all instances of this dispatcher type.
+                pub fn spawn_limit(&self) -> &'static $crate::server::SpawnLimit {
+                    &SPAWN_LIMIT
+                }
+
+                /// Obtain the [`ReplyCache`][$crate::server::ReplyCache] of this dispatcher
+                ///
+                /// The cache is shared by all instances of this dispatcher type, and
+                /// is cleared when any of them resets its connection.
+                pub fn reply_cache(&self) -> &'static $crate::server::ReplyCache {
+                    &REPLY_CACHE
+                }
+
+                /// Obtain the [`Metrics`][$crate::server::Metrics] of this dispatcher
+                ///
+                /// The counters are shared by all instances of this dispatcher type.
+                pub fn metrics(&self) -> &'static $crate::server::Metrics<ENDPOINT_COUNT> {
+                    &METRICS
+                }
+
+                /// Describe the endpoint handlers of this dispatcher, in the order of the table
+                ///
+                /// These are also reported to the host with the
+                /// [`GetEndpointInfoEndpoint`][$crate::standard_icd::GetEndpointInfoEndpoint].
+                pub fn endpoint_info(&self) -> &'static [$crate::standard_icd::EndpointInfo<'static>] {
+                    &ENDPOINT_INFO
+                }
+
+                /// The [`Priority`][$crate::server::Priority] of the endpoint handling `hdr`
+                ///
+                /// Frames not matching an endpoint handler, e.g. topics or standard
+                /// endpoints, have a low priority. This may be passed to a
+                /// `PriorityFrontend` of the `pool` module.
+                pub fn priority_of(hdr: &$crate::header::VarHeader) -> $crate::server::Priority {
+                    ENDPOINT_PRIORITIES
+                        .iter()
+                        .find(|(key, _)| hdr.key == $crate::header::VarKey::Key8(*key))
+                        .map_or($crate::server::Priority::Low, |(_, prio)| *prio)
+                }
+
+                /// Obtain the [`Capabilities`][$crate::server::Capabilities] granted to the client
+                ///
+                /// Each instance of this dispatcher holds its own capabilities, which
+                /// are revoked when the connection is closed.
+                pub fn capabilities(&self) -> &$crate::server::Capabilities {
+                    &self.caps
+                }
+
+                /// Obtain the [`Subscriptions`][$crate::server::Subscriptions] of the client
+                ///
+                /// The subscriptions are shared by all instances of this dispatcher type,
+                /// and are cleared when any of them resets its connection.
+                /// Pass them to `Server::set_subscriptions()` to only publish topics
+                /// the client is subscribed to.
+                pub fn subscriptions(&self) -> &'static $crate::server::Subscriptions {
+                    &SUBSCRIPTIONS
+                }
+            }
+
+            impl<const N: usize> $crate::server::Service for $app_name<N> {
+                const KEYS: &'static [(&'static str, $crate::Key)] = &[
+                    $(
+                        (stringify!($endpoint), <$endpoint as $crate::Endpoint>::REQ_KEY),
+                    )*
+                    $(
+                        (stringify!($topic_in), <$topic_in as $crate::Topic>::TOPIC_KEY),
+                    )*
+                ];
+                const PATHS: &'static [&'static str] = &[
+                    $(<$endpoint as $crate::Endpoint>::PATH,)*
+                    $(<$topic_in as $crate::Topic>::PATH,)*
+                ];
             }
 
             $crate::define_dispatch! {
                 @matcher 1 $app_name $tx_impl; $spawn_fn $crate::Key1; $crate::header::VarKeyKind::Key1;
                 REQ_KEY1 / TOPIC_KEY1 = u8;
-                ($($endpoint | $ep_flavor | $ep_handler)*)
+                ($($endpoint | $ep_flavor | $ep_handler [$($ep_timeout)?] [$($ep_idem)?] [$($ep_cap)?])*)
                 ($($topic_in | $tp_flavor | $tp_handler)*)
+                ($($fb_flavor $fb_handler)?)
             }
             $crate::define_dispatch! {
                 @matcher 2 $app_name $tx_impl; $spawn_fn $crate::Key2; $crate::header::VarKeyKind::Key2;
                 REQ_KEY2 / TOPIC_KEY2 = [u8; 2];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
+                ($($endpoint | $ep_flavor | $ep_handler [$($ep_timeout)?] [$($ep_idem)?] [$($ep_cap)?])*)
                 ($($topic_in | $tp_flavor | $tp_handler)*)
+                ($($fb_flavor $fb_handler)?)
             }
             $crate::define_dispatch! {
                 @matcher 4 $app_name $tx_impl; $spawn_fn $crate::Key4; $crate::header::VarKeyKind::Key4;
                 REQ_KEY4 / TOPIC_KEY4 = [u8; 4];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
+                ($($endpoint | $ep_flavor | $ep_handler [$($ep_timeout)?] [$($ep_idem)?] [$($ep_cap)?])*)
                 ($($topic_in | $tp_flavor | $tp_handler)*)
+                ($($fb_flavor $fb_handler)?)
             }
             $crate::define_dispatch! {
                 @matcher 8 $app_name $tx_impl; $spawn_fn $crate::Key; $crate::header::VarKeyKind::Key8;
                 REQ_KEY / TOPIC_KEY = [u8; 8];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
+                ($($endpoint | $ep_flavor | $ep_handler [$($ep_timeout)?] [$($ep_idem)?] [$($ep_cap)?])*)
                 ($($topic_in | $tp_flavor | $tp_handler)*)
+                ($($fb_flavor $fb_handler)?)
             }
         }
 
     }
 }
+
+/// Nest Dispatch Macro
+///
+/// Combines the dispatchers of several subsystems, each created with
+/// [`define_dispatch!`][crate::define_dispatch], into a single dispatcher. Each
+/// subsystem is mounted under a service prefix: all of its endpoints and topics must
+/// have paths below that prefix, e.g. by listing them with `service = "motor";` in
+/// [`endpoints!`][crate::endpoints] and [`topics!`][crate::topics], so that two
+/// subsystems can both have a `status` endpoint. This is checked at compile time,
+/// as is that no two keys of all dispatchers collide.
+///
+/// ```rust,ignore
+/// nest! {
+///     // This becomes the name of the combined dispatcher
+///     app: DeviceDispatcher;
+///     // This is the WireTx impl, which must be the same for all dispatchers
+///     tx_impl: WireTxImpl;
+///     // This dispatcher handles the standard endpoints, and all frames not
+///     // handled by a service
+///     root: RootDispatcher;
+///
+///     services: {
+///         | Field     | Prefix    | Dispatcher        |
+///         | -----     | ------    | ----------        |
+///         | motor     | "motor"   | MotorDispatcher   |
+///         | imu       | "imu"     | ImuDispatcher     |
+///     };
+/// }
+///
+/// let dispatch = DeviceDispatcher::new(root, motor, imu);
+/// ```
+///
+/// Frames are routed by their key to the service with a handler for it, see
+/// [`Service`][crate::server::Service], and all other frames to the root
+/// dispatcher. Cancellation requests are passed to all dispatchers. Capabilities are
+/// granted by the root dispatcher, and apply to the endpoints of all services. The schemas
+/// and keys reported to clients are those of the lists of the root dispatcher,
+/// which should therefore include the endpoints and topics of all services.
+#[macro_export]
+macro_rules! nest {
+    (
+        app: $app_name:ident;
+        tx_impl: $tx_impl:ty;
+        root: $root:ty;
+
+        services: {
+            | Field         | Prefix            | Dispatcher    |
+            | $(-)*         | $(-)*             | $(-)*         |
+          $(| $name:ident   | $prefix:literal   | $service:ty   | )*
+        };
+    ) => {
+        #[doc=concat!("This defines the nested postcard-rpc app implementation for ", stringify!($app_name))]
+        pub struct $app_name {
+            /// The dispatcher of all frames not handled by a service
+            pub root: $root,
+            $(
+                #[doc=concat!("The dispatcher of the `", $prefix, "` service")]
+                pub $name: $service,
+            )*
+        }
+
+        impl $app_name {
+            /// Create a new instance of the dispatcher
+            pub fn new(root: $root, $($name: $service,)*) -> Self {
+                Self { root, $($name,)* }
+            }
+
+            /// Route a single frame, with an optional deadline, to the dispatchers
+            /// handling it
+            async fn route(
+                &mut self,
+                tx: &$crate::server::Sender<$tx_impl>,
+                hdr: &$crate::header::VarHeader,
+                deadline_ms: Option<u32>,
+                body: &[u8],
+            ) -> Result<(), <$tx_impl as $crate::server::WireTx>::Error> {
+                let cancel = <$crate::standard_icd::CancelTopic as $crate::Topic>::TOPIC_KEY;
+                if hdr.key == $crate::header::VarKey::Key8(cancel) {
+                    // Each dispatcher only knows its own in-flight requests
+                    $(
+                        $crate::nest!(@handle (self.$name) tx hdr deadline_ms body)?;
+                    )*
+                    return $crate::nest!(@handle (self.root) tx hdr deadline_ms body);
+                }
+                $(
+                    if $crate::server::Service::handles(&self.$name, &hdr.key) {
+                        // Capabilities are granted by the root dispatcher
+                        self.$name.set_granted_caps(self.root.granted_caps());
+                        return $crate::nest!(@handle (self.$name) tx hdr deadline_ms body);
+                    }
+                )*
+                $crate::nest!(@handle (self.root) tx hdr deadline_ms body)
+            }
+        }
+
+        const _: () = {
+            $(
+                assert!(
+                    $crate::server::paths_under(<$service as $crate::server::Service>::PATHS, $prefix),
+                    concat!("All endpoints and topics of `", stringify!($service), "` must be below `", $prefix, "/`!"),
+                );
+            )*
+            $crate::server::assert_no_key_collisions($crate::nest!(@all_keys $root; $($name $service)*));
+        };
+
+        impl $crate::server::Dispatch for $app_name {
+            type Tx = $tx_impl;
+
+            fn min_key_len(&self) -> $crate::header::VarKeyKind {
+                // Keys of different dispatchers may only collide when shortened
+                const ALL: &[(&str, $crate::Key)] = $crate::nest!(@all_keys $root; $($name $service)*);
+                const KEYS: [$crate::Key; ALL.len()] = const {
+                    let mut keys = [unsafe { $crate::Key::from_bytes([0u8; 8]) }; ALL.len()];
+                    let mut i = 0;
+                    while i < ALL.len() {
+                        keys[i] = ALL[i].1;
+                        i += 1;
+                    }
+                    keys
+                };
+                const NEEDED: usize = $crate::server::min_key_needed(&[&KEYS]);
+                let needed = match NEEDED {
+                    1 => $crate::header::VarKeyKind::Key1,
+                    2 => $crate::header::VarKeyKind::Key2,
+                    4 => $crate::header::VarKeyKind::Key4,
+                    _ => $crate::header::VarKeyKind::Key8,
+                };
+                let kind = $crate::server::max_key_kind(needed, self.root.min_key_len());
+                $(
+                    let kind = $crate::server::max_key_kind(kind, self.$name.min_key_len());
+                )*
+                kind
+            }
+
+            /// Handle dispatching of a single frame
+            async fn handle(
+                &mut self,
+                tx: &$crate::server::Sender<Self::Tx>,
+                hdr: &$crate::header::VarHeader,
+                body: &[u8],
+            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                self.route(tx, hdr, None, body).await
+            }
+
+            /// Handle dispatching of a single frame with a deadline
+            async fn handle_with_deadline(
+                &mut self,
+                tx: &$crate::server::Sender<Self::Tx>,
+                hdr: &$crate::header::VarHeader,
+                deadline_ms: u32,
+                body: &[u8],
+            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                self.route(tx, hdr, Some(deadline_ms), body).await
+            }
+
+            fn granted_caps(&self) -> u32 {
+                self.root.granted_caps()
+            }
+
+            fn set_granted_caps(&mut self, caps: u32) {
+                self.root.set_granted_caps(caps);
+            }
+
+            fn clear_replies(&mut self) {
+                self.root.clear_replies();
+                $(
+                    self.$name.clear_replies();
+                )*
+            }
+
+            fn reset_connection(&mut self) {
+                self.root.reset_connection();
+                $(
+                    self.$name.reset_connection();
+                )*
+            }
+        }
+    };
+
+    // Pass a frame to one dispatcher, with the deadline if it has one
+    (@handle ($dispatch:expr) $tx:ident $hdr:ident $deadline_ms:ident $body:ident) => {
+        match $deadline_ms {
+            Some(ms) => $crate::server::Dispatch::handle_with_deadline(&mut $dispatch, $tx, $hdr, ms, $body).await,
+            None => $crate::server::Dispatch::handle(&mut $dispatch, $tx, $hdr, $body).await,
+        }
+    };
+
+    // The names and keys of all dispatchers, as a single slice
+    (@all_keys $root:ty; $($name:ident $service:ty)*) => {
+        const {
+            const NULL_KEY: $crate::Key = unsafe { $crate::Key::from_bytes([0u8; 8]) };
+            #[allow(non_upper_case_globals)]
+            const root: &[(&str, $crate::Key)] = <$root as $crate::server::Service>::KEYS;
+            $(
+                #[allow(non_upper_case_globals)]
+                const $name: &[(&str, $crate::Key)] = <$service as $crate::server::Service>::KEYS;
+            )*
+            $crate::concat_arrays! {
+                init = ("", NULL_KEY);
+                ty = (&str, $crate::Key);
+                [root $(, $name)*]
+            }
+        }
+    };
+}
@@ -0,0 +1,208 @@
+//! A [`Dispatch`] impl with handlers registered at runtime
+//!
+//! [`define_dispatch!`][crate::define_dispatch] matches keys to handlers at compile
+//! time, which is the smallest and fastest option, but requires all handlers to be
+//! known when the firmware is built. A [`DynDispatcher`] instead holds a table of
+//! boxed [`Handler`] trait objects, which can be registered and unregistered at
+//! runtime, e.g. for plugins. Once the server is created, the dispatcher can be
+//! accessed between calls to [`Server::run()`] with [`Server::dispatch_mut()`].
+//!
+//! This costs an allocation per handler and per request, and a linear search of
+//! the table for each request.
+//!
+//! ```rust
+//! use postcard_rpc::{
+//!     header::VarHeader,
+//!     server::{
+//!         dyn_dispatch::{DynDispatcher, Handler, HandlerFuture},
+//!         Sender, WireTx,
+//!     },
+//!     standard_icd::GetCreditsEndpoint,
+//! };
+//!
+//! struct Context {
+//!     credits: u32,
+//! }
+//!
+//! struct CreditsHandler;
+//!
+//! impl<Tx: WireTx> Handler<Context, Tx> for CreditsHandler {
+//!     fn handle<'a>(
+//!         &'a mut self,
+//!         context: &'a mut Context,
+//!         hdr: VarHeader,
+//!         _body: &'a [u8],
+//!         tx: &'a Sender<Tx>,
+//!     ) -> HandlerFuture<'a, Tx> {
+//!         Box::pin(async move {
+//!             tx.reply::<GetCreditsEndpoint>(hdr.seq_no, &context.credits).await
+//!         })
+//!     }
+//! }
+//!
+//! fn dispatcher<Tx: WireTx>() -> DynDispatcher<Context, Tx, 8> {
+//!     let mut dispatch = DynDispatcher::new(Context { credits: 4 });
+//!     // Handlers can be registered and unregistered at any time
+//!     assert!(dispatch.register_endpoint::<GetCreditsEndpoint>(Box::new(CreditsHandler)).is_ok());
+//!     dispatch
+//! }
+//! ```
+//!
+//! Unlike `define_dispatch!`, the schemas of the handlers are not known, so the
+//! [`GetAllSchemasEndpoint`] and [`GetKeysEndpoint`] are not answered unless a
+//! handler is registered for them. [`PingEndpoint`] requests are answered unless a
+//! handler is registered for them.
+//!
+//! Requests are matched to handlers by their key, which may be shorter than the
+//! full key if it still matches a single handler. As handlers can be registered at
+//! any time, a `DynDispatcher` always asks for full keys in
+//! [`Dispatch::min_key_len()`].
+//!
+//! **Requires feature**: `dyn-dispatch`, and an allocator
+//!
+//! [`Server::run()`]: crate::server::Server::run
+//! [`Server::dispatch_mut()`]: crate::server::Server::dispatch_mut
+//! [`GetAllSchemasEndpoint`]: crate::standard_icd::GetAllSchemasEndpoint
+//! [`GetKeysEndpoint`]: crate::standard_icd::GetKeysEndpoint
+//! [`PingEndpoint`]: crate::standard_icd::PingEndpoint
+
+use alloc::boxed::Box;
+use core::{future::Future, pin::Pin};
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind},
+    server::{Dispatch, Sender, WireTx},
+    standard_icd::{PingEndpoint, WireError},
+    Endpoint, Key, Topic,
+};
+
+/// The future returned by [`Handler::handle()`]
+pub type HandlerFuture<'a, Tx> =
+    Pin<Box<dyn Future<Output = Result<(), <Tx as WireTx>::Error>> + 'a>>;
+
+/// A boxed [`Handler`], as held by a [`DynDispatcher`]
+pub type BoxedHandler<Context, Tx> = Box<dyn Handler<Context, Tx>>;
+
+/// A handler for an endpoint or topic, registered with a [`DynDispatcher`]
+pub trait Handler<Context, Tx: WireTx> {
+    /// Handle a single request or topic message
+    ///
+    /// `body` is the serialized message. Endpoint handlers are expected to reply
+    /// with `tx`, either with a response, or with [`Sender::dispatch_error()`] if
+    /// the request can't be handled.
+    fn handle<'a>(
+        &'a mut self,
+        context: &'a mut Context,
+        hdr: VarHeader,
+        body: &'a [u8],
+        tx: &'a Sender<Tx>,
+    ) -> HandlerFuture<'a, Tx>;
+}
+
+/// A [`Dispatch`] impl with up to `N` handlers registered at runtime
+///
+/// See the [module docs](self) for details.
+pub struct DynDispatcher<Context, Tx: WireTx, const N: usize> {
+    context: Context,
+    handlers: heapless::Vec<(Key, BoxedHandler<Context, Tx>), N>,
+}
+
+impl<Context, Tx: WireTx, const N: usize> DynDispatcher<Context, Tx, N> {
+    /// Create a new dispatcher, without any handlers
+    pub fn new(context: Context) -> Self {
+        Self {
+            context,
+            handlers: heapless::Vec::new(),
+        }
+    }
+
+    /// The context passed to handlers
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Register a handler for messages with the given key
+    ///
+    /// Replaces any handler already registered for `key`. Returns the handler if
+    /// `N` handlers are already registered.
+    pub fn register(
+        &mut self,
+        key: Key,
+        handler: BoxedHandler<Context, Tx>,
+    ) -> Result<(), BoxedHandler<Context, Tx>> {
+        if let Some((_, old)) = self.handlers.iter_mut().find(|(k, _)| *k == key) {
+            *old = handler;
+            return Ok(());
+        }
+        self.handlers.push((key, handler)).map_err(|(_, h)| h)
+    }
+
+    /// Register a handler for requests to the endpoint `E`
+    ///
+    /// See [`DynDispatcher::register()`].
+    pub fn register_endpoint<E: Endpoint>(
+        &mut self,
+        handler: BoxedHandler<Context, Tx>,
+    ) -> Result<(), BoxedHandler<Context, Tx>> {
+        self.register(E::REQ_KEY, handler)
+    }
+
+    /// Register a handler for messages on the topic `T`
+    ///
+    /// See [`DynDispatcher::register()`].
+    pub fn register_topic<T: Topic>(
+        &mut self,
+        handler: BoxedHandler<Context, Tx>,
+    ) -> Result<(), BoxedHandler<Context, Tx>> {
+        self.register(T::TOPIC_KEY, handler)
+    }
+
+    /// Remove the handler for messages with the given key, returning it
+    ///
+    /// Later messages with this key are answered with [`WireError::UnknownKey`].
+    pub fn unregister(&mut self, key: &Key) -> Option<BoxedHandler<Context, Tx>> {
+        let pos = self.handlers.iter().position(|(k, _)| k == key)?;
+        Some(self.handlers.swap_remove(pos).1)
+    }
+
+    /// Whether a handler is registered for messages with the given key
+    pub fn is_registered(&self, key: &Key) -> bool {
+        self.handlers.iter().any(|(k, _)| k == key)
+    }
+}
+
+impl<Context, Tx: WireTx, const N: usize> Dispatch for DynDispatcher<Context, Tx, N> {
+    type Tx = Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        VarKeyKind::Key8
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        // A shortened key may match more than one handler
+        let mut matches = self
+            .handlers
+            .iter_mut()
+            .filter(|(k, _)| VarKey::Key8(*k) == hdr.key);
+        let found = matches.next();
+        if matches.next().is_some() {
+            return tx.dispatch_error(hdr, WireError::KeyTooSmall).await;
+        }
+
+        match found {
+            Some((_, handler)) => handler.handle(&mut self.context, *hdr, body, tx).await,
+            None if hdr.key == VarKey::Key8(PingEndpoint::REQ_KEY) => {
+                let Ok(req) = postcard::from_bytes::<u32>(body) else {
+                    return tx.dispatch_error(hdr, WireError::DeserFailed).await;
+                };
+                tx.reply::<PingEndpoint>(hdr.seq_no, &req).await
+            }
+            None => tx.dispatch_error(hdr, WireError::UnknownKey).await,
+        }
+    }
+}
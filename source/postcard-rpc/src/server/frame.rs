@@ -0,0 +1,405 @@
+//! Serialization of outgoing frames, without any transport or executor
+//!
+//! The [`WireTx`][crate::server::WireTx] impls in [`impls`][crate::server::impls]
+//! use these helpers to serialize frames into a buffer before writing them to the
+//! transport. They can also be used directly, for example when sending from an
+//! interrupt-driven TX queue instead of through an async `WireTx`:
+//!
+//! ```rust
+//! use postcard_rpc::{
+//!     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+//!     server::frame::SenderCore,
+//!     standard_icd::PingEndpoint,
+//!     Endpoint,
+//! };
+//!
+//! let mut core = SenderCore::new();
+//! let mut buf = [0u8; 64];
+//!
+//! let hdr = VarHeader { key: VarKey::Key8(PingEndpoint::RESP_KEY), seq_no: VarSeq::Seq1(3) };
+//! let frame = core.frame(&mut buf, hdr, &1234u32).unwrap();
+//! // ... push `frame` to the TX queue
+//!
+//! let frame = core.log_fmt(&mut buf, VarKeyKind::Key2, format_args!("x: {}", 5)).unwrap();
+//! // ... push `frame` to the TX queue
+//! ```
+
+use core::fmt::Arguments;
+
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::WireTxErrorKind,
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+/// The state needed to serialize outgoing frames
+///
+/// This only holds the sequence number of log messages. Access to the buffer and
+/// the transport is up to the caller.
+#[derive(Debug, Default)]
+pub struct SenderCore {
+    log_seq: u16,
+}
+
+impl SenderCore {
+    /// Create a new `SenderCore`
+    pub const fn new() -> Self {
+        Self { log_seq: 0 }
+    }
+
+    /// Serialize the header and message into `buf`, returning the used part
+    ///
+    /// See [`serialize_frame()`].
+    pub fn frame<'a, T: Serialize + ?Sized>(
+        &self,
+        buf: &'a mut [u8],
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<&'a mut [u8], WireTxErrorKind> {
+        serialize_frame(buf, hdr, msg)
+    }
+
+    /// Serialize a [`LoggingTopic`] message into `buf`, returning the used part
+    ///
+    /// Fails if the message does not fit in `buf`.
+    pub fn log_str<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        kkind: VarKeyKind,
+        s: &str,
+    ) -> Result<&'a mut [u8], WireTxErrorKind> {
+        let hdr = self.log_header(kkind);
+        serialize_frame(buf, hdr, s)
+    }
+
+    /// Format a [`LoggingTopic`] message into `buf`, returning the used part
+    ///
+    /// See [`serialize_log_fmt()`].
+    pub fn log_fmt<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        kkind: VarKeyKind,
+        args: Arguments<'_>,
+    ) -> Result<&'a mut [u8], WireTxErrorKind> {
+        let hdr = self.log_header(kkind);
+        serialize_log_fmt(buf, hdr, args)
+    }
+
+    /// The header of the next log message, with a key of the given length
+    pub fn log_header(&mut self, kkind: VarKeyKind) -> VarHeader {
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        let ctr = self.log_seq;
+        self.log_seq = self.log_seq.wrapping_add(1);
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq2(ctr),
+        }
+    }
+}
+
+/// Serialize the header and message into `buf`, returning the used part
+///
+/// Fails if the frame does not fit in `buf`.
+pub fn serialize_frame<'a, T: Serialize + ?Sized>(
+    buf: &'a mut [u8],
+    hdr: VarHeader,
+    msg: &T,
+) -> Result<&'a mut [u8], WireTxErrorKind> {
+    let (hdr_used, remain) = hdr.write_to_slice(buf).ok_or(WireTxErrorKind::Other)?;
+    let hdr_len = hdr_used.len();
+    let bdy_used = postcard::to_slice(msg, remain).map_err(|_| WireTxErrorKind::Other)?;
+    let used_ttl = hdr_len + bdy_used.len();
+    buf.get_mut(..used_ttl).ok_or(WireTxErrorKind::Other)
+}
+
+/// The alignment of an [`AlignedBuf`], in bytes
+///
+/// This is the size of a cache line on cores with a data cache, e.g. the
+/// Cortex-M7, and a multiple of the alignment required by most DMA controllers.
+pub const ALIGN: usize = 32;
+
+/// A buffer of `N` bytes, aligned to [`ALIGN`] bytes
+///
+/// Some drivers require the buffers they transmit from to be aligned, e.g. for DMA.
+/// An `AlignedBuf` can be given wherever a `WireTx` impl takes its buffer, such as
+/// the `tx_buf` of the embassy-usb `WireStorage`, or a `TxBufPool`.
+///
+/// ## Alignment contract
+///
+/// * The first byte of the buffer is aligned to [`ALIGN`] bytes
+/// * [`serialize_frame()`] and [`SenderCore`] write each frame starting at the
+///   first byte of the buffer, header first, so the frame has the alignment of
+///   the buffer
+/// * The `WireTx` impls send the frame from the buffer, without copying it. If the
+///   frame is split into packets, packet `i` starts at `i * max_packet_size`, so
+///   it is aligned to the largest power of two that divides the max packet size,
+///   up to [`ALIGN`]
+///
+/// ```rust
+/// use postcard_rpc::server::frame::{AlignedBuf, ALIGN};
+///
+/// let mut buf = AlignedBuf::<64>::new();
+/// assert_eq!(buf.as_mut_slice().as_ptr() as usize % ALIGN, 0);
+/// ```
+#[repr(C, align(32))]
+pub struct AlignedBuf<const N: usize> {
+    buf: [u8; N],
+}
+
+impl<const N: usize> AlignedBuf<N> {
+    /// Create a new, zeroed buffer
+    pub const fn new() -> Self {
+        Self { buf: [0u8; N] }
+    }
+
+    /// The contents of the buffer
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The contents of the buffer, e.g. to be used as a `&'static mut [u8]` when
+    /// the buffer itself is `'static`
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl<const N: usize> Default for AlignedBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::ops::Deref for AlignedBuf<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl<const N: usize> core::ops::DerefMut for AlignedBuf<N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+/// A message body that is already serialized, written to the frame as-is
+///
+/// This can be passed anywhere a message is serialized, e.g. to [`serialize_frame()`]
+/// or [`WireTx::send()`][crate::server::WireTx::send], to send a pre-computed body
+/// without an intermediate buffer. The bytes are not checked, and must be a valid
+/// postcard encoding of the message the receiver expects.
+///
+/// The bytes are serialized as a tuple of `u8`s, which postcard encodes without a
+/// length prefix. Other serializers may not produce the same bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct RawBody<'a>(pub &'a [u8]);
+
+impl Serialize for RawBody<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tup = serializer.serialize_tuple(self.0.len())?;
+        for b in self.0 {
+            tup.serialize_element(b)?;
+        }
+        tup.end()
+    }
+}
+
+/// Format a string message directly into `buf`, returning the used part
+///
+/// This avoids formatting into a temporary buffer first. If the formatted message
+/// does not fit, it is truncated, and ends with `...`.
+pub fn serialize_log_fmt<'a>(
+    buf: &'a mut [u8],
+    hdr: VarHeader,
+    args: Arguments<'_>,
+) -> Result<&'a mut [u8], WireTxErrorKind> {
+    let ttl_len = buf.len();
+    let Some((_hdr, remaining)) = hdr.write_to_slice(buf) else {
+        return Err(WireTxErrorKind::Other);
+    };
+    let max_log_len = actual_varint_max_len(remaining.len());
+
+    // Then, reserve space for non-canonical length fields
+    // We also set all but the last bytes to be "continuation"
+    // bytes
+    if remaining.len() < max_log_len {
+        return Err(WireTxErrorKind::Other);
+    }
+
+    let (len_field, body) = remaining.split_at_mut(max_log_len);
+    for b in len_field.iter_mut() {
+        *b = 0x80;
+    }
+    if let Some(b) = len_field.last_mut() {
+        *b = 0x00;
+    }
+
+    // Then, do the formatting
+    let body_len = body.len();
+    let mut sw = SliceWriter(body);
+    let res = core::fmt::write(&mut sw, args);
+
+    // Calculate the number of bytes used *for formatting*.
+    let remain = sw.0.len();
+    let used = body_len - remain;
+
+    // If we had an error, that's probably because we ran out
+    // of room. If we had an error, AND there is at least three
+    // bytes, then replace those with '.'s like ...
+    if res.is_err() && (body.len() >= 3) {
+        let start = body.len() - 3;
+        body[start..].iter_mut().for_each(|b| *b = b'.');
+    }
+
+    // then go back and fill in the len - we write the len
+    // directly to the reserved bytes, and if we DIDN'T use
+    // the full space, we mark the end of the real length as
+    // a continuation field. This will result in a non-canonical
+    // "extended" length in postcard, and will "spill into" the
+    // bytes we wrote previously above
+    let mut len_bytes = [0u8; varint_max::<usize>()];
+    let len_used = varint_usize(used, &mut len_bytes);
+    if len_used.len() != len_field.len() {
+        if let Some(b) = len_used.last_mut() {
+            *b |= 0x80;
+        }
+    }
+    len_field[..len_used.len()].copy_from_slice(len_used);
+
+    // Calculate the TOTAL amount
+    let act_used = ttl_len - remain;
+    Ok(&mut buf[..act_used])
+}
+
+struct SliceWriter<'a>(&'a mut [u8]);
+
+impl core::fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        let sli = core::mem::take(&mut self.0);
+
+        // If this write would overflow us, note that, but still take
+        // as much as we possibly can here
+        let bad = s.len() > sli.len();
+        let to_write = s.len().min(sli.len());
+        let (now, later) = sli.split_at_mut(to_write);
+        now.copy_from_slice(&s.as_bytes()[..to_write]);
+        self.0 = later;
+
+        // Now, report whether we overflowed or not
+        if bad {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the maximum number of bytes required to encode T.
+const fn varint_max<T: Sized>() -> usize {
+    const BITS_PER_BYTE: usize = 8;
+    const BITS_PER_VARINT_BYTE: usize = 7;
+
+    // How many data bits do we need for this type?
+    let bits = core::mem::size_of::<T>() * BITS_PER_BYTE;
+
+    // We add (BITS_PER_VARINT_BYTE - 1), to ensure any integer divisions
+    // with a remainder will always add exactly one full byte, but
+    // an evenly divided number of bits will be the same
+    let roundup_bits = bits + (BITS_PER_VARINT_BYTE - 1);
+
+    // Apply division, using normal "round down" integer division
+    roundup_bits / BITS_PER_VARINT_BYTE
+}
+
+#[inline]
+fn varint_usize(n: usize, out: &mut [u8; varint_max::<usize>()]) -> &mut [u8] {
+    let mut value = n;
+    for i in 0..varint_max::<usize>() {
+        out[i] = value.to_le_bytes()[0];
+        if value < 128 {
+            return &mut out[..=i];
+        }
+
+        out[i] |= 0x80;
+        value >>= 7;
+    }
+    debug_assert_eq!(value, 0);
+    &mut out[..]
+}
+
+fn actual_varint_max_len(largest: usize) -> usize {
+    if largest < (2 << 7) {
+        1
+    } else if largest < (2 << 14) {
+        2
+    } else if largest < (2 << 21) {
+        3
+    } else if largest < (2 << 28) {
+        4
+    } else {
+        varint_max::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serialize_frame, AlignedBuf, SenderCore, ALIGN};
+    use crate::{
+        header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+        standard_icd::PingEndpoint,
+        Endpoint,
+    };
+
+    #[test]
+    fn log_frames() {
+        let mut core = SenderCore::new();
+        let mut buf = [0u8; 32];
+
+        let frame = core.log_str(&mut buf, VarKeyKind::Key2, "hello").unwrap();
+        let (hdr, body) = VarHeader::take_from_slice(frame).unwrap();
+        assert_eq!(hdr.seq_no, VarSeq::Seq2(0));
+        assert_eq!(postcard::from_bytes::<&str>(body).unwrap(), "hello");
+
+        let frame = core
+            .log_fmt(&mut buf, VarKeyKind::Key2, format_args!("x: {}", 5))
+            .unwrap();
+        let (hdr, body) = VarHeader::take_from_slice(frame).unwrap();
+        assert_eq!(hdr.seq_no, VarSeq::Seq2(1));
+        assert_eq!(postcard::from_bytes::<&str>(body).unwrap(), "x: 5");
+
+        // Messages that don't fit are truncated
+        let frame = core
+            .log_fmt(&mut buf, VarKeyKind::Key2, format_args!("{:64}", 0))
+            .unwrap();
+        let (_hdr, body) = VarHeader::take_from_slice(frame).unwrap();
+        assert!(postcard::from_bytes::<&str>(body).unwrap().ends_with("..."));
+    }
+
+    #[test]
+    fn aligned_frames() {
+        assert_eq!(core::mem::align_of::<AlignedBuf<1>>(), ALIGN);
+
+        // The frame is serialized in place, at the start of the buffer
+        let mut buf = AlignedBuf::<64>::new();
+        let start = buf.as_ptr() as usize;
+        assert_eq!(start % ALIGN, 0);
+        let hdr = VarHeader {
+            key: VarKey::Key8(PingEndpoint::RESP_KEY),
+            seq_no: VarSeq::Seq1(0),
+        };
+        let frame = serialize_frame(&mut buf, hdr, &1234u32).unwrap();
+        assert_eq!(frame.as_ptr() as usize, start);
+    }
+}
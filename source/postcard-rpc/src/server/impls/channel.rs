@@ -0,0 +1,207 @@
+//! Implementation using channels of frames
+//!
+//! Sometimes the transport is not owned by postcard-rpc, e.g. when a UART or USB
+//! endpoint carries several protocols, and a multiplexer routes the bytes of each
+//! protocol to its own handler. With a [`ChannelWire`], the server receives whole
+//! frames from one channel, and sends whole frames to another. The multiplexer
+//! pushes the frames it received for postcard-rpc with [`ChannelWire::incoming()`],
+//! and takes the frames to send with [`ChannelWire::outgoing()`], framing them as
+//! it sees fit.
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::impls::channel::{ChannelWire, Frame};
+//!
+//! // Frames of up to 256 bytes, and up to 4 frames in each direction
+//! static WIRE: ChannelWire<CriticalSectionRawMutex, 256, 4> = ChannelWire::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     tx_impl: ChannelWireTx<CriticalSectionRawMutex, 256, 4>;
+//!     spawn_impl: EmbassyWireSpawn;
+//!     // ...
+//! }
+//!
+//! let (rx, tx) = WIRE.split();
+//! let server = Server::new(tx, rx, buf_256, dispatcher, kkind);
+//!
+//! // In the multiplexer
+//! loop {
+//!     match select(port.read_packet(), WIRE.outgoing().receive()).await {
+//!         Either::First((PROTO_RPC, data)) => {
+//!             WIRE.incoming().send(Frame::from_slice(data).unwrap()).await;
+//!         }
+//!         Either::First((proto, data)) => other_protocol(proto, data).await,
+//!         Either::Second(frame) => port.write_packet(PROTO_RPC, &frame).await,
+//!     }
+//! }
+//! ```
+//!
+//! This impl does not include a [`WireSpawn`](crate::server::WireSpawn), use the
+//! one of the executor instead, e.g. `EmbassyWireSpawn`.
+//!
+//! The matching host side is `HostClient::new_with_channels()`.
+//!
+//! **Requires feature**: `channel-server`
+
+use core::{cell::RefCell, fmt::Arguments};
+
+use embassy_sync_0_7::{
+    blocking_mutex::{raw::RawMutex, Mutex as BlockingMutex},
+    channel::Channel,
+};
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKeyKind},
+    server::{frame::SenderCore, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
+};
+
+/// A single frame of up to `N` bytes
+pub type Frame<const N: usize> = heapless::Vec<u8, N>;
+
+/// A channel of up to `DEPTH` frames of up to `N` bytes
+pub type FrameChannel<M, const N: usize, const DEPTH: usize> = Channel<M, Frame<N>, DEPTH>;
+
+/// The channels of frames to and from the host
+///
+/// See the [module docs](self) for details.
+pub struct ChannelWire<M: RawMutex + 'static, const N: usize, const DEPTH: usize> {
+    incoming: FrameChannel<M, N, DEPTH>,
+    outgoing: FrameChannel<M, N, DEPTH>,
+    core: BlockingMutex<M, RefCell<SenderCore>>,
+}
+
+/// The WireTX impl for channels
+pub struct ChannelWireTx<M: RawMutex + 'static, const N: usize, const DEPTH: usize> {
+    wire: &'static ChannelWire<M, N, DEPTH>,
+}
+
+/// The WireRX impl for channels
+pub struct ChannelWireRx<M: RawMutex + 'static, const N: usize, const DEPTH: usize> {
+    wire: &'static ChannelWire<M, N, DEPTH>,
+}
+
+// ----- IMPLS -----
+
+// impl ChannelWire
+
+impl<M: RawMutex + 'static, const N: usize, const DEPTH: usize> ChannelWire<M, N, DEPTH> {
+    /// Create new, empty channels
+    pub const fn new() -> Self {
+        Self {
+            incoming: Channel::new(),
+            outgoing: Channel::new(),
+            core: BlockingMutex::new(RefCell::new(SenderCore::new())),
+        }
+    }
+
+    /// Create the RX and TX impls of the server
+    pub fn split(&'static self) -> (ChannelWireRx<M, N, DEPTH>, ChannelWireTx<M, N, DEPTH>) {
+        (ChannelWireRx { wire: self }, ChannelWireTx { wire: self })
+    }
+
+    /// The frames received from the host, to be handled by the server
+    pub fn incoming(&self) -> &FrameChannel<M, N, DEPTH> {
+        &self.incoming
+    }
+
+    /// The frames sent by the server, to be sent to the host
+    pub fn outgoing(&self) -> &FrameChannel<M, N, DEPTH> {
+        &self.outgoing
+    }
+
+    /// Serialize a frame with `f`, into a new [`Frame`]
+    fn frame_with<F>(&self, f: F) -> Result<Frame<N>, WireTxErrorKind>
+    where
+        F: FnOnce(&mut SenderCore, &mut [u8]) -> Result<usize, WireTxErrorKind>,
+    {
+        let mut frame = Frame::<N>::new();
+        // NOTE: can't fail, the frame has a capacity of exactly N
+        let _ = frame.resize_default(N);
+        let len = self
+            .core
+            .lock(|core| f(&mut core.borrow_mut(), &mut frame))?;
+        frame.truncate(len);
+        Ok(frame)
+    }
+}
+
+impl<M: RawMutex + 'static, const N: usize, const DEPTH: usize> Default
+    for ChannelWire<M, N, DEPTH>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// impl ChannelWireTx
+
+impl<M: RawMutex + 'static, const N: usize, const DEPTH: usize> Clone
+    for ChannelWireTx<M, N, DEPTH>
+{
+    fn clone(&self) -> Self {
+        Self { wire: self.wire }
+    }
+}
+
+impl<M: RawMutex + 'static, const N: usize, const DEPTH: usize> WireTx
+    for ChannelWireTx<M, N, DEPTH>
+{
+    type Error = WireTxErrorKind;
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let frame = self
+            .wire
+            .frame_with(|core, buf| core.frame(buf, hdr, msg).map(|used| used.len()))?;
+        self.wire.outgoing.send(frame).await;
+        Ok(())
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let frame = Frame::from_slice(buf).map_err(|_| WireTxErrorKind::Other)?;
+        self.wire.outgoing.send(frame).await;
+        Ok(())
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let frame = self
+            .wire
+            .frame_with(|core, buf| core.log_str(buf, kkind, s).map(|used| used.len()))?;
+        self.wire.outgoing.send(frame).await;
+        Ok(())
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let frame = self
+            .wire
+            .frame_with(|core, buf| core.log_fmt(buf, kkind, args).map(|used| used.len()))?;
+        self.wire.outgoing.send(frame).await;
+        Ok(())
+    }
+}
+
+// impl ChannelWireRx
+
+impl<M: RawMutex + 'static, const N: usize, const DEPTH: usize> WireRx
+    for ChannelWireRx<M, N, DEPTH>
+{
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let frame = self.wire.incoming.receive().await;
+        let out = buf
+            .get_mut(..frame.len())
+            .ok_or(WireRxErrorKind::ReceivedMessageTooLarge)?;
+        out.copy_from_slice(&frame);
+        Ok(out)
+    }
+}
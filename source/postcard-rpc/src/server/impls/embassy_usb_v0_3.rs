@@ -410,6 +410,18 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
 
         send_all::<D>(ep_in, &tx_buf[..act_used], pending_frame).await
     }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        // Taking the lock waits for any write in progress
+        let mut inner = self.inner.lock().await;
+        let EUsbWireTxInner {
+            ep_in,
+            pending_frame,
+            ..
+        }: &mut EUsbWireTxInner<D> = &mut inner;
+        // 2ms per frame, like `send_all`
+        flush_pending::<D>(ep_in, pending_frame, 2).await
+    }
 }
 
 #[inline]
@@ -532,6 +544,33 @@ fn actual_varint_max_len(largest: usize) -> usize {
     }
 }
 
+/// Terminate a frame left unterminated by a timed out [`send_all`]
+async fn flush_pending<D>(
+    ep_in: &mut D::EndpointIn,
+    pending_frame: &mut bool,
+    timeout_ms_per_frame: usize,
+) -> Result<(), WireTxErrorKind>
+where
+    D: Driver<'static>,
+{
+    if !*pending_frame {
+        return Ok(());
+    }
+    match select(
+        ep_in.write(&[]),
+        Timer::after_millis(timeout_ms_per_frame as u64),
+    )
+    .await
+    {
+        Either::First(Ok(())) => {
+            *pending_frame = false;
+            Ok(())
+        }
+        Either::First(Err(_)) => Err(WireTxErrorKind::ConnectionClosed),
+        Either::Second(()) => Err(WireTxErrorKind::Timeout),
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // RX
 //////////////////////////////////////////////////////////////////////////////
@@ -557,7 +596,16 @@ impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
                 Err(EndpointError::BufferOverflow) => {
                     return Err(WireRxErrorKind::ReceivedMessageTooLarge)
                 }
-                Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
+                Err(EndpointError::Disabled) => {
+                    // If the endpoint was disabled part way through a frame, e.g.
+                    // by a reset, let the client know the request was lost. The
+                    // partial frame is discarded, so the next frame starts fresh.
+                    let len = buflen - window.len();
+                    return Err(match VarHeader::take_from_slice(&buf[..len]) {
+                        Some((hdr, _)) => WireRxErrorKind::TruncatedFrame(hdr),
+                        None => WireRxErrorKind::ConnectionClosed,
+                    });
+                }
             };
 
             let (_now, later) = window.split_at_mut(n);
@@ -572,15 +620,18 @@ impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
             }
         }
 
-        // If we got here, we've run out of space. That's disappointing. Accumulate to the
-        // end of this packet
+        // If we got here, we've run out of space. That's disappointing. Note which
+        // request this was, so the client can be told, then accumulate to the end
+        // of this packet
+        let too_large = match VarHeader::take_from_slice(buf) {
+            Some((hdr, _)) => WireRxErrorKind::ReceivedBodyTooLarge(hdr),
+            None => WireRxErrorKind::ReceivedMessageTooLarge,
+        };
         loop {
             match self.ep_out.read(buf).await {
                 Ok(64) => {}
-                Ok(_) => return Err(WireRxErrorKind::ReceivedMessageTooLarge),
-                Err(EndpointError::BufferOverflow) => {
-                    return Err(WireRxErrorKind::ReceivedMessageTooLarge)
-                }
+                Ok(_) => return Err(too_large),
+                Err(EndpointError::BufferOverflow) => return Err(too_large),
                 Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
             };
         }
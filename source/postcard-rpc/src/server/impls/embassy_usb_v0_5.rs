@@ -1,15 +1,18 @@
 //! Implementation using `embassy-usb` and bulk interfaces
 
 use crate::{
-    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
-    server::{WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
-    standard_icd::LoggingTopic,
-    Topic,
+    header::{VarHeader, VarKeyKind},
+    server::{
+        frame::{serialize_frame, AlignedBuf, SenderCore},
+        WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
 };
 use core::fmt::Arguments;
+use core::future::poll_fn;
 use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::{Context, Poll};
 use embassy_futures::select::{select, Either};
-use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, channel::Channel, mutex::Mutex};
 use embassy_time::Timer;
 use embassy_usb_driver_0_2::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
 use serde::Serialize;
@@ -23,6 +26,134 @@ static HDLR: ConstStaticCell<PoststationHandler> = ConstStaticCell::new(Poststat
 /// Default time in milliseconds to wait for the completion of sending
 pub const DEFAULT_TIMEOUT_MS_PER_FRAME: usize = 2;
 
+/// The speed the device is used at, which sets the limits of an [`EndpointConfig`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbSpeed {
+    /// USB Full Speed, 12Mbit/s
+    Full,
+    /// USB High Speed, 480Mbit/s
+    High,
+}
+
+/// The transfer type of the endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EndpointKind {
+    /// Bulk endpoints, using all bandwidth left over by other transfers
+    Bulk,
+    /// Interrupt endpoints, polled by the host at a guaranteed interval
+    Interrupt {
+        /// The `bInterval` of the endpoint descriptors
+        ///
+        /// At Full Speed, this is the polling interval in milliseconds, in the range
+        /// `1..=255`. At High Speed, the interval is `2^(interval - 1)` microframes of
+        /// 125us, with `interval` in the range `1..=16`.
+        interval: u8,
+    },
+}
+
+/// An invalid [`EndpointConfig`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EndpointConfigError {
+    /// The max packet size is not allowed for this transfer type and speed
+    InvalidMaxPacketSize,
+    /// The interval is not allowed for this speed
+    InvalidInterval,
+}
+
+/// The configuration of the IN and OUT endpoints used by postcard-rpc
+///
+/// Larger packets increase throughput, as fewer packets are needed for each
+/// frame, while interrupt endpoints bound the latency of each packet, as the host
+/// polls them at a fixed interval instead of when bandwidth is left over. The
+/// constructors check the configuration against the limits of the USB 2.0 spec:
+///
+/// | Speed | Bulk          | Interrupt    | Interval    |
+/// | ----- | ----          | ---------    | --------    |
+/// | Full  | 8, 16, 32, 64 | 1..=64       | 1..=255 ms  |
+/// | High  | 512           | 1..=1024     | 1..=16      |
+///
+/// ```rust,ignore
+/// const ENDPOINTS: EndpointConfig = match EndpointConfig::bulk(UsbSpeed::High, 512) {
+///     Ok(cfg) => cfg,
+///     Err(_) => panic!("invalid endpoint config"),
+/// };
+/// static STORAGE: AppStorage = AppStorage::new_with_endpoints(ENDPOINTS);
+/// ```
+///
+/// The default is Full Speed bulk endpoints with a max packet size of 64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EndpointConfig {
+    kind: EndpointKind,
+    max_packet_size: u16,
+}
+
+impl EndpointConfig {
+    /// Full Speed bulk endpoints with a max packet size of 64
+    pub const DEFAULT: Self = Self {
+        kind: EndpointKind::Bulk,
+        max_packet_size: 64,
+    };
+
+    /// Bulk endpoints with the given max packet size
+    pub const fn bulk(speed: UsbSpeed, max_packet_size: u16) -> Result<Self, EndpointConfigError> {
+        let valid = match speed {
+            UsbSpeed::Full => matches!(max_packet_size, 8 | 16 | 32 | 64),
+            UsbSpeed::High => max_packet_size == 512,
+        };
+        if !valid {
+            return Err(EndpointConfigError::InvalidMaxPacketSize);
+        }
+        Ok(Self {
+            kind: EndpointKind::Bulk,
+            max_packet_size,
+        })
+    }
+
+    /// Interrupt endpoints with the given max packet size and interval
+    ///
+    /// See [`EndpointKind::Interrupt`] for the meaning of `interval`.
+    pub const fn interrupt(
+        speed: UsbSpeed,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self, EndpointConfigError> {
+        let (max_mps, max_interval) = match speed {
+            UsbSpeed::Full => (64, 255),
+            UsbSpeed::High => (1024, 16),
+        };
+        if max_packet_size == 0 || max_packet_size > max_mps {
+            return Err(EndpointConfigError::InvalidMaxPacketSize);
+        }
+        if interval == 0 || interval > max_interval {
+            return Err(EndpointConfigError::InvalidInterval);
+        }
+        Ok(Self {
+            kind: EndpointKind::Interrupt { interval },
+            max_packet_size,
+        })
+    }
+
+    /// The transfer type of the endpoints
+    pub const fn kind(&self) -> EndpointKind {
+        self.kind
+    }
+
+    /// The max packet size of the endpoints
+    pub const fn max_packet_size(&self) -> u16 {
+        self.max_packet_size
+    }
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 impl embassy_usb_0_5::Handler for PoststationHandler {
     fn get_string(
         &mut self,
@@ -46,7 +177,8 @@ impl embassy_usb_0_5::Handler for PoststationHandler {
 /// A collection of types and aliases useful for importing the correct types
 pub mod dispatch_impl {
     use super::{
-        EUsbWireRx, EUsbWireTx, EUsbWireTxInner, UsbDeviceBuffers, DEFAULT_TIMEOUT_MS_PER_FRAME,
+        EUsbWireRx, EUsbWireTx, EUsbWireTxInner, EndpointConfig, EndpointKind, UsbDeviceBuffers,
+        DEFAULT_TIMEOUT_MS_PER_FRAME,
     };
     pub use crate::server::impls::embassy_shared::embassy_spawn as spawn_fn;
 
@@ -56,7 +188,7 @@ pub mod dispatch_impl {
     use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, mutex::Mutex};
     use embassy_usb_0_5::{
         msos::{self, windows_version},
-        Builder, Config, UsbDevice,
+        Builder, Config, InterfaceAltBuilder, UsbDevice,
     };
     use embassy_usb_driver_0_2::Driver;
     use static_cell::{ConstStaticCell, StaticCell};
@@ -83,6 +215,8 @@ pub mod dispatch_impl {
         pub bufs_usb: ConstStaticCell<UsbDeviceBuffers<CONFIG, BOS, CONTROL, MSOS>>,
         /// WireTx/Sender static storage
         pub cell: StaticCell<Mutex<M, EUsbWireTxInner<D>>>,
+        /// The configuration of the endpoints
+        pub endpoints: EndpointConfig,
     }
 
     impl<
@@ -96,9 +230,36 @@ pub mod dispatch_impl {
     {
         /// Create a new, uninitialized static set of buffers
         pub const fn new() -> Self {
+            Self::new_with_endpoints(EndpointConfig::DEFAULT)
+        }
+
+        /// Create a new, uninitialized static set of buffers, using the given
+        /// configuration for the endpoints
+        ///
+        /// See [`EndpointConfig`] for details.
+        pub const fn new_with_endpoints(endpoints: EndpointConfig) -> Self {
             Self {
                 bufs_usb: ConstStaticCell::new(UsbDeviceBuffers::new()),
                 cell: StaticCell::new(),
+                endpoints,
+            }
+        }
+
+        /// Allocate the OUT and IN endpoints, as configured
+        fn alloc_endpoints(
+            &self,
+            alt: &mut InterfaceAltBuilder<'_, 'static, D>,
+        ) -> (D::EndpointOut, D::EndpointIn) {
+            let mps = self.endpoints.max_packet_size();
+            match self.endpoints.kind() {
+                EndpointKind::Bulk => (
+                    alt.endpoint_bulk_out(None, mps),
+                    alt.endpoint_bulk_in(None, mps),
+                ),
+                EndpointKind::Interrupt { interval } => (
+                    alt.endpoint_interrupt_out(None, mps, interval),
+                    alt.endpoint_interrupt_in(None, mps, interval),
+                ),
             }
         }
 
@@ -146,26 +307,42 @@ pub mod dispatch_impl {
             let stindx = interface.string();
             super::STINDX.store(stindx.0, core::sync::atomic::Ordering::Relaxed);
             let mut alt = interface.alt_setting(0xFF, 0xCA, 0x7D, Some(stindx));
-            let ep_out = alt.endpoint_bulk_out(None, 64);
-            let ep_in = alt.endpoint_bulk_in(None, 64);
+            let (ep_out, ep_in) = self.alloc_endpoints(&mut alt);
             drop(function);
 
+            let max_packet_size = usize::from(self.endpoints.max_packet_size());
             let wtx = self.cell.init(Mutex::new(EUsbWireTxInner {
                 ep_in,
-                log_seq: 0,
+                core: crate::server::frame::SenderCore::new(),
                 tx_buf,
                 pending_frame: false,
                 timeout_ms_per_frame: DEFAULT_TIMEOUT_MS_PER_FRAME,
+                max_packet_size,
             }));
 
             // Build the builder.
             let usb = builder.build();
 
-            (usb, EUsbWireTx { inner: wtx }, EUsbWireRx { ep_out })
+            (
+                usb,
+                EUsbWireTx {
+                    inner: wtx,
+                    pool: None,
+                },
+                EUsbWireRx {
+                    ep_out,
+                    max_packet_size,
+                },
+            )
         }
 
         /// Initialize the static storage.
         ///
+        /// Frames are serialized into `tx_buf`, and sent from it without copying.
+        /// If the driver requires aligned buffers, e.g. for DMA, use an
+        /// [`AlignedBuf`], such as the `tx_buf`
+        /// of [`PacketBuffers`](super::PacketBuffers).
+        ///
         /// This must only be called once.
         pub fn init(
             &'static self,
@@ -215,19 +392,30 @@ pub mod dispatch_impl {
             let mut function = builder.function(0xFF, 0, 0);
             let mut interface = function.interface();
             let mut alt = interface.alt_setting(0xFF, 0, 0, None);
-            let ep_out = alt.endpoint_bulk_out(None, 64);
-            let ep_in = alt.endpoint_bulk_in(None, 64);
+            let (ep_out, ep_in) = self.alloc_endpoints(&mut alt);
             drop(function);
 
+            let max_packet_size = usize::from(self.endpoints.max_packet_size());
             let wtx = self.cell.init(Mutex::new(EUsbWireTxInner {
                 ep_in,
-                log_seq: 0,
+                core: crate::server::frame::SenderCore::new(),
                 tx_buf,
                 pending_frame: false,
                 timeout_ms_per_frame: DEFAULT_TIMEOUT_MS_PER_FRAME,
+                max_packet_size,
             }));
 
-            (builder, EUsbWireTx { inner: wtx }, EUsbWireRx { ep_out })
+            (
+                builder,
+                EUsbWireTx {
+                    inner: wtx,
+                    pool: None,
+                },
+                EUsbWireRx {
+                    ep_out,
+                    max_packet_size,
+                },
+            )
         }
     }
 }
@@ -239,19 +427,118 @@ pub mod dispatch_impl {
 /// Implementation detail, holding the endpoint and scratch buffer used for sending
 pub struct EUsbWireTxInner<D: Driver<'static>> {
     ep_in: D::EndpointIn,
-    log_seq: u16,
+    core: SenderCore,
     tx_buf: &'static mut [u8],
     pending_frame: bool,
     timeout_ms_per_frame: usize,
+    max_packet_size: usize,
 }
 
 /// A [`WireTx`] implementation for embassy-usb 0.4.
 #[derive(Copy)]
 pub struct EUsbWireTx<M: RawMutex + 'static, D: Driver<'static> + 'static> {
     inner: &'static Mutex<M, EUsbWireTxInner<D>>,
+    pool: Option<&'static dyn TxPool>,
+}
+
+/// A pool of buffers used for serializing outgoing frames
+///
+/// By default, [`EUsbWireTx`] serializes every frame into the single `tx_buf`
+/// provided at init time, while holding exclusive access to the USB endpoint, so
+/// a large reply delays all other replies until it has been serialized AND sent.
+/// With a pool (see [`EUsbWireTx::with_tx_pool()`]), each send checks out a buffer
+/// of its own, so concurrent handlers (e.g. `spawn` handlers) can serialize in
+/// parallel, and only wait for each other for the USB write itself.
+///
+/// If all buffers are checked out, senders wait for one to be returned.
+///
+/// ```rust,ignore
+/// static POOL: TxBufPool<ThreadModeRawMutex, 4> = TxBufPool::new();
+///
+/// for buf in [BUF_A.take(), BUF_B.take(), BUF_C.take(), BUF_D.take()] {
+///     POOL.add(buf).unwrap();
+/// }
+/// let tx = tx.with_tx_pool(&POOL);
+/// ```
+pub struct TxBufPool<M: RawMutex, const N: usize> {
+    free: Channel<M, &'static mut [u8], N>,
+}
+
+impl<M: RawMutex, const N: usize> TxBufPool<M, N> {
+    /// Create a new, empty, pool
+    pub const fn new() -> Self {
+        Self {
+            free: Channel::new(),
+        }
+    }
+
+    /// Add a buffer to the pool
+    ///
+    /// Returns the buffer if the pool already holds `N` buffers.
+    pub fn add(&self, buf: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+        self.free.try_send(buf).map_err(|e| match e {
+            embassy_sync_0_7::channel::TrySendError::Full(buf) => buf,
+        })
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default for TxBufPool<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Object safe interface of [`TxBufPool`], so that the size of the pool is not
+/// part of the type of [`EUsbWireTx`]
+trait TxPool: Sync {
+    fn poll_take(&self, cx: &mut Context<'_>) -> Poll<&'static mut [u8]>;
+    fn put(&self, buf: &'static mut [u8]);
+}
+
+impl<M: RawMutex + Sync, const N: usize> TxPool for TxBufPool<M, N> {
+    fn poll_take(&self, cx: &mut Context<'_>) -> Poll<&'static mut [u8]> {
+        self.free.poll_receive(cx)
+    }
+
+    fn put(&self, buf: &'static mut [u8]) {
+        // We only ever return buffers we took, so there is always room
+        let _ = self.free.try_send(buf);
+    }
+}
+
+/// A buffer checked out from a [`TxPool`], returned when dropped
+struct PoolBuf {
+    pool: &'static dyn TxPool,
+    buf: &'static mut [u8],
+}
+
+impl PoolBuf {
+    async fn take(pool: &'static dyn TxPool) -> Self {
+        let buf = poll_fn(|cx| pool.poll_take(cx)).await;
+        Self { pool, buf }
+    }
+}
+
+impl Drop for PoolBuf {
+    fn drop(&mut self) {
+        self.pool.put(core::mem::take(&mut self.buf));
+    }
 }
 
 impl<M: RawMutex + 'static, D: Driver<'static> + 'static> EUsbWireTx<M, D> {
+    /// Serialize frames into buffers from the given pool, instead of the shared `tx_buf`
+    ///
+    /// This applies to frames sent with [`WireTx::send()`] by this [`EUsbWireTx`]
+    /// and all clones made from it afterwards, log messages still use the shared
+    /// `tx_buf`. See [`TxBufPool`] for more details.
+    pub fn with_tx_pool<PM: RawMutex + Sync, const N: usize>(
+        mut self,
+        pool: &'static TxBufPool<PM, N>,
+    ) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
     /// Set the timeout in milliseconds per USB frame
     ///
     /// The sender will wait `(frames * timeout)` in milliseconds before reporting
@@ -288,7 +575,10 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> EUsbWireTx<M, D> {
 
 impl<M: RawMutex + 'static, D: Driver<'static> + 'static> Clone for EUsbWireTx<M, D> {
     fn clone(&self) -> Self {
-        EUsbWireTx { inner: self.inner }
+        EUsbWireTx {
+            inner: self.inner,
+            pool: self.pool,
+        }
     }
 }
 
@@ -305,25 +595,33 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
         hdr: VarHeader,
         msg: &T,
     ) -> Result<(), Self::Error> {
+        if let Some(pool) = self.pool {
+            // Serialize before taking the lock, so others may send in the meantime
+            let pbuf = PoolBuf::take(pool).await;
+            let used = serialize_frame(pbuf.buf, hdr, msg)?;
+            return self.send_raw(used).await;
+        }
+
         let mut inner = self.inner.lock().await;
 
         let EUsbWireTxInner {
             ep_in,
-            log_seq: _,
+            core,
             tx_buf,
             pending_frame,
             timeout_ms_per_frame,
+            max_packet_size,
         }: &mut EUsbWireTxInner<D> = &mut inner;
 
-        let (hdr_used, remain) = hdr.write_to_slice(tx_buf).ok_or(WireTxErrorKind::Other)?;
-        let bdy_used = postcard::to_slice(msg, remain).map_err(|_| WireTxErrorKind::Other)?;
-        let used_ttl = hdr_used.len() + bdy_used.len();
-
-        if let Some(used) = tx_buf.get(..used_ttl) {
-            send_all::<D>(ep_in, used, pending_frame, *timeout_ms_per_frame).await
-        } else {
-            Err(WireTxErrorKind::Other)
-        }
+        let used = core.frame(tx_buf, hdr, msg)?;
+        send_all::<D>(
+            ep_in,
+            used,
+            pending_frame,
+            *timeout_ms_per_frame,
+            *max_packet_size,
+        )
+        .await
     }
 
     async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
@@ -332,9 +630,17 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
             ep_in,
             pending_frame,
             timeout_ms_per_frame,
+            max_packet_size,
             ..
         }: &mut EUsbWireTxInner<D> = &mut inner;
-        send_all::<D>(ep_in, buf, pending_frame, *timeout_ms_per_frame).await
+        send_all::<D>(
+            ep_in,
+            buf,
+            pending_frame,
+            *timeout_ms_per_frame,
+            *max_packet_size,
+        )
+        .await
     }
 
     async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
@@ -342,34 +648,22 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
 
         let EUsbWireTxInner {
             ep_in,
-            log_seq,
+            core,
             tx_buf,
             pending_frame,
             timeout_ms_per_frame,
+            max_packet_size,
         }: &mut EUsbWireTxInner<D> = &mut inner;
 
-        let key = match kkind {
-            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
-            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
-            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
-            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
-        };
-        let ctr = *log_seq;
-        *log_seq = log_seq.wrapping_add(1);
-        let wh = VarHeader {
-            key,
-            seq_no: VarSeq::Seq2(ctr),
-        };
-
-        let (hdr_used, remain) = wh.write_to_slice(tx_buf).ok_or(WireTxErrorKind::Other)?;
-        let bdy_used = postcard::to_slice::<str>(s, remain).map_err(|_| WireTxErrorKind::Other)?;
-        let used_ttl = hdr_used.len() + bdy_used.len();
-
-        if let Some(used) = tx_buf.get(..used_ttl) {
-            send_all::<D>(ep_in, used, pending_frame, *timeout_ms_per_frame).await
-        } else {
-            Err(WireTxErrorKind::Other)
-        }
+        let used = core.log_str(tx_buf, kkind, s)?;
+        send_all::<D>(
+            ep_in,
+            used,
+            pending_frame,
+            *timeout_ms_per_frame,
+            *max_packet_size,
+        )
+        .await
     }
 
     async fn send_log_fmt<'a>(
@@ -381,88 +675,35 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
 
         let EUsbWireTxInner {
             ep_in,
-            log_seq,
+            core,
             tx_buf,
             pending_frame,
             timeout_ms_per_frame,
+            max_packet_size,
         }: &mut EUsbWireTxInner<D> = &mut inner;
-        let ttl_len = tx_buf.len();
-
-        let key = match kkind {
-            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
-            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
-            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
-            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
-        };
-        let ctr = *log_seq;
-        *log_seq = log_seq.wrapping_add(1);
-        let wh = VarHeader {
-            key,
-            seq_no: VarSeq::Seq2(ctr),
-        };
-        let Some((_hdr, remaining)) = wh.write_to_slice(tx_buf) else {
-            return Err(WireTxErrorKind::Other);
-        };
-        let max_log_len = actual_varint_max_len(remaining.len());
-
-        // Then, reserve space for non-canonical length fields
-        // We also set all but the last bytes to be "continuation"
-        // bytes
-        if remaining.len() < max_log_len {
-            return Err(WireTxErrorKind::Other);
-        }
-
-        let (len_field, body) = remaining.split_at_mut(max_log_len);
-        for b in len_field.iter_mut() {
-            *b = 0x80;
-        }
-        if let Some(b) = len_field.last_mut() {
-            *b = 0x00;
-        }
-
-        // Then, do the formatting
-        let body_len = body.len();
-        let mut sw = SliceWriter(body);
-        let res = core::fmt::write(&mut sw, args);
-
-        // Calculate the number of bytes used *for formatting*.
-        let remain = sw.0.len();
-        let used = body_len - remain;
-
-        // If we had an error, that's probably because we ran out
-        // of room. If we had an error, AND there is at least three
-        // bytes, then replace those with '.'s like ...
-        if res.is_err() && (body.len() >= 3) {
-            let start = body.len() - 3;
-            body[start..].iter_mut().for_each(|b| *b = b'.');
-        }
-
-        // then go back and fill in the len - we write the len
-        // directly to the reserved bytes, and if we DIDN'T use
-        // the full space, we mark the end of the real length as
-        // a continuation field. This will result in a non-canonical
-        // "extended" length in postcard, and will "spill into" the
-        // bytes we wrote previously above
-        let mut len_bytes = [0u8; varint_max::<usize>()];
-        let len_used = varint_usize(used, &mut len_bytes);
-        if len_used.len() != len_field.len() {
-            if let Some(b) = len_used.last_mut() {
-                *b |= 0x80;
-            }
-        }
-        len_field[..len_used.len()].copy_from_slice(len_used);
-
-        // Calculate the TOTAL amount
-        let act_used = ttl_len - remain;
 
+        let used = core.log_fmt(tx_buf, kkind, args)?;
         send_all::<D>(
             ep_in,
-            &tx_buf[..act_used],
+            used,
             pending_frame,
             *timeout_ms_per_frame,
+            *max_packet_size,
         )
         .await
     }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        // Taking the lock waits for any write in progress
+        let mut inner = self.inner.lock().await;
+        let EUsbWireTxInner {
+            ep_in,
+            pending_frame,
+            timeout_ms_per_frame,
+            ..
+        }: &mut EUsbWireTxInner<D> = &mut inner;
+        flush_pending::<D>(ep_in, pending_frame, *timeout_ms_per_frame).await
+    }
 }
 
 #[inline]
@@ -471,6 +712,7 @@ async fn send_all<D>(
     out: &[u8],
     pending_frame: &mut bool,
     timeout_ms_per_frame: usize,
+    max_packet_size: usize,
 ) -> Result<(), WireTxErrorKind>
 where
     D: Driver<'static>,
@@ -481,7 +723,7 @@ where
 
     // Calculate an estimated timeout based on the number of frames we need to send
     // For now, we use 2ms/frame by default, rounded UP
-    let frames = (out.len() + 63) / 64;
+    let frames = out.len().div_ceil(max_packet_size);
     let timeout_ms = frames * timeout_ms_per_frame;
 
     let send_fut = async {
@@ -492,17 +734,17 @@ where
         }
         *pending_frame = true;
 
-        // write in segments of 64. The last chunk may
-        // be 0 < len <= 64.
-        for ch in out.chunks(64) {
+        // write in segments of max_packet_size. The last chunk may
+        // be 0 < len <= max_packet_size.
+        for ch in out.chunks(max_packet_size) {
             if ep_in.write(ch).await.is_err() {
                 return Err(WireTxErrorKind::ConnectionClosed);
             }
         }
-        // If the total we sent was a multiple of 64, send an
+        // If the total we sent was a multiple of max_packet_size, send an
         // empty message to "flush" the transaction. We already checked
         // above that the len != 0.
-        if (out.len() & (64 - 1)) == 0 && ep_in.write(&[]).await.is_err() {
+        if out.len().is_multiple_of(max_packet_size) && ep_in.write(&[]).await.is_err() {
             return Err(WireTxErrorKind::ConnectionClosed);
         }
 
@@ -516,73 +758,30 @@ where
     }
 }
 
-struct SliceWriter<'a>(&'a mut [u8]);
-
-impl<'a> core::fmt::Write for SliceWriter<'a> {
-    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
-        let sli = core::mem::take(&mut self.0);
-
-        // If this write would overflow us, note that, but still take
-        // as much as we possibly can here
-        let bad = s.len() > sli.len();
-        let to_write = s.len().min(sli.len());
-        let (now, later) = sli.split_at_mut(to_write);
-        now.copy_from_slice(s.as_bytes());
-        self.0 = later;
-
-        // Now, report whether we overflowed or not
-        if bad {
-            Err(core::fmt::Error)
-        } else {
-            Ok(())
-        }
+/// Terminate a frame left unterminated by a timed out [`send_all`]
+async fn flush_pending<D>(
+    ep_in: &mut D::EndpointIn,
+    pending_frame: &mut bool,
+    timeout_ms_per_frame: usize,
+) -> Result<(), WireTxErrorKind>
+where
+    D: Driver<'static>,
+{
+    if !*pending_frame {
+        return Ok(());
     }
-}
-
-/// Returns the maximum number of bytes required to encode T.
-const fn varint_max<T: Sized>() -> usize {
-    const BITS_PER_BYTE: usize = 8;
-    const BITS_PER_VARINT_BYTE: usize = 7;
-
-    // How many data bits do we need for this type?
-    let bits = core::mem::size_of::<T>() * BITS_PER_BYTE;
-
-    // We add (BITS_PER_VARINT_BYTE - 1), to ensure any integer divisions
-    // with a remainder will always add exactly one full byte, but
-    // an evenly divided number of bits will be the same
-    let roundup_bits = bits + (BITS_PER_VARINT_BYTE - 1);
-
-    // Apply division, using normal "round down" integer division
-    roundup_bits / BITS_PER_VARINT_BYTE
-}
-
-#[inline]
-fn varint_usize(n: usize, out: &mut [u8; varint_max::<usize>()]) -> &mut [u8] {
-    let mut value = n;
-    for i in 0..varint_max::<usize>() {
-        out[i] = value.to_le_bytes()[0];
-        if value < 128 {
-            return &mut out[..=i];
+    match select(
+        ep_in.write(&[]),
+        Timer::after_millis(timeout_ms_per_frame as u64),
+    )
+    .await
+    {
+        Either::First(Ok(())) => {
+            *pending_frame = false;
+            Ok(())
         }
-
-        out[i] |= 0x80;
-        value >>= 7;
-    }
-    debug_assert_eq!(value, 0);
-    &mut out[..]
-}
-
-fn actual_varint_max_len(largest: usize) -> usize {
-    if largest < (2 << 7) {
-        1
-    } else if largest < (2 << 14) {
-        2
-    } else if largest < (2 << 21) {
-        3
-    } else if largest < (2 << 28) {
-        4
-    } else {
-        varint_max::<usize>()
+        Either::First(Err(_)) => Err(WireTxErrorKind::ConnectionClosed),
+        Either::Second(()) => Err(WireTxErrorKind::Timeout),
     }
 }
 
@@ -593,6 +792,7 @@ fn actual_varint_max_len(largest: usize) -> usize {
 /// A [`WireRx`] implementation for embassy-usb 0.4.
 pub struct EUsbWireRx<D: Driver<'static>> {
     ep_out: D::EndpointOut,
+    max_packet_size: usize,
 }
 
 impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
@@ -611,12 +811,21 @@ impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
                 Err(EndpointError::BufferOverflow) => {
                     return Err(WireRxErrorKind::ReceivedMessageTooLarge)
                 }
-                Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
+                Err(EndpointError::Disabled) => {
+                    // If the endpoint was disabled part way through a frame, e.g.
+                    // by a reset, let the client know the request was lost. The
+                    // partial frame is discarded, so the next frame starts fresh.
+                    let len = buflen - window.len();
+                    return Err(match VarHeader::take_from_slice(&buf[..len]) {
+                        Some((hdr, _)) => WireRxErrorKind::TruncatedFrame(hdr),
+                        None => WireRxErrorKind::ConnectionClosed,
+                    });
+                }
             };
 
             let (_now, later) = window.split_at_mut(n);
             window = later;
-            if n != 64 {
+            if n != self.max_packet_size {
                 // We now have a full frame! Great!
                 let wlen = window.len();
                 let len = buflen - wlen;
@@ -626,15 +835,18 @@ impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
             }
         }
 
-        // If we got here, we've run out of space. That's disappointing. Accumulate to the
-        // end of this packet
+        // If we got here, we've run out of space. That's disappointing. Note which
+        // request this was, so the client can be told, then accumulate to the end
+        // of this packet
+        let too_large = match VarHeader::take_from_slice(buf) {
+            Some((hdr, _)) => WireRxErrorKind::ReceivedBodyTooLarge(hdr),
+            None => WireRxErrorKind::ReceivedMessageTooLarge,
+        };
         loop {
             match self.ep_out.read(buf).await {
-                Ok(64) => {}
-                Ok(_) => return Err(WireRxErrorKind::ReceivedMessageTooLarge),
-                Err(EndpointError::BufferOverflow) => {
-                    return Err(WireRxErrorKind::ReceivedMessageTooLarge)
-                }
+                Ok(n) if n == self.max_packet_size => {}
+                Ok(_) => return Err(too_large),
+                Err(EndpointError::BufferOverflow) => return Err(too_large),
                 Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
             };
         }
@@ -684,8 +896,8 @@ impl<const CONFIG: usize, const BOS: usize, const CONTROL: usize, const MSOS: us
 
 /// Static storage for generically sized input and output packet buffers
 pub struct PacketBuffers<const TX: usize = 1024, const RX: usize = 1024> {
-    /// the transmit buffer
-    pub tx_buf: [u8; TX],
+    /// the transmit buffer, aligned for DMA, see [`AlignedBuf`]
+    pub tx_buf: AlignedBuf<TX>,
     /// thereceive buffer
     pub rx_buf: [u8; RX],
 }
@@ -694,7 +906,7 @@ impl<const TX: usize, const RX: usize> PacketBuffers<TX, RX> {
     /// Create new empty buffers
     pub const fn new() -> Self {
         Self {
-            tx_buf: [0u8; TX],
+            tx_buf: AlignedBuf::new(),
             rx_buf: [0u8; RX],
         }
     }
@@ -1024,3 +1236,45 @@ pub mod fake {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{EndpointConfig, EndpointConfigError, EndpointKind, UsbSpeed};
+
+    #[test]
+    fn endpoint_config_limits() {
+        let cfg = EndpointConfig::bulk(UsbSpeed::Full, 32).unwrap();
+        assert_eq!(cfg.kind(), EndpointKind::Bulk);
+        assert_eq!(cfg.max_packet_size(), 32);
+        assert!(EndpointConfig::bulk(UsbSpeed::High, 512).is_ok());
+        assert_eq!(
+            EndpointConfig::bulk(UsbSpeed::Full, 48),
+            Err(EndpointConfigError::InvalidMaxPacketSize)
+        );
+        assert_eq!(
+            EndpointConfig::bulk(UsbSpeed::High, 64),
+            Err(EndpointConfigError::InvalidMaxPacketSize)
+        );
+
+        let cfg = EndpointConfig::interrupt(UsbSpeed::Full, 48, 10).unwrap();
+        assert_eq!(cfg.kind(), EndpointKind::Interrupt { interval: 10 });
+        assert!(EndpointConfig::interrupt(UsbSpeed::High, 1024, 16).is_ok());
+        assert_eq!(
+            EndpointConfig::interrupt(UsbSpeed::Full, 65, 1),
+            Err(EndpointConfigError::InvalidMaxPacketSize)
+        );
+        assert_eq!(
+            EndpointConfig::interrupt(UsbSpeed::Full, 0, 1),
+            Err(EndpointConfigError::InvalidMaxPacketSize)
+        );
+        assert_eq!(
+            EndpointConfig::interrupt(UsbSpeed::High, 64, 17),
+            Err(EndpointConfigError::InvalidInterval)
+        );
+        assert_eq!(
+            EndpointConfig::interrupt(UsbSpeed::Full, 64, 0),
+            Err(EndpointConfigError::InvalidInterval)
+        );
+        assert_eq!(EndpointConfig::default(), EndpointConfig::DEFAULT);
+    }
+}
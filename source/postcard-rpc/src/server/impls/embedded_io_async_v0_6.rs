@@ -1,14 +1,27 @@
 //! Implementation using `embedded-io-async`
+//!
+//! This implementation uses COBS framing over any byte stream that implements
+//! the `embedded-io-async` [`Read`] and [`Write`] traits, such as a UART (for
+//! example embassy's `BufferedUart`), allowing a server to be used on targets
+//! without USB.
+//!
+//! The send half is guarded by a mutex of any [`MutexKind`], e.g. an
+//! `embassy-sync` `RawMutex` like `CriticalSectionRawMutex` with the default
+//! `embassy-sync-0_7` feature, or the mutex of an RTOS, see the
+//! [`mutex`](crate::server::mutex) module. Handlers are spawned with
+//! `embassy-executor` either way.
 use core::{fmt::Arguments, marker::PhantomData, ops::DerefMut};
 
 use crate::{
     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
-    server::{WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
+    server::{
+        mutex::{AsyncMutex, MutexKind},
+        WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+    },
     standard_icd::LoggingTopic,
     Topic,
 };
 use cobs::decode;
-use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, mutex::Mutex};
 use embedded_io_async_0_6::{Read, Write};
 use postcard::{
     ser_flavors::{Flavor, Slice},
@@ -35,25 +48,19 @@ pub use super::embassy_shared::embassy_spawn;
 pub use super::embassy_shared::EmbassyWireSpawn as EioWireSpawn;
 
 /// A handy type for storing buffers and the RX/TX impls
-pub struct WireStorage<
-    Rx: Read,
-    Tx: Write,
-    M: RawMutex + 'static,
-    const RXB: usize,
-    const TXB: usize,
-> {
+pub struct WireStorage<Rx: Read, Tx: Write, M: MutexKind, const RXB: usize, const TXB: usize> {
     bufs: ConstStaticCell<([u8; RXB], [u8; TXB])>,
-    tx: StaticCell<Mutex<M, EioWireTxInner<Tx>>>,
+    tx: StaticCell<M::Mutex<EioWireTxInner<Tx>>>,
     _rx: PhantomData<Rx>,
 }
 
 /// The WireTX impl for embedded-io-async
 pub struct EioWireTx<R, Tx>
 where
-    R: RawMutex + 'static,
+    R: MutexKind,
     Tx: Write + 'static,
 {
-    t: &'static Mutex<R, EioWireTxInner<Tx>>,
+    t: &'static R::Mutex<EioWireTxInner<Tx>>,
 }
 
 /// Embedded-IO Wire RX implementation
@@ -76,7 +83,7 @@ struct EioWireTxInner<Tx: Write> {
 
 // impl WireStorage
 
-impl<Rx: Read, Tx: Write, M: RawMutex + 'static, const RXB: usize, const TXB: usize>
+impl<Rx: Read, Tx: Write, M: MutexKind, const RXB: usize, const TXB: usize>
     WireStorage<Rx, Tx, M, RXB, TXB>
 {
     /// Create a new wire storage
@@ -91,7 +98,7 @@ impl<Rx: Read, Tx: Write, M: RawMutex + 'static, const RXB: usize, const TXB: us
     /// Create a new Wire pair using this storage
     pub fn init(&'static self, r: Rx, t: Tx) -> Option<(EioWireRx<Rx>, EioWireTx<M, Tx>)> {
         let (rxb, txb) = self.bufs.try_take()?;
-        let txi = self.tx.try_init(Mutex::new(EioWireTxInner {
+        let txi = self.tx.try_init(M::new_mutex(EioWireTxInner {
             t,
             tx_buf: txb,
             log_seq: 0,
@@ -106,7 +113,7 @@ impl<Rx: Read, Tx: Write, M: RawMutex + 'static, const RXB: usize, const TXB: us
     }
 }
 
-impl<Rx: Read, Tx: Write, M: RawMutex + 'static, const RXB: usize, const TXB: usize> Default
+impl<Rx: Read, Tx: Write, M: MutexKind, const RXB: usize, const TXB: usize> Default
     for WireStorage<Rx, Tx, M, RXB, TXB>
 {
     fn default() -> Self {
@@ -118,7 +125,7 @@ impl<Rx: Read, Tx: Write, M: RawMutex + 'static, const RXB: usize, const TXB: us
 
 impl<R, Tx> Clone for EioWireTx<R, Tx>
 where
-    R: RawMutex + 'static,
+    R: MutexKind,
     Tx: Write + 'static,
 {
     fn clone(&self) -> Self {
@@ -128,7 +135,7 @@ where
 
 impl<R, Tx> WireTx for EioWireTx<R, Tx>
 where
-    R: RawMutex + 'static,
+    R: MutexKind,
     Tx: Write + 'static,
 {
     type Error = WireTxErrorKind;
@@ -225,10 +232,89 @@ where
 
     async fn send_log_fmt<'a>(
         &self,
-        _kkind: VarKeyKind,
-        _a: Arguments<'a>,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
     ) -> Result<(), Self::Error> {
-        todo!()
+        let mut guard = self.t.lock().await;
+        let EioWireTxInner { t, tx_buf, log_seq } = guard.deref_mut();
+        let ttl_len = tx_buf.len();
+
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        let ctr = *log_seq;
+        *log_seq = log_seq.wrapping_add(1);
+        let wh = VarHeader {
+            key,
+            seq_no: VarSeq::Seq2(ctr),
+        };
+
+        // We can't go back and patch the length field once the data has been
+        // cobs encoded, so first figure out how long the formatted message is
+        let mut counter = CountWriter(0);
+        let _ = core::fmt::write(&mut counter, args);
+
+        // Work out how much room we have for the message: the worst case header,
+        // the length varint, and the cobs overhead (one byte per 254, plus the
+        // leading code byte and trailing sentinel).
+        let overhead = (1 + 4 + 8) + varint_max::<usize>() + (ttl_len / 254) + 2;
+        let max_log_len = ttl_len.saturating_sub(overhead);
+
+        // If the message doesn't fit, truncate it and replace the last three
+        // bytes with '.'s like ...
+        let (msg_len, ellipsis) = if counter.0 > max_log_len {
+            (max_log_len, max_log_len >= 3)
+        } else {
+            (counter.0, false)
+        };
+
+        // Create a cobs-encoding flavor using our temp buffer
+        let mut flavor = flava_flav(tx_buf)?;
+        header_to_flavor(&wh, &mut flavor)?;
+
+        let mut len_buf = [0u8; varint_max::<usize>()];
+        let len_used = varint_usize(msg_len, &mut len_buf);
+        flavor
+            .try_extend(len_used)
+            .map_err(|_| WireTxErrorKind::Other)?;
+
+        let fmt_len = if ellipsis { msg_len - 3 } else { msg_len };
+        let mut cw = CobsWriter {
+            flav: &mut flavor,
+            remain: fmt_len,
+        };
+        let _ = core::fmt::write(&mut cw, args);
+
+        // The formatter gave us fewer bytes than it did the first time around,
+        // we can't fix up the length field now, so just give up.
+        if cw.remain != 0 {
+            return Err(WireTxErrorKind::Other);
+        }
+        if ellipsis {
+            flavor
+                .try_extend(b"...")
+                .map_err(|_| WireTxErrorKind::Other)?;
+        }
+        let used = flavor.finalize().map_err(|_| WireTxErrorKind::Other)?;
+
+        // Write it all to the serial port now
+        t.write_all(used)
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)?;
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        guard
+            .t
+            .flush()
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)
     }
 }
 
@@ -402,6 +488,70 @@ fn body_to_flavor<'a, T: Serialize + ?Sized>(
     Ok(used)
 }
 
+/// A `fmt::Write` impl that only counts the number of bytes written
+struct CountWriter(usize);
+
+impl core::fmt::Write for CountWriter {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// A `fmt::Write` impl that cobs encodes up to `remain` bytes into the flavor
+struct CobsWriter<'a, 'b> {
+    flav: &'a mut Cobs<Slice<'b>>,
+    remain: usize,
+}
+
+impl core::fmt::Write for CobsWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        let to_write = s.len().min(self.remain);
+        self.flav
+            .try_extend(&s.as_bytes()[..to_write])
+            .map_err(|_| core::fmt::Error)?;
+        self.remain -= to_write;
+        if to_write < s.len() {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the maximum number of bytes required to encode T.
+const fn varint_max<T: Sized>() -> usize {
+    const BITS_PER_BYTE: usize = 8;
+    const BITS_PER_VARINT_BYTE: usize = 7;
+
+    // How many data bits do we need for this type?
+    let bits = core::mem::size_of::<T>() * BITS_PER_BYTE;
+
+    // We add (BITS_PER_VARINT_BYTE - 1), to ensure any integer divisions
+    // with a remainder will always add exactly one full byte, but
+    // an evenly divided number of bits will be the same
+    let roundup_bits = bits + (BITS_PER_VARINT_BYTE - 1);
+
+    // Apply division, using normal "round down" integer division
+    roundup_bits / BITS_PER_VARINT_BYTE
+}
+
+/// Encode a usize as a postcard varint into the given buffer
+fn varint_usize(n: usize, out: &mut [u8; varint_max::<usize>()]) -> &[u8] {
+    let mut value = n;
+    for i in 0..varint_max::<usize>() {
+        out[i] = value.to_le_bytes()[0];
+        if value < 128 {
+            return &out[..=i];
+        }
+
+        out[i] |= 0x80;
+        value >>= 7;
+    }
+    debug_assert_eq!(value, 0);
+    &out[..]
+}
+
 // ---- COPY AND PASTE SHAME ----
 
 use core::ops::IndexMut;
@@ -469,3 +619,69 @@ where
         self.flav.finalize()
     }
 }
+
+#[cfg(all(test, feature = "use-std", feature = "embassy-sync-0_7"))]
+mod test {
+    use core::convert::Infallible;
+
+    use embassy_sync_0_7::blocking_mutex::raw::NoopRawMutex;
+    use embedded_io_async_0_6::{ErrorType, Write};
+
+    use super::{EioWireSpawn, WireStorage};
+    use crate::{
+        header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+        server::{WireClock, WireTimer, WireTx},
+        standard_icd::LoggingTopic,
+        Topic,
+    };
+
+    /// A UART collecting everything written to it
+    struct VecWriter(Vec<u8>);
+
+    impl ErrorType for VecWriter {
+        type Error = Infallible;
+    }
+
+    impl Write for VecWriter {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Send a formatted log message through a transport with a `TXB` byte buffer,
+    /// and decode the frame written to the UART
+    async fn log_frame<const TXB: usize>(args: core::fmt::Arguments<'_>) -> (VarHeader, String) {
+        let storage: &'static WireStorage<&'static [u8], VecWriter, NoopRawMutex, 64, TXB> =
+            Box::leak(Box::new(WireStorage::new()));
+        let (_rx, tx) = storage.init(&[], VecWriter(vec![])).unwrap();
+        tx.send_log_fmt(VarKeyKind::Key8, args).await.unwrap();
+
+        let written = core::mem::take(&mut tx.t.lock().await.t.0);
+        assert_eq!(written.last(), Some(&0), "frames end with the sentinel");
+        let mut frame = vec![0u8; written.len()];
+        let rpt = cobs::decode(&written, &mut frame).unwrap();
+        let (hdr, body) = VarHeader::take_from_slice(&frame[..rpt.frame_size()]).unwrap();
+        let msg: &str = postcard::from_bytes(body).unwrap();
+        (hdr, msg.to_string())
+    }
+
+    #[test]
+    fn spawn_supports_timeouts() {
+        // Required by `timeout_ms` handlers, and the `async_deadline` kind
+        fn timer<T: WireTimer + WireClock>() {}
+        timer::<EioWireSpawn>();
+    }
+
+    #[tokio::test]
+    async fn send_log_fmt_frames() {
+        let (hdr, msg) = log_frame::<128>(format_args!("temp {} C", 21)).await;
+        assert_eq!(hdr.key, VarKey::Key8(LoggingTopic::TOPIC_KEY));
+        assert_eq!(hdr.seq_no, VarSeq::Seq2(0));
+        assert_eq!(msg, "temp 21 C");
+
+        // Messages not fitting into the buffer are truncated with an ellipsis
+        let (_, msg) = log_frame::<32>(format_args!("{} is way too long", "this")).await;
+        assert_eq!(msg, "this...");
+    }
+}
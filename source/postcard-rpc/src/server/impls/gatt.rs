@@ -0,0 +1,310 @@
+//! Implementation using a Bluetooth LE GATT characteristic
+//!
+//! The client writes requests and topic messages to a characteristic, and the
+//! server sends replies, topic messages, and logs as notifications of the same
+//! characteristic. This works with any BLE stack, such as `trouble-host` or
+//! `nrf-softdevice`: the application passes writes of the characteristic to a
+//! [`GattWriteQueue`], and implements [`GattNotify`] to send notifications.
+//!
+//! A single write or notification carries at most the ATT MTU minus 3 bytes, which
+//! is as little as 20 bytes before a larger MTU is negotiated. Larger frames are
+//! split into fragments of up to `F` bytes with the [`fragment`](crate::fragment)
+//! module, so the client must enable fragmentation with the same size, see
+//! `HostClient::set_fragmentation()`. Log messages are sent unfragmented, and are
+//! truncated to fit in a single notification.
+//!
+//! For example, with `trouble-host`:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::impls::gatt::{
+//!     dispatch_impl::{WireRxImpl, WireTxImpl, WireSpawnImpl, spawn_fn},
+//!     GattNotify, GattWriteQueue, WireStorage,
+//! };
+//!
+//! type Notify = ServerNotify<'static>;
+//!
+//! static WRITES: GattWriteQueue<CriticalSectionRawMutex, 244, 4> = GattWriteQueue::new();
+//! static STORAGE: WireStorage<CriticalSectionRawMutex, Notify, 256> = WireStorage::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     // Frames of up to 512 bytes are sent, in notifications of up to 20 bytes
+//!     tx_impl: WireTxImpl<CriticalSectionRawMutex, Notify, 512, 20>;
+//!     spawn_impl: WireSpawnImpl;
+//!     // ...
+//! }
+//!
+//! struct ServerNotify<'a> {
+//!     rpc: Characteristic<[u8; 244]>,
+//!     conn: GattConnection<'a, 'static, DefaultPacketPool>,
+//! }
+//!
+//! impl GattNotify for ServerNotify<'_> {
+//!     type Error = trouble_host::Error;
+//!
+//!     fn max_payload(&self) -> usize {
+//!         usize::from(self.conn.raw().att_mtu()) - 3
+//!     }
+//!
+//!     async fn notify(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+//!         self.rpc.notify(&self.conn, data).await
+//!     }
+//! }
+//!
+//! // In the GATT event loop, pass writes of the characteristic to the server
+//! if let GattEvent::Write(event) = &event {
+//!     if event.handle() == rpc.handle {
+//!         let _ = WRITES.try_push(event.data());
+//!     }
+//! }
+//!
+//! // Start the server
+//! let (rx, tx) = STORAGE.init(ServerNotify { rpc, conn }, &WRITES).unwrap();
+//! let server = Server::new(
+//!     FragWireTx::new(tx),
+//!     FragWireRx::new(rx),
+//!     // Requests are reassembled in the receive buffer
+//!     buf_512,
+//!     dispatcher,
+//!     kkind,
+//! );
+//! ```
+
+use core::{fmt::Arguments, ops::DerefMut};
+
+use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, channel::Channel, mutex::Mutex};
+use serde::Serialize;
+use static_cell::{ConstStaticCell, StaticCell};
+
+use crate::{
+    header::{VarHeader, VarKeyKind},
+    server::{frame::SenderCore, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
+};
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    use crate::fragment::{FragWireRx, FragWireTx};
+    pub use crate::server::impls::embassy_shared::embassy_spawn as spawn_fn;
+
+    /// Type alias for `WireTx` impl, sending frames of up to `TXB` bytes in
+    /// notifications of up to `F` bytes
+    pub type WireTxImpl<M, N, const TXB: usize, const F: usize> =
+        FragWireTx<super::GattWireTx<M, N>, TXB, F>;
+    /// Type alias for `WireRx` impl, receiving writes of up to `P` bytes
+    pub type WireRxImpl<M, const P: usize, const D: usize> = FragWireRx<super::GattWireRx<M, P, D>>;
+    /// Type alias for `WireSpawn` impl
+    pub type WireSpawnImpl = crate::server::impls::embassy_shared::EmbassyWireSpawn;
+    /// Type alias for the receive buffer
+    pub type WireRxBuf = &'static mut [u8];
+}
+
+pub use super::embassy_shared::embassy_spawn;
+pub use super::embassy_shared::EmbassyWireSpawn as GattWireSpawn;
+
+/// Sends notifications of the characteristic, implemented by the application
+pub trait GattNotify {
+    /// The error type of the BLE stack
+    type Error;
+
+    /// The largest number of bytes of a single notification
+    ///
+    /// This is the ATT MTU of the connection, minus 3 bytes.
+    fn max_payload(&self) -> usize;
+
+    /// Send a notification of the characteristic
+    ///
+    /// Errors are treated as the connection being closed.
+    async fn notify(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The error returned by [`GattWriteQueue::try_push()`]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GattWriteError {
+    /// The write was longer than `P` bytes
+    TooLong,
+    /// `D` writes are already waiting to be received
+    QueueFull,
+}
+
+/// A queue of up to `D` writes of the characteristic, of up to `P` bytes each
+pub struct GattWriteQueue<M: RawMutex, const P: usize, const D: usize> {
+    writes: Channel<M, heapless::Vec<u8, P>, D>,
+}
+
+/// A handy type for storing buffers and the TX impl
+pub struct WireStorage<M: RawMutex + 'static, N: GattNotify + 'static, const TXB: usize> {
+    buf: ConstStaticCell<[u8; TXB]>,
+    tx: StaticCell<Mutex<M, GattWireTxInner<N>>>,
+}
+
+/// The WireTX impl for BLE GATT
+pub struct GattWireTx<M: RawMutex + 'static, N: GattNotify + 'static> {
+    t: &'static Mutex<M, GattWireTxInner<N>>,
+}
+
+/// The WireRX impl for BLE GATT
+pub struct GattWireRx<M: RawMutex + 'static, const P: usize, const D: usize> {
+    writes: &'static GattWriteQueue<M, P, D>,
+}
+
+struct GattWireTxInner<N: GattNotify> {
+    notify: N,
+    tx_buf: &'static mut [u8],
+    core: SenderCore,
+}
+
+// ----- IMPLS -----
+
+// impl GattWriteQueue
+
+impl<M: RawMutex, const P: usize, const D: usize> GattWriteQueue<M, P, D> {
+    /// Create a new, empty, queue
+    pub const fn new() -> Self {
+        Self {
+            writes: Channel::new(),
+        }
+    }
+
+    /// Queue a write of the characteristic, to be received by the server
+    ///
+    /// This doesn't wait, so it can be called from the event loop of the BLE stack.
+    pub fn try_push(&self, data: &[u8]) -> Result<(), GattWriteError> {
+        let data = heapless::Vec::from_slice(data).map_err(|_| GattWriteError::TooLong)?;
+        self.writes
+            .try_send(data)
+            .map_err(|_| GattWriteError::QueueFull)
+    }
+
+    /// Drop all queued writes, e.g. when the client disconnects
+    pub fn clear(&self) {
+        self.writes.clear();
+    }
+}
+
+impl<M: RawMutex, const P: usize, const D: usize> Default for GattWriteQueue<M, P, D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// impl WireStorage
+
+impl<M: RawMutex + 'static, N: GattNotify + 'static, const TXB: usize> WireStorage<M, N, TXB> {
+    /// Create a new wire storage
+    pub const fn new() -> Self {
+        Self {
+            buf: ConstStaticCell::new([0u8; TXB]),
+            tx: StaticCell::new(),
+        }
+    }
+
+    /// Create a new Wire pair using this storage, receiving writes from `writes`
+    pub fn init<const P: usize, const D: usize>(
+        &'static self,
+        notify: N,
+        writes: &'static GattWriteQueue<M, P, D>,
+    ) -> Option<(GattWireRx<M, P, D>, GattWireTx<M, N>)> {
+        let tx_buf = self.buf.try_take()?;
+        let t = self.tx.try_init(Mutex::new(GattWireTxInner {
+            notify,
+            tx_buf,
+            core: SenderCore::new(),
+        }))?;
+        Some((GattWireRx { writes }, GattWireTx { t }))
+    }
+}
+
+impl<M: RawMutex + 'static, N: GattNotify + 'static, const TXB: usize> Default
+    for WireStorage<M, N, TXB>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// impl GattWireTx
+
+impl<M: RawMutex + 'static, N: GattNotify + 'static> Clone for GattWireTx<M, N> {
+    fn clone(&self) -> Self {
+        Self { t: self.t }
+    }
+}
+
+impl<N: GattNotify> GattWireTxInner<N> {
+    /// The part of the buffer that fits in a single notification
+    fn notify_buf(&mut self) -> (&mut N, &mut [u8], &mut SenderCore) {
+        let len = self.tx_buf.len().min(self.notify.max_payload());
+        (&mut self.notify, &mut self.tx_buf[..len], &mut self.core)
+    }
+}
+
+impl<M: RawMutex + 'static, N: GattNotify + 'static> WireTx for GattWireTx<M, N> {
+    type Error = WireTxErrorKind;
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let (notify, buf, core) = guard.deref_mut().notify_buf();
+        let frame = core.frame(buf, hdr, msg)?;
+        notify
+            .notify(frame)
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let GattWireTxInner { notify, .. } = guard.deref_mut();
+        if buf.len() > notify.max_payload() {
+            return Err(WireTxErrorKind::Other);
+        }
+        notify
+            .notify(buf)
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let (notify, buf, core) = guard.deref_mut().notify_buf();
+        let frame = core.log_str(buf, kkind, s)?;
+        notify
+            .notify(frame)
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let (notify, buf, core) = guard.deref_mut().notify_buf();
+        let frame = core.log_fmt(buf, kkind, args)?;
+        notify
+            .notify(frame)
+            .await
+            .map_err(|_| WireTxErrorKind::ConnectionClosed)
+    }
+}
+
+// impl GattWireRx
+
+impl<M: RawMutex + 'static, const P: usize, const D: usize> WireRx for GattWireRx<M, P, D> {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let data = self.writes.writes.receive().await;
+        let out = buf
+            .get_mut(..data.len())
+            .ok_or(WireRxErrorKind::ReceivedMessageTooLarge)?;
+        out.copy_from_slice(&data);
+        Ok(out)
+    }
+}
@@ -14,14 +14,31 @@ pub mod embassy_usb_v0_5;
 #[cfg(feature = "embedded-io-async-0_6-server")]
 pub mod embedded_io_async_v0_6;
 
+#[cfg(feature = "gatt-server")]
+pub mod gatt;
+
+#[cfg(feature = "udp-server")]
+pub mod udp;
+
+#[cfg(feature = "rtt-server")]
+pub mod rtt;
+
+#[cfg(feature = "channel-server")]
+pub mod channel;
+
 #[cfg(feature = "test-utils")]
 pub mod test_channels;
 
+#[cfg(all(feature = "tcp", not(target_family = "wasm")))]
+pub mod tcp;
+
 #[cfg(any(
     feature = "embassy-usb-0_3-server",
     feature = "embassy-usb-0_4-server",
     feature = "embassy-usb-0_5-server",
     feature = "embedded-io-async-0_6-server",
+    feature = "gatt-server",
+    feature = "udp-server",
 ))]
 pub(crate) mod embassy_shared {
     use crate::server::WireSpawn;
@@ -54,6 +71,18 @@ pub(crate) mod embassy_shared {
         }
     }
 
+    impl crate::server::WireTimer for EmbassyWireSpawn {
+        async fn delay_ms(&self, ms: u32) {
+            embassy_time::Timer::after_millis(ms.into()).await;
+        }
+    }
+
+    impl crate::server::WireClock for EmbassyWireSpawn {
+        fn now_ms() -> u64 {
+            embassy_time::Instant::now().as_millis()
+        }
+    }
+
     /// Attempt to spawn the given token
     pub fn embassy_spawn<Sp, S: Sized>(sp: &Sp, tok: SpawnToken<S>) -> Result<(), Sp::Error>
     where
@@ -0,0 +1,205 @@
+//! Implementation using SEGGER RTT channels
+//!
+//! RTT channels are ring buffers in the RAM of the target, which a debug probe
+//! reads and writes while the target is running. They need no hardware besides
+//! the probe, so they are available from the first instructions of the firmware,
+//! e.g. for diagnostics during bring-up, before the product's real transport is
+//! configured.
+//!
+//! Frames are COBS encoded, like with the [`embedded_io_async_v0_6`] impl, which
+//! this impl reuses: an RTT up channel (target to host) is adapted to an
+//! `embedded-io-async` [`Write`] with [`RttWrite`], and a down channel (host to
+//! target) to a [`Read`] with [`RttRead`]. The channels are polled, waiting with
+//! a [`WireTimer`] between attempts, as RTT has no way to notify the target.
+//!
+//! This works with any RTT implementation, by implementing [`RttUp`] and
+//! [`RttDown`] for its channels. With `rtt-target`, the up channel must be in
+//! `NoBlockTrim` mode:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::impls::rtt::{
+//!     dispatch_impl::{spawn_fn, WireRxImpl, WireSpawnImpl, WireTxImpl},
+//!     RttDown, RttRead, RttUp, RttWrite, WireStorage,
+//! };
+//! use rtt_target::{rtt_init, ChannelMode, DownChannel, UpChannel};
+//!
+//! struct Up(UpChannel);
+//! struct Down(DownChannel);
+//!
+//! impl RttUp for Up {
+//!     fn write(&mut self, data: &[u8]) -> usize {
+//!         self.0.write(data)
+//!     }
+//! }
+//!
+//! impl RttDown for Down {
+//!     fn read(&mut self, buf: &mut [u8]) -> usize {
+//!         self.0.read(buf)
+//!     }
+//! }
+//!
+//! /// Waits with `embassy-time`
+//! #[derive(Clone)]
+//! struct Delay;
+//!
+//! impl WireTimer for Delay {
+//!     async fn delay_ms(&self, ms: u32) {
+//!         embassy_time::Timer::after_millis(ms.into()).await;
+//!     }
+//! }
+//!
+//! static STORAGE: WireStorage<Up, Down, Delay, CriticalSectionRawMutex, 256, 256> =
+//!     WireStorage::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     tx_impl: WireTxImpl<CriticalSectionRawMutex, Up, Delay>;
+//!     spawn_impl: WireSpawnImpl;
+//!     // ...
+//! }
+//!
+//! // In main, channel 0 is left for defmt or other logs
+//! let channels = rtt_init! {
+//!     up: { 0: { size: 1024, name: "defmt" } 1: { size: 1024, name: "postcard-rpc" } }
+//!     down: { 0: { size: 16, name: "terminal" } 1: { size: 512, name: "postcard-rpc" } }
+//! };
+//! let mut up = channels.up.1;
+//! up.set_mode(ChannelMode::NoBlockTrim);
+//! let (rx, tx) = STORAGE
+//!     .init(
+//!         RttRead::new(Down(channels.down.1), Delay, 1),
+//!         RttWrite::new(Up(up), Delay, 1),
+//!     )
+//!     .unwrap();
+//! ```
+//!
+//! `defmt-rtt` only provides the up channel 0 for defmt, and can't be used
+//! together with further channels. Use `rtt-target` with its `defmt` feature
+//! instead, to log with defmt on channel 0.
+//!
+//! Writes wait until the host has read enough of the up channel to make room, so
+//! the server blocks on sending while no probe is attached. The host side is
+//! `HostClient::new_rtt()`, with the `rtt-host` feature.
+//!
+//! [`embedded_io_async_v0_6`]: crate::server::impls::embedded_io_async_v0_6
+
+use core::convert::Infallible;
+
+use embedded_io_async_0_6::{ErrorType, Read, Write};
+
+use crate::server::WireTimer;
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    pub use crate::server::impls::embassy_shared::embassy_spawn as spawn_fn;
+
+    /// Type alias for `WireTx` impl
+    pub type WireTxImpl<M, Up, T> =
+        crate::server::impls::embedded_io_async_v0_6::EioWireTx<M, super::RttWrite<Up, T>>;
+    /// Type alias for `WireRx` impl
+    pub type WireRxImpl<Down, T> =
+        crate::server::impls::embedded_io_async_v0_6::EioWireRx<super::RttRead<Down, T>>;
+    /// Type alias for `WireSpawn` impl
+    pub type WireSpawnImpl = crate::server::impls::embassy_shared::EmbassyWireSpawn;
+    /// Type alias for the receive buffer
+    pub type WireRxBuf = &'static mut [u8];
+}
+
+pub use super::embassy_shared::embassy_spawn;
+pub use super::embassy_shared::EmbassyWireSpawn as RttWireSpawn;
+
+/// A handy type for storing buffers and the RX/TX impls, receiving frames of up
+/// to `RXB` bytes, and sending frames of up to `TXB` bytes, after encoding
+pub type WireStorage<Up, Down, T, M, const RXB: usize, const TXB: usize> =
+    crate::server::impls::embedded_io_async_v0_6::WireStorage<
+        RttRead<Down, T>,
+        RttWrite<Up, T>,
+        M,
+        RXB,
+        TXB,
+    >;
+
+/// An RTT up channel, carrying bytes from the target to the host
+pub trait RttUp {
+    /// Write as many bytes of `data` as fit into the channel, without waiting
+    ///
+    /// Returns the number of bytes written, which is 0 if the channel is full.
+    fn write(&mut self, data: &[u8]) -> usize;
+}
+
+/// An RTT down channel, carrying bytes from the host to the target
+pub trait RttDown {
+    /// Read as many bytes as available into `buf`, without waiting
+    ///
+    /// Returns the number of bytes read, which is 0 if the channel is empty.
+    fn read(&mut self, buf: &mut [u8]) -> usize;
+}
+
+/// An `embedded-io-async` [`Write`] impl over an [`RttUp`] channel
+///
+/// While the channel is full, it is polled again every `poll_ms` milliseconds.
+pub struct RttWrite<Up, T> {
+    up: Up,
+    timer: T,
+    poll_ms: u32,
+}
+
+impl<Up: RttUp, T: WireTimer> RttWrite<Up, T> {
+    /// Write to `up`, waiting `poll_ms` milliseconds with `timer` while it is full
+    pub fn new(up: Up, timer: T, poll_ms: u32) -> Self {
+        Self { up, timer, poll_ms }
+    }
+}
+
+impl<Up, T> ErrorType for RttWrite<Up, T> {
+    type Error = Infallible;
+}
+
+impl<Up: RttUp, T: WireTimer> Write for RttWrite<Up, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        loop {
+            let used = self.up.write(buf);
+            if used != 0 || buf.is_empty() {
+                return Ok(used);
+            }
+            self.timer.delay_ms(self.poll_ms).await;
+        }
+    }
+}
+
+/// An `embedded-io-async` [`Read`] impl over an [`RttDown`] channel
+///
+/// While the channel is empty, it is polled again every `poll_ms` milliseconds.
+pub struct RttRead<Down, T> {
+    down: Down,
+    timer: T,
+    poll_ms: u32,
+}
+
+impl<Down: RttDown, T: WireTimer> RttRead<Down, T> {
+    /// Read from `down`, waiting `poll_ms` milliseconds with `timer` while it is empty
+    pub fn new(down: Down, timer: T, poll_ms: u32) -> Self {
+        Self {
+            down,
+            timer,
+            poll_ms,
+        }
+    }
+}
+
+impl<Down, T> ErrorType for RttRead<Down, T> {
+    type Error = Infallible;
+}
+
+impl<Down: RttDown, T: WireTimer> Read for RttRead<Down, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            let used = self.down.read(buf);
+            if used != 0 || buf.is_empty() {
+                return Ok(used);
+            }
+            self.timer.delay_ms(self.poll_ms).await;
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Implementation using tokio TCP sockets, useful for host-side simulators
+//!
+//! Each frame is prefixed with its length, as a 4-byte little-endian integer.
+//! This matches the framing used by [`HostClient::new_tcp()`][crate::host_client::HostClient::new_tcp].
+
+use core::{
+    convert::Infallible,
+    fmt::Arguments,
+    future::Future,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    sync::Mutex,
+};
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{
+        AsWireRxErrorKind, AsWireTxErrorKind, OffloadError, WireClock, WireOffload, WireRx,
+        WireRxErrorKind, WireSpawn, WireTimer, WireTx, WireTxErrorKind,
+    },
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+//////////////////////////////////////////////////////////////////////////////
+// DISPATCH IMPL
+//////////////////////////////////////////////////////////////////////////////
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::server::{Dispatch, Server};
+
+    pub use super::tokio_spawn as spawn_fn;
+
+    /// Type alias for `WireTx` impl
+    pub type WireTxImpl = super::TcpWireTx;
+    /// Type alias for `WireRx` impl
+    pub type WireRxImpl = super::TcpWireRx;
+    /// Type alias for `WireSpawn` impl
+    pub type WireSpawnImpl = super::TcpWireSpawn;
+    /// Type alias for the receive buffer
+    pub type WireRxBuf = Box<[u8]>;
+
+    /// Create a new server for a single connected socket
+    ///
+    /// `buf` is the size of the receive buffer, which limits the maximum frame size.
+    pub fn new_server<D>(
+        dispatch: D,
+        stream: TcpStream,
+        buf: usize,
+    ) -> Server<WireTxImpl, WireRxImpl, WireRxBuf, D>
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        let _ = stream.set_nodelay(true);
+        let (rx, tx) = stream.into_split();
+        let kkind = dispatch.min_key_len();
+        let buf = vec![0; buf];
+        Server::new(
+            super::TcpWireTx::new(tx),
+            super::TcpWireRx::new(rx),
+            buf.into_boxed_slice(),
+            dispatch,
+            kkind,
+        )
+    }
+
+    /// Accept connections on the given listener, serving them one at a time
+    ///
+    /// For each connection, a new dispatcher is created with `make_dispatch`, and
+    /// served until the connection is closed. Returns if accepting a connection fails.
+    pub async fn serve<D, F>(
+        listener: TcpListener,
+        buf: usize,
+        mut make_dispatch: F,
+    ) -> std::io::Result<()>
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+        F: FnMut() -> D,
+    {
+        loop {
+            let (stream, _addr) = listener.accept().await?;
+            let mut server = new_server(make_dispatch(), stream, buf);
+            // Any error is fatal for this connection, wait for the next one
+            let _ = server.run().await;
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] impl using a tokio TCP socket
+#[derive(Clone)]
+pub struct TcpWireTx {
+    tx: Arc<Mutex<OwnedWriteHalf>>,
+    log_ctr: Arc<AtomicU32>,
+}
+
+impl TcpWireTx {
+    /// Create a new [`TcpWireTx`]
+    pub fn new(tx: OwnedWriteHalf) -> Self {
+        Self {
+            tx: Arc::new(Mutex::new(tx)),
+            log_ctr: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    async fn inner_send(&self, msg: &[u8]) -> Result<(), TcpWireTxError> {
+        let len = u32::try_from(msg.len()).map_err(|_| TcpWireTxError::MessageTooLarge)?;
+        let mut tx = self.tx.lock().await;
+        tx.write_all(&len.to_le_bytes()).await?;
+        tx.write_all(msg).await?;
+        Ok(())
+    }
+
+    fn log_header(&self, kkind: VarKeyKind) -> VarHeader {
+        let ctr = self.log_ctr.fetch_add(1, Ordering::Relaxed);
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq4(ctr),
+        }
+    }
+}
+
+impl WireTx for TcpWireTx {
+    type Error = TcpWireTxError;
+
+    async fn send<T: serde::Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut buf = hdr.write_to_vec();
+        let bdy_ser = postcard::to_stdvec(msg).map_err(|_| TcpWireTxError::SerFailed)?;
+        buf.extend_from_slice(&bdy_ser);
+        self.inner_send(&buf).await
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner_send(buf).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let wh = self.log_header(kkind);
+        self.send::<<LoggingTopic as Topic>::Message>(wh, &s.to_string())
+            .await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let wh = self.log_header(kkind);
+        self.send::<<LoggingTopic as Topic>::Message>(wh, &format!("{a}"))
+            .await
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        self.tx.lock().await.flush().await?;
+        Ok(())
+    }
+}
+
+/// A wire tx error
+#[derive(Debug)]
+pub enum TcpWireTxError {
+    /// The socket returned an error, the connection is likely closed
+    Io(std::io::Error),
+    /// The message was too large to be sent
+    MessageTooLarge,
+    /// Serialization of the message failed
+    SerFailed,
+}
+
+impl From<std::io::Error> for TcpWireTxError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl AsWireTxErrorKind for TcpWireTxError {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            TcpWireTxError::Io(_) => WireTxErrorKind::ConnectionClosed,
+            TcpWireTxError::MessageTooLarge => WireTxErrorKind::Other,
+            TcpWireTxError::SerFailed => WireTxErrorKind::Other,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] impl using a tokio TCP socket
+pub struct TcpWireRx {
+    rx: OwnedReadHalf,
+}
+
+impl TcpWireRx {
+    /// Create a new [`TcpWireRx`]
+    pub fn new(rx: OwnedReadHalf) -> Self {
+        Self { rx }
+    }
+}
+
+impl WireRx for TcpWireRx {
+    type Error = TcpWireRxError;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let mut len = [0u8; 4];
+        self.rx.read_exact(&mut len).await?;
+        let len = u32::from_le_bytes(len) as usize;
+
+        if len > buf.len() {
+            // Read as much as fits, so we can tell the client which request was
+            // dropped, then discard the rest to stay in sync with the framing
+            self.rx.read_exact(buf).await?;
+            let mut frame = (&mut self.rx).take((len - buf.len()) as u64);
+            tokio::io::copy(&mut frame, &mut tokio::io::sink()).await?;
+            return Err(match VarHeader::take_from_slice(buf) {
+                Some((hdr, _)) => TcpWireRxError::BodyTooLarge(hdr),
+                None => TcpWireRxError::MessageTooLarge,
+            });
+        }
+        let out = &mut buf[..len];
+        self.rx.read_exact(out).await?;
+        Ok(out)
+    }
+}
+
+/// A wire rx error
+#[derive(Debug)]
+pub enum TcpWireRxError {
+    /// The socket returned an error, the connection is likely closed
+    Io(std::io::Error),
+    /// The client sent a too-large message
+    MessageTooLarge,
+    /// The client sent a too-large message, with the given header
+    BodyTooLarge(VarHeader),
+}
+
+impl From<std::io::Error> for TcpWireRxError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl AsWireRxErrorKind for TcpWireRxError {
+    fn as_kind(&self) -> WireRxErrorKind {
+        match self {
+            TcpWireRxError::Io(_) => WireRxErrorKind::ConnectionClosed,
+            TcpWireRxError::MessageTooLarge => WireRxErrorKind::ReceivedMessageTooLarge,
+            TcpWireRxError::BodyTooLarge(hdr) => WireRxErrorKind::ReceivedBodyTooLarge(*hdr),
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SPAWN
+//////////////////////////////////////////////////////////////////////////////
+
+/// A wire spawn implementation, using tokio
+#[derive(Clone)]
+pub struct TcpWireSpawn;
+
+impl WireSpawn for TcpWireSpawn {
+    type Error = Infallible;
+
+    type Info = ();
+
+    fn info(&self) -> &Self::Info {
+        &()
+    }
+}
+
+impl WireTimer for TcpWireSpawn {
+    async fn delay_ms(&self, ms: u32) {
+        tokio::time::sleep(core::time::Duration::from_millis(ms.into())).await;
+    }
+}
+
+impl WireOffload for TcpWireSpawn {
+    async fn offload<F, R>(&self, f: F) -> Result<R, OffloadError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|_| OffloadError::Failed)
+    }
+}
+
+impl WireClock for TcpWireSpawn {
+    fn now_ms() -> u64 {
+        static EPOCH: std::sync::OnceLock<tokio::time::Instant> = std::sync::OnceLock::new();
+        let epoch = *EPOCH.get_or_init(tokio::time::Instant::now);
+        let elapsed = tokio::time::Instant::now().saturating_duration_since(epoch);
+        u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
+/// Spawn a task using tokio
+pub fn tokio_spawn<Sp, F>(_sp: &Sp, fut: F) -> Result<(), Sp::Error>
+where
+    Sp: WireSpawn<Error = Infallible, Info = ()>,
+    F: Future<Output = ()> + 'static + Send,
+{
+    tokio::task::spawn(fut);
+    Ok(())
+}
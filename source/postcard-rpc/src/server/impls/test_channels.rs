@@ -11,8 +11,8 @@ use crate::{
     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
     host_client::util::Stopper,
     server::{
-        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireSpawn, WireTx,
-        WireTxErrorKind,
+        AsWireRxErrorKind, AsWireTxErrorKind, OffloadError, WireClock, WireOffload, WireRx,
+        WireRxErrorKind, WireSpawn, WireTimer, WireTx, WireTxErrorKind,
     },
     standard_icd::LoggingTopic,
     Topic,
@@ -28,9 +28,12 @@ use tokio::{select, sync::mpsc};
 pub mod dispatch_impl {
     pub use crate::host_client::util::Stopper;
     use crate::{
-        header::VarKeyKind,
+        header::{VarKeyKind, VarSeqKind},
+        host_client::{test_channels, HostClient},
         server::{Dispatch, Server},
+        standard_icd::WireError,
     };
+    use tokio::sync::mpsc;
 
     pub use super::tokio_spawn as spawn_fn;
 
@@ -73,16 +76,50 @@ pub mod dispatch_impl {
         )
     }
 
+    /// Create a connected [`HostClient`] and [`Server`] pair, for testing dispatch logic
+    ///
+    /// The server must be run (e.g. in a spawned task) for the client to receive
+    /// any responses. `buf` is the size of the server's receive buffer.
+    ///
+    /// ```rust,ignore
+    /// let (client, mut server) = loopback(dispatch, 1024, VarSeqKind::Seq2);
+    /// tokio::task::spawn(async move { server.run().await });
+    /// let resp = client.send_resp::<PingEndpoint>(&42).await.unwrap();
+    /// ```
+    pub fn loopback<D>(
+        dispatch: D,
+        buf: usize,
+        seq_kind: VarSeqKind,
+    ) -> (
+        HostClient<WireError>,
+        Server<WireTxImpl, WireRxImpl, WireRxBuf, D>,
+    )
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        let (client_tx, server_rx) = mpsc::channel(16);
+        let (server_tx, client_rx) = mpsc::channel(16);
+        let kkind = dispatch.min_key_len();
+        let server = new_server(
+            dispatch,
+            Settings {
+                tx: super::ChannelWireTx::new(server_tx),
+                rx: super::ChannelWireRx::new(server_rx),
+                buf,
+                kkind,
+            },
+        );
+        let client = test_channels::new_from_channels(client_tx, client_rx, seq_kind);
+        (client, server)
+    }
+
     /// Create a new server using the [`Settings`] and [`Dispatch`] implementation
     ///
     /// Also returns a [`Stopper`] that can be used to halt the server's operation
     pub fn new_server_stoppable<D>(
         dispatch: D,
         mut settings: Settings,
-    ) -> (
-        crate::server::Server<WireTxImpl, WireRxImpl, WireRxBuf, D>,
-        Stopper,
-    )
+    ) -> (Server<WireTxImpl, WireRxImpl, WireRxBuf, D>, Stopper)
     where
         D: Dispatch<Tx = WireTxImpl>,
     {
@@ -268,9 +305,12 @@ impl WireRx for ChannelWireRx {
             }
             msg = rx.recv() => {
                 let msg = msg.ok_or(ChannelWireRxError::ChannelClosed)?;
-                let out = buf
-                    .get_mut(..msg.len())
-                    .ok_or(ChannelWireRxError::MessageTooLarge)?;
+                let Some(out) = buf.get_mut(..msg.len()) else {
+                    return Err(match VarHeader::take_from_slice(&msg) {
+                        Some((hdr, _)) => ChannelWireRxError::BodyTooLarge(hdr),
+                        None => ChannelWireRxError::MessageTooLarge,
+                    });
+                };
                 out.copy_from_slice(&msg);
                 Ok(out)
             }
@@ -285,6 +325,8 @@ pub enum ChannelWireRxError {
     ChannelClosed,
     /// The sender sent a too-large message
     MessageTooLarge,
+    /// The sender sent a too-large message, with the given header
+    BodyTooLarge(VarHeader),
 }
 
 impl AsWireRxErrorKind for ChannelWireRxError {
@@ -292,6 +334,7 @@ impl AsWireRxErrorKind for ChannelWireRxError {
         match self {
             ChannelWireRxError::ChannelClosed => WireRxErrorKind::ConnectionClosed,
             ChannelWireRxError::MessageTooLarge => WireRxErrorKind::ReceivedMessageTooLarge,
+            ChannelWireRxError::BodyTooLarge(hdr) => WireRxErrorKind::ReceivedBodyTooLarge(*hdr),
         }
     }
 }
@@ -314,6 +357,33 @@ impl WireSpawn for ChannelWireSpawn {
     }
 }
 
+impl WireTimer for ChannelWireSpawn {
+    async fn delay_ms(&self, ms: u32) {
+        tokio::time::sleep(core::time::Duration::from_millis(ms.into())).await;
+    }
+}
+
+impl WireOffload for ChannelWireSpawn {
+    async fn offload<F, R>(&self, f: F) -> Result<R, OffloadError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|_| OffloadError::Failed)
+    }
+}
+
+impl WireClock for ChannelWireSpawn {
+    fn now_ms() -> u64 {
+        static EPOCH: std::sync::OnceLock<tokio::time::Instant> = std::sync::OnceLock::new();
+        let epoch = *EPOCH.get_or_init(tokio::time::Instant::now);
+        let elapsed = tokio::time::Instant::now().saturating_duration_since(epoch);
+        u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
 /// Spawn a task using tokio
 pub fn tokio_spawn<Sp, F>(_sp: &Sp, fut: F) -> Result<(), Sp::Error>
 where
@@ -0,0 +1,308 @@
+//! Implementation using UDP datagrams
+//!
+//! Each frame is sent as a single datagram, without any further framing, so
+//! frames must fit in a datagram of the network stack. This works with any UDP
+//! stack, such as `embassy-net`: the application implements [`DatagramSocket`]
+//! for its socket.
+//!
+//! Several hosts may talk to the same server. Every received frame notes the
+//! address of its sender, and replies are sent to the host that most recently
+//! sent a request with the same sequence number. Frames without a matching request,
+//! such as topic messages and logs, are sent to the host that most recently sent
+//! any frame. As replies are matched by sequence number rather than by order,
+//! datagrams may be reordered on the way. Hosts sharing a server should use 4-byte
+//! sequence numbers, so their requests are unlikely to use the same ones.
+//!
+//! For example, with `embassy-net`:
+//!
+//! ```rust,ignore
+//! use embassy_net::{udp::{RecvError, UdpSocket}, IpEndpoint};
+//! use postcard_rpc::server::{
+//!     impls::udp::{
+//!         dispatch_impl::{WireRxImpl, WireTxImpl, WireSpawnImpl, spawn_fn},
+//!         DatagramSocket, WireStorage,
+//!     },
+//!     WireRxErrorKind, WireTxErrorKind,
+//! };
+//!
+//! struct Socket(UdpSocket<'static>);
+//!
+//! impl DatagramSocket for Socket {
+//!     type Endpoint = IpEndpoint;
+//!
+//!     async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), WireRxErrorKind> {
+//!         match self.0.recv_from(buf).await {
+//!             Ok((len, meta)) => Ok((len, meta.endpoint)),
+//!             Err(RecvError::Truncated) => Err(WireRxErrorKind::ReceivedMessageTooLarge),
+//!         }
+//!     }
+//!
+//!     async fn send_to(&self, frame: &[u8], remote: IpEndpoint) -> Result<(), WireTxErrorKind> {
+//!         self.0.send_to(frame, remote).await.map_err(|_| WireTxErrorKind::Other)
+//!     }
+//! }
+//!
+//! static STORAGE: WireStorage<CriticalSectionRawMutex, Socket, 512> = WireStorage::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     tx_impl: WireTxImpl<CriticalSectionRawMutex, Socket>;
+//!     spawn_impl: WireSpawnImpl;
+//!     // ...
+//! }
+//!
+//! let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+//! socket.bind(4000).unwrap();
+//! let (rx, tx) = STORAGE.init(Socket(socket)).unwrap();
+//! let server = Server::new(tx, rx, buf_512, dispatcher, kkind);
+//! ```
+
+use core::{cell::RefCell, fmt::Arguments, ops::DerefMut};
+
+use embassy_sync_0_7::{
+    blocking_mutex::{raw::RawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::Serialize;
+use static_cell::{ConstStaticCell, StaticCell};
+
+use crate::{
+    header::{VarHeader, VarKeyKind, VarSeq},
+    server::{frame::SenderCore, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
+};
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    pub use crate::server::impls::embassy_shared::embassy_spawn as spawn_fn;
+
+    /// Type alias for `WireTx` impl
+    pub type WireTxImpl<M, S> = super::UdpWireTx<M, S>;
+    /// Type alias for `WireRx` impl
+    pub type WireRxImpl<M, S> = super::UdpWireRx<M, S>;
+    /// Type alias for `WireSpawn` impl
+    pub type WireSpawnImpl = crate::server::impls::embassy_shared::EmbassyWireSpawn;
+    /// Type alias for the receive buffer
+    pub type WireRxBuf = &'static mut [u8];
+}
+
+pub use super::embassy_shared::embassy_spawn;
+pub use super::embassy_shared::EmbassyWireSpawn as UdpWireSpawn;
+
+/// The number of recent requests whose sender is remembered for replies
+pub const RECENT_PEERS: usize = 8;
+
+/// A UDP socket, implemented by the application
+///
+/// Both methods take `&self`, as the socket is used to receive and send at the
+/// same time.
+pub trait DatagramSocket {
+    /// The address of a remote host
+    type Endpoint: Copy;
+
+    /// Receive a single datagram, returning its length and sender
+    ///
+    /// Datagrams that don't fit in `buf` should be reported as
+    /// [`WireRxErrorKind::ReceivedMessageTooLarge`].
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Self::Endpoint), WireRxErrorKind>;
+
+    /// Send a single datagram to `remote`
+    async fn send_to(&self, frame: &[u8], remote: Self::Endpoint) -> Result<(), WireTxErrorKind>;
+}
+
+/// A handy type for storing buffers and the TX/RX impls
+pub struct WireStorage<M: RawMutex + 'static, S: DatagramSocket + 'static, const TXB: usize> {
+    buf: ConstStaticCell<[u8; TXB]>,
+    shared: StaticCell<UdpShared<M, S>>,
+    tx: StaticCell<Mutex<M, UdpWireTxInner>>,
+}
+
+/// The WireTX impl for UDP
+pub struct UdpWireTx<M: RawMutex + 'static, S: DatagramSocket + 'static> {
+    shared: &'static UdpShared<M, S>,
+    t: &'static Mutex<M, UdpWireTxInner>,
+}
+
+/// The WireRX impl for UDP
+pub struct UdpWireRx<M: RawMutex + 'static, S: DatagramSocket + 'static> {
+    shared: &'static UdpShared<M, S>,
+}
+
+struct UdpShared<M: RawMutex, S: DatagramSocket> {
+    socket: S,
+    peers: BlockingMutex<M, RefCell<Peers<S::Endpoint>>>,
+}
+
+struct UdpWireTxInner {
+    tx_buf: &'static mut [u8],
+    core: SenderCore,
+}
+
+/// The senders of the most recent requests
+struct Peers<E> {
+    recent: [Option<(VarSeq, E)>; RECENT_PEERS],
+    next: usize,
+    last: Option<E>,
+}
+
+// ----- IMPLS -----
+
+// impl Peers
+
+impl<E: Copy> Peers<E> {
+    const fn new() -> Self {
+        Self {
+            recent: [None; RECENT_PEERS],
+            next: 0,
+            last: None,
+        }
+    }
+
+    fn record(&mut self, seq_no: VarSeq, peer: E) {
+        self.recent[self.next] = Some((seq_no, peer));
+        self.next = (self.next + 1) % RECENT_PEERS;
+        self.last = Some(peer);
+    }
+
+    /// The sender of the most recent request with `seq_no`, or else the most
+    /// recent sender
+    fn lookup(&self, seq_no: VarSeq) -> Option<E> {
+        (1..=RECENT_PEERS)
+            .map(|i| (self.next + RECENT_PEERS - i) % RECENT_PEERS)
+            .filter_map(|idx| self.recent[idx])
+            .find(|(seq, _)| *seq == seq_no)
+            .map(|(_, peer)| peer)
+            .or(self.last)
+    }
+}
+
+// impl UdpShared
+
+impl<M: RawMutex, S: DatagramSocket> UdpShared<M, S> {
+    fn peer(&self, seq_no: Option<VarSeq>) -> Option<S::Endpoint> {
+        self.peers.lock(|p| {
+            let p = p.borrow();
+            match seq_no {
+                Some(seq_no) => p.lookup(seq_no),
+                None => p.last,
+            }
+        })
+    }
+
+    /// Send `frame` to the peer for `seq_no`. Frames are dropped if no host has
+    /// sent anything yet, as there is nobody to send them to.
+    async fn send_to_peer(
+        &self,
+        seq_no: Option<VarSeq>,
+        frame: &[u8],
+    ) -> Result<(), WireTxErrorKind> {
+        match self.peer(seq_no) {
+            Some(peer) => self.socket.send_to(frame, peer).await,
+            None => Ok(()),
+        }
+    }
+}
+
+// impl WireStorage
+
+impl<M: RawMutex + 'static, S: DatagramSocket + 'static, const TXB: usize> WireStorage<M, S, TXB> {
+    /// Create a new wire storage
+    pub const fn new() -> Self {
+        Self {
+            buf: ConstStaticCell::new([0u8; TXB]),
+            shared: StaticCell::new(),
+            tx: StaticCell::new(),
+        }
+    }
+
+    /// Create a new Wire pair using this storage and `socket`
+    pub fn init(&'static self, socket: S) -> Option<(UdpWireRx<M, S>, UdpWireTx<M, S>)> {
+        let tx_buf = self.buf.try_take()?;
+        let shared = self.shared.try_init(UdpShared {
+            socket,
+            peers: BlockingMutex::new(RefCell::new(Peers::new())),
+        })?;
+        let t = self.tx.try_init(Mutex::new(UdpWireTxInner {
+            tx_buf,
+            core: SenderCore::new(),
+        }))?;
+        Some((UdpWireRx { shared }, UdpWireTx { shared, t }))
+    }
+}
+
+impl<M: RawMutex + 'static, S: DatagramSocket + 'static, const TXB: usize> Default
+    for WireStorage<M, S, TXB>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// impl UdpWireTx
+
+impl<M: RawMutex + 'static, S: DatagramSocket + 'static> Clone for UdpWireTx<M, S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared,
+            t: self.t,
+        }
+    }
+}
+
+impl<M: RawMutex + 'static, S: DatagramSocket + 'static> WireTx for UdpWireTx<M, S> {
+    type Error = WireTxErrorKind;
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let UdpWireTxInner { tx_buf, core } = guard.deref_mut();
+        let frame = core.frame(tx_buf, hdr, msg)?;
+        self.shared.send_to_peer(Some(hdr.seq_no), frame).await
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let seq_no = VarHeader::take_from_slice(buf).map(|(hdr, _)| hdr.seq_no);
+        self.shared.send_to_peer(seq_no, buf).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let UdpWireTxInner { tx_buf, core } = guard.deref_mut();
+        let frame = core.log_str(tx_buf, kkind, s)?;
+        self.shared.send_to_peer(None, frame).await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let mut guard = self.t.lock().await;
+        let UdpWireTxInner { tx_buf, core } = guard.deref_mut();
+        let frame = core.log_fmt(tx_buf, kkind, args)?;
+        self.shared.send_to_peer(None, frame).await
+    }
+}
+
+// impl UdpWireRx
+
+impl<M: RawMutex + 'static, S: DatagramSocket + 'static> WireRx for UdpWireRx<M, S> {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let (len, peer) = self.shared.socket.recv_from(buf).await?;
+        let frame = buf
+            .get_mut(..len)
+            .ok_or(WireRxErrorKind::ReceivedMessageTooLarge)?;
+        // Frames without a valid header are still passed on, the server ignores them
+        if let Some((hdr, _)) = VarHeader::take_from_slice(frame) {
+            self.shared
+                .peers
+                .lock(|p| p.borrow_mut().record(hdr.seq_no, peer));
+        }
+        Ok(frame)
+    }
+}
@@ -0,0 +1,211 @@
+//! Sending `log` records to the host on the [`LogTopic`]
+//!
+//! A [`TopicLogger`] is a [`log::Log`] impl, which queues every record as a
+//! [`LogRecord`], and a task publishing the queued records with a [`Sender`].
+//! The records share the transport with RPC traffic, and are received on the
+//! host with `HostClient::log_stream()`:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::log_topic::TopicLogger;
+//!
+//! // Up to 8 records of up to 128 bytes each are queued
+//! static LOGGER: TopicLogger<CriticalSectionRawMutex, 128, 8> = TopicLogger::new();
+//!
+//! #[embassy_executor::task]
+//! async fn log_task(sender: Sender<AppTx>) {
+//!     LOGGER.run(&sender).await;
+//! }
+//!
+//! // In main
+//! log::set_logger(&LOGGER).unwrap();
+//! log::set_max_level(log::LevelFilter::Info);
+//! spawner.must_spawn(log_task(server.sender()));
+//! ```
+//!
+//! Logging never waits: records are formatted into the queue, and dropped if it
+//! is full, see [`TopicLogger::dropped()`]. Messages are truncated to fit in `N`
+//! bytes, together with the target and level. The [`LogTopic`] is a standard
+//! topic, so records are sent even if the server has
+//! [`Subscriptions`][crate::server::Subscriptions] the host didn't subscribe to it in.
+//!
+//! `defmt` frames are not supported, as they can only be decoded with the ELF
+//! file of the firmware.
+//!
+//! **Requires feature**: `log-topic`
+
+use core::fmt::{self, Write};
+
+use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, channel::Channel};
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq},
+    server::{Sender, WireTx},
+    standard_icd::{LogLevel, LogRecord, LogTopic},
+    Topic,
+};
+
+/// The most bytes the level and the lengths of the target and message take
+const RECORD_OVERHEAD: usize = 1 + 5 + 5;
+
+/// A [`log::Log`] impl sending records on the [`LogTopic`]
+///
+/// Queues up to `DEPTH` records of up to `N` bytes each, until they are sent by
+/// [`TopicLogger::run()`]. See the [module docs](self).
+pub struct TopicLogger<M: RawMutex, const N: usize, const DEPTH: usize> {
+    queue: Channel<M, heapless::Vec<u8, N>, DEPTH>,
+    dropped: AtomicU32,
+}
+
+impl<M: RawMutex, const N: usize, const DEPTH: usize> TopicLogger<M, N, DEPTH> {
+    /// Create a new logger, with an empty queue
+    pub const fn new() -> Self {
+        Self {
+            queue: Channel::new(),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// The number of records dropped so far, as the queue was full or their
+    /// target didn't fit in `N` bytes
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue a record, dropping it if the queue is full
+    ///
+    /// This is what the [`log::Log`] impl does, and can also be used without
+    /// setting this logger as the global logger.
+    pub fn log_record(&self, level: LogLevel, target: &str, args: fmt::Arguments<'_>) {
+        let mut message = [0u8; N];
+        let budget = N.saturating_sub(RECORD_OVERHEAD + target.len());
+        let mut writer = Truncating {
+            buf: &mut message[..budget],
+            used: 0,
+        };
+        // Truncating never fails, other errors of the arguments can be ignored
+        _ = writer.write_fmt(args);
+        let used = writer.used;
+        // Only whole chars are written, so this is always valid
+        let message = core::str::from_utf8(&message[..used]).unwrap_or_default();
+
+        let record = LogRecord {
+            level,
+            target,
+            message,
+        };
+        let mut frame = heapless::Vec::new();
+        // NOTE: can't fail, the frame has a capacity of exactly N
+        let _ = frame.resize_default(N);
+        let Ok(used) = postcard::to_slice(&record, &mut frame).map(|used| used.len()) else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        frame.truncate(used);
+        if self.queue.try_send(frame).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Publish queued records with `sender`, forever
+    ///
+    /// Records that can't be sent, e.g. while the host is disconnected, are
+    /// dropped.
+    pub async fn run<Tx: WireTx>(&self, sender: &Sender<Tx>) {
+        let mut seq_no = 0u32;
+        loop {
+            let frame = self.queue.receive().await;
+            let Ok(record) = postcard::from_bytes::<LogRecord<'_>>(&frame) else {
+                continue;
+            };
+            let mut key = VarKey::Key8(LogTopic::TOPIC_KEY);
+            key.shrink_to(sender.kkind);
+            let hdr = VarHeader {
+                key,
+                seq_no: VarSeq::Seq4(seq_no),
+            };
+            seq_no = seq_no.wrapping_add(1);
+            if sender.send(hdr, &record).await.is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize, const DEPTH: usize> Default for TopicLogger<M, N, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, const N: usize, const DEPTH: usize> log::Log for TopicLogger<M, N, DEPTH>
+where
+    M: RawMutex + Send + Sync,
+{
+    fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+        // Filtered with `log::set_max_level()`
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        let level = match record.level() {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        };
+        self.log_record(level, record.target(), *record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// A [`fmt::Write`] impl writing as many whole chars as fit into `buf`
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    used: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remain = self.buf.len() - self.used;
+        let mut len = s.len().min(remain);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.used..][..len].copy_from_slice(&s.as_bytes()[..len]);
+        self.used += len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_sync_0_7::blocking_mutex::raw::NoopRawMutex;
+
+    use super::TopicLogger;
+    use crate::standard_icd::{LogLevel, LogRecord};
+
+    #[test]
+    fn truncates_and_drops() {
+        let logger = TopicLogger::<NoopRawMutex, 32, 2>::new();
+        logger.log_record(LogLevel::Warn, "app", format_args!("héllo {}", 42));
+        logger.log_record(LogLevel::Info, "app", format_args!("{:é<40}", ""));
+        // The queue is full
+        logger.log_record(LogLevel::Info, "app", format_args!("lost"));
+        assert_eq!(logger.dropped(), 1);
+
+        let frame = logger.queue.try_receive().unwrap();
+        let record = postcard::from_bytes::<LogRecord<'_>>(&frame).unwrap();
+        assert_eq!(record.level, LogLevel::Warn);
+        assert_eq!(record.target, "app");
+        assert_eq!(record.message, "héllo 42");
+
+        // Truncated at a char boundary, to fit in 32 bytes
+        let frame = logger.queue.try_receive().unwrap();
+        let record = postcard::from_bytes::<LogRecord<'_>>(&frame).unwrap();
+        assert_eq!(record.message, "é".repeat(9));
+        assert!(frame.len() <= 32);
+    }
+}
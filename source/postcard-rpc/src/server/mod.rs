@@ -22,20 +22,46 @@
 
 #![allow(async_fn_in_trait)]
 
+#[cfg(feature = "device-requests")]
+pub mod device_request;
 #[doc(hidden)]
 pub mod dispatch_macro;
 
+#[cfg(feature = "dyn-dispatch")]
+pub mod dyn_dispatch;
+pub mod frame;
 pub mod impls;
-
-use core::{fmt::Arguments, ops::DerefMut};
+#[cfg(feature = "log-topic")]
+pub mod log_topic;
+#[cfg(feature = "multi-transport")]
+pub mod multi;
+pub mod mutex;
+#[cfg(feature = "offload")]
+pub mod offload;
+#[cfg(feature = "worker-pool")]
+pub mod pool;
+pub mod sans_io;
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Arguments,
+    future::{poll_fn, Future},
+    marker::PhantomData,
+    ops::{ControlFlow, DerefMut},
+    pin::pin,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Poll,
+};
 
 use postcard_schema::Schema;
 use serde::Serialize;
 
 use crate::{
     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
-    DeviceMap, Key, TopicDirection,
+    standard_icd::{EndpointStats, WireError},
+    DeviceMap, FrameDirection, Key, TopicDirection,
 };
+use sans_io::RxEvent;
 
 //////////////////////////////////////////////////////////////////////////////
 // TX
@@ -74,6 +100,15 @@ pub trait WireTx {
         kkind: VarKeyKind,
         a: Arguments<'a>,
     ) -> Result<(), Self::Error>;
+
+    /// Wait until all frames sent so far have been transmitted
+    ///
+    /// Should be implemented by impls that buffer outgoing frames, or may return
+    /// from [`WireTx::send()`] before the frame is fully written, e.g. after a
+    /// timeout. The default returns immediately.
+    async fn flush(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// The base [`WireTx`] Error Kind
@@ -136,6 +171,23 @@ pub enum WireRxErrorKind {
     ConnectionClosed,
     /// The received message was too large for the server to handle
     ReceivedMessageTooLarge,
+    /// The received message was too large for the server to handle, but its header
+    /// could be read. The server replies with [`WireError::BodyTooLarge`].
+    ReceivedBodyTooLarge(VarHeader),
+    /// The received message had an invalid authentication tag, but its header could
+    /// be read. The server replies with [`WireError::AuthFailed`].
+    AuthFailed(VarHeader),
+    /// Fragments of the received message were lost, but its header could be read.
+    /// The server replies with [`WireError::ReassemblyFailed`].
+    ReassemblyFailed(VarHeader),
+    /// The received message had an invalid checksum, but its header could be read.
+    /// The server replies with [`WireError::ChecksumFailed`].
+    ChecksumFailed(VarHeader),
+    /// The connection was interrupted while receiving the message, e.g. by a reset,
+    /// but its header could be read. The server replies with
+    /// [`WireError::TruncatedFrame`]. The rest of the message is discarded, so the
+    /// next message is received from its start.
+    TruncatedFrame(VarHeader),
     /// Other message kinds
     Other,
 }
@@ -176,18 +228,234 @@ pub trait WireSpawn: Clone {
     fn info(&self) -> &Self::Info;
 }
 
+/// This trait defines how the server waits for a given amount of time
+///
+/// This is required when using the `timeout_ms` option of
+/// [`define_dispatch!`][crate::define_dispatch], and is typically implemented
+/// by the same type as [`WireSpawn`]. All spawn types of this crate implement it:
+/// the `EmbassyWireSpawn` of the embassy-usb, embedded-io-async, GATT, and UDP
+/// servers with `embassy-time`, and the spawn types of the TCP server and the test
+/// channels with tokio.
+pub trait WireTimer {
+    /// Wait for the given number of milliseconds
+    async fn delay_ms(&self, ms: u32);
+}
+
+/// This trait defines how the server reads the current time
+///
+/// This is required when using the `async_deadline` handler kind of
+/// [`define_dispatch!`][crate::define_dispatch], and is typically implemented
+/// by the same type as [`WireSpawn`].
+pub trait WireClock {
+    /// The number of milliseconds since an arbitrary, fixed point in time
+    fn now_ms() -> u64;
+}
+
+/// This trait defines how the server runs a blocking handler away from the
+/// dispatch loop
+///
+/// This is required when using the `offload` handler kind of
+/// [`define_dispatch!`][crate::define_dispatch], and is typically implemented
+/// by the same type as [`WireSpawn`]. With tokio, jobs are run with
+/// `spawn_blocking`, and with embassy, by an `Offloader` running on a lower
+/// priority executor, see the `offload` module.
+pub trait WireOffload {
+    /// Run `f` elsewhere, and wait for its result
+    async fn offload<F, R>(&self, f: F) -> Result<R, OffloadError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static;
+}
+
+/// An error returned by [`WireOffload::offload()`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OffloadError {
+    /// The job could not be started right now, e.g. because another job is
+    /// still running
+    Busy,
+    /// The job did not run to completion, e.g. because it panicked
+    Failed,
+}
+
+impl From<OffloadError> for WireError {
+    fn from(value: OffloadError) -> Self {
+        match value {
+            OffloadError::Busy => WireError::Busy,
+            OffloadError::Failed => WireError::FailedToSpawn,
+        }
+    }
+}
+
+/// The time by which the client expects a reply to a request
+///
+/// Clients may send a deadline with a request, see
+/// [`HostClient::set_deadline_propagation()`](crate::host_client::HostClient::set_deadline_propagation).
+/// It is passed to `async_deadline` handlers of [`define_dispatch!`][crate::define_dispatch],
+/// which may check it while working, to stop early once the client gave up waiting.
+/// Requests without a deadline never expire.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    expires_at_ms: Option<u64>,
+    now_ms: fn() -> u64,
+}
+
+impl Deadline {
+    /// A deadline expiring `deadline_ms` milliseconds from now, or never if `None`
+    pub fn start<C: WireClock>(_clock: &C, deadline_ms: Option<u32>) -> Self {
+        Self {
+            expires_at_ms: deadline_ms.map(|ms| C::now_ms().saturating_add(ms.into())),
+            now_ms: C::now_ms,
+        }
+    }
+
+    /// Has the deadline expired?
+    pub fn expired(&self) -> bool {
+        self.remaining_ms() == Some(0)
+    }
+
+    /// The number of milliseconds left until the deadline expires, or `None` if
+    /// the request has no deadline
+    pub fn remaining_ms(&self) -> Option<u64> {
+        self.expires_at_ms
+            .map(|at| at.saturating_sub((self.now_ms)()))
+    }
+
+    /// Return a [`WireError::DeadlineExceeded`] if the deadline has expired
+    ///
+    /// This allows handlers to bail early with `deadline.check()?`.
+    pub fn check(&self) -> Result<(), WireError> {
+        if self.expired() {
+            Err(WireError::DeadlineExceeded)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The output of [`with_timeout()`] when the timeout expired first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedOut;
+
+/// Run the given future to completion, unless the `timeout` future completes first
+///
+/// If the timeout completes first, `fut` is dropped without being polled again.
+pub async fn with_timeout<F, T>(fut: F, timeout: T) -> Result<F::Output, TimedOut>
+where
+    F: Future,
+    T: Future<Output = ()>,
+{
+    let mut fut = pin!(fut);
+    let mut timeout = pin!(timeout);
+    poll_fn(|cx| {
+        if let Poll::Ready(out) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Ok(out));
+        }
+        if timeout.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(TimedOut));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// INTERCEPTORS
+//////////////////////////////////////////////////////////////////////////////
+
+/// A hook that runs around every handled message, e.g. for logging, rate limiting,
+/// or authorization
+///
+/// Interceptors are registered with the `interceptors` option of
+/// [`define_dispatch!`][crate::define_dispatch].
+pub trait Interceptor {
+    /// Called for every received message, before it is handled
+    ///
+    /// Returning [`ControlFlow::Break`] skips the handler, and sends the given
+    /// error to the client instead.
+    async fn before(&mut self, hdr: &VarHeader, body: &[u8]) -> ControlFlow<WireError>;
+
+    /// Called after the message has been handled
+    ///
+    /// This is only called if [`Interceptor::before()`] returned [`ControlFlow::Continue`]
+    /// for this interceptor. For `spawn` and `cancellable` handlers, this is called
+    /// once the handler has been spawned, not when it completes.
+    async fn after(&mut self, hdr: &VarHeader) {
+        let _ = hdr;
+    }
+}
+
+/// The empty chain of interceptors, which handles all messages
+impl Interceptor for () {
+    async fn before(&mut self, _hdr: &VarHeader, _body: &[u8]) -> ControlFlow<WireError> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// A chain of interceptors, where `H` runs around `T`
+///
+/// [`define_dispatch!`][crate::define_dispatch] nests the registered interceptors
+/// as `(A, (B, (C, ())))`, so `before` runs in registration order, and `after`
+/// in reverse.
+impl<H: Interceptor, T: Interceptor> Interceptor for (H, T) {
+    async fn before(&mut self, hdr: &VarHeader, body: &[u8]) -> ControlFlow<WireError> {
+        self.0.before(hdr, body).await?;
+        let res = self.1.before(hdr, body).await;
+        if res.is_break() {
+            self.0.after(hdr).await;
+        }
+        res
+    }
+
+    async fn after(&mut self, hdr: &VarHeader) {
+        self.1.after(hdr).await;
+        self.0.after(hdr).await;
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // SENDER (wrapper of WireTx)
 //////////////////////////////////////////////////////////////////////////////
 
 /// The [`Sender`] type wraps a [`WireTx`] impl, and provides higher level functionality
 /// over it
-#[derive(Clone)]
 pub struct Sender<Tx: WireTx> {
     tx: Tx,
     kkind: VarKeyKind,
+    tap: Option<WireTap>,
+    permit: Option<SpawnPermit>,
+    reply_cache: Option<&'static ReplyCache>,
+    resp_alias: Option<(Key, Key)>,
+    subscriptions: Option<&'static Subscriptions>,
+}
+
+impl<Tx: WireTx + Clone> Clone for Sender<Tx> {
+    /// Clones the [`Sender`], without any [`SpawnPermit`] or [`ReplyCache`] it holds
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            kkind: self.kkind,
+            tap: self.tap,
+            permit: None,
+            reply_cache: None,
+            resp_alias: self.resp_alias,
+            subscriptions: self.subscriptions,
+        }
+    }
 }
 
+/// A function observing every frame sent or received by a [`Server`]
+///
+/// See [`Sender::set_wire_tap()`].
+pub type WireTap = fn(FrameDirection, &[u8]);
+
+/// The largest outgoing frame that can be passed to a [`WireTap`] without the
+/// `use-std` feature
+///
+/// Outgoing frames are serialized into a buffer of this size on the stack before
+/// being passed to the tap, larger frames are sent but not observed.
+pub const WIRE_TAP_MAX_FRAME: usize = 256;
+
 impl<Tx: WireTx> Sender<Tx> {
     /// Create a new Sender
     ///
@@ -196,7 +464,102 @@ impl<Tx: WireTx> Sender<Tx> {
     ///
     /// `kkind` should usually come from [`Dispatch::min_key_len()`].
     pub fn new(tx: Tx, kkind: VarKeyKind) -> Self {
-        Self { tx, kkind }
+        Self {
+            tx,
+            kkind,
+            tap: None,
+            permit: None,
+            reply_cache: None,
+            resp_alias: None,
+            subscriptions: None,
+        }
+    }
+
+    /// Hold `permit` until this [`Sender`] is dropped
+    ///
+    /// Used by `define_dispatch!` to count spawned handlers against their
+    /// [`SpawnLimit`], as each spawned handler owns its [`Sender`].
+    #[doc(hidden)]
+    pub fn with_permit(mut self, permit: SpawnPermit) -> Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    /// Store replies sent with [`Sender::reply()`] in `cache`
+    ///
+    /// Used by `define_dispatch!` for idempotent endpoints.
+    #[doc(hidden)]
+    pub fn with_reply_cache(mut self, cache: &'static ReplyCache) -> Self {
+        self.reply_cache = Some(cache);
+        self
+    }
+
+    /// Send replies with the response key `resp_key` as `alias` instead
+    ///
+    /// Used by `define_dispatch!` for requests sent to an endpoint alias, so
+    /// the reply has the key the client expects.
+    #[doc(hidden)]
+    pub fn with_resp_alias(mut self, resp_key: Key, alias: Key) -> Self {
+        self.resp_alias = Some((resp_key, alias));
+        self
+    }
+
+    /// The key of replies to `E`, taking an endpoint alias into account
+    #[inline]
+    fn resp_key<E: crate::Endpoint>(&self) -> VarKey {
+        let key = match self.resp_alias {
+            Some((resp_key, alias)) if resp_key == E::RESP_KEY => alias,
+            _ => E::RESP_KEY,
+        };
+        let mut key = VarKey::Key8(key);
+        key.shrink_to(self.kkind);
+        key
+    }
+
+    /// Observe every frame sent with this [`Sender`]
+    ///
+    /// `tap` is called with each full frame (header and body) just before it is
+    /// sent. When set on the [`Sender`] owned by a [`Server`] (see
+    /// [`Server::set_wire_tap()`]), it is also called with each frame just after
+    /// it is received.
+    ///
+    /// Frames are serialized an extra time for the tap, so this is intended for
+    /// debugging. Messages sent with [`Sender::log_str()`] and [`Sender::log_fmt()`]
+    /// are not observed.
+    pub fn set_wire_tap(&mut self, tap: Option<WireTap>) {
+        self.tap = tap;
+    }
+
+    /// Only publish topics the client is subscribed to in `subscriptions`
+    ///
+    /// See [`Subscriptions`]. With `None`, the default, all topics are published.
+    pub fn set_subscriptions(&mut self, subscriptions: Option<&'static Subscriptions>) {
+        self.subscriptions = subscriptions;
+    }
+
+    /// Whether the client is subscribed to the topic `T`
+    ///
+    /// This is always `true` unless [`Sender::set_subscriptions()`] was called.
+    #[inline]
+    pub fn has_subscribers<T>(&self) -> bool
+    where
+        T: ?Sized,
+        T: crate::Topic,
+    {
+        self.subscriptions
+            .is_none_or(|subs| subs.is_subscribed(T::TOPIC_KEY))
+    }
+
+    /// Send a frame, passing it to the wire tap first
+    #[inline]
+    async fn send<T>(&self, hdr: VarHeader, msg: &T) -> Result<(), Tx::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        if let Some(tap) = self.tap {
+            tap_outgoing(tap, &hdr, msg);
+        }
+        self.tx.send::<T>(hdr, msg).await
     }
 
     /// Send a reply for the given endpoint
@@ -206,10 +569,88 @@ impl<Tx: WireTx> Sender<Tx> {
         E: crate::Endpoint,
         E::Response: Serialize + Schema,
     {
-        let mut key = VarKey::Key8(E::RESP_KEY);
-        key.shrink_to(self.kkind);
+        let key = self.resp_key::<E>();
+        let wh = VarHeader { key, seq_no };
+        self.send::<E::Response>(wh, resp).await?;
+        if let Some(cache) = self.reply_cache {
+            cache.store(E::REQ_KEY, wh, resp);
+        }
+        Ok(())
+    }
+
+    /// Send a reply for the given endpoint, serializing it into `buf` first
+    ///
+    /// Unlike [`Sender::reply()`], the frame is serialized before the [`WireTx`] is
+    /// used, and only the finished frame is passed to [`WireTx::send_raw()`]. Impls
+    /// sharing one buffer behind a mutex, such as the embassy-usb impls, then only
+    /// hold the lock while writing the frame, rather than also while serializing it,
+    /// which reduces contention when many handlers reply at once.
+    ///
+    /// Replies that don't fit in `buf` are sent with [`Sender::reply()`] instead.
+    pub async fn reply_from_buf<E>(
+        &self,
+        seq_no: VarSeq,
+        resp: &E::Response,
+        buf: &mut [u8],
+    ) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+        E::Response: Serialize + Schema,
+    {
+        let key = self.resp_key::<E>();
+        let wh = VarHeader { key, seq_no };
+        let Ok(frame) = frame::serialize_frame(buf, wh, resp) else {
+            return self.reply::<E>(seq_no, resp).await;
+        };
+        if let Some(tap) = self.tap {
+            tap(FrameDirection::Outgoing, frame);
+        }
+        self.tx.send_raw(frame).await?;
+        if let Some(cache) = self.reply_cache {
+            cache.store(E::REQ_KEY, wh, resp);
+        }
+        Ok(())
+    }
+
+    /// Send a reply with [`Sender::reply_from_buf()`], using a buffer of `N` bytes
+    /// on the stack, or with [`Sender::reply()`] if `N` is zero
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch], see its `reply_buf` option.
+    #[doc(hidden)]
+    pub async fn reply_buffered<E, const N: usize>(
+        &self,
+        seq_no: VarSeq,
+        resp: &E::Response,
+    ) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+        E::Response: Serialize + Schema,
+    {
+        if N == 0 {
+            return self.reply::<E>(seq_no, resp).await;
+        }
+        let mut buf = [0u8; N];
+        self.reply_from_buf::<E>(seq_no, resp, &mut buf).await
+    }
+
+    /// Send a reply for the given endpoint, with a body that is already serialized
+    ///
+    /// The body is written to the frame as-is, see [`RawBody`](frame::RawBody). It is
+    /// not checked, and must be a valid postcard encoding of `E::Response`, e.g. a
+    /// constant table serialized ahead of time.
+    #[inline]
+    pub async fn reply_raw<E>(&self, seq_no: VarSeq, body: &[u8]) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+    {
+        let key = self.resp_key::<E>();
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<E::Response>(wh, resp).await
+        let body = frame::RawBody(body);
+        self.send(wh, &body).await?;
+        if let Some(cache) = self.reply_cache {
+            cache.store(E::REQ_KEY, wh, &body);
+        }
+        Ok(())
     }
 
     /// Send a reply with the given Key
@@ -225,12 +666,56 @@ impl<Tx: WireTx> Sender<Tx> {
         let mut key = VarKey::Key8(key);
         key.shrink_to(self.kkind);
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<T>(wh, resp).await
+        self.send::<T>(wh, resp).await
+    }
+
+    /// Send a single chunk of a streaming reply for the given endpoint
+    ///
+    /// This may be called any number of times with the same `seq_no`, followed
+    /// by a call to [`Sender::end_stream()`]. The `stream` flavor of
+    /// [`define_dispatch!`][crate::define_dispatch] ends the stream automatically
+    /// once the handler returns.
+    #[inline]
+    pub async fn reply_chunk<E>(&self, seq_no: VarSeq, resp: &E::Response) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+        E::Response: Serialize + Schema,
+    {
+        self.reply::<E>(seq_no, resp).await
+    }
+
+    /// Signal that no more chunks will be sent for the streaming reply with the
+    /// given `seq_no`
+    #[inline]
+    pub async fn end_stream(&self, seq_no: VarSeq) -> Result<(), Tx::Error> {
+        self.reply_keyed(seq_no, crate::standard_icd::STREAM_END_KEY, &())
+            .await
     }
 
     /// Publish a Topic message
+    ///
+    /// Nothing is sent if the client is not subscribed to `T`, see
+    /// [`Sender::has_subscribers()`]. See also [`Sender::publisher()`], which keeps
+    /// track of sequence numbers.
     #[inline]
     pub async fn publish<T>(&self, seq_no: VarSeq, msg: &T::Message) -> Result<(), Tx::Error>
+    where
+        T: ?Sized,
+        T: crate::Topic,
+        T::Message: Serialize + Schema,
+    {
+        if !self.has_subscribers::<T>() {
+            return Ok(());
+        }
+        self.send_topic::<T>(seq_no, msg).await
+    }
+
+    /// Send a Topic message, whether or not the client is subscribed to it
+    ///
+    /// Used for the standard topics, which are part of the protocol, such as the
+    /// schemas sent by [`Sender::send_all_schemas()`].
+    #[inline]
+    async fn send_topic<T>(&self, seq_no: VarSeq, msg: &T::Message) -> Result<(), Tx::Error>
     where
         T: ?Sized,
         T: crate::Topic,
@@ -239,7 +724,7 @@ impl<Tx: WireTx> Sender<Tx> {
         let mut key = VarKey::Key8(T::TOPIC_KEY);
         key.shrink_to(self.kkind);
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<T::Message>(wh, msg).await
+        self.send::<T::Message>(wh, msg).await
     }
 
     /// Log a `str` directly to the [`LoggingTopic`][crate::standard_icd::LoggingTopic]
@@ -254,6 +739,15 @@ impl<Tx: WireTx> Sender<Tx> {
         self.tx.send_log_fmt(self.kkind, msg).await
     }
 
+    /// Wait until all frames sent so far have been transmitted
+    ///
+    /// See [`WireTx::flush()`]. Useful before the link goes away, e.g. to make
+    /// sure the reply acknowledging a reset was sent before the USB disconnect.
+    #[inline]
+    pub async fn flush(&self) -> Result<(), Tx::Error> {
+        self.tx.flush().await
+    }
+
     /// Send a single error message
     pub async fn error(
         &self,
@@ -264,6 +758,130 @@ impl<Tx: WireTx> Sender<Tx> {
             .await
     }
 
+    /// Report that the handler for the request with `seq_no` and `key` faulted
+    ///
+    /// `message` is not sent, only its [`fault_hash()`][crate::standard_icd::fault_hash],
+    /// see [`WireError::HandlerFault`]. Handlers of the `blocking` flavors in `std`
+    /// servers report panics automatically.
+    pub async fn report_fault(
+        &self,
+        seq_no: VarSeq,
+        key: Key,
+        message: &str,
+    ) -> Result<(), Tx::Error> {
+        let error = WireError::HandlerFault {
+            key: key.to_bytes(),
+            message_hash: crate::standard_icd::fault_hash(message),
+        };
+        self.error(seq_no, error).await
+    }
+
+    /// Send an error in reply to the given request, logging it locally first
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch] for errors that occur
+    /// while dispatching. See [`log_dispatch_error()`].
+    #[doc(hidden)]
+    pub async fn dispatch_error(&self, hdr: &VarHeader, error: WireError) -> Result<(), Tx::Error> {
+        log_dispatch_error(hdr, &error);
+        self.error(hdr.seq_no, error).await
+    }
+
+    /// Implements the [`DeviceInfoEndpoint`][crate::standard_icd::DeviceInfoEndpoint] endpoint
+    pub async fn send_device_info(
+        &self,
+        hdr: &VarHeader,
+        info: &crate::standard_icd::DeviceInfo<'_>,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::DeviceInfoEndpoint;
+
+        #[cfg(feature = "use-std")]
+        let info = &crate::standard_icd::OwnedDeviceInfo::from(info);
+
+        if self
+            .reply::<DeviceInfoEndpoint>(hdr.seq_no, info)
+            .await
+            .is_err()
+        {
+            // The strings may be too long for the outgoing buffer
+            let err = crate::standard_icd::WireError::SerFailed;
+            self.error(hdr.seq_no, err).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Implements the [`GetEndpointInfoEndpoint`][crate::standard_icd::GetEndpointInfoEndpoint] endpoint
+    pub async fn send_endpoint_info(
+        &self,
+        hdr: &VarHeader,
+        info: Option<&crate::standard_icd::EndpointInfo<'_>>,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::GetEndpointInfoEndpoint;
+
+        #[cfg(not(feature = "use-std"))]
+        let info = info.copied();
+        #[cfg(feature = "use-std")]
+        let info = info.map(crate::standard_icd::OwnedEndpointInfo::from);
+
+        if self
+            .reply::<GetEndpointInfoEndpoint>(hdr.seq_no, &info)
+            .await
+            .is_err()
+        {
+            // The path may be too long for the outgoing buffer
+            let err = crate::standard_icd::WireError::SerFailed;
+            self.error(hdr.seq_no, err).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Implements the [`GetKeysEndpoint`][crate::standard_icd::GetKeysEndpoint] endpoint
+    pub async fn send_device_keys(
+        &self,
+        hdr: &VarHeader,
+        device_map: &DeviceMap,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::GetKeysEndpoint;
+
+        #[cfg(not(feature = "use-std"))]
+        let keys = crate::standard_icd::DeviceKeys {
+            endpoints: device_map.endpoints,
+            topics_in: device_map.topics_in,
+            topics_out: device_map.topics_out,
+        };
+        #[cfg(feature = "use-std")]
+        let keys = crate::standard_icd::OwnedDeviceKeys {
+            endpoints: device_map
+                .endpoints
+                .iter()
+                .map(|(path, req, resp)| (path.to_string(), *req, *resp))
+                .collect(),
+            topics_in: device_map
+                .topics_in
+                .iter()
+                .map(|(path, key)| (path.to_string(), *key))
+                .collect(),
+            topics_out: device_map
+                .topics_out
+                .iter()
+                .map(|(path, key)| (path.to_string(), *key))
+                .collect(),
+        };
+
+        if self
+            .reply::<GetKeysEndpoint>(hdr.seq_no, &keys)
+            .await
+            .is_err()
+        {
+            // The map may be too large for the outgoing buffer
+            let err = crate::standard_icd::WireError::SerFailed;
+            self.error(hdr.seq_no, err).await
+        } else {
+            Ok(())
+        }
+    }
+
     /// Implements the [`GetAllSchemasEndpoint`][crate::standard_icd::GetAllSchemasEndpoint] endpoint
     pub async fn send_all_schemas(
         &self,
@@ -282,7 +900,7 @@ impl<Tx: WireTx> Sender<Tx> {
         // First, send all types
         for ty in device_map.types {
             let res = self
-                .publish::<GetAllSchemaDataTopic>(
+                .send_topic::<GetAllSchemaDataTopic>(
                     VarSeq::Seq2(msg_ctr),
                     &SchemaData::Type((*ty).into()),
                 )
@@ -296,7 +914,7 @@ impl<Tx: WireTx> Sender<Tx> {
         // Then all endpoints
         for ep in device_map.endpoints {
             let res = self
-                .publish::<GetAllSchemaDataTopic>(
+                .send_topic::<GetAllSchemaDataTopic>(
                     VarSeq::Seq2(msg_ctr),
                     &SchemaData::Endpoint {
                         path: ep.0.into(),
@@ -315,7 +933,7 @@ impl<Tx: WireTx> Sender<Tx> {
         // Then output topics
         for to in device_map.topics_out {
             let res = self
-                .publish::<GetAllSchemaDataTopic>(
+                .send_topic::<GetAllSchemaDataTopic>(
                     VarSeq::Seq2(msg_ctr),
                     &SchemaData::Topic {
                         direction: TopicDirection::ToClient,
@@ -333,7 +951,7 @@ impl<Tx: WireTx> Sender<Tx> {
         // Then input topics
         for ti in device_map.topics_in {
             let res = self
-                .publish::<GetAllSchemaDataTopic>(
+                .send_topic::<GetAllSchemaDataTopic>(
                     VarSeq::Seq2(msg_ctr),
                     &SchemaData::Topic {
                         direction: TopicDirection::ToServer,
@@ -365,37 +983,187 @@ impl<Tx: WireTx> Sender<Tx> {
     }
 }
 
-//////////////////////////////////////////////////////////////////////////////
-// SERVER
-//////////////////////////////////////////////////////////////////////////////
+impl<Tx: WireTx + Clone> Sender<Tx> {
+    /// Obtain a [`Publisher`] for the given topic
+    ///
+    /// The publisher starts with sequence number zero.
+    pub fn publisher<T>(&self) -> Publisher<Tx, T>
+    where
+        T: ?Sized,
+        T: crate::Topic,
+        T::Message: Serialize + Schema,
+    {
+        Publisher {
+            sender: self.clone(),
+            seq_no: 0,
+            _pd: PhantomData,
+        }
+    }
+}
 
-/// The [`Server`] is the main interface for handling communication
-pub struct Server<Tx, Rx, Buf, D>
-where
-    Tx: WireTx,
-    Rx: WireRx,
-    Buf: DerefMut<Target = [u8]>,
-    D: Dispatch<Tx = Tx>,
-{
-    tx: Sender<Tx>,
-    rx: Rx,
-    buf: Buf,
-    dis: D,
+/// A handle for publishing messages on a single [`Topic`][crate::Topic]
+///
+/// This wraps a [`Sender`], and assigns an incrementing sequence number to each
+/// message, which clients may use to detect dropped messages. Obtained with
+/// [`Sender::publisher()`] or [`Server::publisher()`].
+///
+/// ```rust,ignore
+/// let mut publisher = server.publisher::<TemperatureTopic>();
+/// spawner.must_spawn(async move {
+///     loop {
+///         let _ = publisher.publish(&read_temperature()).await;
+///         Timer::after_millis(100).await;
+///     }
+/// });
+/// ```
+pub struct Publisher<Tx: WireTx, T: ?Sized> {
+    sender: Sender<Tx>,
+    seq_no: u32,
+    _pd: PhantomData<fn() -> T>,
 }
 
-/// A type representing the different errors [`Server::run()`] may return
-pub enum ServerError<Tx, Rx>
+impl<Tx, T> Publisher<Tx, T>
 where
     Tx: WireTx,
-    Rx: WireRx,
+    T: ?Sized,
+    T: crate::Topic,
+    T::Message: Serialize + Schema,
 {
-    /// A fatal error occurred with the [`WireTx::send()`] implementation
-    TxFatal(Tx::Error),
-    /// A fatal error occurred with the [`WireRx::receive()`] implementation
-    RxFatal(Rx::Error),
-}
+    /// Publish a message, using the next sequence number
+    ///
+    /// Nothing is sent, and the sequence number is not used, if the client is not
+    /// subscribed to the topic.
+    pub async fn publish(&mut self, msg: &T::Message) -> Result<(), Tx::Error> {
+        if !self.has_subscribers() {
+            return Ok(());
+        }
+        let seq_no = VarSeq::Seq4(self.seq_no);
+        self.seq_no = self.seq_no.wrapping_add(1);
+        self.sender.publish::<T>(seq_no, msg).await
+    }
 
-impl<Tx, Rx, Buf, D> Server<Tx, Rx, Buf, D>
+    /// Whether the client is subscribed to the topic
+    ///
+    /// See [`Sender::has_subscribers()`].
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.has_subscribers::<T>()
+    }
+
+    /// The sequence number that will be used for the next message
+    pub fn next_seq_no(&self) -> u32 {
+        self.seq_no
+    }
+
+    /// Get the [`Sender`] used by this publisher
+    pub fn sender(&self) -> &Sender<Tx> {
+        &self.sender
+    }
+}
+
+/// Log an error that occurred while dispatching the given frame
+///
+/// With the `defmt` feature enabled, this emits a `defmt` warning, so that
+/// problems show up on the target's console and not only on the client. Does
+/// nothing otherwise.
+#[doc(hidden)]
+#[inline]
+pub fn log_dispatch_error(hdr: &VarHeader, error: &WireError) {
+    #[cfg(feature = "defmt")]
+    defmt::warn!("postcard-rpc: error {} while dispatching {}", error, hdr);
+
+    #[cfg(not(feature = "defmt"))]
+    let _ = (hdr, error);
+}
+
+/// Call a blocking handler, converting a panic into a [`WireError::HandlerFault`]
+///
+/// Used by [`define_dispatch!`][crate::define_dispatch]. Panics are only caught
+/// with the `use-std` feature, otherwise `f` is simply called.
+#[doc(hidden)]
+pub fn catch_fault<R>(key: Key, f: impl FnOnce() -> R) -> Result<R, WireError> {
+    #[cfg(feature = "use-std")]
+    {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("");
+            WireError::HandlerFault {
+                key: key.to_bytes(),
+                message_hash: crate::standard_icd::fault_hash(message),
+            }
+        })
+    }
+
+    #[cfg(not(feature = "use-std"))]
+    {
+        let _ = key;
+        Ok(f())
+    }
+}
+
+/// Serialize an outgoing frame, and pass it to the wire tap
+fn tap_outgoing<T>(tap: WireTap, hdr: &VarHeader, msg: &T)
+where
+    T: Serialize + ?Sized,
+{
+    #[cfg(feature = "use-std")]
+    {
+        let mut frame = hdr.write_to_vec();
+        if let Ok(body) = postcard::to_stdvec(msg) {
+            frame.extend_from_slice(&body);
+            tap(FrameDirection::Outgoing, &frame);
+        }
+    }
+
+    #[cfg(not(feature = "use-std"))]
+    {
+        let mut frame = [0u8; WIRE_TAP_MAX_FRAME];
+        let Some((hdr_used, remain)) = hdr.write_to_slice(&mut frame) else {
+            return;
+        };
+        let hdr_len = hdr_used.len();
+        let Ok(body) = postcard::to_slice(msg, remain) else {
+            return;
+        };
+        let len = hdr_len + body.len();
+        tap(FrameDirection::Outgoing, &frame[..len]);
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SERVER
+//////////////////////////////////////////////////////////////////////////////
+
+/// The [`Server`] is the main interface for handling communication
+pub struct Server<Tx, Rx, Buf, D>
+where
+    Tx: WireTx,
+    Rx: WireRx,
+    Buf: DerefMut<Target = [u8]>,
+    D: Dispatch<Tx = Tx>,
+{
+    tx: Sender<Tx>,
+    rx: Rx,
+    buf: Buf,
+    dis: D,
+    hook: Option<LoopHook>,
+}
+
+/// A type representing the different errors [`Server::run()`] may return
+pub enum ServerError<Tx, Rx>
+where
+    Tx: WireTx,
+    Rx: WireRx,
+{
+    /// A fatal error occurred with the [`WireTx::send()`] implementation
+    TxFatal(Tx::Error),
+    /// A fatal error occurred with the [`WireRx::receive()`] implementation
+    RxFatal(Rx::Error),
+}
+
+impl<Tx, Rx, Buf, D> Server<Tx, Rx, Buf, D>
 where
     Tx: WireTx,
     Rx: WireRx,
@@ -413,94 +1181,1311 @@ where
     /// * a [`VarKeyKind`], which controls the key sizes sent by the [`WireTx`] impl
     pub fn new(tx: Tx, rx: Rx, buf: Buf, dis: D, kkind: VarKeyKind) -> Self {
         Self {
-            tx: Sender { tx, kkind },
-            rx,
-            buf,
-            dis,
+            tx: Sender::new(tx, kkind),
+            rx,
+            buf,
+            dis,
+            hook: None,
+        }
+    }
+
+    /// Observe every frame sent or received by this server
+    ///
+    /// See [`Sender::set_wire_tap()`]. This only applies to [`Sender`]s obtained
+    /// after calling this method.
+    pub fn set_wire_tap(&mut self, tap: Option<WireTap>) {
+        self.tx.set_wire_tap(tap);
+    }
+
+    /// Only publish topics the client is subscribed to in `subscriptions`
+    ///
+    /// See [`Subscriptions`], usually obtained with the `subscriptions()` method
+    /// of the dispatcher. This only applies to [`Sender`]s and [`Publisher`]s
+    /// obtained after calling this method.
+    pub fn set_subscriptions(&mut self, subscriptions: Option<&'static Subscriptions>) {
+        self.tx.set_subscriptions(subscriptions);
+    }
+
+    /// Observe the progress of the dispatch loop, e.g. to feed a watchdog
+    ///
+    /// See [`LoopEvent`] for when the hook is called, and [`Liveness`] for detecting
+    /// a stuck handler.
+    pub fn set_loop_hook(&mut self, hook: Option<LoopHook>) {
+        self.hook = hook;
+    }
+
+    /// Access the dispatcher of this server
+    ///
+    /// This can be used between calls to [`Server::run()`], e.g. to change the
+    /// handlers of a dispatcher with handlers registered at runtime.
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dis
+    }
+
+    /// Run until a fatal error occurs
+    ///
+    /// The server will receive frames, and dispatch them. When a fatal error occurs,
+    /// this method will return with the fatal error.
+    ///
+    /// The caller may decide to wait until a connection is re-established, reset any
+    /// state, or immediately begin re-running.
+    pub async fn run(&mut self) -> ServerError<Tx, Rx> {
+        loop {
+            if let Err(e) = self.run_once().await {
+                return e;
+            }
+        }
+    }
+
+    /// Run until `shutdown` is drained, then flush any pending transmit
+    ///
+    /// Like [`Server::run()`], but after every frame, returns `Ok(())` once
+    /// [`Shutdown::is_drained()`], after [flushing](Sender::flush) the replies sent
+    /// so far. This is used for a controlled reset, e.g. with a handler calling
+    /// [`Shutdown::shutdown()`] and replying "reset acknowledged": the reply is
+    /// transmitted before this returns, and the link may be dropped.
+    ///
+    /// The check happens after each frame, so a shutdown requested by another task
+    /// while no frames are received is only noticed with the next frame. In that
+    /// case, select [`Server::run()`] with [`Shutdown::wait_drained()`], and call
+    /// [`Sender::flush()`] afterwards.
+    pub async fn run_until_drained(
+        &mut self,
+        shutdown: &Shutdown,
+    ) -> Result<(), ServerError<Tx, Rx>> {
+        loop {
+            if shutdown.is_drained() {
+                return self.tx.flush().await.map_err(ServerError::TxFatal);
+            }
+            self.run_once().await?;
+        }
+    }
+
+    /// Receive and dispatch a single frame
+    async fn run_once(&mut self) -> Result<(), ServerError<Tx, Rx>> {
+        let Self {
+            tx,
+            rx,
+            buf,
+            dis: d,
+            hook,
+        } = self;
+        if let Some(hook) = hook {
+            hook(LoopEvent::Waiting);
+        }
+        rx.wait_connection().await;
+        tx.tx.wait_connection().await;
+        let buf_len = buf.len();
+        let event = match rx.receive(buf).await {
+            Ok(used) => {
+                if let Some(tap) = tx.tap {
+                    tap(FrameDirection::Incoming, used);
+                }
+                sans_io::on_frame(used)
+            }
+            Err(e) => match sans_io::on_rx_error(e.as_kind(), buf_len) {
+                RxEvent::Closed => {
+                    d.reset_connection();
+                    return Err(ServerError::RxFatal(e));
+                }
+                event => event,
+            },
+        };
+        let res = match event {
+            RxEvent::Dispatch {
+                hdr,
+                deadline_ms,
+                body,
+            } => {
+                if let Some(hook) = hook {
+                    hook(LoopEvent::DispatchStart(&hdr));
+                }
+                let res = match deadline_ms {
+                    Some(ms) => d.handle_with_deadline(tx, &hdr, ms, body).await,
+                    None => d.handle(tx, &hdr, body).await,
+                };
+                if let Some(hook) = hook {
+                    hook(LoopEvent::DispatchDone(&hdr));
+                }
+                res
+            }
+            RxEvent::Reject { hdr, err } => tx.dispatch_error(&hdr, err).await,
+            // A closed connection was handled above
+            RxEvent::Ignore | RxEvent::Closed => return Ok(()),
+        };
+        match res {
+            Err(e) if sans_io::is_fatal(e.as_kind()) => {
+                d.reset_connection();
+                Err(ServerError::TxFatal(e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<Tx, Rx, Buf, D> Server<Tx, Rx, Buf, D>
+where
+    Tx: WireTx + Clone,
+    Rx: WireRx,
+    Buf: DerefMut<Target = [u8]>,
+    D: Dispatch<Tx = Tx>,
+{
+    /// Get a copy of the [`Sender`] to pass to tasks that need it
+    pub fn sender(&self) -> Sender<Tx> {
+        self.tx.clone()
+    }
+
+    /// Obtain a [`Publisher`] for the given topic, to pass to tasks that need it
+    pub fn publisher<T>(&self) -> Publisher<Tx, T>
+    where
+        T: ?Sized,
+        T: crate::Topic,
+        T::Message: Serialize + Schema,
+    {
+        self.tx.publisher::<T>()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// LOOP HOOKS
+//////////////////////////////////////////////////////////////////////////////
+
+/// A function observing the progress of the dispatch loop of a [`Server`]
+///
+/// See [`Server::set_loop_hook()`].
+pub type LoopHook = fn(LoopEvent<'_>);
+
+/// The progress of the dispatch loop, passed to a [`LoopHook`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopEvent<'a> {
+    /// The loop is about to wait for the next frame, once per iteration
+    ///
+    /// The loop may then wait for a frame for any amount of time.
+    Waiting,
+    /// A frame with this header is about to be passed to the dispatcher
+    DispatchStart(&'a VarHeader),
+    /// The dispatcher returned after handling the frame with this header
+    ///
+    /// For `spawn` and `cancellable` handlers, this is once the handler has been
+    /// spawned, not when it completes.
+    DispatchDone(&'a VarHeader),
+}
+
+/// Detects a dispatch loop stuck in a handler, e.g. to stop feeding a watchdog
+///
+/// Every [`LoopEvent`] is passed to [`Liveness::record()`] by a [`LoopHook`], and a
+/// separate task periodically calls [`Liveness::check()`], feeding the watchdog only
+/// while the loop is healthy. The loop is healthy while it is waiting for a frame,
+/// or if it made progress since the last check, so the period of the checks must be
+/// longer than the slowest handler, and shorter than the watchdog timeout:
+///
+/// ```rust,ignore
+/// static LIVENESS: Liveness = Liveness::new();
+///
+/// server.set_loop_hook(Some(|event| LIVENESS.record(&event)));
+///
+/// #[embassy_executor::task]
+/// async fn watchdog_task(mut wdt: Watchdog) {
+///     wdt.start(Duration::from_secs(2));
+///     loop {
+///         Timer::after_millis(500).await;
+///         if LIVENESS.check() {
+///             wdt.feed();
+///         }
+///     }
+/// }
+/// ```
+///
+/// A handler that never yields also blocks the task calling `check()` on the same
+/// executor, which stops feeding the watchdog as well.
+pub struct Liveness {
+    busy: portable_atomic::AtomicBool,
+    progress: portable_atomic::AtomicU32,
+    checked: portable_atomic::AtomicU32,
+}
+
+impl Liveness {
+    /// Create a new, idle, liveness tracker
+    pub const fn new() -> Self {
+        Self {
+            busy: portable_atomic::AtomicBool::new(false),
+            progress: portable_atomic::AtomicU32::new(0),
+            checked: portable_atomic::AtomicU32::new(0),
+        }
+    }
+
+    /// Record an event of the dispatch loop
+    pub fn record(&self, event: &LoopEvent<'_>) {
+        let busy = matches!(event, LoopEvent::DispatchStart(_));
+        self.busy.store(busy, Ordering::Release);
+        self.progress.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Whether the dispatch loop is currently handling a frame
+    pub fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+
+    /// Whether the dispatch loop is waiting for a frame, or made progress since the
+    /// last call to this method
+    pub fn check(&self) -> bool {
+        let progress = self.progress.load(Ordering::Acquire);
+        let last = self.checked.swap(progress, Ordering::AcqRel);
+        !self.is_busy() || progress != last
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// DISPATCH TRAIT
+//////////////////////////////////////////////////////////////////////////////
+
+/// The dispatch trait handles an incoming endpoint or topic message
+///
+/// The implementations of this trait are typically implemented by the
+/// [`define_dispatch!`][crate::define_dispatch] macro.
+pub trait Dispatch {
+    /// The [`WireTx`] impl used by this dispatcher
+    type Tx: WireTx;
+
+    /// The minimum key length required to avoid hash collisions
+    fn min_key_len(&self) -> VarKeyKind;
+
+    /// Handle a single incoming frame (endpoint or topic), and dispatch appropriately
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error>;
+
+    /// Handle a single incoming frame, sent with a deadline of `deadline_ms`
+    /// milliseconds, see [`Deadline`]
+    ///
+    /// By default, the deadline is ignored.
+    async fn handle_with_deadline(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        deadline_ms: u32,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let _ = deadline_ms;
+        self.handle(tx, hdr, body).await
+    }
+
+    /// The [`Capabilities`] granted on the current connection, as a bitmask
+    ///
+    /// By default, no capabilities are ever granted.
+    fn granted_caps(&self) -> u32 {
+        0
+    }
+
+    /// Replace the [`Capabilities`] granted on the current connection
+    ///
+    /// This is used by dispatchers handling frames of several connections, e.g.
+    /// to restore the grants of a connection before handling its frame. By
+    /// default, this does nothing.
+    fn set_granted_caps(&mut self, caps: u32) {
+        let _ = caps;
+    }
+
+    /// Forget the replies cached for idempotent endpoints, see [`ReplyCache`]
+    ///
+    /// This is used by dispatchers handling frames of several connections, as
+    /// their clients may reuse the same sequence numbers. By default, this does
+    /// nothing.
+    fn clear_replies(&mut self) {}
+
+    /// Forget the state of the current connection, e.g. its granted capabilities
+    ///
+    /// Called by the [`Server`] when the connection is closed, so a client
+    /// connecting later doesn't inherit it. By default, this revokes all
+    /// capabilities, and clears the cached replies.
+    fn reset_connection(&mut self) {
+        self.set_granted_caps(0);
+        self.clear_replies();
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SERVICES
+//////////////////////////////////////////////////////////////////////////////
+
+/// A dispatcher for a subsystem, which can be mounted in another dispatcher
+///
+/// This is implemented by [`define_dispatch!`][crate::define_dispatch], and used by
+/// [`nest!`][crate::nest] to route frames to the dispatcher handling their key.
+pub trait Service {
+    /// The names and keys of all endpoints and incoming topics with a handler
+    const KEYS: &'static [(&'static str, Key)];
+
+    /// The paths of all endpoints and incoming topics with a handler
+    const PATHS: &'static [&'static str];
+
+    /// Whether this dispatcher has a handler for frames with the given key
+    fn handles(&self, key: &VarKey) -> bool {
+        Self::KEYS.iter().any(|(_, k)| VarKey::Key8(*k) == *key)
+    }
+}
+
+/// Whether all paths are below the service `prefix`, i.e. start with `"{prefix}/"`
+pub const fn paths_under(paths: &[&str], prefix: &str) -> bool {
+    let prefix = prefix.as_bytes();
+    let mut i = 0;
+    while i < paths.len() {
+        let path = paths[i].as_bytes();
+        if path.len() <= prefix.len() || path[prefix.len()] != b'/' {
+            return false;
+        }
+        let mut j = 0;
+        while j < prefix.len() {
+            if path[j] != prefix[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The longer of two key lengths
+#[doc(hidden)]
+pub const fn max_key_kind(a: VarKeyKind, b: VarKeyKind) -> VarKeyKind {
+    const fn len(kind: VarKeyKind) -> usize {
+        match kind {
+            VarKeyKind::Key1 => 1,
+            VarKeyKind::Key2 => 2,
+            VarKeyKind::Key4 => 4,
+            VarKeyKind::Key8 => 8,
+        }
+    }
+    if len(a) >= len(b) {
+        a
+    } else {
+        b
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// CANCELLATION
+//////////////////////////////////////////////////////////////////////////////
+
+/// A single slot of a [`CancelMap`]
+#[doc(hidden)]
+pub struct CancelSlot {
+    in_use: portable_atomic::AtomicBool,
+    cancelled: AtomicBool,
+    seq_no: AtomicU32,
+}
+
+impl CancelSlot {
+    const fn new() -> Self {
+        Self {
+            in_use: portable_atomic::AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            seq_no: AtomicU32::new(0),
+        }
+    }
+}
+
+/// A fixed size table of in-flight cancellable requests
+///
+/// A `CancelMap` is created by [`define_dispatch!`][crate::define_dispatch] for
+/// use by the `cancellable` handler flavor, and is not typically used directly.
+///
+/// Slots are claimed atomically, so requests may be registered from any task, and
+/// the returned [`CancelToken`]s may be held by any task. Its capacity `N` is set
+/// with the `max_cancellable` option of the macro.
+pub struct CancelMap<const N: usize> {
+    slots: [CancelSlot; N],
+}
+
+impl<const N: usize> CancelMap<N> {
+    /// Create a new, empty, map
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { CancelSlot::new() }; N],
+        }
+    }
+
+    /// Obtain a [`CancelToken`] for the request with the given sequence number
+    ///
+    /// Returns `None` if all slots are in use.
+    pub fn register(&'static self, seq_no: VarSeq) -> Option<CancelToken> {
+        // Claim the slot before filling it in, so two registrations never share it
+        let slot = self.slots.iter().find(|s| {
+            s.in_use
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })?;
+        slot.seq_no.store(seq_no.into(), Ordering::Relaxed);
+        slot.cancelled.store(false, Ordering::Release);
+        Some(CancelToken {
+            slot,
+            _in_flight: None,
+        })
+    }
+
+    /// Cancel the in-flight request with the given sequence number
+    ///
+    /// Returns `true` if a matching request was found.
+    pub fn cancel(&self, seq_no: VarSeq) -> bool {
+        let seq_no: u32 = seq_no.into();
+        let mut found = false;
+        for slot in self.slots.iter() {
+            if slot.in_use.load(Ordering::Acquire) && slot.seq_no.load(Ordering::Relaxed) == seq_no
+            {
+                slot.cancelled.store(true, Ordering::Release);
+                found = true;
+            }
+        }
+        found
+    }
+}
+
+impl<const N: usize> Default for CancelMap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A token given to `cancellable` handlers, used to observe whether the client
+/// has requested cancellation of the request
+///
+/// Cancellation is cooperative: long running handlers should periodically check
+/// [`CancelToken::is_cancelled()`], and stop work (typically replying with
+/// [`WireError::Cancelled`][crate::standard_icd::WireError::Cancelled]) if so.
+pub struct CancelToken {
+    slot: &'static CancelSlot,
+    _in_flight: Option<InFlight>,
+}
+
+impl CancelToken {
+    /// Has the client requested cancellation of this request?
+    pub fn is_cancelled(&self) -> bool {
+        self.slot.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Count the request as in-flight until this token is dropped
+    #[doc(hidden)]
+    pub fn track(&mut self, in_flight: InFlight) {
+        self._in_flight = Some(in_flight);
+    }
+}
+
+impl Drop for CancelToken {
+    fn drop(&mut self) {
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+/// A token given to `deferred` handlers, used to reply to the request later,
+/// e.g. from another task
+///
+/// The token holds the [`Sender`] and the sequence number of the request, so the
+/// reply is sent with the right key and sequence number no matter where
+/// [`ReplyToken::reply()`] is called. The request counts as in-flight, and
+/// against the spawn limit, until the token is consumed or dropped. Dropping the
+/// token without replying sends nothing, and the client waits until it times out.
+pub struct ReplyToken<E: crate::Endpoint, Tx: WireTx> {
+    sender: Sender<Tx>,
+    seq_no: VarSeq,
+    _in_flight: Option<InFlight>,
+    _endpoint: PhantomData<fn() -> E>,
+}
+
+impl<E: crate::Endpoint, Tx: WireTx> ReplyToken<E, Tx> {
+    /// Create a token replying to the request with `seq_no` using `sender`
+    pub fn new(sender: Sender<Tx>, seq_no: VarSeq) -> Self {
+        Self {
+            sender,
+            seq_no,
+            _in_flight: None,
+            _endpoint: PhantomData,
+        }
+    }
+
+    /// Count the request as in-flight until this token is dropped
+    #[doc(hidden)]
+    pub fn track(&mut self, in_flight: InFlight) {
+        self._in_flight = Some(in_flight);
+    }
+
+    /// The sequence number of the request
+    pub fn seq_no(&self) -> VarSeq {
+        self.seq_no
+    }
+
+    /// Send the reply to the request
+    pub async fn reply(self, resp: &E::Response) -> Result<(), Tx::Error>
+    where
+        E::Response: Serialize + Schema,
+    {
+        self.sender.reply::<E>(self.seq_no, resp).await
+    }
+
+    /// Send an error instead of a reply to the request
+    pub async fn error(self, error: crate::standard_icd::WireError) -> Result<(), Tx::Error> {
+        self.sender.error(self.seq_no, error).await
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SHUTDOWN
+//////////////////////////////////////////////////////////////////////////////
+
+/// The interval at which [`Shutdown::wait_drained()`] checks for in-flight requests
+const SHUTDOWN_POLL_MS: u32 = 1;
+
+/// A shutdown signal for a dispatcher, used to stop accepting requests and
+/// wait for in-flight handlers to complete
+///
+/// A `Shutdown` is created by [`define_dispatch!`][crate::define_dispatch], and
+/// obtained with the `shutdown_handle()` method of the dispatcher. After calling
+/// [`Shutdown::shutdown()`], requests to user endpoints are answered with
+/// [`WireError::ShuttingDown`], and topic messages are dropped, while the
+/// dispatcher keeps running until stopped by the caller, e.g.:
+///
+/// ```rust,ignore
+/// let shutdown = dispatcher.shutdown_handle();
+/// let spawn = dispatcher.spawn.clone();
+/// let mut server = Server::new(tx, rx, buf, dispatcher, kkind);
+///
+/// // in another task
+/// shutdown.shutdown();
+///
+/// // Runs the server until all in-flight handlers have completed
+/// select(server.run(), shutdown.wait_drained(&spawn)).await;
+/// ```
+///
+/// For a controlled reset, [`Server::run_until_drained()`] also flushes the
+/// replies sent so far before returning, so the host receives the final reply
+/// before the link drops.
+///
+/// `cancellable` handlers are counted as in-flight until they complete, and
+/// `deferred` requests until their [`ReplyToken`] is consumed or dropped. `spawn`
+/// handlers are not tracked automatically, but can hold an [`InFlight`] guard
+/// from [`Shutdown::enter()`] for as long as they run. Handlers of other kinds
+/// run within the dispatcher, and always complete before the next request is
+/// received.
+pub struct Shutdown {
+    requested: AtomicBool,
+    in_flight: portable_atomic::AtomicU32,
+}
+
+impl Shutdown {
+    /// Create a new shutdown signal, which accepts requests
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            in_flight: portable_atomic::AtomicU32::new(0),
+        }
+    }
+
+    /// Stop accepting requests
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+
+    /// Start accepting requests again, e.g. after a mode change
+    pub fn reset(&self) {
+        self.requested.store(false, Ordering::Release);
+    }
+
+    /// Has [`Shutdown::shutdown()`] been called?
+    pub fn is_shutting_down(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// The number of requests currently in-flight
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Is shutdown requested, with no requests in-flight?
+    pub fn is_drained(&self) -> bool {
+        self.is_shutting_down() && self.in_flight() == 0
+    }
+
+    /// Count a request as in-flight until the returned guard is dropped
+    pub fn enter(&'static self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        InFlight { shutdown: self }
+    }
+
+    /// Wait until shutdown has been requested, and no requests are in-flight
+    ///
+    /// `timer` is used to periodically check the number of in-flight requests.
+    pub async fn wait_drained<T: WireTimer>(&self, timer: &T) {
+        while !self.is_drained() {
+            timer.delay_ms(SHUTDOWN_POLL_MS).await;
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A guard counting a request as in-flight, see [`Shutdown::enter()`]
+pub struct InFlight {
+    shutdown: &'static Shutdown,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SPAWN LIMIT
+//////////////////////////////////////////////////////////////////////////////
+
+/// A limit on the number of spawned handlers running at the same time
+///
+/// Used by `define_dispatch!` with the `max_spawned` option. Every `spawn` or
+/// `cancellable` handler takes a [`SpawnPermit`] before being spawned, which it
+/// holds until it drops its [`Sender`], usually when it returns. Requests that
+/// arrive while all permits are taken are answered with [`WireError::Busy`],
+/// instead of exhausting the resources of the executor.
+pub struct SpawnLimit {
+    max: u32,
+    active: portable_atomic::AtomicU32,
+}
+
+impl SpawnLimit {
+    /// Create a new limit, allowing up to `max` spawned handlers
+    pub const fn new(max: u32) -> Self {
+        Self {
+            max,
+            active: portable_atomic::AtomicU32::new(0),
+        }
+    }
+
+    /// The maximum number of spawned handlers
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// The number of spawned handlers currently holding a permit
+    pub fn active(&self) -> u32 {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Whether all permits are taken
+    pub fn is_exhausted(&self) -> bool {
+        self.active() >= self.max
+    }
+
+    /// Take a permit, if fewer than [`SpawnLimit::max()`] are taken
+    pub fn try_acquire(&'static self) -> Option<SpawnPermit> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(SpawnPermit { limit: self })
+    }
+}
+
+/// A permit to run a spawned handler, see [`SpawnLimit::try_acquire()`]
+pub struct SpawnPermit {
+    limit: &'static SpawnLimit,
+}
+
+impl Drop for SpawnPermit {
+    fn drop(&mut self) {
+        self.limit.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// CAPABILITIES
+//////////////////////////////////////////////////////////////////////////////
+
+/// The capabilities granted to the connected client, as a bitmask
+///
+/// Each dispatcher created by [`define_dispatch!`][crate::define_dispatch] holds
+/// its own `Capabilities` set, obtained with its `capabilities()` method. Requests to
+/// endpoints annotated with `[requires_cap = MASK]` are answered with
+/// [`WireError::Unauthorized`] unless all bits of `MASK` are granted. Capabilities
+/// are granted by the `grant_caps` handler, when the client requests them with
+/// [`GrantCapsEndpoint`][crate::standard_icd::GrantCapsEndpoint].
+///
+/// The grants belong to the connection, not the dispatcher: the [`Server`] revokes
+/// them with [`Dispatch::reset_connection()`] when the connection is closed. A
+/// connection reset the server doesn't notice must be handled by the application,
+/// with [`Capabilities::revoke_all()`].
+pub struct Capabilities {
+    granted: portable_atomic::AtomicU32,
+}
+
+impl Capabilities {
+    /// Create a new set, with no capabilities granted
+    pub const fn new() -> Self {
+        Self {
+            granted: portable_atomic::AtomicU32::new(0),
         }
     }
 
-    /// Run until a fatal error occurs
+    /// The bitmask of all granted capabilities
+    pub fn granted(&self) -> u32 {
+        self.granted.load(Ordering::Acquire)
+    }
+
+    /// Whether all capabilities in `mask` are granted
+    pub fn has(&self, mask: u32) -> bool {
+        self.granted() & mask == mask
+    }
+
+    /// Grant the capabilities in `mask`, in addition to those already granted
+    pub fn grant(&self, mask: u32) {
+        self.granted.fetch_or(mask, Ordering::AcqRel);
+    }
+
+    /// Revoke the capabilities in `mask`
+    pub fn revoke(&self, mask: u32) {
+        self.granted.fetch_and(!mask, Ordering::AcqRel);
+    }
+
+    /// Revoke all capabilities, e.g. when the connection is reset
+    pub fn revoke_all(&self) {
+        self.granted.store(0, Ordering::Release);
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// SUBSCRIPTIONS
+//////////////////////////////////////////////////////////////////////////////
+
+/// The number of topics a [`Subscriptions`] registry can track
+pub const SUBSCRIPTION_SLOTS: usize = 16;
+
+/// A single slot of a [`Subscriptions`] registry
+struct SubscriptionSlot {
+    in_use: AtomicBool,
+    key_lo: AtomicU32,
+    key_hi: AtomicU32,
+    count: portable_atomic::AtomicU32,
+}
+
+impl SubscriptionSlot {
+    const fn new() -> Self {
+        Self {
+            in_use: AtomicBool::new(false),
+            key_lo: AtomicU32::new(0),
+            key_hi: AtomicU32::new(0),
+            count: portable_atomic::AtomicU32::new(0),
+        }
+    }
+
+    fn key(&self) -> [u8; 8] {
+        let lo = self.key_lo.load(Ordering::Relaxed).to_le_bytes();
+        let hi = self.key_hi.load(Ordering::Relaxed).to_le_bytes();
+        [lo[0], lo[1], lo[2], lo[3], hi[0], hi[1], hi[2], hi[3]]
+    }
+}
+
+/// The topics the connected client is subscribed to
+///
+/// A `Subscriptions` registry is created by [`define_dispatch!`][crate::define_dispatch],
+/// and obtained with the `subscriptions()` method of the dispatcher. It counts the
+/// [`SubscribeTopic`][crate::standard_icd::SubscribeTopic] and
+/// [`UnsubscribeTopic`][crate::standard_icd::UnsubscribeTopic] messages the client
+/// sent for each topic.
+///
+/// Once passed to [`Server::set_subscriptions()`], topic messages are only sent
+/// while the client is subscribed to the topic: [`Sender::publish()`] and
+/// [`Publisher::publish()`] do nothing otherwise, so publishing tasks may skip
+/// reading sensors when [`Publisher::has_subscribers()`] is `false`.
+///
+/// The standard topics, e.g. the schemas sent for
+/// [`GetAllSchemasEndpoint`][crate::standard_icd::GetAllSchemasEndpoint] and the
+/// records of the [`LogTopic`][crate::standard_icd::LogTopic], are always sent.
+///
+/// Up to [`SUBSCRIPTION_SLOTS`] topics are tracked. Once more topics were
+/// subscribed to, all topics are considered subscribed, so none are silenced by a
+/// lack of slots.
+///
+/// The subscriptions belong to the connection, not the dispatcher: they are cleared
+/// by [`Dispatch::reset_connection()`] when the connection is closed, so a host
+/// connecting later must subscribe again.
+///
+/// Subscriptions must only be changed from the dispatcher, while any task may
+/// check them.
+pub struct Subscriptions {
+    slots: [SubscriptionSlot; SUBSCRIPTION_SLOTS],
+    overflowed: AtomicBool,
+}
+
+impl Subscriptions {
+    /// Create a new registry, with no subscriptions
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { SubscriptionSlot::new() }; SUBSCRIPTION_SLOTS],
+            overflowed: AtomicBool::new(false),
+        }
+    }
+
+    fn slot(&self, key: &Key) -> Option<&SubscriptionSlot> {
+        let key = key.to_bytes();
+        self.slots
+            .iter()
+            .find(|s| s.in_use.load(Ordering::Acquire) && s.key() == key)
+    }
+
+    /// Add a subscriber to the topic with the given key
+    pub fn subscribe(&self, key: Key) {
+        if let Some(slot) = self.slot(&key) {
+            slot.count.fetch_add(1, Ordering::AcqRel);
+            return;
+        }
+        let Some(slot) = self
+            .slots
+            .iter()
+            .find(|s| !s.in_use.load(Ordering::Acquire))
+        else {
+            self.overflowed.store(true, Ordering::Release);
+            return;
+        };
+        let bytes = key.to_bytes();
+        let [l0, l1, l2, l3, h0, h1, h2, h3] = bytes;
+        slot.key_lo
+            .store(u32::from_le_bytes([l0, l1, l2, l3]), Ordering::Relaxed);
+        slot.key_hi
+            .store(u32::from_le_bytes([h0, h1, h2, h3]), Ordering::Relaxed);
+        slot.count.store(1, Ordering::Relaxed);
+        slot.in_use.store(true, Ordering::Release);
+    }
+
+    /// Remove a subscriber from the topic with the given key
     ///
-    /// The server will receive frames, and dispatch them. When a fatal error occurs,
-    /// this method will return with the fatal error.
+    /// Unsubscribing from a topic without subscribers is ignored.
+    pub fn unsubscribe(&self, key: Key) {
+        if let Some(slot) = self.slot(&key) {
+            // A `fetch_sub` that stops at zero
+            let _ = slot
+                .count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(1));
+        }
+    }
+
+    /// The number of subscribers of the topic with the given key
+    pub fn subscribers(&self, key: Key) -> u32 {
+        self.slot(&key)
+            .map_or(0, |slot| slot.count.load(Ordering::Acquire))
+    }
+
+    /// Whether the topic with the given key has any subscribers
+    pub fn is_subscribed(&self, key: Key) -> bool {
+        self.overflowed.load(Ordering::Acquire) || self.subscribers(key) != 0
+    }
+
+    /// Remove all subscriptions, e.g. when the connection is reset
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.in_use.store(false, Ordering::Release);
+        }
+        self.overflowed.store(false, Ordering::Release);
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// PRIORITY
+//////////////////////////////////////////////////////////////////////////////
+
+/// The priority of an endpoint, set with `[priority = high]` in
+/// [`define_dispatch!`][crate::define_dispatch]
+///
+/// Priorities only take effect when frames are passed to worker pools with a
+/// `PriorityFrontend` from the `pool` module, which routes high-priority requests
+/// to their own queue, handled by workers on a higher priority executor. For more
+/// levels, or to reorder frames without a second executor, see the `PriorityQueue`
+/// of the `pool` module. Otherwise, all frames are handled in the order they were
+/// received.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// The default priority of endpoints, topics, and standard endpoints
+    #[default]
+    Low,
+    /// Requests that must not wait behind low-priority work, e.g. an emergency stop
+    High,
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// ALIASES
+//////////////////////////////////////////////////////////////////////////////
+
+/// A legacy path of an endpoint, set with `[alias = "old/path"]` in
+/// [`define_dispatch!`][crate::define_dispatch]
+///
+/// Requests with the key of the alias are handled by the endpoint, and answered
+/// with the response key of the alias.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointAlias {
+    /// The request key of the alias
+    pub req_key: Key,
+    /// The response key of the alias
+    pub resp_key: Key,
+    /// The request key of the endpoint
+    pub endpoint_req_key: Key,
+    /// The response key of the endpoint
+    pub endpoint_resp_key: Key,
+}
+
+impl EndpointAlias {
+    /// The alias matching `key`, if any
+    pub fn find<'a>(aliases: &'a [EndpointAlias], key: &VarKey) -> Option<&'a EndpointAlias> {
+        aliases.iter().find(|a| *key == VarKey::Key8(a.req_key))
+    }
+
+    /// A copy of `hdr` with the request key of the endpoint, at the same length
+    pub fn resolve(&self, hdr: &VarHeader) -> VarHeader {
+        let mut key = VarKey::Key8(self.endpoint_req_key);
+        key.shrink_to(hdr.key.kind());
+        VarHeader {
+            key,
+            seq_no: hdr.seq_no,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// REPLY CACHE
+//////////////////////////////////////////////////////////////////////////////
+
+/// The number of replies kept by a [`ReplyCache`]
+pub const REPLY_CACHE_SLOTS: usize = 8;
+
+/// The largest reply frame kept by a [`ReplyCache`], larger replies are not cached
+pub const REPLY_CACHE_MAX_FRAME: usize = 64;
+
+/// A small cache of the most recent replies to `idempotent` endpoints
+///
+/// A `ReplyCache` is created by [`define_dispatch!`][crate::define_dispatch] for
+/// endpoints marked as idempotent, and is not typically used directly. Requests are
+/// identified by their key and sequence number: when a client retransmits a request
+/// it already sent, the cached reply is sent again instead of running the handler
+/// twice. The least recently used reply is dropped when the cache is full.
+///
+/// The cache is cleared by [`Dispatch::reset_connection()`], so a client connecting
+/// later, which may reuse the same sequence numbers, is never answered with the
+/// replies of a previous one.
+///
+/// The cache is only accessed by the dispatcher. If it is already in use, e.g. by
+/// [`ReplyCache::clear()`] from another task, replies are not cached.
+pub struct ReplyCache {
+    locked: portable_atomic::AtomicBool,
+    inner: UnsafeCell<ReplyCacheInner>,
+}
+
+// SAFETY: `inner` is only accessed while holding the `locked` flag
+unsafe impl Sync for ReplyCache {}
+
+struct ReplyCacheInner {
+    clock: u32,
+    slots: [ReplySlot; REPLY_CACHE_SLOTS],
+}
+
+struct ReplySlot {
+    req: Option<(Key, u32)>,
+    last_used: u32,
+    len: usize,
+    frame: [u8; REPLY_CACHE_MAX_FRAME],
+}
+
+impl ReplyCache {
+    /// Create a new, empty, cache
+    pub const fn new() -> Self {
+        const EMPTY: ReplySlot = ReplySlot {
+            req: None,
+            last_used: 0,
+            len: 0,
+            frame: [0; REPLY_CACHE_MAX_FRAME],
+        };
+        Self {
+            locked: portable_atomic::AtomicBool::new(false),
+            inner: UnsafeCell::new(ReplyCacheInner {
+                clock: 0,
+                slots: [EMPTY; REPLY_CACHE_SLOTS],
+            }),
+        }
+    }
+
+    /// Run `f` with exclusive access to the cache, if it is not already in use
+    fn with<R>(&self, f: impl FnOnce(&mut ReplyCacheInner) -> R) -> Option<R> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        // SAFETY: we hold the lock, so this is the only reference to `inner`
+        let res = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        Some(res)
+    }
+
+    /// Forget all cached replies
     ///
-    /// The caller may decide to wait until a connection is re-established, reset any
-    /// state, or immediately begin re-running.
-    pub async fn run(&mut self) -> ServerError<Tx, Rx> {
-        loop {
-            let Self {
-                tx,
-                rx,
-                buf,
-                dis: d,
-            } = self;
-            rx.wait_connection().await;
-            tx.tx.wait_connection().await;
-            let used = match rx.receive(buf).await {
-                Ok(u) => u,
-                Err(e) => {
-                    let kind = e.as_kind();
-                    match kind {
-                        WireRxErrorKind::ConnectionClosed => return ServerError::RxFatal(e),
-                        WireRxErrorKind::ReceivedMessageTooLarge => continue,
-                        WireRxErrorKind::Other => continue,
-                    }
-                }
-            };
-            let Some((hdr, body)) = VarHeader::take_from_slice(used) else {
-                // TODO: send a nak on badly formed messages? We don't have
-                // much to say because we don't have a key or seq no or anything
-                continue;
-            };
-            let fut = d.handle(tx, &hdr, body);
-            if let Err(e) = fut.await {
-                let kind = e.as_kind();
-                match kind {
-                    WireTxErrorKind::ConnectionClosed => return ServerError::TxFatal(e),
-                    WireTxErrorKind::Other => {}
-                    WireTxErrorKind::Timeout => return ServerError::TxFatal(e),
+    /// If the cache is in use, this waits until it is released, which it is as
+    /// soon as the reply being cached is copied.
+    pub fn clear(&self) {
+        let clear = |inner: &mut ReplyCacheInner| {
+            for slot in inner.slots.iter_mut() {
+                slot.req = None;
+            }
+        };
+        while self.with(clear).is_none() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Copy the cached reply to the request with key `key` into `buf`, if any
+    fn get(&self, key: Key, seq_no: VarSeq, buf: &mut [u8]) -> Option<usize> {
+        let req = Some((key, seq_no.into()));
+        self.with(|inner| {
+            inner.clock = inner.clock.wrapping_add(1);
+            let slot = inner.slots.iter_mut().find(|s| s.req == req)?;
+            slot.last_used = inner.clock;
+            let frame = &slot.frame[..slot.len];
+            buf.get_mut(..frame.len())?.copy_from_slice(frame);
+            Some(frame.len())
+        })
+        .flatten()
+    }
+
+    /// Cache the reply to the request with key `key`
+    fn store<T: Serialize + ?Sized>(&self, key: Key, hdr: VarHeader, resp: &T) {
+        let req = Some((key, hdr.seq_no.into()));
+        self.with(|inner| {
+            inner.clock = inner.clock.wrapping_add(1);
+            let clock = inner.clock;
+            let slots = &mut inner.slots;
+            let pos = slots
+                .iter()
+                .position(|s| s.req == req)
+                .or_else(|| slots.iter().position(|s| s.req.is_none()))
+                .unwrap_or_else(|| {
+                    // Replace the least recently used reply
+                    (0..REPLY_CACHE_SLOTS)
+                        .max_by_key(|&i| clock.wrapping_sub(slots[i].last_used))
+                        .unwrap_or(0)
+                });
+            let slot = &mut slots[pos];
+            match frame::serialize_frame(&mut slot.frame, hdr, resp) {
+                Ok(used) => {
+                    slot.len = used.len();
+                    slot.req = req;
+                    slot.last_used = clock;
                 }
+                // Too large to cache, so a retransmission runs the handler again
+                Err(_) => slot.req = None,
             }
+        });
+    }
+
+    /// Send the cached reply to the request with key `key` again, if any
+    ///
+    /// Used by `define_dispatch!` for requests to idempotent endpoints.
+    #[doc(hidden)]
+    pub async fn resend<Tx: WireTx>(
+        &self,
+        key: Key,
+        seq_no: VarSeq,
+        tx: &Sender<Tx>,
+    ) -> Option<Result<(), Tx::Error>> {
+        let mut buf = [0u8; REPLY_CACHE_MAX_FRAME];
+        let len = self.get(key, seq_no, &mut buf)?;
+        let frame = &buf[..len];
+        if let Some(tap) = tx.tap {
+            tap(FrameDirection::Outgoing, frame);
         }
+        Some(tx.tx.send_raw(frame).await)
     }
 }
 
-impl<Tx, Rx, Buf, D> Server<Tx, Rx, Buf, D>
-where
-    Tx: WireTx + Clone,
-    Rx: WireRx,
-    Buf: DerefMut<Target = [u8]>,
-    D: Dispatch<Tx = Tx>,
-{
-    /// Get a copy of the [`Sender`] to pass to tasks that need it
-    pub fn sender(&self) -> Sender<Tx> {
-        self.tx.clone()
+impl Default for ReplyCache {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 //////////////////////////////////////////////////////////////////////////////
-// DISPATCH TRAIT
+// METRICS
 //////////////////////////////////////////////////////////////////////////////
 
-/// The dispatch trait handles an incoming endpoint or topic message
+/// Counters of the requests handled by each of `N` endpoints
 ///
-/// The implementations of this trait are typically implemented by the
-/// [`define_dispatch!`][crate::define_dispatch] macro.
-pub trait Dispatch {
-    /// The [`WireTx`] impl used by this dispatcher
-    type Tx: WireTx;
+/// A `Metrics` table is created by [`define_dispatch!`][crate::define_dispatch] for
+/// the endpoint handlers of the dispatcher, and can be obtained with its `metrics()`
+/// method. Clients can also read the counters with
+/// [`GetMetricsEndpoint`][crate::standard_icd::GetMetricsEndpoint].
+///
+/// Errors are counted when they are sent by the dispatcher. `spawn` and `cancellable`
+/// handlers send their own replies, so only their requests and failures before
+/// spawning are counted. The dispatcher has no clock, so latency is not measured,
+/// but can be measured by an [`Interceptor`].
+///
+/// **Requires feature**: `metrics`. Without it, nothing is counted, and the table
+/// is empty.
+pub struct Metrics<const N: usize> {
+    #[cfg(feature = "metrics")]
+    keys: [Key; N],
+    #[cfg(feature = "metrics")]
+    counters: [EndpointCounters; N],
+}
 
-    /// The minimum key length required to avoid hash collisions
-    fn min_key_len(&self) -> VarKeyKind;
+#[cfg(feature = "metrics")]
+struct EndpointCounters {
+    requests: portable_atomic::AtomicU32,
+    deser_failed: portable_atomic::AtomicU32,
+    ser_failed: portable_atomic::AtomicU32,
+    timed_out: portable_atomic::AtomicU32,
+    errors: portable_atomic::AtomicU32,
+}
 
-    /// Handle a single incoming frame (endpoint or topic), and dispatch appropriately
-    async fn handle(
-        &mut self,
-        tx: &Sender<Self::Tx>,
-        hdr: &VarHeader,
-        body: &[u8],
-    ) -> Result<(), <Self::Tx as WireTx>::Error>;
+#[cfg(feature = "metrics")]
+impl EndpointCounters {
+    const fn new() -> Self {
+        Self {
+            requests: portable_atomic::AtomicU32::new(0),
+            deser_failed: portable_atomic::AtomicU32::new(0),
+            ser_failed: portable_atomic::AtomicU32::new(0),
+            timed_out: portable_atomic::AtomicU32::new(0),
+            errors: portable_atomic::AtomicU32::new(0),
+        }
+    }
+}
+
+impl<const N: usize> Metrics<N> {
+    /// Create a new table, with all counters of the endpoints with the given
+    /// request keys set to zero
+    #[allow(unused_variables)]
+    pub const fn new(keys: [Key; N]) -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            keys,
+            #[cfg(feature = "metrics")]
+            counters: [const { EndpointCounters::new() }; N],
+        }
+    }
+
+    /// Whether the counters are maintained, i.e. the `metrics` feature is enabled
+    pub const fn is_enabled(&self) -> bool {
+        cfg!(feature = "metrics")
+    }
+
+    /// The number of endpoints in the table
+    pub const fn len(&self) -> usize {
+        if cfg!(feature = "metrics") {
+            N
+        } else {
+            0
+        }
+    }
+
+    /// Whether the table has no endpoints
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A snapshot of the counters of the `idx`th endpoint
+    #[allow(unused_variables)]
+    pub fn stats(&self, idx: usize) -> Option<EndpointStats> {
+        #[cfg(feature = "metrics")]
+        {
+            let ctrs = self.counters.get(idx)?;
+            Some(EndpointStats {
+                key: self.keys[idx],
+                requests: ctrs.requests.load(Ordering::Relaxed),
+                deser_failed: ctrs.deser_failed.load(Ordering::Relaxed),
+                ser_failed: ctrs.ser_failed.load(Ordering::Relaxed),
+                timed_out: ctrs.timed_out.load(Ordering::Relaxed),
+                errors: ctrs.errors.load(Ordering::Relaxed),
+            })
+        }
+        #[cfg(not(feature = "metrics"))]
+        None
+    }
+
+    /// A snapshot of the counters of the endpoint with the given request key
+    pub fn get(&self, key: &Key) -> Option<EndpointStats> {
+        self.iter().find(|stats| stats.key == *key)
+    }
+
+    /// A snapshot of the counters of all endpoints
+    ///
+    /// Each endpoint is read separately, so the counters of different endpoints
+    /// may be read before and after a request was handled.
+    pub fn iter(&self) -> impl Iterator<Item = EndpointStats> + '_ {
+        (0..self.len()).filter_map(|idx| self.stats(idx))
+    }
+
+    /// Set all counters to zero
+    pub fn reset(&self) {
+        #[cfg(feature = "metrics")]
+        for ctrs in self.counters.iter() {
+            ctrs.requests.store(0, Ordering::Relaxed);
+            ctrs.deser_failed.store(0, Ordering::Relaxed);
+            ctrs.ser_failed.store(0, Ordering::Relaxed);
+            ctrs.timed_out.store(0, Ordering::Relaxed);
+            ctrs.errors.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a request to the endpoint with the given request key
+    #[doc(hidden)]
+    #[allow(unused_variables)]
+    pub fn request(&self, key: &Key) {
+        #[cfg(feature = "metrics")]
+        if let Some(ctrs) = self.counters(key) {
+            ctrs.requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count an error sent in reply to the endpoint with the given request key
+    #[doc(hidden)]
+    #[allow(unused_variables)]
+    pub fn error(&self, key: &Key, err: &WireError) {
+        #[cfg(feature = "metrics")]
+        if let Some(ctrs) = self.counters(key) {
+            let ctr = match err {
+                WireError::DeserFailed => &ctrs.deser_failed,
+                WireError::SerFailed => &ctrs.ser_failed,
+                WireError::Timeout => &ctrs.timed_out,
+                _ => &ctrs.errors,
+            };
+            ctr.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn counters(&self, key: &Key) -> Option<&EndpointCounters> {
+        let idx = self.keys.iter().position(|k| k == key)?;
+        Some(&self.counters[idx])
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -509,7 +2494,9 @@ pub trait Dispatch {
 
 /// A conversion trait for taking the Context and making a SpawnContext
 ///
-/// This is necessary if you use the `spawn` variant of `define_dispatch!`.
+/// This is necessary if you use the `spawn` variant of `define_dispatch!`. All
+/// spawned handlers are given the same [`Self::SpawnCtxt`] type, implement
+/// [`SpawnContextFor`] instead to give each handler its own type.
 pub trait SpawnContext {
     /// The spawn context type
     type SpawnCtxt: 'static;
@@ -517,6 +2504,45 @@ pub trait SpawnContext {
     fn spawn_ctxt(&mut self) -> Self::SpawnCtxt;
 }
 
+/// A conversion trait for taking the Context and making the SpawnContext of the
+/// endpoint or topic `E`
+///
+/// `define_dispatch!` calls this before spawning a `spawn` or `cancellable`
+/// handler, so that each handler can take only the owned resources it needs:
+///
+/// ```rust,ignore
+/// impl SpawnContextFor<FlashEndpoint> for Context {
+///     type SpawnCtxt = &'static Mutex<Flash>;
+///     fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt {
+///         self.flash
+///     }
+/// }
+///
+/// impl SpawnContextFor<LedEndpoint> for Context {
+///     type SpawnCtxt = LedSender;
+///     fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt {
+///         self.leds.sender()
+///     }
+/// }
+/// ```
+///
+/// This is implemented for all types implementing [`SpawnContext`], which gives
+/// every handler the same type, so a context implements one or the other.
+pub trait SpawnContextFor<E> {
+    /// The spawn context type of `E`
+    type SpawnCtxt: 'static;
+    /// A method to convert the regular context into [`Self::SpawnCtxt`]
+    fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt;
+}
+
+impl<C: SpawnContext, E> SpawnContextFor<E> for C {
+    type SpawnCtxt = C::SpawnCtxt;
+
+    fn spawn_ctxt_for(&mut self) -> Self::SpawnCtxt {
+        self.spawn_ctxt()
+    }
+}
+
 // Hilarious quadruply nested loop. Hope our lists are relatively small!
 macro_rules! keycheck {
     (
@@ -612,9 +2638,153 @@ pub const fn min_key_needed(lists: &[&[Key]]) -> usize {
     panic!("Collision requiring more than 8 bytes!");
 }
 
+/// Find the first pair of items with identical keys, if any
+///
+/// Returns the indexes of the two colliding items in `items`.
+pub const fn find_key_collision(items: &[(&str, Key)]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    while i < items.len() {
+        let a = u64::from_le_bytes(items[i].1.to_bytes());
+        let mut j = i + 1;
+        while j < items.len() {
+            let b = u64::from_le_bytes(items[j].1.to_bytes());
+            if a == b {
+                return Some((i, j));
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Panic at compile time if any two items have identical keys, naming both items
+///
+/// This is called by [`define_dispatch!`][crate::define_dispatch] with all endpoint
+/// and incoming topic keys, and is not intended to be called outside of const context.
+pub const fn assert_no_key_collisions(items: &[(&str, Key)]) {
+    const PREFIX: &str = "Key collision between `";
+    const MIDDLE: &str = "` and `";
+    const SUFFIX: &str = "`! Are they registered twice, or do they share a path and type?";
+    const fn push(buf: &mut [u8; 256], mut len: usize, s: &str) -> usize {
+        let s = s.as_bytes();
+        let mut i = 0;
+        while i < s.len() && len < buf.len() {
+            buf[len] = s[i];
+            len += 1;
+            i += 1;
+        }
+        len
+    }
+
+    let Some((i, j)) = find_key_collision(items) else {
+        return;
+    };
+    let mut buf = [0u8; 256];
+    let mut len = 0;
+    len = push(&mut buf, len, PREFIX);
+    len = push(&mut buf, len, items[i].0);
+    len = push(&mut buf, len, MIDDLE);
+    len = push(&mut buf, len, items[j].0);
+    len = push(&mut buf, len, SUFFIX);
+    let (msg, _) = buf.split_at(len);
+    match core::str::from_utf8(msg) {
+        Ok(msg) => panic!("{}", msg),
+        // We may have truncated in the middle of a character
+        Err(_) => panic!("Key collision between two endpoints or topics!"),
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{server::min_key_needed, Key};
+    use crate::{
+        header::VarSeq,
+        server::{
+            find_key_collision, min_key_needed, CancelMap, Subscriptions, SUBSCRIPTION_SLOTS,
+        },
+        Key,
+    };
+
+    #[test]
+    fn subscriptions() {
+        let subs = Subscriptions::new();
+        let key = |n: u8| unsafe { Key::from_bytes([n, 0, 0, 0, 0, 0, 0, 0xA5]) };
+        assert!(!subs.is_subscribed(key(0)));
+        subs.unsubscribe(key(0));
+        assert_eq!(subs.subscribers(key(0)), 0);
+
+        for n in 0..SUBSCRIPTION_SLOTS as u8 {
+            subs.subscribe(key(n));
+        }
+        assert!(subs.is_subscribed(key(0)));
+        assert!(!subs.is_subscribed(key(0xFF)));
+
+        // Untracked topics are not silenced
+        subs.subscribe(key(0xFE));
+        assert!(subs.is_subscribed(key(0xFF)));
+
+        subs.clear();
+        assert!(!subs.is_subscribed(key(0)));
+        assert!(!subs.is_subscribed(key(0xFF)));
+    }
+
+    #[test]
+    fn cancel_map() {
+        let map: &'static CancelMap<2> = Box::leak(Box::new(CancelMap::new()));
+        let a = map.register(VarSeq::Seq1(1)).unwrap();
+        let b = map.register(VarSeq::Seq1(2)).unwrap();
+        assert!(map.register(VarSeq::Seq1(3)).is_none());
+
+        assert!(map.cancel(VarSeq::Seq1(2)));
+        assert!(!a.is_cancelled());
+        assert!(b.is_cancelled());
+
+        // Slots are reused once their token is dropped
+        drop(b);
+        let c = map.register(VarSeq::Seq1(3)).unwrap();
+        assert!(!c.is_cancelled());
+        assert!(!map.cancel(VarSeq::Seq1(2)));
+    }
+
+    #[test]
+    fn cancel_map_concurrent_register() {
+        let map: &'static CancelMap<4> = Box::leak(Box::new(CancelMap::new()));
+        let threads: Vec<_> = (0..16)
+            .map(|n| std::thread::spawn(move || map.register(VarSeq::Seq4(n))))
+            .collect();
+        let tokens: Vec<_> = threads
+            .into_iter()
+            .filter_map(|t| t.join().unwrap())
+            .collect();
+
+        // Every slot is claimed by exactly one registration
+        assert_eq!(tokens.len(), 4);
+        for (i, a) in tokens.iter().enumerate() {
+            for b in &tokens[i + 1..] {
+                assert!(!core::ptr::eq(a.slot, b.slot));
+            }
+        }
+    }
+
+    #[test]
+    fn collisions() {
+        let a = unsafe { Key::from_bytes([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]) };
+        let b = unsafe { Key::from_bytes([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]) };
+        assert_eq!(find_key_collision(&[]), None);
+        assert_eq!(find_key_collision(&[("a", a), ("b", b)]), None);
+        assert_eq!(
+            find_key_collision(&[("a", a), ("b", b), ("c", a)]),
+            Some((0, 2))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Key collision between `a` and `c`")]
+    fn collision_message() {
+        let a = unsafe { Key::from_bytes([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]) };
+        let b = unsafe { Key::from_bytes([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]) };
+        super::assert_no_key_collisions(&[("a", a), ("b", b), ("c", a)]);
+    }
 
     #[test]
     fn min_test_1() {
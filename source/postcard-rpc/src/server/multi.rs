@@ -0,0 +1,293 @@
+//! One dispatcher serving several transports
+//!
+//! A device may expose the same endpoints on more than one transport, e.g. USB
+//! for configuration tools, and a UART for a companion MCU. Instead of running
+//! one dispatcher per transport, each with its own context, a single dispatcher
+//! can be shared by one [`Server`](crate::server::Server) per transport:
+//!
+//! * The dispatcher is made with an [`EitherTx`] of both transports as its
+//!   `tx_impl`, and wrapped in a [`SharedDispatcher`]
+//! * Each server sends with its own variant of [`EitherTx`], and dispatches with
+//!   a [`SharedDispatcher::handle()`]
+//!
+//! Every request is handled with the [`Sender`] of the
+//! server that received it, so replies, including those of `spawn` handlers, are
+//! sent back on the transport the request came from. Frames are dispatched one at
+//! a time, in the order the servers receive them.
+//!
+//! Capabilities are granted per transport: each [`SharedHandle`] keeps those
+//! granted on its connection, and restores them in the dispatcher before handling
+//! a frame, so a client on one transport can't use the grants of another. They are
+//! revoked when the connection of the transport is closed.
+//!
+//! Clients on different transports may use the same sequence numbers, so the
+//! replies cached for idempotent endpoints are cleared whenever a frame arrives on
+//! another transport than the previous one, or after a connection is closed. A
+//! retransmitted request is then handled again, as if its reply was not cached.
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::multi::{EitherTx, SharedDispatcher};
+//!
+//! type AppTx = EitherTx<UsbTx, UartTx>;
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     tx_impl: AppTx;
+//!     spawn_impl: EmbassyWireSpawn;
+//!     // ...
+//! }
+//!
+//! static SHARED: StaticCell<SharedDispatcher<CriticalSectionRawMutex, MyApp>> = StaticCell::new();
+//!
+//! let shared = SHARED.init(SharedDispatcher::new(MyApp::new(context, spawner.into())));
+//! let kkind = shared.min_key_len();
+//! let mut usb = Server::new(EitherTx::A(usb_tx), usb_rx, usb_buf, shared.handle(), kkind);
+//! let mut uart = Server::new(EitherTx::B(uart_tx), uart_rx, uart_buf, shared.handle(), kkind);
+//! join(usb.run(), uart.run()).await;
+//! ```
+//!
+//! More than two transports can be served by nesting, e.g. with
+//! `EitherTx<UsbTx, EitherTx<UartTx, TcpTx>>`.
+//!
+//! **Requires feature**: `multi-transport`
+
+use core::fmt::Arguments;
+
+use embassy_sync_0_7::{
+    blocking_mutex::raw::RawMutex,
+    mutex::{Mutex, MutexGuard},
+};
+use portable_atomic::{AtomicUsize, Ordering};
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKeyKind},
+    server::{AsWireTxErrorKind, Dispatch, Sender, WireTx, WireTxErrorKind},
+};
+
+/// One of two transports, the [`WireTx`] of a dispatcher shared between them
+#[derive(Debug, Clone)]
+pub enum EitherTx<A, B> {
+    /// The first transport
+    A(A),
+    /// The second transport
+    B(B),
+}
+
+/// The error of an [`EitherTx`], from the transport that failed
+#[derive(Debug, Clone, Copy)]
+pub enum EitherError<A, B> {
+    /// The first transport failed
+    A(A),
+    /// The second transport failed
+    B(B),
+}
+
+/// A dispatcher shared by the servers of several transports
+///
+/// See the [module docs](self) for details.
+pub struct SharedDispatcher<M: RawMutex, D> {
+    dispatch: Mutex<M, D>,
+    kkind: VarKeyKind,
+    /// The id of the next handle
+    next_id: AtomicUsize,
+    /// The id of the handle the replies in the cache belong to, if any
+    replies_of: AtomicUsize,
+}
+
+/// A handle to a [`SharedDispatcher`], passed to the [`Server`](crate::server::Server)
+/// of one transport
+pub struct SharedHandle<'a, M: RawMutex, D> {
+    shared: &'a SharedDispatcher<M, D>,
+    id: usize,
+    caps: u32,
+}
+
+/// The `replies_of` a [`SharedDispatcher`] whose cached replies belong to no handle
+const NO_HANDLE: usize = 0;
+
+// ----- IMPLS -----
+
+// impl EitherTx
+
+impl<A: WireTx, B: WireTx> WireTx for EitherTx<A, B> {
+    type Error = EitherError<A::Error, B::Error>;
+
+    async fn wait_connection(&self) {
+        match self {
+            EitherTx::A(tx) => tx.wait_connection().await,
+            EitherTx::B(tx) => tx.wait_connection().await,
+        }
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        match self {
+            EitherTx::A(tx) => tx.send(hdr, msg).await.map_err(EitherError::A),
+            EitherTx::B(tx) => tx.send(hdr, msg).await.map_err(EitherError::B),
+        }
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            EitherTx::A(tx) => tx.send_raw(buf).await.map_err(EitherError::A),
+            EitherTx::B(tx) => tx.send_raw(buf).await.map_err(EitherError::B),
+        }
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        match self {
+            EitherTx::A(tx) => tx.send_log_str(kkind, s).await.map_err(EitherError::A),
+            EitherTx::B(tx) => tx.send_log_str(kkind, s).await.map_err(EitherError::B),
+        }
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        a: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        match self {
+            EitherTx::A(tx) => tx.send_log_fmt(kkind, a).await.map_err(EitherError::A),
+            EitherTx::B(tx) => tx.send_log_fmt(kkind, a).await.map_err(EitherError::B),
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        match self {
+            EitherTx::A(tx) => tx.flush().await.map_err(EitherError::A),
+            EitherTx::B(tx) => tx.flush().await.map_err(EitherError::B),
+        }
+    }
+}
+
+// impl EitherError
+
+impl<A: AsWireTxErrorKind, B: AsWireTxErrorKind> AsWireTxErrorKind for EitherError<A, B> {
+    fn as_kind(&self) -> WireTxErrorKind {
+        match self {
+            EitherError::A(e) => e.as_kind(),
+            EitherError::B(e) => e.as_kind(),
+        }
+    }
+}
+
+// impl SharedDispatcher
+
+impl<M: RawMutex, D: Dispatch> SharedDispatcher<M, D> {
+    /// Share `dispatch` between several transports
+    pub fn new(dispatch: D) -> Self {
+        let kkind = dispatch.min_key_len();
+        Self {
+            dispatch: Mutex::new(dispatch),
+            kkind,
+            next_id: AtomicUsize::new(NO_HANDLE + 1),
+            replies_of: AtomicUsize::new(NO_HANDLE),
+        }
+    }
+
+    /// The minimum key length of the dispatcher, to create the servers with
+    pub fn min_key_len(&self) -> VarKeyKind {
+        self.kkind
+    }
+
+    /// A handle to pass to the [`Server`](crate::server::Server) of one transport
+    pub fn handle(&self) -> SharedHandle<'_, M, D> {
+        SharedHandle {
+            shared: self,
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            caps: 0,
+        }
+    }
+
+    /// Access the dispatcher, e.g. its context, between frames
+    pub async fn lock(&self) -> MutexGuard<'_, M, D> {
+        self.dispatch.lock().await
+    }
+}
+
+// impl SharedHandle
+
+impl<M: RawMutex, D> Clone for SharedHandle<'_, M, D> {
+    /// Clones the handle, without the capabilities granted on its connection
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared,
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            caps: 0,
+        }
+    }
+}
+
+impl<M: RawMutex, D: Dispatch> SharedHandle<'_, M, D> {
+    /// Restore the state of this handle's connection in `dispatch`, before handling
+    /// one of its frames
+    fn restore(&self, dispatch: &mut D) {
+        // Only accessed with the dispatcher locked, so a load and store is enough
+        if self.shared.replies_of.load(Ordering::Relaxed) != self.id {
+            dispatch.clear_replies();
+            self.shared.replies_of.store(self.id, Ordering::Relaxed);
+        }
+        dispatch.set_granted_caps(self.caps);
+    }
+}
+
+impl<M: RawMutex, D: Dispatch> Dispatch for SharedHandle<'_, M, D> {
+    type Tx = D::Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.shared.kkind
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let mut dispatch = self.shared.dispatch.lock().await;
+        self.restore(&mut dispatch);
+        let res = dispatch.handle(tx, hdr, body).await;
+        self.caps = dispatch.granted_caps();
+        res
+    }
+
+    async fn handle_with_deadline(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        deadline_ms: u32,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let mut dispatch = self.shared.dispatch.lock().await;
+        self.restore(&mut dispatch);
+        let res = dispatch
+            .handle_with_deadline(tx, hdr, deadline_ms, body)
+            .await;
+        self.caps = dispatch.granted_caps();
+        res
+    }
+
+    fn granted_caps(&self) -> u32 {
+        self.caps
+    }
+
+    fn set_granted_caps(&mut self, caps: u32) {
+        self.caps = caps;
+    }
+
+    fn clear_replies(&mut self) {
+        // The dispatcher may be in use by another transport, so its cache is
+        // cleared before the next frame instead
+        let _ = self.shared.replies_of.compare_exchange(
+            self.id,
+            NO_HANDLE,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
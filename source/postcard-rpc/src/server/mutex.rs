@@ -0,0 +1,126 @@
+//! Async mutexes guarding the state of transports
+//!
+//! The [`WireTx`](crate::server::WireTx) of a transport is shared by all tasks
+//! sending replies and topic messages, so it locks its connection and buffers
+//! while writing a frame. Transports taking a [`MutexKind`] parameter, like the
+//! `embedded_io_async_v0_6` impl, can use the mutex of any RTOS or executor for
+//! this, instead of `embassy-sync`.
+//!
+//! With the `embassy-sync-0_7` feature, enabled by default, every `embassy_sync`
+//! `RawMutex` is a [`MutexKind`], creating `embassy_sync::mutex::Mutex`es, so e.g.
+//! `CriticalSectionRawMutex` may be used as before.
+//!
+//! Without it, the transports don't depend on `embassy-sync`. Note that the
+//! `embedded_io_async_v0_6` impl still spawns handlers with `embassy-executor`.
+//!
+//! Other mutexes implement [`AsyncMutex`], and provide a [`MutexKind`] creating
+//! them for any type, e.g. for an RTOS:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::mutex::{AsyncMutex, MutexKind};
+//!
+//! pub struct RtosMutex<T> {
+//!     sem: rtos::Semaphore,
+//!     data: UnsafeCell<T>,
+//! }
+//!
+//! impl<T> AsyncMutex for RtosMutex<T> {
+//!     type Data = T;
+//!     type Guard<'a> = RtosGuard<'a, T> where T: 'a;
+//!
+//!     async fn lock(&self) -> RtosGuard<'_, T> {
+//!         self.sem.take_async().await;
+//!         RtosGuard { mutex: self }
+//!     }
+//! }
+//!
+//! pub struct Rtos;
+//!
+//! impl MutexKind for Rtos {
+//!     type Mutex<T> = RtosMutex<T>;
+//!
+//!     fn new_mutex<T>(value: T) -> RtosMutex<T> {
+//!         RtosMutex { sem: rtos::Semaphore::new_binary(), data: UnsafeCell::new(value) }
+//!     }
+//! }
+//!
+//! pub type AppTx = EioWireTx<Rtos, Uart>;
+//! ```
+
+use core::ops::DerefMut;
+
+/// An async mutex, serializing access to its data between tasks
+pub trait AsyncMutex {
+    /// The data guarded by the mutex
+    type Data;
+
+    /// Access to the data, unlocking the mutex when dropped
+    type Guard<'a>: DerefMut<Target = Self::Data>
+    where
+        Self: 'a;
+
+    /// Lock the mutex, waiting until it is unlocked by its current holder
+    async fn lock(&self) -> Self::Guard<'_>;
+}
+
+/// A kind of [`AsyncMutex`], able to guard data of any type
+///
+/// Transports are generic over the kind, as their state guarded by the mutex is
+/// private.
+pub trait MutexKind: 'static {
+    /// The mutex of this kind guarding a `T`
+    type Mutex<T>: AsyncMutex<Data = T>;
+
+    /// Create an unlocked mutex guarding `value`
+    fn new_mutex<T>(value: T) -> Self::Mutex<T>;
+}
+
+#[cfg(feature = "embassy-sync-0_7")]
+mod embassy_sync_impls {
+    use embassy_sync_0_7::{
+        blocking_mutex::raw::RawMutex,
+        mutex::{Mutex, MutexGuard},
+    };
+
+    use super::{AsyncMutex, MutexKind};
+
+    impl<M: RawMutex, T> AsyncMutex for Mutex<M, T> {
+        type Data = T;
+        type Guard<'a>
+            = MutexGuard<'a, M, T>
+        where
+            Self: 'a;
+
+        async fn lock(&self) -> MutexGuard<'_, M, T> {
+            Mutex::lock(self).await
+        }
+    }
+
+    impl<M: RawMutex + 'static> MutexKind for M {
+        type Mutex<T> = Mutex<M, T>;
+
+        fn new_mutex<T>(value: T) -> Mutex<M, T> {
+            Mutex::new(value)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "embassy-sync-0_7", feature = "use-std"))]
+mod test {
+    use embassy_sync_0_7::blocking_mutex::raw::NoopRawMutex;
+
+    use super::{AsyncMutex, MutexKind};
+
+    /// Append to a vec behind a mutex of any kind
+    async fn push<K: MutexKind>(mutex: &K::Mutex<Vec<u8>>, val: u8) {
+        mutex.lock().await.push(val);
+    }
+
+    #[tokio::test]
+    async fn embassy_mutex_kind() {
+        let mutex = NoopRawMutex::new_mutex(vec![]);
+        push::<NoopRawMutex>(&mutex, 1).await;
+        push::<NoopRawMutex>(&mutex, 2).await;
+        assert_eq!(*AsyncMutex::lock(&mutex).await, [1, 2]);
+    }
+}
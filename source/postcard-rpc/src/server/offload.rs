@@ -0,0 +1,352 @@
+//! Running blocking handlers on a lower priority executor
+//!
+//! `blocking` handlers of [`define_dispatch!`][crate::define_dispatch] run
+//! inline on the executor of the server, so a handler that computes for a long
+//! time stalls every other task on that executor, including the ones driving
+//! the transport. `offload` handlers are instead passed to the
+//! [`WireOffload`] impl of the dispatcher, and the dispatcher waits for them to
+//! return without blocking its executor.
+//!
+//! With tokio, [`WireOffload`] is implemented by the spawn types of the server
+//! impls, and runs handlers with `spawn_blocking`. On embassy, there are no
+//! threads, so handlers are passed to an [`Offloader`] instead, and run by a
+//! task on a separate executor with a lower priority than the one of the
+//! server. While a handler runs, the higher priority executor preempts it as
+//! soon as one of its tasks is woken, so the server keeps receiving and
+//! sending frames.
+//!
+//! The usual setup runs the server on an `InterruptExecutor`, and the
+//! [`Offloader`] in thread mode, where it may be preempted at any time:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::offload::{OffloadSpawn, Offloader};
+//!
+//! // Closures and results of up to 64 bytes
+//! static OFFLOADER: Offloader<CriticalSectionRawMutex, 64> = Offloader::new();
+//! static EXECUTOR_HIGH: InterruptExecutor = InterruptExecutor::new();
+//!
+//! #[interrupt]
+//! unsafe fn SWI_IRQ_1() {
+//!     EXECUTOR_HIGH.on_interrupt()
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn server_task(mut server: AppServer) {
+//!     server.run().await;
+//! }
+//!
+//! #[embassy_executor::main]
+//! async fn main(spawner: Spawner) {
+//!     interrupt::SWI_IRQ_1.set_priority(Priority::P2);
+//!     let high_spawner = EXECUTOR_HIGH.start(interrupt::SWI_IRQ_1);
+//!
+//!     let spawn = OffloadSpawn::new(EmbassyWireSpawn::from(high_spawner), &OFFLOADER);
+//!     let dispatcher = MyApp::new(context, spawn);
+//!     let server = Server::new(tx_impl, rx_impl, buf, dispatcher, vkk);
+//!     high_spawner.must_spawn(server_task(server));
+//!
+//!     // Runs offloaded handlers until the device is reset
+//!     OFFLOADER.run().await;
+//! }
+//! ```
+//!
+//! Anything shared by the two executors, like the [`Offloader`] itself or the
+//! context of the handlers, must be safe to use from interrupts, e.g. by using
+//! a `CriticalSectionRawMutex`. Preempting code on the same executor is not
+//! possible, so running the [`Offloader`] on the executor of the server works,
+//! but only moves the stall from the handler to the worker.
+//!
+//! An [`Offloader`] runs one handler at a time. A dispatcher waits for its
+//! offloaded handler to return before handling the next frame, but several
+//! dispatchers may share one [`Offloader`], e.g. the workers of a
+//! `server::pool`. Their requests to `offload` endpoints received
+//! while another handler runs are answered with [`WireError::Busy`].
+//! Handlers are moved into the [`Offloader`] together with their spawn context,
+//! header, and request, so all of these must fit in `SIZE` bytes, as must the
+//! response. This is checked at compile time.
+//!
+//! [`WireError::Busy`]: crate::standard_icd::WireError::Busy
+//!
+//! **Requires feature**: `offload`
+
+use core::{
+    cell::{RefCell, UnsafeCell},
+    future::poll_fn,
+    mem::{align_of, size_of, MaybeUninit},
+    ptr,
+    task::Poll,
+};
+
+use embassy_sync_0_7::{
+    blocking_mutex::{raw::RawMutex, Mutex},
+    waitqueue::WakerRegistration,
+};
+
+use crate::server::{OffloadError, WireClock, WireOffload, WireSpawn, WireTimer};
+
+/// The alignment of the job buffer, and the largest alignment of closures and
+/// results that can be offloaded
+const ALIGN: usize = 8;
+
+#[repr(C, align(8))]
+struct JobBuf<const SIZE: usize>([MaybeUninit<u8>; SIZE]);
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// No job, the buffer is empty
+    Idle,
+    /// The buffer holds a closure waiting for the worker
+    Queued,
+    /// The worker runs the closure
+    Running,
+    /// The buffer holds the result of the closure
+    Done,
+    /// The worker runs a closure whose caller stopped waiting
+    Abandoned,
+}
+
+struct Inner {
+    state: State,
+    /// Runs the closure in the buffer, replacing it with its result
+    run: unsafe fn(*mut u8),
+    /// Drops the closure in the buffer
+    drop_job: unsafe fn(*mut u8),
+    /// Drops the result in the buffer
+    drop_result: unsafe fn(*mut u8),
+    caller: WakerRegistration,
+    worker: WakerRegistration,
+}
+
+/// A worker running offloaded closures of up to `SIZE` bytes, one at a time
+///
+/// See the [module docs](self) for details.
+pub struct Offloader<M: RawMutex, const SIZE: usize> {
+    inner: Mutex<M, RefCell<Inner>>,
+    buf: UnsafeCell<JobBuf<SIZE>>,
+}
+
+// SAFETY: The buffer is only accessed by the caller while no job is queued or
+// running, and by the worker while a job runs, as tracked by `state`. It only
+// ever holds closures and results that are `Send`.
+unsafe impl<M: RawMutex + Sync, const SIZE: usize> Sync for Offloader<M, SIZE> {}
+
+unsafe fn noop(_buf: *mut u8) {}
+
+unsafe fn run_as<F: FnOnce() -> R, R>(buf: *mut u8) {
+    let f = ptr::read(buf.cast::<F>());
+    ptr::write(buf.cast::<R>(), f());
+}
+
+unsafe fn drop_as<T>(buf: *mut u8) {
+    ptr::drop_in_place(buf.cast::<T>());
+}
+
+impl<M: RawMutex, const SIZE: usize> Offloader<M, SIZE> {
+    /// Create a new worker, with no job
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                state: State::Idle,
+                run: noop,
+                drop_job: noop,
+                drop_result: noop,
+                caller: WakerRegistration::new(),
+                worker: WakerRegistration::new(),
+            })),
+            buf: UnsafeCell::new(JobBuf([MaybeUninit::uninit(); SIZE])),
+        }
+    }
+
+    /// Whether a job is queued or running
+    pub fn is_busy(&self) -> bool {
+        self.inner.lock(|inner| inner.borrow().state != State::Idle)
+    }
+
+    /// Pass `f` to the worker, and wait for its result
+    ///
+    /// Returns [`OffloadError::Busy`] immediately if another job is queued or
+    /// running. Dropping the returned future before `f` is started drops `f`,
+    /// otherwise `f` runs to completion, and its result is dropped.
+    pub async fn offload<F, R>(&self, f: F) -> Result<R, OffloadError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        const {
+            assert!(
+                size_of::<F>() <= SIZE,
+                "offloaded closure is larger than SIZE"
+            );
+            assert!(
+                size_of::<R>() <= SIZE,
+                "offloaded result is larger than SIZE"
+            );
+            assert!(align_of::<F>() <= ALIGN, "offloaded closure is overaligned");
+            assert!(align_of::<R>() <= ALIGN, "offloaded result is overaligned");
+        }
+
+        let claimed = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.state != State::Idle {
+                return false;
+            }
+            // SAFETY: No job is queued or running, so the worker does not access
+            // the buffer, and the size and alignment were checked above
+            unsafe { ptr::write(self.buf.get().cast::<F>(), f) };
+            inner.run = run_as::<F, R>;
+            inner.drop_job = drop_as::<F>;
+            inner.drop_result = drop_as::<R>;
+            inner.state = State::Queued;
+            inner.worker.wake();
+            true
+        });
+        if !claimed {
+            return Err(OffloadError::Busy);
+        }
+        let guard = JobGuard { offloader: self };
+
+        let res = poll_fn(|cx| {
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+                if inner.state != State::Done {
+                    inner.caller.register(cx.waker());
+                    return Poll::Pending;
+                }
+                inner.state = State::Idle;
+                // SAFETY: The worker is done, and the buffer holds the result
+                Poll::Ready(unsafe { ptr::read(self.buf.get().cast::<R>()) })
+            })
+        })
+        .await;
+        core::mem::forget(guard);
+        Ok(res)
+    }
+
+    /// Run offloaded jobs, forever
+    ///
+    /// This should be run by a task on a lower priority executor than the one of
+    /// the server, see the [module docs](self).
+    pub async fn run(&self) -> ! {
+        loop {
+            let run = poll_fn(|cx| {
+                self.inner.lock(|inner| {
+                    let mut inner = inner.borrow_mut();
+                    if inner.state != State::Queued {
+                        inner.worker.register(cx.waker());
+                        return Poll::Pending;
+                    }
+                    inner.state = State::Running;
+                    Poll::Ready(inner.run)
+                })
+            })
+            .await;
+
+            // SAFETY: The job is running, so the caller does not access the buffer
+            unsafe { run(self.buf.get().cast()) };
+
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+                if inner.state == State::Abandoned {
+                    // SAFETY: Nobody will read the result
+                    unsafe { (inner.drop_result)(self.buf.get().cast()) };
+                    inner.state = State::Idle;
+                } else {
+                    inner.state = State::Done;
+                    inner.caller.wake();
+                }
+            });
+        }
+    }
+}
+
+impl<M: RawMutex, const SIZE: usize> Default for Offloader<M, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cleans up the job of a caller that stopped waiting for it
+struct JobGuard<'a, M: RawMutex, const SIZE: usize> {
+    offloader: &'a Offloader<M, SIZE>,
+}
+
+impl<M: RawMutex, const SIZE: usize> Drop for JobGuard<'_, M, SIZE> {
+    fn drop(&mut self) {
+        let buf = self.offloader.buf.get().cast();
+        self.offloader.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            match inner.state {
+                // SAFETY: The worker has not started the job, or is done with it
+                State::Queued => unsafe { (inner.drop_job)(buf) },
+                State::Done => unsafe { (inner.drop_result)(buf) },
+                State::Running => {
+                    inner.state = State::Abandoned;
+                    return;
+                }
+                State::Idle | State::Abandoned => return,
+            }
+            inner.state = State::Idle;
+        });
+    }
+}
+
+/// A [`WireSpawn`] impl passing `offload` handlers to an [`Offloader`], and
+/// everything else to the wrapped impl
+pub struct OffloadSpawn<S, M: RawMutex + 'static, const SIZE: usize> {
+    spawn: S,
+    offloader: &'static Offloader<M, SIZE>,
+}
+
+impl<S, M: RawMutex + 'static, const SIZE: usize> OffloadSpawn<S, M, SIZE> {
+    /// Wrap `spawn`, running offloaded handlers with `offloader`
+    pub fn new(spawn: S, offloader: &'static Offloader<M, SIZE>) -> Self {
+        Self { spawn, offloader }
+    }
+}
+
+impl<S: Clone, M: RawMutex + 'static, const SIZE: usize> Clone for OffloadSpawn<S, M, SIZE> {
+    fn clone(&self) -> Self {
+        Self {
+            spawn: self.spawn.clone(),
+            offloader: self.offloader,
+        }
+    }
+}
+
+impl<S: WireSpawn, M: RawMutex + 'static, const SIZE: usize> WireSpawn
+    for OffloadSpawn<S, M, SIZE>
+{
+    type Error = S::Error;
+
+    type Info = S::Info;
+
+    fn info(&self) -> &Self::Info {
+        self.spawn.info()
+    }
+}
+
+impl<S: WireTimer, M: RawMutex + 'static, const SIZE: usize> WireTimer
+    for OffloadSpawn<S, M, SIZE>
+{
+    async fn delay_ms(&self, ms: u32) {
+        self.spawn.delay_ms(ms).await
+    }
+}
+
+impl<S: WireClock, M: RawMutex + 'static, const SIZE: usize> WireClock
+    for OffloadSpawn<S, M, SIZE>
+{
+    fn now_ms() -> u64 {
+        S::now_ms()
+    }
+}
+
+impl<S, M: RawMutex + 'static, const SIZE: usize> WireOffload for OffloadSpawn<S, M, SIZE> {
+    async fn offload<F, R>(&self, f: F) -> Result<R, OffloadError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.offloader.offload(f).await
+    }
+}
@@ -0,0 +1,523 @@
+//! A bounded pool of workers handling requests concurrently
+//!
+//! By default, [`Server::run()`] passes each frame to its dispatcher and waits for
+//! the handler to return before receiving the next frame, so a slow `async` handler
+//! delays all later requests. With a worker pool, the server instead copies each
+//! frame into a [`PoolQueue`] with a [`PoolFrontend`], and immediately receives the
+//! next frame, while a fixed number of [`PoolWorker`]s take frames from the queue
+//! and handle them with their own dispatcher. Replies are sent by the workers, and
+//! are matched to their requests by sequence number as usual, so they may be sent
+//! in a different order than the requests were received.
+//!
+//! Each worker owns a separate instance of the dispatcher, e.g. one made with
+//! [`define_dispatch!`][crate::define_dispatch], so each has its own context. The
+//! cancellation, shutdown, reply cache, and metrics of a `define_dispatch!`
+//! dispatcher are shared by all instances, and work as without a pool.
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::pool::{PoolFrontend, PoolQueue, PoolWorker};
+//!
+//! // Up to 4 frames of up to 128 bytes waiting for a worker
+//! static QUEUE: PoolQueue<CriticalSectionRawMutex, 128, 4> = PoolQueue::new();
+//!
+//! #[embassy_executor::task(pool_size = 2)]
+//! async fn worker(mut worker: PoolWorker<CriticalSectionRawMutex, AppTx, MyApp, 128, 4>) {
+//!     worker.run().await;
+//! }
+//!
+//! let frontend = PoolFrontend::new(&QUEUE, VarKeyKind::Key8);
+//! let mut server = Server::new(tx_impl, rx_impl, buf, frontend, VarKeyKind::Key8);
+//! for _ in 0..2 {
+//!     let dispatcher = MyApp::new(Context::new(), spawner.into());
+//!     spawner.must_spawn(worker(PoolWorker::new(&QUEUE, dispatcher, server.sender())));
+//! }
+//! server.run().await;
+//! ```
+//!
+//! The frontend must be created with the `min_key_len()` of the worker
+//! dispatchers, as the server uses it for all replies.
+//!
+//! ## Priorities
+//!
+//! Workers handle frames in the order they were queued, so a request to stop a
+//! motor may wait behind a queue of telemetry requests. Endpoints tagged with
+//! `[priority = high]` in [`define_dispatch!`][crate::define_dispatch] can instead
+//! be passed to a separate queue with a [`PriorityFrontend`]. Its workers are
+//! spawned on a higher priority executor, e.g. an embassy `InterruptExecutor`, so
+//! they preempt the workers handling low-priority requests as soon as a frame
+//! arrives, instead of waiting for them to yield:
+//!
+//! ```rust,ignore
+//! static HIGH: PoolQueue<CriticalSectionRawMutex, 128, 2> = PoolQueue::new();
+//! static LOW: PoolQueue<CriticalSectionRawMutex, 128, 4> = PoolQueue::new();
+//! static EXECUTOR_HIGH: InterruptExecutor = InterruptExecutor::new();
+//!
+//! #[interrupt]
+//! unsafe fn SWI_IRQ_1() {
+//!     EXECUTOR_HIGH.on_interrupt()
+//! }
+//!
+//! let frontend = PriorityFrontend::new(&HIGH, &LOW, VarKeyKind::Key8, MyApp::priority_of);
+//! let mut server = Server::new(tx_impl, rx_impl, buf, frontend, VarKeyKind::Key8);
+//!
+//! interrupt::SWI_IRQ_1.set_priority(Priority::P2);
+//! let high_spawner = EXECUTOR_HIGH.start(interrupt::SWI_IRQ_1);
+//! let dispatcher = MyApp::new(Context::new(), spawner.into());
+//! high_spawner.must_spawn(high_worker(PoolWorker::new(&HIGH, dispatcher, server.sender())));
+//! for _ in 0..2 {
+//!     let dispatcher = MyApp::new(Context::new(), spawner.into());
+//!     spawner.must_spawn(low_worker(PoolWorker::new(&LOW, dispatcher, server.sender())));
+//! }
+//! server.run().await;
+//! ```
+//!
+//! The queues are shared between executors, so must use a `RawMutex` that is
+//! safe to use from interrupts, like `CriticalSectionRawMutex`. The server keeps
+//! receiving while the low-priority queue is full: low-priority requests that
+//! don't fit are answered with [`WireError::Busy`], rather than holding up a
+//! high-priority request received after them. High-priority requests wait for
+//! space in their queue as usual.
+//!
+//! ## Ordered by priority
+//!
+//! Alternatively, frames can be buffered in a [`PriorityQueue`] with a number of
+//! priority levels, by a [`PriorityQueueFrontend`] classifying them by key. Its
+//! [`PriorityQueueWorker`]s always take a frame of the highest level waiting, so
+//! a request to stop a motor is handled before a large configuration read that
+//! arrived just before it, and frames of the same level are handled in the order
+//! they were received. With a single worker, requests are still handled one at
+//! a time, like with [`Server::run()`][crate::server::Server::run] alone:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::pool::{PriorityQueue, PriorityQueueFrontend, PriorityQueueWorker};
+//!
+//! // Two levels of up to 4 frames of up to 128 bytes
+//! static QUEUE: PriorityQueue<CriticalSectionRawMutex, 128, 4, 2> = PriorityQueue::new();
+//!
+//! // Keys not in the table have the lowest level, 0
+//! static LEVELS: &[(Key, u8)] = &[(StopEndpoint::REQ_KEY, 1)];
+//!
+//! let frontend = PriorityQueueFrontend::new(&QUEUE, VarKeyKind::Key8, LEVELS);
+//! let mut server = Server::new(tx_impl, rx_impl, buf, frontend, VarKeyKind::Key8);
+//! let dispatcher = MyApp::new(Context::new(), spawner.into());
+//! spawner.must_spawn(worker(PriorityQueueWorker::new(&QUEUE, dispatcher, server.sender())));
+//! server.run().await;
+//! ```
+//!
+//! **Requires feature**: `worker-pool`
+
+use core::{future::poll_fn, marker::PhantomData, task::Poll};
+
+use embassy_sync_0_7::{blocking_mutex::raw::RawMutex, channel::Channel};
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind},
+    server::{Dispatch, Priority, Sender, WireTx},
+    standard_icd::WireError,
+    Key,
+};
+
+/// A queue of up to `DEPTH` frames, with bodies of up to `BODY` bytes, waiting
+/// for a [`PoolWorker`]
+pub struct PoolQueue<M: RawMutex, const BODY: usize, const DEPTH: usize> {
+    frames: Channel<M, PoolFrame<BODY>, DEPTH>,
+}
+
+struct PoolFrame<const BODY: usize> {
+    hdr: VarHeader,
+    body: heapless::Vec<u8, BODY>,
+}
+
+impl<M: RawMutex, const BODY: usize, const DEPTH: usize> PoolQueue<M, BODY, DEPTH> {
+    /// Create a new, empty, queue
+    pub const fn new() -> Self {
+        Self {
+            frames: Channel::new(),
+        }
+    }
+
+    /// The number of frames waiting for a worker
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frames are waiting for a worker
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Copy a frame into a queue entry, or reply with an error if the body is too large
+    async fn frame<Tx: WireTx>(
+        tx: &Sender<Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<Option<PoolFrame<BODY>>, Tx::Error> {
+        let Ok(body) = heapless::Vec::from_slice(body) else {
+            let max = u32::try_from(BODY).unwrap_or(u32::MAX);
+            tx.dispatch_error(hdr, WireError::BodyTooLarge { max })
+                .await?;
+            return Ok(None);
+        };
+        Ok(Some(PoolFrame { hdr: *hdr, body }))
+    }
+}
+
+impl<M: RawMutex, const BODY: usize, const DEPTH: usize> Default for PoolQueue<M, BODY, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`Dispatch`] impl passing all frames to a [`PoolQueue`]
+///
+/// When the queue is full, the server waits for a worker to take a frame, so
+/// at most `DEPTH` frames are buffered. Bodies larger than `BODY` bytes are
+/// answered with [`WireError::BodyTooLarge`].
+pub struct PoolFrontend<M: RawMutex + 'static, Tx, const BODY: usize, const DEPTH: usize> {
+    queue: &'static PoolQueue<M, BODY, DEPTH>,
+    kkind: VarKeyKind,
+    _pd: PhantomData<fn() -> Tx>,
+}
+
+impl<M: RawMutex + 'static, Tx, const BODY: usize, const DEPTH: usize>
+    PoolFrontend<M, Tx, BODY, DEPTH>
+{
+    /// Create a new frontend, passing frames to `queue`, and requiring keys of at
+    /// least `kkind`
+    pub fn new(queue: &'static PoolQueue<M, BODY, DEPTH>, kkind: VarKeyKind) -> Self {
+        Self {
+            queue,
+            kkind,
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<M: RawMutex + 'static, Tx: WireTx, const BODY: usize, const DEPTH: usize> Dispatch
+    for PoolFrontend<M, Tx, BODY, DEPTH>
+{
+    type Tx = Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.kkind
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        if let Some(frame) = PoolQueue::<M, BODY, DEPTH>::frame(tx, hdr, body).await? {
+            self.queue.frames.send(frame).await;
+        }
+        Ok(())
+    }
+}
+
+/// A [`Dispatch`] impl passing frames to one of two [`PoolQueue`]s, by the
+/// [`Priority`] of their endpoint
+///
+/// High-priority frames wait for space in their queue, while low-priority frames
+/// are answered with [`WireError::Busy`] if their queue is full, so the server
+/// never waits on low-priority work. Bodies larger than `BODY` bytes are answered
+/// with [`WireError::BodyTooLarge`]. See the [module docs](self) for details.
+pub struct PriorityFrontend<M: RawMutex + 'static, Tx, const BODY: usize, const DEPTH: usize> {
+    high: &'static PoolQueue<M, BODY, DEPTH>,
+    low: &'static PoolQueue<M, BODY, DEPTH>,
+    kkind: VarKeyKind,
+    priority_of: fn(&VarHeader) -> Priority,
+    _pd: PhantomData<fn() -> Tx>,
+}
+
+impl<M: RawMutex + 'static, Tx, const BODY: usize, const DEPTH: usize>
+    PriorityFrontend<M, Tx, BODY, DEPTH>
+{
+    /// Create a new frontend, passing frames to `high` or `low` according to
+    /// `priority_of`, usually the `priority_of` function of a `define_dispatch!`
+    /// dispatcher, and requiring keys of at least `kkind`
+    pub fn new(
+        high: &'static PoolQueue<M, BODY, DEPTH>,
+        low: &'static PoolQueue<M, BODY, DEPTH>,
+        kkind: VarKeyKind,
+        priority_of: fn(&VarHeader) -> Priority,
+    ) -> Self {
+        Self {
+            high,
+            low,
+            kkind,
+            priority_of,
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<M: RawMutex + 'static, Tx: WireTx, const BODY: usize, const DEPTH: usize> Dispatch
+    for PriorityFrontend<M, Tx, BODY, DEPTH>
+{
+    type Tx = Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.kkind
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let Some(frame) = PoolQueue::<M, BODY, DEPTH>::frame(tx, hdr, body).await? else {
+            return Ok(());
+        };
+        match (self.priority_of)(hdr) {
+            Priority::High => self.high.frames.send(frame).await,
+            Priority::Low => {
+                if self.low.frames.try_send(frame).is_err() {
+                    return tx.dispatch_error(hdr, WireError::Busy).await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A worker handling frames from a [`PoolQueue`] with its own dispatcher
+pub struct PoolWorker<M, Tx, D, const BODY: usize, const DEPTH: usize>
+where
+    M: RawMutex + 'static,
+    Tx: WireTx,
+    D: Dispatch<Tx = Tx>,
+{
+    queue: &'static PoolQueue<M, BODY, DEPTH>,
+    dispatch: D,
+    tx: Sender<Tx>,
+}
+
+impl<M, Tx, D, const BODY: usize, const DEPTH: usize> PoolWorker<M, Tx, D, BODY, DEPTH>
+where
+    M: RawMutex + 'static,
+    Tx: WireTx,
+    D: Dispatch<Tx = Tx>,
+{
+    /// Create a new worker, handling frames from `queue` with `dispatch`, and
+    /// replying with `tx`, e.g. obtained with [`Server::sender()`][crate::server::Server::sender]
+    pub fn new(queue: &'static PoolQueue<M, BODY, DEPTH>, dispatch: D, tx: Sender<Tx>) -> Self {
+        Self {
+            queue,
+            dispatch,
+            tx,
+        }
+    }
+
+    /// Access the dispatcher of this worker
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dispatch
+    }
+
+    /// Handle a single frame, waiting for one if the queue is empty
+    pub async fn handle_one(&mut self) -> Result<(), Tx::Error> {
+        let frame = self.queue.frames.receive().await;
+        self.dispatch
+            .handle(&self.tx, &frame.hdr, &frame.body)
+            .await
+    }
+
+    /// Handle frames forever
+    ///
+    /// Errors sending replies are ignored, as the server reports them when it
+    /// next sends a frame.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let _ = self.handle_one().await;
+        }
+    }
+}
+
+/// Frames waiting for a [`PriorityQueueWorker`], in `LEVELS` priority levels of up
+/// to `DEPTH` frames each, with bodies of up to `BODY` bytes
+///
+/// Frames of the highest level are taken first. See the [module docs](self) for
+/// details.
+pub struct PriorityQueue<M: RawMutex, const BODY: usize, const DEPTH: usize, const LEVELS: usize> {
+    levels: [Channel<M, PoolFrame<BODY>, DEPTH>; LEVELS],
+}
+
+impl<M: RawMutex, const BODY: usize, const DEPTH: usize, const LEVELS: usize>
+    PriorityQueue<M, BODY, DEPTH, LEVELS>
+{
+    /// Create a new, empty, queue
+    pub const fn new() -> Self {
+        Self {
+            levels: [const { Channel::new() }; LEVELS],
+        }
+    }
+
+    /// The number of frames waiting for a worker, at all levels
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(Channel::len).sum()
+    }
+
+    /// Whether no frames are waiting for a worker
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(Channel::is_empty)
+    }
+
+    /// Take a frame of the highest level waiting, waiting for one if the queue
+    /// is empty
+    async fn receive(&self) -> PoolFrame<BODY> {
+        poll_fn(|cx| {
+            for level in self.levels.iter().rev() {
+                if let Poll::Ready(frame) = level.poll_receive(cx) {
+                    return Poll::Ready(frame);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<M: RawMutex, const BODY: usize, const DEPTH: usize, const LEVELS: usize> Default
+    for PriorityQueue<M, BODY, DEPTH, LEVELS>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`Dispatch`] impl passing frames to a [`PriorityQueue`], at the level of
+/// their key
+///
+/// Levels are looked up in a table of keys, frames with keys not in the table
+/// have the lowest level, 0. Levels above the highest level of the queue are
+/// clamped to it. When the level of a frame is full, the server waits for a
+/// worker to take a frame of that level. Bodies larger than `BODY` bytes are
+/// answered with [`WireError::BodyTooLarge`].
+pub struct PriorityQueueFrontend<
+    M: RawMutex + 'static,
+    Tx,
+    const BODY: usize,
+    const DEPTH: usize,
+    const LEVELS: usize,
+> {
+    queue: &'static PriorityQueue<M, BODY, DEPTH, LEVELS>,
+    kkind: VarKeyKind,
+    levels: &'static [(Key, u8)],
+    _pd: PhantomData<fn() -> Tx>,
+}
+
+impl<M: RawMutex + 'static, Tx, const BODY: usize, const DEPTH: usize, const LEVELS: usize>
+    PriorityQueueFrontend<M, Tx, BODY, DEPTH, LEVELS>
+{
+    /// Create a new frontend, passing frames to `queue` at the level given by
+    /// `levels`, and requiring keys of at least `kkind`
+    pub fn new(
+        queue: &'static PriorityQueue<M, BODY, DEPTH, LEVELS>,
+        kkind: VarKeyKind,
+        levels: &'static [(Key, u8)],
+    ) -> Self {
+        Self {
+            queue,
+            kkind,
+            levels,
+            _pd: PhantomData,
+        }
+    }
+
+    /// The level of the frame with header `hdr`
+    pub fn level_of(&self, hdr: &VarHeader) -> usize {
+        let level = self
+            .levels
+            .iter()
+            .find(|(key, _)| hdr.key == VarKey::Key8(*key))
+            .map_or(0, |(_, level)| usize::from(*level));
+        level.min(LEVELS.saturating_sub(1))
+    }
+}
+
+impl<
+        M: RawMutex + 'static,
+        Tx: WireTx,
+        const BODY: usize,
+        const DEPTH: usize,
+        const LEVELS: usize,
+    > Dispatch for PriorityQueueFrontend<M, Tx, BODY, DEPTH, LEVELS>
+{
+    type Tx = Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.kkind
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        if let Some(frame) = PoolQueue::<M, BODY, DEPTH>::frame(tx, hdr, body).await? {
+            self.queue.levels[self.level_of(hdr)].send(frame).await;
+        }
+        Ok(())
+    }
+}
+
+/// A worker handling frames from a [`PriorityQueue`], highest level first, with
+/// its own dispatcher
+pub struct PriorityQueueWorker<M, Tx, D, const BODY: usize, const DEPTH: usize, const LEVELS: usize>
+where
+    M: RawMutex + 'static,
+    Tx: WireTx,
+    D: Dispatch<Tx = Tx>,
+{
+    queue: &'static PriorityQueue<M, BODY, DEPTH, LEVELS>,
+    dispatch: D,
+    tx: Sender<Tx>,
+}
+
+impl<M, Tx, D, const BODY: usize, const DEPTH: usize, const LEVELS: usize>
+    PriorityQueueWorker<M, Tx, D, BODY, DEPTH, LEVELS>
+where
+    M: RawMutex + 'static,
+    Tx: WireTx,
+    D: Dispatch<Tx = Tx>,
+{
+    /// Create a new worker, handling frames from `queue` with `dispatch`, and
+    /// replying with `tx`, e.g. obtained with [`Server::sender()`][crate::server::Server::sender]
+    pub fn new(
+        queue: &'static PriorityQueue<M, BODY, DEPTH, LEVELS>,
+        dispatch: D,
+        tx: Sender<Tx>,
+    ) -> Self {
+        Self {
+            queue,
+            dispatch,
+            tx,
+        }
+    }
+
+    /// Access the dispatcher of this worker
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dispatch
+    }
+
+    /// Handle a single frame of the highest level waiting, waiting for one if the
+    /// queue is empty
+    pub async fn handle_one(&mut self) -> Result<(), Tx::Error> {
+        let frame = self.queue.receive().await;
+        self.dispatch
+            .handle(&self.tx, &frame.hdr, &frame.body)
+            .await
+    }
+
+    /// Handle frames forever
+    ///
+    /// Errors sending replies are ignored, as the server reports them when it
+    /// next sends a frame.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let _ = self.handle_one().await;
+        }
+    }
+}
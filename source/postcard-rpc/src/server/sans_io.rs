@@ -0,0 +1,338 @@
+//! The protocol logic of the server, without any transport or executor
+//!
+//! [`Server::run()`](crate::server::Server::run) and the dispatchers generated by
+//! [`define_dispatch!`](crate::define_dispatch) are thin async layers, which
+//! receive and send frames, and call into these functions for every decision:
+//!
+//! * [`on_frame()`] and [`on_rx_error()`] turn a received frame, or a failed
+//!   receive, into an [`RxEvent`], telling the loop what to do next
+//! * [`resolve_alias()`], [`narrow_key()`], [`authorize()`] and [`admit()`] decide
+//!   whether a request reaches its handler, or is rejected with which error
+//! * [`frame`](crate::server::frame) serializes outgoing frames into bytes
+//!
+//! As none of these need an executor, the protocol can be tested
+//! deterministically, one frame at a time:
+//!
+//! ```rust
+//! use postcard_rpc::{
+//!     header::{VarHeader, VarKey, VarSeq},
+//!     server::sans_io::{on_frame, RxEvent},
+//!     standard_icd::PingEndpoint,
+//!     Endpoint,
+//! };
+//!
+//! let hdr = VarHeader { key: VarKey::Key8(PingEndpoint::REQ_KEY), seq_no: VarSeq::Seq1(3) };
+//! let mut frame = hdr.write_to_vec();
+//! frame.push(42);
+//!
+//! assert_eq!(
+//!     on_frame(&frame),
+//!     RxEvent::Dispatch { hdr, deadline_ms: None, body: &[42] },
+//! );
+//! assert_eq!(on_frame(&[]), RxEvent::Ignore);
+//! ```
+
+use crate::{
+    header::{take_deadline, VarHeader, VarKey, VarKeyKind, VarSeq, PROTOCOL_VERSION},
+    server::{EndpointAlias, WireRxErrorKind, WireTxErrorKind},
+    standard_icd::{FrameTooShort, WireError},
+};
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// What the dispatch loop does with a received frame, or a failed receive
+#[derive(Debug, PartialEq)]
+pub enum RxEvent<'a> {
+    /// Pass the frame to the dispatcher
+    Dispatch {
+        /// The header of the frame
+        hdr: VarHeader,
+        /// The deadline of the request in milliseconds, if the client set one
+        deadline_ms: Option<u32>,
+        /// The body of the frame, after the header and deadline
+        body: &'a [u8],
+    },
+    /// Answer the request with an error, without dispatching it
+    Reject {
+        /// The header of the rejected request
+        hdr: VarHeader,
+        /// The error to send to the client
+        err: WireError,
+    },
+    /// Drop the frame, there is nobody to tell about it
+    Ignore,
+    /// The connection is gone, stop the loop
+    Closed,
+}
+
+/// Decode a received frame
+///
+/// Frames without a valid header are ignored, as there is no key or sequence
+/// number to reply to. Frames of another [`PROTOCOL_VERSION`], and frames cut
+/// short before the end of their deadline, are rejected.
+pub fn on_frame(frame: &[u8]) -> RxEvent<'_> {
+    let Some((hdr, version, body)) = VarHeader::take_versioned_from_slice(frame) else {
+        // TODO: send a nak on badly formed messages? We don't have
+        // much to say because we don't have a key or seq no or anything
+        return RxEvent::Ignore;
+    };
+    match take_deadline(version, body) {
+        None => {
+            // The header announced a deadline, but the frame ends early
+            let len = u32::try_from(frame.len()).unwrap_or(u32::MAX);
+            RxEvent::Reject {
+                hdr,
+                err: WireError::FrameTooShort(FrameTooShort { len }),
+            }
+        }
+        Some((_, version, _)) if version != PROTOCOL_VERSION => RxEvent::Reject {
+            hdr,
+            err: WireError::ProtocolVersionMismatch {
+                expected: PROTOCOL_VERSION,
+                got: version,
+            },
+        },
+        Some((deadline_ms, _, body)) => RxEvent::Dispatch {
+            hdr,
+            deadline_ms,
+            body,
+        },
+    }
+}
+
+/// Decide what to do after a failed receive into a buffer of `buf_len` bytes
+///
+/// Errors that still carry the header of the request are answered, so the client
+/// doesn't wait for a reply forever.
+pub fn on_rx_error(err: WireRxErrorKind, buf_len: usize) -> RxEvent<'static> {
+    let (hdr, err) = match err {
+        WireRxErrorKind::ConnectionClosed => return RxEvent::Closed,
+        WireRxErrorKind::ReceivedBodyTooLarge(hdr) => {
+            let max = buf_len.saturating_sub(header_len(&hdr));
+            let max = u32::try_from(max).unwrap_or(u32::MAX);
+            (hdr, WireError::BodyTooLarge { max })
+        }
+        WireRxErrorKind::AuthFailed(hdr) => (hdr, WireError::AuthFailed),
+        WireRxErrorKind::ReassemblyFailed(hdr) => (hdr, WireError::ReassemblyFailed),
+        WireRxErrorKind::ChecksumFailed(hdr) => (hdr, WireError::ChecksumFailed),
+        // If the connection is still down, sending fails, and the loop stops
+        // as if the receive had failed
+        WireRxErrorKind::TruncatedFrame(hdr) => (hdr, WireError::TruncatedFrame),
+        WireRxErrorKind::ReceivedMessageTooLarge => return RxEvent::Ignore,
+        WireRxErrorKind::Other => return RxEvent::Ignore,
+    };
+    RxEvent::Reject { hdr, err }
+}
+
+/// Whether a failed send stops the dispatch loop
+pub fn is_fatal(err: WireTxErrorKind) -> bool {
+    match err {
+        WireTxErrorKind::ConnectionClosed => true,
+        WireTxErrorKind::Other => false,
+        WireTxErrorKind::Timeout => true,
+    }
+}
+
+/// The number of bytes used to encode the given header
+fn header_len(hdr: &VarHeader) -> usize {
+    let key_len = match hdr.key.kind() {
+        VarKeyKind::Key1 => 1,
+        VarKeyKind::Key2 => 2,
+        VarKeyKind::Key4 => 4,
+        VarKeyKind::Key8 => 8,
+    };
+    let seq_len = match hdr.seq_no {
+        VarSeq::Seq1(_) => 1,
+        VarSeq::Seq2(_) => 2,
+        VarSeq::Seq4(_) => 4,
+    };
+    1 + key_len + seq_len
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// ROUTING
+//////////////////////////////////////////////////////////////////////////////
+
+/// The state of the dispatcher that requests are admitted against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DispatchState {
+    /// Whether the dispatcher is draining, see `Shutdown`
+    pub shutting_down: bool,
+    /// Whether the limit of spawned handlers is reached, see `SpawnLimit`
+    pub spawn_limit_reached: bool,
+}
+
+/// The header a request is dispatched with, and the alias it was sent to, if any
+///
+/// Requests to an alias are handled like requests to its endpoint, with the
+/// request key of the endpoint, shortened to the length used by the client.
+pub fn resolve_alias<'a>(
+    aliases: &'a [EndpointAlias],
+    hdr: &VarHeader,
+) -> (VarHeader, Option<&'a EndpointAlias>) {
+    match EndpointAlias::find(aliases, &hdr.key) {
+        Some(alias) => (alias.resolve(hdr), Some(alias)),
+        None => (*hdr, None),
+    }
+}
+
+/// Shorten `key` to the key length the dispatcher matches on
+///
+/// Fails with [`WireError::KeyTooSmall`] if the key is shorter than that.
+pub fn narrow_key<K>(key: &VarKey) -> Result<K, WireError>
+where
+    K: for<'k> TryFrom<&'k VarKey>,
+{
+    K::try_from(key).map_err(|_| WireError::KeyTooSmall)
+}
+
+/// Check that all `required` capabilities are part of the `granted` ones
+pub fn authorize(granted: u32, required: u32) -> Result<(), WireError> {
+    if granted & required == required {
+        Ok(())
+    } else {
+        Err(WireError::Unauthorized)
+    }
+}
+
+/// Check that a handler may be started, before the message is deserialized
+///
+/// No new work is started while draining, and handlers that are spawned are
+/// rejected once the spawn limit is reached.
+pub fn admit(state: DispatchState, spawns: bool) -> Result<(), WireError> {
+    if state.shutting_down {
+        Err(WireError::ShuttingDown)
+    } else if spawns && state.spawn_limit_reached {
+        Err(WireError::Busy)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Key, Key2};
+
+    fn hdr(kind: VarKeyKind, seq_no: u8) -> VarHeader {
+        let mut key = VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) });
+        key.shrink_to(kind);
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq1(seq_no),
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let hdr = hdr(VarKeyKind::Key2, 7);
+        let mut frame = hdr.write_to_vec();
+        frame.extend_from_slice(&[1, 2]);
+        assert_eq!(
+            on_frame(&frame),
+            RxEvent::Dispatch {
+                hdr,
+                deadline_ms: None,
+                body: &[1, 2],
+            }
+        );
+
+        // A deadline follows the header
+        let mut frame = hdr.write_to_vec();
+        frame[0] |= VarHeader::DEADLINE_BITS;
+        frame.extend_from_slice(&500u32.to_le_bytes());
+        frame.push(3);
+        assert_eq!(
+            on_frame(&frame),
+            RxEvent::Dispatch {
+                hdr,
+                deadline_ms: Some(500),
+                body: &[3],
+            }
+        );
+
+        // ...but might be cut short
+        frame.truncate(frame.len() - 3);
+        let len = frame.len() as u32;
+        assert_eq!(
+            on_frame(&frame),
+            RxEvent::Reject {
+                hdr,
+                err: WireError::FrameTooShort(FrameTooShort { len }),
+            }
+        );
+
+        assert_eq!(on_frame(&[]), RxEvent::Ignore);
+    }
+
+    #[test]
+    fn rx_errors() {
+        let hdr = hdr(VarKeyKind::Key4, 7);
+        assert_eq!(
+            on_rx_error(WireRxErrorKind::ConnectionClosed, 64),
+            RxEvent::Closed
+        );
+        assert_eq!(on_rx_error(WireRxErrorKind::Other, 64), RxEvent::Ignore);
+        // One byte of flags, four of key, and one of sequence number
+        assert_eq!(
+            on_rx_error(WireRxErrorKind::ReceivedBodyTooLarge(hdr), 64),
+            RxEvent::Reject {
+                hdr,
+                err: WireError::BodyTooLarge { max: 58 },
+            }
+        );
+        assert_eq!(
+            on_rx_error(WireRxErrorKind::TruncatedFrame(hdr), 64),
+            RxEvent::Reject {
+                hdr,
+                err: WireError::TruncatedFrame,
+            }
+        );
+        assert!(is_fatal(WireTxErrorKind::Timeout));
+        assert!(!is_fatal(WireTxErrorKind::Other));
+    }
+
+    #[test]
+    fn routing() {
+        let alias = EndpointAlias {
+            req_key: unsafe { Key::from_bytes([9; 8]) },
+            resp_key: unsafe { Key::from_bytes([10; 8]) },
+            endpoint_req_key: unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) },
+            endpoint_resp_key: unsafe { Key::from_bytes([11; 8]) },
+        };
+        let aliases = [alias];
+
+        // Requests to the alias get the key of the endpoint, at the same length
+        let mut aliased = hdr(VarKeyKind::Key8, 1);
+        aliased.key = VarKey::Key8(alias.req_key);
+        let (resolved, found) = resolve_alias(&aliases, &aliased);
+        assert_eq!(resolved, hdr(VarKeyKind::Key8, 1));
+        assert!(found.is_some());
+        let (resolved, found) = resolve_alias(&aliases, &hdr(VarKeyKind::Key2, 1));
+        assert_eq!(resolved, hdr(VarKeyKind::Key2, 1));
+        assert!(found.is_none());
+
+        assert!(narrow_key::<Key2>(&hdr(VarKeyKind::Key4, 1).key).is_ok());
+        assert_eq!(
+            narrow_key::<Key2>(&hdr(VarKeyKind::Key1, 1).key),
+            Err(WireError::KeyTooSmall)
+        );
+
+        assert_eq!(authorize(0b11, 0b01), Ok(()));
+        assert_eq!(authorize(0b01, 0b11), Err(WireError::Unauthorized));
+
+        let busy = DispatchState {
+            shutting_down: false,
+            spawn_limit_reached: true,
+        };
+        assert_eq!(admit(busy, false), Ok(()));
+        assert_eq!(admit(busy, true), Err(WireError::Busy));
+        let draining = DispatchState {
+            shutting_down: true,
+            spawn_limit_reached: false,
+        };
+        assert_eq!(admit(draining, false), Err(WireError::ShuttingDown));
+    }
+}
@@ -13,13 +13,48 @@ use postcard_schema::schema::NamedType;
 use postcard_schema::schema::owned::OwnedNamedType;
 
 /// The calculated Key for the type [`WireError`] and the path [`ERROR_PATH`]
+///
+/// The key depends on the schema of [`WireError`], so it changes whenever a
+/// variant is added, which breaks decoding errors between hosts and devices using
+/// different versions. Such changes are only made with a new minor version, see
+/// the changelog.
 pub const ERROR_KEY: Key = Key::for_path::<WireError>(ERROR_PATH);
 
 /// The path string used for the error type
 pub const ERROR_PATH: &str = "error";
 
+/// The calculated Key for the type `()` and the path [`STREAM_END_PATH`]
+///
+/// This is sent by the server after the last chunk of a streaming endpoint reply,
+/// using the same sequence number as the request.
+pub const STREAM_END_KEY: Key = Key::for_path::<()>(STREAM_END_PATH);
+
+/// The path string used for the end of a streaming endpoint reply
+pub const STREAM_END_PATH: &str = "postcard-rpc/stream/end";
+
+/// The calculated Key for the [`CancelTopic`], sent by the client to request
+/// cancellation of an in-flight request
+///
+/// The sequence number of the message is the sequence number of the request to
+/// cancel. Only handlers using the `cancellable` flavor of
+/// [`define_dispatch!()`][crate::define_dispatch] can be cancelled.
+pub const CANCEL_KEY: Key = <CancelTopic as crate::Topic>::TOPIC_KEY;
+
+/// The calculated Key for the [`SubscribeTopic`], sent by the client when it
+/// subscribes to a topic
+///
+/// The message is the [`TOPIC_KEY`][crate::Topic::TOPIC_KEY] of the topic. Servers
+/// with [`Subscriptions`][crate::server::Subscriptions] only publish topics with
+/// at least one subscriber.
+pub const SUBSCRIBE_KEY: Key = <SubscribeTopic as crate::Topic>::TOPIC_KEY;
+
+/// The calculated Key for the [`UnsubscribeTopic`], sent by the client when it
+/// no longer needs a topic it subscribed to with [`SubscribeTopic`]
+pub const UNSUBSCRIBE_KEY: Key = <UnsubscribeTopic as crate::Topic>::TOPIC_KEY;
+
 /// The given frame was too long
 #[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FrameTooLong {
     /// The length of the too-long frame
     pub len: u32,
@@ -29,6 +64,7 @@ pub struct FrameTooLong {
 
 /// The given frame was too short
 #[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FrameTooShort {
     /// The length of the too-short frame
     pub len: u32,
@@ -37,6 +73,7 @@ pub struct FrameTooShort {
 /// A protocol error that is handled outside of the normal request type, usually
 /// indicating a protocol-level error
 #[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum WireError {
     /// The frame exceeded the buffering capabilities of the server
     FrameTooLong(FrameTooLong),
@@ -55,6 +92,49 @@ pub enum WireError {
     /// The provided key is below the minimum key size calculated to avoid hash
     /// collisions, and was rejected to avoid potential misunderstanding
     KeyTooSmall,
+    /// The request was cancelled by the client before it completed
+    Cancelled,
+    /// The handler did not complete within the timeout configured for the endpoint
+    Timeout,
+    /// The request was rejected by an interceptor before being handled
+    Rejected,
+    /// The request was too large for the receive buffer of the server, and was dropped
+    BodyTooLarge {
+        /// The largest request body the server can receive with the same header
+        max: u32,
+    },
+    /// The server is shutting down, and no longer accepts requests
+    ShuttingDown,
+    /// The authentication tag of the request was invalid, and the request was dropped
+    AuthFailed,
+    /// The request used a different protocol version than the server
+    ProtocolVersionMismatch {
+        /// The protocol version of the server
+        expected: u8,
+        /// The protocol version of the request
+        got: u8,
+    },
+    /// The server is already running as many spawned handlers as it allows
+    Busy,
+    /// Fragments of the request were lost, and the request was dropped
+    ReassemblyFailed,
+    /// The checksum of the request was invalid, and the request was dropped
+    ChecksumFailed,
+    /// The connection was interrupted while the request was received, e.g. by a
+    /// reset, and the incomplete request was dropped
+    TruncatedFrame,
+    /// The handler faulted, e.g. panicked, while handling the request
+    HandlerFault {
+        /// The key of the request, see [`Key::to_bytes()`]
+        key: [u8; 8],
+        /// The [`fault_hash()`] of the fault message, e.g. the panic message
+        message_hash: u32,
+    },
+    /// The deadline of the request expired before the handler completed
+    DeadlineExceeded,
+    /// The endpoint requires a capability that was not granted to the client, see
+    /// [`GrantCapsEndpoint`]
+    Unauthorized,
 }
 
 impl core::fmt::Display for WireError {
@@ -67,12 +147,43 @@ impl core::fmt::Display for WireError {
             WireError::UnknownKey => f.write_str("The key associated with this request was unknown"),
             WireError::FailedToSpawn => f.write_str("The server was unable to spawn the associated handler, typically due to an exhaustion of resources"),
             WireError::KeyTooSmall => f.write_str("The provided key is below the minimum key size calculated to avoid hash collisions, and was rejected to avoid potential misunderstanding"),
+            WireError::Cancelled => f.write_str("The request was cancelled by the client before it completed"),
+            WireError::Timeout => f.write_str("The handler did not complete within the timeout configured for the endpoint"),
+            WireError::Rejected => f.write_str("The request was rejected by an interceptor before being handled"),
+            WireError::BodyTooLarge { max } => write!(f, "The request was too large for the receive buffer of the server: the body may be at most {max} bytes"),
+            WireError::ShuttingDown => f.write_str("The server is shutting down, and no longer accepts requests"),
+            WireError::AuthFailed => f.write_str("The authentication tag of the request was invalid, and the request was dropped"),
+            WireError::ProtocolVersionMismatch { expected, got } => write!(f, "The request used protocol version {got}, but the server uses protocol version {expected}"),
+            WireError::Busy => f.write_str("The server is already running as many spawned handlers as it allows, try again later"),
+            WireError::ReassemblyFailed => f.write_str("Fragments of the request were lost, and the request was dropped"),
+            WireError::ChecksumFailed => f.write_str("The checksum of the request was invalid, and the request was dropped"),
+            WireError::TruncatedFrame => f.write_str("The connection was interrupted while the request was received, and the incomplete request was dropped"),
+            WireError::DeadlineExceeded => f.write_str("The deadline of the request expired before the handler completed"),
+            WireError::Unauthorized => f.write_str("The endpoint requires a capability that was not granted to the client"),
+            WireError::HandlerFault { message_hash, .. } => write!(f, "The handler faulted while handling the request, with message hash {message_hash:#010x}"),
         }
     }
 }
 
 impl core::error::Error for WireError {}
 
+/// The hash of a fault message, as sent in [`WireError::HandlerFault`]
+///
+/// This is the 32-bit FNV-1a hash of the message, so targets can report a fault
+/// without sending the message itself. The host may compare it against the hashes
+/// of known messages.
+pub const fn fault_hash(message: &str) -> u32 {
+    let bytes = message.as_bytes();
+    let mut hash = 0x811c_9dc5u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
 /// A single element of schema information
 #[cfg(not(feature = "use-std"))]
 #[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
@@ -140,15 +251,296 @@ pub struct SchemaTotals {
     pub errors: u32,
 }
 
+/// The counters of a single endpoint, returned by [`GetMetricsEndpoint`]
+///
+/// See [`Metrics`](crate::server::Metrics) for which requests are counted.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct EndpointStats {
+    /// The request key of the endpoint
+    pub key: Key,
+    /// The number of requests received
+    pub requests: u32,
+    /// The number of requests that failed to deserialize
+    pub deser_failed: u32,
+    /// The number of replies that failed to serialize or send
+    pub ser_failed: u32,
+    /// The number of requests whose handler did not complete in time
+    pub timed_out: u32,
+    /// The number of requests answered with any other error
+    pub errors: u32,
+}
+
+/// The response of [`GetMetricsEndpoint`] for the requested endpoint index, `None`
+/// once past the last endpoint
+pub type MetricsResponse = Option<EndpointStats>;
+
+/// The set of keys supported by a device, returned by [`GetKeysEndpoint`]
+///
+/// Each key is a hash of both the path and the schema of the message, so a
+/// key mismatch means either the path or the type has changed.
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct DeviceKeys<'a> {
+    /// The list of endpoints by path string, request key, and response key
+    pub endpoints: &'a [(&'a str, Key, Key)],
+    /// The list of topics (client to server) by path string and topic key
+    pub topics_in: &'a [(&'a str, Key)],
+    /// The list of topics (server to client) by path string and topic key
+    pub topics_out: &'a [(&'a str, Key)],
+}
+
+/// The set of keys supported by a device, returned by [`GetKeysEndpoint`]
+///
+/// Each key is a hash of both the path and the schema of the message, so a
+/// key mismatch means either the path or the type has changed.
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedDeviceKeys {
+    /// The list of endpoints by path string, request key, and response key
+    pub endpoints: Vec<(String, Key, Key)>,
+    /// The list of topics (client to server) by path string and topic key
+    pub topics_in: Vec<(String, Key)>,
+    /// The list of topics (server to client) by path string and topic key
+    pub topics_out: Vec<(String, Key)>,
+}
+
+#[cfg(feature = "use-std")]
+impl OwnedDeviceKeys {
+    /// Returns the paths of all endpoints in `list` that the device does not support
+    ///
+    /// An endpoint is unsupported if either its request or response key is missing,
+    /// e.g. because the path or types differ between the host and the device.
+    pub fn missing_endpoints(&self, list: &crate::EndpointMap) -> Vec<&'static str> {
+        list.endpoints
+            .iter()
+            .filter(|(_, req, resp)| {
+                !self
+                    .endpoints
+                    .iter()
+                    .any(|(_, dreq, dresp)| dreq == req && dresp == resp)
+            })
+            .map(|(path, _, _)| *path)
+            .collect()
+    }
+
+    /// Returns the paths of all topics in `list` that the device does not support
+    ///
+    /// The topics of the device are chosen based on the direction of `list`.
+    pub fn missing_topics(&self, list: &crate::TopicMap) -> Vec<&'static str> {
+        let topics = match list.direction {
+            TopicDirection::ToServer => &self.topics_in,
+            TopicDirection::ToClient => &self.topics_out,
+        };
+        list.topics
+            .iter()
+            .filter(|(_, key)| !topics.iter().any(|(_, dkey)| dkey == key))
+            .map(|(path, _)| *path)
+            .collect()
+    }
+}
+
+/// The firmware running on a device, returned by [`DeviceInfoEndpoint`]
+///
+/// Usually created with the [`device_info!`](crate::device_info) macro, which
+/// fills in the version of the firmware crate, and the git hash and build time
+/// set by its build script.
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct DeviceInfo<'a> {
+    /// A description of the product, chosen by the firmware
+    pub product: &'a str,
+    /// The version of the firmware, e.g. `CARGO_PKG_VERSION`
+    pub version: &'a str,
+    /// The git hash the firmware was built from, or an empty string if unknown
+    pub git_hash: &'a str,
+    /// The time the firmware was built, or an empty string if unknown
+    pub build_time: &'a str,
+}
+
+/// The firmware running on a device, returned by [`DeviceInfoEndpoint`]
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedDeviceInfo {
+    /// A description of the product, chosen by the firmware
+    pub product: String,
+    /// The version of the firmware, e.g. `CARGO_PKG_VERSION`
+    pub version: String,
+    /// The git hash the firmware was built from, or an empty string if unknown
+    pub git_hash: String,
+    /// The time the firmware was built, or an empty string if unknown
+    pub build_time: String,
+}
+
+#[cfg(feature = "use-std")]
+impl From<&DeviceInfo<'_>> for OwnedDeviceInfo {
+    fn from(info: &DeviceInfo<'_>) -> Self {
+        Self {
+            product: info.product.to_string(),
+            version: info.version.to_string(),
+            git_hash: info.git_hash.to_string(),
+            build_time: info.build_time.to_string(),
+        }
+    }
+}
+
+/// An endpoint handled by a device, returned by [`GetEndpointInfoEndpoint`]
+///
+/// `define_dispatch!` generates one for each endpoint in its table, see the
+/// `endpoint_info()` method of the dispatcher.
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct EndpointInfo<'a> {
+    /// The path of the endpoint
+    pub path: &'a str,
+    /// The key of requests
+    pub req_key: Key,
+    /// The key of responses
+    pub resp_key: Key,
+    /// The [`schema_hash()`] of the request type
+    pub req_schema: u64,
+    /// The [`schema_hash()`] of the response type
+    pub resp_schema: u64,
+    /// The kind of the handler in the table of `define_dispatch!`, e.g. `async`
+    /// or `stream`
+    pub kind: &'a str,
+}
+
+/// An endpoint handled by a device, returned by [`GetEndpointInfoEndpoint`]
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedEndpointInfo {
+    /// The path of the endpoint
+    pub path: String,
+    /// The key of requests
+    pub req_key: Key,
+    /// The key of responses
+    pub resp_key: Key,
+    /// The [`schema_hash()`] of the request type
+    pub req_schema: u64,
+    /// The [`schema_hash()`] of the response type
+    pub resp_schema: u64,
+    /// The kind of the handler in the table of `define_dispatch!`, e.g. `async`
+    /// or `stream`
+    pub kind: String,
+}
+
+#[cfg(feature = "use-std")]
+impl From<&EndpointInfo<'_>> for OwnedEndpointInfo {
+    fn from(info: &EndpointInfo<'_>) -> Self {
+        Self {
+            path: info.path.to_string(),
+            req_key: info.req_key,
+            resp_key: info.resp_key,
+            req_schema: info.req_schema,
+            resp_schema: info.resp_schema,
+            kind: info.kind.to_string(),
+        }
+    }
+}
+
+/// The response of [`GetEndpointInfoEndpoint`] for the requested endpoint index,
+/// `None` once past the last endpoint
+#[cfg(not(feature = "use-std"))]
+pub type EndpointInfoResponse<'a> = Option<EndpointInfo<'a>>;
+
+/// The response of [`GetEndpointInfoEndpoint`] for the requested endpoint index,
+/// `None` once past the last endpoint
+#[cfg(feature = "use-std")]
+pub type OwnedEndpointInfoResponse = Option<OwnedEndpointInfo>;
+
+/// A hash of the schema of `T`, independent of any path
+///
+/// Unlike keys, which hash the path together with the schema, this tells whether
+/// two types have the same schema, e.g. to compare the [`EndpointInfo`] reported
+/// by a device with the types known to the host.
+pub const fn schema_hash<T: Schema + ?Sized>() -> u64 {
+    u64::from_le_bytes(postcard_schema::key::hash::fnv1a64::hash_ty_path::<T>(""))
+}
+
+/// A request for capabilities, sent with [`GrantCapsEndpoint`]
+///
+/// The response is the bitmask of all capabilities granted to the client.
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct CapRequest<'a> {
+    /// The bitmask of the requested capabilities
+    pub caps: u32,
+    /// The proof that the client may hold the capabilities, e.g. a password or
+    /// the answer to a challenge
+    pub proof: &'a [u8],
+}
+
+/// A request for capabilities, sent with [`GrantCapsEndpoint`]
+///
+/// The response is the bitmask of all capabilities granted to the client.
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedCapRequest {
+    /// The bitmask of the requested capabilities
+    pub caps: u32,
+    /// The proof that the client may hold the capabilities, e.g. a password or
+    /// the answer to a challenge
+    pub proof: Vec<u8>,
+}
+
+/// The level of a [`LogRecord`], from the most to the least severe
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum LogLevel {
+    /// Errors
+    Error,
+    /// Warnings
+    Warn,
+    /// Informational messages
+    Info,
+    /// Debugging messages
+    Debug,
+    /// Very verbose debugging messages
+    Trace,
+}
+
+/// A log record, sent by the device on the [`LogTopic`]
+///
+/// See `server::log_topic` for sending records, and
+/// `HostClient::log_stream()` for receiving them.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct LogRecord<'a> {
+    /// The level of the record
+    pub level: LogLevel,
+    /// The target of the record, usually the module path it was logged from
+    pub target: &'a str,
+    /// The formatted message, which may have been truncated
+    pub message: &'a str,
+}
+
+/// A log record, sent by the device on the [`LogTopic`]
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedLogRecord {
+    /// The level of the record
+    pub level: LogLevel,
+    /// The target of the record, usually the module path it was logged from
+    pub target: String,
+    /// The formatted message, which may have been truncated
+    pub message: String,
+}
+
 endpoints! {
     list = STANDARD_ICD_ENDPOINTS;
     // NOTE: "omit_std" should ONLY be used by the standard_icd! You should NOT set this
     // in your code!
     omit_std = true;
-    | EndpointTy            | RequestTy     | ResponseTy    | Path                       |
-    | ----------            | ---------     | ----------    | ----                       |
-    | PingEndpoint          | u32           | u32           | "postcard-rpc/ping"        |
-    | GetAllSchemasEndpoint | ()            | SchemaTotals  | "postcard-rpc/schemas/get" |
+    | EndpointTy              | RequestTy       | ResponseTy                | Path                         | Cfg                           |
+    | ----------              | ---------       | ----------                | ----                         | ---                           |
+    | PingEndpoint            | u32             | u32                       | "postcard-rpc/ping"          |                               |
+    | GetAllSchemasEndpoint   | ()              | SchemaTotals              | "postcard-rpc/schemas/get"   |                               |
+    | GetKeysEndpoint         | ()              | DeviceKeys<'a>            | "postcard-rpc/keys/get"      | cfg(not(feature = "use-std")) |
+    | GetKeysEndpoint         | ()              | OwnedDeviceKeys           | "postcard-rpc/keys/get"      | cfg(feature = "use-std")      |
+    | GetCreditsEndpoint      | ()              | u32                       | "postcard-rpc/credits/get"   |                               |
+    | GetMetricsEndpoint      | u32             | MetricsResponse           | "postcard-rpc/metrics/get"   |                               |
+    | GrantCapsEndpoint       | CapRequest<'a>  | u32                       | "postcard-rpc/caps/grant"    | cfg(not(feature = "use-std")) |
+    | GrantCapsEndpoint       | OwnedCapRequest | u32                       | "postcard-rpc/caps/grant"    | cfg(feature = "use-std")      |
+    | DeviceInfoEndpoint      | ()              | DeviceInfo<'a>            | "postcard-rpc/info/get"      | cfg(not(feature = "use-std")) |
+    | DeviceInfoEndpoint      | ()              | OwnedDeviceInfo           | "postcard-rpc/info/get"      | cfg(feature = "use-std")      |
+    | GetEndpointInfoEndpoint | u32             | EndpointInfoResponse<'a>  | "postcard-rpc/endpoints/get" | cfg(not(feature = "use-std")) |
+    | GetEndpointInfoEndpoint | u32             | OwnedEndpointInfoResponse | "postcard-rpc/endpoints/get" | cfg(feature = "use-std")      |
 }
 
 topics! {
@@ -163,6 +555,8 @@ topics! {
     | GetAllSchemaDataTopic | OwnedSchemaData   | "postcard-rpc/schema/data"    | cfg(feature = "use-std")      |
     | LoggingTopic          | str               | "postcard-rpc/logging"        | cfg(not(feature = "use-std")) |
     | LoggingTopic          | String            | "postcard-rpc/logging"        | cfg(feature = "use-std")      |
+    | LogTopic              | LogRecord<'a>     | "postcard-rpc/log"            | cfg(not(feature = "use-std")) |
+    | LogTopic              | OwnedLogRecord    | "postcard-rpc/log"            | cfg(feature = "use-std")      |
 }
 
 topics! {
@@ -171,6 +565,9 @@ topics! {
     // NOTE: "omit_std" should ONLY be used by the standard_icd! You should NOT set this
     // in your code!
     omit_std = true;
-    | TopicTy           | MessageTy         | Path                          | Cfg                           |
-    | -------           | ---------         | ----                          | ---                           |
+    | TopicTy           | MessageTy         | Path                              | Cfg                           |
+    | -------           | ---------         | ----                              | ---                           |
+    | CancelTopic       | ()                | "postcard-rpc/cancel"             |                               |
+    | SubscribeTopic    | Key               | "postcard-rpc/topics/subscribe"   |                               |
+    | UnsubscribeTopic  | Key               | "postcard-rpc/topics/unsubscribe" |                               |
 }
@@ -1,12 +1,20 @@
 //! Test utilities for doctests and integration tests
 
 use core::{fmt::Display, future::Future};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use crate::header::{VarHeader, VarKey, VarSeq, VarSeqKind};
 use crate::host_client::util::Stopper;
 use crate::{
     host_client::{HostClient, RpcFrame, WireRx, WireSpawn, WireTx},
-    Endpoint, Topic,
+    standard_icd::{PingEndpoint, WireError, ERROR_KEY},
+    Endpoint, Key, Topic,
 };
 use postcard_schema::Schema;
 use serde::{de::DeserializeOwned, Serialize};
@@ -215,3 +223,200 @@ where
 
     (lfs, client)
 }
+
+/// The depth of the channels between a [`MockServer`] and its client
+const MOCK_DEPTH: usize = 64;
+
+/// A handler of a [`MockServer`], taking the request body, and returning the
+/// response body and key, or an error
+type MockHandler = Box<dyn FnMut(&[u8]) -> Result<(Key, Vec<u8>), WireError> + Send>;
+
+/// A fake device answering requests with scripted handlers
+///
+/// Each request is deserialized, passed to the handler registered for its
+/// endpoint with [`MockServer::on()`], and the serialized response is sent back,
+/// so the host is tested with the same encoding as a real device. Requests to
+/// endpoints without a handler are answered with [`WireError::UnknownKey`], and
+/// requests that fail to deserialize with [`WireError::DeserFailed`]. The
+/// [`PingEndpoint`] is answered by default.
+///
+/// ```rust
+/// # use postcard_rpc::{endpoints, topics, TopicDirection};
+/// # use postcard_rpc::standard_icd::{WireError, ERROR_PATH};
+/// use postcard_rpc::test_utils::MockServer;
+///
+/// endpoints! {
+///     list = ENDPOINT_LIST;
+///     | EndpointTy     | RequestTy | ResponseTy | Path     |
+///     | ----------     | --------- | ---------- | ----     |
+///     | DoubleEndpoint | u32       | u32        | "double" |
+/// }
+///
+/// topics! {
+///     list = TOPICS_OUT_LIST;
+///     direction = TopicDirection::ToClient;
+///     | TopicTy    | MessageTy | Path    |
+///     | -------    | --------- | ----    |
+///     | TempTopic  | i16       | "temp"  |
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mut mock = MockServer::new().on::<DoubleEndpoint>(|req| req * 2);
+/// let client = mock.client::<WireError>(ERROR_PATH);
+///
+/// assert_eq!(client.send_resp::<DoubleEndpoint>(&21).await.unwrap(), 42);
+///
+/// let mut sub = client.subscribe_exclusive::<TempTopic>(8).await.unwrap();
+/// mock.publish::<TempTopic>(&-5).await.unwrap();
+/// assert_eq!(sub.recv().await, Some(-5));
+/// # }
+/// ```
+pub struct MockServer {
+    handlers: Arc<Mutex<HashMap<Key, MockHandler>>>,
+    to_client: Option<Sender<Vec<u8>>>,
+    seq_no: AtomicU32,
+}
+
+impl MockServer {
+    /// Create a mock server answering only the [`PingEndpoint`]
+    pub fn new() -> Self {
+        Self {
+            handlers: Arc::new(Mutex::new(HashMap::new())),
+            to_client: None,
+            seq_no: AtomicU32::new(0),
+        }
+        .on::<PingEndpoint>(|nonce| nonce)
+    }
+
+    /// Answer requests to `E` with `handler`, replacing any previous handler of `E`
+    pub fn on<E>(self, mut handler: impl FnMut(E::Request) -> E::Response + Send + 'static) -> Self
+    where
+        E: Endpoint,
+        E::Request: DeserializeOwned,
+        E::Response: Serialize,
+    {
+        self.on_try::<E>(move |req| Ok(handler(req)))
+    }
+
+    /// Answer requests to `E` with `handler`, which may also reply with an error
+    pub fn on_try<E>(
+        self,
+        mut handler: impl FnMut(E::Request) -> Result<E::Response, WireError> + Send + 'static,
+    ) -> Self
+    where
+        E: Endpoint,
+        E::Request: DeserializeOwned,
+        E::Response: Serialize,
+    {
+        let handler: MockHandler = Box::new(move |body| {
+            let req = crate::tolerant::request_from_bytes::<E, _>(body)
+                .map_err(|_| WireError::DeserFailed)?;
+            let resp = handler(req)?;
+            let body = postcard::to_stdvec(&resp).map_err(|_| WireError::SerFailed)?;
+            Ok((E::RESP_KEY, body))
+        });
+        self.handlers.lock().unwrap().insert(E::REQ_KEY, handler);
+        self
+    }
+
+    /// Create the client connected to this mock server
+    ///
+    /// This spawns the task answering requests, so must be called within a tokio
+    /// runtime. Errors are sent as [`WireError`], so `err_uri_path` is usually
+    /// [`ERROR_PATH`](crate::standard_icd::ERROR_PATH).
+    ///
+    /// # Panics
+    ///
+    /// A mock server can only be connected to a single client, so this panics if
+    /// called more than once.
+    pub fn client<WireErr>(&mut self, err_uri_path: &str) -> HostClient<WireErr>
+    where
+        WireErr: Schema + DeserializeOwned,
+    {
+        assert!(
+            self.to_client.is_none(),
+            "A MockServer can only be connected to one client"
+        );
+        let (c2s_tx, mut c2s_rx) = channel::<Vec<u8>>(MOCK_DEPTH);
+        let (s2c_tx, s2c_rx) = channel(MOCK_DEPTH);
+        self.to_client = Some(s2c_tx.clone());
+
+        // The mock never fails, but the local transports expect a stopper
+        let fake_error = Stopper::new();
+        let client = HostClient::<WireErr>::new_with_wire(
+            LocalTx {
+                to_server: c2s_tx,
+                fake_error: fake_error.clone(),
+            },
+            LocalRx {
+                from_server: s2c_rx,
+                fake_error,
+            },
+            LocalSpawn,
+            VarSeqKind::Seq2,
+            err_uri_path,
+            MOCK_DEPTH,
+        );
+
+        let handlers = self.handlers.clone();
+        tokio::task::spawn(async move {
+            while let Some(msg) = c2s_rx.recv().await {
+                let Some((hdr, body)) = VarHeader::take_from_slice(&msg) else {
+                    continue;
+                };
+                let VarKey::Key8(key) = hdr.key else {
+                    continue;
+                };
+                let res = match handlers.lock().unwrap().get_mut(&key) {
+                    Some(handler) => handler(body),
+                    None => Err(WireError::UnknownKey),
+                };
+                let (key, body) = match res {
+                    Ok(reply) => reply,
+                    Err(err) => (ERROR_KEY, postcard::to_stdvec(&err).unwrap()),
+                };
+                let frame = RpcFrame {
+                    header: VarHeader {
+                        key: VarKey::Key8(key),
+                        seq_no: hdr.seq_no,
+                    },
+                    body,
+                };
+                if s2c_tx.send(frame.to_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        client
+    }
+
+    /// Publish a message to the client on topic `T`
+    ///
+    /// Returns [`LocalError::RxClosed`] if the client is closed, or was not yet
+    /// created with [`MockServer::client()`].
+    pub async fn publish<T>(&self, msg: &T::Message) -> Result<(), LocalError>
+    where
+        T: Topic,
+        T::Message: Serialize,
+    {
+        let to_client = self.to_client.as_ref().ok_or(LocalError::RxClosed)?;
+        let frame = RpcFrame {
+            header: VarHeader {
+                key: VarKey::Key8(T::TOPIC_KEY),
+                seq_no: VarSeq::Seq4(self.seq_no.fetch_add(1, Ordering::Relaxed)),
+            },
+            body: postcard::to_stdvec(msg).unwrap(),
+        };
+        to_client
+            .send(frame.to_bytes())
+            .await
+            .map_err(|_| LocalError::RxClosed)
+    }
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
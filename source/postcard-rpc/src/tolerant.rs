@@ -0,0 +1,283 @@
+//! Tolerant decoding of requests, for evolving request types
+//!
+//! Postcard is not self-describing: a request is decoded field by field, and a
+//! request missing its last fields fails with
+//! [`DeserializeUnexpectedEnd`](postcard::Error::DeserializeUnexpectedEnd).
+//! Trailing bytes are already ignored, so a newer client may send fields an older
+//! server doesn't know about, but an older client can't talk to a newer server.
+//!
+//! Endpoints can opt in to tolerant decoding with `#[define_endpoint(tolerant)]`,
+//! `#[rpc(tolerant)]`, or by setting [`Endpoint::TOLERANT`] and using
+//! [`request_key()`] as the [`REQ_KEY`](Endpoint::REQ_KEY) when implementing the
+//! trait by hand. The requests of a tolerant endpoint are decoded with
+//! [`from_bytes()`], which stops at the end of the body, and leaves the missing
+//! trailing fields to their `#[serde(default)]`:
+//!
+//! ```rust,ignore
+//! #[define_endpoint(path = "motor/set", tolerant)]
+//! #[response(())]
+//! pub struct SetMotorReq {
+//!     pub speed: u16,
+//!     // Added in v2, older clients don't send it
+//!     #[serde(default)]
+//!     pub ramp_ms: u32,
+//! }
+//! ```
+//!
+//! The request key of most endpoints is a hash of their path and request schema,
+//! so any change to the request type changes the key, and older clients are
+//! answered with [`UnknownKey`](crate::standard_icd::WireError::UnknownKey). The
+//! request key of a tolerant endpoint only depends on its path, see
+//! [`request_key()`]. This makes `tolerant` part of the protocol: it must be set
+//! from the first release of the endpoint, and clients and servers must agree on
+//! it. The response key is unchanged, so responses can not evolve this way.
+//!
+//! ## Safe changes
+//!
+//! Only these changes to the request type of a tolerant endpoint are compatible
+//! with clients built before the change:
+//!
+//! * Appending fields at the end of the request struct, or tuple struct, that are
+//!   marked `#[serde(default)]`, or any fields if the struct itself is marked
+//!   `#[serde(default)]`, which takes the missing fields from its `Default` impl.
+//!   Older clients don't send these fields, and get the default values. Older
+//!   servers ignore them, as trailing bytes.
+//!
+//! Any other change breaks compatibility, without a change of key to detect it:
+//!
+//! * Removing, reordering, or changing the type of existing fields, including
+//!   changes between types with the same encoding, e.g. `u16` and `u32`
+//! * Appending fields anywhere but the end of the request itself, e.g. at the end
+//!   of a nested struct, or of an enum variant. Only the top level is tolerant
+//! * Appending fields without a default
+//! * Adding, removing or reordering enum variants, in the request or any type it
+//!   holds, except for appending variants that older clients never send
+//! * Changing a request that is not a struct, e.g. a primitive or an enum
+//!
+//! Bodies that end in the middle of a field still fail to decode.
+
+use postcard::Result;
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::{Endpoint, Key};
+
+/// The request key of a tolerant endpoint at `path`
+///
+/// Unlike [`Key::for_path()`] with the request type, this doesn't depend on the
+/// request schema, so it stays the same as fields are appended to the request.
+pub const fn request_key(path: &str) -> Key {
+    Key::for_path::<TolerantRequest>(path)
+}
+
+/// Stands in for the request schema in the key of tolerant endpoints
+#[derive(postcard_schema::Schema)]
+struct TolerantRequest;
+
+/// Deserialize `T` from `bytes`, defaulting missing trailing fields
+///
+/// Like [`postcard::from_bytes()`], but if `T` is a struct or tuple struct,
+/// decoding stops at the end of `bytes` instead of failing, and the remaining
+/// fields get their `#[serde(default)]`. Fields without a default still fail.
+/// See the [module docs](self) for the changes this tolerates.
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    T::deserialize(TrailingDefaults { bytes })
+}
+
+/// Deserialize the request of `E` from `bytes`
+///
+/// Uses [`from_bytes()`] if `E` is [tolerant](Endpoint::TOLERANT), and
+/// [`postcard::from_bytes()`] otherwise.
+pub fn request_from_bytes<'de, E, T>(bytes: &'de [u8]) -> Result<T>
+where
+    E: Endpoint<Request = T>,
+    T: Deserialize<'de>,
+{
+    if E::TOLERANT {
+        from_bytes(bytes)
+    } else {
+        postcard::from_bytes(bytes)
+    }
+}
+
+/// The top level deserializer of [`from_bytes()`]
+///
+/// Structs are handled by [`TrailingFields`], everything else by postcard.
+struct TrailingDefaults<'de> {
+    bytes: &'de [u8],
+}
+
+/// Forward `deserialize_*` methods to a postcard deserializer of the whole input
+macro_rules! forward_to_postcard {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value> {
+                postcard::Deserializer::from_bytes(self.bytes).$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for TrailingDefaults<'de> {
+    type Error = postcard::Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(TrailingFields {
+            bytes: self.bytes,
+            left: fields.len(),
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(TrailingFields {
+            bytes: self.bytes,
+            left: len,
+        })
+    }
+
+    forward_to_postcard! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_map();
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+}
+
+/// The fields of a struct, ending early at the end of the input
+struct TrailingFields<'de> {
+    bytes: &'de [u8],
+    left: usize,
+}
+
+impl<'de> SeqAccess<'de> for TrailingFields<'de> {
+    type Error = postcard::Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        // Fields after the end of the input are missing, and left to the visitor
+        // to default. Bytes after the last field are ignored, like postcard does
+        if self.left == 0 || self.bytes.is_empty() {
+            return Ok(None);
+        }
+        self.left -= 1;
+        let mut de = postcard::Deserializer::from_bytes(self.bytes);
+        let value = seed.deserialize(&mut de)?;
+        self.bytes = de.finalize()?;
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::from_bytes;
+
+    #[derive(Serialize)]
+    struct V1 {
+        a: u8,
+        b: u16,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct V2 {
+        a: u8,
+        b: u16,
+        #[serde(default)]
+        c: u32,
+        #[serde(default)]
+        d: Option<bool>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct NoDefault {
+        a: u8,
+        b: u16,
+        c: u32,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq, Default)]
+    #[serde(default)]
+    struct Tuple(u8, u16, u32);
+
+    #[test]
+    fn missing_trailing_fields() {
+        let v1 = postcard::to_stdvec(&V1 { a: 1, b: 300 }).unwrap();
+        let expected = V2 {
+            a: 1,
+            b: 300,
+            c: 0,
+            d: None,
+        };
+        assert_eq!(from_bytes::<V2>(&v1).unwrap(), expected);
+        assert!(postcard::from_bytes::<V2>(&v1).is_err());
+
+        // Tuple structs are tolerant too, and extra bytes are ignored
+        assert_eq!(from_bytes::<Tuple>(&v1).unwrap(), Tuple(1, 300, 0));
+        let mut long = postcard::to_stdvec(&(1u8, 300u16, 5u32)).unwrap();
+        long.push(0xFF);
+        assert_eq!(from_bytes::<Tuple>(&long).unwrap(), Tuple(1, 300, 5));
+
+        // Complete bodies decode the same as postcard
+        let v2 = postcard::to_stdvec(&(1u8, 300u16, 7u32, Some(true))).unwrap();
+        let expected = V2 {
+            a: 1,
+            b: 300,
+            c: 7,
+            d: Some(true),
+        };
+        assert_eq!(from_bytes::<V2>(&v2).unwrap(), expected);
+        assert_eq!(from_bytes::<u16>(&[0xAC, 0x02]).unwrap(), 300);
+    }
+
+    #[test]
+    fn still_fails() {
+        let v1 = postcard::to_stdvec(&V1 { a: 1, b: 300 }).unwrap();
+        // A missing field without a default
+        assert!(from_bytes::<NoDefault>(&v1).is_err());
+        // A body ending in the middle of a field
+        assert!(from_bytes::<V2>(&v1[..2]).is_err());
+    }
+}
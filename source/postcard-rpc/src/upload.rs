@@ -0,0 +1,164 @@
+//! Resumable uploads of large data in offset-keyed chunks
+//!
+//! Data too large for a single request, e.g. a firmware image, is sent in chunks,
+//! each carrying its offset within the data as an [`UploadChunk`]. The device
+//! stores the data in order, and answers every chunk with an [`UploadAck`], holding
+//! the offset up to which all data has been received. When the link drops during
+//! the transfer, the host resumes from the acknowledged offset, instead of
+//! restarting from zero.
+//!
+//! Uploads use an endpoint of the application, taking chunks and replying with
+//! acknowledgements, e.g. one per kind of upload:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::upload::{UploadAck, UploadChunk};
+//!
+//! endpoints! {
+//!     list = ENDPOINT_LIST;
+//!     | EndpointTy        | RequestTy         | ResponseTy    | Path          |
+//!     | ----------        | ---------         | ----------    | ----          |
+//!     | FirmwareEndpoint  | UploadChunk<'a>   | UploadAck     | "ota/chunk"   |
+//! }
+//! ```
+//!
+//! On the device, the handler keeps a [`ChunkedUpload`], which decides which part
+//! of each chunk is new:
+//!
+//! ```rust,ignore
+//! async fn firmware_handler(ctx: &mut Context, _hdr: VarHeader, chunk: UploadChunk<'_>) -> UploadAck {
+//!     if let Some((offset, data)) = ctx.upload.next_write(&chunk) {
+//!         if ctx.flash.write(offset, data).await.is_ok() {
+//!             ctx.upload.commit(data.len());
+//!         }
+//!     }
+//!     ctx.upload.ack()
+//! }
+//! ```
+//!
+//! On the host, `host_client::ChunkedUpload` sends the chunks, and can be run
+//! again after an error to resume the upload.
+//!
+//! The protocol needs no separate handshake to resume: a chunk at an offset the
+//! device already received is acknowledged without being stored again, and a
+//! chunk beyond the acknowledged offset is dropped, as it would leave a gap. In
+//! both cases, the acknowledgement tells the host where to continue. Starting a
+//! new upload, e.g. of a new image, is up to the application, which calls
+//! [`ChunkedUpload::reset()`], e.g. from an endpoint announcing the upload.
+//!
+//! **Requires feature**: `upload`
+
+use postcard_schema::Schema;
+use serde::{Deserialize, Serialize};
+
+/// A chunk of an upload, sent by the host
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct UploadChunk<'a> {
+    /// The offset of the chunk within the uploaded data
+    pub offset: u32,
+    /// The data of the chunk
+    pub data: &'a [u8],
+}
+
+/// The acknowledgement of an [`UploadChunk`], sent by the device
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UploadAck {
+    /// The offset up to which all data has been received, i.e. the offset of the
+    /// next chunk the device expects
+    pub acked: u32,
+}
+
+/// The receiving side of an upload, on the device
+///
+/// Tracks the offset up to which all data has been received. See the
+/// [module documentation](self) for an example.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChunkedUpload {
+    acked: u32,
+}
+
+impl ChunkedUpload {
+    /// Create an upload which has not received any data yet
+    pub const fn new() -> Self {
+        Self { acked: 0 }
+    }
+
+    /// The offset up to which all data has been received
+    pub fn acked(&self) -> u32 {
+        self.acked
+    }
+
+    /// The acknowledgement to send in reply to a chunk
+    pub fn ack(&self) -> UploadAck {
+        UploadAck { acked: self.acked }
+    }
+
+    /// Forget all received data, to start a new upload
+    pub fn reset(&mut self) {
+        self.acked = 0;
+    }
+
+    /// The part of `chunk` which has not been received yet, with its offset, if any
+    ///
+    /// Data before the acknowledged offset was already received, e.g. when the
+    /// host resends a chunk whose acknowledgement was lost, and is skipped.
+    /// Chunks beyond the acknowledged offset would leave a gap, so they are
+    /// dropped entirely. Once the returned data has been stored, it must be
+    /// committed with [`ChunkedUpload::commit()`].
+    pub fn next_write<'a>(&self, chunk: &UploadChunk<'a>) -> Option<(u32, &'a [u8])> {
+        if chunk.offset > self.acked {
+            return None;
+        }
+        let skip = (self.acked - chunk.offset) as usize;
+        match chunk.data.get(skip..) {
+            Some(data) if !data.is_empty() => Some((self.acked, data)),
+            _ => None,
+        }
+    }
+
+    /// Acknowledge `len` more bytes, after storing the data returned by
+    /// [`ChunkedUpload::next_write()`]
+    pub fn commit(&mut self, len: usize) {
+        self.acked = self.acked.saturating_add(len as u32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChunkedUpload, UploadChunk};
+
+    #[test]
+    fn in_order_overlapping_and_gaps() {
+        let mut upload = ChunkedUpload::new();
+        let data = [0u8, 1, 2, 3, 4, 5, 6, 7];
+
+        let chunk = UploadChunk {
+            offset: 0,
+            data: &data[..4],
+        };
+        assert_eq!(upload.next_write(&chunk), Some((0, &data[..4])));
+        upload.commit(4);
+        assert_eq!(upload.ack().acked, 4);
+
+        // A resent chunk is skipped, an overlapping one only yields the new part
+        assert_eq!(upload.next_write(&chunk), None);
+        let chunk = UploadChunk {
+            offset: 2,
+            data: &data[2..6],
+        };
+        assert_eq!(upload.next_write(&chunk), Some((4, &data[4..6])));
+        upload.commit(2);
+
+        // A gap is not accepted
+        let chunk = UploadChunk {
+            offset: 7,
+            data: &data[7..],
+        };
+        assert_eq!(upload.next_write(&chunk), None);
+        assert_eq!(upload.acked(), 6);
+
+        upload.reset();
+        assert_eq!(upload.acked(), 0);
+    }
+}